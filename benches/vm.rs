@@ -0,0 +1,230 @@
+// Myula benchmarks: compiler phases and VM workloads, `cargo bench [-- <name filter>]`
+//
+// a plain timing harness (harness = false) without external crates: every case is warmed up,
+// then timed over a fixed number of samples; min and median are printed per case
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use myula::backend::translator::scanner::Scanner;
+use myula::backend::vm::{FuncMetadata, LogLevel, VirtualMachine};
+use myula::frontend::ir::IRGenerator;
+use myula::frontend::lexer::Lexer;
+use myula::frontend::parser::Parser;
+
+const WARMUP: usize = 2;
+const SAMPLES: usize = 10;
+
+const FIB: &str = "
+function fib(n)
+    if n < 2 then return n end
+    return fib(n - 1) + fib(n - 2)
+end
+result = fib(24)
+";
+
+const LOOP: &str = "
+local i = 0
+local s = 0
+while i < 1000000 do
+    i = i + 1
+    if i % 3 == 0 then s = s + i end
+end
+result = s
+";
+
+const TABLES: &str = "
+local t = {}
+local i = 0
+while i < 200000 do
+    i = i + 1
+    t[i] = i * 2
+end
+local s = 0
+i = 0
+while i < 200000 do
+    i = i + 1
+    s = s + t[i]
+end
+result = s
+";
+
+const STRINGS: &str = "
+local i = 0
+local s = ''
+while i < 50000 do
+    i = i + 1
+    local t = {key = i}
+    s = 'x' .. t.key
+end
+result = s
+";
+
+const CLOSURES: &str = "
+local function counter()
+    local n = 0
+    return function()
+        n = n + 1
+        return n
+    end
+end
+local c = counter()
+local i = 0
+while i < 200000 do
+    i = i + 1
+    c()
+end
+result = c()
+";
+
+struct Stats {
+    min: Duration,
+    median: Duration,
+}
+
+fn time(run: impl FnOnce()) -> Duration {
+    let start = Instant::now();
+    run();
+    start.elapsed()
+}
+
+// run returns the part of each sample that counts
+fn measure(mut run: impl FnMut() -> Duration) -> Stats {
+    for _ in 0..WARMUP {
+        run();
+    }
+    let mut times: Vec<Duration> = (0..SAMPLES).map(|_| run()).collect();
+    times.sort();
+    Stats {
+        min: times[0],
+        median: times[SAMPLES / 2],
+    }
+}
+
+fn report(name: &str, stats: &Stats, extra: &str) {
+    println!(
+        "{:<24} min {:>10.3} ms   median {:>10.3} ms   {}",
+        name,
+        stats.min.as_secs_f64() * 1000.0,
+        stats.median.as_secs_f64() * 1000.0,
+        extra
+    );
+}
+
+fn compile(source: &str) -> HashMap<String, FuncMetadata> {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+    assert!(parser.get_err().is_empty(), "{:#?}", parser.get_err());
+
+    let mut ir_gen = IRGenerator::new();
+    ir_gen.generate(&program);
+
+    let mut scanner = Scanner::new();
+    scanner.global_scan(ir_gen.get_module());
+    VirtualMachine::compile(&ir_gen, &mut scanner, false).unwrap()
+}
+
+// each phase of the compiler on its own, over a source with many small functions
+fn bench_compiler(filter: &dyn Fn(&str) -> bool) {
+    let mut source = String::new();
+    for i in 0..500 {
+        source.push_str(&format!(
+            "function f{i}(a, b)\n  local t = {{x = a, y = b}}\n  if a < b then return t.x + {i} end\n  return t.y - {i}\nend\n"
+        ));
+    }
+
+    if filter("compile/parse") {
+        let stats = measure(|| {
+            time(|| {
+                let mut lexer = Lexer::new(&source);
+                let mut parser = Parser::new(&mut lexer);
+                black_box(parser.parse());
+            })
+        });
+        report("compile/parse", &stats, "");
+    }
+
+    let mut lexer = Lexer::new(&source);
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+
+    if filter("compile/ir") {
+        let stats = measure(|| {
+            time(|| {
+                let mut ir_gen = IRGenerator::new();
+                ir_gen.generate(&program);
+                black_box(ir_gen.get_module());
+            })
+        });
+        report("compile/ir", &stats, "");
+    }
+
+    let mut ir_gen = IRGenerator::new();
+    ir_gen.generate(&program);
+
+    if filter("compile/emit") {
+        let stats = measure(|| {
+            time(|| {
+                let mut scanner = Scanner::new();
+                scanner.global_scan(ir_gen.get_module());
+                black_box(VirtualMachine::compile(&ir_gen, &mut scanner, false).unwrap());
+            })
+        });
+        report("compile/emit", &stats, "");
+    }
+}
+
+// only the execution is timed, the chunk is compiled once and loaded into a fresh VM per sample
+fn bench_workload(name: &str, source: &str) {
+    let funcs = compile(source);
+    let mut instructions = 0;
+    let mut collections = 0;
+    let stats = measure(|| {
+        let mut vm = VirtualMachine::new();
+        vm.init_precompiled(funcs.clone(), LogLevel::Release);
+        let before = vm.stats;
+        let elapsed = time(|| {
+            if let Err(e) = vm.execute() {
+                panic!("{}: {}", name, e);
+            }
+        });
+        instructions = vm.stats.instructions - before.instructions;
+        collections = (vm.stats.full_collections - before.full_collections)
+            + (vm.stats.minor_collections - before.minor_collections);
+        elapsed
+    });
+    let mips = instructions as f64 / stats.median.as_secs_f64() / 1e6;
+    report(
+        name,
+        &stats,
+        &format!(
+            "{} instructions ({:.1} M/s), {} GC cycles",
+            instructions, mips, collections
+        ),
+    );
+}
+
+fn main() {
+    // `cargo bench` passes --bench, everything else filters the cases by name
+    let filters: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    let filter = |name: &str| filters.is_empty() || filters.iter().any(|f| name.contains(f));
+
+    bench_compiler(&filter);
+
+    for (name, source) in [
+        ("vm/fib", FIB),
+        ("vm/loop", LOOP),
+        ("vm/tables", TABLES),
+        ("vm/strings", STRINGS),
+        ("vm/closures", CLOSURES),
+    ] {
+        if filter(name) {
+            bench_workload(name, source);
+        }
+    }
+}
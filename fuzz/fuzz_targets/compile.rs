@@ -0,0 +1,29 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use myula::backend::translator::scanner::Scanner;
+use myula::backend::vm::VirtualMachine;
+use myula::frontend::ir::IRGenerator;
+use myula::frontend::lexer::Lexer;
+use myula::frontend::parser::Parser;
+
+// the pipeline of the CLI up to the bytecode, each phase only runs on what the one before accepted
+fuzz_target!(|source: &str| {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+    if !parser.get_err().is_empty() {
+        return;
+    }
+
+    let mut ir_gen = IRGenerator::new();
+    ir_gen.generate(&program);
+    if !ir_gen.get_err().is_empty() {
+        return;
+    }
+
+    let mut scanner = Scanner::new();
+    scanner.global_scan(ir_gen.get_module());
+    // a function too large for the bytecode is reported, not a crash
+    let _ = VirtualMachine::compile(&ir_gen, &mut scanner, true);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use myula::frontend::lexer::Lexer;
+use myula::frontend::lexer::token::Token;
+
+// any bytes, streamed like a source file so invalid UTF-8 reaches the lexer too
+fuzz_target!(|data: &[u8]| {
+    let mut lexer = Lexer::from_reader(data);
+    while lexer.next_token() != Token::Eof {}
+});
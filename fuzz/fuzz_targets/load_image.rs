@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use myula::backend::deserializer::deserialize_module;
+use myula::backend::vm::{LogLevel, VirtualMachine};
+
+// a .myb image is either refused by the loader or runs to its end or an error, it never crashes the VM
+fuzz_target!(|bytes: &[u8]| {
+    let Ok(funcs) = deserialize_module(bytes) else {
+        return;
+    };
+
+    let mut vm = VirtualMachine::new();
+    vm.set_stdout(Box::new(std::io::sink()));
+    vm.set_fuel(Some(100_000));
+    vm.init_precompiled(funcs, LogLevel::Release);
+    let _ = vm.execute();
+});
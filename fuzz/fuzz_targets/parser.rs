@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use myula::frontend::lexer::Lexer;
+use myula::frontend::parser::Parser;
+
+// the parser recovers from every syntax error, whatever it is given it must return
+fuzz_target!(|source: &str| {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(&mut lexer);
+    parser.parse();
+});
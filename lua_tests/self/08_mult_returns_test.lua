@@ -1,4 +1,3 @@
--- UNSUPPORTED
 -- 测试多返回值的典型场景
 function get_user_data()
     local name = "Alice"
@@ -0,0 +1,20 @@
+-- unpack, select and next return several values
+
+local a, b, c = unpack({1, 2, 3})
+print(a, b, c)
+print(unpack({"x", "y", "z"}, 2))
+print(select(2, "a", "b", "c"))
+print(select(-2, "a", "b", "c"))
+print(select("#", unpack({1, 2, 3, 4})))
+
+local t = {10}
+local k, v = next(t)
+print(k, v)
+print(next(t, k))
+
+-- a call in the middle gives one value, the last one gives them all
+print(unpack({1, 2}), unpack({3, 4}))
+local function sum(x, y, z)
+    return x + y + z
+end
+print(sum(select(2, 4, 5, 6, 7)))
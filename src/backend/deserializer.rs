@@ -1,3 +1,971 @@
 /*
    IR反序列化
 */
+// Bytecode (.myb) format versioning
+// Changelog:
+// 2026-02-23: Every .myb file starts with a header: the 4 byte magic, the format version (u16, little endian)
+//            and the fingerprint (u64, little endian) of the OpCode / FuncMetadata layout it was written with.
+//            Loaders must go through `read_header`, which refuses anything that was not produced by this
+//            exact format instead of misinterpreting the bytes.
+//
+//            The version is pinned together with the fingerprint of `LAYOUT`. Changing the layout of
+//            OpCode, UnaryOpType or the serialized fields of FuncMetadata does not compile until `LAYOUT`
+//            is updated (see `opcode_tag` and the FuncMetadata destructuring below), and updating `LAYOUT`
+//            does not compile until the version is bumped and the new fingerprint is pinned.
+// 2026-02-24: Version 2: the constant pool distinguishes integer and float constants, constant pool
+//            values are part of LAYOUT (see the LuaValue match below).
+// 2026-02-24: Version 3: the payload after the header is defined, a whole compiled module can be written
+//            with `serialize_module` and loaded with `deserialize_module` (myulac -o).
+// 2026-02-24: Version 4: functions carry their line table (u32 count, then one u32 line per opcode).
+// 2026-02-24: Version 5: GetField / SetField, table access with a constant string key.
+// 2026-02-24: Version 6: TailCall.
+// 2026-02-24: Version 7: AddK / SubK (arithmetic with a number constant) and JumpIfFalse.
+// 2026-02-24: Constant pools are `Constant`s instead of `LuaValue`s, the bytes written are the same so the
+//            version stays 7.
+// 2026-02-24: Version 8: functions carry the names of their local slots (u32 count, then one string per
+//            slot), debug.getlocal works on precompiled images too.
+// 2026-02-24: Version 9: Switch, functions carry their jump tables (u32 count, then per table the i64 low
+//            key, the i32 default offset, a u32 count and one i32 offset per key).
+// 2026-02-24: Version 10: functions carry their span table after the line table (u32 count, then the u32
+//            start and u32 end offset of the source span of each opcode).
+// 2026-02-24: Version 11: ConcatN.
+// 2026-02-24: Version 12: SetList.
+// 2026-02-24: Version 13: GetResult, calls and returns with several values.
+// 2026-02-24: A function is checked once it is read (`check_function`): its register, constant, upvalue,
+//            prototype and jump table operands and its jump targets must lie within the function, and the
+//            registers a prototype captures within its parent. A corrupted image is a FormatError
+//            instead of an index out of bounds in the VM.
+
+use crate::backend::vm::{FuncMetadata, NUM_PAD_REGS};
+use crate::common::instruction::encode_all;
+use crate::common::object::Constant;
+use crate::common::opcode::{JumpTable, MULTRET, OpCode, OperandKind, UnaryOpType};
+use crate::frontend::ir::{IRUpVal, IRUpValType};
+use crate::frontend::parser::ast::Span;
+use std::collections::HashMap;
+use std::fmt;
+
+pub const MYB_MAGIC: &[u8; 4] = b"\x1bMYB";
+
+pub const BYTECODE_FORMAT_VERSION: u16 = 13;
+
+// magic + version + fingerprint
+pub const HEADER_SIZE: usize = 4 + 2 + 8;
+
+// the serialized layout, one entry per opcode in tag order, then the operand enums and the function record
+const LAYOUT: &[&str] = &[
+    "LoadK{dest:u16,const_idx:u16}",
+    "LoadNil{dest:u16}",
+    "LoadBool{dest:u16,value:bool}",
+    "Move{dest:u16,src:u16}",
+    "GetGlobal{dest:u16,name_idx:u16}",
+    "SetGlobal{name_idx:u16,src:u16}",
+    "GetUpVal{dest:u16,upval_idx:u16}",
+    "SetUpVal{upval_idx:u16,src:u16}",
+    "Add{dest:u16,left:u16,right:u16}",
+    "Sub{dest:u16,left:u16,right:u16}",
+    "Mul{dest:u16,left:u16,right:u16}",
+    "Div{dest:u16,left:u16,right:u16}",
+    "Mod{dest:u16,left:u16,right:u16}",
+    "Pow{dest:u16,left:u16,right:u16}",
+    "Concat{dest:u16,left:u16,right:u16}",
+    "And{dest:u16,left:u16,right:u16}",
+    "Or{dest:u16,left:u16,right:u16}",
+    "UnOp{dest:u16,src:u16,op:UnaryOpType}",
+    "Eq{dest:u16,left:u16,right:u16}",
+    "Ne{dest:u16,left:u16,right:u16}",
+    "Lt{dest:u16,left:u16,right:u16}",
+    "Gt{dest:u16,left:u16,right:u16}",
+    "Le{dest:u16,left:u16,right:u16}",
+    "Ge{dest:u16,left:u16,right:u16}",
+    "Test{reg:u16}",
+    "Jump{offset:i32}",
+    "NewTable{dest:u16,size_array:u16,size_hash:u16}",
+    "GetTable{dest:u16,table:u16,key:u16}",
+    "SetTable{table:u16,key:u16,value:u16}",
+    "FnProto{dest:u16,proto_idx:u16}",
+    "Call{func_reg:u16,argc:u8,retc:u8}",
+    "Push{src:u16}",
+    "Return{start:u16,count:u8}",
+    "Halt",
+    "GetField{dest:u16,table:u16,key:u16}",
+    "SetField{table:u16,key:u16,value:u16}",
+    "TailCall{func_reg:u16,argc:u8}",
+    "AddK{dest:u16,left:u16,const_idx:u16}",
+    "SubK{dest:u16,left:u16,const_idx:u16}",
+    "JumpIfFalse{reg:u16,offset:i32}",
+    "Switch{reg:u16,table:u16}",
+    "ConcatN{dest:u16,start:u16,count:u16}",
+    "SetList{table:u16,count:u16,batch:u16}",
+    "GetResult{dest:u16,index:u8}",
+    "UnaryOpType{Neg,Not,Len}",
+    // the string constant is still spelled like the `LuaValue::TempString` it used to be,
+    // renaming it would change the fingerprint of an unchanged layout
+    "Constant{Nil,Number:f64,Integer:i64,TempString}",
+    "FuncMetadata{bytecode,constants,num_locals,max_stack_size,upvalues_metadata,child_protos,line_info:[u32],span_info:[u32,u32],local_names:[str],jump_tables:[JumpTable]}",
+    "UpVal{slot:u32,LocalVar:u32,UpVal:u32}",
+    "JumpTable{low:i64,default:i32,targets:[i32]}",
+    "Module{count:u32,[name:str,FuncMetadata]}",
+];
+
+// (version, fingerprint) this build writes, a layout change must bump the version
+// together with the fingerprint, old files are then refused by `read_header`
+const PINNED: (u16, u64) = (13, 0x461d_bb56_7b7c_a610);
+
+pub const LAYOUT_FINGERPRINT: u64 = layout_fingerprint();
+
+const _: () = assert!(
+    PINNED.0 == BYTECODE_FORMAT_VERSION && PINNED.1 == LAYOUT_FINGERPRINT,
+    "bytecode layout changed: bump BYTECODE_FORMAT_VERSION and pin it with the new LAYOUT_FINGERPRINT"
+);
+
+// FNV-1a over the layout description and the in-memory size of an opcode
+const fn layout_fingerprint() -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut i = 0;
+    while i < LAYOUT.len() {
+        let bytes = LAYOUT[i].as_bytes();
+        let mut j = 0;
+        while j < bytes.len() {
+            hash ^= bytes[j] as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+            j += 1;
+        }
+        // entry separator
+        hash ^= 0xff;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+        i += 1;
+    }
+    hash ^= std::mem::size_of::<OpCode>() as u64;
+    hash.wrapping_mul(0x0100_0000_01b3)
+}
+
+/// the tag an opcode is serialized with, i.e. its index in LAYOUT
+///
+/// the match is exhaustive on purpose: a new opcode must be given a tag (and a LAYOUT entry) here
+pub fn opcode_tag(op: &OpCode) -> u8 {
+    match op {
+        OpCode::LoadK { .. } => 0,
+        OpCode::LoadNil { .. } => 1,
+        OpCode::LoadBool { .. } => 2,
+        OpCode::Move { .. } => 3,
+        OpCode::GetGlobal { .. } => 4,
+        OpCode::SetGlobal { .. } => 5,
+        OpCode::GetUpVal { .. } => 6,
+        OpCode::SetUpVal { .. } => 7,
+        OpCode::Add { .. } => 8,
+        OpCode::Sub { .. } => 9,
+        OpCode::Mul { .. } => 10,
+        OpCode::Div { .. } => 11,
+        OpCode::Mod { .. } => 12,
+        OpCode::Pow { .. } => 13,
+        OpCode::Concat { .. } => 14,
+        OpCode::And { .. } => 15,
+        OpCode::Or { .. } => 16,
+        OpCode::UnOp { .. } => 17,
+        OpCode::Eq { .. } => 18,
+        OpCode::Ne { .. } => 19,
+        OpCode::Lt { .. } => 20,
+        OpCode::Gt { .. } => 21,
+        OpCode::Le { .. } => 22,
+        OpCode::Ge { .. } => 23,
+        OpCode::Test { .. } => 24,
+        OpCode::Jump { .. } => 25,
+        OpCode::NewTable { .. } => 26,
+        OpCode::GetTable { .. } => 27,
+        OpCode::SetTable { .. } => 28,
+        OpCode::FnProto { .. } => 29,
+        OpCode::Call { .. } => 30,
+        OpCode::Push { .. } => 31,
+        OpCode::Return { .. } => 32,
+        OpCode::Halt => 33,
+        OpCode::GetField { .. } => 34,
+        OpCode::SetField { .. } => 35,
+        OpCode::TailCall { .. } => 36,
+        OpCode::AddK { .. } => 37,
+        OpCode::SubK { .. } => 38,
+        OpCode::JumpIfFalse { .. } => 39,
+        OpCode::Switch { .. } => 40,
+        OpCode::ConcatN { .. } => 41,
+        OpCode::SetList { .. } => 42,
+        OpCode::GetResult { .. } => 43,
+    }
+}
+
+// same for the unary operators
+const _: fn(UnaryOpType) = |op| match op {
+    UnaryOpType::Neg | UnaryOpType::Not | UnaryOpType::Len => {}
+};
+
+// and for constant pool entries: a new Constant variant goes into LAYOUT
+const _: fn(&Constant) = |val| match val {
+    Constant::Nil | Constant::Number(_) | Constant::Integer(_) | Constant::String(_) => {}
+};
+
+// and for FuncMetadata: a new field must be classified as serialized (and added to LAYOUT)
+// or as runtime-only before this compiles again
+const _: fn(&FuncMetadata) = |meta| {
+    let FuncMetadata {
+        // serialized
+        bytecode: _,
+        constants: _,
+        num_locals: _,
+        max_stack_size: _,
+        upvalues_metadata: _,
+        child_protos: _,
+        line_info: _,
+        span_info: _,
+        local_names: _,
+        jump_tables: _,
+        // rebuilt at load time / debug only
+        code: _,
+        reg_metadata: _,
+        operand_names: _,
+    } = meta;
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatError {
+    // shorter than the header
+    Truncated,
+    // does not start with MYB_MAGIC
+    NotBytecode,
+    // written by an incompatible version of Myula
+    VersionMismatch { found: u16, expected: u16 },
+    // same version number but a different layout, i.e. written by a development build
+    LayoutMismatch { found: u64, expected: u64 },
+    // the header is fine but the payload cannot be decoded
+    Malformed(String),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Truncated => write!(
+                f,
+                "BytecodeFormatException: file is truncated (shorter than the {} byte header)",
+                HEADER_SIZE
+            ),
+            FormatError::NotBytecode => write!(
+                f,
+                "BytecodeFormatException: not a Myula bytecode file (bad magic)"
+            ),
+            FormatError::VersionMismatch { found, expected } => write!(
+                f,
+                "BytecodeFormatException: bytecode format version {} is not supported (expected version {}), recompile the source",
+                found, expected
+            ),
+            FormatError::LayoutMismatch { found, expected } => write!(
+                f,
+                "BytecodeFormatException: bytecode layout {:016x} does not match this build ({:016x}), recompile the source",
+                found, expected
+            ),
+            FormatError::Malformed(msg) => {
+                write!(
+                    f,
+                    "BytecodeFormatException: malformed bytecode image, {}",
+                    msg
+                )
+            }
+        }
+    }
+}
+
+pub fn write_header(out: &mut Vec<u8>) {
+    out.extend_from_slice(MYB_MAGIC);
+    out.extend_from_slice(&BYTECODE_FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&LAYOUT_FINGERPRINT.to_le_bytes());
+}
+
+/// validate the header of a .myb image and return the payload that follows it
+pub fn read_header(bytes: &[u8]) -> Result<&[u8], FormatError> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(
+            if bytes.starts_with(MYB_MAGIC) || MYB_MAGIC.starts_with(bytes) {
+                FormatError::Truncated
+            } else {
+                FormatError::NotBytecode
+            },
+        );
+    }
+    if &bytes[..4] != MYB_MAGIC {
+        return Err(FormatError::NotBytecode);
+    }
+
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != BYTECODE_FORMAT_VERSION {
+        return Err(FormatError::VersionMismatch {
+            found: version,
+            expected: BYTECODE_FORMAT_VERSION,
+        });
+    }
+
+    let fingerprint = u64::from_le_bytes(bytes[6..HEADER_SIZE].try_into().unwrap());
+    if fingerprint != LAYOUT_FINGERPRINT {
+        return Err(FormatError::LayoutMismatch {
+            found: fingerprint,
+            expected: LAYOUT_FINGERPRINT,
+        });
+    }
+
+    Ok(&bytes[HEADER_SIZE..])
+}
+
+// ---------------------------------------------------------------------------
+// module payload: every integer is little endian, strings are a u32 byte length followed by UTF-8,
+// an opcode is its tag (see `opcode_tag`) followed by its operands in LAYOUT order;
+// functions are written sorted by name so the same source always gives the same image
+
+/// a complete .myb image of compiled functions (name -> metadata), as produced by
+/// `VirtualMachine::compile`, i.e. with constant strings not interned yet
+pub fn serialize_module(funcs: &HashMap<String, FuncMetadata>) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_header(&mut out);
+
+    let mut names: Vec<&String> = funcs.keys().collect();
+    names.sort();
+    write_u32(&mut out, names.len());
+    for name in names {
+        write_str(&mut out, name);
+        write_function(&mut out, &funcs[name]);
+    }
+    out
+}
+
+/// read back an image written by `serialize_module`, debug-only metadata comes back empty
+pub fn deserialize_module(bytes: &[u8]) -> Result<HashMap<String, FuncMetadata>, FormatError> {
+    let mut reader = Reader {
+        bytes: read_header(bytes)?,
+        pos: 0,
+    };
+
+    let count = reader.u32()?;
+    let mut funcs = HashMap::new();
+    for _ in 0..count {
+        let name = reader.string()?;
+        let meta = reader.function()?;
+        funcs.insert(name, meta);
+    }
+    if reader.pos != reader.bytes.len() {
+        return Err(FormatError::Malformed(format!(
+            "{} trailing bytes after the last function",
+            reader.bytes.len() - reader.pos
+        )));
+    }
+
+    if !funcs.contains_key("_start") {
+        return Err(FormatError::Malformed(
+            "no entry function '_start'".to_string(),
+        ));
+    }
+    // a prototype captures registers of the function creating it, which must have them
+    for (name, meta) in &funcs {
+        for child in meta
+            .child_protos
+            .iter()
+            .filter_map(|child| funcs.get(child))
+        {
+            for upval in &child.upvalues_metadata {
+                if let IRUpValType::LocalVar(slot) = upval.ty
+                    && slot >= meta.max_stack_size
+                {
+                    return Err(FormatError::Malformed(format!(
+                        "a prototype of '{}' captures register {} of {}",
+                        name, slot, meta.max_stack_size
+                    )));
+                }
+            }
+        }
+    }
+    Ok(funcs)
+}
+
+// registers are u16 operands, no frame needs more of them than that and the VM's padding
+const MAX_STACK_SIZE: usize = u16::MAX as usize + 1 + NUM_PAD_REGS;
+
+// the VM runs an image without checking its operands, so every index an instruction uses must lie
+// within the function: registers within its frame, constants, upvalues, prototypes and jump tables
+// within their lists, jump targets within its code
+fn check_function(meta: &FuncMetadata) -> Result<(), FormatError> {
+    if meta.max_stack_size > MAX_STACK_SIZE {
+        return Err(FormatError::Malformed(format!(
+            "stack size {} exceeds {}",
+            meta.max_stack_size, MAX_STACK_SIZE
+        )));
+    }
+
+    let len = meta.bytecode.len();
+    for (pc, op) in meta.bytecode.iter().enumerate() {
+        let mut bad = None;
+        op.visit_operands(|kind, idx| {
+            let (what, count) = match kind {
+                OperandKind::Reg => ("register", meta.max_stack_size),
+                OperandKind::Const => ("constant", meta.constants.len()),
+                OperandKind::UpVal => ("upvalue", meta.upvalues_metadata.len()),
+                OperandKind::Proto => ("prototype", meta.child_protos.len()),
+            };
+            if idx as usize >= count && bad.is_none() {
+                bad = Some(format!("{} {} of {}", what, idx, count));
+            }
+        });
+
+        // the registers after the first of a window
+        let window = match *op {
+            OpCode::ConcatN { start, count, .. } => Some((start as usize, count as usize)),
+            OpCode::Return { start, count } if count != MULTRET => {
+                Some((start as usize, count as usize))
+            }
+            _ => None,
+        };
+        if let Some((start, count)) = window
+            && start + count > meta.max_stack_size
+            && bad.is_none()
+        {
+            bad = Some(format!(
+                "registers {}..{} of {}",
+                start,
+                start + count,
+                meta.max_stack_size
+            ));
+        }
+
+        let offsets = match *op {
+            OpCode::Jump { offset } | OpCode::JumpIfFalse { offset, .. } => vec![offset],
+            OpCode::Switch { table, .. } => match meta.jump_tables.get(table as usize) {
+                Some(table) => table
+                    .targets
+                    .iter()
+                    .copied()
+                    .chain([table.default])
+                    .collect(),
+                None => {
+                    return Err(FormatError::Malformed(format!(
+                        "SWITCH refers to jump table {} of {}",
+                        table,
+                        meta.jump_tables.len()
+                    )));
+                }
+            },
+            _ => vec![],
+        };
+        for offset in offsets {
+            let target = pc as i64 + offset as i64;
+            if (target < 0 || target >= len as i64) && bad.is_none() {
+                bad = Some(format!("jump to {} of {} instructions", target, len));
+            }
+        }
+
+        if let Some(bad) = bad {
+            return Err(FormatError::Malformed(format!(
+                "{} at pc {} refers to {}",
+                op.name(),
+                pc,
+                bad
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn write_u32(out: &mut Vec<u8>, n: usize) {
+    let n = u32::try_from(n).expect("BytecodeFormatException: count does not fit in u32");
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+pub(crate) fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_u32(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+pub(crate) fn write_function(out: &mut Vec<u8>, meta: &FuncMetadata) {
+    write_u32(out, meta.bytecode.len());
+    for op in &meta.bytecode {
+        write_opcode(out, op);
+    }
+
+    write_u32(out, meta.constants.len());
+    for val in &meta.constants {
+        match val {
+            Constant::Nil => out.push(0),
+            Constant::Number(n) => {
+                out.push(1);
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+            Constant::Integer(i) => {
+                out.push(2);
+                out.extend_from_slice(&i.to_le_bytes());
+            }
+            Constant::String(s) => {
+                out.push(3);
+                write_str(out, s);
+            }
+        }
+    }
+
+    write_u32(out, meta.num_locals);
+    write_u32(out, meta.max_stack_size);
+
+    write_u32(out, meta.upvalues_metadata.len());
+    for upval in &meta.upvalues_metadata {
+        write_u32(out, upval.slot);
+        match upval.ty {
+            IRUpValType::LocalVar(slot) => {
+                out.push(0);
+                write_u32(out, slot);
+            }
+            IRUpValType::UpVal(idx) => {
+                out.push(1);
+                write_u32(out, idx);
+            }
+        }
+    }
+
+    write_u32(out, meta.child_protos.len());
+    for child in &meta.child_protos {
+        write_str(out, child);
+    }
+
+    write_u32(out, meta.line_info.len());
+    for line in &meta.line_info {
+        out.extend_from_slice(&line.to_le_bytes());
+    }
+
+    write_u32(out, meta.span_info.len());
+    for span in &meta.span_info {
+        write_u32(out, span.start);
+        write_u32(out, span.end);
+    }
+
+    write_u32(out, meta.local_names.len());
+    for name in &meta.local_names {
+        write_str(out, name);
+    }
+
+    write_u32(out, meta.jump_tables.len());
+    for table in &meta.jump_tables {
+        out.extend_from_slice(&table.low.to_le_bytes());
+        out.extend_from_slice(&table.default.to_le_bytes());
+        write_u32(out, table.targets.len());
+        for offset in &table.targets {
+            out.extend_from_slice(&offset.to_le_bytes());
+        }
+    }
+}
+
+fn write_opcode(out: &mut Vec<u8>, op: &OpCode) {
+    out.push(opcode_tag(op));
+    let u16s = |out: &mut Vec<u8>, vals: &[u16]| {
+        for v in vals {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+    };
+    match *op {
+        OpCode::LoadK { dest, const_idx } => u16s(out, &[dest, const_idx]),
+        OpCode::LoadNil { dest } => u16s(out, &[dest]),
+        OpCode::LoadBool { dest, value } => {
+            u16s(out, &[dest]);
+            out.push(value as u8);
+        }
+        OpCode::Move { dest, src } => u16s(out, &[dest, src]),
+        OpCode::GetGlobal { dest, name_idx } => u16s(out, &[dest, name_idx]),
+        OpCode::SetGlobal { name_idx, src } => u16s(out, &[name_idx, src]),
+        OpCode::GetUpVal { dest, upval_idx } => u16s(out, &[dest, upval_idx]),
+        OpCode::SetUpVal { upval_idx, src } => u16s(out, &[upval_idx, src]),
+        OpCode::Add { dest, left, right }
+        | OpCode::Sub { dest, left, right }
+        | OpCode::Mul { dest, left, right }
+        | OpCode::Div { dest, left, right }
+        | OpCode::Mod { dest, left, right }
+        | OpCode::Pow { dest, left, right }
+        | OpCode::Concat { dest, left, right }
+        | OpCode::And { dest, left, right }
+        | OpCode::Or { dest, left, right }
+        | OpCode::Eq { dest, left, right }
+        | OpCode::Ne { dest, left, right }
+        | OpCode::Lt { dest, left, right }
+        | OpCode::Gt { dest, left, right }
+        | OpCode::Le { dest, left, right }
+        | OpCode::Ge { dest, left, right } => u16s(out, &[dest, left, right]),
+        OpCode::UnOp { dest, src, op } => {
+            u16s(out, &[dest, src]);
+            out.push(match op {
+                UnaryOpType::Neg => 0,
+                UnaryOpType::Not => 1,
+                UnaryOpType::Len => 2,
+            });
+        }
+        OpCode::Test { reg } => u16s(out, &[reg]),
+        OpCode::Jump { offset } => out.extend_from_slice(&offset.to_le_bytes()),
+        OpCode::NewTable {
+            dest,
+            size_array,
+            size_hash,
+        } => u16s(out, &[dest, size_array, size_hash]),
+        OpCode::GetTable { dest, table, key } => u16s(out, &[dest, table, key]),
+        OpCode::SetTable { table, key, value } => u16s(out, &[table, key, value]),
+        OpCode::GetField { dest, table, key } => u16s(out, &[dest, table, key]),
+        OpCode::SetField { table, key, value } => u16s(out, &[table, key, value]),
+        OpCode::FnProto { dest, proto_idx } => u16s(out, &[dest, proto_idx]),
+        OpCode::Call {
+            func_reg,
+            argc,
+            retc,
+        } => {
+            u16s(out, &[func_reg]);
+            out.extend_from_slice(&[argc, retc]);
+        }
+        OpCode::GetResult { dest, index } => {
+            u16s(out, &[dest]);
+            out.push(index);
+        }
+        OpCode::Push { src } => u16s(out, &[src]),
+        OpCode::Return { start, count } => {
+            u16s(out, &[start]);
+            out.push(count);
+        }
+        OpCode::TailCall { func_reg, argc } => {
+            u16s(out, &[func_reg]);
+            out.push(argc);
+        }
+        OpCode::AddK {
+            dest,
+            left,
+            const_idx,
+        }
+        | OpCode::SubK {
+            dest,
+            left,
+            const_idx,
+        } => u16s(out, &[dest, left, const_idx]),
+        OpCode::JumpIfFalse { reg, offset } => {
+            u16s(out, &[reg]);
+            out.extend_from_slice(&offset.to_le_bytes());
+        }
+        OpCode::Switch { reg, table } => u16s(out, &[reg, table]),
+        OpCode::ConcatN { dest, start, count } => u16s(out, &[dest, start, count]),
+        OpCode::SetList {
+            table,
+            count,
+            batch,
+        } => u16s(out, &[table, count, batch]),
+        OpCode::Halt => {}
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], FormatError> {
+        if self.bytes.len() - self.pos < n {
+            return Err(FormatError::Malformed(format!(
+                "unexpected end of image at payload offset {}",
+                self.pos
+            )));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, FormatError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<usize, FormatError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize)
+    }
+
+    fn i32(&mut self) -> Result<i32, FormatError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<[u8; 8], FormatError> {
+        Ok(self.take(8)?.try_into().unwrap())
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String, FormatError> {
+        let len = self.u32()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| FormatError::Malformed("string is not valid UTF-8".into()))
+    }
+
+    pub(crate) fn function(&mut self) -> Result<FuncMetadata, FormatError> {
+        let count = self.u32()?;
+        let mut bytecode = Vec::new();
+        for _ in 0..count {
+            bytecode.push(self.opcode()?);
+        }
+
+        let count = self.u32()?;
+        let mut constants = Vec::new();
+        for _ in 0..count {
+            constants.push(match self.u8()? {
+                0 => Constant::Nil,
+                1 => Constant::Number(f64::from_le_bytes(self.u64()?)),
+                2 => Constant::Integer(i64::from_le_bytes(self.u64()?)),
+                3 => Constant::String(self.string()?),
+                tag => {
+                    return Err(FormatError::Malformed(format!(
+                        "unknown constant tag {}",
+                        tag
+                    )));
+                }
+            });
+        }
+
+        let num_locals = self.u32()?;
+        let max_stack_size = self.u32()?;
+
+        let count = self.u32()?;
+        let mut upvalues_metadata = Vec::new();
+        for _ in 0..count {
+            let slot = self.u32()?;
+            let ty = match self.u8()? {
+                0 => IRUpValType::LocalVar(self.u32()?),
+                1 => IRUpValType::UpVal(self.u32()?),
+                tag => {
+                    return Err(FormatError::Malformed(format!(
+                        "unknown upvalue tag {}",
+                        tag
+                    )));
+                }
+            };
+            upvalues_metadata.push(IRUpVal { slot, ty });
+        }
+
+        let count = self.u32()?;
+        let mut child_protos = Vec::new();
+        for _ in 0..count {
+            child_protos.push(self.string()?);
+        }
+
+        let count = self.u32()?;
+        let mut line_info = Vec::new();
+        for _ in 0..count {
+            line_info.push(self.u32()? as u32);
+        }
+
+        let count = self.u32()?;
+        let mut span_info = Vec::new();
+        for _ in 0..count {
+            let start = self.u32()?;
+            let end = self.u32()?;
+            span_info.push(Span::new(start, end));
+        }
+
+        let count = self.u32()?;
+        let mut local_names = Vec::new();
+        for _ in 0..count {
+            local_names.push(self.string()?);
+        }
+
+        let count = self.u32()?;
+        let mut jump_tables = Vec::new();
+        for _ in 0..count {
+            let low = i64::from_le_bytes(self.u64()?);
+            let default = self.i32()?;
+            let len = self.u32()?;
+            let mut targets = Vec::new();
+            for _ in 0..len {
+                targets.push(self.i32()?);
+            }
+            jump_tables.push(JumpTable {
+                low,
+                targets,
+                default,
+            });
+        }
+
+        let meta = FuncMetadata {
+            code: encode_all(&bytecode),
+            bytecode,
+            constants,
+            num_locals,
+            max_stack_size,
+            reg_metadata: HashMap::new(),
+            upvalues_metadata,
+            child_protos,
+            operand_names: HashMap::new(),
+            line_info,
+            span_info,
+            local_names,
+            jump_tables,
+        };
+        check_function(&meta)?;
+        Ok(meta)
+    }
+
+    fn opcode(&mut self) -> Result<OpCode, FormatError> {
+        let tag = self.u8()?;
+        let op = match tag {
+            0 => OpCode::LoadK {
+                dest: self.u16()?,
+                const_idx: self.u16()?,
+            },
+            1 => OpCode::LoadNil { dest: self.u16()? },
+            2 => OpCode::LoadBool {
+                dest: self.u16()?,
+                value: self.u8()? != 0,
+            },
+            3 => OpCode::Move {
+                dest: self.u16()?,
+                src: self.u16()?,
+            },
+            4 => OpCode::GetGlobal {
+                dest: self.u16()?,
+                name_idx: self.u16()?,
+            },
+            5 => OpCode::SetGlobal {
+                name_idx: self.u16()?,
+                src: self.u16()?,
+            },
+            6 => OpCode::GetUpVal {
+                dest: self.u16()?,
+                upval_idx: self.u16()?,
+            },
+            7 => OpCode::SetUpVal {
+                upval_idx: self.u16()?,
+                src: self.u16()?,
+            },
+            8..=16 | 18..=23 => {
+                let (dest, left, right) = (self.u16()?, self.u16()?, self.u16()?);
+                match tag {
+                    8 => OpCode::Add { dest, left, right },
+                    9 => OpCode::Sub { dest, left, right },
+                    10 => OpCode::Mul { dest, left, right },
+                    11 => OpCode::Div { dest, left, right },
+                    12 => OpCode::Mod { dest, left, right },
+                    13 => OpCode::Pow { dest, left, right },
+                    14 => OpCode::Concat { dest, left, right },
+                    15 => OpCode::And { dest, left, right },
+                    16 => OpCode::Or { dest, left, right },
+                    18 => OpCode::Eq { dest, left, right },
+                    19 => OpCode::Ne { dest, left, right },
+                    20 => OpCode::Lt { dest, left, right },
+                    21 => OpCode::Gt { dest, left, right },
+                    22 => OpCode::Le { dest, left, right },
+                    _ => OpCode::Ge { dest, left, right },
+                }
+            }
+            17 => OpCode::UnOp {
+                dest: self.u16()?,
+                src: self.u16()?,
+                op: match self.u8()? {
+                    0 => UnaryOpType::Neg,
+                    1 => UnaryOpType::Not,
+                    2 => UnaryOpType::Len,
+                    op => {
+                        return Err(FormatError::Malformed(format!(
+                            "unknown unary operator {}",
+                            op
+                        )));
+                    }
+                },
+            },
+            24 => OpCode::Test { reg: self.u16()? },
+            25 => OpCode::Jump {
+                offset: i32::from_le_bytes(self.take(4)?.try_into().unwrap()),
+            },
+            26 => OpCode::NewTable {
+                dest: self.u16()?,
+                size_array: self.u16()?,
+                size_hash: self.u16()?,
+            },
+            27 => OpCode::GetTable {
+                dest: self.u16()?,
+                table: self.u16()?,
+                key: self.u16()?,
+            },
+            28 => OpCode::SetTable {
+                table: self.u16()?,
+                key: self.u16()?,
+                value: self.u16()?,
+            },
+            29 => OpCode::FnProto {
+                dest: self.u16()?,
+                proto_idx: self.u16()?,
+            },
+            30 => OpCode::Call {
+                func_reg: self.u16()?,
+                argc: self.u8()?,
+                retc: self.u8()?,
+            },
+            31 => OpCode::Push { src: self.u16()? },
+            32 => OpCode::Return {
+                start: self.u16()?,
+                count: self.u8()?,
+            },
+            33 => OpCode::Halt,
+            34 => OpCode::GetField {
+                dest: self.u16()?,
+                table: self.u16()?,
+                key: self.u16()?,
+            },
+            35 => OpCode::SetField {
+                table: self.u16()?,
+                key: self.u16()?,
+                value: self.u16()?,
+            },
+            36 => OpCode::TailCall {
+                func_reg: self.u16()?,
+                argc: self.u8()?,
+            },
+            37 => OpCode::AddK {
+                dest: self.u16()?,
+                left: self.u16()?,
+                const_idx: self.u16()?,
+            },
+            38 => OpCode::SubK {
+                dest: self.u16()?,
+                left: self.u16()?,
+                const_idx: self.u16()?,
+            },
+            39 => OpCode::JumpIfFalse {
+                reg: self.u16()?,
+                offset: i32::from_le_bytes(self.take(4)?.try_into().unwrap()),
+            },
+            40 => OpCode::Switch {
+                reg: self.u16()?,
+                table: self.u16()?,
+            },
+            41 => OpCode::ConcatN {
+                dest: self.u16()?,
+                start: self.u16()?,
+                count: self.u16()?,
+            },
+            42 => OpCode::SetList {
+                table: self.u16()?,
+                count: self.u16()?,
+                batch: self.u16()?,
+            },
+            43 => OpCode::GetResult {
+                dest: self.u16()?,
+                index: self.u8()?,
+            },
+            _ => {
+                return Err(FormatError::Malformed(format!(
+                    "unknown opcode tag {}",
+                    tag
+                )));
+            }
+        };
+        debug_assert_eq!(opcode_tag(&op), tag);
+        Ok(op)
+    }
+}
@@ -0,0 +1,432 @@
+// Myula bytecode disassembler
+// Changelog:
+// 2026-02-24: Initial version. `Disassembler` lists one function: header, constant pool, the code with
+//            source lines, resolved constants and arrows for jumps, and the pc range each register is used
+//            over. `to_json` renders the same information for tools (myulac --emit bytecode-json).
+// 2026-02-24: Jump tables are listed after the prototypes, arrows are drawn for every target of a SWITCH.
+
+use crate::backend::vm::FuncMetadata;
+use crate::backend::vm::std_lib::format_number;
+use crate::common::object::Constant;
+use crate::common::opcode::{MULTRET, OpCode, OperandKind};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+
+pub struct Disassembler<'a> {
+    name: &'a str,
+    meta: &'a FuncMetadata,
+}
+
+// a register's first and last mention in the code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterUse {
+    pub first: usize,
+    pub last: usize,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(name: &'a str, meta: &'a FuncMetadata) -> Self {
+        Self { name, meta }
+    }
+
+    /// where control goes from `pc` besides the next instruction, if anywhere
+    pub fn jump_target(&self, pc: usize) -> Option<usize> {
+        match self.meta.bytecode.get(pc)? {
+            OpCode::Jump { offset } | OpCode::JumpIfFalse { offset, .. } => {
+                usize::try_from(pc as i64 + *offset as i64).ok()
+            }
+            // a falsy register skips the jump that follows
+            OpCode::Test { .. } => Some(pc + 2),
+            _ => None,
+        }
+    }
+
+    /// where a SWITCH at `pc` may go: the target of every key of its table, then the default
+    pub fn switch_targets(&self, pc: usize) -> Option<(Vec<usize>, usize)> {
+        let OpCode::Switch { table, .. } = self.meta.bytecode.get(pc)? else {
+            return None;
+        };
+        let table = self.meta.jump_tables.get(*table as usize)?;
+        let to = |offset: i32| usize::try_from(pc as i64 + offset as i64).ok();
+        let targets: Option<Vec<usize>> = table.targets.iter().map(|offset| to(*offset)).collect();
+        Some((targets?, to(table.default)?))
+    }
+
+    // every place a jump instruction may go, without the next instruction
+    fn jump_edges(&self, pc: usize) -> Vec<usize> {
+        match self.switch_targets(pc) {
+            Some((mut targets, default)) => {
+                targets.push(default);
+                targets.sort_unstable();
+                targets.dedup();
+                targets
+            }
+            None => self.jump_target(pc).into_iter().collect(),
+        }
+    }
+
+    /// pc range every register is read or written over; a register that is reused for
+    /// several values spans all of them
+    pub fn register_uses(&self) -> BTreeMap<u16, RegisterUse> {
+        let mut uses: BTreeMap<u16, RegisterUse> = BTreeMap::new();
+        for (pc, op) in self.meta.bytecode.iter().enumerate() {
+            let mut mention = |reg: u16| {
+                uses.entry(reg)
+                    .and_modify(|u| u.last = pc)
+                    .or_insert(RegisterUse {
+                        first: pc,
+                        last: pc,
+                    });
+            };
+            op.visit_operands(|kind, idx| {
+                if kind == OperandKind::Reg {
+                    mention(idx);
+                }
+            });
+            // the values returned, and the operands concatenated, start at `start`
+            let window = match *op {
+                OpCode::Return { start, count } if count != MULTRET => start..start + count as u16,
+                OpCode::ConcatN { start, count, .. } => start..start + count,
+                _ => 0..0,
+            };
+            for reg in window {
+                mention(reg);
+            }
+        }
+        uses
+    }
+
+    // what an operand of the instruction refers to, e.g. the value of a constant
+    fn comment(&self, pc: usize, op: &OpCode) -> String {
+        let mut notes = Vec::new();
+        match op {
+            OpCode::LoadK { const_idx: k, .. }
+            | OpCode::GetGlobal { name_idx: k, .. }
+            | OpCode::SetGlobal { name_idx: k, .. }
+            | OpCode::GetField { key: k, .. }
+            | OpCode::SetField { key: k, .. }
+            | OpCode::AddK { const_idx: k, .. }
+            | OpCode::SubK { const_idx: k, .. } => notes.push(self.constant(*k as usize)),
+            OpCode::FnProto { proto_idx, .. } => notes.push(
+                self.meta
+                    .child_protos
+                    .get(*proto_idx as usize)
+                    .cloned()
+                    .unwrap_or_else(|| "?".to_string()),
+            ),
+            OpCode::Jump { .. } => {
+                if let Some(target) = self.jump_target(pc) {
+                    notes.push(format!("to {:03}", target));
+                }
+            }
+            OpCode::Test { .. } => notes.push(format!("to {:03} if falsy", pc + 2)),
+            OpCode::JumpIfFalse { .. } => {
+                if let Some(target) = self.jump_target(pc) {
+                    notes.push(format!("to {:03} if falsy", target));
+                }
+            }
+            OpCode::Switch { table, .. } => {
+                if let Some(table) = self.meta.jump_tables.get(*table as usize) {
+                    let high = table.low + table.targets.len() as i64 - 1;
+                    notes.push(format!("{}..{}", table.low, high));
+                }
+            }
+            _ => {}
+        }
+        if let Some(name) = self.meta.operand_names.get(&pc) {
+            notes.push(name.clone());
+        }
+        notes.join(", ")
+    }
+
+    fn constant(&self, idx: usize) -> String {
+        match self.meta.constants.get(idx) {
+            Some(val) => constant_literal(val),
+            None => format!("K{} out of range", idx),
+        }
+    }
+
+    fn line(&self, pc: usize) -> Option<u32> {
+        self.meta.line_info.get(pc).copied().filter(|&l| l > 0)
+    }
+
+    // one lane per jump, inner jumps closest to the code; every row gets the lanes' characters
+    // followed by '>' when it is a jump target
+    fn arrows(&self) -> Vec<String> {
+        let len = self.meta.bytecode.len();
+        let mut jumps: Vec<(usize, usize)> = (0..len)
+            .filter(|&pc| is_jump(&self.meta.bytecode[pc]))
+            .flat_map(|pc| self.jump_edges(pc).into_iter().map(move |to| (pc, to)))
+            .filter(|&(_, to)| to < len)
+            .collect();
+        jumps.sort_by_key(|&(from, to)| from.abs_diff(to));
+
+        let mut lanes: Vec<Vec<(usize, usize)>> = Vec::new();
+        for (from, to) in jumps {
+            let (lo, hi) = (from.min(to), from.max(to));
+            let free = lanes
+                .iter()
+                .position(|lane| lane.iter().all(|&(l, h)| h < lo || hi < l));
+            match free {
+                Some(i) => lanes[i].push((lo, hi)),
+                None => lanes.push(vec![(lo, hi)]),
+            }
+        }
+
+        let targets: HashSet<usize> = (0..len)
+            .filter(|&pc| is_jump(&self.meta.bytecode[pc]))
+            .flat_map(|pc| self.jump_edges(pc))
+            .collect();
+        let mut rows = vec![String::new(); len];
+        for (pc, row) in rows.iter_mut().enumerate() {
+            for lane in lanes.iter().rev() {
+                let ch = match lane.iter().find(|&&(l, h)| l <= pc && pc <= h) {
+                    Some(&(l, _)) if l == pc => '┌',
+                    Some(&(_, h)) if h == pc => '└',
+                    Some(_) => '│',
+                    None => ' ',
+                };
+                row.push(ch);
+            }
+            row.push(if targets.contains(&pc) { '>' } else { ' ' });
+        }
+        rows
+    }
+
+    /// human readable listing
+    pub fn render(&self) -> String {
+        let meta = self.meta;
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "function {} ({} instructions, {} locals, {} registers, {} upvalues)",
+            self.name,
+            meta.bytecode.len(),
+            meta.num_locals,
+            meta.max_stack_size,
+            meta.upvalues_metadata.len()
+        );
+
+        if !meta.constants.is_empty() {
+            let _ = writeln!(out, "constants:");
+            for (i, val) in meta.constants.iter().enumerate() {
+                let _ = writeln!(out, "  K{:<4} {}", i, constant_literal(val));
+            }
+        }
+        if !meta.child_protos.is_empty() {
+            let _ = writeln!(out, "protos:");
+            for (i, name) in meta.child_protos.iter().enumerate() {
+                let _ = writeln!(out, "  P{:<4} {}", i, name);
+            }
+        }
+        let switches: Vec<(usize, u16)> = (0..meta.bytecode.len())
+            .filter_map(|pc| match meta.bytecode[pc] {
+                OpCode::Switch { table, .. } => Some((pc, table)),
+                _ => None,
+            })
+            .collect();
+        if !switches.is_empty() {
+            let _ = writeln!(out, "jump tables:");
+            for (pc, table) in switches {
+                let Some((targets, default)) = self.switch_targets(pc) else {
+                    continue;
+                };
+                let low = meta.jump_tables[table as usize].low;
+                let targets: Vec<String> = targets
+                    .iter()
+                    .enumerate()
+                    .map(|(i, to)| format!("{}:{:03}", low + i as i64, to))
+                    .collect();
+                let _ = writeln!(
+                    out,
+                    "  T{:<4} {} else:{:03}",
+                    table,
+                    targets.join(" "),
+                    default
+                );
+            }
+        }
+
+        let _ = writeln!(out, "code:");
+        let arrows = self.arrows();
+        for (pc, op) in meta.bytecode.iter().enumerate() {
+            let line = match self.line(pc) {
+                Some(l) => format!("[{:>4}]", l),
+                None => "[   -]".to_string(),
+            };
+            let text = op.to_string();
+            let comment = self.comment(pc, op);
+            let mut row = format!("  {} {:03} {} {}", arrows[pc], pc, line, text);
+            if !comment.is_empty() {
+                row = format!("{:<48} ; {}", row, comment);
+            }
+            let _ = writeln!(out, "{}", row.trim_end());
+        }
+
+        let uses = self.register_uses();
+        if !uses.is_empty() {
+            let _ = writeln!(out, "registers:");
+            for (reg, range) in &uses {
+                let kind = if (*reg as usize) < meta.num_locals {
+                    "local"
+                } else {
+                    "temp"
+                };
+                let _ = writeln!(
+                    out,
+                    "  R{:<4} {:03}..{:03} {}",
+                    reg, range.first, range.last, kind
+                );
+            }
+        }
+        out
+    }
+
+    /// the listing as one JSON object, for tools
+    pub fn to_json(&self) -> String {
+        let meta = self.meta;
+        let constants: Vec<String> = meta.constants.iter().map(constant_json).collect();
+        let protos: Vec<String> = meta.child_protos.iter().map(|p| json_string(p)).collect();
+
+        let code: Vec<String> = meta
+            .bytecode
+            .iter()
+            .enumerate()
+            .map(|(pc, op)| {
+                let text = op.to_string();
+                let mut words = text.split_whitespace();
+                let mnemonic = words.next().unwrap_or_default();
+                let args: Vec<String> = words.map(json_string).collect();
+                format!(
+                    "{{\"pc\":{},\"line\":{},\"op\":{},\"args\":[{}],\"jump\":{},\"comment\":{}}}",
+                    pc,
+                    json_option(self.line(pc)),
+                    json_string(mnemonic),
+                    args.join(","),
+                    json_option(self.jump_target(pc)),
+                    json_string(&self.comment(pc, op))
+                )
+            })
+            .collect();
+
+        let registers: Vec<String> = self
+            .register_uses()
+            .iter()
+            .map(|(reg, range)| {
+                format!(
+                    "{{\"reg\":{},\"first\":{},\"last\":{},\"local\":{}}}",
+                    reg,
+                    range.first,
+                    range.last,
+                    (*reg as usize) < meta.num_locals
+                )
+            })
+            .collect();
+
+        let jump_tables: Vec<String> = meta
+            .jump_tables
+            .iter()
+            .map(|table| {
+                let targets: Vec<String> = table.targets.iter().map(|t| t.to_string()).collect();
+                format!(
+                    "{{\"low\":{},\"targets\":[{}],\"default\":{}}}",
+                    table.low,
+                    targets.join(","),
+                    table.default
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"name\":{},\"num_locals\":{},\"max_stack_size\":{},\"upvalues\":{},\"constants\":[{}],\"protos\":[{}],\"jump_tables\":[{}],\"code\":[{}],\"registers\":[{}]}}",
+            json_string(self.name),
+            meta.num_locals,
+            meta.max_stack_size,
+            meta.upvalues_metadata.len(),
+            constants.join(","),
+            protos.join(","),
+            jump_tables.join(","),
+            code.join(","),
+            registers.join(",")
+        )
+    }
+}
+
+// instructions that carry an offset, arrows are drawn for these
+fn is_jump(op: &OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::Jump { .. } | OpCode::JumpIfFalse { .. } | OpCode::Switch { .. }
+    )
+}
+
+// the entry first, the other functions by name
+fn ordered(funcs: &HashMap<String, FuncMetadata>) -> Vec<(&String, &FuncMetadata)> {
+    let mut list: Vec<_> = funcs.iter().collect();
+    list.sort_by_key(|(name, _)| (!name.ends_with("_start"), name.as_str()));
+    list
+}
+
+/// every function of a compiled module, as text
+pub fn disassemble_module(funcs: &HashMap<String, FuncMetadata>) -> String {
+    ordered(funcs)
+        .into_iter()
+        .map(|(name, meta)| Disassembler::new(name, meta).render())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// every function of a compiled module, as `{"functions": [...]}`
+pub fn module_to_json(funcs: &HashMap<String, FuncMetadata>) -> String {
+    let functions: Vec<String> = ordered(funcs)
+        .into_iter()
+        .map(|(name, meta)| Disassembler::new(name, meta).to_json())
+        .collect();
+    format!("{{\"functions\":[{}]}}", functions.join(","))
+}
+
+// a constant as it would be written in Lua source
+fn constant_literal(val: &Constant) -> String {
+    match val {
+        Constant::Nil => "nil".to_string(),
+        Constant::Integer(i) => i.to_string(),
+        Constant::Number(n) => format_number(*n),
+        Constant::String(s) => format!("{:?}", s),
+    }
+}
+
+fn constant_json(val: &Constant) -> String {
+    let (ty, value) = match val {
+        Constant::Nil => ("nil", "null".to_string()),
+        Constant::Integer(i) => ("integer", i.to_string()),
+        // JSON has no inf or nan
+        Constant::Number(n) if n.is_finite() => ("number", format!("{:?}", n)),
+        Constant::Number(n) => ("number", json_string(&format_number(*n))),
+        Constant::String(s) => ("string", json_string(s)),
+    };
+    format!("{{\"type\":\"{}\",\"value\":{}}}", ty, value)
+}
+
+fn json_option<T: ToString>(val: Option<T>) -> String {
+    val.map_or_else(|| "null".to_string(), |v| v.to_string())
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
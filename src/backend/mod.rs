@@ -1,3 +1,4 @@
 pub mod deserializer;
+pub mod disasm;
 pub mod translator;
 pub mod vm;
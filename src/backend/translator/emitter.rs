@@ -12,22 +12,200 @@
 // 2026-02-21: Changed the behavior of Return terminator,
 //             It should not move return values to R0, instead it should directly return the register where the return value is located,
 //             Otherwise it causes extremely unpredictable behaviors
+// 2026-02-22: Reworked jump backpatching: every branch target is recorded as a fixup against a
+//             basic-block label, and a dedicated fixup pass resolves them through the block
+//             address table once all blocks are emitted; unresolved labels are now reported
+//             instead of silently leaving a zero offset (which used to spin forever on the same PC)
+// 2026-02-22: Operand naming side-table for debug builds: the emitter remembers where each register
+//             came from (global, local, upvalue, field) and attaches that description to the PC of
+//             table access and call opcodes, so the VM can say "attempt to index a nil value (field 'config')"
+// 2026-02-24: IR Move lowers to MOVE, skipped when both registers were allocated to the same slot
+// 2026-02-24: Line table: every emitted opcode records the source line of the IR it was lowered from
+// 2026-02-24: MemberOf / SetMember with a literal member name lower to GETFIELD / SETFIELD, the name is a
+//            string constant and is interned (hash included) once when the chunk is loaded
+// 2026-02-24: A block that returns the result of its last Call lowers the pair to TAILCALL
+// 2026-02-24: TAILCALL is followed by a RETURN of the callee register, for native callees
+// 2026-02-24: The implicit return at the end of a function lowers to `RETURN R0 0`, it used to return
+//            whatever was left in R0
+// 2026-02-24: Drop stays a no-op in the register bytecode, its liveness information is used by the scanner
+// 2026-02-24: Span table next to the line table: every emitted opcode records the source span of the IR
+//            it was lowered from
+// 2026-02-24: Fused opcodes: Add / Sub with a number literal on the right lower to ADDK / SUBK, a Branch
+//            lowers to JMPFALSE towards the false block (plus a JUMP to the true block unless it comes
+//            next) instead of TEST, JUMP, JUMP; a literal that every reader takes as a constant operand
+//            is not loaded into its register at all
+// 2026-02-24: `emit` also returns the bytecode packed into 32-bit `Instruction`s, the form the VM runs
+// 2026-02-24: The constant pool holds `Constant`s; numbers are deduplicated by their bits, so -0.0 no longer
+//            turns into 0.0
+// 2026-02-24: A Switch terminator lowers to SWITCH and a jump table of the function, its entries are
+//            patched like jumps once every block has its address
+// 2026-02-24: ConcatN copies its operands into the scanner's concat window and lowers to one CONCATN
+// 2026-02-24: SetList pushes its values and lowers to SETLIST, like the arguments of a CALL
+// 2026-02-24: Operand widths are checked: a function with more constants, registers, call arguments,
+//            table fields, nested functions, upvalues or jump tables than its opcodes can address is
+//            an `EmitError` instead of silently wrapping indices
+// 2026-02-24: Several results: a Call lowers to CALL with the number of values its caller takes, followed
+//            by a GETRESULT per value after the first; a call that keeps every result (the last argument
+//            or return value) is not pushed, the CALL or RETURN after it takes its results with MULTRET.
+//            A return of several values copies them into the scanner's window
 
 use crate::backend::translator::scanner::{Scanner, VarKind};
-use crate::common::object::LuaValue;
-use crate::common::opcode::{OpCode, UnaryOpType};
-use crate::frontend::ir::{IRBinOp, IRFunction, IRInstruction, IROperand, IRTerminator, IRUnOp};
-use std::collections::HashMap;
+use crate::common::instruction::{Instruction, encode_all};
+use crate::common::object::Constant;
+use crate::common::opcode::{JumpTable, MULTRET, OpCode, SETLIST_BATCH, UnaryOpType};
+use crate::frontend::ir::{
+    IRBasicBlock, IRBinOp, IRFunction, IRInstruction, IROperand, IRTerminator, IRUnOp,
+};
+use crate::frontend::parser::ast::Span;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// pc -> symbolic description of the interesting operand of that instruction,
+// e.g. "global 'print'" for the callee of a CALL, or "field 'config'" for the table of a GETTABLE
+pub type OperandNames = HashMap<usize, String>;
+
+// what `emit` produces: the opcodes, their packed form, the constant pool, the operand names,
+// the line and span tables and the jump tables
+pub type EmittedFunction = (
+    Vec<OpCode>,
+    Vec<Instruction>,
+    Vec<Constant>,
+    OperandNames,
+    Vec<u32>,
+    Vec<Span>,
+    Vec<JumpTable>,
+);
+
+/// a function that does not fit the operands of its opcodes
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmitError {
+    // constant indices are 16 bits wide
+    TooManyConstants { function: String, count: usize },
+    // register indices are 16 bits wide
+    FrameTooLarge { function: String, registers: usize },
+    // the argument count of a call is 8 bits wide, MULTRET excluded
+    TooManyArguments { function: String, count: usize },
+    // so are the result count of a call and the value count of a return
+    TooManyResults { function: String, count: usize },
+    // the batch of a SETLIST is 16 bits wide
+    TooManyTableFields { function: String, count: usize },
+    // prototype indices are 16 bits wide
+    TooManyNestedFunctions { function: String, count: usize },
+    // upvalue indices are 16 bits wide
+    TooManyUpvalues { function: String, count: usize },
+    // jump table indices are 16 bits wide
+    TooManyJumpTables { function: String, count: usize },
+}
+
+impl fmt::Display for EmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmitError::TooManyConstants { function, count } => write!(
+                f,
+                "function '{}' has {} constants, at most {} are allowed",
+                function,
+                count,
+                u16::MAX as usize + 1
+            ),
+            EmitError::FrameTooLarge {
+                function,
+                registers,
+            } => write!(
+                f,
+                "function '{}' needs {} registers, at most {} are allowed",
+                function,
+                registers,
+                u16::MAX as usize + 1
+            ),
+            EmitError::TooManyArguments { function, count } => write!(
+                f,
+                "a call in function '{}' passes {} arguments, at most {} are allowed",
+                function,
+                count,
+                MULTRET - 1
+            ),
+            EmitError::TooManyResults { function, count } => write!(
+                f,
+                "a call or return in function '{}' takes {} values, at most {} are allowed",
+                function,
+                count,
+                MULTRET - 1
+            ),
+            EmitError::TooManyTableFields { function, count } => write!(
+                f,
+                "a table constructor in function '{}' has {} positional fields, at most {} are allowed",
+                function,
+                count,
+                (u16::MAX as usize + 1) * SETLIST_BATCH
+            ),
+            EmitError::TooManyNestedFunctions { function, count } => write!(
+                f,
+                "function '{}' has {} nested functions, at most {} are allowed",
+                function,
+                count,
+                u16::MAX as usize + 1
+            ),
+            EmitError::TooManyUpvalues { function, count } => write!(
+                f,
+                "function '{}' has {} upvalues, at most {} are allowed",
+                function,
+                count,
+                u16::MAX as usize + 1
+            ),
+            EmitError::TooManyJumpTables { function, count } => write!(
+                f,
+                "function '{}' has {} jump tables, at most {} are allowed",
+                function,
+                count,
+                u16::MAX as usize + 1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EmitError {}
+
+// a jump whose target is a basic-block label that has not been placed yet
+#[derive(Debug, Clone, Copy)]
+struct JumpFixup {
+    pc: usize,    // pc of the Jump instruction to patch
+    label: usize, // id of the target basic block
+}
+
+// a SWITCH whose jump table still holds block labels
+#[derive(Debug, Clone)]
+struct SwitchFixup {
+    pc: usize,    // pc of the Switch instruction
+    table: usize, // index of its jump table
+    targets: Vec<usize>,
+    default: usize,
+}
 
 pub struct BytecodeEmitter<'a> {
     func_ir: &'a IRFunction,
     scanner: &'a Scanner,
-    constants: Vec<LuaValue>,
+    constants: Vec<Constant>,
     bytecode: Vec<OpCode>,
-    const_map: HashMap<LuaValue, u16>,
+    const_map: HashMap<Constant, u16>,
     var_literals: HashMap<usize, IROperand>,
-    block_offsets: HashMap<usize, usize>,
-    pending_jumps: Vec<(usize, usize)>,
+    // literals no instruction reads from a register, see constant_only_literals
+    constant_only: HashSet<usize>,
+    // calls that keep every result, see multret_calls
+    multret: HashSet<usize>,
+    // basic block id -> pc of its first instruction
+    block_addrs: HashMap<usize, usize>,
+    fixups: Vec<JumpFixup>,
+    jump_tables: Vec<JumpTable>,
+    switch_fixups: Vec<SwitchFixup>,
+    // only collected when debug info is requested
+    debug_info: bool,
+    reg_origins: HashMap<usize, String>,
+    operand_names: OperandNames,
+    // pc -> source line / span, kept in step with bytecode
+    line_info: Vec<u32>,
+    span_info: Vec<Span>,
+    // the first operand that did not fit, emitting goes on and `emit` reports it
+    error: Option<EmitError>,
 }
 
 impl<'a> BytecodeEmitter<'a> {
@@ -39,42 +217,344 @@ impl<'a> BytecodeEmitter<'a> {
             bytecode: Vec::new(),
             const_map: HashMap::new(),
             var_literals: HashMap::new(),
-            block_offsets: HashMap::new(),
-            pending_jumps: Vec::new(),
+            constant_only: constant_only_literals(func),
+            multret: multret_calls(func),
+            block_addrs: HashMap::new(),
+            fixups: Vec::new(),
+            jump_tables: Vec::new(),
+            switch_fixups: Vec::new(),
+            debug_info: false,
+            reg_origins: HashMap::new(),
+            operand_names: HashMap::new(),
+            line_info: Vec::new(),
+            span_info: Vec::new(),
+            error: None,
         }
     }
 
-    pub fn emit(mut self) -> (Vec<OpCode>, Vec<LuaValue>) {
-        for block in &self.func_ir.basic_blocks {
-            self.block_offsets.insert(block.id, self.bytecode.len());
+    pub fn with_debug_info(mut self, enabled: bool) -> Self {
+        self.debug_info = enabled;
+        self
+    }
 
-            for instr in &block.instructions {
-                self.emit_instr(instr);
-            }
-            self.emit_terminator(&block.terminator);
+    pub fn emit(mut self) -> Result<EmittedFunction, EmitError> {
+        // every register of the frame fits an operand after this, see get_phys_reg
+        let registers = self
+            .scanner
+            .func_stack_info
+            .get(&self.func_ir.name)
+            .map_or(0, |&(_, max_usage)| max_usage);
+        if registers > u16::MAX as usize + 1 {
+            return Err(EmitError::FrameTooLarge {
+                function: self.func_ir.name.clone(),
+                registers,
+            });
         }
 
-        for (instr_pc, target_id) in self.pending_jumps.iter() {
-            if let Some(&target_pc) = self.block_offsets.get(target_id) {
-                let offset = (target_pc as i32) - (*instr_pc as i32);
+        let blocks = &self.func_ir.basic_blocks;
+        for (idx, block) in blocks.iter().enumerate() {
+            self.block_addrs.insert(block.id, self.bytecode.len());
 
-                if let Some(OpCode::Jump { offset: off }) = self.bytecode.get_mut(*instr_pc) {
-                    *off = offset;
+            let tail_call = tail_call_index(block);
+            for (i, instr) in block.instructions.iter().enumerate() {
+                if Some(i) == tail_call {
+                    self.emit_tail_call(instr);
+                } else {
+                    self.emit_instr(instr);
                 }
+                self.mark_source(
+                    block.lines.get(i).copied().unwrap_or(0),
+                    block.spans.get(i).copied().unwrap_or_default(),
+                );
+            }
+            // a tail call is the return
+            if tail_call.is_none() {
+                let next_block = blocks.get(idx + 1).map(|b| b.id);
+                self.emit_terminator(&block.terminator, next_block);
+                self.mark_source(block.terminator_line, block.terminator_span);
             }
         }
 
-        (self.bytecode, self.constants)
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.patch_jumps();
+
+        let code = encode_all(&self.bytecode);
+        Ok((
+            self.bytecode,
+            code,
+            self.constants,
+            self.operand_names,
+            self.line_info,
+            self.span_info,
+            self.jump_tables,
+        ))
+    }
+
+    // keep the first error only, later ones are usually caused by the same construct
+    fn fail(&mut self, err: EmitError) {
+        self.error.get_or_insert(err);
+    }
+
+    // a 16 bit operand, `err` describes the function when the index does not fit
+    fn operand_u16(&mut self, index: usize, err: fn(String, usize) -> EmitError) -> u16 {
+        u16::try_from(index).unwrap_or_else(|_| {
+            self.fail(err(self.func_ir.name.clone(), index + 1));
+            0
+        })
+    }
+
+    // whether the operand is the result of a call that keeps every result in the VM
+    fn is_multret(&self, op: Option<&IROperand>) -> bool {
+        matches!(op, Some(IROperand::Reg(id)) if self.multret.contains(id))
+    }
+
+    // PUSH the arguments of a call and return its argc, MULTRET when the last one is a call
+    // that keeps every result, those are not pushed but taken from the VM
+    fn push_args(&mut self, args: &[IROperand]) -> u8 {
+        let spread = self.is_multret(args.last());
+        let pushed = if spread {
+            &args[..args.len() - 1]
+        } else {
+            args
+        };
+        for arg in pushed {
+            let r_src = self.get_reg_index(arg);
+            self.bytecode.push(OpCode::Push { src: r_src });
+        }
+        if spread {
+            return MULTRET;
+        }
+        match u8::try_from(args.len()) {
+            Ok(argc) if argc != MULTRET => argc,
+            _ => {
+                self.fail(EmitError::TooManyArguments {
+                    function: self.func_ir.name.clone(),
+                    count: args.len(),
+                });
+                0
+            }
+        }
+    }
+
+    // the retc of a call or count of a return, MULTRET for None
+    fn value_count(&mut self, count: Option<usize>) -> u8 {
+        let Some(count) = count else {
+            return MULTRET;
+        };
+        match u8::try_from(count) {
+            Ok(n) if n != MULTRET => n,
+            _ => {
+                self.fail(EmitError::TooManyResults {
+                    function: self.func_ir.name.clone(),
+                    count,
+                });
+                0
+            }
+        }
+    }
+
+    // attribute every opcode emitted since the last call to the given line and span
+    fn mark_source(&mut self, line: usize, span: Span) {
+        self.line_info.resize(self.bytecode.len(), line as u32);
+        self.span_info.resize(self.bytecode.len(), span);
+    }
+
+    // remember a readable description of where the value in IR register dest comes from
+    fn record_origin(&mut self, instr: &IRInstruction) {
+        let origin = match instr {
+            IRInstruction::LoadGlobal {
+                dest,
+                name: IROperand::Reg(id),
+            } => match self.var_literals.get(id) {
+                Some(IROperand::ImmStr(s)) => Some((*dest, format!("global '{}'", s))),
+                _ => None,
+            },
+            IRInstruction::LoadLocal {
+                dest,
+                src: IROperand::Slot(slot),
+            } => self
+                .func_ir
+                .local_name(*slot)
+                .map(|name| (*dest, format!("local '{}'", name))),
+            IRInstruction::LoadUpVal {
+                dest,
+                src: IROperand::UpVal(slot),
+            } => self
+                .func_ir
+                .upvalues
+                .iter()
+                .find(|(_, uv)| uv.slot == *slot)
+                .map(|(name, _)| (*dest, format!("upvalue '{}'", name))),
+            IRInstruction::MemberOf {
+                dest,
+                member: IROperand::Reg(id),
+                ..
+            } => match self.var_literals.get(id) {
+                Some(IROperand::ImmStr(s)) => Some((*dest, format!("field '{}'", s))),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        if let Some((dest, desc)) = origin {
+            self.reg_origins.insert(dest, desc);
+        }
+    }
+
+    // attach the origin of operand to the instruction that is about to be emitted
+    fn name_operand(&mut self, operand: &IROperand) {
+        if let IROperand::Reg(id) = operand
+            && let Some(desc) = self.reg_origins.get(id)
+        {
+            self.operand_names.insert(self.bytecode.len(), desc.clone());
+        }
+    }
+
+    // emit a Jump placeholder towards the given block label,
+    // the real offset is filled in by patch_jumps
+    fn emit_jump_to(&mut self, label: usize) {
+        let pc = self.bytecode.len();
+        self.bytecode.push(OpCode::Jump { offset: 0 });
+        self.fixups.push(JumpFixup { pc, label });
+    }
+
+    // same for a JumpIfFalse on the given register
+    fn emit_jump_if_false_to(&mut self, reg: u16, label: usize) {
+        let pc = self.bytecode.len();
+        self.bytecode.push(OpCode::JumpIfFalse { reg, offset: 0 });
+        self.fixups.push(JumpFixup { pc, label });
+    }
+
+    // a Switch on the given register, its jump table is filled in by patch_jumps
+    fn emit_switch_to(&mut self, reg: u16, low: i64, targets: &[usize], default: usize) {
+        let pc = self.bytecode.len();
+        let table = self.jump_tables.len();
+        let table_idx = self.operand_u16(table, |function, count| EmitError::TooManyJumpTables {
+            function,
+            count,
+        });
+        self.bytecode.push(OpCode::Switch {
+            reg,
+            table: table_idx,
+        });
+        self.jump_tables.push(JumpTable {
+            low,
+            targets: vec![0; targets.len()],
+            default: 0,
+        });
+        self.switch_fixups.push(SwitchFixup {
+            pc,
+            table,
+            targets: targets.to_vec(),
+            default,
+        });
+    }
+
+    // offset from the jump at pc to the first instruction of the block
+    fn jump_offset(&self, pc: usize, label: usize) -> i32 {
+        let target_pc = *self.block_addrs.get(&label).unwrap_or_else(|| {
+            panic!(
+                "[Emitter Error] Jump at PC {} in '{}' targets unknown block _Tag{}",
+                pc, self.func_ir.name, label
+            )
+        });
+        target_pc as i32 - pc as i32
+    }
+
+    // resolve every recorded fixup through the block address table
+    // Jump offsets are relative to the pc of the jump itself, see VirtualMachine::handle_jump
+    fn patch_jumps(&mut self) {
+        for fixup in std::mem::take(&mut self.switch_fixups) {
+            let targets = fixup
+                .targets
+                .iter()
+                .map(|label| self.jump_offset(fixup.pc, *label))
+                .collect();
+            let default = self.jump_offset(fixup.pc, fixup.default);
+            let table = &mut self.jump_tables[fixup.table];
+            table.targets = targets;
+            table.default = default;
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let offset = self.jump_offset(fixup.pc, fixup.label);
+
+            match self.bytecode.get_mut(fixup.pc) {
+                Some(OpCode::Jump { offset: off } | OpCode::JumpIfFalse { offset: off, .. }) => {
+                    *off = offset
+                }
+                other => panic!(
+                    "[Emitter Error] Fixup at PC {} in '{}' does not point to a jump, got: {:?}",
+                    fixup.pc, self.func_ir.name, other
+                ),
+            }
+        }
     }
+
     fn emit_instr(&mut self, instr: &IRInstruction) {
+        if self.debug_info {
+            self.record_origin(instr);
+        }
+
         match instr {
+            IRInstruction::MemberOf {
+                dest,
+                collection,
+                member: IROperand::Reg(id),
+            } if matches!(self.var_literals.get(id), Some(IROperand::ImmStr(_))) => {
+                let d = self.get_phys_reg(VarKind::Reg(*dest));
+                let t = self.get_reg_index(collection);
+                let k = self.get_literal_as_const(id);
+                if self.debug_info {
+                    self.name_operand(collection);
+                }
+                self.bytecode.push(OpCode::GetField {
+                    dest: d,
+                    table: t,
+                    key: k,
+                });
+            }
+
+            IRInstruction::SetMember {
+                dest,
+                collection,
+                member: IROperand::Reg(id),
+                value,
+            } if matches!(self.var_literals.get(id), Some(IROperand::ImmStr(_))) => {
+                let t = self.get_reg_index(collection);
+                let k = self.get_literal_as_const(id);
+                let v = self.get_reg_index(value);
+                if self.debug_info {
+                    self.name_operand(collection);
+                }
+                self.bytecode.push(OpCode::SetField {
+                    table: t,
+                    key: k,
+                    value: v,
+                });
+                let d = self.get_phys_reg(VarKind::Reg(*dest));
+                self.bytecode.push(OpCode::Move { dest: d, src: v });
+            }
+
             IRInstruction::LoadImm { dest, value } => {
                 self.var_literals.insert(*dest, value.clone());
+                if self.constant_only.contains(dest) {
+                    return;
+                }
 
                 let d = self.get_phys_reg(VarKind::Reg(*dest));
                 match value {
                     IROperand::ImmFloat(f) => {
-                        let c_idx = self.add_constant(LuaValue::Number(*f));
+                        let c_idx = self.add_constant(Constant::Number(*f));
+                        self.bytecode.push(OpCode::LoadK {
+                            dest: d,
+                            const_idx: c_idx,
+                        });
+                    }
+                    IROperand::ImmInt(i) => {
+                        let c_idx = self.add_constant(Constant::Integer(*i));
                         self.bytecode.push(OpCode::LoadK {
                             dest: d,
                             const_idx: c_idx,
@@ -85,7 +565,7 @@ impl<'a> BytecodeEmitter<'a> {
                     }
                     IROperand::Nil => self.bytecode.push(OpCode::LoadNil { dest: d }),
                     IROperand::ImmStr(s) => {
-                        let c_idx = self.add_constant(LuaValue::TempString(s.clone()));
+                        let c_idx = self.add_constant(Constant::String(s.clone()));
                         self.bytecode.push(OpCode::LoadK {
                             dest: d,
                             const_idx: c_idx,
@@ -95,6 +575,33 @@ impl<'a> BytecodeEmitter<'a> {
                 }
             }
 
+            IRInstruction::Binary {
+                dest,
+                src1,
+                src2: IROperand::Reg(id),
+                operator: operator @ (IRBinOp::Add | IRBinOp::Sub),
+            } if matches!(
+                self.var_literals.get(id),
+                Some(IROperand::ImmInt(_) | IROperand::ImmFloat(_))
+            ) =>
+            {
+                let d = self.get_phys_reg(VarKind::Reg(*dest));
+                let l = self.get_reg_index(src1);
+                let k = self.get_literal_as_const(id);
+                self.bytecode.push(match operator {
+                    IRBinOp::Add => OpCode::AddK {
+                        dest: d,
+                        left: l,
+                        const_idx: k,
+                    },
+                    _ => OpCode::SubK {
+                        dest: d,
+                        left: l,
+                        const_idx: k,
+                    },
+                });
+            }
+
             IRInstruction::Binary {
                 dest,
                 src1,
@@ -202,6 +709,47 @@ impl<'a> BytecodeEmitter<'a> {
                     op,
                 });
             }
+            IRInstruction::SetList {
+                table,
+                batch,
+                values,
+            } => {
+                let t = self.get_reg_index(table);
+                for value in values {
+                    let v = self.get_reg_index(value);
+                    self.bytecode.push(OpCode::Push { src: v });
+                }
+                let fields = *batch * SETLIST_BATCH + values.len();
+                let batch = u16::try_from(*batch).unwrap_or_else(|_| {
+                    self.fail(EmitError::TooManyTableFields {
+                        function: self.func_ir.name.clone(),
+                        count: fields,
+                    });
+                    0
+                });
+                self.bytecode.push(OpCode::SetList {
+                    table: t,
+                    count: values.len() as u16,
+                    batch,
+                });
+            }
+            IRInstruction::ConcatN { dest, srcs } => {
+                let d = self.get_phys_reg(VarKind::Reg(*dest));
+                // the window is the top of the frame, so its registers fit too
+                let base = self.scanner.concat_base[&self.func_ir.name];
+                for (i, src) in srcs.iter().enumerate() {
+                    let s = self.get_reg_index(src);
+                    self.bytecode.push(OpCode::Move {
+                        dest: (base + i) as u16,
+                        src: s,
+                    });
+                }
+                self.bytecode.push(OpCode::ConcatN {
+                    dest: d,
+                    start: base as u16,
+                    count: srcs.len() as u16,
+                });
+            }
 
             IRInstruction::GetTable { dest, table, key }
             | IRInstruction::IndexOf {
@@ -217,6 +765,9 @@ impl<'a> BytecodeEmitter<'a> {
                 let d = self.get_phys_reg(VarKind::Reg(*dest));
                 let t = self.get_reg_index(table);
                 let k = self.get_reg_index(key);
+                if self.debug_info {
+                    self.name_operand(table);
+                }
                 self.bytecode.push(OpCode::GetTable {
                     dest: d,
                     table: t,
@@ -245,6 +796,9 @@ impl<'a> BytecodeEmitter<'a> {
                 let t = self.get_reg_index(table);
                 let k = self.get_reg_index(key);
                 let v = self.get_reg_index(value);
+                if self.debug_info {
+                    self.name_operand(table);
+                }
                 self.bytecode.push(OpCode::SetTable {
                     table: t,
                     key: k,
@@ -260,13 +814,14 @@ impl<'a> BytecodeEmitter<'a> {
                 size_hash,
             } => {
                 let d = self.get_phys_reg(VarKind::Reg(*dest));
-                let s_arr = if let IROperand::ImmFloat(f) = size_array {
-                    *f as u16
+                // hints only, a size that does not fit is capped
+                let s_arr = if let IROperand::ImmInt(n) = size_array {
+                    (*n).clamp(0, u16::MAX as i64) as u16
                 } else {
                     0
                 };
-                let s_hash = if let IROperand::ImmFloat(f) = size_hash {
-                    *f as u16
+                let s_hash = if let IROperand::ImmInt(n) = size_hash {
+                    (*n).clamp(0, u16::MAX as i64) as u16
                 } else {
                     0
                 };
@@ -277,6 +832,13 @@ impl<'a> BytecodeEmitter<'a> {
                 });
             }
 
+            IRInstruction::Move { dest, src } => {
+                let d = self.get_phys_reg(VarKind::Reg(*dest));
+                let s = self.get_reg_index(src);
+                if d != s {
+                    self.bytecode.push(OpCode::Move { dest: d, src: s });
+                }
+            }
             IRInstruction::FnProto { dest, func_proto } => {
                 let d = self.get_phys_reg(VarKind::Reg(*dest));
 
@@ -298,22 +860,28 @@ impl<'a> BytecodeEmitter<'a> {
                         proto_name, self.func_ir.name
                     ));
 
-                self.bytecode.push(OpCode::FnProto {
-                    dest: d,
-                    proto_idx: proto_idx as u16,
+                let proto_idx = self.operand_u16(proto_idx, |function, count| {
+                    EmitError::TooManyNestedFunctions { function, count }
                 });
+                self.bytecode.push(OpCode::FnProto { dest: d, proto_idx });
             }
-            IRInstruction::Call { dest, callee, args } => {
+            IRInstruction::Call {
+                dest,
+                callee,
+                args,
+                results,
+            } => {
                 let r_dest = self.get_phys_reg(VarKind::Reg(*dest));
                 let r_func = self.get_reg_index(callee);
-                for arg in args.iter() {
-                    let r_src = self.get_reg_index(arg);
-                    self.bytecode.push(OpCode::Push { src: r_src });
+                let argc = self.push_args(args);
+                if self.debug_info {
+                    self.name_operand(callee);
                 }
+                let retc = self.value_count(*results);
                 self.bytecode.push(OpCode::Call {
                     func_reg: r_func,
-                    argc: args.len() as u8,
-                    retc: 1,
+                    argc,
+                    retc,
                 });
                 if r_dest != r_func {
                     self.bytecode.push(OpCode::Move {
@@ -326,18 +894,18 @@ impl<'a> BytecodeEmitter<'a> {
             IRInstruction::LoadGlobal { dest, name } => {
                 let d = self.get_phys_reg(VarKind::Reg(*dest));
                 let name_idx = match name {
-                    IROperand::ImmStr(s) => self.add_constant(LuaValue::TempString(s.clone())),
+                    IROperand::ImmStr(s) => self.add_constant(Constant::String(s.clone())),
                     IROperand::Reg(id) => self.get_literal_as_const(id),
-                    _ => self.add_constant(LuaValue::Nil),
+                    _ => self.add_constant(Constant::Nil),
                 };
                 self.bytecode.push(OpCode::GetGlobal { dest: d, name_idx });
             }
 
             IRInstruction::StoreGlobal { dest, name, src } => {
                 let name_idx = match name {
-                    IROperand::ImmStr(s) => self.add_constant(LuaValue::TempString(s.clone())),
+                    IROperand::ImmStr(s) => self.add_constant(Constant::String(s.clone())),
                     IROperand::Reg(id) => self.get_literal_as_const(id),
-                    _ => self.add_constant(LuaValue::Nil),
+                    _ => self.add_constant(Constant::Nil),
                 };
 
                 let s = self.get_reg_index(src);
@@ -383,10 +951,10 @@ impl<'a> BytecodeEmitter<'a> {
                         src
                     );
                 };
-                self.bytecode.push(OpCode::GetUpVal {
-                    dest: d,
-                    upval_idx: *upval_idx as u16,
+                let upval_idx = self.operand_u16(*upval_idx, |function, count| {
+                    EmitError::TooManyUpvalues { function, count }
                 });
+                self.bytecode.push(OpCode::GetUpVal { dest: d, upval_idx });
             }
 
             IRInstruction::StoreUpVal { dest, dst, src } => {
@@ -399,39 +967,98 @@ impl<'a> BytecodeEmitter<'a> {
                         dst
                     );
                 };
-                self.bytecode.push(OpCode::SetUpVal {
-                    upval_idx: *upval_idx as u16,
-                    src: s,
+                let upval_idx = self.operand_u16(*upval_idx, |function, count| {
+                    EmitError::TooManyUpvalues { function, count }
                 });
+                self.bytecode.push(OpCode::SetUpVal { upval_idx, src: s });
                 let d = self.get_phys_reg(VarKind::Reg(*dest));
                 if d != s {
                     self.bytecode.push(OpCode::Move { dest: d, src: s });
                 }
             }
 
+            IRInstruction::CallResult { dest, index } => {
+                let d = self.get_phys_reg(VarKind::Reg(*dest));
+                // below the retc of the call, which was checked
+                self.bytecode.push(OpCode::GetResult {
+                    dest: d,
+                    index: u8::try_from(*index).unwrap_or(0),
+                });
+            }
+
             IRInstruction::Drop { src: _ } => {
-                // psedo instr, used for lifetime analysis, just ignore
+                // registers are not popped, nothing to emit; the scanner already ended the
+                // value's lifetime at its last read, so its register has been handed out again
             }
         }
     }
 
-    fn emit_terminator(&mut self, term: &IRTerminator) {
+    // the callee takes over the frame and returns straight to our caller
+    fn emit_tail_call(&mut self, instr: &IRInstruction) {
+        if self.debug_info {
+            self.record_origin(instr);
+        }
+        let IRInstruction::Call { callee, args, .. } = instr else {
+            unreachable!("tail call lowering on a non-call instruction");
+        };
+        let r_func = self.get_reg_index(callee);
+        let argc = self.push_args(args);
+        if self.debug_info {
+            self.name_operand(callee);
+        }
+        self.bytecode.push(OpCode::TailCall {
+            func_reg: r_func,
+            argc,
+        });
+        // only reached when the callee was native, TAILCALL kept all of its results
+        self.bytecode.push(OpCode::Return {
+            start: r_func,
+            count: MULTRET,
+        });
+    }
+
+    // next_block is the block laid out right after this one, control can fall through to it
+    fn emit_terminator(&mut self, term: &IRTerminator, next_block: Option<usize>) {
         match term {
-            IRTerminator::Return(vals) => {
-                if let Some(val) = vals.first() {
+            IRTerminator::Return(vals) => match vals.as_slice() {
+                // falling off the end returns nothing, like a bare `return`
+                [IROperand::Unit] | [] => {
+                    self.bytecode.push(OpCode::Return { start: 0, count: 0 });
+                }
+                // the values before a call that keeps every result are pushed, its results follow
+                [leading @ .., last] if self.is_multret(Some(last)) => {
+                    for val in leading {
+                        let r_src = self.get_reg_index(val);
+                        self.bytecode.push(OpCode::Push { src: r_src });
+                    }
+                    self.bytecode.push(OpCode::Return {
+                        start: 0,
+                        count: MULTRET,
+                    });
+                }
+                [val] => {
                     let r = self.get_reg_index(val);
                     self.bytecode.push(OpCode::Return { start: r, count: 1 });
-                } else {
-                    // todo: this is not ideal
-                    // if the function has no return,
-                    // should return a unit value rather than this random stuff in R0
-                    self.bytecode.push(OpCode::Return { start: 0, count: 0 });
                 }
-            }
+                vals => {
+                    let count = self.value_count(Some(vals.len()));
+                    // the window is the top of the frame, like for CONCATN
+                    let base = self.scanner.concat_base[&self.func_ir.name];
+                    for (i, val) in vals.iter().enumerate() {
+                        let s = self.get_reg_index(val);
+                        self.bytecode.push(OpCode::Move {
+                            dest: (base + i) as u16,
+                            src: s,
+                        });
+                    }
+                    self.bytecode.push(OpCode::Return {
+                        start: base as u16,
+                        count,
+                    });
+                }
+            },
             IRTerminator::Jump(target_id) => {
-                let current_pc = self.bytecode.len();
-                self.bytecode.push(OpCode::Jump { offset: 0 });
-                self.pending_jumps.push((current_pc, *target_id));
+                self.emit_jump_to(*target_id);
             }
             IRTerminator::Branch {
                 cond,
@@ -440,15 +1067,21 @@ impl<'a> BytecodeEmitter<'a> {
             } => {
                 let r_cond = self.get_reg_index(cond);
 
-                self.bytecode.push(OpCode::Test { reg: r_cond });
-
-                let true_jmp_pc = self.bytecode.len();
-                self.bytecode.push(OpCode::Jump { offset: 0 });
-                self.pending_jumps.push((true_jmp_pc, *br_true));
-
-                let false_jmp_pc = self.bytecode.len();
-                self.bytecode.push(OpCode::Jump { offset: 0 });
-                self.pending_jumps.push((false_jmp_pc, *br_false));
+                // JMPFALSE -> false block, then the true block; it usually comes next, e.g. the
+                // body of an if or a loop, otherwise a JUMP gets there
+                self.emit_jump_if_false_to(r_cond, *br_false);
+                if next_block != Some(*br_true) {
+                    self.emit_jump_to(*br_true);
+                }
+            }
+            IRTerminator::Switch {
+                value,
+                low,
+                targets,
+                default,
+            } => {
+                let r_value = self.get_reg_index(value);
+                self.emit_switch_to(r_value, *low, targets, *default);
             }
             _ => {}
         }
@@ -456,18 +1089,16 @@ impl<'a> BytecodeEmitter<'a> {
 
     fn get_literal_as_const(&mut self, reg_id: &usize) -> u16 {
         match self.var_literals.get(reg_id).cloned() {
-            Some(IROperand::ImmStr(s)) => self.add_constant(LuaValue::TempString(s)),
-            Some(IROperand::ImmFloat(f)) => self.add_constant(LuaValue::Number(f)),
-            _ => self.add_constant(LuaValue::Nil),
+            Some(IROperand::ImmStr(s)) => self.add_constant(Constant::String(s)),
+            Some(IROperand::ImmFloat(f)) => self.add_constant(Constant::Number(f)),
+            Some(IROperand::ImmInt(i)) => self.add_constant(Constant::Integer(i)),
+            _ => self.add_constant(Constant::Nil),
         }
     }
 
     fn get_phys_reg(&self, var: VarKind) -> u16 {
-        *self
-            .scanner
-            .reg_map
-            .get(&(self.func_ir.name.clone(), var))
-            .unwrap() as u16
+        let reg = self.scanner.reg_map[&(self.func_ir.name.clone(), var)];
+        u16::try_from(reg).expect("[Emitter Error] register outside the frame checked by emit")
     }
 
     fn get_reg_index(&self, op: &IROperand) -> u16 {
@@ -478,13 +1109,122 @@ impl<'a> BytecodeEmitter<'a> {
         }
     }
 
-    fn add_constant(&mut self, val: LuaValue) -> u16 {
+    fn add_constant(&mut self, val: Constant) -> u16 {
         if let Some(&idx) = self.const_map.get(&val) {
             return idx;
         }
-        let idx = self.constants.len() as u16;
+        let Ok(idx) = u16::try_from(self.constants.len()) else {
+            self.fail(EmitError::TooManyConstants {
+                function: self.func_ir.name.clone(),
+                count: self.constants.len() + 1,
+            });
+            return 0;
+        };
         self.constants.push(val.clone());
         self.const_map.insert(val, idx);
         idx
     }
 }
+
+// index of the block's last instruction if it is a Call whose result is the one value returned
+fn tail_call_index(block: &IRBasicBlock) -> Option<usize> {
+    let IRTerminator::Return(vals) = &block.terminator else {
+        return None;
+    };
+    let last = block.instructions.len().checked_sub(1)?;
+    match (vals.as_slice(), &block.instructions[last]) {
+        ([IROperand::Reg(ret)], IRInstruction::Call { dest, .. }) if ret == dest => Some(last),
+        _ => None,
+    }
+}
+
+// IR registers of the calls that keep every result (the last argument of a call or value of a
+// return), the CALL or RETURN reading one takes the results from the VM instead of a register
+fn multret_calls(func: &IRFunction) -> HashSet<usize> {
+    func.basic_blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|instr| match instr {
+            IRInstruction::Call {
+                dest,
+                results: None,
+                ..
+            } => Some(*dest),
+            _ => None,
+        })
+        .collect()
+}
+
+// IR registers holding a literal that every reader takes as a constant operand instead
+// (GETFIELD / SETFIELD keys, global names, ADDK / SUBK operands), so loading them can be left out
+fn constant_only_literals(func: &IRFunction) -> HashSet<usize> {
+    let mut literals: HashMap<usize, &IROperand> = HashMap::new();
+    for block in &func.basic_blocks {
+        for instr in &block.instructions {
+            if let IRInstruction::LoadImm {
+                dest,
+                value:
+                    value @ (IROperand::ImmStr(_) | IROperand::ImmInt(_) | IROperand::ImmFloat(_)),
+            } = instr
+            {
+                literals.insert(*dest, value);
+            }
+        }
+    }
+    let literal = |op: &IROperand| match op {
+        IROperand::Reg(id) => literals.get(id).copied(),
+        _ => None,
+    };
+    let is_str = |op: &IROperand| matches!(literal(op), Some(IROperand::ImmStr(_)));
+    let is_num = |op: &IROperand| {
+        matches!(
+            literal(op),
+            Some(IROperand::ImmInt(_) | IROperand::ImmFloat(_))
+        )
+    };
+
+    let mut read = HashSet::new();
+    for block in &func.basic_blocks {
+        for instr in &block.instructions {
+            // the operand the emitter turns into a constant, the same choices as in emit_instr
+            let folded = match instr {
+                IRInstruction::MemberOf { member, .. }
+                | IRInstruction::SetMember { member, .. }
+                    if is_str(member) =>
+                {
+                    Some(member)
+                }
+                IRInstruction::LoadGlobal { name, .. }
+                | IRInstruction::StoreGlobal { name, .. }
+                    if is_str(name) =>
+                {
+                    Some(name)
+                }
+                IRInstruction::Binary {
+                    operator: IRBinOp::Add | IRBinOp::Sub,
+                    src2,
+                    ..
+                } if is_num(src2) => Some(src2),
+                // the end of a lifetime is not a read
+                IRInstruction::Drop { .. } => continue,
+                _ => None,
+            };
+            for op in instr.operands() {
+                if let IROperand::Reg(id) = op
+                    && !folded.is_some_and(|f| std::ptr::eq(f, op))
+                {
+                    read.insert(*id);
+                }
+            }
+        }
+        for op in block.terminator.operands() {
+            if let IROperand::Reg(id) = op {
+                read.insert(*id);
+            }
+        }
+    }
+    literals
+        .into_keys()
+        .filter(|id| !read.contains(id))
+        .collect()
+}
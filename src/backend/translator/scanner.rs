@@ -15,9 +15,27 @@
 //            but by the IR's inability to handle mutual calls between `local functions`;
 //            such calls are treated as closure behaviors among multiple functions within the `_start` scope.
 // 2026-02-20: Added support for upvalue tracking in the Scanner
+// 2026-02-24: Move may define its destination on several paths, every definition also counts as a use
+//            so the register stays allocated from the first write to the last one.
+// 2026-02-24: Lifetimes follow control flow: live-in/live-out sets are computed per basic block and a
+//            register's interval is widened over every block boundary it is live across, so a value that
+//            flows around a loop back edge or into a block laid out earlier keeps its register.
+//            Each register still gets a single interval, ranges are not split.
+// 2026-02-24: Drop is an end-of-life marker rather than a use: a discarded value's interval ends at its
+//            last real read (or its definition), so the temporaries of expression statements and the
+//            results of stores free their registers right away.
+// 2026-02-24: `local_names` keeps the source name of every local slot (slot n lives in register n), for
+//            debug.getlocal.
+// 2026-02-24: ConcatN operands are gathered in a window of registers above the allocated ones,
+//            `concat_base` is where it starts and the stack size includes it.
+// 2026-02-24: The values of a return with more than one of them are gathered in the same window.
+// 2026-02-24: `analysis` / `analyses` report the allocation of a function as a `FunctionAnalysis`,
+//            with a JSON export, so tools no longer read the maps keyed by (function, VarKind).
 
+use crate::backend::disasm::json_string;
 use crate::frontend::ir::{self, IRInstruction, IRModule, IROperand, IRTerminator};
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum VarKind {
@@ -25,6 +43,15 @@ pub enum VarKind {
     Slot(usize), // %local_n
 }
 
+impl fmt::Display for VarKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VarKind::Reg(id) => write!(f, "%{}", id),
+            VarKind::Slot(id) => write!(f, "%local_{}", id),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Lifetime {
     pub start: usize,
@@ -33,12 +60,75 @@ pub struct Lifetime {
     pub inferred_type: Option<String>,
 }
 
+/// a local slot or virtual register and the physical register it was given
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Allocation {
+    pub var: VarKind,
+    pub reg: usize,
+}
+
+/// what the scanner worked out for one function, see `Scanner::analysis`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionAnalysis {
+    pub name: String,
+    pub num_locals: usize,
+    // registers the frame needs, the ConcatN window included
+    pub max_stack: usize,
+    // local slots by id, slot n is register n
+    pub locals: Vec<Allocation>,
+    // virtual registers by the position their lifetime starts at
+    pub temps: Vec<Allocation>,
+    // first and last instruction position each variable is live at
+    pub lifetimes: HashMap<VarKind, (usize, usize)>,
+    // the inferred types, a variable without one is dynamic
+    pub types: HashMap<VarKind, String>,
+}
+
+impl FunctionAnalysis {
+    /// `{"function", "locals": count, "max_stack", "registers": [{"name", "kind", "type", "reg",
+    /// "start", "end"}]}`, the locals first
+    pub fn to_json(&self) -> String {
+        let registers: Vec<String> = self
+            .locals
+            .iter()
+            .map(|a| (a, "local"))
+            .chain(self.temps.iter().map(|a| (a, "temp")))
+            .map(|(a, kind)| {
+                let (start, end) = self.lifetimes[&a.var];
+                format!(
+                    "{{\"name\":{},\"kind\":\"{}\",\"type\":{},\"reg\":{},\"start\":{},\"end\":{}}}",
+                    json_string(&a.var.to_string()),
+                    kind,
+                    self.types
+                        .get(&a.var)
+                        .map_or_else(|| "null".to_string(), |t| json_string(t)),
+                    a.reg,
+                    start,
+                    end
+                )
+            })
+            .collect();
+        format!(
+            "{{\"function\":{},\"locals\":{},\"max_stack\":{},\"registers\":[{}]}}",
+            json_string(&self.name),
+            self.num_locals,
+            self.max_stack,
+            registers.join(",")
+        )
+    }
+}
+
 pub struct Scanner {
     pub lifetimes: HashMap<(String, VarKind), Lifetime>,
     pub global_vars: HashSet<String>,
     pub reg_map: HashMap<(String, VarKind), usize>,
     pub func_stack_info: HashMap<String, (usize, usize)>,
     pub child_protos: HashMap<String, Vec<String>>,
+    // function -> source name of each local slot, indexed by slot
+    pub local_names: HashMap<String, Vec<String>>,
+    // function -> first register of the window the operands of a ConcatN, or the values of a
+    // return, are copied into
+    pub concat_base: HashMap<String, usize>,
     instr_count: usize,
 }
 
@@ -50,10 +140,62 @@ impl Scanner {
             reg_map: HashMap::new(),
             func_stack_info: HashMap::new(),
             child_protos: HashMap::new(),
+            local_names: HashMap::new(),
+            concat_base: HashMap::new(),
             instr_count: 0,
         }
     }
 
+    /// the allocation of a scanned function, None for a function that was not scanned
+    pub fn analysis(&self, func_name: &str) -> Option<FunctionAnalysis> {
+        let &(num_locals, max_stack) = self.func_stack_info.get(func_name)?;
+        let mut analysis = FunctionAnalysis {
+            name: func_name.to_string(),
+            num_locals,
+            max_stack,
+            locals: Vec::new(),
+            temps: Vec::new(),
+            lifetimes: HashMap::new(),
+            types: HashMap::new(),
+        };
+        for (key, lt) in &self.lifetimes {
+            if key.0 != func_name {
+                continue;
+            }
+            let Some(&reg) = self.reg_map.get(key) else {
+                continue;
+            };
+            let var = key.1.clone();
+            analysis.lifetimes.insert(var.clone(), (lt.start, lt.end));
+            if let Some(ty) = &lt.inferred_type {
+                analysis.types.insert(var.clone(), ty.clone());
+            }
+            match var {
+                VarKind::Slot(_) => analysis.locals.push(Allocation { var, reg }),
+                VarKind::Reg(_) => analysis.temps.push(Allocation { var, reg }),
+            }
+        }
+        let id = |var: &VarKind| match var {
+            VarKind::Reg(id) | VarKind::Slot(id) => *id,
+        };
+        analysis.locals.sort_by_key(|a| id(&a.var));
+        let lifetimes = &analysis.lifetimes;
+        analysis
+            .temps
+            .sort_by_key(|a| (lifetimes[&a.var].0, id(&a.var)));
+        Some(analysis)
+    }
+
+    /// `analysis` of every scanned function, by name
+    pub fn analyses(&self) -> Vec<FunctionAnalysis> {
+        let mut names: Vec<&String> = self.func_stack_info.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .filter_map(|name| self.analysis(name))
+            .collect()
+    }
+
     pub fn global_scan(&mut self, module: &IRModule) {
         for func in &module.functions {
             self.instr_count = 0;
@@ -66,14 +208,59 @@ impl Scanner {
         for (_, &slot_id) in &func.local_variables {
             self.record_def(&func.name, VarKind::Slot(slot_id), true, None);
         }
+        let names = (0..func.local_variables.len())
+            .map(|slot| func.local_name(slot).unwrap_or_default().to_string())
+            .collect();
+        self.local_names.insert(func.name.clone(), names);
 
+        // position of each block's first instruction and of its terminator
+        let mut block_spans = Vec::with_capacity(func.basic_blocks.len());
         for block in &func.basic_blocks {
+            let first = self.instr_count + 1;
             for instr in &block.instructions {
                 self.instr_count += 1;
                 self.process_instr(&func.name, instr);
             }
             self.instr_count += 1;
             self.process_terminator(&func.name, &block.terminator);
+            block_spans.push((first, self.instr_count));
+        }
+
+        self.extend_over_blocks(func, &block_spans);
+    }
+
+    // the linear pass only sees the layout; widen each register's interval to every use,
+    // wherever it is laid out, and over every block boundary the register is live across
+    fn extend_over_blocks(&mut self, func: &ir::IRFunction, block_spans: &[(usize, usize)]) {
+        let (live_in, live_out) = block_liveness(func);
+        for (i, block) in func.basic_blocks.iter().enumerate() {
+            let (first, term) = block_spans[i];
+            for (pos, instr) in (first..).zip(&block.instructions) {
+                for op in reads(instr) {
+                    if let IROperand::Reg(id) = op {
+                        self.extend_lifetime(&func.name, *id, pos);
+                    }
+                }
+            }
+            for op in block.terminator.operands() {
+                if let IROperand::Reg(id) = op {
+                    self.extend_lifetime(&func.name, *id, term);
+                }
+            }
+            for &id in &live_in[i] {
+                self.extend_lifetime(&func.name, id, first);
+            }
+            for &id in &live_out[i] {
+                self.extend_lifetime(&func.name, id, term);
+            }
+        }
+    }
+
+    fn extend_lifetime(&mut self, func_name: &str, id: usize, pos: usize) {
+        let key = (func_name.to_string(), VarKind::Reg(id));
+        if let Some(lt) = self.lifetimes.get_mut(&key) {
+            lt.start = lt.start.min(pos);
+            lt.end = lt.end.max(pos);
         }
     }
 
@@ -127,8 +314,33 @@ impl Scanner {
             max_usage = max_usage.max(active.len() + num_slots);
         }
 
+        // no allocated register lives there, so the operands can be copied in whatever order;
+        // a return of several values gathers them there too
+        let concat_window = func
+            .basic_blocks
+            .iter()
+            .flat_map(|block| {
+                let returned = match &block.terminator {
+                    IRTerminator::Return(vals) if vals.len() > 1 => Some(vals.len()),
+                    _ => None,
+                };
+                block
+                    .instructions
+                    .iter()
+                    .filter_map(|instr| match instr {
+                        IRInstruction::ConcatN { srcs, .. } => Some(srcs.len()),
+                        _ => None,
+                    })
+                    .chain(returned)
+            })
+            .max()
+            .unwrap_or(0);
+        if concat_window > 0 {
+            self.concat_base.insert(func_name.clone(), max_usage);
+        }
+
         self.func_stack_info
-            .insert(func_name.clone(), (num_slots, max_usage));
+            .insert(func_name.clone(), (num_slots, max_usage + concat_window));
     }
 
     fn process_instr(&mut self, func_name: &str, instr: &IRInstruction) {
@@ -136,6 +348,7 @@ impl Scanner {
             IRInstruction::LoadImm { dest, value } => {
                 let type_str = match value {
                     IROperand::ImmFloat(_) => "Float",
+                    IROperand::ImmInt(_) => "Integer",
                     IROperand::ImmStr(_) => "String",
                     IROperand::ImmBool(_) => "Boolean",
                     IROperand::Nil => "Nil",
@@ -181,7 +394,24 @@ impl Scanner {
                 self.record_def(func_name, VarKind::Reg(*dest), false, None);
                 self.record_use(func_name, src);
             }
-            IRInstruction::Call { dest, callee, args } => {
+            IRInstruction::SetList { table, values, .. } => {
+                self.record_use(func_name, table);
+                for value in values {
+                    self.record_use(func_name, value);
+                }
+            }
+            IRInstruction::CallResult { dest, .. } => {
+                self.record_def(func_name, VarKind::Reg(*dest), false, None);
+            }
+            IRInstruction::ConcatN { dest, srcs } => {
+                self.record_def(func_name, VarKind::Reg(*dest), false, Some("String"));
+                for src in srcs {
+                    self.record_use(func_name, src);
+                }
+            }
+            IRInstruction::Call {
+                dest, callee, args, ..
+            } => {
                 self.record_def(func_name, VarKind::Reg(*dest), false, None);
                 self.record_use(func_name, callee);
 
@@ -227,8 +457,8 @@ impl Scanner {
                 // similar to LoadUpVal, the use of upvalue is not recorded here
                 self.record_use(func_name, src);
             }
-            IRInstruction::Drop { src } => {
-                self.record_use(func_name, src);
+            IRInstruction::Drop { .. } => {
+                // the value is not read, its lifetime ends at its last real use
             }
             IRInstruction::NewTable {
                 dest,
@@ -297,6 +527,12 @@ impl Scanner {
                 self.record_use(func_name, value);
             }
 
+            IRInstruction::Move { dest, src } => {
+                self.record_def(func_name, VarKind::Reg(*dest), false, None);
+                // a later write to dest must not land in a register reused in between
+                self.record_use(func_name, &IROperand::Reg(*dest));
+                self.record_use(func_name, src);
+            }
             IRInstruction::FnProto { dest, func_proto } => {
                 self.record_def(func_name, VarKind::Reg(*dest), false, Some("Function"));
                 self.record_use(func_name, func_proto);
@@ -345,10 +581,95 @@ impl Scanner {
                     self.record_use(func_name, op);
                 }
             }
-            IRTerminator::Branch { cond, .. } => {
+            IRTerminator::Branch { cond, .. } | IRTerminator::Switch { value: cond, .. } => {
                 self.record_use(func_name, cond);
             }
             _ => {}
         }
     }
 }
+
+// operands an instruction reads, Drop only marks the end of its operand's life
+fn reads(instr: &IRInstruction) -> Vec<&IROperand> {
+    match instr {
+        IRInstruction::Drop { .. } => Vec::new(),
+        _ => instr.operands(),
+    }
+}
+
+// live-in and live-out registers of every block, by block index
+fn block_liveness(func: &ir::IRFunction) -> (Vec<HashSet<usize>>, Vec<HashSet<usize>>) {
+    let blocks = &func.basic_blocks;
+    let index: HashMap<usize, usize> = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| (block.id, i))
+        .collect();
+
+    // registers read before being written in the block, and registers written in it
+    let mut uses = vec![HashSet::new(); blocks.len()];
+    let mut defs = vec![HashSet::new(); blocks.len()];
+    let mut succs = vec![Vec::new(); blocks.len()];
+    for (i, block) in blocks.iter().enumerate() {
+        let reads = block
+            .instructions
+            .iter()
+            .map(|instr| (reads(instr), instr.def_reg()))
+            .chain(std::iter::once((block.terminator.operands(), None)));
+        for (ops, def) in reads {
+            for op in ops {
+                if let IROperand::Reg(id) = op
+                    && !defs[i].contains(id)
+                {
+                    uses[i].insert(*id);
+                }
+            }
+            if let Some(id) = def {
+                defs[i].insert(id);
+            }
+        }
+
+        match &block.terminator {
+            IRTerminator::Jump(target) => succs[i].extend(index.get(target)),
+            IRTerminator::Branch {
+                br_true, br_false, ..
+            } => {
+                succs[i].extend(index.get(br_true));
+                succs[i].extend(index.get(br_false));
+            }
+            IRTerminator::Switch {
+                targets, default, ..
+            } => {
+                succs[i].extend(targets.iter().filter_map(|target| index.get(target)));
+                succs[i].extend(index.get(default));
+            }
+            IRTerminator::FallThrough => {
+                if i + 1 < blocks.len() {
+                    succs[i].push(i + 1);
+                }
+            }
+            IRTerminator::Return(_) => {}
+        }
+    }
+
+    let mut live_in: Vec<HashSet<usize>> = vec![HashSet::new(); blocks.len()];
+    let mut live_out: Vec<HashSet<usize>> = vec![HashSet::new(); blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..blocks.len()).rev() {
+            let out: HashSet<usize> = succs[i]
+                .iter()
+                .flat_map(|&s| live_in[s].iter().copied())
+                .collect();
+            let mut inn = uses[i].clone();
+            inn.extend(out.difference(&defs[i]));
+            if out != live_out[i] || inn != live_in[i] {
+                live_out[i] = out;
+                live_in[i] = inn;
+                changed = true;
+            }
+        }
+    }
+    (live_in, live_out)
+}
@@ -0,0 +1,108 @@
+// Myula VM configuration
+// Changelog:
+// 2026-02-24: Initial version. `VmConfig` is given to `VirtualMachine::with_config`: library groups that
+//            reach outside the VM (io, os, package / require) can be left out of the standard library,
+//            the heap gets a per-VM byte limit and global assignments can be restricted to a whitelist.
+// 2026-02-24: The call depth limit and the initial GC threshold are configurable as well (myulac --max-stack,
+//            --max-heap and --gc-threshold set the limits of the VM it runs).
+// 2026-02-24: `debug` toggles the debug library, a script could otherwise replace the host's hook.
+
+use std::collections::HashSet;
+
+/// limits of a VM and what scripts running on it are allowed to do, `VmConfig::default()` allows everything
+#[derive(Debug, Clone)]
+pub struct VmConfig {
+    // register the io library (reads stdin and files, writes stdout)
+    pub io: bool,
+    // register the os library (clock and date)
+    pub os: bool,
+    // register require, dofile and the package table, which load and run files
+    pub package: bool,
+    // register the debug library (hooks and stack inspection)
+    pub debug: bool,
+    // bytes the heap may hold at once, allocations beyond it raise OutOfMemory
+    pub heap_limit: usize,
+    // frames the call stack may hold, a call beyond it raises StackOverflow
+    pub max_call_stack: usize,
+    // heap size that triggers the first full collection, later ones grow by the GC pause
+    pub gc_threshold: usize,
+    // globals a script may assign to, None allows every name; the host can still set any global
+    pub global_whitelist: Option<HashSet<String>>,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        Self {
+            io: true,
+            os: true,
+            package: true,
+            debug: true,
+            heap_limit: crate::backend::vm::HARD_MEMORY_LIMIT,
+            max_call_stack: crate::backend::vm::MAX_CALL_STACK,
+            gc_threshold: crate::backend::vm::VM_THRESHOLD,
+            global_whitelist: None,
+        }
+    }
+}
+
+impl VmConfig {
+    /// nothing that reaches outside the VM or into the host's hooks: no io, os, require, dofile or debug;
+    /// heap and globals are not limited
+    pub fn sandboxed() -> Self {
+        Self {
+            io: false,
+            os: false,
+            package: false,
+            debug: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_io(mut self, enabled: bool) -> Self {
+        self.io = enabled;
+        self
+    }
+
+    pub fn with_os(mut self, enabled: bool) -> Self {
+        self.os = enabled;
+        self
+    }
+
+    pub fn with_package(mut self, enabled: bool) -> Self {
+        self.package = enabled;
+        self
+    }
+
+    pub fn with_debug(mut self, enabled: bool) -> Self {
+        self.debug = enabled;
+        self
+    }
+
+    /// the library tables and builtins take a few kilobytes too, registering them
+    /// panics if the limit does not leave room for them
+    pub fn with_heap_limit(mut self, bytes: usize) -> Self {
+        self.heap_limit = bytes;
+        self
+    }
+
+    pub fn with_max_call_stack(mut self, frames: usize) -> Self {
+        self.max_call_stack = frames;
+        self
+    }
+
+    /// a larger threshold trades memory for fewer collections on allocation-heavy workloads
+    pub fn with_gc_threshold(mut self, bytes: usize) -> Self {
+        self.gc_threshold = bytes;
+        self
+    }
+
+    /// only let scripts assign to the given globals
+    pub fn with_global_whitelist<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.global_whitelist = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+}
@@ -0,0 +1,200 @@
+// Myula coroutines
+// Changelog:
+// 2026-02-24: Initial version. A coroutine owns a call stack and a value stack; resuming it swaps them into
+//            the VM and runs a nested dispatch loop until the coroutine returns, fails or yields. The stacks
+//            of the resumer are parked in `resumers` meanwhile, open upvalues remember which thread's stack
+//            they point into.
+// 2026-02-24: Every value crosses a resume or yield: the values of a resume are delivered to the yield call
+//            like the results of any call, as many as it takes.
+
+use crate::backend::vm::VirtualMachine;
+use crate::backend::vm::error::{ErrorKind, VMError};
+use crate::backend::vm::heap::Gc;
+use crate::backend::vm::stack::{GlobalStack, StackFrame};
+use crate::common::object::{CoroutineStatus, LuaCoroutine, LuaValue};
+use std::mem;
+
+// nested resumes recurse on the Rust stack
+const MAX_RESUME_DEPTH: usize = 200;
+
+/// the stacks of a thread that resumed a coroutine and waits for it to yield or return
+pub struct Resumer {
+    // None for the main thread
+    pub thread: Option<Gc<LuaCoroutine>>,
+    pub value_stack: GlobalStack,
+    pub call_stack: Vec<StackFrame>,
+}
+
+impl VirtualMachine {
+    pub fn create_coroutine(&mut self, func: LuaValue) -> Result<LuaValue, VMError> {
+        if !matches!(func, LuaValue::Function(_)) {
+            return Err(self.error(ErrorKind::TypeError(format!(
+                "TypeMismatchException: a coroutine body must be a Lua function (actual type: '{:?}')",
+                func
+            ))));
+        }
+        let ptr = self
+            .heap
+            .alloc_coroutine(LuaCoroutine::new(func))
+            .ok_or_else(|| self.error(ErrorKind::OutOfMemory))?;
+        Ok(LuaValue::Coroutine(ptr))
+    }
+
+    /// run the coroutine until it yields or returns and hand back what it yielded or returned;
+    /// an error inside it is returned as is and leaves it dead
+    pub(crate) fn resume_coroutine(
+        &mut self,
+        co: Gc<LuaCoroutine>,
+        args: Vec<LuaValue>,
+    ) -> Result<Vec<LuaValue>, VMError> {
+        let status = self.heap.data(co).status;
+        if status != CoroutineStatus::Suspended {
+            let what = if status == CoroutineStatus::Dead {
+                "dead"
+            } else {
+                "non-suspended"
+            };
+            return Err(self.error(ErrorKind::InvalidCall(format!(
+                "IllegalStateException: cannot resume {} coroutine",
+                what
+            ))));
+        }
+        if self.resumers.len() >= MAX_RESUME_DEPTH {
+            return Err(self.error(ErrorKind::StackOverflow));
+        }
+
+        let started = self.heap.data(co).is_started();
+        self.switch_to(co);
+        let outcome = if started {
+            // the values of this resume are what the pending yield returns
+            if let Some((dest, retc)) = self.heap.data_mut(co).resume_dest.take() {
+                self.deliver_results(dest, retc, args);
+            }
+            self.run_coroutine()
+        } else {
+            self.start_coroutine(co, args)
+                .and_then(|_| self.run_coroutine())
+        };
+
+        let result = match outcome {
+            Ok(Some(values)) => {
+                self.heap.data_mut(co).status = CoroutineStatus::Suspended;
+                Ok(values)
+            }
+            Ok(None) => {
+                self.heap.data_mut(co).status = CoroutineStatus::Dead;
+                Ok(mem::take(&mut self.return_buffer))
+            }
+            Err(e) => {
+                while self.pop_frame().is_some() {}
+                self.heap.data_mut(co).status = CoroutineStatus::Dead;
+                Err(e)
+            }
+        };
+        self.switch_back(co);
+        result
+    }
+
+    /// called by coroutine.yield: the running coroutine stops after the current instruction
+    pub(crate) fn request_yield(&mut self, values: Vec<LuaValue>) -> Result<(), VMError> {
+        let Some(thread) = self.current_thread else {
+            return Err(self.error(ErrorKind::InvalidCall(
+                "IllegalStateException: attempt to yield from outside a coroutine".into(),
+            )));
+        };
+
+        // the top frame is the native call of yield, its result register is in the frame below,
+        // which is the top frame again once the coroutine is resumed
+        let dest = self
+            .call_stack
+            .last()
+            .and_then(|native| native.ret_dest.map(|r| (r, native.ret_count)));
+        self.heap.data_mut(thread).resume_dest = dest;
+        self.pending_yield = Some(values);
+        Ok(())
+    }
+
+    /// the value stack open upvalues of `thread` point into, wherever it is parked right now
+    pub(crate) fn thread_stack(&mut self, thread: Option<Gc<LuaCoroutine>>) -> &mut GlobalStack {
+        if thread == self.current_thread {
+            return &mut self.value_stack;
+        }
+        if let Some(resumer) = self.resumers.iter_mut().find(|r| r.thread == thread) {
+            return &mut resumer.value_stack;
+        }
+        // a suspended coroutine, the main thread is always current or parked
+        let co = thread.expect("the main thread is never suspended");
+        &mut self.heap.data_mut(co).value_stack
+    }
+
+    fn switch_to(&mut self, co: Gc<LuaCoroutine>) {
+        let data = self.heap.data_mut(co);
+        let (value_stack, call_stack) = (
+            mem::take(&mut data.value_stack),
+            mem::take(&mut data.call_stack),
+        );
+        self.resumers.push(Resumer {
+            thread: self.current_thread,
+            value_stack: mem::replace(&mut self.value_stack, value_stack),
+            call_stack: mem::replace(&mut self.call_stack, call_stack),
+        });
+        if let Some(thread) = self.current_thread {
+            self.heap.data_mut(thread).status = CoroutineStatus::Normal;
+        }
+        self.current_thread = Some(co);
+        self.heap.data_mut(co).status = CoroutineStatus::Running;
+    }
+
+    fn switch_back(&mut self, co: Gc<LuaCoroutine>) {
+        let resumer = self.resumers.pop().unwrap();
+        let value_stack = mem::replace(&mut self.value_stack, resumer.value_stack);
+        let call_stack = mem::replace(&mut self.call_stack, resumer.call_stack);
+        let data = self.heap.data_mut(co);
+        if data.status != CoroutineStatus::Dead {
+            data.value_stack = value_stack;
+            data.call_stack = call_stack;
+        }
+        self.current_thread = resumer.thread;
+        if let Some(thread) = self.current_thread {
+            self.heap.data_mut(thread).status = CoroutineStatus::Running;
+        }
+        // whatever the stacks now hold was stored without a write barrier
+        self.heap.remember(co);
+    }
+
+    // the entry frame of the body, with the arguments of the first resume as its parameters
+    fn start_coroutine(
+        &mut self,
+        co: Gc<LuaCoroutine>,
+        args: Vec<LuaValue>,
+    ) -> Result<(), VMError> {
+        let LuaValue::Function(ptr) = self.heap.data(co).func else {
+            unreachable!("coroutine body checked by create_coroutine");
+        };
+        let func_obj = self.heap.data(ptr);
+        let meta = func_obj.proto.clone();
+        let upvalues = func_obj.upvalues.clone();
+
+        for arg in args {
+            self.value_stack.push(arg);
+        }
+        let frame_size = meta.max_stack_size;
+        self.value_stack.reserve(frame_size);
+        self.value_stack.restore(frame_size);
+        let frame = StackFrame::new(meta.name.clone(), Some(meta), None, 0, frame_size, upvalues);
+        self.push_frame(frame);
+        Ok(())
+    }
+
+    // Some(values) when the coroutine yielded, None when its body returned
+    fn run_coroutine(&mut self) -> Result<Option<Vec<LuaValue>>, VMError> {
+        while !self.call_stack.is_empty() {
+            self.protected_step()?;
+            if let Some(values) = self.pending_yield.take() {
+                return Ok(Some(values));
+            }
+            self.collect_garbage_if_needed();
+        }
+        Ok(None)
+    }
+}
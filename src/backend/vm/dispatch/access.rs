@@ -4,14 +4,14 @@ use crate::common::object::{LuaUpValueState, LuaValue};
 
 impl VirtualMachine {
     pub fn handle_move(&mut self, dest: u16, src: u16) -> Result<(), VMError> {
-        let val = self.get_reg(src as usize).clone();
+        let val = *self.get_reg(src as usize);
         self.set_reg(dest as usize, val);
         self.call_stack.last_mut().unwrap().pc += 1;
         Ok(())
     }
 
     pub fn handle_loadk(&mut self, dest: u16, const_idx: u16) -> Result<(), VMError> {
-        let val = self.get_constant(const_idx as usize).clone();
+        let val = *self.get_constant(const_idx as usize);
         self.set_reg(dest as usize, val);
         self.call_stack.last_mut().unwrap().pc += 1;
         Ok(())
@@ -29,33 +29,73 @@ impl VirtualMachine {
         Ok(())
     }
 
+    /// GETGLOBAL: R[dest] = _G[K[name_idx]], a missing global goes to the __index of _G's metatable
+    /// and is an error without one
     pub fn handle_get_global(&mut self, dest: u16, name_idx: u16) -> Result<(), VMError> {
-        let name = self.get_constant_string(name_idx as usize)?;
+        let key = *self.get_constant(name_idx as usize);
         self.call_stack.last_mut().unwrap().pc += 1;
-        if let Some(val) = self.globals.get(&name).cloned() {
-            self.set_reg(dest as usize, val);
-            Ok(())
-        } else {
-            Err(self.error(ErrorKind::UndefinedVariable(name)))
-        }
+        let globals = LuaValue::Table(self.globals);
+        let val = match self.heap.data(self.globals).get(&key) {
+            LuaValue::Nil => match self.get_metamethod(&globals, "__index") {
+                Some(LuaValue::Table(target)) => self.heap.data(target).get(&key),
+                Some(handler) => self.call_value(handler, vec![globals, key])?,
+                None => {
+                    let name = self.get_constant_string(name_idx as usize)?;
+                    return Err(self.error(ErrorKind::UndefinedVariable(name)));
+                }
+            },
+            val => val,
+        };
+        self.set_reg(dest as usize, val);
+        Ok(())
     }
 
+    /// SETGLOBAL: _G[K[name_idx]] = R[src], a new global goes to the __newindex of _G's metatable
     pub fn handle_set_global(&mut self, name_idx: u16, src: u16) -> Result<(), VMError> {
-        let name = self.get_constant_string(name_idx as usize)?;
-        let val = self.get_reg(src as usize).clone();
+        let key = *self.get_constant(name_idx as usize);
+        let val = *self.get_reg(src as usize);
         self.call_stack.last_mut().unwrap().pc += 1;
-        self.globals.insert(name, val);
+        if let Some(whitelist) = &self.config().global_whitelist {
+            let name = self.get_constant_string(name_idx as usize)?;
+            if !whitelist.contains(&name) {
+                return Err(self.error(ErrorKind::SandboxViolation(format!(
+                    "assignment to global '{}' is not allowed",
+                    name
+                ))));
+            }
+        }
+
+        let globals = LuaValue::Table(self.globals);
+        let handler = match self.heap.data(self.globals).get(&key) {
+            LuaValue::Nil => self.get_metamethod(&globals, "__newindex"),
+            _ => None,
+        };
+        let target = match handler {
+            None => self.globals,
+            Some(LuaValue::Table(target)) => target,
+            Some(handler) => {
+                self.call_value(handler, vec![globals, key, val])?;
+                return Ok(());
+            }
+        };
+        self.heap.write_barrier(target, &key);
+        self.heap.write_barrier(target, &val);
+        self.heap.data_mut(target).set(key, val);
         Ok(())
     }
 
     pub fn handle_get_upval(&mut self, dest: u16, upval_idx: u16) -> Result<(), VMError> {
         let curr_frame = self.call_stack.last().unwrap();
-        if let Some(upval) = curr_frame.upvalues.get(upval_idx as usize) {
-            let upval = match &unsafe { &**upval }.data.value {
-                LuaUpValueState::Open(stack_idx) => self.get_reg_absolute(*stack_idx),
+        if let Some(&upval) = curr_frame.upvalues.get(upval_idx as usize) {
+            let upval = self.heap.data(upval);
+            let val = match upval.value {
+                LuaUpValueState::Open(stack_idx) => {
+                    let thread = upval.thread;
+                    self.thread_stack(thread).values[stack_idx]
+                }
                 LuaUpValueState::Closed(val) => val,
             };
-            self.set_reg(dest as usize, upval.clone());
+            self.set_reg(dest as usize, val);
             self.call_stack.last_mut().unwrap().pc += 1;
             Ok(())
         } else {
@@ -65,17 +105,18 @@ impl VirtualMachine {
 
     pub fn handle_set_upval(&mut self, upval_idx: u16, src: u16) -> Result<(), VMError> {
         let curr_frame = self.call_stack.last().unwrap();
-        if let Some(upval) = curr_frame.upvalues.get(upval_idx as usize) {
-            let new_val = self.get_reg(src as usize).clone();
-            unsafe {
-                let upval_ref = &mut **upval;
-                match &mut upval_ref.data.value {
-                    LuaUpValueState::Open(stack_idx) => {
-                        self.set_reg_absolute(*stack_idx, new_val);
-                    }
-                    LuaUpValueState::Closed(val) => {
-                        *val = new_val;
-                    }
+        if let Some(&upval) = curr_frame.upvalues.get(upval_idx as usize) {
+            let new_val = *self.get_reg(src as usize);
+            let upval_data = self.heap.data_mut(upval);
+            match &mut upval_data.value {
+                LuaUpValueState::Open(stack_idx) => {
+                    let stack_idx = *stack_idx;
+                    let thread = upval_data.thread;
+                    self.thread_stack(thread).values[stack_idx] = new_val;
+                }
+                LuaUpValueState::Closed(val) => {
+                    *val = new_val;
+                    self.heap.write_barrier(upval, &new_val);
                 }
             }
             self.call_stack.last_mut().unwrap().pc += 1;
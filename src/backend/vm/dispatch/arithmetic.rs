@@ -1,5 +1,6 @@
 use crate::backend::vm::VirtualMachine;
 use crate::backend::vm::error::{ErrorKind, VMError};
+use crate::backend::vm::std_lib::format_number;
 use crate::common::object::LuaValue;
 use crate::common::opcode::UnaryOpType;
 
@@ -7,57 +8,128 @@ impl VirtualMachine {
     /// ADD: R[dest] = R[left] + R[right]
     pub fn handle_add(&mut self, dest: u16, left: u16, right: u16) -> Result<(), VMError> {
         self.call_stack.last_mut().unwrap().pc += 1;
-        self.handle_binary_op(dest, left, right, |n1, n2| n1 + n2, "addition")
+        self.handle_binary_op(
+            dest,
+            left,
+            right,
+            i64::wrapping_add,
+            |n1, n2| n1 + n2,
+            "addition",
+        )
     }
 
     /// SUB: R[dest] = R[left] - R[right]
     pub fn handle_sub(&mut self, dest: u16, left: u16, right: u16) -> Result<(), VMError> {
         self.call_stack.last_mut().unwrap().pc += 1;
-        self.handle_binary_op(dest, left, right, |n1, n2| n1 - n2, "subtraction")
+        self.handle_binary_op(
+            dest,
+            left,
+            right,
+            i64::wrapping_sub,
+            |n1, n2| n1 - n2,
+            "subtraction",
+        )
+    }
+
+    /// ADDK: R[dest] = R[left] + K[const_idx]
+    pub fn handle_add_k(&mut self, dest: u16, left: u16, const_idx: u16) -> Result<(), VMError> {
+        self.call_stack.last_mut().unwrap().pc += 1;
+        self.handle_binary_op_k(
+            dest,
+            left,
+            const_idx,
+            i64::wrapping_add,
+            |n1, n2| n1 + n2,
+            "addition",
+        )
+    }
+
+    /// SUBK: R[dest] = R[left] - K[const_idx]
+    pub fn handle_sub_k(&mut self, dest: u16, left: u16, const_idx: u16) -> Result<(), VMError> {
+        self.call_stack.last_mut().unwrap().pc += 1;
+        self.handle_binary_op_k(
+            dest,
+            left,
+            const_idx,
+            i64::wrapping_sub,
+            |n1, n2| n1 - n2,
+            "subtraction",
+        )
     }
 
     /// MUL: R[dest] = R[left] * R[right]
     pub fn handle_mul(&mut self, dest: u16, left: u16, right: u16) -> Result<(), VMError> {
         self.call_stack.last_mut().unwrap().pc += 1;
-        self.handle_binary_op(dest, left, right, |n1, n2| n1 * n2, "multiplication")
+        self.handle_binary_op(
+            dest,
+            left,
+            right,
+            i64::wrapping_mul,
+            |n1, n2| n1 * n2,
+            "multiplication",
+        )
     }
 
     /// DIV: R[dest] = R[left] / R[right]
+    /// always produces a float, even for two integer operands
     pub fn handle_div(&mut self, dest: u16, left: u16, right: u16) -> Result<(), VMError> {
         self.call_stack.last_mut().unwrap().pc += 1;
         let v2 = self.get_reg(right as usize);
-        if let LuaValue::Number(n2) = v2 {
-            if *n2 == 0.0 {
-                return Err(self.error(ErrorKind::ArithmeticError(
-                    "ArithmeticException: division by zero".into(),
-                )));
+        if v2.as_float() == Some(0.0) {
+            return Err(self.error(ErrorKind::ArithmeticError(
+                "ArithmeticException: division by zero".into(),
+            )));
+        }
+        let v1 = self.get_reg(left as usize);
+        match (v1.as_float(), v2.as_float()) {
+            (Some(n1), Some(n2)) => {
+                self.set_reg(dest as usize, LuaValue::Number(n1 / n2));
+                Ok(())
             }
+            _ => Err(self.binary_type_error("division", left, right)),
         }
-        self.handle_binary_op(dest, left, right, |n1, n2| n1 / n2, "division")
     }
 
     /// MOD: R[dest] = R[left] % R[right]
+    /// floored modulo, the result takes the sign of the divisor
     pub fn handle_mod(&mut self, dest: u16, left: u16, right: u16) -> Result<(), VMError> {
         self.call_stack.last_mut().unwrap().pc += 1;
         let v2 = self.get_reg(right as usize);
-        if let LuaValue::Number(n2) = v2 {
-            if *n2 == 0.0 {
-                return Err(self.error(ErrorKind::ArithmeticError(
-                    "ArithmeticException: modulo by zero".into(),
-                )));
-            }
+        if v2.as_float() == Some(0.0) {
+            return Err(self.error(ErrorKind::ArithmeticError(
+                "ArithmeticException: modulo by zero".into(),
+            )));
         }
-        self.handle_binary_op(dest, left, right, |n1, n2| n1 % n2, "modulo")
+        self.handle_binary_op(
+            dest,
+            left,
+            right,
+            |n1, n2| {
+                let r = n1.wrapping_rem(n2);
+                if r != 0 && (r ^ n2) < 0 { r + n2 } else { r }
+            },
+            |n1, n2| {
+                let r = n1 % n2;
+                if r != 0.0 && (r < 0.0) != (n2 < 0.0) {
+                    r + n2
+                } else {
+                    r
+                }
+            },
+            "modulo",
+        )
     }
 
     /// UNOP
     pub fn handle_unary_op(&mut self, dest: u16, src: u16, op: UnaryOpType) -> Result<(), VMError> {
         self.call_stack.last_mut().unwrap().pc += 1;
-        let val = self.get_reg(src as usize).clone();
+        let val = *self.get_reg(src as usize);
 
         let res = match op {
             UnaryOpType::Neg => {
-                if let LuaValue::Number(n) = val {
+                if let LuaValue::Integer(i) = val {
+                    LuaValue::Integer(i.wrapping_neg())
+                } else if let LuaValue::Number(n) = val {
                     LuaValue::Number(-n)
                 } else {
                     return Err(self.error(ErrorKind::TypeError(format!(
@@ -68,15 +140,9 @@ impl VirtualMachine {
             }
             UnaryOpType::Not => LuaValue::Boolean(!val.is_truthy()),
             UnaryOpType::Len => match val {
-                LuaValue::String(ptr) => unsafe {
-                    let s = &(*ptr).data;
-                    LuaValue::Number(s.len() as f64)
-                },
-
-                LuaValue::Table(ptr) => unsafe {
-                    let table_data = &(*ptr).data.data;
-                    LuaValue::Number(table_data.len() as f64)
-                },
+                LuaValue::String(ptr) => LuaValue::Integer(ptr.get().len() as i64),
+
+                LuaValue::Table(ptr) => LuaValue::Integer(self.heap.data(ptr).len() as i64),
                 _ => {
                     return Err(self.error(ErrorKind::TypeError(format!(
                         "TypeMismatchException: operation '#' (len) is not defined for type '{:?}'",
@@ -89,28 +155,56 @@ impl VirtualMachine {
         self.set_reg(dest as usize, res);
         Ok(())
     }
-    fn handle_binary_op<F>(
+    // two integers stay integers (wrapping around on overflow), any float operand promotes both to floats
+    fn handle_binary_op<I, F>(
         &mut self,
         dest: u16,
         left: u16,
         right: u16,
-        op_fn: F,
+        int_fn: I,
+        float_fn: F,
         op_name: &str,
     ) -> Result<(), VMError>
     where
+        I: Fn(i64, i64) -> i64,
         F: Fn(f64, f64) -> f64,
     {
         let v1 = self.get_reg(left as usize);
         let v2 = self.get_reg(right as usize);
 
-        match (v1, v2) {
-            (LuaValue::Number(n1), LuaValue::Number(n2)) => {
-                let res = op_fn(*n1, *n2);
-                self.set_reg(dest as usize, LuaValue::Number(res));
+        match arith(v1, v2, int_fn, float_fn) {
+            Some(res) => {
+                self.set_reg(dest as usize, res);
                 Ok(())
             }
             //TODO: 后续支持Table和String的加法等
-            _ => {
+            None => Err(self.binary_type_error(op_name, left, right)),
+        }
+    }
+
+    // handle_binary_op with a number constant as the right operand
+    fn handle_binary_op_k<I, F>(
+        &mut self,
+        dest: u16,
+        left: u16,
+        const_idx: u16,
+        int_fn: I,
+        float_fn: F,
+        op_name: &str,
+    ) -> Result<(), VMError>
+    where
+        I: Fn(i64, i64) -> i64,
+        F: Fn(f64, f64) -> f64,
+    {
+        let v1 = self.get_reg(left as usize);
+        let v2 = self.get_constant(const_idx as usize);
+
+        match arith(v1, v2, int_fn, float_fn) {
+            Some(res) => {
+                self.set_reg(dest as usize, res);
+                Ok(())
+            }
+            None => {
                 let msg = format!(
                     "TypeMismatchException: binary operator '{}' is not defined for types '{:?}' and '{:?}'",
                     op_name, v1, v2
@@ -120,14 +214,24 @@ impl VirtualMachine {
         }
     }
 
+    fn binary_type_error(&self, op_name: &str, left: u16, right: u16) -> VMError {
+        let msg = format!(
+            "TypeMismatchException: binary operator '{}' is not defined for types '{:?}' and '{:?}'",
+            op_name,
+            self.get_reg(left as usize),
+            self.get_reg(right as usize)
+        );
+        self.error(ErrorKind::TypeError(msg))
+    }
+
     /// AND: R[dest] = R[left] and R[right]
     pub fn handle_and(&mut self, dest: u16, left: u16, right: u16) -> Result<(), VMError> {
         self.call_stack.last_mut().unwrap().pc += 1;
-        let v1 = self.get_reg(left as usize).clone();
+        let v1 = *self.get_reg(left as usize);
         let res = if !v1.is_truthy() {
             v1
         } else {
-            self.get_reg(right as usize).clone()
+            *self.get_reg(right as usize)
         };
         self.set_reg(dest as usize, res);
         Ok(())
@@ -136,11 +240,11 @@ impl VirtualMachine {
     /// OR: R[dest] = R[left] or R[right]
     pub fn handle_or(&mut self, dest: u16, left: u16, right: u16) -> Result<(), VMError> {
         self.call_stack.last_mut().unwrap().pc += 1;
-        let v1 = self.get_reg(left as usize).clone();
+        let v1 = *self.get_reg(left as usize);
         let res = if v1.is_truthy() {
             v1
         } else {
-            self.get_reg(right as usize).clone()
+            *self.get_reg(right as usize)
         };
         self.set_reg(dest as usize, res);
         Ok(())
@@ -148,50 +252,83 @@ impl VirtualMachine {
 
     pub fn handle_concat(&mut self, dest: u16, left: u16, right: u16) -> Result<(), VMError> {
         self.call_stack.last_mut().unwrap().pc += 1;
-        let v1 = self.get_reg(left as usize).clone();
-        let v2 = self.get_reg(right as usize).clone();
+        let v1 = *self.get_reg(left as usize);
+        let v2 = *self.get_reg(right as usize);
+
+        let mut combined = String::new();
+        self.push_concat_operand(&mut combined, &v1)?;
+        self.push_concat_operand(&mut combined, &v2)?;
+
+        // both operands are still in their registers, a collection run by the allocation keeps them
+        let new_str_ptr = self.alloc(|heap| heap.alloc_str(&combined))?;
 
-        let s1 = self.value_to_string(&v1)?;
-        let s2 = self.value_to_string(&v2)?;
+        self.set_reg(dest as usize, LuaValue::String(new_str_ptr));
+
+        Ok(())
+    }
 
-        let combined = s1 + &s2;
+    /// CONCATN: R[dest] = R[start] .. ... .. R[start + count - 1], built in one buffer
+    pub fn handle_concat_n(&mut self, dest: u16, start: u16, count: u16) -> Result<(), VMError> {
+        self.call_stack.last_mut().unwrap().pc += 1;
+        let mut combined = String::new();
+        for reg in start..start + count {
+            let v = *self.get_reg(reg as usize);
+            self.push_concat_operand(&mut combined, &v)?;
+        }
 
-        //heap.alloc_string
-        let new_str_ptr = self
-            .heap
-            .alloc_string(combined)
-            .ok_or_else(|| self.error(ErrorKind::OutOfMemory))?;
+        // the operands stay in their registers until the result is stored
+        let new_str_ptr = self.alloc(|heap| heap.alloc_str(&combined))?;
 
         self.set_reg(dest as usize, LuaValue::String(new_str_ptr));
 
         Ok(())
     }
 
-    fn value_to_string(&self, val: &LuaValue) -> Result<String, VMError> {
+    // appends the string form of a concatenation operand
+    fn push_concat_operand(&self, out: &mut String, val: &LuaValue) -> Result<(), VMError> {
         match val {
             LuaValue::String(ptr) => {
-                unsafe { Ok((*(*ptr)).data.clone()) }
+                out.push_str(ptr.get());
             }
             LuaValue::Number(n) => {
-                Ok(n.to_string())
+                out.push_str(&format_number(*n));
+            }
+            LuaValue::Integer(i) => {
+                out.push_str(&i.to_string());
             }
             LuaValue::Nil => {
-                Err(self.error(ErrorKind::TypeError(
-                    "NullPointerException: illegal concatenation of a nil value".into()
-                )))
+                return Err(self.error(ErrorKind::TypeError(
+                    "NullPointerException: illegal concatenation of a nil value".into(),
+                )));
             }
             LuaValue::Boolean(b) => {
-                Err(self.error(ErrorKind::TypeError(format!(
+                return Err(self.error(ErrorKind::TypeError(format!(
                     "TypeMismatchException: boolean type ({}) does not support implicit string conversion for concatenation",
                     b
-                ))))
+                ))));
             }
             _ => {
-                Err(self.error(ErrorKind::TypeError(format!(
+                return Err(self.error(ErrorKind::TypeError(format!(
                     "IncompatibleTypesException: cannot perform string concatenation on type '{:?}'",
                     val
-                ))))
+                ))));
             }
         }
+        Ok(())
+    }
+}
+
+// the numeric part of handle_binary_op, None if either operand is not a number
+fn arith<I, F>(v1: &LuaValue, v2: &LuaValue, int_fn: I, float_fn: F) -> Option<LuaValue>
+where
+    I: Fn(i64, i64) -> i64,
+    F: Fn(f64, f64) -> f64,
+{
+    match (v1, v2) {
+        (LuaValue::Integer(i1), LuaValue::Integer(i2)) => Some(LuaValue::Integer(int_fn(*i1, *i2))),
+        _ => match (v1.as_float(), v2.as_float()) {
+            (Some(n1), Some(n2)) => Some(LuaValue::Number(float_fn(n1, n2))),
+            _ => None,
+        },
     }
 }
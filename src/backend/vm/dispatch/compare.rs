@@ -1,6 +1,7 @@
 use crate::backend::vm::VirtualMachine;
 use crate::backend::vm::error::{ErrorKind, VMError};
-use crate::common::object::LuaValue;
+use crate::common::object::{LuaValue, compare_numbers};
+use std::cmp::Ordering;
 
 impl VirtualMachine {
     pub fn handle_compare<F>(
@@ -26,13 +27,13 @@ impl VirtualMachine {
     /// EQ: R[dest] = (R[left] == R[right])
     pub fn handle_eq(&mut self, dest: u16, left: u16, right: u16) -> Result<(), VMError> {
         self.call_stack.last_mut().unwrap().pc += 1;
-        self.handle_compare(dest, left, right, |a, b| a == b)
+        self.handle_compare(dest, left, right, |a, b| a.raw_equal(b))
     }
 
     /// NE: R[dest] = (R[left] != R[right])
     pub fn handle_ne(&mut self, dest: u16, left: u16, right: u16) -> Result<(), VMError> {
         self.call_stack.last_mut().unwrap().pc += 1;
-        self.handle_compare(dest, left, right, |a, b| a != b)
+        self.handle_compare(dest, left, right, |a, b| !a.raw_equal(b))
     }
 
     /// LT: R[dest] = (R[left] < R[right])
@@ -42,10 +43,10 @@ impl VirtualMachine {
         let v2 = self.get_reg(right as usize);
 
         let res = match (v1, v2) {
-            (LuaValue::Number(n1), LuaValue::Number(n2)) => n1 < n2,
-            (LuaValue::String(s1), LuaValue::String(s2)) => unsafe {
-                (*(*s1)).data < (*(*s2)).data
-            },
+            (LuaValue::Number(_) | LuaValue::Integer(_), LuaValue::Number(_) | LuaValue::Integer(_)) => {
+                compare_numbers(v1, v2).is_some_and(|ord| ord < Ordering::Equal)
+            }
+            (LuaValue::String(s1), LuaValue::String(s2)) => s1.get() < s2.get(),
             _ => return Err(self.error(ErrorKind::TypeError(format!(
                 "TypeMismatchException: relational operator '<' is not defined between '{:?}' and '{:?}'",
                 v1, v2
@@ -63,10 +64,10 @@ impl VirtualMachine {
         let v2 = self.get_reg(right as usize);
 
         let res = match (v1, v2) {
-            (LuaValue::Number(n1), LuaValue::Number(n2)) => n1 > n2,
-            (LuaValue::String(s1), LuaValue::String(s2)) => unsafe {
-                (*(*s1)).data > (*(*s2)).data
-            },
+            (LuaValue::Number(_) | LuaValue::Integer(_), LuaValue::Number(_) | LuaValue::Integer(_)) => {
+                compare_numbers(v1, v2).is_some_and(|ord| ord > Ordering::Equal)
+            }
+            (LuaValue::String(s1), LuaValue::String(s2)) => s1.get() > s2.get(),
             _ => return Err(self.error(ErrorKind::TypeError(format!(
                 "TypeMismatchException: relational operator '>' is not defined between '{:?}' and '{:?}'",
                 v1, v2
@@ -84,10 +85,10 @@ impl VirtualMachine {
         let v2 = self.get_reg(right as usize);
 
         let res = match (v1, v2) {
-            (LuaValue::Number(n1), LuaValue::Number(n2)) => n1 <= n2,
-            (LuaValue::String(s1), LuaValue::String(s2)) => unsafe {
-                (*(*s1)).data <= (*(*s2)).data
-            },
+            (LuaValue::Number(_) | LuaValue::Integer(_), LuaValue::Number(_) | LuaValue::Integer(_)) => {
+                compare_numbers(v1, v2).is_some_and(|ord| ord <= Ordering::Equal)
+            }
+            (LuaValue::String(s1), LuaValue::String(s2)) => s1.get() <= s2.get(),
             _ => return Err(self.error(ErrorKind::TypeError(format!(
                 "TypeMismatchException: relational operator '<=' is not defined between '{:?}' and '{:?}'",
                 v1, v2
@@ -105,10 +106,10 @@ impl VirtualMachine {
         let v2 = self.get_reg(right as usize);
 
         let res = match (v1, v2) {
-            (LuaValue::Number(n1), LuaValue::Number(n2)) => n1 >= n2,
-            (LuaValue::String(s1), LuaValue::String(s2)) => unsafe {
-                (*(*s1)).data >= (*(*s2)).data
-            },
+            (LuaValue::Number(_) | LuaValue::Integer(_), LuaValue::Number(_) | LuaValue::Integer(_)) => {
+                compare_numbers(v1, v2).is_some_and(|ord| ord >= Ordering::Equal)
+            }
+            (LuaValue::String(s1), LuaValue::String(s2)) => s1.get() >= s2.get(),
             _ => return Err(self.error(ErrorKind::TypeError(format!(
                 "TypeMismatchException: relational operator '>=' is not defined between '{:?}' and '{:?}'",
                 v1, v2
@@ -1,6 +1,9 @@
 use crate::backend::vm::VirtualMachine;
 use crate::backend::vm::error::{ErrorKind, VMError};
-use crate::common::object::LuaValue;
+use crate::backend::vm::stack::StackFrame;
+use crate::common::object::{LuaValue, float_to_integer};
+use crate::common::opcode::MULTRET;
+use std::io::Write;
 
 impl VirtualMachine {
     /// JUMP
@@ -12,74 +15,150 @@ impl VirtualMachine {
         Ok(())
     }
 
+    /// JUMPIFFALSE: jump like JUMP when R[reg] is falsy, otherwise go on with the next instruction
+    pub fn handle_jump_if_false(&mut self, reg: u16, offset: i32) -> Result<(), VMError> {
+        if self.get_reg(reg as usize).is_truthy() {
+            self.call_stack.last_mut().unwrap().pc += 1;
+            Ok(())
+        } else {
+            self.handle_jump(offset)
+        }
+    }
+
+    /// SWITCH: jump through jump table `table` on R[reg], only numbers with an integer value
+    /// can be equal to one of its keys
+    pub fn handle_switch(&mut self, reg: u16, table: u16) -> Result<(), VMError> {
+        let key = match *self.get_reg(reg as usize) {
+            LuaValue::Integer(i) => Some(i),
+            LuaValue::Number(n) => float_to_integer(n),
+            _ => None,
+        };
+        let frame = self.call_stack.last().unwrap();
+        let table = frame
+            .meta
+            .as_ref()
+            .and_then(|meta| meta.jump_tables.get(table as usize))
+            .ok_or_else(|| {
+                self.error(ErrorKind::InternalError(format!(
+                    "InstructionOutOfBoundsException: no jump table T{}",
+                    table
+                )))
+            })?;
+        let offset = key.map_or(table.default, |key| table.offset(key));
+        self.handle_jump(offset)
+    }
+
     /// CALL
     pub fn handle_call(&mut self, func_reg: u16, argc: u8, retc: u8) -> Result<(), VMError> {
+        let argc = self.take_args(argc)?;
+        self.call(func_reg, argc, retc)
+    }
+
+    /// the number of arguments pushed for a CALL or TAILCALL; for MULTRET the results of the call
+    /// before it are pushed after the others and counted too
+    fn take_args(&mut self, argc: u8) -> Result<usize, VMError> {
+        if argc == MULTRET {
+            let results = std::mem::take(&mut self.return_buffer);
+            self.value_stack.values.extend(results);
+        }
+        let pushed = self
+            .value_stack
+            .values
+            .len()
+            .saturating_sub(self.get_actual_stack_top());
+        match argc {
+            MULTRET => Ok(pushed),
+            argc if argc as usize <= pushed => Ok(argc as usize),
+            _ => Err(self.error(ErrorKind::InternalError(
+                "CALL without its arguments on the stack".into(),
+            ))),
+        }
+    }
+
+    fn call(&mut self, func_reg: u16, argc: usize, retc: u8) -> Result<(), VMError> {
+        let pc = self.call_stack.last().unwrap().pc;
         self.call_stack.last_mut().unwrap().pc += 1;
-        let func_val = self.get_reg(func_reg as usize).clone();
+        let func_val = *self.get_reg(func_reg as usize);
 
-        if self.call_stack.len() >= crate::backend::vm::MAX_CALL_STACK {
+        if self.call_stack.len() >= self.config().max_call_stack {
             return Err(self.error(ErrorKind::StackOverflow));
         }
 
+        // a table with a __call metamethod is called with itself as the first argument,
+        // which is slipped in front of the already pushed arguments
+        let (func_val, argc) = match self.get_metamethod(&func_val, "__call") {
+            Some(handler) => {
+                let args_start = self.get_actual_stack_top();
+                self.value_stack.values.insert(args_start, func_val);
+                (handler, argc + 1)
+            }
+            None => (func_val, argc),
+        };
+
         match func_val {
             LuaValue::Function(ptr) => {
-                let func_obj = unsafe { &(*ptr).data };
-                let func_name = &func_obj.name;
-
-                let meta = self.func_meta.get(func_name)
-                    .ok_or_else(|| self.error(ErrorKind::InternalError(format!(
-                        "InternalExecutionException: metadata for function '{}' could not be resolved",
-                        func_name
-                    ))))?;
-
-                let new_frame = self.make_stack_frame(
-                    func_name,
-                    meta.max_stack_size,
+                let meta = self.heap.data(ptr).proto.clone();
+
+                #[cfg(feature = "jit")]
+                if self.jit_call(&meta, func_reg, argc) {
+                    if retc != 1 {
+                        let result = *self.get_reg(func_reg as usize);
+                        self.deliver_results(func_reg as usize, retc, vec![result]);
+                    }
+                    return Ok(());
+                }
+
+                let upvalues = self.heap.data(ptr).upvalues.clone();
+                let mut new_frame = self.make_stack_frame(
+                    meta.name.clone(),
+                    Some(meta),
                     Some(func_reg as usize),
-                    func_obj.upvalues.clone(),
+                    upvalues,
                 );
+                new_frame.ret_count = retc;
 
                 self.push_frame(new_frame);
                 Ok(())
             }
 
-            LuaValue::CFunc(c_func) => {
+            LuaValue::CFunc(_) | LuaValue::NativeClosure(_) => {
                 let func_idx = func_reg as usize;
 
                 let stack_top = self.get_actual_stack_top();
-                let new_frame = self.make_stack_frame(
-                    &format!("__native_{}", func_idx),
-                    0,
+                let mut new_frame = self.make_stack_frame(
+                    format!("__native_{}", func_idx).into(),
+                    None,
                     Some(func_idx),
                     vec![],
                 );
+                new_frame.ret_count = retc;
 
                 // push dummy frame
                 self.push_frame(new_frame);
-                let num_results = c_func(self, argc as usize)?;
+                let num_results = self.call_native(&func_val, argc)?;
+                let results = self.take_native_results(num_results);
 
                 // restore, clean up dummy frame and args
                 self.pop_frame();
                 self.value_stack.restore(stack_top);
 
-                if retc > 0 {
-                    let expected = (retc - 1) as usize;
-                    for i in num_results..expected {
-                        self.set_reg(func_idx + i, LuaValue::Nil);
-                    }
-                }
-
+                self.deliver_results(func_idx, retc, results);
                 Ok(())
             }
 
             _ => {
-                let msg = match func_val {
-                    LuaValue::Nil => {
+                let msg = match (&func_val, self.operand_name(pc)) {
+                    (LuaValue::Nil, Some(name)) => format!(
+                        "NullPointerException: attempt to invoke a nil value ({})",
+                        name
+                    ),
+                    (LuaValue::Nil, None) => {
                         "NullPointerException: attempt to invoke a nil value".to_string()
                     }
                     _ => format!(
-                        "TypeMismatchException: object of type '{:?}' is not callable",
-                        func_val
+                        "TypeMismatchException: object of type '{:?}' is not callable{}",
+                        func_val,
+                        self.describe_operand(pc)
                     ),
                 };
                 Err(self.error(ErrorKind::InvalidCall(msg)))
@@ -87,9 +166,234 @@ impl VirtualMachine {
         }
     }
 
+    /// TAILCALL: return R[func_reg](args...)
+    /// a Lua callee takes over the current frame, so `return f(x)` does not grow the call stack;
+    /// anything else is called as usual and the following RETURN returns its results
+    pub fn handle_tail_call(&mut self, func_reg: u16, argc: u8) -> Result<(), VMError> {
+        let argc = self.take_args(argc)?;
+        let func_val = *self.get_reg(func_reg as usize);
+        let (func_val, argc) = match self.get_metamethod(&func_val, "__call") {
+            Some(handler @ LuaValue::Function(_)) => {
+                let args_start = self.get_actual_stack_top();
+                self.value_stack.values.insert(args_start, func_val);
+                (handler, argc + 1)
+            }
+            _ => (func_val, argc),
+        };
+
+        // the RETURN emitted after TAILCALL hands the results back, it runs as its own instruction
+        // so that a coroutine.yield called here suspends before its frame is gone
+        let LuaValue::Function(ptr) = func_val else {
+            return self.call(func_reg, argc, MULTRET);
+        };
+
+        let meta = self.heap.data(ptr).proto.clone();
+
+        // close what escaped from the frame before its registers are overwritten by the arguments
+        let args_start = self.get_actual_stack_top();
+        let frame = self.pop_frame().unwrap();
+        self.value_stack.values.drain(frame.base_offset..args_start);
+        self.value_stack.values.truncate(frame.base_offset + argc);
+
+        let frame_size = meta.max_stack_size;
+        self.value_stack.reserve(frame.base_offset + frame_size);
+        self.value_stack.restore(frame.base_offset + frame_size);
+        let mut new_frame = StackFrame::new(
+            meta.name.clone(),
+            Some(meta),
+            frame.ret_dest,
+            frame.base_offset,
+            frame_size,
+            self.heap.data(ptr).upvalues.clone(),
+        );
+        new_frame.ret_count = frame.ret_count;
+        self.push_frame(new_frame);
+        Ok(())
+    }
+
+    /// run a native function, plain or a host closure, inside the dummy frame the caller pushed
+    fn call_native(&mut self, func: &LuaValue, argc: usize) -> Result<usize, VMError> {
+        match func {
+            LuaValue::CFunc(c_func) => c_func(self, argc),
+            LuaValue::NativeClosure(ptr) => {
+                let ptr = *ptr;
+                // the closure is moved out for the duration of the call, the object itself
+                // stays reachable from the caller's register or argument list
+                let Some(mut func) = self.heap.data_mut(ptr).func.take() else {
+                    let name = self.heap.data(ptr).name.clone();
+                    return Err(self.error(ErrorKind::InvalidCall(format!(
+                        "IllegalInvocationException: native function '{}' cannot be re-entered",
+                        name
+                    ))));
+                };
+                let result = func(self, argc);
+                self.heap.data_mut(ptr).func = Some(func);
+                result
+            }
+            _ => unreachable!("call_native on a non-native value"),
+        }
+    }
+
+    /// native functions return values by pushing them onto the value stack
+    /// and reporting how many they pushed, this pops them back off in order
+    fn take_native_results(&mut self, count: usize) -> Vec<LuaValue> {
+        let len = self.value_stack.values.len();
+        self.value_stack.values.split_off(len.saturating_sub(count))
+    }
+
+    /// hand the results of a call to the calling frame: the first one (nil if there is none) goes to
+    /// R[dest]; unless the call takes exactly one, `return_buffer` keeps `retc` of them, padded with
+    /// nil, for the GETRESULTs after the CALL, or all of them for MULTRET
+    pub(crate) fn deliver_results(&mut self, dest: usize, retc: u8, mut results: Vec<LuaValue>) {
+        let frame = self.call_stack.last_mut().unwrap();
+        if retc > 0 && dest < frame.reg_count {
+            let first = results.first().copied().unwrap_or(LuaValue::Nil);
+            frame.set_reg(dest, first, &mut self.value_stack);
+        }
+        if retc != 1 {
+            if retc != MULTRET {
+                results.resize(retc as usize, LuaValue::Nil);
+            }
+            self.return_buffer = results;
+        }
+    }
+
+    /// GETRESULT
+    pub fn handle_get_result(&mut self, dest: u16, index: u8) -> Result<(), VMError> {
+        let val = self
+            .return_buffer
+            .get(index as usize)
+            .copied()
+            .unwrap_or(LuaValue::Nil);
+        self.set_reg(dest as usize, val);
+        self.call_stack.last_mut().unwrap().pc += 1;
+        Ok(())
+    }
+
+    /// call a function value from native code and run it to completion,
+    /// returning its first result (nil if it returned nothing)
+    pub fn call_value(&mut self, func: LuaValue, args: Vec<LuaValue>) -> Result<LuaValue, VMError> {
+        let results = self.call_values(func, args)?;
+        Ok(results.into_iter().next().unwrap_or(LuaValue::Nil))
+    }
+
+    /// call_value that returns every result of the call
+    ///
+    /// the callee frame is placed above everything currently on the value stack,
+    /// so the arguments of the calling native function stay intact
+    pub fn call_values(
+        &mut self,
+        func: LuaValue,
+        args: Vec<LuaValue>,
+    ) -> Result<Vec<LuaValue>, VMError> {
+        if self.call_stack.len() >= self.config().max_call_stack {
+            return Err(self.error(ErrorKind::StackOverflow));
+        }
+
+        let base = self.value_stack.values.len();
+        let argc = args.len();
+        for arg in args {
+            self.value_stack.push(arg);
+        }
+
+        match func {
+            LuaValue::Function(ptr) => {
+                let meta = self.heap.data(ptr).proto.clone();
+                let frame_size = meta.max_stack_size;
+
+                self.value_stack.reserve(base + frame_size);
+                self.value_stack.restore(base + frame_size);
+                let frame = StackFrame::new(
+                    meta.name.clone(),
+                    Some(meta),
+                    None,
+                    base,
+                    frame_size,
+                    self.heap.data(ptr).upvalues.clone(),
+                );
+
+                let depth = self.call_stack.len();
+                self.return_buffer.clear();
+                self.push_frame(frame);
+                while self.call_stack.len() > depth {
+                    self.protected_step()?;
+                    // this loop cannot be left half way and picked up again by a later resume
+                    if self.pending_yield.take().is_some() {
+                        return Err(self.error(ErrorKind::InvalidCall(
+                            "IllegalStateException: attempt to yield across a native call boundary"
+                                .into(),
+                        )));
+                    }
+                    self.collect_garbage_if_needed();
+                }
+
+                Ok(std::mem::take(&mut self.return_buffer))
+            }
+
+            LuaValue::CFunc(_) | LuaValue::NativeClosure(_) => {
+                let frame =
+                    StackFrame::new("__native_callback".into(), None, None, base, 0, vec![]);
+                self.push_frame(frame);
+                let num_results = self.call_native(&func, argc)?;
+                let results = self.take_native_results(num_results);
+                self.pop_frame();
+                self.value_stack.restore(base);
+
+                Ok(results)
+            }
+
+            _ => {
+                self.value_stack.restore(base);
+                Err(self.error(ErrorKind::InvalidCall(format!(
+                    "TypeMismatchException: object of type '{:?}' is not callable",
+                    func
+                ))))
+            }
+        }
+    }
+
+    /// call_values behind a recovery point, this is what pcall is built on
+    ///
+    /// the call-stack depth and the value-stack top are saved before the call; if it fails,
+    /// every frame above the saved depth is popped (closing the upvalues that escape from it)
+    /// and the value stack is cut back, so the caller can carry on as if the call had returned
+    pub fn protected_call(
+        &mut self,
+        func: LuaValue,
+        args: Vec<LuaValue>,
+    ) -> Result<Vec<LuaValue>, VMError> {
+        self.protected_call_with(func, args, |_, err| err)
+    }
+
+    /// protected_call that hands a failure to `on_error` before unwinding, while the frames of the
+    /// failed call are still on the stack; whatever `on_error` calls runs on top of them and is
+    /// unwound together with them (xpcall runs its message handler here)
+    pub fn protected_call_with<E>(
+        &mut self,
+        func: LuaValue,
+        args: Vec<LuaValue>,
+        on_error: impl FnOnce(&mut Self, VMError) -> E,
+    ) -> Result<Vec<LuaValue>, E> {
+        let depth = self.call_stack.len();
+        let stack_top = self.value_stack.values.len();
+
+        match self.call_values(func, args) {
+            Ok(results) => Ok(results),
+            Err(err) => {
+                let err = on_error(self, err);
+                while self.call_stack.len() > depth {
+                    self.pop_frame();
+                }
+                self.value_stack.restore(stack_top);
+                self.return_buffer.clear();
+                Err(err)
+            }
+        }
+    }
+
     /// PUSH
     pub fn handle_push(&mut self, src: u16) -> Result<(), VMError> {
-        let val = self.get_reg(src as usize).clone();
+        let val = *self.get_reg(src as usize);
         self.value_stack.push(val);
 
         self.call_stack.last_mut().unwrap().pc += 1;
@@ -98,22 +402,21 @@ impl VirtualMachine {
     }
 
     /// RETURN
-    /// 我非常不建议使用这种策略处理返回值，单个还好说，多个又会变得跟之前那种寄存器传参模式一样乱七八糟的
-    /// Native Call 那边也是一样
-    /// 但如果不做多返回值支持，这个就无所谓了
-    /// 行为是对的，但是寄存器乱飞
-    /// - Li
+    /// count values from R[start]; MULTRET returns the values pushed for the RETURN followed by
+    /// every result of the call right before it
     pub fn handle_return(&mut self, start: u16, count: u8) -> Result<(), VMError> {
-        // FIXME:目前版本不支持多返回值，后续如果需要支持再改这里
-        if count > 1 {
-            return Err(self.error(ErrorKind::MultipleReturnValues(
-                "MultipleReturnValuesException: returning multiple values is not supported in this VM version".into()
-            )));
-        }
-        let mut results = Vec::new();
-        for i in 0..(count as usize) {
-            results.push(self.get_reg(start as usize + i).clone());
-        }
+        let results = if count == MULTRET {
+            let top = self
+                .get_actual_stack_top()
+                .min(self.value_stack.values.len());
+            let mut results = self.value_stack.values.split_off(top);
+            results.append(&mut self.return_buffer);
+            results
+        } else {
+            (0..count as usize)
+                .map(|i| *self.get_reg(start as usize + i))
+                .collect()
+        };
 
         let last_frame = self.pop_frame().ok_or_else(|| {
             self.error(ErrorKind::InternalError(
@@ -122,18 +425,15 @@ impl VirtualMachine {
         })?;
 
         if self.call_stack.is_empty() {
+            // the chunk itself returned, keep the results for the embedder
+            self.return_buffer = results;
             return Ok(());
         }
 
-        if let Some(dest_idx) = last_frame.ret_dest {
-            if let Some(caller_frame) = self.call_stack.last_mut() {
-                for (i, val) in results.into_iter().enumerate() {
-                    let target_idx = dest_idx + i;
-                    if target_idx < caller_frame.reg_count {
-                        caller_frame.set_reg(target_idx, val, &mut self.value_stack);
-                    }
-                }
-            }
+        match last_frame.ret_dest {
+            Some(dest_idx) => self.deliver_results(dest_idx, last_frame.ret_count, results),
+            // entered through call_value, hand the results back to the native caller
+            None => self.return_buffer = results,
         }
 
         self.value_stack.restore(last_frame.base_offset);
@@ -142,11 +442,17 @@ impl VirtualMachine {
     }
 
     pub fn handle_halt(&mut self) -> Result<(), VMError> {
-        println!("[VM] HALT instruction received. Initiating graceful shutdown sequence...");
+        let _ = writeln!(
+            self.output,
+            "[VM] HALT instruction received. Initiating graceful shutdown sequence..."
+        );
 
         self.call_stack.clear();
 
-        println!("[VM] Execution terminated. Status: Success (0)");
+        let _ = writeln!(
+            self.output,
+            "[VM] Execution terminated. Status: Success (0)"
+        );
 
         Ok(())
     }
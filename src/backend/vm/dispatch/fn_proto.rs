@@ -1,8 +1,7 @@
-use std::ptr::null_mut;
-
 use crate::backend::vm::VirtualMachine;
 use crate::backend::vm::error::{ErrorKind, VMError};
-use crate::common::object::{GCObject, LuaUpValue, LuaUpValueState, LuaValue};
+use crate::backend::vm::heap::Gc;
+use crate::common::object::{LFunction, LuaUpValue, LuaUpValueState, LuaValue};
 use crate::frontend::ir::IRUpValType;
 
 impl VirtualMachine {
@@ -10,31 +9,26 @@ impl VirtualMachine {
         self.call_stack.last_mut().unwrap().pc += 1;
         let curr_frame = self.call_stack.last().unwrap();
 
-        let curr_meta = self.func_meta.get(&curr_frame.func_name)
+        let curr_meta = curr_frame.meta.clone()
             .ok_or_else(|| self.error(ErrorKind::InternalError(
                 format!("ResolutionException: failed to resolve metadata for current execution context '{}'", curr_frame.func_name)
             )))?;
 
-        let sub_func_name = curr_meta
-            .child_protos
+        let sub_meta = curr_meta
+            .children
             .get(proto_idx as usize)
+            .and_then(|&id| self.protos.get(id))
+            .cloned()
             .ok_or_else(|| {
                 self.error(ErrorKind::InternalError(format!(
-                    "IndexOutOfBoundsException: function prototype index {} is out of range",
-                    proto_idx
+                    "LinkageError: function prototype P{} of '{}' could not be resolved",
+                    proto_idx, curr_meta.name
                 )))
             })?;
 
-        let sub_meta = self.func_meta.get(sub_func_name).ok_or_else(|| {
-            self.error(ErrorKind::InternalError(format!(
-                "LinkageError: symbolic reference to sub-prototype '{}' could not be resolved",
-                sub_func_name
-            )))
-        })?;
-
-        let mut err: Option<ErrorKind> = None;
-        let mut out_upvalues: Vec<(usize, *mut GCObject<LuaUpValue>)> = vec![];
-        let captured_upvalues: Vec<*mut GCObject<LuaUpValue>> = sub_meta
+        let thread = self.current_thread;
+        let mut out_upvalues: Vec<(usize, Gc<LuaUpValue>)> = vec![];
+        let captured_upvalues: Result<Vec<Gc<LuaUpValue>>, ErrorKind> = sub_meta
             .upvalues_metadata
             .iter()
             .map(|upval| match upval.ty {
@@ -44,7 +38,7 @@ impl VirtualMachine {
                         .binary_search_by_key(&slot, |(s, _)| *s);
                     if let Ok(idx) = search {
                         // this slot is already captured by current frame, reuse the upvalue object
-                        return curr_frame.out_upvalues[idx].1;
+                        return Ok(curr_frame.out_upvalues[idx].1);
                     }
 
                     let reg_idx = curr_frame.reg_absolute(slot);
@@ -53,24 +47,23 @@ impl VirtualMachine {
                         .heap
                         .alloc_upvalue_object(LuaUpValue {
                             value: LuaUpValueState::Open(reg_idx),
+                            thread,
                         })
-                        .ok_or_else(|| {
-                            err = Some(ErrorKind::OutOfMemory);
-                            null_mut::<GCObject<LuaUpValue>>()
-                        })
-                        .unwrap();
+                        .ok_or(ErrorKind::OutOfMemory)?;
                     out_upvalues.push((slot, upval_ptr));
-                    upval_ptr
+                    Ok(upval_ptr)
                 }
                 IRUpValType::UpVal(slot) => {
-                    curr_frame.upvalues.get(slot).unwrap_or(&null_mut()).clone()
+                    curr_frame.upvalues.get(slot).copied().ok_or_else(|| {
+                        ErrorKind::InternalError(format!(
+                            "IndexOutOfBoundsException: upvalue index {} is out of range",
+                            slot
+                        ))
+                    })
                 }
             })
             .collect();
-
-        if let Some(e) = err {
-            self.error(e);
-        }
+        let captured_upvalues = captured_upvalues.map_err(|e| self.error(e))?;
 
         // update exported upvalues of current frame
         self.call_stack
@@ -79,13 +72,9 @@ impl VirtualMachine {
             .out_upvalues
             .append(&mut out_upvalues);
 
-        let new_func = crate::common::object::LFunction {
-            name: sub_func_name.clone(),
-            opcodes: sub_meta.bytecode.clone(),
-            constants: sub_meta.constants.clone(),
+        let new_func = LFunction {
+            proto: sub_meta,
             upvalues: captured_upvalues,
-            num_locals: sub_meta.num_locals,
-            max_stack_size: sub_meta.max_stack_size,
         };
 
         let func_ptr = self
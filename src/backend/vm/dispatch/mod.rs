@@ -3,14 +3,100 @@ mod arithmetic;
 mod compare;
 mod control;
 mod fn_proto;
+pub mod quicken;
 mod table;
 
 use crate::backend::vm::VirtualMachine;
 use crate::backend::vm::error::{ErrorKind, VMError};
+use crate::common::instruction::*;
 use crate::common::opcode::OpCode;
 
 impl VirtualMachine {
-    pub fn execute_instruction(&mut self, instr: OpCode) -> Result<(), VMError> {
+    // the operands are read straight out of the packed instruction, only OP_WIDE goes
+    // through the `OpCode` kept in the function's `bytecode`
+    pub fn execute_instruction(&mut self, instr: Instruction) -> Result<(), VMError> {
+        let (a, b, c) = (instr.a(), instr.b(), instr.c());
+        match instr.op() {
+            OP_MOVE => self.handle_move(a, b),
+            OP_LOADK => self.handle_loadk(a, instr.bx()),
+            OP_LOADNIL => self.handle_load_nil(a),
+            OP_LOADBOOL => self.handle_load_bool(a, b != 0),
+
+            OP_GETGLOBAL => self.handle_get_global(a, instr.bx()),
+            OP_SETGLOBAL => self.handle_set_global(instr.bx(), a),
+
+            OP_GETUPVAL => self.handle_get_upval(a, instr.bx()),
+            OP_SETUPVAL => self.handle_set_upval(instr.bx(), a),
+
+            OP_ADD => self.handle_add_with_feedback(a, b, c),
+            OP_ADDII => self.handle_add_ii(a, b, c),
+            OP_ADDNN => self.handle_add_nn(a, b, c),
+            OP_SUB => self.handle_sub(a, b, c),
+            OP_MUL => self.handle_mul(a, b, c),
+            OP_DIV => self.handle_div(a, b, c),
+            OP_MOD => self.handle_mod(a, b, c),
+            OP_UNOP => self.handle_unary_op(a, b, instr.unary_op()),
+            OP_CONCAT => self.handle_concat(a, b, c),
+            OP_CONCATN => self.handle_concat_n(a, b, c),
+            OP_AND => self.handle_and(a, b, c),
+            OP_OR => self.handle_or(a, b, c),
+            OP_ADDK => self.handle_add_k(a, b, c),
+            OP_SUBK => self.handle_sub_k(a, b, c),
+
+            OP_NEWTABLE => self.handle_new_table(a, b, c),
+            OP_GETTABLE => self.handle_get_table(a, b, c),
+            OP_SETTABLE => self.handle_set_table(a, b, c),
+            OP_SETLIST => self.handle_set_list(a, b, c),
+            OP_GETFIELD => self.handle_get_field(a, b, c),
+            OP_SETFIELD => self.handle_set_field(a, b, c),
+
+            OP_FNPROTO => self.handle_fn_proto(a, instr.bx()),
+
+            OP_EQ => self.handle_eq(a, b, c),
+            OP_NE => self.handle_ne(a, b, c),
+            OP_LT => self.handle_lt(a, b, c),
+            OP_GT => self.handle_gt(a, b, c),
+            OP_LE => self.handle_le(a, b, c),
+            OP_GE => self.handle_ge(a, b, c),
+
+            OP_TEST => self.handle_test(a),
+            OP_JUMP => self.handle_jump(instr.sj()),
+            OP_JMPFALSE => self.handle_jump_if_false(a, instr.sbx()),
+            OP_SWITCH => self.handle_switch(a, instr.bx()),
+            OP_CALL => self.handle_call(a, b as u8, c as u8),
+            OP_GETRESULT => self.handle_get_result(a, b as u8),
+            OP_PUSH => self.handle_push(a),
+            OP_RETURN => self.handle_return(a, b as u8),
+            OP_TAILCALL => self.handle_tail_call(a, b as u8),
+
+            OP_HALT => self.handle_halt(),
+
+            OP_WIDE => {
+                let op = self.wide_opcode()?;
+                self.execute_opcode(op)
+            }
+
+            _ => Err(self.error(ErrorKind::InternalError(format!(
+                "Unsupported opcode: {:?} (Instruction not implemented)",
+                instr
+            )))),
+        }
+    }
+
+    // the unpacked form of the current OP_WIDE instruction
+    fn wide_opcode(&self) -> Result<OpCode, VMError> {
+        self.call_stack
+            .last()
+            .and_then(|frame| frame.meta.as_ref()?.bytecode.get(frame.pc).copied())
+            .ok_or_else(|| {
+                self.error(ErrorKind::InternalError(
+                    "InstructionOutOfBoundsException: wide instruction without an opcode".into(),
+                ))
+            })
+    }
+
+    // runs an instruction in its unpacked form, used for the instructions too wide to be packed
+    pub fn execute_opcode(&mut self, instr: OpCode) -> Result<(), VMError> {
         match instr {
             OpCode::Move { dest, src } => self.handle_move(dest, src),
             OpCode::LoadK { dest, const_idx } => self.handle_loadk(dest, const_idx),
@@ -30,13 +116,35 @@ impl VirtualMachine {
             OpCode::Mod { dest, left, right } => self.handle_mod(dest, left, right),
             OpCode::UnOp { dest, src, op } => self.handle_unary_op(dest, src, op),
             OpCode::Concat { dest, left, right } => self.handle_concat(dest, left, right),
+            OpCode::ConcatN { dest, start, count } => self.handle_concat_n(dest, start, count),
             OpCode::And { dest, left, right } => self.handle_and(dest, left, right),
             OpCode::Or { dest, left, right } => self.handle_or(dest, left, right),
+            OpCode::AddK {
+                dest,
+                left,
+                const_idx,
+            } => self.handle_add_k(dest, left, const_idx),
+            OpCode::SubK {
+                dest,
+                left,
+                const_idx,
+            } => self.handle_sub_k(dest, left, const_idx),
 
             //TODO:未来可能需要增加元表支持
-            OpCode::NewTable { dest, .. } => self.handle_new_table(dest),
+            OpCode::NewTable {
+                dest,
+                size_array,
+                size_hash,
+            } => self.handle_new_table(dest, size_array, size_hash),
             OpCode::GetTable { dest, table, key } => self.handle_get_table(dest, table, key),
             OpCode::SetTable { table, key, value } => self.handle_set_table(table, key, value),
+            OpCode::SetList {
+                table,
+                count,
+                batch,
+            } => self.handle_set_list(table, count, batch),
+            OpCode::GetField { dest, table, key } => self.handle_get_field(dest, table, key),
+            OpCode::SetField { table, key, value } => self.handle_set_field(table, key, value),
 
             OpCode::FnProto { dest, proto_idx } => self.handle_fn_proto(dest, proto_idx),
 
@@ -49,13 +157,17 @@ impl VirtualMachine {
 
             OpCode::Test { reg } => self.handle_test(reg),
             OpCode::Jump { offset } => self.handle_jump(offset),
+            OpCode::JumpIfFalse { reg, offset } => self.handle_jump_if_false(reg, offset),
+            OpCode::Switch { reg, table } => self.handle_switch(reg, table),
             OpCode::Call {
                 func_reg,
                 argc,
                 retc,
             } => self.handle_call(func_reg, argc, retc),
+            OpCode::GetResult { dest, index } => self.handle_get_result(dest, index),
             OpCode::Push { src } => self.handle_push(src),
             OpCode::Return { start, count } => self.handle_return(start, count),
+            OpCode::TailCall { func_reg, argc } => self.handle_tail_call(func_reg, argc),
 
             OpCode::Halt => self.handle_halt(),
 
@@ -0,0 +1,115 @@
+// Quickening: an ADD that keeps seeing two integers or two floats is rewritten, in this VM's copy of
+// the function (`LoadedFunction::quickened`), into ADDII / ADDNN, which only check that the operands
+// still have those types. When they do not, the instruction is put back to ADD and runs generically;
+// one that had to be put back MAX_DEOPTS times stays ADD.
+
+use crate::backend::vm::VirtualMachine;
+use crate::backend::vm::error::VMError;
+use crate::common::instruction::{OP_ADDII, OP_ADDNN};
+use crate::common::object::LuaValue;
+
+// executions in a row with the same operand types before an ADD is quickened
+pub const QUICKEN_AFTER: u8 = 16;
+pub const MAX_DEOPTS: u8 = 4;
+
+/// what one instruction has been seeing, kept per pc
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TypeFeedback {
+    // the quickened opcode the last operands would allow, 0 for none
+    pub op: u8,
+    // executions in a row that allowed it
+    pub hits: u8,
+    pub deopts: u8,
+}
+
+impl VirtualMachine {
+    /// ADD that records the types of its operands and quickens itself once they are stable
+    pub fn handle_add_with_feedback(
+        &mut self,
+        dest: u16,
+        left: u16,
+        right: u16,
+    ) -> Result<(), VMError> {
+        let op = match (self.get_reg(left as usize), self.get_reg(right as usize)) {
+            (LuaValue::Integer(_), LuaValue::Integer(_)) => OP_ADDII,
+            (LuaValue::Number(_), LuaValue::Number(_)) => OP_ADDNN,
+            _ => 0,
+        };
+        if self.record_feedback(op) {
+            self.stats.quickened += 1;
+        }
+        self.handle_add(dest, left, right)
+    }
+
+    /// ADDII: R[dest] = R[left] + R[right] for two integers, wrapping
+    pub fn handle_add_ii(&mut self, dest: u16, left: u16, right: u16) -> Result<(), VMError> {
+        match (*self.get_reg(left as usize), *self.get_reg(right as usize)) {
+            (LuaValue::Integer(x), LuaValue::Integer(y)) => {
+                self.call_stack.last_mut().unwrap().pc += 1;
+                self.set_reg(dest as usize, LuaValue::Integer(x.wrapping_add(y)));
+                Ok(())
+            }
+            _ => {
+                self.deoptimize();
+                self.handle_add(dest, left, right)
+            }
+        }
+    }
+
+    /// ADDNN: R[dest] = R[left] + R[right] for two floats
+    pub fn handle_add_nn(&mut self, dest: u16, left: u16, right: u16) -> Result<(), VMError> {
+        match (*self.get_reg(left as usize), *self.get_reg(right as usize)) {
+            (LuaValue::Number(x), LuaValue::Number(y)) => {
+                self.call_stack.last_mut().unwrap().pc += 1;
+                self.set_reg(dest as usize, LuaValue::Number(x + y));
+                Ok(())
+            }
+            _ => {
+                self.deoptimize();
+                self.handle_add(dest, left, right)
+            }
+        }
+    }
+
+    // count one execution of the instruction at pc that allowed `op`, true when it was quickened
+    fn record_feedback(&self, op: u8) -> bool {
+        let frame = self.call_stack.last().unwrap();
+        let Some(func) = &frame.meta else {
+            return false;
+        };
+        let pc = frame.pc;
+        let mut feedback = func.feedback[pc].get();
+        if feedback.deopts >= MAX_DEOPTS {
+            return false;
+        }
+        if op == 0 {
+            feedback.hits = 0;
+        } else if op == feedback.op {
+            feedback.hits += 1;
+        } else {
+            feedback.op = op;
+            feedback.hits = 1;
+        }
+        let quicken = feedback.hits >= QUICKEN_AFTER;
+        if quicken {
+            feedback.hits = 0;
+            func.quickened[pc].set(func.quickened[pc].get().with_op(op));
+        }
+        func.feedback[pc].set(feedback);
+        quicken
+    }
+
+    // put the quickened instruction at pc back to the generic one it was made from
+    fn deoptimize(&mut self) {
+        let frame = self.call_stack.last().unwrap();
+        if let Some(func) = &frame.meta {
+            let pc = frame.pc;
+            func.quickened[pc].set(func.code[pc]);
+            let mut feedback = func.feedback[pc].get();
+            feedback.deopts += 1;
+            feedback.hits = 0;
+            func.feedback[pc].set(feedback);
+        }
+        self.stats.deopts += 1;
+    }
+}
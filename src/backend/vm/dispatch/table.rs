@@ -1,32 +1,89 @@
 use crate::backend::vm::VirtualMachine;
 use crate::backend::vm::error::{ErrorKind, VMError};
-use crate::common::object::LuaValue;
-use std::collections::HashMap;
+use crate::common::object::{LuaTable, LuaValue};
+use crate::common::opcode::SETLIST_BATCH;
 
 impl VirtualMachine {
-    /// NEWTABLE: 创建新表 R[dest] = {}
-    pub fn handle_new_table(&mut self, dest: u16) -> Result<(), VMError> {
+    /// NEWTABLE: 创建新表 R[dest] = {}, preallocated for the constructor's fields
+    pub fn handle_new_table(
+        &mut self,
+        dest: u16,
+        size_array: u16,
+        size_hash: u16,
+    ) -> Result<(), VMError> {
         self.call_stack.last_mut().unwrap().pc += 1;
-        let new_table = crate::common::object::LuaTable {
-            data: HashMap::new(),
-            metatable: None,
-        };
-
-        let table_ptr = self
-            .heap
-            .alloc_table(new_table)
-            .ok_or_else(|| self.error(ErrorKind::OutOfMemory))?;
+        let table_ptr = self.alloc(|heap| {
+            heap.alloc_table(LuaTable::with_sizes(
+                size_array as usize,
+                size_hash as usize,
+            ))
+        })?;
 
         self.set_reg(dest as usize, LuaValue::Table(table_ptr));
         Ok(())
     }
 
+    /// SETLIST: R[t_reg][batch * SETLIST_BATCH + i] = the i-th of the last `count` values pushed
+    pub fn handle_set_list(&mut self, t_reg: u16, count: u16, batch: u16) -> Result<(), VMError> {
+        self.call_stack.last_mut().unwrap().pc += 1;
+        let first = self
+            .value_stack
+            .values
+            .len()
+            .checked_sub(count as usize)
+            .filter(|&first| first >= self.get_actual_stack_top())
+            .ok_or_else(|| {
+                self.error(ErrorKind::InternalError(
+                    "SETLIST without its values on the stack".into(),
+                ))
+            })?;
+        // only emitted right after the NEWTABLE of a constructor
+        let LuaValue::Table(ptr) = *self.get_reg(t_reg as usize) else {
+            return Err(self.error(ErrorKind::InternalError(
+                "SETLIST on a non-table value".into(),
+            )));
+        };
+
+        let base = batch as i64 * SETLIST_BATCH as i64;
+        for (i, val) in self.value_stack.values[first..].iter().enumerate() {
+            self.heap.write_barrier(ptr, val);
+            self.heap
+                .data_mut(ptr)
+                .set(LuaValue::Integer(base + i as i64 + 1), *val);
+        }
+        self.value_stack.restore(first);
+        Ok(())
+    }
+
     /// SETTABLE: R[t_reg][R[k_reg]] = R[v_reg]
     pub fn handle_set_table(&mut self, t_reg: u16, k_reg: u16, v_reg: u16) -> Result<(), VMError> {
+        let key = *self.get_reg(k_reg as usize);
+        self.set_indexed(t_reg, key, v_reg)
+    }
+
+    /// SETFIELD: R[t_reg][K[k_idx]] = R[v_reg]
+    pub fn handle_set_field(&mut self, t_reg: u16, k_idx: u16, v_reg: u16) -> Result<(), VMError> {
+        let key = *self.get_constant(k_idx as usize);
+        self.set_indexed(t_reg, key, v_reg)
+    }
+
+    /// GETTABLE: R[dest] = R[t_reg][R[k_reg]]
+    pub fn handle_get_table(&mut self, dest: u16, t_reg: u16, k_reg: u16) -> Result<(), VMError> {
+        let key = *self.get_reg(k_reg as usize);
+        self.get_indexed(dest, t_reg, key)
+    }
+
+    /// GETFIELD: R[dest] = R[t_reg][K[k_idx]]
+    pub fn handle_get_field(&mut self, dest: u16, t_reg: u16, k_idx: u16) -> Result<(), VMError> {
+        let key = *self.get_constant(k_idx as usize);
+        self.get_indexed(dest, t_reg, key)
+    }
+
+    fn set_indexed(&mut self, t_reg: u16, key: LuaValue, v_reg: u16) -> Result<(), VMError> {
+        let pc = self.call_stack.last().unwrap().pc;
         self.call_stack.last_mut().unwrap().pc += 1;
-        let table_val = self.get_reg(t_reg as usize).clone();
-        let key = self.get_reg(k_reg as usize).clone();
-        let val = self.get_reg(v_reg as usize).clone();
+        let table_val = *self.get_reg(t_reg as usize);
+        let val = *self.get_reg(v_reg as usize);
 
         if let LuaValue::Table(ptr) = table_val {
             if key == LuaValue::Nil {
@@ -35,59 +92,91 @@ impl VirtualMachine {
                 )));
             }
 
-            unsafe {
-                (*ptr).data.data.insert(key, val);
+            self.heap.write_barrier(ptr, &key);
+            self.heap.write_barrier(ptr, &val);
+            self.heap.data_mut(ptr).set(key, val);
+            Ok(())
+        } else if let (LuaValue::UserData(_), Some(handler)) =
+            (table_val, self.get_metamethod(&table_val, "__newindex"))
+        {
+            // a userdata has no fields of its own, its metatable decides where they go
+            match handler {
+                LuaValue::Table(target) => {
+                    self.heap.write_barrier(target, &key);
+                    self.heap.write_barrier(target, &val);
+                    self.heap.data_mut(target).set(key, val);
+                }
+                handler => {
+                    self.call_value(handler, vec![table_val, key, val])?;
+                }
             }
             Ok(())
         } else {
             Err(self.error(ErrorKind::TypeError(format!(
-                "TypeMismatchException: attempt to index a non-table value (actual type: '{:?}')",
-                table_val
+                "TypeMismatchException: attempt to index a non-table value (actual type: '{:?}'{})",
+                table_val,
+                self.describe_operand(pc)
             ))))
         }
     }
 
-    /// GETTABLE: R[dest] = R[t_reg][R[k_reg]]
-    pub fn handle_get_table(&mut self, dest: u16, t_reg: u16, k_reg: u16) -> Result<(), VMError> {
+    fn get_indexed(&mut self, dest: u16, t_reg: u16, key: LuaValue) -> Result<(), VMError> {
+        let pc = self.call_stack.last().unwrap().pc;
         self.call_stack.last_mut().unwrap().pc += 1;
-        let table_val = self.get_reg(t_reg as usize).clone();
-        let key = self.get_reg(k_reg as usize).clone();
+        let table_val = *self.get_reg(t_reg as usize);
 
         if let LuaValue::Table(ptr) = table_val {
-            let result = unsafe {
-                let lua_table = &(*ptr).data; // 获取 LuaTable 引用
-
-                match lua_table.data.get(&key) {
-                    Some(v) => v.clone(),
-                    None => {
-                        // 如果不存在，检查元表是否存在 __index
-                        // 目前默认返回 nil
-                        LuaValue::Nil
-                    }
-                }
+            // 如果不存在，检查元表是否存在 __index
+            // 目前默认返回 nil
+            let result = self.heap.data(ptr).get(&key);
+            self.set_reg(dest as usize, result);
+            Ok(())
+        } else if let LuaValue::String(_) = table_val {
+            // strings share the `string` library as their index table, e.g. s:upper()
+            let result = match self.get_global("string") {
+                Some(LuaValue::Table(lib)) => self.heap.data(*lib).get(&key),
+                _ => LuaValue::Nil,
+            };
+            self.set_reg(dest as usize, result);
+            Ok(())
+        } else if let (LuaValue::UserData(_), Some(handler)) =
+            (table_val, self.get_metamethod(&table_val, "__index"))
+        {
+            let result = match handler {
+                LuaValue::Table(target) => self.heap.data(target).get(&key),
+                handler => self.call_value(handler, vec![table_val, key])?,
             };
             self.set_reg(dest as usize, result);
             Ok(())
         } else {
             Err(self.error(ErrorKind::TypeError(format!(
-                "TypeMismatchException: attempt to perform property lookup on a non-table value (actual type: '{:?}')",
-                table_val
+                "TypeMismatchException: attempt to perform property lookup on a non-table value (actual type: '{:?}'{})",
+                table_val,
+                self.describe_operand(pc)
             ))))
         }
     }
 
-    // fn get_metamethod(&self, obj: &LuaValue, event: &str) -> Option<LuaValue> {
-    //     if let LuaValue::Table(ptr) = obj {
-    //         unsafe {
-    //             // 1. 获取元表
-    //             let mt_ptr = (*ptr).data.metatable?;
-    //             // 2. 在元表的 data (HashMap) 中寻找事件名（如 "__add"）
-    //             // 注意：这里需要将字符串转为 LuaValue 进行查找
-    //             let key = LuaValue::TempString(event.to_string());
-    //             (*mt_ptr).data.data.get(&key).cloned()
-    //         }
-    //     } else {
-    //         None
-    //     }
-    // }
+    /// ", field 'config'" style suffix for error messages, empty without debug info
+    pub(crate) fn describe_operand(&self, pc: usize) -> String {
+        self.operand_name(pc)
+            .map(|name| format!(", {}", name))
+            .unwrap_or_default()
+    }
+
+    /// look up a metamethod such as "__tostring" in the metatable of obj
+    /// metamethod names are short strings and so always interned, a name that
+    /// was never allocated cannot be a key of any metatable
+    pub fn get_metamethod(&self, obj: &LuaValue, event: &str) -> Option<LuaValue> {
+        let mt_ptr = match obj {
+            LuaValue::Table(ptr) => self.heap.data(*ptr).metatable?,
+            LuaValue::UserData(ptr) => self.heap.data(*ptr).metatable?,
+            _ => return None,
+        };
+        let key = LuaValue::String(*self.heap.string_pool.get(event)?);
+        match self.heap.data(mt_ptr).get(&key) {
+            LuaValue::Nil => None,
+            handler => Some(handler),
+        }
+    }
 }
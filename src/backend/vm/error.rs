@@ -1,3 +1,7 @@
+use crate::backend::vm::std_lib::{format_number, type_name};
+use crate::common::object::LuaValue;
+use std::rc::Rc;
+
 #[derive(Debug, Clone)]
 pub enum ErrorKind {
     // 类型错误：例如 1 + "a"
@@ -18,21 +22,46 @@ pub enum ErrorKind {
     UndefinedUpValue(u16),
     // 尝试多返回值错误
     MultipleReturnValues(String),
+    // io 库读写失败，例如打不开文件
+    IOError(String),
+    // 脚本通过 error() 抛出的任意 Lua 值
+    LuaError(LuaValue),
+    // require 找不到模块，或 require / dofile 加载的源码编译失败
+    ModuleError(String),
+    // 超出嵌入方设置的执行预算（set_fuel / set_timeout），pcall 无法捕获
+    BudgetExceeded(String),
+    // 沙箱配置（VmConfig）禁止的操作，例如给白名单之外的全局变量赋值
+    SandboxViolation(String),
+    // 宿主中止了执行，例如在调试器里输入 quit，pcall 无法捕获
+    Interrupted(String),
+    // 快照无法写出或恢复，例如其中有协程，或镜像损坏
+    SnapshotError(String),
 }
 
+// tracebacks longer than this are cut in the middle by default, see `VirtualMachine::traceback_limit`
+pub const TRACEBACK_MAX_LINES: usize = 22;
+
 #[derive(Debug, Clone)]
 pub struct VMError {
     pub kind: ErrorKind,
     pub func_name: String,
     pub pc: usize,
     pub stack_trace: Vec<String>,
+    // source line each frame of stack_trace was executing, None inside native functions
+    pub stack_lines: Box<[Option<u32>]>,
+    // name of the chunk each frame's lines refer to, e.g. the script path or a required module's file
+    pub stack_chunks: Box<[Rc<str>]>,
 }
 
 impl std::fmt::Display for VMError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ExecutionException: ")?;
+        if let Some(location) = self.location() {
+            write!(f, "{}: ", location)?;
+        }
         write!(
             f,
-            "ExecutionException: {}\n  at function '{}' [Offset: 0x{:04X}]",
+            "{}\n  at function '{}' [Offset: 0x{:04X}]",
             self.get_message(),
             self.func_name,
             self.pc
@@ -41,6 +70,22 @@ impl std::fmt::Display for VMError {
 }
 
 impl VMError {
+    /// source line of the failing instruction
+    pub fn line(&self) -> Option<u32> {
+        self.stack_lines.last().copied().flatten()
+    }
+
+    /// "chunk:line" of the failing instruction, like the prefix of a Lua error message
+    pub fn location(&self) -> Option<String> {
+        let chunk = self.chunk_name()?;
+        self.line().map(|line| format!("{}:{}", chunk, line))
+    }
+
+    /// chunk of the failing frame
+    pub fn chunk_name(&self) -> Option<&str> {
+        self.stack_chunks.last().map(|chunk| chunk.as_ref())
+    }
+
     pub fn get_message(&self) -> String {
         match &self.kind {
             ErrorKind::TypeError(m) => self.format_with_fallback("TypeMismatchException", m),
@@ -68,6 +113,85 @@ impl VMError {
             ErrorKind::MultipleReturnValues(m) => {
                 self.format_with_fallback("MultipleReturnValuesException", m)
             }
+            ErrorKind::IOError(m) => self.format_with_fallback("IOException", m),
+            ErrorKind::ModuleError(m) => self.format_with_fallback("ModuleLoadException", m),
+            ErrorKind::BudgetExceeded(m) => self.format_with_fallback("BudgetExceededException", m),
+            ErrorKind::SandboxViolation(m) => self.format_with_fallback("SecurityException", m),
+            ErrorKind::Interrupted(m) => self.format_with_fallback("InterruptedException", m),
+            ErrorKind::SnapshotError(m) => self.format_with_fallback("SnapshotException", m),
+            ErrorKind::LuaError(val) => match val {
+                LuaValue::String(ptr) => self.format_with_fallback("RuntimeException", ptr.get()),
+                LuaValue::Number(n) => format!("RuntimeException: {}", format_number(*n)),
+                LuaValue::Integer(i) => format!("RuntimeException: {}", i),
+                other => format!(
+                    "RuntimeException: (error object is a {} value)",
+                    type_name(other)
+                ),
+            },
+        }
+    }
+
+    /// traceback lines, most recent call first
+    ///
+    /// runs of the same function (deep recursion) are folded into a single
+    /// "... N more frames like #i at f()" line; with a `limit`, the output is
+    /// further capped to that many lines by dropping lines from the middle
+    pub fn traceback_lines(&self, limit: Option<usize>) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut i = self.stack_trace.len();
+        while i > 0 {
+            let name = &self.stack_trace[i - 1];
+            let mut run = 1;
+            while run < i && &self.stack_trace[i - 1 - run] == name {
+                run += 1;
+            }
+
+            lines.push(format!(
+                "#{:<2} at {}(){}",
+                i - 1,
+                name,
+                self.frame_suffix(i - 1)
+            ));
+            if run > 1 {
+                if run == 2 {
+                    lines.push(format!(
+                        "#{:<2} at {}(){}",
+                        i - 2,
+                        name,
+                        self.frame_suffix(i - 2)
+                    ));
+                } else {
+                    lines.push(format!(
+                        "... {} more frames like #{} at {}()",
+                        run - 1,
+                        i - 1,
+                        name
+                    ));
+                }
+            }
+            i -= run;
+        }
+
+        if let Some(limit) = limit.filter(|&limit| lines.len() > limit) {
+            let (head, tail) = (limit - limit / 2, limit / 2);
+            let omitted = lines.len() - limit;
+            let tail = lines.split_off(lines.len() - tail);
+            lines.truncate(head);
+            lines.push(format!(
+                "... ({} traceback lines omitted, enable the full traceback to see them)",
+                omitted
+            ));
+            lines.extend(tail);
+        }
+
+        lines
+    }
+
+    // " (chunk:line)" for frames whose line is known
+    fn frame_suffix(&self, idx: usize) -> String {
+        match self.stack_lines.get(idx).copied().flatten() {
+            Some(line) => format!(" ({}:{})", self.stack_chunks[idx], line),
+            None => String::new(),
         }
     }
 
@@ -16,99 +16,428 @@
 //            to provide an ultimate safeguard against OOM scenarios in the VM runtime.
 // 2026-02-19: Add more debug information for GC tuning, including max_allocated to track peak memory usage during execution,
 //            aiding in optimizing GC thresholds and understanding memory patterns of Lua programs running on the VM.
-use crate::common::object::{GCObject, HeaderOnly, LFunction, LuaValue, ObjectKind};
+// 2026-02-23: Added alloc_str, which interns from a borrowed string and skips the copy for pooled strings.
+// 2026-02-24: Added alloc_native_closure for host closures.
+// 2026-02-24: Generational mode (GcMode::Generational): new objects go to the `nursery` list and are moved
+//            to `all_objects` once they survive a collection; `write_barrier` records old objects that
+//            were given a reference to a young one in `remembered`, the extra roots of a minor collection.
+// 2026-02-24: `pause` scales the threshold after a full collection (collectgarbage("setpause")), `stress`
+//            asks for a collection after every allocation; `stats` counts the live objects by kind.
+// 2026-02-24: Interned strings carry the hash of their content (`string_hash`) in the object header,
+//            table lookups with a string key hash that instead of the string.
+// 2026-02-24: Added alloc_coroutine; `remember` queues an old object whose contents changed wholesale.
+// 2026-02-24: `lifetime_allocated` counts every byte ever allocated, for the profiler.
+// 2026-02-24: The hard memory limit is the heap's `limit`, set per VM from VmConfig::heap_limit.
+// 2026-02-24: Objects are handed out as `Gc<T>` handles instead of raw pointers. A handle is the only way to
+//            reach an object's data from outside this file; debug builds keep the set of objects that are
+//            still allocated and panic when a freed one is accessed. Marking, sweeping and freeing moved
+//            here from the VM, which only passes in its roots. The open upvalues of a frame are marked too,
+//            they used to be freed with the last closure that captured them and closed on return anyway.
+// 2026-02-24: `roots` holds the values of the open `RootScope`s (`VirtualMachine::with_roots`), marked with the
+//            VM's roots; `VirtualMachine::alloc` runs a full collection and retries when the limit is hit.
+// 2026-02-24: Weak tables (`__mode` "k" / "v") and finalizers: marking skips the weak part of a table and
+//            records it, `finish_marking` clears the entries that point to dead objects and moves the
+//            unreachable `finalizable` tables to `to_finalize`, marking them again (resurrection) so they
+//            survive until their __gc ran. Weak keys are not ephemerons, a value always keeps its key.
+// 2026-02-24: Only short strings (up to MAX_SHORT_STRING_LEN bytes) are interned by alloc_string / alloc_str,
+//            a longer one built at runtime is an object of its own and compares by content; `intern_str`
+//            pools a string of any length, for constants. Freeing a string only drops its pool entry
+//            when the entry is that very object.
+// 2026-02-24: Object data is borrowed through the heap, `data` for reading and `data_mut` (only for the
+//            `Mutable` kinds) for writing; `Gc::get_mut` is gone, it handed out `&mut T` from any copy
+//            of a handle. `Gc::get` is left for strings only, they are never written after allocation.
+use crate::backend::vm::VirtualMachine;
+use crate::backend::vm::error::{ErrorKind, VMError};
+use crate::backend::vm::stack::{GlobalStack, StackFrame};
+use crate::common::object::{
+    GCObject, HeaderOnly, LFunction, LuaCoroutine, LuaTable, LuaUpValue, LuaUpValueState,
+    LuaUserData, LuaValue, NativeClosure, ObjectKind,
+};
+use clap::ValueEnum;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+/// how the VM collects garbage
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+pub enum GcMode {
+    /// stop-the-world mark-and-sweep over every object
+    #[default]
+    Full,
+    /// frequent minor collections of the young objects only, full ones when the heap doubles
+    Generational,
+}
+
+/// strings up to this many bytes are interned, like Lua's short strings
+pub const MAX_SHORT_STRING_LEN: usize = 40;
+
+/// FNV-1a over the bytes, the same for a string in every VM and every run
+pub fn string_hash(s: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in s.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// what the heap holds right now, reported by collectgarbage("count")
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    pub strings: usize,
+    pub tables: usize,
+    pub functions: usize,
+    pub upvalues: usize,
+    pub native_closures: usize,
+    pub coroutines: usize,
+    pub userdata: usize,
+    pub bytes: usize,
+}
+
+/// a reference to an object on the heap, copied around as freely as the pointer it is
+///
+/// a handle does not give access to the object's data by itself, that is borrowed from the heap
+/// (`Heap::data` / `Heap::data_mut`), so the borrow checker keeps a mutable borrow unique however
+/// many copies of the handle exist; strings, which never change, are read with `get`
+///
+/// the object lives as long as the collector can reach it from the VM's roots; a handle kept
+/// somewhere the collector does not look at may outlive its object, debug builds catch the
+/// access that follows
+#[repr(transparent)]
+pub struct Gc<T> {
+    ptr: NonNull<GCObject<T>>,
+}
+
+impl<T> Gc<T> {
+    // only the heap makes handles, for objects it allocated
+    fn from_raw(ptr: *mut GCObject<T>) -> Self {
+        Self {
+            ptr: NonNull::new(ptr).expect("GC handle to a null object"),
+        }
+    }
+
+    /// the object's address, its identity
+    pub fn as_ptr(self) -> *mut GCObject<T> {
+        self.ptr.as_ptr()
+    }
+
+    pub fn addr(self) -> usize {
+        self.as_ptr() as usize
+    }
+
+    /// hash of a string's content, 0 for other objects
+    #[inline]
+    pub fn content_hash(&self) -> u64 {
+        // header fields are read through the pointer, the data may be borrowed mutably meanwhile
+        self.check();
+        unsafe { (*self.as_ptr()).hash }
+    }
+
+    /// survived a collection in generational mode
+    pub fn is_old(&self) -> bool {
+        self.check();
+        unsafe { (*self.as_ptr()).old }
+    }
+
+    #[inline]
+    fn check(&self) {
+        #[cfg(debug_assertions)]
+        live::check(self.addr());
+    }
+
+    fn header(self) -> *mut GCObject<HeaderOnly> {
+        self.as_ptr() as *mut GCObject<HeaderOnly>
+    }
+}
+
+impl Gc<String> {
+    /// strings never change once allocated, so unlike other objects they are read without
+    /// borrowing the heap
+    #[inline]
+    pub fn get(&self) -> &String {
+        self.check();
+        unsafe { &(*self.as_ptr()).data }
+    }
+}
+
+impl<T> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Gc<T> {}
+
+impl<T> PartialEq for Gc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr
+    }
+}
+
+impl<T> Eq for Gc<T> {}
+
+impl<T> Hash for Gc<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.addr().hash(state);
+    }
+}
+
+impl<T> fmt::Pointer for Gc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&self.ptr, f)
+    }
+}
+
+impl<T> fmt::Debug for Gc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Gc({:p})", self.ptr)
+    }
+}
+
+// addresses of the objects every heap of this thread has allocated and not freed yet
+#[cfg(debug_assertions)]
+mod live {
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+
+    thread_local! {
+        static OBJECTS: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+    }
+
+    pub(super) fn insert(addr: usize) {
+        OBJECTS.with(|objects| objects.borrow_mut().insert(addr));
+    }
+
+    pub(super) fn remove(addr: usize) {
+        OBJECTS.with(|objects| objects.borrow_mut().remove(&addr));
+    }
+
+    pub(super) fn check(addr: usize) {
+        if !OBJECTS.with(|objects| objects.borrow().contains(&addr)) {
+            panic!(
+                "use after free: the GC object at {:#x} was already collected",
+                addr
+            );
+        }
+    }
+}
+
+/// the objects the VM changes after allocating them, strings are immutable
+pub trait Mutable {}
+
+impl Mutable for LuaTable {}
+impl Mutable for LFunction {}
+impl Mutable for LuaUpValue {}
+impl Mutable for NativeClosure {}
+impl Mutable for LuaCoroutine {}
+impl Mutable for LuaUserData {}
 
 pub struct Heap {
+    // switched through set_mode only, it moves objects between the lists
+    mode: GcMode,
+    // old objects, every object in Full mode
     pub all_objects: *mut GCObject<HeaderOnly>,
-    pub string_pool: HashMap<String, *mut GCObject<String>>,
+    // objects allocated since the last collection, only used in Generational mode
+    pub nursery: *mut GCObject<HeaderOnly>,
+    pub nursery_allocated: usize,
+    pub nursery_threshold: usize,
+    // old objects that may point to young ones, traced by minor collections
+    pub remembered: Vec<*mut GCObject<HeaderOnly>>,
+    pub string_pool: HashMap<String, Gc<String>>,
     pub total_allocated: usize,
+    // bytes ever allocated, never decreases; the profiler charges its growth to functions
+    pub lifetime_allocated: usize,
     pub threshold: usize,
+    // bytes the heap may hold at once, VmConfig::heap_limit
+    pub limit: usize,
     // used for debugging and tuning GC parameters, not used in actual GC logic
     pub max_allocated: usize,
+    // percentage the threshold grows by at each full collection, 200 doubles it
+    pub pause: usize,
+    // collect at the first safe point after every allocation, to shake out missing roots
+    pub stress: bool,
+    // objects allocated since the last sweep
+    pub allocs_since_collection: usize,
+    // set during a minor collection, marking takes old objects as alive without tracing them
+    pub(crate) minor: bool,
+    // values native code keeps alive on top of the VM's roots, pushed through a `RootScope`
+    pub(crate) roots: Vec<LuaValue>,
+    // tables whose metatable had __gc when it was set, in the order they were registered
+    pub(crate) finalizable: Vec<Gc<LuaTable>>,
+    // unreachable finalizable tables, kept alive until the VM ran their __gc
+    pub(crate) to_finalize: Vec<Gc<LuaTable>>,
+    // weak tables met while marking: the table, weak keys, weak values
+    weak: RefCell<Vec<(Gc<LuaTable>, bool, bool)>>,
 }
 
 impl Heap {
     pub fn new() -> Self {
         Self {
+            mode: GcMode::Full,
             all_objects: std::ptr::null_mut(),
+            nursery: std::ptr::null_mut(),
+            nursery_allocated: 0,
+            nursery_threshold: crate::backend::vm::NURSERY_THRESHOLD,
+            remembered: Vec::new(),
             string_pool: HashMap::new(),
             total_allocated: 0,
+            lifetime_allocated: 0,
             threshold: crate::backend::vm::VM_THRESHOLD,
+            limit: crate::backend::vm::HARD_MEMORY_LIMIT,
             max_allocated: 0,
+            pause: 200,
+            stress: false,
+            allocs_since_collection: 0,
+            minor: false,
+            roots: Vec::new(),
+            finalizable: Vec::new(),
+            to_finalize: Vec::new(),
+            weak: RefCell::new(Vec::new()),
         }
     }
 
-    pub fn alloc_string(&mut self, s: String) -> Option<*mut GCObject<String>> {
+    pub fn alloc_string(&mut self, s: String) -> Option<Gc<String>> {
+        if s.len() > MAX_SHORT_STRING_LEN {
+            return self.alloc_string_object(s, false);
+        }
         if let Some(&ptr) = self.string_pool.get(&s) {
             return Some(ptr);
         }
+        self.alloc_string_object(s, true)
+    }
+    // same as alloc_string, but only copies s when it is not interned yet,
+    // so builtins can intern results straight out of a scratch buffer
+    pub fn alloc_str(&mut self, s: &str) -> Option<Gc<String>> {
+        if let Some(&ptr) = self.string_pool.get(s) {
+            return Some(ptr);
+        }
+        self.alloc_string(s.to_string())
+    }
+
+    // interned whatever its length, constants are looked up and compared over and over
+    pub fn intern_str(&mut self, s: &str) -> Option<Gc<String>> {
+        if let Some(&ptr) = self.string_pool.get(s) {
+            return Some(ptr);
+        }
+        self.alloc_string_object(s.to_string(), true)
+    }
 
+    fn alloc_string_object(&mut self, s: String, interned: bool) -> Option<Gc<String>> {
         let extra_mem = s.capacity();
         let total_size = std::mem::size_of::<GCObject<String>>() + extra_mem;
 
-        if let Some(ptr) = self.alloc_raw_object(s.clone(), ObjectKind::String, total_size) {
-            self.string_pool.insert(s, ptr);
-            Some(ptr)
-        } else {
-            None
+        let hash = string_hash(&s);
+        let key = interned.then(|| s.clone());
+        let ptr = self.alloc_raw_object(s, ObjectKind::String, total_size)?;
+        // not handed out yet, nothing else refers to the object
+        unsafe { (*ptr.as_ptr()).hash = hash };
+        if let Some(key) = key {
+            self.string_pool.insert(key, ptr);
         }
+        Some(ptr)
     }
-    pub fn alloc_table(
-        &mut self,
-        table_data: crate::common::object::LuaTable,
-    ) -> Option<*mut GCObject<crate::common::object::LuaTable>> {
-        let size = std::mem::size_of::<GCObject<crate::common::object::LuaTable>>()
+
+    pub fn alloc_table(&mut self, table_data: LuaTable) -> Option<Gc<LuaTable>> {
+        let size = std::mem::size_of::<GCObject<LuaTable>>()
+            + table_data.array.capacity() * std::mem::size_of::<LuaValue>()
             + table_data.data.capacity() * std::mem::size_of::<(LuaValue, LuaValue)>();
 
         self.alloc_raw_object(table_data, ObjectKind::Table, size)
     }
 
-    pub fn alloc_function(&mut self, data: LFunction) -> Option<*mut GCObject<LFunction>> {
+    pub fn alloc_function(&mut self, data: LFunction) -> Option<Gc<LFunction>> {
         let size = std::mem::size_of::<GCObject<LFunction>>()
-            + data.opcodes.capacity() * std::mem::size_of::<crate::common::opcode::OpCode>()
-            + data.constants.capacity() * std::mem::size_of::<LuaValue>();
+            + data.upvalues.capacity() * std::mem::size_of::<Gc<LuaUpValue>>();
 
         self.alloc_raw_object(data, ObjectKind::Function, size)
     }
 
-    pub fn alloc_upvalue_object(
-        &mut self,
-        upval: crate::common::object::LuaUpValue,
-    ) -> Option<*mut GCObject<crate::common::object::LuaUpValue>> {
-        let size = std::mem::size_of::<GCObject<crate::common::object::LuaUpValue>>();
+    pub fn alloc_upvalue_object(&mut self, upval: LuaUpValue) -> Option<Gc<LuaUpValue>> {
+        let size = std::mem::size_of::<GCObject<LuaUpValue>>();
 
         self.alloc_raw_object(upval, ObjectKind::UpValue, size)
     }
 
-    fn alloc_raw_object<T>(
-        &mut self,
-        data: T,
-        kind: ObjectKind,
-        size: usize,
-    ) -> Option<*mut GCObject<T>> {
-        if self.total_allocated + size > crate::backend::vm::HARD_MEMORY_LIMIT {
+    pub fn alloc_native_closure(&mut self, closure: NativeClosure) -> Option<Gc<NativeClosure>> {
+        // the captured state is opaque, only the box itself is accounted for
+        let size = std::mem::size_of::<GCObject<NativeClosure>>() + closure.name.capacity();
+
+        self.alloc_raw_object(closure, ObjectKind::NativeClosure, size)
+    }
+
+    pub fn alloc_coroutine(&mut self, co: LuaCoroutine) -> Option<Gc<LuaCoroutine>> {
+        // the stacks grow while the coroutine runs, only the object itself is accounted for
+        let size = std::mem::size_of::<GCObject<LuaCoroutine>>();
+
+        self.alloc_raw_object(co, ObjectKind::Coroutine, size)
+    }
+
+    pub fn alloc_userdata(&mut self, userdata: LuaUserData) -> Option<Gc<LuaUserData>> {
+        // the payload is opaque, only the object itself is accounted for
+        let size = std::mem::size_of::<GCObject<LuaUserData>>();
+
+        self.alloc_raw_object(userdata, ObjectKind::UserData, size)
+    }
+
+    /// the data of an object the heap owns, borrowed for as long as the heap is
+    ///
+    /// every object belongs to the heap, so while this borrow lasts no handle can reach the
+    /// object through `data_mut`
+    #[inline]
+    pub fn data<T>(&self, obj: Gc<T>) -> &T {
+        obj.check();
+        unsafe { &(*obj.as_ptr()).data }
+    }
+
+    /// the data of an object the heap owns, borrowed mutably for as long as the heap is;
+    /// the only way to change an object, the borrow of the heap keeps it unique
+    #[inline]
+    pub fn data_mut<T: Mutable>(&mut self, obj: Gc<T>) -> &mut T {
+        obj.check();
+        unsafe { &mut (*obj.as_ptr()).data }
+    }
+
+    fn alloc_raw_object<T>(&mut self, data: T, kind: ObjectKind, size: usize) -> Option<Gc<T>> {
+        if self.total_allocated + size > self.limit {
             return None;
         }
 
+        let list = match self.mode {
+            GcMode::Full => &mut self.all_objects,
+            GcMode::Generational => {
+                self.nursery_allocated += size;
+                &mut self.nursery
+            }
+        };
         let obj = GCObject {
             mark: false,
+            // everything on all_objects counts as old, so the mode can be switched at any time
+            old: self.mode == GcMode::Full,
+            remembered: false,
+            hash: 0,
             kind,
             size,
-            next: self.all_objects,
+            next: *list,
             data,
         };
         let boxed = Box::new(obj);
         let ptr = Box::into_raw(boxed);
-        self.all_objects = ptr as *mut GCObject<HeaderOnly>;
+        *list = ptr as *mut GCObject<HeaderOnly>;
+        #[cfg(debug_assertions)]
+        live::insert(ptr as usize);
 
         self.total_allocated += size;
+        self.lifetime_allocated += size;
+        self.allocs_since_collection += 1;
 
         if self.total_allocated > self.max_allocated {
             self.max_allocated = self.total_allocated;
         }
 
-        Some(ptr)
+        Some(Gc::from_raw(ptr))
     }
 
     pub fn check_gc_condition(&mut self) -> bool {
@@ -119,6 +448,475 @@ impl Heap {
     }
 
     pub fn expand_threshold(&mut self) {
-        self.threshold *= 2;
+        self.threshold = self.threshold.saturating_mul(self.pause) / 100;
+    }
+
+    pub fn check_stress_condition(&self) -> bool {
+        self.stress && self.allocs_since_collection > 0
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let mut stats = HeapStats {
+            bytes: self.total_allocated,
+            ..HeapStats::default()
+        };
+        for list in [self.all_objects, self.nursery] {
+            let mut p = list;
+            while !p.is_null() {
+                unsafe {
+                    match (*p).kind {
+                        ObjectKind::String => stats.strings += 1,
+                        ObjectKind::Table => stats.tables += 1,
+                        ObjectKind::Function => stats.functions += 1,
+                        ObjectKind::UpValue => stats.upvalues += 1,
+                        ObjectKind::NativeClosure => stats.native_closures += 1,
+                        ObjectKind::Coroutine => stats.coroutines += 1,
+                        ObjectKind::UserData => stats.userdata += 1,
+                    }
+                    p = (*p).next;
+                }
+            }
+        }
+        stats
+    }
+
+    pub fn mode(&self) -> GcMode {
+        self.mode
+    }
+
+    /// switch the collector, young objects become old ones when leaving Generational mode
+    pub fn set_mode(&mut self, mode: GcMode) {
+        if mode == GcMode::Full {
+            unsafe {
+                while !self.nursery.is_null() {
+                    let obj = self.nursery;
+                    self.nursery = (*obj).next;
+                    (*obj).old = true;
+                    (*obj).next = self.all_objects;
+                    self.all_objects = obj;
+                }
+            }
+            self.clear_remembered();
+            self.nursery_allocated = 0;
+        }
+        self.mode = mode;
+    }
+
+    /// empty the remembered set, e.g. once no old object refers to a young one any more
+    pub(crate) fn clear_remembered(&mut self) {
+        for &obj in &self.remembered {
+            unsafe { (*obj).remembered = false };
+        }
+        self.remembered.clear();
+    }
+
+    pub fn check_minor_gc_condition(&self) -> bool {
+        self.mode == GcMode::Generational && self.nursery_allocated > self.nursery_threshold
+    }
+
+    /// call after storing `value` into the table or upvalue `obj`: an old object that now refers
+    /// to a young one is remembered, otherwise a minor collection would free the young object
+    pub(crate) fn write_barrier<T>(&mut self, obj: Gc<T>, value: &LuaValue) {
+        let young = match value {
+            LuaValue::String(ptr) => !ptr.is_old(),
+            LuaValue::Table(ptr) => !ptr.is_old(),
+            LuaValue::Function(ptr) => !ptr.is_old(),
+            LuaValue::NativeClosure(ptr) => !ptr.is_old(),
+            LuaValue::Coroutine(ptr) => !ptr.is_old(),
+            LuaValue::UserData(ptr) => !ptr.is_old(),
+            _ => false,
+        };
+        if young {
+            self.remember(obj);
+        }
+    }
+
+    /// queue an old object for the next minor collection whatever it now refers to,
+    /// for stores too many to go through `write_barrier` one by one (a coroutine's stacks)
+    pub(crate) fn remember<T>(&mut self, obj: Gc<T>) {
+        obj.check();
+        let header = obj.header();
+        unsafe {
+            if self.mode == GcMode::Generational && (*header).old && !(*header).remembered {
+                (*header).remembered = true;
+                self.remembered.push(header);
+            }
+        }
+    }
+
+    // ---------------------------------------------------------------------------
+    // collection: the VM marks its roots through the mark_* methods, then sweeps
+    // ---------------------------------------------------------------------------
+
+    pub(crate) fn mark_value(&self, value: &LuaValue) {
+        match value {
+            LuaValue::String(ptr) => self.mark(*ptr),
+            LuaValue::Table(ptr) => self.mark(*ptr),
+            LuaValue::Function(ptr) => self.mark(*ptr),
+            // whatever the closure captured lives on the Rust side
+            LuaValue::NativeClosure(ptr) => self.mark(*ptr),
+            LuaValue::Coroutine(ptr) => self.mark(*ptr),
+            LuaValue::UserData(ptr) => self.mark(*ptr),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn mark<T>(&self, obj: Gc<T>) {
+        let ptr = obj.header();
+        if self.mark_raw(ptr) {
+            self.mark_children(ptr);
+        }
+    }
+
+    // the stacks of a thread
+    pub(crate) fn mark_stacks(&self, value_stack: &GlobalStack, call_stack: &[StackFrame]) {
+        for value in &value_stack.values {
+            self.mark_value(value);
+        }
+        for frame in call_stack {
+            for upval in &frame.upvalues {
+                self.mark(*upval);
+            }
+            // the open upvalues are closed when the frame returns, even after the closures
+            // that captured them are gone
+            for (_, upval) in &frame.out_upvalues {
+                self.mark(*upval);
+            }
+        }
+    }
+
+    // the values rooted by the open `RootScope`s
+    pub(crate) fn mark_roots(&self) {
+        for value in &self.roots {
+            self.mark_value(value);
+        }
+    }
+
+    // the extra roots of a minor collection
+    pub(crate) fn mark_remembered(&self) {
+        for &obj in &self.remembered {
+            self.mark_children(obj);
+        }
+    }
+
+    // mark whatever a heap object refers to
+    fn mark_children(&self, ptr: *mut GCObject<HeaderOnly>) {
+        unsafe {
+            match (*ptr).kind {
+                ObjectKind::Table => {
+                    let table = Gc::from_raw(ptr as *mut GCObject<LuaTable>);
+                    let table_inner = self.data(table);
+                    let (weak_keys, weak_values) = self.weak_mode(table_inner);
+                    if weak_keys || weak_values {
+                        self.weak.borrow_mut().push((table, weak_keys, weak_values));
+                    }
+
+                    for v in &table_inner.array {
+                        self.mark_entry(v, weak_values);
+                    }
+                    for (k, v) in &table_inner.data {
+                        self.mark_entry(k, weak_keys);
+                        self.mark_entry(v, weak_values);
+                    }
+
+                    if let Some(mt_ptr) = table_inner.metatable {
+                        self.mark(mt_ptr);
+                    }
+                }
+                ObjectKind::Function => {
+                    let func = &(*(ptr as *mut GCObject<LFunction>)).data;
+                    // the prototype may be gone from the VM, e.g. after a reset
+                    for val in &func.proto.const_values {
+                        self.mark_value(val);
+                    }
+                    for upval in &func.upvalues {
+                        self.mark(*upval);
+                    }
+                }
+                ObjectKind::UpValue => {
+                    // only mark closed upvalues,
+                    // because open upvalues point to stack slots
+                    let upval = &(*(ptr as *mut GCObject<LuaUpValue>)).data;
+                    match &upval.value {
+                        LuaUpValueState::Closed(val) => self.mark_value(val),
+                        // keeps a suspended coroutine alive, the slot is in its stack
+                        LuaUpValueState::Open(_) => {
+                            if let Some(thread) = upval.thread {
+                                self.mark(thread);
+                            }
+                        }
+                    }
+                }
+                ObjectKind::Coroutine => {
+                    let co = &(*(ptr as *mut GCObject<LuaCoroutine>)).data;
+                    self.mark_value(&co.func);
+                    self.mark_stacks(&co.value_stack, &co.call_stack);
+                }
+                ObjectKind::UserData => {
+                    let userdata = &(*(ptr as *mut GCObject<LuaUserData>)).data;
+                    if let Some(mt_ptr) = userdata.metatable {
+                        self.mark(mt_ptr);
+                    }
+                }
+                ObjectKind::String | ObjectKind::NativeClosure => {}
+            }
+        }
+    }
+
+    // strings are values, not references, a weak table keeps them like any other table
+    fn mark_entry(&self, value: &LuaValue, weak: bool) {
+        if !weak || matches!(value, LuaValue::String(_)) {
+            self.mark_value(value);
+        }
+    }
+
+    // which parts of a table its metatable's __mode makes weak: (keys, values)
+    fn weak_mode(&self, table: &LuaTable) -> (bool, bool) {
+        let Some(mt) = table.metatable else {
+            return (false, false);
+        };
+        let Some(&key) = self.string_pool.get("__mode") else {
+            return (false, false);
+        };
+        match self.data(mt).get(&LuaValue::String(key)) {
+            LuaValue::String(mode) => (mode.get().contains('k'), mode.get().contains('v')),
+            _ => (false, false),
+        }
+    }
+
+    // a value the collection is about to free, the mark bits are only meaningful after marking
+    fn is_dead(&self, value: &LuaValue) -> bool {
+        let header = match value {
+            LuaValue::Table(ptr) => ptr.header(),
+            LuaValue::Function(ptr) => ptr.header(),
+            LuaValue::NativeClosure(ptr) => ptr.header(),
+            LuaValue::Coroutine(ptr) => ptr.header(),
+            LuaValue::UserData(ptr) => ptr.header(),
+            _ => return false,
+        };
+        unsafe { !((*header).mark || (self.minor && (*header).old)) }
+    }
+
+    /// the end of the mark phase, between marking the roots and sweeping: weak values that are
+    /// about to be freed are dropped, unreachable finalizable tables are queued for their __gc
+    /// and marked again with everything they reach, then the remaining dead weak entries go
+    ///
+    /// resurrected objects leave weak values before their finalizer runs, but stay as weak
+    /// keys until the collection after it, as in the reference implementation
+    pub(crate) fn finish_marking(&mut self) {
+        self.clear_weak(false);
+
+        let (dead, alive): (Vec<_>, Vec<_>) = self
+            .finalizable
+            .iter()
+            .partition(|t| self.is_dead(&LuaValue::Table(**t)));
+        self.finalizable = alive;
+        self.to_finalize.extend(dead);
+        for &table in &self.to_finalize {
+            self.mark(table);
+        }
+
+        self.clear_weak(true);
+        self.weak.borrow_mut().clear();
+    }
+
+    // drop the entries of the weak tables that refer to dead objects through their weak part,
+    // values only or keys as well
+    fn clear_weak(&mut self, keys: bool) {
+        let weak = self.weak.borrow().clone();
+        for (table, weak_keys, weak_values) in weak {
+            let weak_keys = weak_keys && keys;
+            let dead: Vec<LuaValue> = self
+                .data(table)
+                .iter()
+                .filter(|(k, v)| (weak_keys && self.is_dead(k)) || (weak_values && self.is_dead(v)))
+                .map(|(k, _)| k)
+                .collect();
+            for key in dead {
+                self.data_mut(table).set(key, LuaValue::Nil);
+            }
+        }
+    }
+
+    fn mark_raw(&self, ptr: *mut GCObject<HeaderOnly>) -> bool {
+        // a minor collection takes old objects as alive without tracing them
+        unsafe {
+            if (*ptr).mark || (self.minor && (*ptr).old) {
+                return false;
+            }
+            (*ptr).mark = true;
+        }
+        true
+    }
+
+    /// free every old object that was not marked, the young ones are left to `sweep_nursery`;
+    /// returns the objects and bytes freed
+    pub(crate) fn sweep(&mut self) -> (usize, usize) {
+        // a remembered object may be freed below, sweep_nursery must not touch it afterwards
+        self.clear_remembered();
+        let mut swept_count = 0;
+        let mut swept_bytes = 0;
+        unsafe {
+            let mut p_prev: *mut GCObject<HeaderOnly> = std::ptr::null_mut();
+            let mut p_curr = self.all_objects;
+
+            while !p_curr.is_null() {
+                if (*p_curr).mark {
+                    (*p_curr).mark = false;
+                    p_prev = p_curr;
+                    p_curr = (*p_curr).next;
+                } else {
+                    let p_next = (*p_curr).next;
+                    if p_prev.is_null() {
+                        self.all_objects = p_next;
+                    } else {
+                        (*p_prev).next = p_next;
+                    }
+
+                    swept_count += 1;
+                    swept_bytes += (*p_curr).size;
+                    self.free_object(p_curr);
+
+                    p_curr = p_next;
+                }
+            }
+        }
+        (swept_count, swept_bytes)
+    }
+
+    /// free the unmarked young objects and move the marked ones to `all_objects`,
+    /// afterwards no old object can point to a young one, so the remembered set is emptied;
+    /// returns the objects and bytes freed
+    pub(crate) fn sweep_nursery(&mut self) -> (usize, usize) {
+        let mut swept_count = 0;
+        let mut swept_bytes = 0;
+        unsafe {
+            let mut p_curr = std::mem::replace(&mut self.nursery, std::ptr::null_mut());
+            while !p_curr.is_null() {
+                let p_next = (*p_curr).next;
+                if (*p_curr).mark {
+                    (*p_curr).mark = false;
+                    (*p_curr).old = true;
+                    (*p_curr).next = self.all_objects;
+                    self.all_objects = p_curr;
+                } else {
+                    swept_count += 1;
+                    swept_bytes += (*p_curr).size;
+                    self.free_object(p_curr);
+                }
+                p_curr = p_next;
+            }
+        }
+        self.nursery_allocated = 0;
+        self.allocs_since_collection = 0;
+        self.clear_remembered();
+        (swept_count, swept_bytes)
+    }
+
+    // the object must already be unlinked from its list
+    unsafe fn free_object(&mut self, ptr: *mut GCObject<HeaderOnly>) {
+        #[cfg(debug_assertions)]
+        live::remove(ptr as usize);
+        unsafe {
+            self.total_allocated = self.total_allocated.saturating_sub((*ptr).size);
+
+            match (*ptr).kind {
+                ObjectKind::String => {
+                    let str_ptr = ptr as *mut GCObject<String>;
+                    // a long string may have the content of an interned constant
+                    let data = &(*str_ptr).data;
+                    if self
+                        .string_pool
+                        .get(data)
+                        .is_some_and(|pooled| pooled.as_ptr() == str_ptr)
+                    {
+                        self.string_pool.remove(data);
+                    }
+                    let _ = Box::from_raw(str_ptr);
+                }
+                ObjectKind::Table => {
+                    let _ = Box::from_raw(ptr as *mut GCObject<LuaTable>);
+                }
+                ObjectKind::Function => {
+                    let _ = Box::from_raw(ptr as *mut GCObject<LFunction>);
+                }
+                ObjectKind::UpValue => {
+                    let _ = Box::from_raw(ptr as *mut GCObject<LuaUpValue>);
+                }
+                ObjectKind::NativeClosure => {
+                    let _ = Box::from_raw(ptr as *mut GCObject<NativeClosure>);
+                }
+                ObjectKind::Coroutine => {
+                    let _ = Box::from_raw(ptr as *mut GCObject<LuaCoroutine>);
+                }
+                // drops the Rust value as well
+                ObjectKind::UserData => {
+                    let _ = Box::from_raw(ptr as *mut GCObject<LuaUserData>);
+                }
+            }
+        }
+    }
+}
+
+/// a VM borrowed by `VirtualMachine::with_roots`, values handed to `root` survive every collection
+/// until the scope ends; everything else is reached through `Deref` to the VM
+pub struct RootScope<'vm> {
+    vm: &'vm mut VirtualMachine,
+    base: usize,
+}
+
+impl RootScope<'_> {
+    /// keep `value` alive until the scope ends, returns it for chaining
+    pub fn root(&mut self, value: LuaValue) -> LuaValue {
+        self.vm.heap.roots.push(value);
+        value
+    }
+}
+
+impl Deref for RootScope<'_> {
+    type Target = VirtualMachine;
+
+    fn deref(&self) -> &VirtualMachine {
+        self.vm
+    }
+}
+
+impl DerefMut for RootScope<'_> {
+    fn deref_mut(&mut self) -> &mut VirtualMachine {
+        self.vm
+    }
+}
+
+impl Drop for RootScope<'_> {
+    fn drop(&mut self) {
+        self.vm.heap.roots.truncate(self.base);
+    }
+}
+
+impl VirtualMachine {
+    /// run `f` with a scope whose rooted values survive the collections that run meanwhile, for
+    /// native code that holds heap values no register or table refers to yet
+    ///
+    /// scopes nest, each one drops only the roots it added
+    pub fn with_roots<R>(&mut self, f: impl FnOnce(&mut RootScope<'_>) -> R) -> R {
+        let base = self.heap.roots.len();
+        let mut scope = RootScope { vm: self, base };
+        f(&mut scope)
+    }
+
+    /// allocate through `alloc`; when the heap limit is in the way, run a full collection and try
+    /// once more before giving up with OutOfMemory
+    ///
+    /// the collection only keeps what the VM's roots and the open `RootScope`s reach, values the
+    /// caller holds anywhere else must be rooted first
+    pub fn alloc<T>(
+        &mut self,
+        mut alloc: impl FnMut(&mut Heap) -> Option<Gc<T>>,
+    ) -> Result<Gc<T>, VMError> {
+        if let Some(obj) = alloc(&mut self.heap) {
+            return Ok(obj);
+        }
+        self.collect_garbage();
+        alloc(&mut self.heap).ok_or_else(|| self.error(ErrorKind::OutOfMemory))
     }
 }
@@ -0,0 +1,314 @@
+// Myula debug hooks
+// Changelog:
+// 2026-02-24: Initial version. One hook per VM, set from Rust (`set_hook`) or by a script (debug.sethook),
+//            is told about calls, returns, new source lines and every n-th instruction. Events are raised by
+//            `protected_step` before the instruction runs: a call when a new frame runs its first instruction,
+//            a return right before RETURN, while the frame is still there to inspect (`frame_info`,
+//            `frame_register`). Nothing is reported while the hook itself runs.
+// 2026-02-24: `frame_local` reads a local of a frame by its position, with the name the compiler gave
+//            its slot, and `traceback` renders the frames like the traceback of an uncaught error
+//            (debug.getlocal and debug.traceback).
+
+use std::ops::BitOr;
+use std::rc::Rc;
+
+use crate::backend::vm::VirtualMachine;
+use crate::backend::vm::error::{ErrorKind, VMError};
+use crate::common::instruction::{Instruction, OP_RETURN, OP_WIDE};
+use crate::common::object::LuaValue;
+use crate::common::opcode::OpCode;
+
+/// which events a hook is told about, combined with `|`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HookMask {
+    flags: u8,
+    // every how many instructions a Count event is raised, 0 for never
+    count: u32,
+}
+
+impl HookMask {
+    pub const NONE: HookMask = HookMask { flags: 0, count: 0 };
+    /// a Lua function starts running
+    pub const CALL: HookMask = HookMask { flags: 1, count: 0 };
+    /// a Lua function is about to return
+    pub const RETURN: HookMask = HookMask { flags: 2, count: 0 };
+    /// execution reaches a new source line, or jumps back within the same one (a loop)
+    pub const LINE: HookMask = HookMask { flags: 4, count: 0 };
+
+    /// a Count event every `n` instructions, n = 0 raises none
+    pub const fn count(n: u32) -> HookMask {
+        HookMask { flags: 0, count: n }
+    }
+
+    pub fn contains(self, other: HookMask) -> bool {
+        self.flags & other.flags == other.flags
+    }
+
+    pub fn instructions(self) -> u32 {
+        self.count
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.flags == 0 && self.count == 0
+    }
+}
+
+impl BitOr for HookMask {
+    type Output = HookMask;
+
+    fn bitor(self, rhs: HookMask) -> HookMask {
+        HookMask {
+            flags: self.flags | rhs.flags,
+            count: self.count.max(rhs.count),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    Call,
+    Return,
+    // the line about to run
+    Line(u32),
+    Count,
+}
+
+impl HookEvent {
+    /// the event name a Lua hook function receives
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::Call => "call",
+            HookEvent::Return => "return",
+            HookEvent::Line(_) => "line",
+            HookEvent::Count => "count",
+        }
+    }
+}
+
+/// a hook set from Rust, it may stop the script by returning an error
+pub type HookFn = Box<dyn FnMut(&mut VirtualMachine, HookEvent) -> Result<(), VMError>>;
+
+pub(crate) enum HookCallback {
+    // None while it runs, it is moved out for the call
+    Native(Option<HookFn>),
+    // a function given to debug.sethook, a GC root
+    Lua(LuaValue),
+}
+
+pub(crate) struct Hook {
+    pub(crate) mask: HookMask,
+    pub(crate) callback: HookCallback,
+    // instructions left until the next Count event
+    count_left: u32,
+    // frames pushed since the last step, each one raises a Call event before its first instruction
+    pending_calls: u32,
+    // set while the callback runs, events are dropped meanwhile
+    running: bool,
+}
+
+/// where a frame of the call stack is, for debuggers and hooks
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameInfo {
+    pub func_name: String,
+    pub chunk_name: Rc<str>,
+    // None for native functions and code without line information
+    pub line: Option<u32>,
+    pub pc: usize,
+    pub native: bool,
+}
+
+impl VirtualMachine {
+    /// call `callback` on the events in `mask`, replacing any hook set before (also one set with
+    /// debug.sethook); an error returned by the callback stops the script like a runtime error
+    ///
+    /// the callback can inspect the running frames with `frame_info` / `frame_register`,
+    /// level 0 is the frame about to run the next instruction
+    pub fn set_hook<F>(&mut self, mask: HookMask, callback: F)
+    where
+        F: FnMut(&mut VirtualMachine, HookEvent) -> Result<(), VMError> + 'static,
+    {
+        self.install_hook(mask, HookCallback::Native(Some(Box::new(callback))));
+    }
+
+    pub fn remove_hook(&mut self) {
+        self.hook = None;
+    }
+
+    /// the events the current hook listens to, HookMask::NONE without one
+    pub fn hook_mask(&self) -> HookMask {
+        self.hook.as_ref().map_or(HookMask::NONE, |hook| hook.mask)
+    }
+
+    // the function given to debug.sethook, None for a hook set from Rust
+    pub(crate) fn lua_hook(&self) -> Option<LuaValue> {
+        match self.hook.as_ref()?.callback {
+            HookCallback::Lua(func) => Some(func),
+            HookCallback::Native(_) => None,
+        }
+    }
+
+    pub(crate) fn install_hook(&mut self, mask: HookMask, callback: HookCallback) {
+        if mask.is_empty() {
+            self.hook = None;
+            return;
+        }
+        self.hook = Some(Box::new(Hook {
+            mask,
+            callback,
+            count_left: mask.count,
+            pending_calls: 0,
+            running: false,
+        }));
+    }
+
+    /// the function, name, position and kind of the frame `level` frames below the top
+    pub fn frame_info(&self, level: usize) -> Option<FrameInfo> {
+        let idx = self.call_stack.len().checked_sub(level + 1)?;
+        let frame = &self.call_stack[idx];
+        Some(FrameInfo {
+            func_name: frame.func_name.to_string(),
+            chunk_name: self.frame_chunk(frame),
+            line: self.frame_line(frame),
+            pc: frame.instr_pc,
+            native: frame.meta.is_none(),
+        })
+    }
+
+    /// register `reg` of the frame `level` frames below the top, None past the frame's registers
+    pub fn frame_register(&self, level: usize, reg: usize) -> Option<LuaValue> {
+        let idx = self.call_stack.len().checked_sub(level + 1)?;
+        let frame = &self.call_stack[idx];
+        if reg >= frame.reg_count {
+            return None;
+        }
+        self.value_stack
+            .values
+            .get(frame.reg_absolute(reg))
+            .copied()
+    }
+
+    /// name and value of the `n`-th local (1-based) of the frame `level` frames below the top;
+    /// every local of the function counts, also one whose block has not been entered yet
+    pub fn frame_local(&self, level: usize, n: usize) -> Option<(String, LuaValue)> {
+        let idx = self.call_stack.len().checked_sub(level + 1)?;
+        let meta = self.call_stack[idx].meta.as_ref()?;
+        let slot = n.checked_sub(1)?;
+        let name = meta.local_names.get(slot)?.clone();
+        // the local in slot n lives in register n
+        let value = self.frame_register(level, slot)?;
+        Some((name, value))
+    }
+
+    /// traceback of the frames from `level` frames below the top down to the entry frame, most recent
+    /// call first and folded like the traceback of an uncaught error
+    pub fn traceback(&self, level: usize) -> Vec<String> {
+        let mut trace = self.error(ErrorKind::InternalError(String::new()));
+        let keep = trace.stack_trace.len().saturating_sub(level);
+        trace.stack_trace.truncate(keep);
+        trace.stack_lines = trace.stack_lines[..keep].into();
+        trace.stack_chunks = trace.stack_chunks[..keep].into();
+        trace.traceback_lines(self.traceback_limit)
+    }
+
+    // a frame was pushed, its Call event is raised before its first instruction
+    pub(crate) fn queue_call_hook(&mut self) {
+        if let Some(hook) = self.hook.as_mut()
+            && hook.mask.contains(HookMask::CALL)
+            && !hook.running
+        {
+            hook.pending_calls += 1;
+        }
+    }
+
+    // raise the events due before `instr` runs at `pc` of the top frame
+    pub(crate) fn run_hooks(&mut self, instr: Instruction, pc: usize) -> Result<(), VMError> {
+        let Some(hook) = self.hook.as_mut() else {
+            return Ok(());
+        };
+        if hook.running {
+            return Ok(());
+        }
+        let mask = hook.mask;
+        let calls = std::mem::take(&mut hook.pending_calls);
+        let count_due = mask.count > 0 && {
+            hook.count_left = hook.count_left.saturating_sub(1);
+            hook.count_left == 0
+        };
+        if count_due {
+            hook.count_left = mask.count;
+        }
+
+        let frame = self.call_stack.last_mut().unwrap();
+        let meta = frame.meta.clone().unwrap();
+        // instr_pc still holds the previous instruction of this frame
+        let last_pc = frame.instr_pc;
+        frame.instr_pc = pc;
+
+        for _ in 0..calls {
+            self.call_hook(HookEvent::Call)?;
+        }
+        if mask.contains(HookMask::LINE) {
+            let line = meta.line_info.get(pc).copied().unwrap_or(0);
+            let last_line = meta.line_info.get(last_pc).copied().unwrap_or(0);
+            if line > 0 && (pc <= last_pc || line != last_line) {
+                self.call_hook(HookEvent::Line(line))?;
+            }
+        }
+        if count_due {
+            self.call_hook(HookEvent::Count)?;
+        }
+        if mask.contains(HookMask::RETURN)
+            && (instr.op() == OP_RETURN
+                || instr.op() == OP_WIDE && matches!(meta.bytecode[pc], OpCode::Return { .. }))
+        {
+            self.call_hook(HookEvent::Return)?;
+        }
+        Ok(())
+    }
+
+    // the hook may run between a CALL and the GETRESULTs reading its results, which it leaves alone
+    fn call_hook(&mut self, event: HookEvent) -> Result<(), VMError> {
+        let results = std::mem::take(&mut self.return_buffer);
+        let result = self.run_hook_callback(event);
+        self.return_buffer = results;
+        result
+    }
+
+    fn run_hook_callback(&mut self, event: HookEvent) -> Result<(), VMError> {
+        let Some(hook) = self.hook.as_mut() else {
+            return Ok(());
+        };
+        hook.running = true;
+        let result = match &mut hook.callback {
+            HookCallback::Native(slot) => {
+                let mut callback = slot.take();
+                let result = callback.as_mut().map_or(Ok(()), |f| f(self, event));
+                // put it back unless the hook was replaced or removed meanwhile
+                if let Some(hook) = self.hook.as_mut()
+                    && let HookCallback::Native(slot @ None) = &mut hook.callback
+                {
+                    *slot = callback;
+                }
+                result
+            }
+            HookCallback::Lua(func) => {
+                let func = *func;
+                self.call_lua_hook(func, event)
+            }
+        };
+        if let Some(hook) = self.hook.as_mut() {
+            hook.running = false;
+        }
+        result
+    }
+
+    // a hook function set by debug.sethook is called with the event name and, for lines, the line
+    fn call_lua_hook(&mut self, func: LuaValue, event: HookEvent) -> Result<(), VMError> {
+        let name = self.alloc(|heap| heap.alloc_str(event.name()))?;
+        let mut args = vec![LuaValue::String(name)];
+        if let HookEvent::Line(line) = event {
+            args.push(LuaValue::Integer(line as i64));
+        }
+        self.call_value(func, args).map(|_| ())
+    }
+}
@@ -0,0 +1,740 @@
+// Baseline JIT (cargo feature `jit`): a Lua function called JIT_THRESHOLD times is compiled to native
+// code with cranelift, specialized for the types of the arguments it is being called with; a function
+// keeps at most MAX_SPECIALIZATIONS of them, the calls with other argument types stay interpreted.
+//
+// Only functions that compute on numbers and booleans are compiled: no globals, upvalues, tables,
+// strings or calls. `check` runs the types of the arguments through the bytecode, so every register
+// has one known type (nil, boolean, integer or float) at every pc; a register that can hold two
+// types at one point, or an instruction outside the subset, keeps the whole function interpreted.
+// Each register becomes one cranelift variable per type it takes and cranelift allocates machine
+// registers for them.
+//
+// The compiled code never touches the VM, it only reads the arguments and returns one value. When it
+// cannot go on (a division or modulo by zero) it bails out and the interpreter runs the call again
+// from the start, which raises the error. It is not used while a budget, a hook or the profiler is
+// set, or below LogLevel::Release: it neither counts instructions nor stops between them.
+
+use crate::backend::vm::{LoadedFunction, LogLevel, VirtualMachine};
+use crate::common::instruction::*;
+use crate::common::object::{Constant, LuaValue};
+use crate::common::opcode::UnaryOpType;
+use cranelift_codegen::Context;
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{AbiParam, Block, InstBuilder, MemFlags, Type, Value, types};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{Module, default_libcall_names};
+use std::cell::{Cell, RefCell};
+
+// calls of a function before it is compiled
+pub const JIT_THRESHOLD: u32 = 64;
+// argument signatures a function is compiled (or failed to compile) for
+pub const MAX_SPECIALIZATIONS: usize = 4;
+
+// what compiled code returns, the value itself is written to its `out` pointer
+const RET_BAIL: u32 = 0;
+const RET_NIL: u32 = 1;
+const RET_BOOL: u32 = 2;
+const RET_INT: u32 = 3;
+const RET_FLOAT: u32 = 4;
+
+// (arguments as raw bits, out) -> one of the RET_ tags
+type JitFn = unsafe extern "C" fn(*const u64, *mut u64) -> u32;
+
+/// the static type of a register at one pc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JitType {
+    Nil,
+    Bool,
+    Int,
+    Float,
+}
+
+impl JitType {
+    fn of(value: &LuaValue) -> Option<Self> {
+        match value {
+            LuaValue::Nil => Some(JitType::Nil),
+            LuaValue::Boolean(_) => Some(JitType::Bool),
+            LuaValue::Integer(_) => Some(JitType::Int),
+            LuaValue::Number(_) => Some(JitType::Float),
+            _ => None,
+        }
+    }
+
+    fn is_number(self) -> bool {
+        matches!(self, JitType::Int | JitType::Float)
+    }
+
+    // the truthiness of a value of this type when it does not depend on the value
+    fn truthy(self) -> Option<bool> {
+        match self {
+            JitType::Nil => Some(false),
+            JitType::Bool => None,
+            JitType::Int | JitType::Float => Some(true),
+        }
+    }
+
+    // the cranelift variable slot of a register of this type, nil has none
+    fn slot(self) -> Option<(u32, Type)> {
+        match self {
+            JitType::Nil => None,
+            JitType::Bool => Some((0, types::I8)),
+            JitType::Int => Some((1, types::I64)),
+            JitType::Float => Some((2, types::F64)),
+        }
+    }
+}
+
+/// the JIT state of one function, kept in its `LoadedFunction`
+#[derive(Default)]
+pub struct JitSlot {
+    calls: Cell<u32>,
+    // argument types -> the code compiled for them, None when they could not be compiled
+    compiled: RefCell<Vec<(Vec<JitType>, Option<JitFn>)>>,
+}
+
+/// the code generator of a VM, created on the first compilation; the code it made is freed with
+/// the process, so functions compiled before a `reset` stay callable
+pub struct Jit {
+    module: JITModule,
+    ctx: Context,
+    builder_ctx: FunctionBuilderContext,
+}
+
+impl Jit {
+    /// None when cranelift does not support the host
+    pub fn new() -> Option<Self> {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").ok()?;
+        let isa = cranelift_native::builder()
+            .ok()?
+            .finish(settings::Flags::new(flags))
+            .ok()?;
+        let module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+        let ctx = module.make_context();
+        Some(Self {
+            module,
+            ctx,
+            builder_ctx: FunctionBuilderContext::new(),
+        })
+    }
+
+    /// compile `func` for arguments of the types `args`, Err names what keeps it interpreted
+    pub fn compile(&mut self, func: &LoadedFunction, args: &[JitType]) -> Result<JitFn, String> {
+        let states = check(func, args)?;
+
+        self.module.clear_context(&mut self.ctx);
+        let ptr = self.module.target_config().pointer_type();
+        let signature = &mut self.ctx.func.signature;
+        signature.params.push(AbiParam::new(ptr));
+        signature.params.push(AbiParam::new(ptr));
+        signature.returns.push(AbiParam::new(types::I32));
+
+        let builder = FunctionBuilder::new(&mut self.ctx.func, &mut self.builder_ctx);
+        Translator::new(builder, func, &states).translate(args);
+
+        let id = self
+            .module
+            .declare_anonymous_function(&self.ctx.func.signature)
+            .map_err(|err| err.to_string())?;
+        self.module
+            .define_function(id, &mut self.ctx)
+            .map_err(|err| err.to_string())?;
+        self.module.clear_context(&mut self.ctx);
+        self.module
+            .finalize_definitions()
+            .map_err(|err| err.to_string())?;
+        let code = self.module.get_finalized_function(id);
+        // SAFETY: the function was built with the (ptr, ptr) -> i32 signature of JitFn
+        Ok(unsafe { std::mem::transmute::<*const u8, JitFn>(code) })
+    }
+}
+
+// register types at the start of every pc, None for the pcs no path reaches; a register that can
+// hold two types there is None and must not be read
+type TypeStates = Vec<Option<Vec<Option<JitType>>>>;
+
+/// run the argument types through the bytecode of `func`, Err when it leaves the compiled subset
+pub fn check(func: &LoadedFunction, args: &[JitType]) -> Result<TypeStates, String> {
+    let code = &func.code;
+    if code.is_empty() {
+        return Err("no code".into());
+    }
+    let regs = func.max_stack_size.max(args.len());
+    let mut entry = vec![Some(JitType::Nil); regs];
+    for (reg, &ty) in args.iter().enumerate() {
+        entry[reg] = Some(ty);
+    }
+
+    let mut states: TypeStates = vec![None; code.len()];
+    let mut work = vec![0usize];
+    states[0] = Some(entry);
+    while let Some(pc) = work.pop() {
+        let Some(instr) = code.get(pc).copied() else {
+            return Err(format!("pc {:04} runs off the end of the code", pc));
+        };
+        let mut state = states[pc].clone().unwrap();
+        for target in step(func, instr, pc, &mut state)? {
+            if target >= code.len() {
+                return Err(format!("pc {:04} jumps out of the code", pc));
+            }
+            let changed = match &mut states[target] {
+                Some(known) => {
+                    let mut changed = false;
+                    for (known, &ty) in known.iter_mut().zip(&state) {
+                        if known.is_some() && *known != ty {
+                            *known = None;
+                            changed = true;
+                        }
+                    }
+                    changed
+                }
+                slot @ None => {
+                    *slot = Some(state.clone());
+                    true
+                }
+            };
+            if changed {
+                work.push(target);
+            }
+        }
+    }
+    Ok(states)
+}
+
+// the effect of one instruction on the register types and the pcs that can follow it
+fn step(
+    func: &LoadedFunction,
+    instr: Instruction,
+    pc: usize,
+    regs: &mut [Option<JitType>],
+) -> Result<Vec<usize>, String> {
+    let (a, b, c) = (instr.a() as usize, instr.b() as usize, instr.c() as usize);
+    let unsupported = || Err(format!("{:?} at pc {:04}", instr, pc));
+    let get = |reg: usize| {
+        regs.get(reg)
+            .copied()
+            .flatten()
+            .ok_or_else(|| format!("R{} has no single type at pc {:04}", reg, pc))
+    };
+    let ty = match instr.op() {
+        OP_MOVE => get(b)?,
+        OP_LOADNIL => JitType::Nil,
+        OP_LOADBOOL => JitType::Bool,
+        OP_LOADK => match func.constants.get(instr.bx() as usize) {
+            Some(Constant::Nil) => JitType::Nil,
+            Some(Constant::Integer(_)) => JitType::Int,
+            Some(Constant::Number(_)) => JitType::Float,
+            _ => return unsupported(),
+        },
+        OP_ADD | OP_SUB | OP_MUL | OP_DIV | OP_MOD | OP_ADDK | OP_SUBK => {
+            let left = get(b)?;
+            let right = match instr.op() {
+                OP_ADDK | OP_SUBK => match func.constants.get(c) {
+                    Some(Constant::Integer(_)) => JitType::Int,
+                    Some(Constant::Number(_)) => JitType::Float,
+                    _ => return unsupported(),
+                },
+                _ => get(c)?,
+            };
+            match (instr.op(), left, right) {
+                (_, l, r) if !l.is_number() || !r.is_number() => return unsupported(),
+                (OP_DIV, _, _) => JitType::Float,
+                (_, JitType::Int, JitType::Int) => JitType::Int,
+                // float modulo rounds like fmod, which cranelift has no instruction for
+                (OP_MOD, _, _) => return unsupported(),
+                _ => JitType::Float,
+            }
+        }
+        OP_UNOP => {
+            let src = get(b)?;
+            match instr.unary_op() {
+                UnaryOpType::Neg if src.is_number() => src,
+                UnaryOpType::Not => JitType::Bool,
+                _ => return unsupported(),
+            }
+        }
+        OP_AND | OP_OR => {
+            let (left, right) = (get(b)?, get(c)?);
+            let and = instr.op() == OP_AND;
+            match left.truthy() {
+                Some(truthy) if truthy == and => right,
+                Some(_) => left,
+                None if right == JitType::Bool => JitType::Bool,
+                None => return unsupported(),
+            }
+        }
+        OP_EQ | OP_NE => {
+            let (left, right) = (get(b)?, get(c)?);
+            if left != right && left.is_number() && right.is_number() {
+                return unsupported();
+            }
+            JitType::Bool
+        }
+        OP_LT | OP_GT | OP_LE | OP_GE => {
+            let (left, right) = (get(b)?, get(c)?);
+            if left != right || !left.is_number() {
+                return unsupported();
+            }
+            JitType::Bool
+        }
+        OP_TEST => {
+            get(a)?;
+            return Ok(vec![pc + 1, pc + 2]);
+        }
+        OP_JUMP => return Ok(vec![jump_target(pc, instr.sj())?]),
+        OP_JMPFALSE => {
+            get(a)?;
+            return Ok(vec![pc + 1, jump_target(pc, instr.sbx())?]);
+        }
+        OP_RETURN => {
+            match b {
+                0 => {}
+                1 => {
+                    get(a)?;
+                }
+                _ => return unsupported(),
+            }
+            return Ok(vec![]);
+        }
+        _ => return unsupported(),
+    };
+    match regs.get_mut(a) {
+        Some(reg) => *reg = Some(ty),
+        None => return unsupported(),
+    }
+    Ok(vec![pc + 1])
+}
+
+fn jump_target(pc: usize, offset: i32) -> Result<usize, String> {
+    usize::try_from(pc as i64 + offset as i64).map_err(|_| format!("pc {:04} jumps before 0", pc))
+}
+
+// emits the cranelift IR of a function `check` accepted
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    func: &'a LoadedFunction,
+    states: &'a TypeStates,
+    blocks: Vec<Option<Block>>,
+    entry: Block,
+    bail: Block,
+    // (argument array, result slot)
+    params: (Value, Value),
+}
+
+impl<'a> Translator<'a> {
+    fn new(
+        mut builder: FunctionBuilder<'a>,
+        func: &'a LoadedFunction,
+        states: &'a TypeStates,
+    ) -> Self {
+        let blocks = states
+            .iter()
+            .map(|state| state.as_ref().map(|_| builder.create_block()))
+            .collect();
+        let bail = builder.create_block();
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        let params = builder.block_params(entry);
+        let params = (params[0], params[1]);
+        Self {
+            builder,
+            func,
+            states,
+            blocks,
+            entry,
+            bail,
+            params,
+        }
+    }
+
+    fn translate(mut self, args: &[JitType]) {
+        self.builder.switch_to_block(self.entry);
+        let arg_ptr = self.params.0;
+        let regs = self.states[0].as_ref().unwrap().len();
+        for reg in 0..regs {
+            for ty in [JitType::Bool, JitType::Int, JitType::Float] {
+                let (var, ty) = Self::var(reg, ty).unwrap();
+                self.builder.declare_var(var, ty);
+            }
+        }
+        for (reg, &ty) in args.iter().enumerate() {
+            let Some((var, cl_ty)) = Self::var(reg, ty) else {
+                continue;
+            };
+            let offset = (reg * 8) as i32;
+            let value = if ty == JitType::Bool {
+                let bits =
+                    self.builder
+                        .ins()
+                        .load(types::I64, MemFlags::trusted(), arg_ptr, offset);
+                self.builder.ins().icmp_imm(IntCC::NotEqual, bits, 0)
+            } else {
+                self.builder
+                    .ins()
+                    .load(cl_ty, MemFlags::trusted(), arg_ptr, offset)
+            };
+            self.builder.def_var(var, value);
+        }
+        let first = self.blocks[0].unwrap();
+        self.builder.ins().jump(first, &[]);
+
+        for pc in 0..self.blocks.len() {
+            if let Some(block) = self.blocks[pc] {
+                self.builder.switch_to_block(block);
+                self.instruction(pc);
+            }
+        }
+
+        self.builder.switch_to_block(self.bail);
+        let tag = self.builder.ins().iconst(types::I32, RET_BAIL as i64);
+        self.builder.ins().return_(&[tag]);
+
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+    }
+
+    fn var(reg: usize, ty: JitType) -> Option<(Variable, Type)> {
+        let (slot, cl_ty) = ty.slot()?;
+        Some((Variable::from_u32(reg as u32 * 3 + slot), cl_ty))
+    }
+
+    // the type of a register `check` saw being read at pc
+    fn ty(&self, pc: usize, reg: usize) -> JitType {
+        self.states[pc].as_ref().unwrap()[reg].unwrap()
+    }
+
+    fn read(&mut self, pc: usize, reg: usize) -> Option<Value> {
+        let (var, _) = Self::var(reg, self.ty(pc, reg))?;
+        Some(self.builder.use_var(var))
+    }
+
+    fn write(&mut self, reg: usize, ty: JitType, value: Option<Value>) {
+        if let (Some((var, _)), Some(value)) = (Self::var(reg, ty), value) {
+            self.builder.def_var(var, value);
+        }
+    }
+
+    // a number as a float, integers are converted like `LuaValue::as_float`
+    fn as_float(&mut self, ty: JitType, value: Value) -> Value {
+        match ty {
+            JitType::Int => self.builder.ins().fcvt_from_sint(types::F64, value),
+            _ => value,
+        }
+    }
+
+    // the value of a number constant and its type
+    fn constant(&mut self, idx: usize) -> Option<(JitType, Value)> {
+        match self.func.constants.get(idx)? {
+            Constant::Integer(i) => Some((JitType::Int, self.builder.ins().iconst(types::I64, *i))),
+            Constant::Number(n) => Some((JitType::Float, self.builder.ins().f64const(*n))),
+            _ => None,
+        }
+    }
+
+    // an I8 that is 1 when the register is truthy
+    fn truthy(&mut self, pc: usize, reg: usize) -> Value {
+        match self.ty(pc, reg).truthy() {
+            Some(truthy) => self.builder.ins().iconst(types::I8, truthy as i64),
+            None => self.read(pc, reg).unwrap(),
+        }
+    }
+
+    fn goto(&mut self, target: usize) {
+        let block = self.blocks[target].unwrap();
+        self.builder.ins().jump(block, &[]);
+    }
+
+    fn branch(&mut self, cond: Value, then_pc: usize, else_pc: usize) {
+        let (then_block, else_block) =
+            (self.blocks[then_pc].unwrap(), self.blocks[else_pc].unwrap());
+        self.builder
+            .ins()
+            .brif(cond, then_block, &[], else_block, &[]);
+    }
+
+    fn ret(&mut self, tag: u32, value: Option<Value>) {
+        if let Some(value) = value {
+            self.builder
+                .ins()
+                .store(MemFlags::trusted(), value, self.params.1, 0);
+        }
+        let tag = self.builder.ins().iconst(types::I32, tag as i64);
+        self.builder.ins().return_(&[tag]);
+    }
+
+    // leave to the interpreter when `cond` is set
+    fn bail_if(&mut self, cond: Value) {
+        let go_on = self.builder.create_block();
+        self.builder.ins().brif(cond, self.bail, &[], go_on, &[]);
+        self.builder.switch_to_block(go_on);
+    }
+
+    fn instruction(&mut self, pc: usize) {
+        let instr = self.func.code[pc];
+        let (a, b, c) = (instr.a() as usize, instr.b() as usize, instr.c() as usize);
+        match instr.op() {
+            OP_MOVE => {
+                let value = self.read(pc, b);
+                self.write(a, self.ty(pc, b), value);
+            }
+            OP_LOADNIL => {}
+            OP_LOADBOOL => {
+                let value = self.builder.ins().iconst(types::I8, (b != 0) as i64);
+                self.write(a, JitType::Bool, Some(value));
+            }
+            OP_LOADK => {
+                if let Some((ty, value)) = self.constant(instr.bx() as usize) {
+                    self.write(a, ty, Some(value));
+                }
+            }
+            OP_ADD | OP_SUB | OP_MUL | OP_DIV | OP_MOD | OP_ADDK | OP_SUBK => {
+                let (lty, left) = (self.ty(pc, b), self.read(pc, b).unwrap());
+                let (rty, right) = match instr.op() {
+                    OP_ADDK | OP_SUBK => self.constant(c).unwrap(),
+                    _ => (self.ty(pc, c), self.read(pc, c).unwrap()),
+                };
+                self.arith(instr.op(), a, (lty, left), (rty, right));
+            }
+            OP_UNOP => {
+                let ty = self.ty(pc, b);
+                match instr.unary_op() {
+                    UnaryOpType::Neg => {
+                        let src = self.read(pc, b).unwrap();
+                        let value = match ty {
+                            JitType::Int => self.builder.ins().ineg(src),
+                            _ => self.builder.ins().fneg(src),
+                        };
+                        self.write(a, ty, Some(value));
+                    }
+                    _ => {
+                        let truthy = self.truthy(pc, b);
+                        let value = self.builder.ins().bxor_imm(truthy, 1);
+                        self.write(a, JitType::Bool, Some(value));
+                    }
+                }
+            }
+            OP_AND | OP_OR => {
+                let and = instr.op() == OP_AND;
+                match self.ty(pc, b).truthy() {
+                    Some(truthy) => {
+                        let src = if truthy == and { c } else { b };
+                        let value = self.read(pc, src);
+                        self.write(a, self.ty(pc, src), value);
+                    }
+                    None => {
+                        let (left, right) = (self.read(pc, b).unwrap(), self.read(pc, c).unwrap());
+                        let value = if and {
+                            self.builder.ins().select(left, right, left)
+                        } else {
+                            self.builder.ins().select(left, left, right)
+                        };
+                        self.write(a, JitType::Bool, Some(value));
+                    }
+                }
+            }
+            OP_EQ | OP_NE | OP_LT | OP_GT | OP_LE | OP_GE => {
+                let value = self.compare(pc, instr.op(), b, c);
+                self.write(a, JitType::Bool, Some(value));
+            }
+            OP_TEST => {
+                let truthy = self.truthy(pc, a);
+                self.branch(truthy, pc + 1, pc + 2);
+                return;
+            }
+            OP_JUMP => {
+                self.goto((pc as i64 + instr.sj() as i64) as usize);
+                return;
+            }
+            OP_JMPFALSE => {
+                let target = (pc as i64 + instr.sbx() as i64) as usize;
+                match self.ty(pc, a).truthy() {
+                    Some(true) => self.goto(pc + 1),
+                    Some(false) => self.goto(target),
+                    None => {
+                        let truthy = self.read(pc, a).unwrap();
+                        self.branch(truthy, pc + 1, target);
+                    }
+                }
+                return;
+            }
+            OP_RETURN => {
+                let ty = if b == 0 { JitType::Nil } else { self.ty(pc, a) };
+                let value = if b == 0 { None } else { self.read(pc, a) };
+                let (tag, value) = match ty {
+                    JitType::Nil => (RET_NIL, None),
+                    JitType::Bool => {
+                        let wide = self.builder.ins().uextend(types::I64, value.unwrap());
+                        (RET_BOOL, Some(wide))
+                    }
+                    JitType::Int => (RET_INT, value),
+                    JitType::Float => (RET_FLOAT, value),
+                };
+                self.ret(tag, value);
+                return;
+            }
+            _ => unreachable!("check accepted {:?}", instr),
+        }
+        self.goto(pc + 1);
+    }
+
+    fn arith(&mut self, op: u8, dest: usize, left: (JitType, Value), right: (JitType, Value)) {
+        if op != OP_DIV && left.0 == JitType::Int && right.0 == JitType::Int {
+            let (l, r) = (left.1, right.1);
+            let value = match op {
+                OP_ADD | OP_ADDK => self.builder.ins().iadd(l, r),
+                OP_SUB | OP_SUBK => self.builder.ins().isub(l, r),
+                OP_MUL => self.builder.ins().imul(l, r),
+                _ => {
+                    let zero = self.builder.ins().icmp_imm(IntCC::Equal, r, 0);
+                    self.bail_if(zero);
+                    // x % -1 is 0 like x % 1, srem would trap on i64::MIN % -1
+                    let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, r, -1);
+                    let one = self.builder.ins().iconst(types::I64, 1);
+                    let divisor = self.builder.ins().select(minus_one, one, r);
+                    let rem = self.builder.ins().srem(l, divisor);
+                    // floored: a nonzero remainder takes the sign of the divisor
+                    let nonzero = self.builder.ins().icmp_imm(IntCC::NotEqual, rem, 0);
+                    let signs = self.builder.ins().bxor(rem, r);
+                    let differ = self.builder.ins().icmp_imm(IntCC::SignedLessThan, signs, 0);
+                    let fix = self.builder.ins().band(nonzero, differ);
+                    let fixed = self.builder.ins().iadd(rem, r);
+                    self.builder.ins().select(fix, fixed, rem)
+                }
+            };
+            self.write(dest, JitType::Int, Some(value));
+            return;
+        }
+        let l = self.as_float(left.0, left.1);
+        let r = self.as_float(right.0, right.1);
+        let value = match op {
+            OP_ADD | OP_ADDK => self.builder.ins().fadd(l, r),
+            OP_SUB | OP_SUBK => self.builder.ins().fsub(l, r),
+            OP_MUL => self.builder.ins().fmul(l, r),
+            _ => {
+                let zero = self.builder.ins().f64const(0.0);
+                let is_zero = self.builder.ins().fcmp(FloatCC::Equal, r, zero);
+                self.bail_if(is_zero);
+                self.builder.ins().fdiv(l, r)
+            }
+        };
+        self.write(dest, JitType::Float, Some(value));
+    }
+
+    fn compare(&mut self, pc: usize, op: u8, left: usize, right: usize) -> Value {
+        let (lty, rty) = (self.ty(pc, left), self.ty(pc, right));
+        let equal = matches!(op, OP_EQ | OP_NE);
+        if equal && (lty != rty || lty == JitType::Nil) {
+            // values of two types are never equal, nil always equals nil
+            let same = (lty == rty) == (op == OP_EQ);
+            return self.builder.ins().iconst(types::I8, same as i64);
+        }
+        let (l, r) = (self.read(pc, left).unwrap(), self.read(pc, right).unwrap());
+        if lty == JitType::Float {
+            let cc = match op {
+                OP_EQ => FloatCC::Equal,
+                OP_NE => FloatCC::NotEqual,
+                OP_LT => FloatCC::LessThan,
+                OP_GT => FloatCC::GreaterThan,
+                OP_LE => FloatCC::LessThanOrEqual,
+                _ => FloatCC::GreaterThanOrEqual,
+            };
+            self.builder.ins().fcmp(cc, l, r)
+        } else {
+            let cc = match op {
+                OP_EQ => IntCC::Equal,
+                OP_NE => IntCC::NotEqual,
+                OP_LT => IntCC::SignedLessThan,
+                OP_GT => IntCC::SignedGreaterThan,
+                OP_LE => IntCC::SignedLessThanOrEqual,
+                _ => IntCC::SignedGreaterThanOrEqual,
+            };
+            self.builder.ins().icmp(cc, l, r)
+        }
+    }
+}
+
+impl VirtualMachine {
+    // the JIT only runs where nothing needs to see single instructions
+    fn jit_allowed(&self) -> bool {
+        self.budget_check_at == u64::MAX
+            && self.hook.is_none()
+            && self.profiler.is_none()
+            && self.log_level == LogLevel::Release
+    }
+
+    /// run the call of `func` with the `argc` arguments pushed above the current frame as native
+    /// code, false when it has to go through the interpreter; on success the arguments are popped
+    /// and the result is in R[func_reg], as after the RETURN of an interpreted call
+    pub(crate) fn jit_call(&mut self, func: &LoadedFunction, func_reg: u16, argc: usize) -> bool {
+        let slot = &func.jit;
+        let calls = slot.calls.get().saturating_add(1);
+        slot.calls.set(calls);
+        if calls < JIT_THRESHOLD || !self.jit_allowed() {
+            return false;
+        }
+
+        let args_start = self.get_actual_stack_top();
+        let Some(args) = self.value_stack.values.get(args_start..args_start + argc) else {
+            return false;
+        };
+        let Some(types) = args.iter().map(JitType::of).collect::<Option<Vec<_>>>() else {
+            return false;
+        };
+
+        let known = slot
+            .compiled
+            .borrow()
+            .iter()
+            .find(|(sig, _)| *sig == types)
+            .map(|&(_, code)| code);
+        let code = match known {
+            Some(code) => code,
+            None if slot.compiled.borrow().len() < MAX_SPECIALIZATIONS => {
+                let jit = match &mut self.jit {
+                    Some(jit) => jit,
+                    None => match Jit::new() {
+                        Some(jit) => self.jit.insert(Box::new(jit)),
+                        None => return false,
+                    },
+                };
+                let code = jit.compile(func, &types).ok();
+                if code.is_some() {
+                    self.stats.jit_compiled += 1;
+                }
+                slot.compiled.borrow_mut().push((types, code));
+                code
+            }
+            None => None,
+        };
+        let Some(code) = code else {
+            return false;
+        };
+
+        let bits: Vec<u64> = self.value_stack.values[args_start..args_start + argc]
+            .iter()
+            .map(|arg| match *arg {
+                LuaValue::Boolean(b) => b as u64,
+                LuaValue::Integer(i) => i as u64,
+                LuaValue::Number(n) => n.to_bits(),
+                _ => 0,
+            })
+            .collect();
+        let mut out = 0u64;
+        // SAFETY: the code was compiled for arguments of exactly these types and reads `argc` of them
+        let tag = unsafe { code(bits.as_ptr(), &mut out) };
+        let result = match tag {
+            RET_NIL => LuaValue::Nil,
+            RET_BOOL => LuaValue::Boolean(out != 0),
+            RET_INT => LuaValue::Integer(out as i64),
+            RET_FLOAT => LuaValue::Number(f64::from_bits(out)),
+            _ => return false,
+        };
+
+        self.value_stack.restore(args_start);
+        self.set_reg(func_reg as usize, result);
+        self.stats.jit_calls += 1;
+        true
+    }
+}
@@ -22,27 +22,165 @@
 //            frame-level reclamation strategy, resolving critical "Nil" value propagation bugs during cross-instruction execution.
 // 2026-02-19: Add more debug messages for instruction execution and GC events, providing better visibility into the VM's internal workings during development and testing.
 // 2026-02-20: Added upvalue capture support
-
+// 2026-02-22: Native functions can now return values by pushing them onto the value stack,
+//            and can call back into Lua through `call_value`, which runs a nested dispatch loop
+//            until the callee frame returns; results of such frames are collected in `return_buffer`.
+// 2026-02-22: In Debug/Trace mode the emitter's operand naming side-table is kept in FuncMetadata
+//            and used to name the offending operand in table access and call errors.
+// 2026-02-23: Added `register_library` for native library tables, the `string` library is the first one;
+//            string values index into it, so `s:upper()` works.
+// 2026-02-23: Stack tracebacks fold runs of recursive frames and are capped in length,
+//            `full_traceback` restores the complete dump.
+// 2026-02-23: Split the dispatch loop out of `run` into `execute`, which returns the runtime error
+//            to embedders; top-level return values are kept in `return_buffer`.
+// 2026-02-23: Added the `pattern` module (Lua pattern matching) backing string.find/match/gmatch/gsub;
+//            tables with a `__call` metamethod can be called.
+// 2026-02-23: Added the `math` library, math.random draws from the per-VM `rng`.
+// 2026-02-23: String builtins build their results in the shared `scratch` buffer.
+// 2026-02-24: Added the `os` and `io` libraries; io.read / io.lines read from the VM's `input`
+//            (stdin unless the embedder swaps it), os.clock measures from `started`.
+// 2026-02-24: Protected calls: `protected_call` unwinds a failed call back to its recovery point,
+//            pcall / xpcall catch errors raised by `error` (ErrorKind::LuaError) or by the VM itself.
+// 2026-02-24: `LuaTable` is the single table type; opcodes, GC and builtins go through `LuaTable::get/set`,
+//            storing nil removes the key and `#` follows the border rule instead of counting entries.
+// 2026-02-24: Integer subtype (`LuaValue::Integer`): +, -, *, % and unary minus keep two integers integral
+//            (wrapping on overflow), / always yields a float; table keys normalize integral floats to integers.
+// 2026-02-24: `reset` returns a VM to its freshly created state so it can run another chunk: stacks, compiled
+//            functions and globals are dropped and a full collection frees the heap; the standard library
+//            can be kept, `init` then skips reloading it.
+// 2026-02-24: `set_random_seed` makes math.random reproducible, the seed is kept in `rng_seed`
+//            and `reset` restarts the generator from it.
+// 2026-02-24: `init` is split into `compile` (scan + emit, no VM needed) and `link`; `init_precompiled`
+//            loads functions read back from a .myb image without the IR.
+// 2026-02-24: `load_chunk` adds another chunk's functions to a running VM, used by the `Myula` facade.
+// 2026-02-24: `register_function` binds stateful Rust closures (`LuaValue::NativeClosure`, GC managed).
+// 2026-02-24: A failed `execute` unwinds its frames (closing their upvalues) and cuts the value stack back,
+//            stale values used to leak into the arguments of the next chunk's native calls.
+// 2026-02-24: FuncMetadata carries the emitter's line table; runtime errors know the source line of the
+//            failing instruction and of every Lua frame in the traceback, `chunk_name` names the file.
+// 2026-02-24: Generational GC (`set_gc_mode`): minor collections mark only young objects, starting from
+//            the roots and the heap's remembered set, and promote the survivors; a full collection still
+//            runs whenever the heap outgrows its threshold. GcMode::Full keeps the plain mark-and-sweep.
+// 2026-02-24: Added the `collectgarbage` builtin; `collect_garbage` and `gc_step` run a collection on demand.
+//            In stress mode (`heap.stress`) a step runs at the first safe point after every allocation,
+//            collections only happen between instructions, so that is as close to "before every
+//            allocation" as the VM gets.
+// 2026-02-24: Tables have an array part, NEWTABLE preallocates both parts from the constructor's size hints.
+// 2026-02-24: Function metadata is shared through `Rc` and every frame keeps the metadata of its function,
+//            so fetching an instruction or a constant no longer clones the function name and looks it up by name.
+// 2026-02-24: The dispatch loop runs the packed 32-bit `Instruction`s of `FuncMetadata::code`, the `OpCode`
+//            enum in `bytecode` is kept for the disassembler, the serializer and bytecode tools.
+// 2026-02-24: `LuaValue` is `Copy` (16 bytes), registers are copied instead of cloned. Compiled constant pools
+//            hold `Constant`s, `finalize_constants` interns them into `FuncMetadata::const_values`.
+// 2026-02-24: `stats` counts executed instructions and full / minor collections (myulac --time, benches).
+// 2026-02-24: Coroutines (see `coroutine`): the running thread, the parked stacks of its resumers and
+//            a pending yield are VM state; all of them are GC roots.
+//            A full sweep empties the remembered set before freeing old objects, the nursery sweep used to
+//            clear flags of remembered objects that were already freed.
+// 2026-02-24: `require` and the `package` table (see `package`); `prefix_functions` renames a separately
+//            compiled chunk's functions apart, shared with the `Myula` facade. Functions of a module keep
+//            its path in `FuncMetadata::chunk_name`, so errors and tracebacks name the right file per frame.
+// 2026-02-24: `dump_internal_state` lists functions through the `Disassembler`.
+// 2026-02-24: Optional `profiler` (see `profiler`): when set, `protected_step` goes through `profiled_step`
+//            and `push_frame` counts calls; the dispatch loop only checks it for None otherwise.
+// 2026-02-24: Execution budgets for embedders: `set_fuel` (an instruction quota) and `set_timeout` (wall clock)
+//            stop a script with ErrorKind::BudgetExceeded. The dispatch loop compares the instruction count
+//            with `budget_check_at`; the clock is only read every BUDGET_CLOCK_INTERVAL instructions.
+// 2026-02-24: `with_config` takes a `VmConfig` (see `config`): the io / os / package groups of the standard
+//            library can be left out, the heap limit is per VM and SETGLOBAL checks the global whitelist.
+// 2026-02-24: MAX_CALL_STACK, HARD_MEMORY_LIMIT and VM_THRESHOLD are only the defaults of `VmConfig` now.
+// 2026-02-24: Debug hooks (see `hook`): `push_frame` queues call events, `protected_step` raises the due
+//            events before each instruction while a hook is set; a Lua hook function is a GC root.
+// 2026-02-24: Heap objects are `Gc<T>` handles; `mark_objects` only hands the roots to the heap's collector.
+// 2026-02-24: `run_finalizers` calls the __gc of the tables a collection queued, from the `execute` loop.
+// 2026-02-24: Compiled functions no longer hold anything of a VM: `FuncMetadata` is `Send + Sync` and shared
+//            through `Arc`, a VM loads it as a `LoadedFunction` that adds the constants interned into its
+//            heap, the names its children were loaded as and the chunk it came from. `load_functions`
+//            replaces `finalize_constants` and `prefix_functions`, so a chunk is renamed apart without a copy.
+// 2026-02-24: `snapshot` / `restore` save and bring back the script state as a `VmImage` (see `snapshot`).
+//            `execute` cuts the value stack back after a chunk returned too, not only after an error.
+// 2026-02-24: Quickening: a `LoadedFunction` keeps its own copy of the packed code, which the dispatch loop
+//            runs and type feedback rewrites (see `dispatch::quicken`); `stats` counts rewrites and deopts.
+// 2026-02-24: With the `jit` feature, CALL runs hot numeric functions as native code compiled by cranelift
+//            (see `jit`); `stats` counts the compiled functions and the calls that ran natively.
+// 2026-02-24: Loaded functions get an id into `protos`, a function's prototypes are resolved to ids when it is
+//            loaded; closures (`LFunction`) share their `LoadedFunction` instead of copying its code and
+//            constants, so FNPROTO and CALL neither clone nor look anything up by name.
+// 2026-02-24: Globals live in a heap table, exposed to scripts as `_G`; GETGLOBAL / SETGLOBAL fall back to
+//            the __index / __newindex of its metatable. The host reads and writes them through
+//            `get_global` / `set_global`.
+// 2026-02-24: The VM's output sink (`set_stdout`) takes print, io.write, the debug log and the exit message;
+//            `write_internal_state` dumps the VM into any writer.
+// 2026-02-24: print and io.write go to `output` instead of straight to stdout, so a test can capture them.
+// 2026-02-24: FuncMetadata::span_info maps every pc to the source span it was compiled from.
+// 2026-02-24: `compile` and `init` return the `EmitError` of a function that does not fit the bytecode operands.
+// 2026-02-24: The VM no longer keeps a copy of the IR module it was initialized from, everything it runs is
+//            in the Arc-shared FuncMetadata of its loaded functions.
+// 2026-02-24: `protected_call_with` hands a failure to a callback before unwinding; xpcall runs its handler
+//            there, on top of the failing frames, so debug.traceback in the handler shows them.
+// 2026-02-24: `full_traceback` became `traceback_limit`, the number of lines a traceback is capped to
+//            (TRACEBACK_MAX_LINES by default), None prints every frame.
+// 2026-02-24: Calls return several values: a frame remembers the retc of its CALL (`ret_count`), the results
+//            past the first wait in `return_buffer` for the GETRESULTs after the CALL, and a MULTRET CALL or
+//            RETURN takes all of them. `return_buffer` is a GC root; hooks run with their own.
+// 2026-02-24: `call_values` returns every result of a call from native code, `protected_call` returns them
+//            all too; pcall and xpcall return true followed by each of them.
+// 2026-02-24: CALL and TAILCALL fail with an error instead of reading below their arguments when fewer than
+//            argc values were pushed, like SETLIST; only a corrupted image gets there.
+
+pub mod config;
+pub mod coroutine;
 pub mod dispatch;
 pub mod error;
 pub mod heap;
+pub mod hook;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod package;
+pub mod pattern;
+pub mod profiler;
+pub mod snapshot;
 pub mod stack;
-mod std_lib;
+pub(crate) mod std_lib;
+pub mod userdata;
 
-use crate::backend::translator::emitter::BytecodeEmitter;
+use crate::backend::disasm::Disassembler;
+use crate::backend::translator::emitter::{BytecodeEmitter, EmitError, OperandNames};
 use crate::backend::translator::scanner::{Lifetime, Scanner};
 use crate::backend::vm::LogLevel::Release;
-use crate::backend::vm::error::{ErrorKind, VMError};
-use crate::backend::vm::heap::Heap;
+use crate::backend::vm::config::VmConfig;
+use crate::backend::vm::coroutine::Resumer;
+use crate::backend::vm::dispatch::quicken::TypeFeedback;
+use crate::backend::vm::error::{ErrorKind, TRACEBACK_MAX_LINES, VMError};
+use crate::backend::vm::heap::{Gc, GcMode, Heap};
+use crate::backend::vm::hook::{Hook, HookCallback};
+#[cfg(feature = "jit")]
+use crate::backend::vm::jit::{Jit, JitSlot};
+use crate::backend::vm::profiler::Profiler;
 use crate::backend::vm::stack::{GlobalStack, StackFrame};
-use crate::backend::vm::std_lib::lua_builtin_print;
-use crate::common::object::{GCObject, HeaderOnly, ObjectKind};
-use crate::common::object::{LuaUpValue, LuaUpValueState, LuaValue};
-use crate::common::opcode::OpCode;
-use crate::frontend::ir::{IRGenerator, IRModule, IRUpVal};
+use crate::backend::vm::std_lib::{
+    COROUTINE_LIB, DEBUG_LIB, IO_LIB, LuaRng, MATH_CONSTANTS, MATH_LIB, OS_LIB, STRING_LIB,
+    lua_builtin_assert, lua_builtin_collectgarbage, lua_builtin_dofile, lua_builtin_error,
+    lua_builtin_getmetatable, lua_builtin_load, lua_builtin_loadstring, lua_builtin_next,
+    lua_builtin_pcall, lua_builtin_print, lua_builtin_rawequal, lua_builtin_rawget,
+    lua_builtin_rawlen, lua_builtin_rawset, lua_builtin_require, lua_builtin_select,
+    lua_builtin_setmetatable, lua_builtin_tonumber, lua_builtin_tostring, lua_builtin_type,
+    lua_builtin_unpack, lua_builtin_xpcall,
+};
+use crate::common::instruction::Instruction;
+use crate::common::object::{CFunction, Constant, LuaTable};
+use crate::common::object::{LuaCoroutine, LuaUpValue, LuaUpValueState, LuaValue, NativeClosure};
+use crate::common::opcode::{JumpTable, OpCode};
+use crate::frontend::ir::{IRGenerator, IRUpVal};
+use crate::frontend::parser::ast::Span;
 use clap::ValueEnum;
-use std::collections::{HashMap, VecDeque};
-use std::io::Write;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
 pub enum LogLevel {
@@ -51,61 +189,400 @@ pub enum LogLevel {
     Trace,   // 输出全量寄存器生命周期、IR 和虚拟机指令追踪
 }
 
+/// what the VM did since it was created or last reset, for `myulac --time` and the benchmarks
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ExecStats {
+    pub instructions: u64,
+    pub full_collections: u64,
+    pub minor_collections: u64,
+    // instructions rewritten into a quickened form, and quickened ones put back (see dispatch::quicken)
+    pub quickened: u64,
+    pub deopts: u64,
+    // functions compiled to native code and calls that ran it, always 0 without the `jit` feature
+    pub jit_compiled: u64,
+    pub jit_calls: u64,
+}
+
+/// a compiled function, independent of any VM: constants are plain `Constant`s,
+/// so it can be shared between VMs and threads (see `LoadedFunction`)
+#[derive(Clone)]
 pub struct FuncMetadata {
     pub bytecode: Vec<OpCode>,
-    pub constants: Vec<LuaValue>,
+    // `bytecode` packed for the dispatch loop, pc for pc
+    pub code: Vec<Instruction>,
+    pub constants: Vec<Constant>,
     pub num_locals: usize,
     pub max_stack_size: usize,
     pub reg_metadata: HashMap<usize, Lifetime>,
     pub upvalues_metadata: Vec<IRUpVal>,
     pub child_protos: Vec<String>,
+    // pc -> symbolic operand description, empty in release mode
+    pub operand_names: OperandNames,
+    // pc -> source line of that instruction
+    pub line_info: Vec<u32>,
+    // pc -> source span of the expression or statement that instruction was compiled from
+    pub span_info: Vec<Span>,
+    // source name of each local slot, the local in slot n lives in register n
+    pub local_names: Vec<String>,
+    // the tables of the SWITCH instructions, by their table operand
+    pub jump_tables: Vec<JumpTable>,
+}
+
+/// a function as one VM loaded it, frames share it through `Rc`; everything that is not
+/// per VM is read through `meta`, which derefs to it
+pub struct LoadedFunction {
+    // index in the VM's `protos`
+    pub id: usize,
+    // the name the function was loaded as, the key of `func_meta`
+    pub name: Rc<str>,
+    pub meta: Arc<FuncMetadata>,
+    // `meta.constants` as runtime values, strings interned into this VM's heap
+    pub const_values: Vec<LuaValue>,
+    // ids of `meta.child_protos` as they were loaded, usize::MAX for one that is missing
+    pub children: Vec<usize>,
+    // file the function was loaded from, None for the VM's own `chunk_name`
+    pub chunk_name: Option<Rc<str>>,
+    // what the dispatch loop runs: `meta.code` with the hot instructions of this VM quickened
+    pub quickened: Vec<Cell<Instruction>>,
+    // pc -> the operand types the instruction has been seeing, see dispatch::quicken
+    pub feedback: Vec<Cell<TypeFeedback>>,
+    // call count and native code of this function, see `jit`
+    #[cfg(feature = "jit")]
+    pub jit: JitSlot,
+}
+
+impl Deref for LoadedFunction {
+    type Target = FuncMetadata;
+
+    fn deref(&self) -> &FuncMetadata {
+        &self.meta
+    }
+}
+
+// a function about to be loaded: its final name, the compiled function, the final names of its
+// prototypes and the chunk it comes from, see `link_functions`
+type PendingFunction = (String, Arc<FuncMetadata>, Vec<String>, Option<Rc<str>>);
+
+/// wrap freshly compiled or deserialized functions so any number of VMs can load them
+pub fn share_functions(
+    func_meta: HashMap<String, FuncMetadata>,
+) -> HashMap<String, Arc<FuncMetadata>> {
+    func_meta
+        .into_iter()
+        .map(|(name, meta)| (name, Arc::new(meta)))
+        .collect()
 }
 
-const MAX_CALL_STACK: usize = 1000;
-const HARD_MEMORY_LIMIT: usize = 1024 * 1024 * 512; //512MB
-const VM_THRESHOLD: usize = 1024 * 1024; //1MB
+pub const MAX_CALL_STACK: usize = 1000;
+pub const HARD_MEMORY_LIMIT: usize = 1024 * 1024 * 512; //512MB
+pub const VM_THRESHOLD: usize = 1024 * 1024; //1MB
+const NURSERY_THRESHOLD: usize = 256 * 1024; //256KB
+// instructions between two looks at the clock while a timeout is set
+pub const BUDGET_CLOCK_INTERVAL: u64 = 1024;
 
 // number of padded regs at the end of each stack frame
 // to support some functionalities
-const NUM_PAD_REGS: usize = 2;
+pub(crate) const NUM_PAD_REGS: usize = 2;
 
 pub struct VirtualMachine {
     pub call_stack: Vec<StackFrame>,
     pub value_stack: GlobalStack,
-    pub globals: HashMap<String, LuaValue>,
-    pub module: IRModule,
-    pub func_meta: HashMap<String, FuncMetadata>,
+    // the global namespace, `_G` to scripts; reset empties it but keeps the object
+    pub globals: Gc<LuaTable>,
+    pub func_meta: HashMap<String, Rc<LoadedFunction>>,
+    // every loaded function by id, FNPROTO finds the prototypes it creates here
+    pub protos: Vec<Rc<LoadedFunction>>,
     pub heap: Heap,
     pub log_level: LogLevel,
+    // results of the last frame that returned without a destination register, i.e. a frame entered
+    // through call_value from native code, or of the last call that takes more than one result
+    pub return_buffer: Vec<LuaValue>,
+    // lines a stack traceback is capped to by dropping lines from its middle, None prints every frame
+    pub traceback_limit: Option<usize>,
+    // generator behind math.random
+    pub rng: LuaRng,
+    // seed `rng` starts from, None seeds it from the clock
+    rng_seed: Option<u64>,
+    // reusable buffer for string builtins, results are interned straight out of it
+    pub(crate) scratch: Vec<u8>,
+    // where io.read / io.lines take their input from, a REPL or a test can replace it
+    pub input: Box<dyn BufRead>,
+    // where print, io.write and the debug log go, stdout unless replaced with `set_stdout`
+    output: Box<dyn Write>,
+    // creation time of the VM, the origin of os.clock
+    pub started: Instant,
+    // the globals defined by load_standard_library, restored by reset(true)
+    stdlib_globals: HashMap<String, LuaValue>,
+    // shown in front of source lines in errors, usually the script path
+    pub chunk_name: Rc<str>,
+    // the coroutine being run, None for the main thread
+    pub current_thread: Option<Gc<LuaCoroutine>>,
+    // threads waiting in coroutine.resume, innermost last
+    pub(crate) resumers: Vec<Resumer>,
+    // values passed to coroutine.yield, taken by the resume loop after the instruction
+    pub(crate) pending_yield: Option<Vec<LuaValue>>,
+    // modules whose main chunk is running, a require of one of them is a loop
+    pub(crate) requiring: Vec<String>,
+    // number of modules and chunks loaded at runtime so far, their functions are renamed apart with it
+    modules_loaded: usize,
+    pub stats: ExecStats,
+    // `stats.instructions` at which check_budget runs next, u64::MAX without a budget
+    budget_check_at: u64,
+    // `stats.instructions` at which the fuel given to set_fuel runs out
+    fuel_end: Option<u64>,
+    // when the time given to set_timeout runs out
+    deadline: Option<Instant>,
+    // what scripts are allowed to do, fixed when the VM is created
+    config: VmConfig,
+    // set_hook / debug.sethook, None when nothing listens
+    pub(crate) hook: Option<Box<Hook>>,
+    // per-function and per-opcode statistics, None unless profiling (myulac --profile)
+    pub profiler: Option<Box<Profiler>>,
+    // the native code generator, created by the first compilation
+    #[cfg(feature = "jit")]
+    pub(crate) jit: Option<Box<Jit>>,
 }
 
 impl VirtualMachine {
     pub fn new() -> Self {
+        Self::with_config(VmConfig::default())
+    }
+
+    /// a VM restricted by `config`, which applies to every chunk it runs, also after a `reset`
+    pub fn with_config(config: VmConfig) -> Self {
+        let mut heap = Heap::new();
+        heap.limit = config.heap_limit;
+        heap.threshold = config.gc_threshold;
+        let globals = heap
+            .alloc_table(LuaTable::new())
+            .expect("BootstrapError: OutOfMemory while creating the global table");
         Self {
             call_stack: Vec::new(),
             value_stack: GlobalStack::default(),
-            globals: HashMap::new(),
-            module: IRModule { functions: vec![] },
+            globals,
             func_meta: HashMap::new(),
-            heap: Heap::new(),
+            protos: Vec::new(),
+            heap,
             log_level: Release,
+            return_buffer: Vec::new(),
+            traceback_limit: Some(TRACEBACK_MAX_LINES),
+            rng: LuaRng::from_time(),
+            rng_seed: None,
+            scratch: Vec::new(),
+            input: Box::new(BufReader::new(std::io::stdin())),
+            output: Box::new(std::io::stdout()),
+            started: Instant::now(),
+            stdlib_globals: HashMap::new(),
+            chunk_name: "?".into(),
+            current_thread: None,
+            resumers: Vec::new(),
+            pending_yield: None,
+            requiring: Vec::new(),
+            modules_loaded: 0,
+            stats: ExecStats::default(),
+            budget_check_at: u64::MAX,
+            fuel_end: None,
+            deadline: None,
+            config,
+            hook: None,
+            profiler: None,
+            #[cfg(feature = "jit")]
+            jit: None,
+        }
+    }
+
+    pub fn config(&self) -> &VmConfig {
+        &self.config
+    }
+
+    /// choose the garbage collector, GcMode::Full by default; it can be switched at any time
+    pub fn set_gc_mode(&mut self, mode: GcMode) {
+        self.heap.set_mode(mode);
+    }
+
+    /// seed math.random so every run of a script draws the same numbers, `None` goes back
+    /// to seeding from the clock; the generator restarts right away and again on every `reset`
+    ///
+    /// each VM owns its generator, so VMs seeded alike produce the same sequence independently
+    pub fn set_random_seed(&mut self, seed: Option<u64>) {
+        self.rng_seed = seed;
+        self.rng = LuaRng::new(seed);
+    }
+
+    /// stop execution with ErrorKind::BudgetExceeded once `fuel` more instructions have run,
+    /// `None` removes the quota; the count is deterministic, the same script always stops at the
+    /// same instruction
+    ///
+    /// pcall cannot catch the error, and the fuel stays used up until it is set again
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel_end = fuel.map(|fuel| self.stats.instructions.saturating_add(fuel));
+        self.schedule_budget_check();
+    }
+
+    /// instructions left before the quota given to `set_fuel` runs out
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel_end
+            .map(|end| end.saturating_sub(self.stats.instructions))
+    }
+
+    /// stop execution with ErrorKind::BudgetExceeded once `timeout` has passed, counted from now;
+    /// `None` removes the limit
+    ///
+    /// the clock is read every BUDGET_CLOCK_INTERVAL instructions, so a script overshoots by
+    /// up to that many instructions, or by a single native call that runs long (e.g. string.rep)
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.deadline = timeout.map(|timeout| Instant::now() + timeout);
+        self.schedule_budget_check();
+    }
+
+    fn schedule_budget_check(&mut self) {
+        let clock = self.deadline.map(|_| {
+            self.stats
+                .instructions
+                .saturating_add(BUDGET_CLOCK_INTERVAL)
+        });
+        self.budget_check_at = match (self.fuel_end, clock) {
+            (Some(fuel), Some(clock)) => fuel.min(clock),
+            (Some(at), None) | (None, Some(at)) => at,
+            (None, None) => u64::MAX,
+        };
+    }
+
+    // runs when the instruction count reaches budget_check_at, before the next instruction
+    #[cold]
+    fn check_budget(&mut self) -> Result<(), VMError> {
+        if let Some(end) = self.fuel_end
+            && self.stats.instructions >= end
+        {
+            return Err(self.error(ErrorKind::BudgetExceeded(
+                "instruction budget exhausted".into(),
+            )));
+        }
+        if let Some(deadline) = self.deadline
+            && Instant::now() >= deadline
+        {
+            return Err(self.error(ErrorKind::BudgetExceeded(
+                "execution time limit exceeded".into(),
+            )));
+        }
+        self.schedule_budget_check();
+        Ok(())
+    }
+
+    /// drop everything the last chunk left behind so the VM can `init` and run another one:
+    /// call/value stacks, compiled functions and globals are cleared and a full collection
+    /// frees every object that is no longer reachable
+    ///
+    /// with `keep_stdlib` the standard library globals are restored to the values they were
+    /// registered with and `init` does not load them again; changes a script made inside
+    /// the library tables (e.g. `string.trim = ...`) are kept
+    pub fn reset(&mut self, keep_stdlib: bool) {
+        // close open upvalues first, a closure stored in a library table may still refer to the stack
+        while self.pop_frame().is_some() {}
+        self.value_stack.values.clear();
+        self.return_buffer.clear();
+        self.scratch = Vec::new();
+        self.current_thread = None;
+        self.resumers.clear();
+        self.pending_yield = None;
+        self.requiring.clear();
+        self.modules_loaded = 0;
+        // a hook function of the script goes with it, one set by the host stays
+        if let Some(hook) = &self.hook
+            && let HookCallback::Lua(_) = hook.callback
+        {
+            self.hook = None;
+        }
+
+        self.func_meta.clear();
+        self.protos.clear();
+        *self.heap.data_mut(self.globals) = LuaTable::new();
+        if keep_stdlib {
+            for (name, value) in self.stdlib_globals.clone() {
+                self.set_global(&name, value);
+            }
+            self.reset_package();
+        } else {
+            self.stdlib_globals.clear();
+        }
+
+        // the script's finalizers are dropped with it, they must not run on the next one
+        self.heap.finalizable.clear();
+        self.heap.to_finalize.clear();
+        self.collect_garbage();
+        self.heap.threshold = self.config.gc_threshold;
+        self.heap.max_allocated = self.heap.total_allocated;
+        self.started = Instant::now();
+        self.rng = LuaRng::new(self.rng_seed);
+        // the fuel left over is kept, counted from the new zero
+        self.fuel_end = self.fuel();
+        self.stats = ExecStats::default();
+        self.schedule_budget_check();
+        if self.profiler.is_some() {
+            self.profiler = Some(Box::new(Profiler::new()));
         }
     }
 
     /// IR 扫描 -> 寄存器分配 -> 字节码生成 -> 入口帧准备
-    pub fn init(&mut self, generator: &IRGenerator, log_level: LogLevel, scanner: &mut Scanner) {
+    pub fn init(
+        &mut self,
+        generator: &IRGenerator,
+        log_level: LogLevel,
+        scanner: &mut Scanner,
+    ) -> Result<(), EmitError> {
         self.log_level = log_level;
         if matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
-            println!(
+            let _ = writeln!(
+                self.output,
                 "[DEBUG] VM initialization started with log level: {:?}",
                 self.log_level
             );
-            println!("[DEBUG] Starting scanner...");
-            std::io::stdout().flush().unwrap();
+            let _ = writeln!(self.output, "[DEBUG] Starting scanner...");
+            let _ = self.output.flush();
+        }
+        let funcs = share_functions(Self::compile(
+            generator,
+            scanner,
+            self.log_level != LogLevel::Release,
+        )?);
+
+        if matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
+            let _ = writeln!(self.output, "[DEBUG] Finished emit");
+            let _ = self.output.flush();
+        }
+
+        self.link(&funcs);
+        Ok(())
+    }
+
+    /// load functions compiled ahead of time (see `compile` and `deserializer::deserialize_module`),
+    /// nothing is scanned or emitted, only the constants are finalized and the entry frame prepared
+    pub fn init_precompiled(
+        &mut self,
+        func_meta: HashMap<String, FuncMetadata>,
+        log_level: LogLevel,
+    ) {
+        self.log_level = log_level;
+        if matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
+            let _ = writeln!(
+                self.output,
+                "[DEBUG] VM initialization started with log level: {:?} (precompiled)",
+                self.log_level
+            );
+            let _ = self.output.flush();
         }
-        self.module = generator.get_module().clone();
+        self.link(&share_functions(func_meta));
+    }
 
-        for func_ir in &self.module.functions {
+    /// scan and emit every function of the module; constants stay `Constant`s
+    /// until a VM loads the result, so it can also be serialized as it is
+    pub fn compile(
+        generator: &IRGenerator,
+        scanner: &mut Scanner,
+        debug_info: bool,
+    ) -> Result<HashMap<String, FuncMetadata>, EmitError> {
+        let mut func_meta = HashMap::new();
+        for func_ir in &generator.get_module().functions {
             let func_name = &func_ir.name;
 
             let (num_locals, max_usage) = scanner
@@ -123,15 +600,9 @@ impl VirtualMachine {
                 }
             }
 
-            if matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
-                println!("[DEBUG] Finished scanning");
-                std::io::stdout().flush().unwrap();
-                println!("[DEBUG] Starting emitter...");
-                std::io::stdout().flush().unwrap();
-            }
-
-            let emitter = BytecodeEmitter::new(func_ir, &scanner);
-            let (bytecode, constants) = emitter.emit();
+            let emitter = BytecodeEmitter::new(func_ir, scanner).with_debug_info(debug_info);
+            let (bytecode, code, constants, operand_names, line_info, span_info, jump_tables) =
+                emitter.emit()?;
 
             // should not use upvalues.values() here because the order matters
             // and hashtable does not guarantee the order
@@ -140,56 +611,213 @@ impl VirtualMachine {
 
             let meta = FuncMetadata {
                 bytecode,
+                code,
                 constants,
                 num_locals,
                 max_stack_size: max_usage + NUM_PAD_REGS,
                 reg_metadata: reg_info_map,
                 upvalues_metadata: upvalues,
                 child_protos: func_ir.sub_functions.clone(),
+                operand_names,
+                line_info,
+                span_info,
+                jump_tables,
+                local_names: scanner
+                    .local_names
+                    .get(func_name)
+                    .cloned()
+                    .unwrap_or_default(),
             };
 
-            self.func_meta.insert(func_name.clone(), meta);
+            func_meta.insert(func_name.clone(), meta);
         }
+        Ok(func_meta)
+    }
 
+    // standard library, constants and entry frame, shared by `init` and `init_precompiled`
+    fn link(&mut self, funcs: &HashMap<String, Arc<FuncMetadata>>) {
         if matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
-            println!("[DEBUG] Finished emit");
-            std::io::stdout().flush().unwrap();
-            println!("[DEBUG] Loading standard library...");
-            std::io::stdout().flush().unwrap();
+            let _ = writeln!(self.output, "[DEBUG] Loading standard library...");
+            let _ = self.output.flush();
         }
 
-        self.load_standard_library();
+        // a VM reset with its standard library kept already has it
+        if self.stdlib_globals.is_empty() {
+            self.load_standard_library();
+        }
 
         if matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
-            println!("[DEBUG] Loading finalize constants...");
-            std::io::stdout().flush().unwrap();
+            let _ = writeln!(self.output, "[DEBUG] Loading finalize constants...");
+            let _ = self.output.flush();
         }
 
-        self.finalize_constants();
+        self.func_meta.clear();
+        self.protos.clear();
+        self.load_functions(funcs, "", None);
 
         if matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
-            println!("[DEBUG] Preparing entry frame...");
-            std::io::stdout().flush().unwrap();
+            let _ = writeln!(self.output, "[DEBUG] Preparing entry frame...");
+            let _ = self.output.flush();
         }
 
-        self.prepare_entry_frame();
+        self.prepare_entry_frame("_start");
 
         if matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
-            println!(
+            let _ = writeln!(
+                self.output,
                 "[DEBUG] Initialization successful: {} function metadata resolved. Entry point '_start' initialized (stack_size: {}).",
                 self.func_meta.len(),
                 self.func_meta
                     .get("_start")
                     .map(|m| m.max_stack_size)
                     .unwrap_or(0)
-            )
+            );
         }
     }
 
     pub fn load_standard_library(&mut self) {
-        self.globals
-            .insert("print".to_string(), LuaValue::CFunc(lua_builtin_print));
+        self.set_global("print", LuaValue::CFunc(lua_builtin_print));
+        self.set_global("tostring", LuaValue::CFunc(lua_builtin_tostring));
+        self.set_global("tonumber", LuaValue::CFunc(lua_builtin_tonumber));
+        self.set_global("setmetatable", LuaValue::CFunc(lua_builtin_setmetatable));
+        self.set_global("getmetatable", LuaValue::CFunc(lua_builtin_getmetatable));
+        self.set_global("pcall", LuaValue::CFunc(lua_builtin_pcall));
+        self.set_global("xpcall", LuaValue::CFunc(lua_builtin_xpcall));
+        self.set_global("error", LuaValue::CFunc(lua_builtin_error));
+        self.set_global("assert", LuaValue::CFunc(lua_builtin_assert));
+        self.set_global(
+            "collectgarbage",
+            LuaValue::CFunc(lua_builtin_collectgarbage),
+        );
+        self.set_global("load", LuaValue::CFunc(lua_builtin_load));
+        self.set_global("loadstring", LuaValue::CFunc(lua_builtin_loadstring));
+        self.set_global("type", LuaValue::CFunc(lua_builtin_type));
+        self.set_global("rawget", LuaValue::CFunc(lua_builtin_rawget));
+        self.set_global("rawset", LuaValue::CFunc(lua_builtin_rawset));
+        self.set_global("rawequal", LuaValue::CFunc(lua_builtin_rawequal));
+        self.set_global("rawlen", LuaValue::CFunc(lua_builtin_rawlen));
+        self.set_global("next", LuaValue::CFunc(lua_builtin_next));
+        self.set_global("select", LuaValue::CFunc(lua_builtin_select));
+        self.set_global("unpack", LuaValue::CFunc(lua_builtin_unpack));
+        self.register_library("string", STRING_LIB);
+        let math = self.register_library("math", MATH_LIB);
+        for (name, value) in MATH_CONSTANTS {
+            let key = self
+                .heap
+                .alloc_string(name.to_string())
+                .expect("BootstrapError: OutOfMemory during standard library registration");
+            self.heap
+                .data_mut(math)
+                .set(LuaValue::String(key), LuaValue::Number(*value));
+        }
+        if self.config.os {
+            self.register_library("os", OS_LIB);
+        }
+        if self.config.io {
+            self.register_library("io", IO_LIB);
+        }
+        self.register_library("coroutine", COROUTINE_LIB);
+        if self.config.debug {
+            self.register_library("debug", DEBUG_LIB);
+        }
+        if self.config.package {
+            self.set_global("require", LuaValue::CFunc(lua_builtin_require));
+            self.set_global("dofile", LuaValue::CFunc(lua_builtin_dofile));
+            self.register_library("package", &[]);
+        }
         //TODO:完成其他标准库注册
+        // writes through `_G` would get around the whitelist, a restricted script goes without it
+        if self.config.global_whitelist.is_none() {
+            self.set_global("_G", LuaValue::Table(self.globals));
+        }
+
+        self.stdlib_globals = self
+            .named_globals()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect();
+        // package.loaded lists the libraries above, so it is filled last
+        self.reset_package();
+    }
+
+    /// the global `name`, None if it is not set; a raw read that skips the metatable of `_G`
+    pub fn get_global(&self, name: &str) -> Option<&LuaValue> {
+        // strings are interned, a name that was never allocated is not a key of the table
+        let key = LuaValue::String(*self.heap.string_pool.get(name)?);
+        self.heap.data(self.globals).data.get(&key)
+    }
+
+    /// set the global `name`, nil removes it; a raw write that skips the metatable of `_G`
+    /// and the global whitelist, which only applies to scripts
+    pub fn set_global(&mut self, name: &str, value: LuaValue) {
+        let key = self
+            .heap
+            .alloc_str(name)
+            .expect("BootstrapError: OutOfMemory while setting a global");
+        let key = LuaValue::String(key);
+        self.heap.write_barrier(self.globals, &key);
+        self.heap.write_barrier(self.globals, &value);
+        self.heap.data_mut(self.globals).set(key, value);
+    }
+
+    /// every global with a string name, in no particular order
+    pub fn named_globals(&self) -> impl Iterator<Item = (&str, &LuaValue)> {
+        self.heap
+            .data(self.globals)
+            .data
+            .iter()
+            .filter_map(|(key, value)| match key {
+                LuaValue::String(name) => Some((name.get().as_str(), value)),
+                _ => None,
+            })
+    }
+
+    /// bind a Rust closure to the global `name`, it is called like any native function:
+    /// arguments are read with `native_arg`, results are pushed onto `value_stack`
+    /// and their number is returned
+    ///
+    /// the closure cannot call itself recursively (through Lua), such a call raises an error
+    pub fn register_function<F>(&mut self, name: &str, func: F)
+    where
+        F: FnMut(&mut VirtualMachine, usize) -> Result<usize, VMError> + 'static,
+    {
+        let closure = NativeClosure {
+            name: name.to_string(),
+            func: Some(Box::new(func)),
+        };
+        let ptr = self
+            .heap
+            .alloc_native_closure(closure)
+            .expect("BootstrapError: OutOfMemory during native function registration");
+        self.set_global(name, LuaValue::NativeClosure(ptr));
+    }
+
+    /// the i-th argument (0-based) of the running native function, missing arguments are nil
+    pub fn native_arg(&self, argc: usize, i: usize) -> LuaValue {
+        if i < argc {
+            *self.get_reg(i)
+        } else {
+            LuaValue::Nil
+        }
+    }
+
+    // create a global table `name` holding the given native functions,
+    // the table is returned so that constants can be added to it
+    pub fn register_library(&mut self, name: &str, funcs: &[(&str, CFunction)]) -> Gc<LuaTable> {
+        let mut lib = LuaTable::with_capacity(funcs.len());
+        for (func_name, func) in funcs {
+            let key = self
+                .heap
+                .alloc_string(func_name.to_string())
+                .expect("BootstrapError: OutOfMemory during standard library registration");
+            lib.set(LuaValue::String(key), LuaValue::CFunc(*func));
+        }
+
+        let lib_ptr = self
+            .heap
+            .alloc_table(lib)
+            .expect("BootstrapError: OutOfMemory during standard library registration");
+        self.set_global(name, LuaValue::Table(lib_ptr));
+        lib_ptr
     }
 
     // util function to calculate the actual top of the stack for the current frame
@@ -209,15 +837,22 @@ impl VirtualMachine {
 
     fn make_stack_frame(
         &mut self,
-        func_name: &str,
-        frame_size: usize,
+        func_name: Rc<str>,
+        meta: Option<Rc<LoadedFunction>>,
         return_dest: Option<usize>,
-        upvalues: Vec<*mut GCObject<LuaUpValue>>,
+        upvalues: Vec<Gc<LuaUpValue>>,
     ) -> StackFrame {
         let base_offset = self.get_actual_stack_top();
+        let frame_size = meta.as_ref().map_or(0, |m| m.max_stack_size);
         self.value_stack.reserve(base_offset + frame_size);
+        // surplus arguments of a Lua function are dropped, what it pushes goes right above its
+        // registers; a native frame has none and reads its arguments from there
+        if meta.is_some() {
+            self.value_stack.restore(base_offset + frame_size);
+        }
         StackFrame::new(
-            func_name.to_string(),
+            func_name,
+            meta,
             return_dest,
             base_offset,
             frame_size,
@@ -226,30 +861,43 @@ impl VirtualMachine {
     }
 
     fn push_frame(&mut self, frame: StackFrame) {
+        if self.hook.is_some() && frame.meta.is_some() {
+            self.queue_call_hook();
+        }
+        if let (Some(profiler), Some(meta)) = (self.profiler.as_mut(), frame.meta.as_ref()) {
+            profiler.record_call(meta, &frame.func_name, &self.chunk_name);
+        }
         self.call_stack.push(frame);
     }
 
     fn pop_frame(&mut self) -> Option<StackFrame> {
         let frame = self.call_stack.pop()?;
         // close any open upvalues that escape from this frame
-        for (_, upval_ptr) in &frame.out_upvalues {
-            unsafe {
-                let upval = &mut **upval_ptr;
-                if let LuaUpValueState::Open(stack_idx) = upval.data.value {
-                    // close the upvalue by capturing the current value from the stack
-                    let val = self.get_reg_absolute(stack_idx).clone();
-                    upval.data.value = LuaUpValueState::Closed(val);
-                }
+        for &(_, upval) in &frame.out_upvalues {
+            if let LuaUpValueState::Open(stack_idx) = self.heap.data(upval).value {
+                // close the upvalue by capturing the current value from the stack
+                let val = *self.get_reg_absolute(stack_idx);
+                self.heap.write_barrier(upval, &val);
+                self.heap.data_mut(upval).value = LuaUpValueState::Closed(val);
             }
         }
         Some(frame)
     }
 
-    fn prepare_entry_frame(&mut self) {
-        let entry_name = "_start";
-        if let Some(meta) = self.func_meta.get(entry_name) {
-            let entry_frame = self.make_stack_frame(entry_name, meta.max_stack_size, None, vec![]);
-            self.call_stack.push(entry_frame);
+    /// add the functions of another compiled chunk to a VM that is already initialized
+    /// and prepare a frame for its entry point, globals and earlier functions are kept
+    ///
+    /// every chunk names its entry `_start` and numbers its functions from 0, so each one
+    /// needs a `prefix` that no other chunk loaded into this VM uses
+    pub fn load_chunk(&mut self, funcs: &HashMap<String, Arc<FuncMetadata>>, prefix: &str) {
+        self.load_functions(funcs, prefix, None);
+        self.prepare_entry_frame(&format!("{}_start", prefix));
+    }
+
+    fn prepare_entry_frame(&mut self, entry_name: &str) {
+        if let Some(meta) = self.func_meta.get(entry_name).cloned() {
+            let entry_frame = self.make_stack_frame(meta.name.clone(), Some(meta), None, vec![]);
+            self.push_frame(entry_frame);
         } else {
             panic!(
                 "[ERROR] SymbolResolutionError: entry point '{}' not found. Ensure the IR generation phase emitted the mandatory entry symbol.",
@@ -260,7 +908,7 @@ impl VirtualMachine {
 
     pub fn run(&mut self) {
         if matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
-            println!("[DEBUG] Starting execution engine...");
+            let _ = writeln!(self.output, "[DEBUG] Starting execution engine...");
         }
 
         if self.call_stack.is_empty() {
@@ -268,62 +916,162 @@ impl VirtualMachine {
                 "[ERROR] IllegalStateException: call stack is uninitialized. No entry frame found."
             );
         }
-        //loop
+
+        if let Err(e) = self.execute() {
+            self.report_error(e);
+            return;
+        }
+
+        if matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
+            let _ = writeln!(
+                self.output,
+                "[DEBUG] Max memory allocated during execution: {} bytes",
+                self.heap.max_allocated
+            );
+        }
+        let _ = writeln!(self.output, "Program exited with code 0.");
+    }
+
+    /// run the prepared entry frame to completion and hand runtime errors back
+    /// to the caller instead of reporting them, used by embedders
+    ///
+    /// whatever the chunk returns at top level is left in `return_buffer`
+    pub fn execute(&mut self) -> Result<(), VMError> {
+        let stack_base = self.call_stack.first().map_or(0, |frame| frame.base_offset);
         while !self.call_stack.is_empty() {
             // 核心步骤：获取当前栈帧和指令，执行指令，并更新 PC
-            let result = self.protected_step();
-
-            if let Err(e) = result {
-                self.report_error(e);
-                self.call_stack.clear();
-                return;
+            if let Err(e) = self.protected_step() {
+                // unwind like protected_call does, so the VM can run another chunk afterwards
+                while !self.call_stack.is_empty() {
+                    self.pop_frame();
+                }
+                self.value_stack.restore(stack_base);
+                return Err(e);
             }
 
             //GC
-            if self.heap.check_gc_condition() {
-                self.heap.expand_threshold();
-                self.mark_objects();
-                self.sweep_objects();
+            self.collect_garbage_if_needed();
+            // not once the chunk returned, its results are waiting in return_buffer
+            if !self.heap.to_finalize.is_empty() && !self.call_stack.is_empty() {
+                self.run_finalizers().inspect_err(|_| {
+                    while self.pop_frame().is_some() {}
+                    self.value_stack.restore(stack_base);
+                })?;
             }
         }
-        if matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
-            println!(
-                "[DEBUG] Max memory allocated during execution: {} bytes",
-                self.heap.max_allocated
-            );
+        // arguments pushed for native calls are left above the frames, with no frame left they
+        // would sit where the next chunk's calls push theirs
+        self.value_stack.restore(stack_base);
+        Ok(())
+    }
+
+    fn collect_garbage_if_needed(&mut self) {
+        if self.heap.check_gc_condition() {
+            self.heap.expand_threshold();
+            self.collect_garbage();
+        } else if self.heap.check_minor_gc_condition() {
+            self.minor_collection();
+        } else if self.heap.check_stress_condition() {
+            self.gc_step();
+        }
+    }
+
+    /// a full collection, whatever the mode
+    pub fn collect_garbage(&mut self) {
+        self.stats.full_collections += 1;
+        self.mark_objects();
+        self.heap.finish_marking();
+        self.sweep_objects();
+    }
+
+    /// call the __gc metamethod of every table the collector queued, the most recently
+    /// registered first; the tables are freed by the next collection that finds them unreachable
+    ///
+    /// an error raised by a finalizer does not stop the script, it is only logged; the ones
+    /// pcall cannot catch either (budget, interrupt, a broken VM) are returned
+    pub fn run_finalizers(&mut self) -> Result<(), VMError> {
+        while let Some(table) = self.heap.to_finalize.pop() {
+            let table = LuaValue::Table(table);
+            let Some(gc) = self.get_metamethod(&table, "__gc") else {
+                continue;
+            };
+            match self.protected_call(gc, vec![table]) {
+                Ok(_) => {}
+                Err(e)
+                    if matches!(
+                        e.kind,
+                        ErrorKind::InternalError(_)
+                            | ErrorKind::BudgetExceeded(_)
+                            | ErrorKind::Interrupted(_)
+                    ) =>
+                {
+                    return Err(e);
+                }
+                Err(e) => {
+                    if matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
+                        let _ = writeln!(self.output, "[DEBUG] error in __gc metamethod: {}", e);
+                    }
+                }
+            }
         }
-        println!("Program exited with code 0.");
+        Ok(())
+    }
+
+    /// one unit of collection work: a minor collection in generational mode, a full one otherwise
+    pub fn gc_step(&mut self) {
+        match self.heap.mode() {
+            GcMode::Full => self.collect_garbage(),
+            GcMode::Generational => self.minor_collection(),
+        }
+    }
+
+    // mark the young objects reachable from the roots or from remembered old objects,
+    // free the rest of the nursery and promote the survivors
+    fn minor_collection(&mut self) {
+        self.stats.minor_collections += 1;
+        self.heap.minor = true;
+        self.mark_objects();
+        self.heap.mark_remembered();
+        self.heap.finish_marking();
+        self.heap.minor = false;
+        self.sweep_nursery();
     }
+
+    #[inline]
     fn protected_step(&mut self) -> Result<(), VMError> {
-        let (func_name, pc) = {
-            let frame = self.call_stack.last().ok_or_else(|| {
-                self.error(ErrorKind::InternalError(
-                    "IllegalStateException: attempt to step execution on an empty call stack"
-                        .into(),
-                ))
-            })?;
-            (frame.func_name.clone(), frame.pc)
-        };
+        let frame = self.call_stack.last().ok_or_else(|| {
+            self.error(ErrorKind::InternalError(
+                "IllegalStateException: attempt to step execution on an empty call stack".into(),
+            ))
+        })?;
+        let pc = frame.pc;
 
-        let meta = self.func_meta.get(&func_name).ok_or_else(|| {
+        let meta = frame.meta.as_ref().ok_or_else(|| {
             self.error(ErrorKind::InternalError(format!(
                 "ResolutionException: failed to resolve metadata for function symbol '{}'",
-                func_name
+                frame.func_name
             )))
         })?;
 
-        if pc >= meta.bytecode.len() {
+        let Some(curr_instr) = meta.quickened.get(pc).map(Cell::get) else {
             return Err(self.error(ErrorKind::InternalError(format!(
                 "InstructionOutOfBoundsException: PC ({:04}) exceeded bytecode range for function '{}' (total instructions: {})",
                 pc,
-                func_name,
-                meta.bytecode.len()
+                frame.func_name,
+                meta.code.len()
             ))));
-        }
-
-        let old_stack_depth = self.call_stack.len();
+        };
 
-        let curr_instr = meta.bytecode[pc];
+        if self.hook.is_some() {
+            self.run_hooks(curr_instr, pc)?;
+        }
+        if let Some(frame) = self.call_stack.last_mut() {
+            frame.instr_pc = pc;
+        }
+        if self.stats.instructions >= self.budget_check_at {
+            self.check_budget()?;
+        }
+        self.stats.instructions += 1;
 
         // // --- 新增调试打印开始 ---
         // print!("[TRACE] {:<10} | PC: {:03} | Instr: {:<20} | ", func_name, pc, format!("{:?}", curr_instr));
@@ -340,7 +1088,11 @@ impl VirtualMachine {
         // println!();
         // // --- 新增调试打印结束 ---
 
-        self.execute_instruction(curr_instr)?;
+        if self.profiler.is_some() {
+            self.profiled_step(curr_instr)?;
+        } else {
+            self.execute_instruction(curr_instr)?;
+        }
 
         // 不在这里统一将pc加1，而是让每条指令的处理函数根据需要自行调整PC（例如跳转指令会直接修改PC，而普通指令则在执行完后自动加1）
 
@@ -354,7 +1106,10 @@ impl VirtualMachine {
         let sep = "=".repeat(70);
         eprintln!("\n{}", sep);
 
-        eprintln!("  {}", err.get_message());
+        match err.location() {
+            Some(location) => eprintln!("  {}: {}", location, err.get_message()),
+            None => eprintln!("  {}", err.get_message()),
+        }
 
         eprintln!(
             "  Location: Function '{}' at instruction offset [PC: {:04}]",
@@ -366,8 +1121,8 @@ impl VirtualMachine {
         if err.stack_trace.is_empty() {
             eprintln!("    <empty_stack>");
         } else {
-            for (i, frame_name) in err.stack_trace.iter().enumerate().rev() {
-                eprintln!("    #{:<2} at {}()", i, frame_name);
+            for line in err.traceback_lines(self.traceback_limit) {
+                eprintln!("    {}", line);
             }
         }
         eprintln!("{}\n", sep);
@@ -375,7 +1130,7 @@ impl VirtualMachine {
 
     pub fn error(&self, kind: ErrorKind) -> VMError {
         let (func_name, pc) = if let Some(frame) = self.call_stack.last() {
-            (frame.func_name.clone(), frame.pc)
+            (frame.func_name.to_string(), frame.pc)
         } else {
             ("<unknown_context>".to_string(), 0)
         };
@@ -383,7 +1138,13 @@ impl VirtualMachine {
         let stack_trace = self
             .call_stack
             .iter()
-            .map(|f| f.func_name.clone())
+            .map(|f| f.func_name.to_string())
+            .collect();
+        let stack_lines = self.call_stack.iter().map(|f| self.frame_line(f)).collect();
+        let stack_chunks = self
+            .call_stack
+            .iter()
+            .map(|f| self.frame_chunk(f))
             .collect();
 
         VMError {
@@ -391,13 +1152,33 @@ impl VirtualMachine {
             func_name,
             pc,
             stack_trace,
+            stack_lines,
+            stack_chunks,
         }
     }
 
+    // file a frame's function comes from, required modules name their own
+    fn frame_chunk(&self, frame: &StackFrame) -> Rc<str> {
+        frame
+            .meta
+            .as_ref()
+            .and_then(|meta| meta.chunk_name.clone())
+            .unwrap_or_else(|| self.chunk_name.clone())
+    }
+
+    // source line of the instruction a frame is executing, None for functions without a line table
+    fn frame_line(&self, frame: &StackFrame) -> Option<u32> {
+        let meta = frame.meta.as_ref()?;
+        meta.line_info
+            .get(frame.instr_pc)
+            .copied()
+            .filter(|&l| l > 0)
+    }
+
     #[allow(dead_code)]
     fn cleanup_expired_registers(&mut self) {
         if let Some(frame) = self.call_stack.last_mut() {
-            if let Some(meta) = self.func_meta.get(&frame.func_name) {
+            if let Some(meta) = frame.meta.clone() {
                 for (&idx, lt) in &meta.reg_metadata {
                     // 修正：只有当 PC 已经走过了生命周期的终点，才设为 Nil
                     // 这样可以确保在 PC == lt.end 的那条指令执行时，数据依然有效
@@ -413,220 +1194,228 @@ impl VirtualMachine {
     }
 
     fn mark_objects(&mut self) {
-        unsafe {
-            for value in self.globals.values() {
-                self.mark_value(value);
-            }
+        let heap = &self.heap;
+        heap.mark_value(&LuaValue::Table(self.globals));
 
-            for value in &self.value_stack.values {
-                self.mark_value(value);
-            }
+        for value in self.stdlib_globals.values() {
+            heap.mark_value(value);
+        }
 
-            for meta in self.func_meta.values() {
-                for value in &meta.constants {
-                    self.mark_value(value);
-                }
-            }
-
-            for stack_frame in &self.call_stack {
-                // for stack frames, mark upvalues
-                for upval in &stack_frame.upvalues {
-                    let upval = &mut **upval;
-                    upval.mark = true; // mark the upvalue object itself to prevent it from being collected
-
-                    // only mark closed upvalues,
-                    // because open upvalues point to stack slots
-                    if let LuaUpValueState::Closed(val) = &upval.data.value {
-                        self.mark_value(val);
-                    }
-                }
+        for meta in self.func_meta.values() {
+            for value in &meta.const_values {
+                heap.mark_value(value);
             }
         }
-    }
-
-    fn sweep_objects(&mut self) {
-        unsafe {
-            let mut p_prev: *mut GCObject<HeaderOnly> = std::ptr::null_mut();
-            let mut p_curr = self.heap.all_objects;
-
-            // use for debug and performance monitoring
-            let mut swept_count = 0;
-            let mut swept_bytes = 0;
-
-            while !p_curr.is_null() {
-                if (*p_curr).mark {
-                    (*p_curr).mark = false;
-                    p_prev = p_curr;
-                    p_curr = (*p_curr).next;
-                } else {
-                    let p_next = (*p_curr).next;
-                    if p_prev.is_null() {
-                        self.heap.all_objects = p_next;
-                    } else {
-                        (*p_prev).next = p_next;
-                    }
-
-                    let kind = (*p_curr).kind;
-                    let obj_size = (*p_curr).size;
 
-                    swept_count += 1;
-                    swept_bytes += obj_size;
-
-                    self.heap.total_allocated = self.heap.total_allocated.saturating_sub(obj_size);
+        // results a GETRESULT has yet to read, or the chunk returned
+        for value in &self.return_buffer {
+            heap.mark_value(value);
+        }
 
-                    match kind {
-                        ObjectKind::String => {
-                            let str_ptr = p_curr as *mut GCObject<String>;
-                            self.heap.string_pool.remove(&(*str_ptr).data);
-                            let _ = Box::from_raw(str_ptr);
-                        }
-                        ObjectKind::Table => {
-                            let _ = Box::from_raw(
-                                p_curr as *mut GCObject<crate::common::object::LuaTable>,
-                            );
-                        }
-                        ObjectKind::Function => {
-                            let _ = Box::from_raw(
-                                p_curr as *mut GCObject<crate::common::object::LFunction>,
-                            );
-                        }
-                        ObjectKind::UpValue => {
-                            let _ = Box::from_raw(
-                                p_curr as *mut GCObject<crate::common::object::LuaUpValue>,
-                            );
-                        }
-                    }
+        // for stack frames, mark upvalues
+        heap.mark_stacks(&self.value_stack, &self.call_stack);
+        heap.mark_roots();
+        // queued by an earlier collection, their __gc has not run yet
+        for table in &heap.to_finalize {
+            heap.mark(*table);
+        }
 
-                    p_curr = p_next;
-                }
-            }
+        if let Some(hook) = &self.hook
+            && let HookCallback::Lua(func) = &hook.callback
+        {
+            heap.mark_value(func);
+        }
 
-            //use for debug and performance monitoring
-            if swept_count > 0 && matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
-                println!(
-                    "[DEBUG] Sweep phase finished: reclaimed {} objects, {} bytes released. Current heap: {} bytes.",
-                    swept_count, swept_bytes, self.heap.total_allocated
-                );
+        if let Some(thread) = self.current_thread {
+            heap.mark(thread);
+        }
+        for resumer in &self.resumers {
+            if let Some(thread) = resumer.thread {
+                heap.mark(thread);
             }
+            heap.mark_stacks(&resumer.value_stack, &resumer.call_stack);
         }
     }
 
-    unsafe fn mark_value(&self, value: &LuaValue) {
-        unsafe {
-            match value {
-                LuaValue::String(ptr) => {
-                    self.mark_raw(*ptr as *mut GCObject<HeaderOnly>);
-                }
-                LuaValue::Table(ptr) => {
-                    if self.mark_raw(*ptr as *mut GCObject<HeaderOnly>) {
-                        let table_inner = &(*(*ptr)).data;
-
-                        for (k, v) in &table_inner.data {
-                            self.mark_value(k);
-                            self.mark_value(v);
-                        }
-
-                        if let Some(mt_ptr) = table_inner.metatable {
-                            self.mark_value(&LuaValue::Table(mt_ptr));
-                        }
-                    }
-                }
-                LuaValue::Function(ptr) => {
-                    if self.mark_raw(*ptr as *mut GCObject<HeaderOnly>) {
-                        for val in &(*(*ptr)).data.constants {
-                            self.mark_value(val);
-                        }
-                        for upval in &(*(*ptr)).data.upvalues {
-                            // open upvalues points to stack slots
-                            let upval = &mut **upval;
-                            // mark the upvalue object itself to prevent it from being collected
-                            upval.mark = true;
-                            if let LuaUpValueState::Closed(val) = &upval.data.value {
-                                self.mark_value(val);
-                            }
-                        }
-                    }
-                }
-                _ => {}
-            }
+    // full collection sweep: every unmarked object is freed, young survivors are promoted
+    fn sweep_objects(&mut self) {
+        let (swept_count, swept_bytes) = self.heap.sweep();
+
+        //use for debug and performance monitoring
+        if swept_count > 0 && matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
+            let _ = writeln!(
+                self.output,
+                "[DEBUG] Sweep phase finished: reclaimed {} objects, {} bytes released. Current heap: {} bytes.",
+                swept_count, swept_bytes, self.heap.total_allocated
+            );
         }
+        self.sweep_nursery();
     }
-    unsafe fn mark_raw(&self, ptr: *mut GCObject<HeaderOnly>) -> bool {
-        if ptr.is_null() || (*ptr).mark {
-            return false;
+
+    // free the unmarked young objects and promote the marked ones
+    fn sweep_nursery(&mut self) {
+        let (swept_count, swept_bytes) = self.heap.sweep_nursery();
+        if swept_count > 0 && matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
+            let _ = writeln!(
+                self.output,
+                "[DEBUG] Minor sweep finished: reclaimed {} young objects, {} bytes released. Current heap: {} bytes.",
+                swept_count, swept_bytes, self.heap.total_allocated
+            );
         }
-        (*ptr).mark = true;
-        true
     }
 
-    pub fn dump_internal_state(&self) {
+    /// send the program's output (print, io.write) and the Debug/Trace log to `out` instead of stdout,
+    /// e.g. a buffer when embedding or testing; runtime errors still go to stderr
+    pub fn set_stdout(&mut self, out: Box<dyn Write>) {
+        self.output = out;
+    }
+
+    /// the compiled functions, the call stack and the value stack, written to the VM's output
+    pub fn dump_internal_state(&mut self) {
+        let mut out = std::mem::replace(&mut self.output, Box::new(std::io::sink()));
+        let _ = self.write_internal_state(&mut out);
+        self.output = out;
+    }
+
+    pub fn write_internal_state(&self, out: &mut dyn Write) -> std::io::Result<()> {
         let sep = "=".repeat(50);
-        println!("\n{}", sep);
-        println!("         VIRTUAL MACHINE INTERNAL STATE");
-        println!("{}", sep);
-
-        println!("\n[1. Function Metadata & Opcodes]");
-        for (name, meta) in &self.func_meta {
-            println!("Function: {}", name);
-            println!(
-                "  Locals: {}, Max Stack: {}",
-                meta.num_locals, meta.max_stack_size
-            );
-            println!("  Constants: {:?}", meta.constants);
-            println!("  Bytecode:");
-            for (pc, op) in meta.bytecode.iter().enumerate() {
-                println!("    [{:03}] {}", pc, op);
-            }
-            println!("  Register Lifetimes:");
-            let mut sorted_regs: Vec<_> = meta.reg_metadata.keys().collect();
-            sorted_regs.sort();
-            for reg in sorted_regs {
-                let lt = &meta.reg_metadata[reg];
-                println!("    R{} : start={}, end={}", reg, lt.start, lt.end);
-            }
-            println!("{}", "-".repeat(30));
+        writeln!(out, "\n{}", sep)?;
+        writeln!(out, "         VIRTUAL MACHINE INTERNAL STATE")?;
+        writeln!(out, "{}", sep)?;
+
+        writeln!(out, "\n[1. Function Metadata & Opcodes]")?;
+        let mut names: Vec<_> = self.func_meta.keys().collect();
+        names.sort();
+        for name in names {
+            write!(
+                out,
+                "{}",
+                Disassembler::new(name, &self.func_meta[name]).render()
+            )?;
+            writeln!(out, "{}", "-".repeat(30))?;
         }
 
-        println!("\n[2. Current Call Stack]");
+        writeln!(out, "\n[2. Current Call Stack]")?;
         if self.call_stack.is_empty() {
-            println!("  (Stack is empty)");
+            writeln!(out, "  (Stack is empty)")?;
         } else {
             for (depth, frame) in self.call_stack.iter().enumerate() {
-                println!("  Frame #{} -> Function: {}", depth, frame.func_name);
-                println!("    PC: {}", frame.pc);
-                print!("    Registers: ");
+                writeln!(out, "  Frame #{} -> Function: {}", depth, frame.func_name)?;
+                writeln!(out, "    PC: {}", frame.pc)?;
+                write!(out, "    Registers: ")?;
                 for i in 0..frame.reg_count {
-                    print!("R{}:{:?} ", i, frame.get_reg(i, &self.value_stack));
+                    write!(out, "R{}:{:?} ", i, frame.get_reg(i, &self.value_stack))?;
                 }
-                println!();
+                writeln!(out)?;
             }
         }
 
-        println!("\n[3. Global Stack]");
+        writeln!(out, "\n[3. Global Stack]")?;
         for (idx, val) in self.value_stack.values.iter().enumerate() {
-            println!("  [{}] {:?}", idx, val);
+            writeln!(out, "  [{}] {:?}", idx, val)?;
         }
 
-        println!("{}\n", "=".repeat(50));
+        writeln!(out, "{}\n", "=".repeat(50))?;
+        out.flush()
     }
 
-    //用于将所有临时字符串常量转换为 GC 管理的字符串对象，确保在运行时阶段它们能被正确处理和回收
-    pub fn finalize_constants(&mut self) {
-        for meta in self.func_meta.values_mut() {
-            for val in &mut meta.constants {
-                if let LuaValue::TempString(_) = val {
-                    if let LuaValue::TempString(raw_s) = std::mem::replace(val, LuaValue::Nil) {
-                        let gc_ptr = self.heap.alloc_string(raw_s).expect(
-                            "BootstrapError: OutOfMemory during constant pool string interning",
-                        );
-                        *val = LuaValue::String(gc_ptr);
-                    }
-                }
-            }
-        }
+    /// load compiled functions under `prefix` + their name, their string constants are
+    /// interned into this VM's heap; `chunk_name` names the file they come from in errors
+    pub fn load_functions(
+        &mut self,
+        funcs: &HashMap<String, Arc<FuncMetadata>>,
+        prefix: &str,
+        chunk_name: Option<Rc<str>>,
+    ) {
+        let batch = funcs
+            .iter()
+            .map(|(name, meta)| {
+                let children = meta
+                    .child_protos
+                    .iter()
+                    .map(|child| format!("{}{}", prefix, child))
+                    .collect();
+                (
+                    format!("{}{}", prefix, name),
+                    meta.clone(),
+                    children,
+                    chunk_name.clone(),
+                )
+            })
+            .collect();
+        self.link_functions(batch);
         if matches!(self.log_level, LogLevel::Debug | LogLevel::Trace) {
-            println!("[DEBUG] Constant pool resolution completed. Runtime environment is ready.");
+            let _ = writeln!(
+                self.output,
+                "[DEBUG] Constant pool resolution completed. Runtime environment is ready."
+            );
+        }
+    }
+
+    // add functions under their final names, each with the names its prototypes were loaded as;
+    // the ids are given out first, so a function can come before its prototypes
+    fn link_functions(&mut self, batch: Vec<PendingFunction>) {
+        let first = self.protos.len();
+        let ids: HashMap<&str, usize> = batch
+            .iter()
+            .enumerate()
+            .map(|(i, (name, ..))| (name.as_str(), first + i))
+            .collect();
+        let resolved: Vec<Vec<usize>> = batch
+            .iter()
+            .map(|(_, _, children, _)| {
+                children
+                    .iter()
+                    .map(|child| match ids.get(child.as_str()) {
+                        Some(&id) => id,
+                        None => self.func_meta.get(child).map_or(usize::MAX, |f| f.id),
+                    })
+                    .collect()
+            })
+            .collect();
+        for ((name, meta, _, chunk_name), children) in batch.into_iter().zip(resolved) {
+            self.load_function(name, meta, children, chunk_name);
+        }
+    }
+
+    // add one function under its final name and the next id, `children` are its prototypes' ids
+    fn load_function(
+        &mut self,
+        name: String,
+        meta: Arc<FuncMetadata>,
+        children: Vec<usize>,
+        chunk_name: Option<Rc<str>>,
+    ) {
+        let mut values = Vec::with_capacity(meta.constants.len());
+        for constant in &meta.constants {
+            values.push(match constant {
+                Constant::Nil => LuaValue::Nil,
+                Constant::Number(n) => LuaValue::Number(*n),
+                Constant::Integer(i) => LuaValue::Integer(*i),
+                Constant::String(s) => {
+                    let gc_ptr = self.heap.intern_str(s).expect(
+                        "BootstrapError: OutOfMemory during constant pool string interning",
+                    );
+                    LuaValue::String(gc_ptr)
+                }
+            });
         }
+        let quickened = meta.code.iter().copied().map(Cell::new).collect();
+        let feedback = vec![Cell::new(TypeFeedback::default()); meta.code.len()];
+        let loaded = Rc::new(LoadedFunction {
+            id: self.protos.len(),
+            name: name.as_str().into(),
+            meta,
+            const_values: values,
+            children,
+            chunk_name,
+            quickened,
+            feedback,
+            #[cfg(feature = "jit")]
+            jit: JitSlot::default(),
+        });
+        self.protos.push(loaded.clone());
+        self.func_meta.insert(name, loaded);
     }
 
     // get the value of a register in the current frame, with bounds checking
@@ -650,18 +1439,21 @@ impl VirtualMachine {
             .set_reg(idx, val, &mut self.value_stack);
     }
 
-    fn set_reg_absolute(&mut self, idx_abs: usize, val: LuaValue) {
-        self.value_stack.values[idx_abs] = val;
+    // describe the named operand of the instruction at pc in the current frame,
+    // e.g. "field 'config'", if the emitter recorded one
+    fn operand_name(&self, pc: usize) -> Option<&String> {
+        let frame = self.call_stack.last()?;
+        frame.meta.as_ref()?.operand_names.get(&pc)
     }
 
     fn get_constant(&self, idx: usize) -> &LuaValue {
         let frame = self.call_stack.last().unwrap();
-        &self.func_meta.get(&frame.func_name).unwrap().constants[idx]
+        &frame.meta.as_ref().unwrap().const_values[idx]
     }
 
     fn get_constant_string(&self, idx: usize) -> Result<String, VMError> {
         match self.get_constant(idx) {
-            LuaValue::String(ptr) => unsafe { Ok((*(*ptr)).data.clone()) },
+            LuaValue::String(ptr) => Ok(ptr.get().clone()),
             _ => Err(self.error(ErrorKind::InternalError(format!(
                 "LinkageError: expected string constant at index {} was not found or has invalid type",
                 idx
@@ -0,0 +1,240 @@
+// Myula module loading
+// Changelog:
+// 2026-02-24: Initial version. `require` looks a module up in package.loaded, otherwise searches the
+//            templates of package.path, compiles the file with the frontend and runs its main chunk on
+//            the calling VM; the result is cached in package.loaded. Every module's functions get a
+//            `__module_N::` prefix so they do not collide with the main chunk's, and the module's path
+//            as their chunk name.
+// 2026-02-24: A module with lexer errors is refused like one with syntax errors.
+// 2026-02-24: `compile_source` / `link_chunk` are the pipeline of `require` on its own, `load`, `loadstring` and
+//            `dofile` compile source text into the running VM with them; every chunk gets its own prefix.
+
+use crate::backend::translator::scanner::Scanner;
+use crate::backend::vm::error::{ErrorKind, VMError};
+use crate::backend::vm::heap::Gc;
+use crate::backend::vm::{FuncMetadata, LogLevel, VirtualMachine, share_functions};
+use crate::common::object::{LFunction, LuaTable, LuaValue};
+use crate::frontend::diagnostics::Diagnostics;
+use crate::frontend::ir::IRGenerator;
+use crate::frontend::lexer::Lexer;
+use crate::frontend::parser::Parser;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_PACKAGE_PATH: &str = "./?.lua;./?/init.lua";
+
+impl VirtualMachine {
+    /// the value of `require(name)`: what the module's main chunk returned (true if nothing),
+    /// loaded at most once per VM
+    pub(crate) fn require_module(&mut self, name: &str) -> Result<LuaValue, VMError> {
+        let cached = self.loaded_module(name)?;
+        if cached != LuaValue::Nil {
+            return Ok(cached);
+        }
+        if self.requiring.iter().any(|n| n == name) {
+            return Err(self.error(ErrorKind::ModuleError(format!(
+                "loop while loading module '{}'",
+                name
+            ))));
+        }
+
+        let path = self.search_module(name)?;
+        let funcs = self.compile_module(name, &path)?;
+        let entry = self.link_chunk(funcs, &path.display().to_string())?;
+        let arg = self.alloc_name(name)?;
+
+        self.requiring.push(name.to_string());
+        let result = self.call_value(entry, vec![arg]);
+        self.requiring.pop();
+        let returned = result?;
+
+        // a module may fill package.loaded[name] itself instead of returning its table
+        let value = match (returned, self.loaded_module(name)?) {
+            (LuaValue::Nil, LuaValue::Nil) => LuaValue::Boolean(true),
+            (LuaValue::Nil, stored) => stored,
+            (returned, _) => returned,
+        };
+        let loaded = self.package_field_table("loaded")?;
+        let key = self.alloc_name(name)?;
+        self.heap.write_barrier(loaded, &value);
+        self.heap.data_mut(loaded).set(key, value);
+        Ok(value)
+    }
+
+    /// empty package.loaded except for the standard libraries and restore package.path,
+    /// the functions of previously required modules are gone after `reset`
+    pub(crate) fn reset_package(&mut self) {
+        let Some(LuaValue::Table(package)) = self.get_global("package").copied() else {
+            return;
+        };
+        let mut loaded = LuaTable::new();
+        for (name, value) in &self.stdlib_globals {
+            if let LuaValue::Table(_) = value {
+                let key = self
+                    .heap
+                    .alloc_string(name.clone())
+                    .expect("BootstrapError: OutOfMemory during package library registration");
+                loaded.set(LuaValue::String(key), *value);
+            }
+        }
+        let loaded = self
+            .heap
+            .alloc_table(loaded)
+            .expect("BootstrapError: OutOfMemory during package library registration");
+        let path = self
+            .heap
+            .alloc_string(DEFAULT_PACKAGE_PATH.to_string())
+            .expect("BootstrapError: OutOfMemory during package library registration");
+        let loaded_key = self
+            .heap
+            .alloc_string("loaded".to_string())
+            .expect("BootstrapError: OutOfMemory during package library registration");
+        let path_key = self
+            .heap
+            .alloc_string("path".to_string())
+            .expect("BootstrapError: OutOfMemory during package library registration");
+
+        let loaded = LuaValue::Table(loaded);
+        let path = LuaValue::String(path);
+        self.heap.write_barrier(package, &loaded);
+        self.heap.write_barrier(package, &path);
+        let package = self.heap.data_mut(package);
+        package.set(LuaValue::String(loaded_key), loaded);
+        package.set(LuaValue::String(path_key), path);
+    }
+
+    // package.loaded[name], nil if the module has not been loaded
+    fn loaded_module(&mut self, name: &str) -> Result<LuaValue, VMError> {
+        let loaded = self.package_field_table("loaded")?;
+        let key = self.alloc_name(name)?;
+        Ok(self.heap.data(loaded).get(&key))
+    }
+
+    // the first file of package.path that exists, '?' in each template stands for the
+    // module name with its dots turned into directory separators
+    fn search_module(&mut self, name: &str) -> Result<PathBuf, VMError> {
+        let templates = match self.package_field("path")? {
+            LuaValue::String(ptr) => ptr.get().clone(),
+            _ => {
+                return Err(self.error(ErrorKind::ModuleError(
+                    "'package.path' must be a string".into(),
+                )));
+            }
+        };
+        let file_name = name.replace('.', "/");
+
+        let mut tried = String::new();
+        for template in templates.split(';').filter(|t| !t.is_empty()) {
+            let candidate = PathBuf::from(template.replace('?', &file_name));
+            if candidate.is_file() {
+                return Ok(candidate);
+            }
+            tried.push_str(&format!("\n\tno file '{}'", candidate.display()));
+        }
+        Err(self.error(ErrorKind::ModuleError(format!(
+            "module '{}' not found:{}",
+            name, tried
+        ))))
+    }
+
+    // read and compile a module file, errors name the module
+    fn compile_module(
+        &mut self,
+        name: &str,
+        path: &Path,
+    ) -> Result<HashMap<String, FuncMetadata>, VMError> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| self.error(ErrorKind::IOError(format!("{}: {}", path.display(), e))))?;
+        self.compile_source(&source).map_err(|detail| {
+            self.error(ErrorKind::ModuleError(format!(
+                "error loading module '{}' from file '{}': {}",
+                name,
+                path.display(),
+                detail
+            )))
+        })
+    }
+
+    /// parse, lower and emit a chunk, the same pipeline as the main chunk; the first
+    /// syntax or lowering error is returned as "message (line n)"
+    pub(crate) fn compile_source(
+        &self,
+        source: &str,
+    ) -> Result<HashMap<String, FuncMetadata>, String> {
+        let mut lexer = Lexer::new(source);
+        let mut parser = Parser::new(&mut lexer);
+        let program = parser.parse();
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.add_parse(&parser);
+        if let Some(first) = diagnostics.iter().next() {
+            return Err(format!("{} (line {})", first.message, first.line));
+        }
+
+        let mut ir_gen = IRGenerator::new();
+        ir_gen.generate(&program);
+        diagnostics.add_ir(&ir_gen);
+        if let Some(first) = diagnostics.iter().next() {
+            return Err(format!("{} (line {})", first.message, first.line));
+        }
+
+        let mut scanner = Scanner::new();
+        scanner.global_scan(ir_gen.get_module());
+        let debug_info = matches!(self.log_level, LogLevel::Debug | LogLevel::Trace);
+        Self::compile(&ir_gen, &mut scanner, debug_info).map_err(|e| e.to_string())
+    }
+
+    /// load compiled functions into this VM under a prefix of their own and return a closure
+    /// over their main chunk, which has no upvalues; `chunk_name` names them in errors
+    pub(crate) fn link_chunk(
+        &mut self,
+        funcs: HashMap<String, FuncMetadata>,
+        chunk_name: &str,
+    ) -> Result<LuaValue, VMError> {
+        self.modules_loaded += 1;
+        let prefix = format!("__module_{}::", self.modules_loaded);
+        self.load_functions(&share_functions(funcs), &prefix, Some(chunk_name.into()));
+
+        let entry_name = format!("{}_start", prefix);
+        let meta = self.func_meta.get(&entry_name).cloned().ok_or_else(|| {
+            self.error(ErrorKind::InternalError(format!(
+                "LinkageError: entry point '{}' of a loaded chunk could not be resolved",
+                entry_name
+            )))
+        })?;
+        let func = LFunction {
+            proto: meta,
+            upvalues: vec![],
+        };
+        let ptr = self
+            .heap
+            .alloc_function(func)
+            .ok_or_else(|| self.error(ErrorKind::OutOfMemory))?;
+        Ok(LuaValue::Function(ptr))
+    }
+
+    fn package_field(&mut self, field: &str) -> Result<LuaValue, VMError> {
+        let Some(LuaValue::Table(package)) = self.get_global("package").copied() else {
+            return Err(self.error(ErrorKind::ModuleError("'package' must be a table".into())));
+        };
+        let key = self.alloc_name(field)?;
+        Ok(self.heap.data(package).get(&key))
+    }
+
+    fn package_field_table(&mut self, field: &str) -> Result<Gc<LuaTable>, VMError> {
+        match self.package_field(field)? {
+            LuaValue::Table(ptr) => Ok(ptr),
+            _ => Err(self.error(ErrorKind::ModuleError(format!(
+                "'package.{}' must be a table",
+                field
+            )))),
+        }
+    }
+
+    fn alloc_name(&mut self, name: &str) -> Result<LuaValue, VMError> {
+        let ptr = self
+            .heap
+            .alloc_string(name.to_string())
+            .ok_or_else(|| self.error(ErrorKind::OutOfMemory))?;
+        Ok(LuaValue::String(ptr))
+    }
+}
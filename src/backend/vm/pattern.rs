@@ -0,0 +1,445 @@
+// Myula Lua pattern matching
+// Changelog:
+// 2026-02-23: Initial implementation, a backtracking matcher over bytes following the reference
+//            lstrlib.c: character classes (%a %d ... and their complements), sets, `.`, the `*` `+` `-` `?`
+//            quantifiers, `^`/`$` anchors, captures (including position captures `()`), back references
+//            `%1`-`%9`, balanced matches `%bxy` and frontiers `%f[set]`.
+//            Used by string.find/match/gmatch/gsub in std_lib.
+
+pub const MAX_CAPTURES: usize = 32;
+// bounds the recursion of the matcher, "pattern too complex" beyond that
+const MAX_MATCH_DEPTH: usize = 200;
+
+const L_ESC: u8 = b'%';
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+
+const CAP_UNFINISHED: isize = -1;
+const CAP_POSITION: isize = -2;
+
+pub type PatternError = String;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Capture {
+    // byte range [start, end) of the subject
+    Slice(usize, usize),
+    // `()` captures the 1-based position in the subject
+    Position(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    // byte range [start, end) of the whole match
+    pub start: usize,
+    pub end: usize,
+    // explicit captures only, empty if the pattern has none
+    pub captures: Vec<Capture>,
+}
+
+impl Match {
+    /// captures as returned by string.match/gmatch: the whole match stands in
+    /// for the captures of a pattern that has none
+    pub fn values(&self) -> Vec<Capture> {
+        if self.captures.is_empty() {
+            vec![Capture::Slice(self.start, self.end)]
+        } else {
+            self.captures.clone()
+        }
+    }
+}
+
+/// true if the pattern has no magic characters and can be searched for literally
+pub fn is_plain(pat: &[u8]) -> bool {
+    !pat.iter().any(|b| SPECIALS.contains(b))
+}
+
+/// search for the first match starting at or after byte offset init,
+/// a leading '^' anchors the search at init
+pub fn find(src: &[u8], pat: &[u8], init: usize) -> Result<Option<Match>, PatternError> {
+    let (anchor, pat) = match pat.first() {
+        Some(b'^') => (true, &pat[1..]),
+        _ => (false, pat),
+    };
+
+    let mut matcher = Matcher::new(src, pat);
+    let mut s = init;
+    loop {
+        if let Some(m) = matcher.match_here(s)? {
+            return Ok(Some(m));
+        }
+        s += 1;
+        if anchor || s > src.len() {
+            return Ok(None);
+        }
+    }
+}
+
+/// try to match the pattern exactly at byte offset s, '^' has no special meaning
+pub fn match_at(src: &[u8], pat: &[u8], s: usize) -> Result<Option<Match>, PatternError> {
+    Matcher::new(src, pat).match_here(s)
+}
+
+struct Matcher<'a> {
+    src: &'a [u8],
+    pat: &'a [u8],
+    level: usize,
+    // (start, len) of every open or closed capture, len may be CAP_UNFINISHED or CAP_POSITION
+    capture: [(usize, isize); MAX_CAPTURES],
+    depth: usize,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(src: &'a [u8], pat: &'a [u8]) -> Self {
+        Self {
+            src,
+            pat,
+            level: 0,
+            capture: [(0, 0); MAX_CAPTURES],
+            depth: MAX_MATCH_DEPTH,
+        }
+    }
+
+    fn match_here(&mut self, s: usize) -> Result<Option<Match>, PatternError> {
+        self.level = 0;
+        self.depth = MAX_MATCH_DEPTH;
+        match self.do_match(s, 0)? {
+            Some(e) => {
+                let captures = (0..self.level)
+                    .map(|i| self.get_capture(i))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Some(Match {
+                    start: s,
+                    end: e,
+                    captures,
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // the C implementation relies on NUL-terminated strings,
+    // reading past the end yields 0 here as well
+    fn p(&self, i: usize) -> u8 {
+        self.pat.get(i).copied().unwrap_or(0)
+    }
+
+    fn s(&self, i: usize) -> u8 {
+        self.src.get(i).copied().unwrap_or(0)
+    }
+
+    fn get_capture(&self, i: usize) -> Result<Capture, PatternError> {
+        let (start, len) = self.capture[i];
+        match len {
+            CAP_UNFINISHED => Err("unfinished capture".into()),
+            CAP_POSITION => Ok(Capture::Position(start + 1)),
+            len => Ok(Capture::Slice(start, start + len as usize)),
+        }
+    }
+
+    // index just past the single-character class starting at p
+    fn class_end(&self, mut p: usize) -> Result<usize, PatternError> {
+        let c = self.p(p);
+        p += 1;
+        match c {
+            L_ESC => {
+                if p >= self.pat.len() {
+                    return Err("malformed pattern (ends with '%')".into());
+                }
+                Ok(p + 1)
+            }
+            b'[' => {
+                if self.p(p) == b'^' {
+                    p += 1;
+                }
+                // look for a ']', the first character of the set may be a ']' itself
+                loop {
+                    if p >= self.pat.len() {
+                        return Err("malformed pattern (missing ']')".into());
+                    }
+                    let cc = self.p(p);
+                    p += 1;
+                    if cc == L_ESC && p < self.pat.len() {
+                        // skip escapes such as '%]'
+                        p += 1;
+                    }
+                    if self.p(p) == b']' {
+                        return Ok(p + 1);
+                    }
+                }
+            }
+            _ => Ok(p),
+        }
+    }
+
+    // does the character at s match the class [p, ep)
+    fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+        if s >= self.src.len() {
+            return false;
+        }
+        let c = self.src[s];
+        match self.p(p) {
+            b'.' => true,
+            L_ESC => match_class(c, self.p(p + 1)),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    // p points at the '[' of a set, ec at its closing ']'
+    fn match_bracket_class(&self, c: u8, mut p: usize, ec: usize) -> bool {
+        let mut sig = true;
+        if self.p(p + 1) == b'^' {
+            sig = false;
+            p += 1;
+        }
+        p += 1;
+        while p < ec {
+            if self.p(p) == L_ESC {
+                p += 1;
+                if match_class(c, self.p(p)) {
+                    return sig;
+                }
+            } else if self.p(p + 1) == b'-' && p + 2 < ec {
+                if self.p(p) <= c && c <= self.p(p + 2) {
+                    return sig;
+                }
+                p += 2;
+            } else if self.p(p) == c {
+                return sig;
+            }
+            p += 1;
+        }
+        !sig
+    }
+
+    // returns the end of the match of pat[p..] against src[s..]
+    fn do_match(&mut self, s: usize, p: usize) -> Result<Option<usize>, PatternError> {
+        if self.depth == 0 {
+            return Err("pattern too complex".into());
+        }
+        self.depth -= 1;
+        let res = self.do_match_inner(s, p);
+        self.depth += 1;
+        res
+    }
+
+    fn do_match_inner(
+        &mut self,
+        mut s: usize,
+        mut p: usize,
+    ) -> Result<Option<usize>, PatternError> {
+        loop {
+            if p >= self.pat.len() {
+                return Ok(Some(s));
+            }
+
+            match self.p(p) {
+                b'(' => {
+                    return if self.p(p + 1) == b')' {
+                        self.start_capture(s, p + 2, CAP_POSITION)
+                    } else {
+                        self.start_capture(s, p + 1, CAP_UNFINISHED)
+                    };
+                }
+                b')' => return self.end_capture(s, p + 1),
+                b'$' if p + 1 == self.pat.len() => {
+                    return Ok((s == self.src.len()).then_some(s));
+                }
+                L_ESC if self.p(p + 1) == b'b' => match self.match_balance(s, p + 2)? {
+                    Some(e) => {
+                        s = e;
+                        p += 4;
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+                L_ESC if self.p(p + 1) == b'f' => {
+                    p += 2;
+                    if self.p(p) != b'[' {
+                        return Err("missing '[' after '%f' in pattern".into());
+                    }
+                    let ep = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.s(s - 1) };
+                    if !self.match_bracket_class(previous, p, ep - 1)
+                        && self.match_bracket_class(self.s(s), p, ep - 1)
+                    {
+                        p = ep;
+                        continue;
+                    }
+                    return Ok(None);
+                }
+                L_ESC if self.p(p + 1).is_ascii_digit() => {
+                    match self.match_capture(s, self.p(p + 1))? {
+                        Some(e) => {
+                            s = e;
+                            p += 2;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                _ => {
+                    let ep = self.class_end(p)?;
+                    let quantifier = self.p(ep);
+
+                    if !self.single_match(s, p, ep) {
+                        if matches!(quantifier, b'*' | b'?' | b'-') {
+                            // accepts the empty match
+                            p = ep + 1;
+                            continue;
+                        }
+                        return Ok(None);
+                    }
+
+                    match quantifier {
+                        b'?' => {
+                            if let Some(e) = self.do_match(s + 1, ep + 1)? {
+                                return Ok(Some(e));
+                            }
+                            p = ep + 1;
+                        }
+                        b'+' => return self.max_expand(s + 1, p, ep),
+                        b'*' => return self.max_expand(s, p, ep),
+                        b'-' => return self.min_expand(s, p, ep),
+                        _ => {
+                            s += 1;
+                            p = ep;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // greedy repetition, backtracks from the longest run
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, PatternError> {
+        let mut i = 0;
+        while self.single_match(s + i, p, ep) {
+            i += 1;
+        }
+        loop {
+            if let Some(e) = self.do_match(s + i, ep + 1)? {
+                return Ok(Some(e));
+            }
+            if i == 0 {
+                return Ok(None);
+            }
+            i -= 1;
+        }
+    }
+
+    // lazy repetition, grows from the shortest run
+    fn min_expand(
+        &mut self,
+        mut s: usize,
+        p: usize,
+        ep: usize,
+    ) -> Result<Option<usize>, PatternError> {
+        loop {
+            if let Some(e) = self.do_match(s, ep + 1)? {
+                return Ok(Some(e));
+            }
+            if self.single_match(s, p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(
+        &mut self,
+        s: usize,
+        p: usize,
+        what: isize,
+    ) -> Result<Option<usize>, PatternError> {
+        if self.level >= MAX_CAPTURES {
+            return Err("too many captures".into());
+        }
+        self.capture[self.level] = (s, what);
+        self.level += 1;
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.level -= 1;
+        }
+        Ok(res)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, PatternError> {
+        let l = (0..self.level)
+            .rev()
+            .find(|&i| self.capture[i].1 == CAP_UNFINISHED)
+            .ok_or_else(|| "invalid pattern capture".to_string())?;
+        self.capture[l].1 = (s - self.capture[l].0) as isize;
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.capture[l].1 = CAP_UNFINISHED;
+        }
+        Ok(res)
+    }
+
+    // %bxy, p points at x
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, PatternError> {
+        if p + 1 >= self.pat.len() {
+            return Err("malformed pattern (missing arguments to '%b')".into());
+        }
+        if s >= self.src.len() || self.src[s] != self.p(p) {
+            return Ok(None);
+        }
+
+        let (open, close) = (self.p(p), self.p(p + 1));
+        let mut depth = 1;
+        for i in s + 1..self.src.len() {
+            let c = self.src[i];
+            if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    // back reference %1-%9
+    fn match_capture(&self, s: usize, l: u8) -> Result<Option<usize>, PatternError> {
+        let idx = l as isize - b'1' as isize;
+        if idx < 0 || idx as usize >= self.level || self.capture[idx as usize].1 == CAP_UNFINISHED {
+            return Err(format!("invalid capture index %{}", idx + 1));
+        }
+
+        let (start, len) = self.capture[idx as usize];
+        if len < 0 {
+            return Ok(None);
+        }
+        let len = len as usize;
+        if self.src.len() - s >= len && self.src[start..start + len] == self.src[s..s + len] {
+            Ok(Some(s + len))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+// %a, %d, ... upper case letters complement the class, anything else matches itself
+fn match_class(c: u8, class: u8) -> bool {
+    let res = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => c.is_ascii_whitespace() || c == 0x0b,
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => return class == c,
+    };
+    if class.is_ascii_uppercase() {
+        !res
+    } else {
+        res
+    }
+}
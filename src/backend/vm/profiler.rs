@@ -0,0 +1,193 @@
+// Myula execution profiler
+// Changelog:
+// 2026-02-24: Initial version. With a `Profiler` installed (myulac --profile) every instruction is counted
+//            for its function and timed for its opcode, calls and allocated bytes are charged to functions;
+//            `Display` prints the sorted report. Without one `protected_step` only checks for it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::backend::vm::error::VMError;
+use crate::backend::vm::{LoadedFunction, VirtualMachine};
+use crate::common::instruction::{Instruction, OP_WIDE};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FunctionProfile {
+    pub name: String,
+    // where the function starts, e.g. "main.lua:12"
+    pub location: String,
+    pub calls: u64,
+    pub instructions: u64,
+    // spent in the function's own instructions, callees run through a nested step are not included
+    pub time: Duration,
+    pub alloc_bytes: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpcodeProfile {
+    pub count: u64,
+    pub time: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct Profiler {
+    // keyed by the address of the function's metadata, names are only copied once
+    functions: HashMap<usize, FunctionProfile>,
+    opcodes: HashMap<&'static str, OpcodeProfile>,
+    // time and bytes of the nested steps inside each step that is running,
+    // e.g. a comparator called by table.sort inside a CALL
+    nested: Vec<(Duration, usize)>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn function(
+        &mut self,
+        meta: &Rc<LoadedFunction>,
+        name: &str,
+        chunk_name: &str,
+    ) -> &mut FunctionProfile {
+        self.functions
+            .entry(Rc::as_ptr(meta) as usize)
+            .or_insert_with(|| FunctionProfile {
+                name: name.to_string(),
+                location: format!(
+                    "{}:{}",
+                    meta.chunk_name.as_deref().unwrap_or(chunk_name),
+                    meta.line_info.first().copied().unwrap_or(0)
+                ),
+                ..Default::default()
+            })
+    }
+
+    pub(crate) fn record_call(&mut self, meta: &Rc<LoadedFunction>, name: &str, chunk_name: &str) {
+        self.function(meta, name, chunk_name).calls += 1;
+    }
+
+    /// per function, most instructions first
+    pub fn functions(&self) -> Vec<&FunctionProfile> {
+        let mut functions: Vec<_> = self.functions.values().collect();
+        functions.sort_by(|a, b| {
+            b.instructions
+                .cmp(&a.instructions)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        functions
+    }
+
+    /// per opcode, most time first
+    pub fn opcodes(&self) -> Vec<(&'static str, &OpcodeProfile)> {
+        let mut opcodes: Vec<_> = self.opcodes.iter().map(|(&op, p)| (op, p)).collect();
+        opcodes.sort_by(|a, b| b.1.time.cmp(&a.1.time).then_with(|| a.0.cmp(b.0)));
+        opcodes
+    }
+}
+
+impl fmt::Display for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sep = "=".repeat(100);
+        writeln!(f, "{}", sep)?;
+        writeln!(
+            f,
+            "{:<40} {:>10} {:>14} {:>14} {:>16}",
+            "function", "calls", "instructions", "time (ms)", "alloc (bytes)"
+        )?;
+        writeln!(f, "{}", "-".repeat(100))?;
+        for func in self.functions() {
+            writeln!(
+                f,
+                "{:<40} {:>10} {:>14} {:>14.3} {:>16}",
+                format!("{} ({})", func.name, func.location),
+                func.calls,
+                func.instructions,
+                func.time.as_secs_f64() * 1000.0,
+                func.alloc_bytes
+            )?;
+        }
+        writeln!(f, "{}", sep)?;
+        writeln!(
+            f,
+            "{:<40} {:>10} {:>14} {:>14}",
+            "opcode", "count", "time (ms)", "avg (ns)"
+        )?;
+        writeln!(f, "{}", "-".repeat(100))?;
+        for (op, profile) in self.opcodes() {
+            writeln!(
+                f,
+                "{:<40} {:>10} {:>14.3} {:>14.1}",
+                op,
+                profile.count,
+                profile.time.as_secs_f64() * 1000.0,
+                profile.time.as_nanos() as f64 / profile.count.max(1) as f64
+            )?;
+        }
+        writeln!(f, "{}", sep)
+    }
+}
+
+impl VirtualMachine {
+    /// count, time and charge the allocations of every instruction from now on
+    ///
+    /// frames already on the call stack, e.g. the prepared entry frame, count as one call each
+    pub fn enable_profiler(&mut self) {
+        let mut profiler = Profiler::new();
+        for frame in &self.call_stack {
+            if let Some(meta) = &frame.meta {
+                profiler.record_call(meta, &frame.func_name, &self.chunk_name);
+            }
+        }
+        self.profiler = Some(Box::new(profiler));
+    }
+
+    // execute_instruction with the bookkeeping of the profiler around it
+    pub(crate) fn profiled_step(&mut self, instr: Instruction) -> Result<(), VMError> {
+        let frame = self.call_stack.last().unwrap();
+        let Some(meta) = frame.meta.clone() else {
+            return self.execute_instruction(instr);
+        };
+        let op = if instr.op() == OP_WIDE {
+            meta.bytecode[frame.pc].name()
+        } else {
+            instr.name()
+        };
+        let key = Rc::as_ptr(&meta) as usize;
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            // the entry is made up front, so the function name is not copied for every instruction
+            profiler.function(&meta, &frame.func_name, &self.chunk_name);
+            profiler.nested.push((Duration::ZERO, 0));
+        }
+        let allocated = self.heap.lifetime_allocated;
+        let start = Instant::now();
+        let result = self.execute_instruction(instr);
+        let elapsed = start.elapsed();
+        let allocated = self.heap.lifetime_allocated - allocated;
+
+        // the profiler may have been removed by the instruction, e.g. by a reset from native code
+        if let Some(profiler) = self.profiler.as_mut() {
+            let (nested_time, nested_bytes) = profiler.nested.pop().unwrap_or_default();
+            let own_time = elapsed.saturating_sub(nested_time);
+            let own_bytes = allocated.saturating_sub(nested_bytes);
+            if let Some(outer) = profiler.nested.last_mut() {
+                outer.0 += elapsed;
+                outer.1 += allocated;
+            }
+
+            if let Some(func) = profiler.functions.get_mut(&key) {
+                func.instructions += 1;
+                func.time += own_time;
+                func.alloc_bytes += own_bytes;
+            }
+
+            let opcode = profiler.opcodes.entry(op).or_default();
+            opcode.count += 1;
+            opcode.time += own_time;
+        }
+        result
+    }
+}
@@ -0,0 +1,867 @@
+// Myula VM snapshots
+// Changelog:
+// 2026-02-24: Initial version. `snapshot` writes the state of a script into a `VmImage`: the loaded functions,
+//            the globals, every heap object reachable from them, the value stack and the call stack;
+//            `restore` rebuilds it in any VM of the same build. Objects are numbered the first time they
+//            are reached and referred to by number, so shared and cyclic tables come back as one object.
+//            Native functions are not saved, they are written as the global path they are reachable
+//            under (`print`, `string.upper`) and looked up in the restoring VM.
+// 2026-02-24: Format 2: the globals are a heap table, saved as an object like any other; the restoring VM
+//            rebuilds it into its own global table, so `_G` and tables holding it stay the same object.
+// 2026-02-24: Format 3: every frame saves the number of results its caller takes after the return register.
+
+use crate::backend::deserializer::{
+    FormatError, HEADER_SIZE, Reader, read_header, write_function, write_header, write_str,
+    write_u32,
+};
+use crate::backend::vm::error::{ErrorKind, VMError};
+use crate::backend::vm::heap::{Gc, Heap};
+use crate::backend::vm::stack::StackFrame;
+use crate::backend::vm::std_lib::LuaRng;
+use crate::backend::vm::{FuncMetadata, VirtualMachine};
+use crate::common::object::{LFunction, LuaTable, LuaUpValue, LuaUpValueState, LuaValue};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"\x1bMYS";
+
+// bump whenever the payload below changes; the embedded functions are covered by the .myb header
+pub const SNAPSHOT_FORMAT_VERSION: u16 = 3;
+
+const VAL_NIL: u8 = 0;
+const VAL_FALSE: u8 = 1;
+const VAL_TRUE: u8 = 2;
+const VAL_INTEGER: u8 = 3;
+const VAL_NUMBER: u8 = 4;
+const VAL_STRING: u8 = 5;
+const VAL_TABLE: u8 = 6;
+const VAL_FUNCTION: u8 = 7;
+const VAL_NATIVE: u8 = 8;
+
+const OBJ_TABLE: u8 = 0;
+const OBJ_FUNCTION: u8 = 1;
+const OBJ_UPVALUE_OPEN: u8 = 2;
+const OBJ_UPVALUE_CLOSED: u8 = 3;
+
+/// the state of a script, taken with `VirtualMachine::snapshot` and brought back with `restore`;
+/// the bytes can be stored and read back with `from_bytes` by the same build of Myula
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmImage {
+    bytes: Vec<u8>,
+}
+
+impl VmImage {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// check the headers of stored image bytes, the payload is only decoded by `restore`
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, FormatError> {
+        image_payload(&bytes)?;
+        Ok(Self { bytes })
+    }
+}
+
+// the payload after the image header and the .myb header of the embedded functions
+fn image_payload(bytes: &[u8]) -> Result<&[u8], FormatError> {
+    if bytes.len() < 6 {
+        return Err(FormatError::Truncated);
+    }
+    if &bytes[..4] != SNAPSHOT_MAGIC {
+        return Err(FormatError::Malformed(
+            "not a Myula VM image (bad magic)".into(),
+        ));
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != SNAPSHOT_FORMAT_VERSION {
+        return Err(FormatError::Malformed(format!(
+            "VM image version {} is not supported (expected version {})",
+            version, SNAPSHOT_FORMAT_VERSION
+        )));
+    }
+    read_header(&bytes[6..])
+}
+
+// a native function, identified by the fn pointer or the closure object
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum NativeKey {
+    CFunc(usize),
+    Closure(usize),
+}
+
+fn native_key(value: &LuaValue) -> Option<NativeKey> {
+    match value {
+        LuaValue::CFunc(f) => Some(NativeKey::CFunc(*f as usize)),
+        LuaValue::NativeClosure(c) => Some(NativeKey::Closure(c.addr())),
+        _ => None,
+    }
+}
+
+// every native function reachable from the globals as `name` or `lib.name`, the standard library before
+// the script's globals and plain globals before library fields, so the same VM always gives a function
+// the same path and a library function is named after its library rather than a script table holding it
+fn native_paths(vm: &VirtualMachine) -> Vec<(String, LuaValue)> {
+    let mut paths = Vec::new();
+    let stdlib = vm
+        .stdlib_globals
+        .iter()
+        .map(|(name, value)| (name.as_str(), value));
+    let lists: [Vec<(&str, &LuaValue)>; 2] = [stdlib.collect(), vm.named_globals().collect()];
+    for mut entries in lists {
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, value) in &entries {
+            if native_key(value).is_some() {
+                paths.push((name.to_string(), **value));
+            }
+        }
+        for (name, value) in &entries {
+            let LuaValue::Table(lib) = value else {
+                continue;
+            };
+            // the fields of `_G` are the globals, already named above
+            if *lib == vm.globals {
+                continue;
+            }
+            let mut fields: Vec<(String, LuaValue)> = vm
+                .heap
+                .data(*lib)
+                .data
+                .iter()
+                .filter(|(_, value)| native_key(value).is_some())
+                .filter_map(|(key, value)| match key {
+                    LuaValue::String(key) => Some((format!("{}.{}", name, key.get()), *value)),
+                    _ => None,
+                })
+                .collect();
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            paths.extend(fields);
+        }
+    }
+    paths
+}
+
+#[derive(Clone, Copy)]
+enum ObjectRef {
+    Table(Gc<LuaTable>),
+    Function(Gc<LFunction>),
+    UpValue(Gc<LuaUpValue>),
+}
+
+impl ObjectRef {
+    fn addr(self) -> usize {
+        match self {
+            ObjectRef::Table(t) => t.addr(),
+            ObjectRef::Function(f) => f.addr(),
+            ObjectRef::UpValue(u) => u.addr(),
+        }
+    }
+}
+
+struct ImageWriter<'a> {
+    heap: &'a Heap,
+    natives: HashMap<NativeKey, String>,
+    // object address -> its number in the image
+    ids: HashMap<usize, usize>,
+    // objects in the order they were numbered, the ones from `written` on still need their body
+    objects: Vec<ObjectRef>,
+    written: usize,
+}
+
+impl ImageWriter<'_> {
+    fn object(&mut self, obj: ObjectRef) -> usize {
+        if let Some(&id) = self.ids.get(&obj.addr()) {
+            return id;
+        }
+        let id = self.objects.len();
+        self.ids.insert(obj.addr(), id);
+        self.objects.push(obj);
+        id
+    }
+
+    fn value(&mut self, out: &mut Vec<u8>, value: &LuaValue) -> Result<(), ErrorKind> {
+        match value {
+            LuaValue::Nil => out.push(VAL_NIL),
+            LuaValue::Boolean(false) => out.push(VAL_FALSE),
+            LuaValue::Boolean(true) => out.push(VAL_TRUE),
+            LuaValue::Integer(i) => {
+                out.push(VAL_INTEGER);
+                out.extend_from_slice(&i.to_le_bytes());
+            }
+            LuaValue::Number(n) => {
+                out.push(VAL_NUMBER);
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+            LuaValue::String(s) => {
+                out.push(VAL_STRING);
+                write_str(out, s.get());
+            }
+            LuaValue::Table(t) => {
+                out.push(VAL_TABLE);
+                let id = self.object(ObjectRef::Table(*t));
+                write_u32(out, id);
+            }
+            LuaValue::Function(f) => {
+                out.push(VAL_FUNCTION);
+                let id = self.object(ObjectRef::Function(*f));
+                write_u32(out, id);
+            }
+            LuaValue::CFunc(_) | LuaValue::NativeClosure(_) => {
+                let path = native_key(value).and_then(|key| self.natives.get(&key));
+                let Some(path) = path else {
+                    let name = match value {
+                        LuaValue::NativeClosure(c) => {
+                            format!("native function '{}'", self.heap.data(*c).name)
+                        }
+                        _ => "a native function".to_string(),
+                    };
+                    return Err(ErrorKind::SnapshotError(format!(
+                        "{} is not reachable from the globals, it cannot be saved",
+                        name
+                    )));
+                };
+                out.push(VAL_NATIVE);
+                write_str(out, path);
+            }
+            LuaValue::Coroutine(_) => {
+                return Err(ErrorKind::SnapshotError(
+                    "coroutines cannot be saved".into(),
+                ));
+            }
+            LuaValue::UserData(_) => {
+                return Err(ErrorKind::SnapshotError("userdata cannot be saved".into()));
+            }
+        }
+        Ok(())
+    }
+
+    fn upvalue(&mut self, out: &mut Vec<u8>, upval: Gc<LuaUpValue>) {
+        let id = self.object(ObjectRef::UpValue(upval));
+        write_u32(out, id);
+    }
+
+    // the bodies of all objects numbered so far and of those they reach in turn
+    fn objects(&mut self, out: &mut Vec<u8>) -> Result<(), ErrorKind> {
+        let heap = self.heap;
+        while self.written < self.objects.len() {
+            match self.objects[self.written] {
+                ObjectRef::Table(t) => {
+                    let table = heap.data(t);
+                    out.push(OBJ_TABLE);
+                    match table.metatable {
+                        Some(mt) => {
+                            out.push(1);
+                            let id = self.object(ObjectRef::Table(mt));
+                            write_u32(out, id);
+                        }
+                        None => out.push(0),
+                    }
+                    write_u32(out, table.array.len());
+                    for value in &table.array {
+                        self.value(out, value)?;
+                    }
+                    write_u32(out, table.data.len());
+                    for (key, value) in &table.data {
+                        self.value(out, key)?;
+                        self.value(out, value)?;
+                    }
+                }
+                ObjectRef::Function(f) => {
+                    let func = heap.data(f);
+                    out.push(OBJ_FUNCTION);
+                    write_str(out, func.name());
+                    write_u32(out, func.upvalues.len());
+                    for &upval in &func.upvalues {
+                        self.upvalue(out, upval);
+                    }
+                }
+                ObjectRef::UpValue(u) => {
+                    let upval = heap.data(u);
+                    if upval.thread.is_some() {
+                        return Err(ErrorKind::SnapshotError(
+                            "upvalues of coroutines cannot be saved".into(),
+                        ));
+                    }
+                    match &upval.value {
+                        LuaUpValueState::Open(idx) => {
+                            out.push(OBJ_UPVALUE_OPEN);
+                            write_u32(out, *idx);
+                        }
+                        LuaUpValueState::Closed(value) => {
+                            out.push(OBJ_UPVALUE_CLOSED);
+                            self.value(out, value)?;
+                        }
+                    }
+                }
+            }
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+// a value read back from an image, objects are numbers until they are allocated
+enum ImageValue {
+    Plain(LuaValue),
+    Str(String),
+    Table(usize),
+    Function(usize),
+    Native(String),
+}
+
+enum ImageObject {
+    Table {
+        metatable: Option<usize>,
+        array: Vec<ImageValue>,
+        hash: Vec<(ImageValue, ImageValue)>,
+    },
+    Function {
+        name: String,
+        upvalues: Vec<usize>,
+    },
+    OpenUpValue(usize),
+    ClosedUpValue(ImageValue),
+}
+
+struct ImageFrame {
+    func_name: String,
+    base_offset: usize,
+    reg_count: usize,
+    pc: usize,
+    instr_pc: usize,
+    ret_dest: Option<usize>,
+    ret_count: u8,
+    upvalues: Vec<usize>,
+    out_upvalues: Vec<(usize, usize)>,
+}
+
+struct ImageFunction {
+    name: String,
+    meta: FuncMetadata,
+    children: Vec<String>,
+    chunk_name: Option<String>,
+}
+
+// an image decoded completely before the VM is touched
+struct DecodedImage {
+    chunk_name: String,
+    modules_loaded: usize,
+    rng_state: u64,
+    functions: Vec<ImageFunction>,
+    objects: Vec<ImageObject>,
+    // object number of the global table
+    globals: usize,
+    stdlib_globals: Vec<(String, ImageValue)>,
+    value_stack: Vec<ImageValue>,
+    frames: Vec<ImageFrame>,
+    finalizable: Vec<usize>,
+    to_finalize: Vec<usize>,
+}
+
+fn write_opt_str(out: &mut Vec<u8>, s: Option<&str>) {
+    match s {
+        Some(s) => {
+            out.push(1);
+            write_str(out, s);
+        }
+        None => out.push(0),
+    }
+}
+
+fn read_value(reader: &mut Reader) -> Result<ImageValue, FormatError> {
+    Ok(match reader.u8()? {
+        VAL_NIL => ImageValue::Plain(LuaValue::Nil),
+        VAL_FALSE => ImageValue::Plain(LuaValue::Boolean(false)),
+        VAL_TRUE => ImageValue::Plain(LuaValue::Boolean(true)),
+        VAL_INTEGER => ImageValue::Plain(LuaValue::Integer(i64::from_le_bytes(reader.u64()?))),
+        VAL_NUMBER => ImageValue::Plain(LuaValue::Number(f64::from_le_bytes(reader.u64()?))),
+        VAL_STRING => ImageValue::Str(reader.string()?),
+        VAL_TABLE => ImageValue::Table(reader.u32()?),
+        VAL_FUNCTION => ImageValue::Function(reader.u32()?),
+        VAL_NATIVE => ImageValue::Native(reader.string()?),
+        tag => return Err(FormatError::Malformed(format!("unknown value tag {}", tag))),
+    })
+}
+
+fn read_opt(reader: &mut Reader) -> Result<bool, FormatError> {
+    match reader.u8()? {
+        0 => Ok(false),
+        1 => Ok(true),
+        flag => Err(FormatError::Malformed(format!("bad option flag {}", flag))),
+    }
+}
+
+fn read_list<T>(
+    reader: &mut Reader,
+    mut item: impl FnMut(&mut Reader) -> Result<T, FormatError>,
+) -> Result<Vec<T>, FormatError> {
+    let count = reader.u32()?;
+    // every item takes at least a byte, a corrupted count must not reserve gigabytes
+    let mut items = Vec::with_capacity(count.min(reader.remaining()));
+    for _ in 0..count {
+        items.push(item(reader)?);
+    }
+    Ok(items)
+}
+
+fn read_named_values(reader: &mut Reader) -> Result<Vec<(String, ImageValue)>, FormatError> {
+    read_list(reader, |r| Ok((r.string()?, read_value(r)?)))
+}
+
+fn decode(bytes: &[u8]) -> Result<DecodedImage, FormatError> {
+    let mut reader = Reader::new(image_payload(bytes)?);
+    let r = &mut reader;
+
+    let chunk_name = r.string()?;
+    let modules_loaded = r.u32()?;
+    let rng_state = u64::from_le_bytes(r.u64()?);
+    let functions = read_list(r, |r| {
+        let name = r.string()?;
+        let meta = r.function()?;
+        let children = read_list(r, |r| r.string())?;
+        let chunk_name = if read_opt(r)? {
+            Some(r.string()?)
+        } else {
+            None
+        };
+        Ok(ImageFunction {
+            name,
+            meta,
+            children,
+            chunk_name,
+        })
+    })?;
+    let objects = read_list(r, |r| {
+        Ok(match r.u8()? {
+            OBJ_TABLE => {
+                let metatable = if read_opt(r)? { Some(r.u32()?) } else { None };
+                ImageObject::Table {
+                    metatable,
+                    array: read_list(r, read_value)?,
+                    hash: read_list(r, |r| Ok((read_value(r)?, read_value(r)?)))?,
+                }
+            }
+            OBJ_FUNCTION => ImageObject::Function {
+                name: r.string()?,
+                upvalues: read_list(r, |r| r.u32())?,
+            },
+            OBJ_UPVALUE_OPEN => ImageObject::OpenUpValue(r.u32()?),
+            OBJ_UPVALUE_CLOSED => ImageObject::ClosedUpValue(read_value(r)?),
+            tag => {
+                return Err(FormatError::Malformed(format!(
+                    "unknown object tag {}",
+                    tag
+                )));
+            }
+        })
+    })?;
+    let globals = match read_value(r)? {
+        ImageValue::Table(id) => id,
+        _ => return Err(FormatError::Malformed("the globals are not a table".into())),
+    };
+    let stdlib_globals = read_named_values(r)?;
+    let value_stack = read_list(r, read_value)?;
+    let frames = read_list(r, |r| {
+        Ok(ImageFrame {
+            func_name: r.string()?,
+            base_offset: r.u32()?,
+            reg_count: r.u32()?,
+            pc: r.u32()?,
+            instr_pc: r.u32()?,
+            ret_dest: if read_opt(r)? { Some(r.u32()?) } else { None },
+            ret_count: r.u8()?,
+            upvalues: read_list(r, |r| r.u32())?,
+            out_upvalues: read_list(r, |r| Ok((r.u32()?, r.u32()?)))?,
+        })
+    })?;
+    let finalizable = read_list(r, |r| r.u32())?;
+    let to_finalize = read_list(r, |r| r.u32())?;
+
+    if r.remaining() != 0 {
+        return Err(FormatError::Malformed(format!(
+            "{} trailing bytes after the call stack",
+            r.remaining()
+        )));
+    }
+    Ok(DecodedImage {
+        chunk_name,
+        modules_loaded,
+        rng_state,
+        functions,
+        objects,
+        globals,
+        stdlib_globals,
+        value_stack,
+        frames,
+        finalizable,
+        to_finalize,
+    })
+}
+
+// the heap objects of an image while they are being rebuilt
+struct Rebuilt {
+    objects: Vec<LuaValue>,
+    upvalues: Vec<Option<Gc<LuaUpValue>>>,
+    natives: HashMap<String, LuaValue>,
+}
+
+fn malformed(msg: String) -> ErrorKind {
+    ErrorKind::SnapshotError(FormatError::Malformed(msg).to_string())
+}
+
+impl Rebuilt {
+    fn value(&self, vm: &mut VirtualMachine, value: &ImageValue) -> Result<LuaValue, ErrorKind> {
+        Ok(match value {
+            ImageValue::Plain(value) => *value,
+            ImageValue::Str(s) => {
+                LuaValue::String(vm.heap.alloc_str(s).ok_or(ErrorKind::OutOfMemory)?)
+            }
+            ImageValue::Table(id) => LuaValue::Table(self.table(*id)?),
+            ImageValue::Function(id) => match self.objects.get(*id) {
+                Some(value @ LuaValue::Function(_)) => *value,
+                _ => return Err(malformed(format!("object {} is not a function", id))),
+            },
+            ImageValue::Native(path) => *self.natives.get(path).ok_or_else(|| {
+                ErrorKind::SnapshotError(format!(
+                    "native function '{}' is not available in this VM",
+                    path
+                ))
+            })?,
+        })
+    }
+
+    fn table(&self, id: usize) -> Result<Gc<LuaTable>, ErrorKind> {
+        match self.objects.get(id) {
+            Some(LuaValue::Table(t)) => Ok(*t),
+            _ => Err(malformed(format!("object {} is not a table", id))),
+        }
+    }
+
+    fn upvalue(&self, id: usize) -> Result<Gc<LuaUpValue>, ErrorKind> {
+        self.upvalues
+            .get(id)
+            .copied()
+            .flatten()
+            .ok_or_else(|| malformed(format!("object {} is not an upvalue", id)))
+    }
+}
+
+impl VirtualMachine {
+    /// save the state of the running script: loaded functions, globals, the heap objects reachable from
+    /// them and the call stack, e.g. from a hook between two instructions or after a chunk returned
+    ///
+    /// natives, hooks, budgets and the configuration are not saved; a snapshot cannot be taken while a
+    /// native function or a coroutine runs, nor when a coroutine or userdata is reachable
+    pub fn snapshot(&self) -> Result<VmImage, VMError> {
+        let bytes = self.write_image().map_err(|kind| self.error(kind))?;
+        Ok(VmImage { bytes })
+    }
+
+    /// replace the script state of this VM with the one saved in `image`; every native function the
+    /// image refers to must be reachable from this VM's globals under the same path, the standard
+    /// library is loaded first if needed
+    ///
+    /// the image is decoded before the VM is touched, a VM that fails to restore is left reset
+    pub fn restore(&mut self, image: &VmImage) -> Result<(), VMError> {
+        let decoded = decode(&image.bytes)
+            .map_err(|e| self.error(ErrorKind::SnapshotError(e.to_string())))?;
+        if self.stdlib_globals.is_empty() {
+            self.load_standard_library();
+        }
+        // the host's natives may only be referenced from `globals`, which the reset drops
+        let natives = native_paths(self);
+        let roots = self.heap.roots.len();
+        self.heap
+            .roots
+            .extend(natives.iter().map(|(_, value)| *value));
+        self.reset(true);
+        self.heap.roots.truncate(roots);
+
+        let mut natives_by_path = HashMap::new();
+        for (path, value) in natives {
+            natives_by_path.entry(path).or_insert(value);
+        }
+        if let Err(kind) = self.rebuild(decoded, natives_by_path) {
+            let err = self.error(kind);
+            self.reset(true);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    fn write_image(&self) -> Result<Vec<u8>, ErrorKind> {
+        if self.current_thread.is_some() || !self.resumers.is_empty() {
+            return Err(ErrorKind::SnapshotError(
+                "cannot snapshot while a coroutine is running".into(),
+            ));
+        }
+        for (level, frame) in self.call_stack.iter().enumerate() {
+            // natives have no metadata, a Lua frame without a return register above the entry
+            // was called back from native code through call_value
+            if frame.meta.is_none() || (level > 0 && frame.ret_dest.is_none()) {
+                return Err(ErrorKind::SnapshotError(
+                    "cannot snapshot while a native function is running".into(),
+                ));
+            }
+        }
+
+        let mut writer = ImageWriter {
+            heap: &self.heap,
+            natives: HashMap::new(),
+            ids: HashMap::new(),
+            objects: Vec::new(),
+            written: 0,
+        };
+        for (path, value) in native_paths(self) {
+            if let Some(key) = native_key(&value) {
+                writer.natives.entry(key).or_insert(path);
+            }
+        }
+
+        // the roots go after the objects, but numbering the objects starts from them
+        let mut roots = Vec::new();
+        writer.value(&mut roots, &LuaValue::Table(self.globals))?;
+        let mut names: Vec<&String> = self.stdlib_globals.keys().collect();
+        names.sort();
+        write_u32(&mut roots, names.len());
+        for name in names {
+            write_str(&mut roots, name);
+            writer.value(&mut roots, &self.stdlib_globals[name])?;
+        }
+        write_u32(&mut roots, self.value_stack.values.len());
+        for value in &self.value_stack.values {
+            writer.value(&mut roots, value)?;
+        }
+        write_u32(&mut roots, self.call_stack.len());
+        for frame in &self.call_stack {
+            write_str(&mut roots, &frame.func_name);
+            write_u32(&mut roots, frame.base_offset);
+            write_u32(&mut roots, frame.reg_count);
+            write_u32(&mut roots, frame.pc);
+            write_u32(&mut roots, frame.instr_pc);
+            match frame.ret_dest {
+                Some(dest) => {
+                    roots.push(1);
+                    write_u32(&mut roots, dest);
+                }
+                None => roots.push(0),
+            }
+            roots.push(frame.ret_count);
+            write_u32(&mut roots, frame.upvalues.len());
+            for &upval in &frame.upvalues {
+                writer.upvalue(&mut roots, upval);
+            }
+            write_u32(&mut roots, frame.out_upvalues.len());
+            for &(slot, upval) in &frame.out_upvalues {
+                write_u32(&mut roots, slot);
+                writer.upvalue(&mut roots, upval);
+            }
+        }
+        for list in [&self.heap.finalizable, &self.heap.to_finalize] {
+            write_u32(&mut roots, list.len());
+            for &t in list {
+                let id = writer.object(ObjectRef::Table(t));
+                write_u32(&mut roots, id);
+            }
+        }
+        let mut objects = Vec::new();
+        writer.objects(&mut objects)?;
+
+        let mut out = Vec::with_capacity(HEADER_SIZE + 6 + objects.len() + roots.len());
+        out.extend_from_slice(SNAPSHOT_MAGIC);
+        out.extend_from_slice(&SNAPSHOT_FORMAT_VERSION.to_le_bytes());
+        write_header(&mut out);
+        write_str(&mut out, &self.chunk_name);
+        write_u32(&mut out, self.modules_loaded);
+        out.extend_from_slice(&self.rng.state().to_le_bytes());
+
+        let mut names: Vec<&String> = self.func_meta.keys().collect();
+        names.sort();
+        write_u32(&mut out, names.len());
+        for name in names {
+            let loaded = &self.func_meta[name];
+            write_str(&mut out, name);
+            write_function(&mut out, &loaded.meta);
+            write_u32(&mut out, loaded.children.len());
+            for &child in &loaded.children {
+                write_str(&mut out, self.protos.get(child).map_or("", |f| &f.name));
+            }
+            write_opt_str(&mut out, loaded.chunk_name.as_deref());
+        }
+
+        write_u32(&mut out, writer.objects.len());
+        out.extend_from_slice(&objects);
+        out.extend_from_slice(&roots);
+        Ok(out)
+    }
+
+    // the second half of `restore`, on a VM that was just reset
+    fn rebuild(
+        &mut self,
+        image: DecodedImage,
+        natives: HashMap<String, LuaValue>,
+    ) -> Result<(), ErrorKind> {
+        self.chunk_name = image.chunk_name.into();
+        self.modules_loaded = image.modules_loaded;
+        self.rng = LuaRng::from_state(image.rng_state);
+        let functions = image
+            .functions
+            .into_iter()
+            .map(|func| {
+                (
+                    func.name,
+                    Arc::new(func.meta),
+                    func.children,
+                    func.chunk_name.map(Rc::from),
+                )
+            })
+            .collect();
+        self.link_functions(functions);
+
+        // allocate every object first, the bodies may refer to any of them
+        let mut rebuilt = Rebuilt {
+            objects: Vec::with_capacity(image.objects.len()),
+            upvalues: Vec::with_capacity(image.objects.len()),
+            natives,
+        };
+        for (id, obj) in image.objects.iter().enumerate() {
+            let (value, upval) = match obj {
+                // filled in below like the others, `_G` already refers to it
+                ImageObject::Table { .. } if id == image.globals => {
+                    (LuaValue::Table(self.globals), None)
+                }
+                ImageObject::Table { .. } => {
+                    let t = self.heap.alloc_table(LuaTable::new());
+                    (LuaValue::Table(t.ok_or(ErrorKind::OutOfMemory)?), None)
+                }
+                ImageObject::Function { name, .. } => {
+                    let loaded = self.func_meta.get(name).cloned().ok_or_else(|| {
+                        malformed(format!("function '{}' is not part of the image", name))
+                    })?;
+                    let func = LFunction {
+                        proto: loaded,
+                        upvalues: vec![],
+                    };
+                    let f = self.heap.alloc_function(func);
+                    (LuaValue::Function(f.ok_or(ErrorKind::OutOfMemory)?), None)
+                }
+                ImageObject::OpenUpValue(_) | ImageObject::ClosedUpValue(_) => {
+                    let upval = self.heap.alloc_upvalue_object(LuaUpValue {
+                        value: LuaUpValueState::Closed(LuaValue::Nil),
+                        thread: None,
+                    });
+                    (LuaValue::Nil, Some(upval.ok_or(ErrorKind::OutOfMemory)?))
+                }
+            };
+            rebuilt.objects.push(value);
+            rebuilt.upvalues.push(upval);
+        }
+
+        for (id, obj) in image.objects.iter().enumerate() {
+            match obj {
+                ImageObject::Table {
+                    metatable,
+                    array,
+                    hash,
+                } => {
+                    let mut data = LuaTable::with_sizes(array.len(), hash.len());
+                    data.metatable = metatable.map(|mt| rebuilt.table(mt)).transpose()?;
+                    for value in array {
+                        data.array.push(rebuilt.value(self, value)?);
+                    }
+                    for (key, value) in hash {
+                        let key = rebuilt.value(self, key)?;
+                        let value = rebuilt.value(self, value)?;
+                        data.data.insert(key, value);
+                    }
+                    let t = rebuilt.table(id)?;
+                    *self.heap.data_mut(t) = data;
+                }
+                ImageObject::Function { upvalues, .. } => {
+                    let LuaValue::Function(f) = rebuilt.objects[id] else {
+                        unreachable!("functions were allocated as functions");
+                    };
+                    self.heap.data_mut(f).upvalues = upvalues
+                        .iter()
+                        .map(|&u| rebuilt.upvalue(u))
+                        .collect::<Result<_, _>>()?;
+                }
+                ImageObject::OpenUpValue(idx) => {
+                    let upval = rebuilt.upvalue(id)?;
+                    self.heap.data_mut(upval).value = LuaUpValueState::Open(*idx);
+                }
+                ImageObject::ClosedUpValue(value) => {
+                    let value = rebuilt.value(self, value)?;
+                    let upval = rebuilt.upvalue(id)?;
+                    self.heap.data_mut(upval).value = LuaUpValueState::Closed(value);
+                }
+            }
+        }
+
+        // a globals number past the objects, or one of another kind, did not reach the table above
+        rebuilt.table(image.globals)?;
+        self.stdlib_globals.clear();
+        for (name, value) in &image.stdlib_globals {
+            let value = rebuilt.value(self, value)?;
+            self.stdlib_globals.insert(name.clone(), value);
+        }
+
+        let mut stack = Vec::with_capacity(image.value_stack.len());
+        for value in &image.value_stack {
+            stack.push(rebuilt.value(self, value)?);
+        }
+        self.value_stack.values = stack;
+        for frame in image.frames {
+            let meta = self
+                .func_meta
+                .get(&frame.func_name)
+                .cloned()
+                .ok_or_else(|| {
+                    malformed(format!(
+                        "function '{}' of the call stack is not part of the image",
+                        frame.func_name
+                    ))
+                })?;
+            if frame.base_offset + frame.reg_count > self.value_stack.values.len() {
+                return Err(malformed(format!(
+                    "frame of '{}' lies outside the value stack",
+                    frame.func_name
+                )));
+            }
+            let upvalues = frame
+                .upvalues
+                .iter()
+                .map(|&u| rebuilt.upvalue(u))
+                .collect::<Result<_, _>>()?;
+            let mut restored = StackFrame::new(
+                frame.func_name.into(),
+                Some(meta),
+                frame.ret_dest,
+                frame.base_offset,
+                frame.reg_count,
+                upvalues,
+            );
+            restored.pc = frame.pc;
+            restored.instr_pc = frame.instr_pc;
+            restored.ret_count = frame.ret_count;
+            restored.out_upvalues = frame
+                .out_upvalues
+                .iter()
+                .map(|&(slot, u)| Ok((slot, rebuilt.upvalue(u)?)))
+                .collect::<Result<_, ErrorKind>>()?;
+            self.call_stack.push(restored);
+        }
+
+        for &id in &image.finalizable {
+            self.heap.finalizable.push(rebuilt.table(id)?);
+        }
+        for &id in &image.to_finalize {
+            self.heap.to_finalize.push(rebuilt.table(id)?);
+        }
+        Ok(())
+    }
+}
@@ -5,18 +5,31 @@
 //                and updated StackFrame to use base offsets into the global stack
 //                instead of maintaining its own local register array
 //      26-02-20: Added upvalues field to StackFrame to support closure captures
-use crate::common::object::{GCObject, LuaUpValue, LuaValue};
+//      26-02-24: Added instr_pc, the pc of the instruction being executed, used to map errors to source lines
+//      26-02-24: Added meta, the metadata of the function being executed, so dispatch does not look it up by name
+//      26-02-24: func_name is shared with the function's `LoadedFunction`, pushing a frame no longer copies it
+//      26-02-24: Added ret_count, the number of results the call that pushed the frame takes
+use crate::backend::vm::LoadedFunction;
+use crate::backend::vm::heap::Gc;
+use crate::common::object::{LuaUpValue, LuaValue};
+use std::rc::Rc;
 
 pub struct StackFrame {
-    pub func_name: String,
+    pub func_name: Rc<str>,
+    // None for the placeholder frame of a native function called from the host
+    pub meta: Option<Rc<LoadedFunction>>,
     pub base_offset: usize, // base offset in the global stack for this frame
     pub reg_count: usize,   // number of registers used by this frame
     pub pc: usize,
+    // pc of the instruction currently executing; `pc` may already have moved past it
+    pub instr_pc: usize,
     pub ret_dest: Option<usize>,
+    // the retc of the CALL that pushed this frame, how many results ret_dest takes
+    pub ret_count: u8,
     // upvalues **CAPUTURED** by the function prototype that this frame is executing
-    pub upvalues: Vec<*mut GCObject<LuaUpValue>>,
+    pub upvalues: Vec<Gc<LuaUpValue>>,
     // upvalues **ESCAPED** from this frame that need to be closed when this frame is popped
-    pub out_upvalues: Vec<(usize, *mut GCObject<LuaUpValue>)>,
+    pub out_upvalues: Vec<(usize, Gc<LuaUpValue>)>,
 }
 
 #[derive(Default)]
@@ -47,17 +60,21 @@ impl GlobalStack {
 
 impl StackFrame {
     pub fn new(
-        name: String,
+        name: Rc<str>,
+        meta: Option<Rc<LoadedFunction>>,
         ret_dest: Option<usize>,
         base_offset: usize,
         reg_count: usize,
-        upvalues: Vec<*mut GCObject<LuaUpValue>>,
+        upvalues: Vec<Gc<LuaUpValue>>,
     ) -> Self {
         Self {
             func_name: name,
+            meta,
             base_offset,
             pc: 0,
+            instr_pc: 0,
             ret_dest,
+            ret_count: 1,
             reg_count,
             upvalues,
             out_upvalues: vec![],
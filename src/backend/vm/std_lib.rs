@@ -1,33 +1,2718 @@
 use crate::backend::vm::VirtualMachine;
-use crate::backend::vm::error::VMError;
-use crate::common::object::LuaValue;
+use crate::backend::vm::error::{ErrorKind, VMError};
+use crate::backend::vm::heap::Gc;
+use crate::backend::vm::hook::{HookCallback, HookMask};
+use crate::backend::vm::pattern::{self, Capture, Match, PatternError};
+use crate::common::object::{
+    CFunction, CoroutineStatus, LuaCoroutine, LuaTable, LuaValue, compare_numbers, float_to_integer,
+};
+use std::cmp::Ordering;
+use std::io::{BufRead, Read, Write};
 
+// 原生函数的返回值约定：把结果依次压到全局栈顶，然后返回结果个数
+// handle_call / call_value 会从栈顶把它们取回来
+
+// read the i-th argument of the current native call, missing arguments are nil
+fn get_arg(vm: &VirtualMachine, argc: usize, i: usize) -> LuaValue {
+    if i < argc {
+        *vm.get_reg(i)
+    } else {
+        LuaValue::Nil
+    }
+}
+
+fn bad_argument(vm: &VirtualMachine, idx: usize, func: &str, msg: &str) -> VMError {
+    vm.error(ErrorKind::TypeError(format!(
+        "TypeMismatchException: bad argument #{} to '{}' ({})",
+        idx + 1,
+        func,
+        msg
+    )))
+}
+
+// may run a collection, heap values the caller holds outside the stack must be rooted
+fn new_string(vm: &mut VirtualMachine, s: String) -> Result<LuaValue, VMError> {
+    let ptr = vm.alloc(|heap| heap.alloc_str(&s))?;
+    Ok(LuaValue::String(ptr))
+}
+
+fn push_string(vm: &mut VirtualMachine, s: String) -> Result<(), VMError> {
+    let val = new_string(vm, s)?;
+    vm.value_stack.push(val);
+    Ok(())
+}
+
+// move `table` onto the heap, what it holds is rooted while a collection may run
+fn new_table(vm: &mut VirtualMachine, table: LuaTable) -> Result<Gc<LuaTable>, VMError> {
+    vm.with_roots(|vm| {
+        for (k, v) in table.iter() {
+            vm.root(k);
+            vm.root(*v);
+        }
+        if let Some(mt) = table.metatable {
+            vm.root(LuaValue::Table(mt));
+        }
+        let (array, hash) = (table.array.len(), table.data.len());
+        let ptr = vm.alloc(|heap| heap.alloc_table(LuaTable::with_sizes(array, hash)))?;
+        *vm.heap.data_mut(ptr) = table;
+        Ok(ptr)
+    })
+}
+
+// t[name] = value for a table the native function is filling in, `value` is rooted while the
+// key is interned
+fn set_field(
+    vm: &mut VirtualMachine,
+    table: Gc<LuaTable>,
+    name: &str,
+    value: LuaValue,
+) -> Result<(), VMError> {
+    let key = vm.with_roots(|vm| {
+        vm.root(value);
+        new_string(vm, name.to_string())
+    })?;
+    vm.heap.write_barrier(table, &key);
+    vm.heap.write_barrier(table, &value);
+    vm.heap.data_mut(table).set(key, value);
+    Ok(())
+}
+
+// number formatting follows the "%.14g" convention of the reference implementation,
+// floats that would read back as integers keep a ".0" suffix so the subtype stays visible
+pub(crate) fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        return if n.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+    if n.is_infinite() {
+        return if n > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    if n == n.trunc() && n.abs() < 1e15 {
+        return format!("{:.1}", n);
+    }
+
+    // let the formatter do the rounding, then read back the decimal exponent
+    let sci = format!("{:.13e}", n);
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+
+    if !(-4..14).contains(&exp) {
+        let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", mantissa, sign, exp.abs())
+    } else {
+        let decimals = (13 - exp).max(0) as usize;
+        let fixed = format!("{:.*}", decimals, n);
+        if fixed.contains('.') {
+            fixed
+                .trim_end_matches('0')
+                .trim_end_matches('.')
+                .to_string()
+        } else {
+            fixed
+        }
+    }
+}
+
+// convert a value to its string form without consulting metamethods
+pub(crate) fn raw_tostring(val: &LuaValue) -> String {
+    match val {
+        LuaValue::Nil => "nil".to_string(),
+        LuaValue::Boolean(b) => b.to_string(),
+        LuaValue::Number(n) => format_number(*n),
+        LuaValue::Integer(i) => i.to_string(),
+        LuaValue::String(ptr) => ptr.get().clone(),
+        LuaValue::Table(ptr) => format!("table: {:p}", *ptr),
+        LuaValue::Function(ptr) => format!("function: {:p}", *ptr),
+        LuaValue::CFunc(f) => format!("function: {:p}", *f as *const ()),
+        LuaValue::NativeClosure(ptr) => format!("function: {:p}", *ptr),
+        LuaValue::Coroutine(ptr) => format!("thread: {:p}", *ptr),
+        LuaValue::UserData(ptr) => format!("userdata: {:p}", *ptr),
+    }
+}
+
+// parse a numeral the way the lexer would, surrounding whitespace is allowed
+// accepts decimal floats with exponents and hexadecimal numerals (with optional fraction and 'p' exponent)
+pub(crate) fn str_to_number(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let (neg, body) = match s.as_bytes().first()? {
+        b'-' => (true, &s[1..]),
+        b'+' => (false, &s[1..]),
+        _ => (false, s),
+    };
+
+    let value = if body.starts_with("0x") || body.starts_with("0X") {
+        parse_hex_numeral(&body[2..])?
+    } else {
+        // rust accepts "inf" and "nan", lua does not
+        if body.is_empty()
+            || !body
+                .chars()
+                .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'))
+        {
+            return None;
+        }
+        body.parse::<f64>().ok()?
+    };
+
+    Some(if neg { -value } else { value })
+}
+
+// like str_to_number, but numerals without a fraction or exponent become integers;
+// decimal integers that overflow fall back to floats, hexadecimal ones wrap around
+pub(crate) fn str_to_value(s: &str) -> Option<LuaValue> {
+    let t = s.trim();
+    let neg = t.starts_with('-');
+    let body = t.strip_prefix(['-', '+']).unwrap_or(t);
+
+    if let Some(hex) = body.strip_prefix("0x").or_else(|| body.strip_prefix("0X"))
+        && !hex.is_empty()
+        && hex.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        let value = hex.chars().fold(0i64, |acc, c| {
+            acc.wrapping_mul(16)
+                .wrapping_add(c.to_digit(16).unwrap() as i64)
+        });
+        return Some(LuaValue::Integer(if neg {
+            value.wrapping_neg()
+        } else {
+            value
+        }));
+    }
+    if !body.is_empty()
+        && body.bytes().all(|c| c.is_ascii_digit())
+        && let Ok(int) = t.parse::<i64>()
+    {
+        return Some(LuaValue::Integer(int));
+    }
+    str_to_number(t).map(LuaValue::Number)
+}
+
+fn parse_hex_numeral(s: &str) -> Option<f64> {
+    let (digits, exp) = match s.find(['p', 'P']) {
+        Some(i) => (&s[..i], Some(s[i + 1..].parse::<i32>().ok()?)),
+        None => (s, None),
+    };
+    let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+    if int_part.is_empty() && frac_part.is_empty() {
+        return None;
+    }
+
+    let mut value = 0.0;
+    for c in int_part.chars() {
+        value = value * 16.0 + c.to_digit(16)? as f64;
+    }
+    let mut scale = 1.0 / 16.0;
+    for c in frac_part.chars() {
+        value += c.to_digit(16)? as f64 * scale;
+        scale /= 16.0;
+    }
+
+    Some(value * 2f64.powi(exp.unwrap_or(0)))
+}
+
+// parse an integer numeral in the given base (2..=36), as tonumber(s, base) does
+fn str_to_number_base(s: &str, base: u32) -> Option<i64> {
+    let s = s.trim();
+    let (neg, body) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    if body.is_empty() {
+        return None;
+    }
+
+    let mut value = 0i64;
+    for c in body.chars() {
+        value = value
+            .wrapping_mul(base as i64)
+            .wrapping_add(c.to_digit(base)? as i64);
+    }
+    Some(if neg { value.wrapping_neg() } else { value })
+}
+
+// print(...)
+// writes the arguments converted the way tostring does, separated by tabs, and a newline;
+// all of them are converted before anything is written, a __tostring may print itself
 pub fn lua_builtin_print(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let mut line = String::new();
+    for i in 0..argc {
+        if i > 0 {
+            line.push('\t');
+        }
+        let val = get_arg(vm, argc, i);
+        line.push_str(&tostring_value(vm, val)?);
+    }
+    line.push('\n');
+
+    // one write for the whole line, flushed so it is not overtaken by stderr
+    vm.output
+        .write_all(line.as_bytes())
+        .and_then(|_| vm.output.flush())
+        .map_err(|e| io_error(vm, "print", e))?;
+    Ok(0)
+}
+
+// tostring(v)
+// honors the __tostring metamethod, which must produce a string
+pub fn lua_builtin_tostring(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    if argc == 0 {
+        return Err(bad_argument(vm, 0, "tostring", "value expected"));
+    }
+    match get_arg(vm, argc, 0) {
+        // already a string, hand back the very same object
+        val @ LuaValue::String(_) => vm.value_stack.push(val),
+        val => {
+            let s = tostring_value(vm, val)?;
+            push_string(vm, s)?;
+        }
+    }
+    Ok(1)
+}
+
+// tonumber(v [, base])
+// without a base, numbers are returned as is and strings are parsed as numerals (hex included);
+// with a base, v must be a string holding an integer numeral in that base
+// anything that cannot be converted yields nil
+pub fn lua_builtin_tonumber(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    if argc == 0 {
+        return Err(bad_argument(vm, 0, "tonumber", "value expected"));
+    }
+    let val = get_arg(vm, argc, 0);
+    let base = get_arg(vm, argc, 1);
+
+    let res = match base {
+        LuaValue::Nil => match &val {
+            LuaValue::Number(_) | LuaValue::Integer(_) => Some(val),
+            LuaValue::String(ptr) => str_to_value(ptr.get()),
+            _ => None,
+        },
+        LuaValue::Number(_) | LuaValue::Integer(_) => {
+            let b = match base.as_integer() {
+                Some(b) if (2..=36).contains(&b) => b,
+                _ => return Err(bad_argument(vm, 1, "tonumber", "base out of range")),
+            };
+            match &val {
+                LuaValue::String(ptr) => {
+                    str_to_number_base(ptr.get(), b as u32).map(LuaValue::Integer)
+                }
+                _ => return Err(bad_argument(vm, 0, "tonumber", "string expected")),
+            }
+        }
+        _ => return Err(bad_argument(vm, 1, "tonumber", "number expected")),
+    };
+
+    vm.value_stack.push(res.unwrap_or(LuaValue::Nil));
+    Ok(1)
+}
+
+// setmetatable(t, mt), returns t
+pub fn lua_builtin_setmetatable(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let target = get_arg(vm, argc, 0);
+    let mt = get_arg(vm, argc, 1);
+
+    let LuaValue::Table(t_ptr) = target else {
+        return Err(bad_argument(vm, 0, "setmetatable", "table expected"));
+    };
+    let mt_ptr = match mt {
+        LuaValue::Table(ptr) => Some(ptr),
+        LuaValue::Nil => None,
+        _ => return Err(bad_argument(vm, 1, "setmetatable", "nil or table expected")),
+    };
+
+    vm.heap.write_barrier(t_ptr, &mt);
+    vm.heap.data_mut(t_ptr).metatable = mt_ptr;
+    // only a metatable that has __gc when it is set marks the table for finalization
+    if vm.get_metamethod(&target, "__gc").is_some() && !vm.heap.finalizable.contains(&t_ptr) {
+        vm.heap.finalizable.push(t_ptr);
+    }
+    vm.value_stack.push(target);
+    Ok(1)
+}
+
+// getmetatable(t)
+pub fn lua_builtin_getmetatable(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let metatable = match get_arg(vm, argc, 0) {
+        LuaValue::Table(ptr) => vm.heap.data(ptr).metatable,
+        LuaValue::UserData(ptr) => vm.heap.data(ptr).metatable,
+        _ => None,
+    };
+    let res = metatable.map(LuaValue::Table).unwrap_or(LuaValue::Nil);
+    vm.value_stack.push(res);
+    Ok(1)
+}
+
+// upper bound for the results of unpack, guards against accidental OOM
+const MAX_RESULTS: i64 = 1 << 20;
+
+fn check_table(
+    vm: &VirtualMachine,
+    argc: usize,
+    i: usize,
+    func: &str,
+) -> Result<Gc<LuaTable>, VMError> {
+    match get_arg(vm, argc, i) {
+        LuaValue::Table(ptr) => Ok(ptr),
+        other => Err(bad_argument(
+            vm,
+            i,
+            func,
+            &format!("table expected, got {}", type_name(&other)),
+        )),
+    }
+}
+
+// type(v), the name of the value's type as a string
+pub fn lua_builtin_type(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    if argc == 0 {
+        return Err(bad_argument(vm, 0, "type", "value expected"));
+    }
+    let name = type_name(&get_arg(vm, argc, 0));
+    push_string(vm, name.to_string())?;
+    Ok(1)
+}
+
+// rawget(t, k), t[k] without metamethods
+pub fn lua_builtin_rawget(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let table = check_table(vm, argc, 0, "rawget")?;
+    let res = vm.heap.data(table).get(&get_arg(vm, argc, 1));
+    vm.value_stack.push(res);
+    Ok(1)
+}
+
+// rawset(t, k, v), t[k] = v without metamethods, returns t
+pub fn lua_builtin_rawset(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let table = check_table(vm, argc, 0, "rawset")?;
+    let key = get_arg(vm, argc, 1);
+    let val = get_arg(vm, argc, 2);
+    match key {
+        LuaValue::Nil => return Err(bad_argument(vm, 1, "rawset", "index is nil")),
+        LuaValue::Number(n) if n.is_nan() => {
+            return Err(bad_argument(vm, 1, "rawset", "index is NaN"));
+        }
+        _ => {}
+    }
+    vm.heap.write_barrier(table, &key);
+    vm.heap.write_barrier(table, &val);
+    vm.heap.data_mut(table).set(key, val);
+    vm.value_stack.push(LuaValue::Table(table));
+    Ok(1)
+}
+
+// rawequal(a, b), a == b without __eq
+pub fn lua_builtin_rawequal(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    if argc < 2 {
+        return Err(bad_argument(vm, argc, "rawequal", "value expected"));
+    }
+    let res = get_arg(vm, argc, 0).raw_equal(&get_arg(vm, argc, 1));
+    vm.value_stack.push(LuaValue::Boolean(res));
+    Ok(1)
+}
+
+// rawlen(v), the length of a table or string without __len
+pub fn lua_builtin_rawlen(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let len = match get_arg(vm, argc, 0) {
+        LuaValue::Table(ptr) => vm.heap.data(ptr).len(),
+        LuaValue::String(ptr) => ptr.get().len(),
+        _ => return Err(bad_argument(vm, 0, "rawlen", "table or string expected")),
+    };
+    vm.value_stack.push(LuaValue::Integer(len as i64));
+    Ok(1)
+}
+
+// next(t [, k])
+// the key and value that follow k, the first pair for a nil k and nil after the last one;
+// the array part comes first, then the hash part in no particular order
+pub fn lua_builtin_next(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let table = check_table(vm, argc, 0, "next")?;
+    let key = get_arg(vm, argc, 1);
+    match vm.heap.data(table).next(&key) {
+        Some(Some((key, val))) => {
+            vm.value_stack.push(key);
+            vm.value_stack.push(val);
+            Ok(2)
+        }
+        Some(None) => {
+            vm.value_stack.push(LuaValue::Nil);
+            Ok(1)
+        }
+        None => Err(vm.error(ErrorKind::TypeError(format!(
+            "TypeMismatchException: invalid key to 'next' ({})",
+            raw_tostring(&key)
+        )))),
+    }
+}
+
+// select(n, ...)
+// the arguments from the n-th on, a negative n counts from the end; select('#', ...) counts them
+pub fn lua_builtin_select(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let count = argc.saturating_sub(1) as i64;
+    if let LuaValue::String(ptr) = get_arg(vm, argc, 0)
+        && ptr.get() == "#"
+    {
+        vm.value_stack.push(LuaValue::Integer(count));
+        return Ok(1);
+    }
+    let n = check_integer(vm, argc, 0, "select")?;
+    let first = match n {
+        n if n < 0 && -n <= count => count + n,
+        n if n > 0 => (n - 1).min(count),
+        _ => return Err(bad_argument(vm, 0, "select", "index out of range")),
+    };
+    for i in first..count {
+        let val = get_arg(vm, argc, i as usize + 1);
+        vm.value_stack.push(val);
+    }
+    Ok((count - first) as usize)
+}
+
+// unpack(t [, i [, j]]), the values t[i] .. t[j]; i defaults to 1 and j to #t
+pub fn lua_builtin_unpack(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let table = check_table(vm, argc, 0, "unpack")?;
+    let first = opt_integer(vm, argc, 1, "unpack", 1)?;
+    let last = match get_arg(vm, argc, 2) {
+        LuaValue::Nil => vm.heap.data(table).len() as i64,
+        _ => check_integer(vm, argc, 2, "unpack")?,
+    };
+    if first > last {
+        return Ok(0);
+    }
+    if last.saturating_sub(first) >= MAX_RESULTS {
+        return Err(vm.error(ErrorKind::TypeError(
+            "TypeMismatchException: too many results to unpack".into(),
+        )));
+    }
+    for i in first..=last {
+        let val = vm.heap.data(table).get(&LuaValue::Integer(i));
+        vm.value_stack.push(val);
+    }
+    Ok((last - first + 1) as usize)
+}
+
+// collectgarbage([opt [, arg]])
+// "collect" (default) runs a full collection, "step" one unit of work (a minor collection in
+// generational mode), "setpause" sets the threshold growth in percent and returns the old one;
+// "count" returns the heap size in kilobytes and a table of live objects by kind
+pub fn lua_builtin_collectgarbage(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let opt = match get_arg(vm, argc, 0) {
+        LuaValue::Nil => "collect".to_string(),
+        _ => check_string(vm, argc, 0, "collectgarbage")?,
+    };
+    match opt.as_str() {
+        "collect" => {
+            vm.collect_garbage();
+            vm.value_stack.push(LuaValue::Integer(0));
+            Ok(1)
+        }
+        "step" => {
+            vm.gc_step();
+            vm.value_stack.push(LuaValue::Boolean(true));
+            Ok(1)
+        }
+        "setpause" => {
+            let pause = opt_integer(vm, argc, 1, "collectgarbage", 200)?;
+            if pause < 0 {
+                return Err(bad_argument(
+                    vm,
+                    1,
+                    "collectgarbage",
+                    "pause must not be negative",
+                ));
+            }
+            let old = std::mem::replace(&mut vm.heap.pause, pause as usize);
+            vm.value_stack.push(LuaValue::Integer(old as i64));
+            Ok(1)
+        }
+        "count" => {
+            let stats = vm.heap.stats();
+            let table = new_table(vm, LuaTable::new())?;
+            vm.value_stack
+                .push(LuaValue::Number(stats.bytes as f64 / 1024.0));
+            vm.value_stack.push(LuaValue::Table(table));
+            for (name, count) in [
+                ("strings", stats.strings),
+                ("tables", stats.tables),
+                ("functions", stats.functions),
+                ("upvalues", stats.upvalues),
+                ("native_closures", stats.native_closures),
+                ("coroutines", stats.coroutines),
+                ("userdata", stats.userdata),
+                ("bytes", stats.bytes),
+            ] {
+                set_field(vm, table, name, LuaValue::Integer(count as i64))?;
+            }
+            Ok(2)
+        }
+        other => Err(bad_argument(
+            vm,
+            0,
+            "collectgarbage",
+            &format!("invalid option '{}'", other),
+        )),
+    }
+}
+
+// the Lua value a caught error is reported as: the value given to error(),
+// or the message of a VM error
+fn error_value(vm: &mut VirtualMachine, err: VMError) -> Result<LuaValue, VMError> {
+    match err.kind {
+        ErrorKind::LuaError(val) => Ok(val),
+        // a broken VM is not something a script can recover from,
+        // and a script must not be able to outlive the budget its host gave it or a stop it asked for
+        ErrorKind::InternalError(_) | ErrorKind::BudgetExceeded(_) | ErrorKind::Interrupted(_) => {
+            Err(err)
+        }
+        _ => new_string(vm, err.get_message()),
+    }
+}
+
+// pcall(f, ...)
+// returns true followed by every result of f, or false and the error value
+pub fn lua_builtin_pcall(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    if argc == 0 {
+        return Err(bad_argument(vm, 0, "pcall", "value expected"));
+    }
+    let func = get_arg(vm, argc, 0);
+    let args = (1..argc).map(|i| get_arg(vm, argc, i)).collect();
+
+    match vm.protected_call(func, args) {
+        Ok(results) => {
+            let count = results.len();
+            vm.value_stack.push(LuaValue::Boolean(true));
+            for res in results {
+                vm.value_stack.push(res);
+            }
+            Ok(count + 1)
+        }
+        Err(err) => {
+            let val = error_value(vm, err)?;
+            vm.value_stack.push(LuaValue::Boolean(false));
+            vm.value_stack.push(val);
+            Ok(2)
+        }
+    }
+}
+
+// xpcall(f, handler, ...)
+// like pcall, but on error the second result is handler(error value); the handler runs before the
+// failed call is unwound, so debug.traceback inside it still sees the failing frames;
+// an error inside the handler itself is returned as is
+pub fn lua_builtin_xpcall(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let func = get_arg(vm, argc, 0);
+    let handler = get_arg(vm, argc, 1);
+    if !matches!(
+        handler,
+        LuaValue::Function(_) | LuaValue::CFunc(_) | LuaValue::NativeClosure(_)
+    ) {
+        return Err(bad_argument(
+            vm,
+            1,
+            "xpcall",
+            &format!("function expected, got {}", type_name(&handler)),
+        ));
+    }
+    let args = (2..argc).map(|i| get_arg(vm, argc, i)).collect();
+
+    let result = vm.protected_call_with(func, args, |vm, err| {
+        let val = error_value(vm, err)?;
+        match vm.call_value(handler, vec![val]) {
+            Ok(res) => Ok(res),
+            Err(err) => error_value(vm, err),
+        }
+    });
+    match result {
+        Ok(results) => {
+            let count = results.len();
+            vm.value_stack.push(LuaValue::Boolean(true));
+            for res in results {
+                vm.value_stack.push(res);
+            }
+            Ok(count + 1)
+        }
+        Err(handled) => {
+            let handled = handled?;
+            vm.value_stack.push(LuaValue::Boolean(false));
+            vm.value_stack.push(handled);
+            Ok(2)
+        }
+    }
+}
+
+// error(value [, level])
+// raises any Lua value, it unwinds to the nearest pcall / xpcall or ends the program;
+// there is no line information to prepend, so the level is only validated
+pub fn lua_builtin_error(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    opt_integer(vm, argc, 1, "error", 1)?;
+    let val = get_arg(vm, argc, 0);
+    Err(vm.error(ErrorKind::LuaError(val)))
+}
+
+// assert(v [, message, ...])
+// returns all its arguments when v is truthy, raises message (default "assertion failed!") otherwise
+pub fn lua_builtin_assert(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    if argc == 0 {
+        return Err(bad_argument(vm, 0, "assert", "value expected"));
+    }
+    match get_arg(vm, argc, 0) {
+        LuaValue::Nil | LuaValue::Boolean(false) => {
+            let val = match get_arg(vm, argc, 1) {
+                LuaValue::Nil => new_string(vm, "assertion failed!".to_string())?,
+                msg => msg,
+            };
+            Err(vm.error(ErrorKind::LuaError(val)))
+        }
+        _ => {
+            for i in 0..argc {
+                let val = get_arg(vm, argc, i);
+                vm.value_stack.push(val);
+            }
+            Ok(argc)
+        }
+    }
+}
+
+// require(name)
+// loads a module once per VM (see `package`), later calls return the cached value
+pub fn lua_builtin_require(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let name = check_string(vm, argc, 0, "require")?;
+    let val = vm.require_module(&name)?;
+    vm.value_stack.push(val);
+    Ok(1)
+}
+
+// load(chunk [, chunkname [, mode [, env]]])
+// compiles a string, or the concatenated pieces a reader function returns until it gives nil or "",
+// into the running VM and returns its main chunk as a function; a chunk that does not compile
+// yields nil and the error message. Chunks run with the VM's globals, no other `env` can be given
+pub fn lua_builtin_load(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let chunk = get_arg(vm, argc, 0);
+    let source = match chunk {
+        LuaValue::String(ptr) => ptr.get().clone(),
+        LuaValue::Function(_) | LuaValue::CFunc(_) | LuaValue::NativeClosure(_) => {
+            let mut source = String::new();
+            loop {
+                match vm.call_value(chunk, vec![])? {
+                    LuaValue::Nil => break,
+                    LuaValue::String(piece) if piece.get().is_empty() => break,
+                    LuaValue::String(piece) => source.push_str(piece.get()),
+                    _ => {
+                        return load_failed(vm, "reader function must return a string".to_string());
+                    }
+                }
+            }
+            source
+        }
+        other => {
+            return Err(bad_argument(
+                vm,
+                0,
+                "load",
+                &format!("string expected, got {}", type_name(&other)),
+            ));
+        }
+    };
+    let chunk_name = match get_arg(vm, argc, 1) {
+        LuaValue::Nil => match chunk {
+            LuaValue::String(_) => default_chunk_name(&source),
+            _ => "=(load)".to_string(),
+        },
+        _ => check_string(vm, argc, 1, "load")?,
+    };
+    if let LuaValue::String(mode) = get_arg(vm, argc, 2)
+        && !mode.get().contains('t')
+    {
+        let msg = format!("attempt to load a text chunk (mode is '{}')", mode.get());
+        return load_failed(vm, msg);
+    }
+    if !matches!(get_arg(vm, argc, 3), LuaValue::Nil) {
+        return Err(bad_argument(
+            vm,
+            3,
+            "load",
+            "environments other than _G are not supported",
+        ));
+    }
+    load_source(vm, &source, &chunk_name)
+}
+
+// loadstring(s [, chunkname])
+// the Lua 5.1 name of load for strings
+pub fn lua_builtin_loadstring(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let source = check_string(vm, argc, 0, "loadstring")?;
+    let chunk_name = match get_arg(vm, argc, 1) {
+        LuaValue::Nil => default_chunk_name(&source),
+        _ => check_string(vm, argc, 1, "loadstring")?,
+    };
+    load_source(vm, &source, &chunk_name)
+}
+
+// dofile(filename)
+// compiles the file into the running VM and runs it, its first result is returned;
+// unlike load, a file that cannot be read or compiled raises an error
+pub fn lua_builtin_dofile(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let path = check_string(vm, argc, 0, "dofile")?;
+    let source = std::fs::read_to_string(&path)
+        .map_err(|e| vm.error(ErrorKind::IOError(format!("{}: {}", path, e))))?;
+    let funcs = vm.compile_source(&source).map_err(|detail| {
+        vm.error(ErrorKind::ModuleError(format!(
+            "error loading file '{}': {}",
+            path, detail
+        )))
+    })?;
+    let entry = vm.link_chunk(funcs, &path)?;
+    let val = vm.call_value(entry, vec![])?;
+    vm.value_stack.push(val);
+    Ok(1)
+}
+
+// `[string "first line..."]`, what errors in a chunk loaded from a string are reported under
+fn default_chunk_name(source: &str) -> String {
+    const MAX_SHOWN: usize = 40;
+    let line = source.lines().next().unwrap_or("");
+    let shown: String = line.chars().take(MAX_SHOWN).collect();
+    if shown.len() < source.len() {
+        format!("[string \"{}...\"]", shown)
+    } else {
+        format!("[string \"{}\"]", shown)
+    }
+}
+
+fn load_source(vm: &mut VirtualMachine, source: &str, chunk_name: &str) -> Result<usize, VMError> {
+    // "=name" is used as given, like in the reference implementation
+    let chunk_name = chunk_name.strip_prefix('=').unwrap_or(chunk_name);
+    match vm.compile_source(source) {
+        Ok(funcs) => {
+            let func = vm.link_chunk(funcs, chunk_name)?;
+            vm.value_stack.push(func);
+            Ok(1)
+        }
+        Err(detail) => load_failed(vm, format!("{}: {}", chunk_name, detail)),
+    }
+}
+
+// nil and the message, how load reports a chunk it cannot compile
+fn load_failed(vm: &mut VirtualMachine, msg: String) -> Result<usize, VMError> {
+    let msg = new_string(vm, msg)?;
+    vm.value_stack.push(LuaValue::Nil);
+    vm.value_stack.push(msg);
+    Ok(2)
+}
+
+// ---------------------------------------------------------------------------
+// string library
+// strings are treated as byte sequences, indices are 1-based and negative
+// indices count from the end, just like the reference implementation
+// ---------------------------------------------------------------------------
+
+pub const STRING_LIB: &[(&str, CFunction)] = &[
+    ("len", lua_string_len),
+    ("sub", lua_string_sub),
+    ("upper", lua_string_upper),
+    ("lower", lua_string_lower),
+    ("rep", lua_string_rep),
+    ("byte", lua_string_byte),
+    ("char", lua_string_char),
+    ("reverse", lua_string_reverse),
+    ("format", lua_string_format),
+    ("find", lua_string_find),
+    ("match", lua_string_match),
+    ("gmatch", lua_string_gmatch),
+    ("gsub", lua_string_gsub),
+];
+
+// upper bound for strings built by string.rep, guards against accidental OOM
+const MAX_STRING_SIZE: usize = 1 << 28;
+
+// string argument, numbers are converted the way tostring would
+fn check_string(vm: &VirtualMachine, argc: usize, i: usize, func: &str) -> Result<String, VMError> {
+    match get_arg(vm, argc, i) {
+        LuaValue::String(ptr) => Ok(ptr.get().clone()),
+        LuaValue::Number(n) => Ok(format_number(n)),
+        LuaValue::Integer(n) => Ok(n.to_string()),
+        other => Err(bad_argument(
+            vm,
+            i,
+            func,
+            &format!("string expected, got {}", type_name(&other)),
+        )),
+    }
+}
+
+// integer argument, numeric strings are accepted as well
+fn check_integer(vm: &VirtualMachine, argc: usize, i: usize, func: &str) -> Result<i64, VMError> {
+    let n = match get_arg(vm, argc, i) {
+        LuaValue::String(ptr) => str_to_value(ptr.get()),
+        val @ (LuaValue::Number(_) | LuaValue::Integer(_)) => Some(val),
+        _ => None,
+    };
+    match n {
+        Some(n) if let Some(int) = n.as_integer() => Ok(int),
+        Some(_) => Err(bad_argument(
+            vm,
+            i,
+            func,
+            "number has no integer representation",
+        )),
+        None => Err(bad_argument(vm, i, func, "number expected")),
+    }
+}
+
+fn opt_integer(
+    vm: &VirtualMachine,
+    argc: usize,
+    i: usize,
+    func: &str,
+    default: i64,
+) -> Result<i64, VMError> {
+    match get_arg(vm, argc, i) {
+        LuaValue::Nil => Ok(default),
+        _ => check_integer(vm, argc, i, func),
+    }
+}
+
+pub(crate) fn type_name(val: &LuaValue) -> &'static str {
+    match val {
+        LuaValue::Nil => "nil",
+        LuaValue::Boolean(_) => "boolean",
+        LuaValue::Number(_) | LuaValue::Integer(_) => "number",
+        LuaValue::String(_) => "string",
+        LuaValue::Table(_) => "table",
+        LuaValue::Function(_) | LuaValue::CFunc(_) | LuaValue::NativeClosure(_) => "function",
+        LuaValue::Coroutine(_) => "thread",
+        LuaValue::UserData(_) => "userdata",
+    }
+}
+
+// turn a possibly negative 1-based position into a 1-based position from the start,
+// the result may still be out of range and has to be clamped by the caller
+fn str_index(pos: i64, len: usize) -> i64 {
+    if pos >= 0 { pos } else { len as i64 + pos + 1 }
+}
+
+fn push_bytes(vm: &mut VirtualMachine, bytes: &[u8]) -> Result<(), VMError> {
+    let s = String::from_utf8_lossy(bytes);
+    let ptr = vm.alloc(|heap| heap.alloc_str(&s))?;
+    vm.value_stack.push(LuaValue::String(ptr));
+    Ok(())
+}
+
+// scratch buffers that grew beyond this are dropped instead of being kept around
+const SCRATCH_KEEP_CAPACITY: usize = 64 * 1024;
+
+// build a result in the VM's shared scratch buffer and push it as an interned string,
+// so the only allocation is the heap string itself (none if it is already interned)
+fn push_built<F>(vm: &mut VirtualMachine, build: F) -> Result<(), VMError>
+where
+    F: FnOnce(&mut VirtualMachine, &mut Vec<u8>) -> Result<(), VMError>,
+{
+    let mut buf = std::mem::take(&mut vm.scratch);
+    buf.clear();
+    let res = build(vm, &mut buf).and_then(|_| push_bytes(vm, &buf));
+    if buf.capacity() <= SCRATCH_KEEP_CAPACITY {
+        vm.scratch = buf;
+    }
+    res
+}
+
+// string.len(s)
+pub fn lua_string_len(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let s = check_string(vm, argc, 0, "len")?;
+    vm.value_stack.push(LuaValue::Integer(s.len() as i64));
+    Ok(1)
+}
+
+// string.sub(s, i [, j])
+pub fn lua_string_sub(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let s = check_string(vm, argc, 0, "sub")?;
+    let len = s.len();
+    let start = str_index(check_integer(vm, argc, 1, "sub")?, len).max(1);
+    let end = str_index(opt_integer(vm, argc, 2, "sub", -1)?, len).min(len as i64);
+
+    if start > end {
+        push_string(vm, String::new())?;
+    } else {
+        push_bytes(vm, &s.as_bytes()[start as usize - 1..end as usize])?;
+    }
+    Ok(1)
+}
+
+// string.upper(s), only ASCII letters are affected
+pub fn lua_string_upper(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let s = check_string(vm, argc, 0, "upper")?;
+    push_built(vm, |_, buf| {
+        buf.extend(s.bytes().map(|b| b.to_ascii_uppercase()));
+        Ok(())
+    })?;
+    Ok(1)
+}
+
+// string.lower(s), only ASCII letters are affected
+pub fn lua_string_lower(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let s = check_string(vm, argc, 0, "lower")?;
+    push_built(vm, |_, buf| {
+        buf.extend(s.bytes().map(|b| b.to_ascii_lowercase()));
+        Ok(())
+    })?;
+    Ok(1)
+}
+
+// string.reverse(s), byte-wise like the reference implementation
+pub fn lua_string_reverse(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let s = check_string(vm, argc, 0, "reverse")?;
+    push_built(vm, |_, buf| {
+        buf.extend(s.bytes().rev());
+        Ok(())
+    })?;
+    Ok(1)
+}
+
+// string.rep(s, n [, sep])
+pub fn lua_string_rep(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let s = check_string(vm, argc, 0, "rep")?;
+    let n = check_integer(vm, argc, 1, "rep")?.max(0) as usize;
+    let sep = match get_arg(vm, argc, 2) {
+        LuaValue::Nil => String::new(),
+        _ => check_string(vm, argc, 2, "rep")?,
+    };
+
+    let total = (s.len() + sep.len())
+        .checked_mul(n)
+        .filter(|&size| size <= MAX_STRING_SIZE)
+        .ok_or_else(|| bad_argument(vm, 1, "rep", "resulting string too large"))?;
+
+    push_built(vm, |_, buf| {
+        buf.reserve(total);
+        for i in 0..n {
+            if i > 0 {
+                buf.extend_from_slice(sep.as_bytes());
+            }
+            buf.extend_from_slice(s.as_bytes());
+        }
+        Ok(())
+    })?;
+    Ok(1)
+}
+
+// string.byte(s [, i [, j]]), returns the codes of s[i..j]
+pub fn lua_string_byte(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let s = check_string(vm, argc, 0, "byte")?;
+    let len = s.len();
+    let i = opt_integer(vm, argc, 1, "byte", 1)?;
+    let start = str_index(i, len).max(1);
+    let end = str_index(opt_integer(vm, argc, 2, "byte", i)?, len).min(len as i64);
+
+    if start > end {
+        return Ok(0);
+    }
+    for &b in &s.as_bytes()[start as usize - 1..end as usize] {
+        vm.value_stack.push(LuaValue::Integer(b as i64));
+    }
+    Ok((end - start + 1) as usize)
+}
+
+// string.char(...), builds a string from byte codes
+pub fn lua_string_char(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    push_built(vm, |vm, buf| {
+        for i in 0..argc {
+            let c = check_integer(vm, argc, i, "char")?;
+            if !(0..=255).contains(&c) {
+                return Err(bad_argument(vm, i, "char", "value out of range"));
+            }
+            buf.push(c as u8);
+        }
+        Ok(())
+    })?;
+    Ok(1)
+}
+
+// tostring semantics for print and the builtins that stringify their arguments, __tostring included
+fn tostring_value(vm: &mut VirtualMachine, val: LuaValue) -> Result<String, VMError> {
+    match vm.get_metamethod(&val, "__tostring") {
+        Some(handler) => match vm.call_value(handler, vec![val])? {
+            LuaValue::String(ptr) => Ok(ptr.get().clone()),
+            _ => Err(vm.error(ErrorKind::TypeError(
+                "TypeMismatchException: '__tostring' must return a string".into(),
+            ))),
+        },
+        None => Ok(raw_tostring(&val)),
+    }
+}
+
+// flags, width and precision of a single string.format conversion
+#[derive(Default)]
+struct FormatSpec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl FormatSpec {
+    // pad a converted value to the field width, zero padding goes after the sign
+    fn pad(&self, buf: &mut Vec<u8>, body: &str, numeric: bool) {
+        let fill = self.width.saturating_sub(body.len());
+        if self.left {
+            buf.extend_from_slice(body.as_bytes());
+            buf.extend(std::iter::repeat_n(b' ', fill));
+        } else if self.zero && numeric {
+            let sign_len = body
+                .bytes()
+                .take_while(|b| matches!(b, b'-' | b'+' | b' '))
+                .count();
+            buf.extend_from_slice(&body.as_bytes()[..sign_len]);
+            buf.extend(std::iter::repeat_n(b'0', fill));
+            buf.extend_from_slice(&body.as_bytes()[sign_len..]);
+        } else {
+            buf.extend(std::iter::repeat_n(b' ', fill));
+            buf.extend_from_slice(body.as_bytes());
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+}
+
+// "%.{p}e" with a C style exponent (at least two digits, always signed)
+fn format_exp(n: f64, precision: usize, upper: bool) -> String {
+    let sci = format!("{:.*e}", precision, n.abs());
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let e = if upper { 'E' } else { 'e' };
+    let sign = if exp < 0 { '-' } else { '+' };
+    format!("{}{}{}{:02}", mantissa, e, sign, exp.abs())
+}
+
+// "%.{p}g": the shorter of %e and %f, trailing zeros removed unless alt is set
+fn format_general(n: f64, precision: usize, alt: bool, upper: bool) -> String {
+    let p = precision.max(1);
+    let sci = format!("{:.*e}", p - 1, n.abs());
+    let exp: i32 = sci.split_once('e').unwrap().1.parse().unwrap();
+
+    let body = if exp < -4 || exp >= p as i32 {
+        format_exp(n, p - 1, upper)
+    } else {
+        format!("{:.*}", (p as i32 - 1 - exp) as usize, n.abs())
+    };
+    if alt || !body.contains('.') {
+        return body;
+    }
+
+    // strip zeros of the fraction, keeping any exponent
+    let (digits, exp_part) = match body.find(['e', 'E']) {
+        Some(i) => body.split_at(i),
+        None => (body.as_str(), ""),
+    };
+    let digits = digits.trim_end_matches('0').trim_end_matches('.');
+    format!("{}{}", digits, exp_part)
+}
+
+// append s as a Lua string literal that reads back to the same value
+fn quote_string(buf: &mut Vec<u8>, s: &[u8]) {
+    buf.push(b'"');
+    for (i, &c) in s.iter().enumerate() {
+        match c {
+            b'"' | b'\\' => {
+                buf.push(b'\\');
+                buf.push(c);
+            }
+            b'\n' => buf.extend_from_slice(b"\\\n"),
+            b'\r' => buf.extend_from_slice(b"\\r"),
+            0 => {
+                // a following digit would be read as part of the escape
+                if s.get(i + 1).is_some_and(u8::is_ascii_digit) {
+                    buf.extend_from_slice(b"\\000");
+                } else {
+                    buf.extend_from_slice(b"\\0");
+                }
+            }
+            c if c.is_ascii_control() => {
+                if s.get(i + 1).is_some_and(u8::is_ascii_digit) {
+                    buf.extend_from_slice(format!("\\{:03}", c).as_bytes());
+                } else {
+                    buf.extend_from_slice(format!("\\{}", c).as_bytes());
+                }
+            }
+            c => buf.push(c),
+        }
+    }
+    buf.push(b'"');
+}
+
+// string.format(fmt, ...)
+// supports %d %i %u %c %x %X %o %e %E %f %F %g %G %q %s and %%,
+// with the flags '-', '+', ' ', '#', '0', a field width and a precision
+pub fn lua_string_format(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let fmt = check_string(vm, argc, 0, "format")?;
+
+    push_built(vm, |vm, buf| {
+        let fmt = fmt.as_bytes();
+        let mut arg = 0;
+        let mut i = 0;
+        while i < fmt.len() {
+            let c = fmt[i];
+            i += 1;
+            if c != b'%' {
+                buf.push(c);
+                continue;
+            }
+            if fmt.get(i) == Some(&b'%') {
+                buf.push(b'%');
+                i += 1;
+                continue;
+            }
+
+            let mut spec = FormatSpec::default();
+            while let Some(&flag) = fmt.get(i) {
+                match flag {
+                    b'-' => spec.left = true,
+                    b'+' => spec.plus = true,
+                    b' ' => spec.space = true,
+                    b'#' => spec.alt = true,
+                    b'0' => spec.zero = true,
+                    _ => break,
+                }
+                i += 1;
+            }
+            let digits = |i: &mut usize| {
+                let start = *i;
+                while *i < fmt.len() && fmt[*i].is_ascii_digit() && *i - start < 2 {
+                    *i += 1;
+                }
+                std::str::from_utf8(&fmt[start..*i])
+                    .unwrap()
+                    .parse::<usize>()
+                    .unwrap_or(0)
+            };
+            spec.width = digits(&mut i);
+            if fmt.get(i) == Some(&b'.') {
+                i += 1;
+                spec.precision = Some(digits(&mut i));
+            }
+
+            let Some(&conv) = fmt.get(i) else {
+                return Err(bad_argument(
+                    vm,
+                    0,
+                    "format",
+                    "invalid conversion '%' to format string",
+                ));
+            };
+            i += 1;
+            arg += 1;
+            if arg >= argc && conv != b'%' {
+                return Err(bad_argument(vm, arg, "format", "no value"));
+            }
+
+            match conv {
+                b'd' | b'i' | b'u' => {
+                    let n = check_integer(vm, argc, arg, "format")?;
+                    let mut digits = n.unsigned_abs().to_string();
+                    if let Some(p) = spec.precision {
+                        if p == 0 && n == 0 {
+                            digits.clear();
+                        } else if digits.len() < p {
+                            digits.insert_str(0, &"0".repeat(p - digits.len()));
+                        }
+                        spec.zero = false;
+                    }
+                    spec.pad(buf, &format!("{}{}", spec.sign(n < 0), digits), true);
+                }
+                b'x' | b'X' | b'o' => {
+                    let n = check_integer(vm, argc, arg, "format")?;
+                    let (mut body, prefix) = match conv {
+                        b'x' => (format!("{:x}", n), "0x"),
+                        b'X' => (format!("{:X}", n), "0X"),
+                        _ => (format!("{:o}", n), "0"),
+                    };
+                    if let Some(p) = spec.precision
+                        && body.len() < p
+                    {
+                        body.insert_str(0, &"0".repeat(p - body.len()));
+                    }
+                    if spec.alt && n != 0 && !body.starts_with(prefix) {
+                        body.insert_str(0, prefix);
+                    }
+                    spec.pad(buf, &body, true);
+                }
+                b'c' => {
+                    let n = check_integer(vm, argc, arg, "format")?;
+                    spec.pad(buf, "", false);
+                    buf.push(n as u8);
+                }
+                b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+                    let n = check_number(vm, argc, arg, "format")?;
+                    let precision = spec.precision.unwrap_or(6);
+                    let upper = conv.is_ascii_uppercase();
+                    let body = if !n.is_finite() {
+                        let s = if n.is_nan() { "nan" } else { "inf" };
+                        spec.zero = false;
+                        if upper {
+                            s.to_uppercase()
+                        } else {
+                            s.to_string()
+                        }
+                    } else {
+                        match conv {
+                            b'e' | b'E' => format_exp(n, precision, upper),
+                            b'f' | b'F' => {
+                                let mut s = format!("{:.*}", precision, n.abs());
+                                if spec.alt && precision == 0 {
+                                    s.push('.');
+                                }
+                                s
+                            }
+                            _ => format_general(n, precision, spec.alt, upper),
+                        }
+                    };
+                    let negative = n.is_sign_negative() && !n.is_nan();
+                    spec.pad(buf, &format!("{}{}", spec.sign(negative), body), true);
+                }
+                b'q' => match get_arg(vm, argc, arg) {
+                    LuaValue::String(ptr) => quote_string(buf, ptr.get().as_bytes()),
+                    LuaValue::Integer(i) => buf.extend_from_slice(i.to_string().as_bytes()),
+                    LuaValue::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => {
+                        buf.extend_from_slice(format_number(n).as_bytes())
+                    }
+                    LuaValue::Number(n) => {
+                        buf.extend_from_slice(format_general(n, 17, false, false).as_bytes())
+                    }
+                    v @ (LuaValue::Nil | LuaValue::Boolean(_)) => {
+                        buf.extend_from_slice(raw_tostring(&v).as_bytes())
+                    }
+                    _ => {
+                        return Err(bad_argument(vm, arg, "format", "value has no literal form"));
+                    }
+                },
+                b's' => {
+                    let val = get_arg(vm, argc, arg);
+                    let s = tostring_value(vm, val)?;
+                    let s = match spec.precision {
+                        Some(p) if p < s.len() => {
+                            String::from_utf8_lossy(&s.as_bytes()[..p]).into_owned()
+                        }
+                        _ => s,
+                    };
+                    spec.pad(buf, &s, false);
+                }
+                other => {
+                    return Err(bad_argument(
+                        vm,
+                        0,
+                        "format",
+                        &format!("invalid conversion '%{}' to format string", other as char),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    })?;
+    Ok(1)
+}
+
+fn pattern_error(vm: &VirtualMachine, func: &str, msg: PatternError) -> VMError {
+    vm.error(ErrorKind::TypeError(format!(
+        "PatternException: {} in '{}'",
+        msg, func
+    )))
+}
+
+// push one capture of a match as a Lua value
+fn push_capture(vm: &mut VirtualMachine, src: &[u8], cap: &Capture) -> Result<(), VMError> {
+    match cap {
+        Capture::Slice(start, end) => push_bytes(vm, &src[*start..*end]),
+        Capture::Position(pos) => {
+            vm.value_stack.push(LuaValue::Integer(*pos as i64));
+            Ok(())
+        }
+    }
+}
+
+fn capture_value(vm: &mut VirtualMachine, src: &[u8], cap: &Capture) -> Result<LuaValue, VMError> {
+    push_capture(vm, src, cap)?;
+    Ok(vm.value_stack.values.pop().unwrap())
+}
+
+// start position for find/match, None if it lies past the end of the subject
+fn start_position(init: i64, len: usize) -> Option<usize> {
+    let init = str_index(init, len).max(1);
+    (init <= len as i64 + 1).then(|| init as usize - 1)
+}
+
+// string.find(s, pattern [, init [, plain]])
+// returns the 1-based start and end of the match followed by its captures
+pub fn lua_string_find(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let s = check_string(vm, argc, 0, "find")?;
+    let pattern = check_string(vm, argc, 1, "find")?;
+    let init = opt_integer(vm, argc, 2, "find", 1)?;
+    let plain = get_arg(vm, argc, 3).is_truthy();
+
+    let Some(init) = start_position(init, s.len()) else {
+        vm.value_stack.push(LuaValue::Nil);
+        return Ok(1);
+    };
+
+    let (src, pat) = (s.as_bytes(), pattern.as_bytes());
+    let found = if plain || pattern::is_plain(pat) {
+        let offset = if pat.is_empty() {
+            Some(0)
+        } else {
+            src[init..].windows(pat.len()).position(|w| w == pat)
+        };
+        offset.map(|offset| Match {
+            start: init + offset,
+            end: init + offset + pat.len(),
+            captures: vec![],
+        })
+    } else {
+        pattern::find(src, pat, init).map_err(|e| pattern_error(vm, "find", e))?
+    };
+
+    match found {
+        Some(m) => {
+            vm.value_stack.push(LuaValue::Integer((m.start + 1) as i64));
+            vm.value_stack.push(LuaValue::Integer(m.end as i64));
+            for cap in &m.captures {
+                push_capture(vm, src, cap)?;
+            }
+            Ok(2 + m.captures.len())
+        }
+        None => {
+            vm.value_stack.push(LuaValue::Nil);
+            Ok(1)
+        }
+    }
+}
+
+// string.match(s, pattern [, init])
+// returns the captures of the first match, or the whole match if there are none
+pub fn lua_string_match(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let s = check_string(vm, argc, 0, "match")?;
+    let pattern = check_string(vm, argc, 1, "match")?;
+    let init = opt_integer(vm, argc, 2, "match", 1)?;
+
+    let found = match start_position(init, s.len()) {
+        Some(init) => pattern::find(s.as_bytes(), pattern.as_bytes(), init)
+            .map_err(|e| pattern_error(vm, "match", e))?,
+        None => None,
+    };
+
+    match found {
+        Some(m) => {
+            let values = m.values();
+            for cap in &values {
+                push_capture(vm, s.as_bytes(), cap)?;
+            }
+            Ok(values.len())
+        }
+        None => {
+            vm.value_stack.push(LuaValue::Nil);
+            Ok(1)
+        }
+    }
+}
+
+// string.gmatch(s, pattern)
+// native functions cannot hold state, so the iterator is a table carrying
+// { s, pattern, next offset, end of last match } whose metatable makes it callable
+pub fn lua_string_gmatch(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let s = check_string(vm, argc, 0, "gmatch")?;
+    let pattern = check_string(vm, argc, 1, "gmatch")?;
+
+    vm.with_roots(|vm| {
+        let mut state = LuaTable::new();
+        let s = new_string(vm, s)?;
+        state.set(LuaValue::Integer(1), vm.root(s));
+        state.set(LuaValue::Integer(2), new_string(vm, pattern)?);
+        state.set(LuaValue::Integer(3), LuaValue::Integer(0));
+        state.set(LuaValue::Integer(4), LuaValue::Integer(-1));
+
+        push_iterator(vm, state, lua_string_gmatch_step)
+    })
+}
+
+// push `state` as a callable iterator: its metatable's __call is `step`,
+// which receives the state table as its first argument
+fn push_iterator(
+    vm: &mut VirtualMachine,
+    mut state: LuaTable,
+    step: CFunction,
+) -> Result<usize, VMError> {
+    vm.with_roots(|vm| {
+        for (k, v) in state.iter() {
+            vm.root(k);
+            vm.root(*v);
+        }
+        let call_key = new_string(vm, "__call".to_string())?;
+        let mut mt = LuaTable::new();
+        mt.set(call_key, LuaValue::CFunc(step));
+
+        state.metatable = Some(new_table(vm, mt)?);
+        let state_ptr = new_table(vm, state)?;
+        vm.value_stack.push(LuaValue::Table(state_ptr));
+        Ok(1)
+    })
+}
+
+// __call of the gmatch iterator, the state table is the first argument
+fn lua_string_gmatch_step(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let LuaValue::Table(state) = get_arg(vm, argc, 0) else {
+        return Err(bad_argument(vm, 0, "gmatch", "iterator state expected"));
+    };
+    let field = |i: i64| vm.heap.data(state).get(&LuaValue::Integer(i));
+    let (LuaValue::String(s), LuaValue::String(p), LuaValue::Integer(pos), LuaValue::Integer(last)) =
+        (field(1), field(2), field(3), field(4))
+    else {
+        return Err(bad_argument(vm, 0, "gmatch", "corrupted iterator state"));
+    };
+    let (s, p) = (s.get().clone(), p.get().clone());
+
+    let mut start = pos as usize;
+    while start <= s.len() {
+        let found = pattern::match_at(s.as_bytes(), p.as_bytes(), start)
+            .map_err(|e| pattern_error(vm, "gmatch", e))?;
+        if let Some(m) = found
+            && m.end as i64 != last
+        {
+            let data = vm.heap.data_mut(state);
+            data.set(LuaValue::Integer(3), LuaValue::Integer(m.end as i64));
+            data.set(LuaValue::Integer(4), LuaValue::Integer(m.end as i64));
+            let values = m.values();
+            for cap in &values {
+                push_capture(vm, s.as_bytes(), cap)?;
+            }
+            return Ok(values.len());
+        }
+        start += 1;
+    }
+
+    vm.heap
+        .data_mut(state)
+        .set(LuaValue::Integer(3), LuaValue::Integer(start as i64));
+    vm.value_stack.push(LuaValue::Nil);
+    Ok(1)
+}
+
+// string.gsub(s, pattern, repl [, n])
+// repl may be a string with %0-%9 references, a table indexed by the first capture,
+// or a function called with the captures; nil or false keeps the original match
+pub fn lua_string_gsub(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let s = check_string(vm, argc, 0, "gsub")?;
+    let pattern = check_string(vm, argc, 1, "gsub")?;
+    let repl = get_arg(vm, argc, 2);
+    let max_n = match get_arg(vm, argc, 3) {
+        LuaValue::Nil => i64::MAX,
+        _ => check_integer(vm, argc, 3, "gsub")?,
+    };
+    if !matches!(
+        repl,
+        LuaValue::String(_)
+            | LuaValue::Number(_)
+            | LuaValue::Integer(_)
+            | LuaValue::Table(_)
+            | LuaValue::Function(_)
+            | LuaValue::CFunc(_)
+            | LuaValue::NativeClosure(_)
+    ) {
+        return Err(bad_argument(
+            vm,
+            2,
+            "gsub",
+            &format!("string/function/table expected, got {}", type_name(&repl)),
+        ));
+    }
+
+    let src = s.as_bytes();
+    let (anchor, pat) = match pattern.as_bytes() {
+        [b'^', rest @ ..] => (true, rest),
+        pat => (false, pat),
+    };
+
+    let mut count = 0;
+    push_built(vm, |vm, out| {
+        let mut pos = 0;
+        let mut last_match = None;
+        while count < max_n {
+            let found =
+                pattern::match_at(src, pat, pos).map_err(|e| pattern_error(vm, "gsub", e))?;
+            match found {
+                Some(m) if Some(m.end) != last_match => {
+                    count += 1;
+                    gsub_add_value(vm, out, src, &m, &repl)?;
+                    pos = m.end;
+                    last_match = Some(m.end);
+                }
+                _ if pos < src.len() => {
+                    out.push(src[pos]);
+                    pos += 1;
+                }
+                _ => break,
+            }
+            if anchor {
+                break;
+            }
+        }
+        out.extend_from_slice(&src[pos.min(src.len())..]);
+        Ok(())
+    })?;
+
+    vm.value_stack.push(LuaValue::Integer(count));
+    Ok(2)
+}
+
+fn gsub_add_value(
+    vm: &mut VirtualMachine,
+    out: &mut Vec<u8>,
+    src: &[u8],
+    m: &Match,
+    repl: &LuaValue,
+) -> Result<(), VMError> {
+    let whole = &src[m.start..m.end];
+    let replacement = match repl {
+        LuaValue::String(_) | LuaValue::Number(_) | LuaValue::Integer(_) => {
+            let template = raw_tostring(repl);
+            let template = template.as_bytes();
+            let mut i = 0;
+            while i < template.len() {
+                let c = template[i];
+                i += 1;
+                if c != b'%' {
+                    out.push(c);
+                    continue;
+                }
+                match template.get(i) {
+                    Some(b'%') => out.push(b'%'),
+                    Some(b'0') => out.extend_from_slice(whole),
+                    Some(d @ b'1'..=b'9') => {
+                        let idx = (d - b'1') as usize;
+                        let cap = match m.values().get(idx) {
+                            Some(cap) => cap.clone(),
+                            None => {
+                                return Err(pattern_error(
+                                    vm,
+                                    "gsub",
+                                    format!("invalid capture index %{}", idx + 1),
+                                ));
+                            }
+                        };
+                        match cap {
+                            Capture::Slice(start, end) => out.extend_from_slice(&src[start..end]),
+                            Capture::Position(p) => out.extend_from_slice(p.to_string().as_bytes()),
+                        }
+                    }
+                    _ => {
+                        return Err(pattern_error(
+                            vm,
+                            "gsub",
+                            "invalid use of '%' in replacement string".into(),
+                        ));
+                    }
+                }
+                i += 1;
+            }
+            return Ok(());
+        }
+        LuaValue::Table(ptr) => {
+            let key = capture_value(vm, src, &m.values()[0])?;
+            vm.heap.data(*ptr).get(&key)
+        }
+        _ => {
+            let mut args = Vec::new();
+            for cap in &m.values() {
+                args.push(capture_value(vm, src, cap)?);
+            }
+            vm.call_value(*repl, args)?
+        }
+    };
+
+    match replacement {
+        LuaValue::Nil | LuaValue::Boolean(false) => out.extend_from_slice(whole),
+        LuaValue::String(_) | LuaValue::Number(_) | LuaValue::Integer(_) => {
+            out.extend_from_slice(raw_tostring(&replacement).as_bytes())
+        }
+        other => {
+            return Err(vm.error(ErrorKind::TypeError(format!(
+                "TypeMismatchException: invalid replacement value (a {})",
+                type_name(&other)
+            ))));
+        }
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// math library
+// ---------------------------------------------------------------------------
+
+pub const MATH_LIB: &[(&str, CFunction)] = &[
+    ("floor", lua_math_floor),
+    ("ceil", lua_math_ceil),
+    ("abs", lua_math_abs),
+    ("sqrt", lua_math_sqrt),
+    ("exp", lua_math_exp),
+    ("log", lua_math_log),
+    ("sin", lua_math_sin),
+    ("cos", lua_math_cos),
+    ("tan", lua_math_tan),
+    ("fmod", lua_math_fmod),
+    ("max", lua_math_max),
+    ("min", lua_math_min),
+    ("random", lua_math_random),
+    ("randomseed", lua_math_randomseed),
+    ("type", lua_math_type),
+    ("tointeger", lua_math_tointeger),
+];
+
+pub const MATH_CONSTANTS: &[(&str, f64)] = &[("huge", f64::INFINITY), ("pi", std::f64::consts::PI)];
+
+// xorshift64* generator behind math.random, seeded from the clock unless
+// the embedder (VirtualMachine::set_random_seed) or the script (math.randomseed) sets a seed
+pub struct LuaRng {
+    state: u64,
+}
+
+impl LuaRng {
+    pub fn new(seed: Option<u64>) -> Self {
+        match seed {
+            Some(seed) => Self::with_seed(seed),
+            None => Self::from_time(),
+        }
+    }
+
+    pub fn from_time() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self::with_seed(nanos)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        // splitmix the seed so that small seeds do not start in a weak state, the state must not be 0
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        Self {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    // the raw generator state, a VM snapshot saves it so math.random continues where it was
+    pub(crate) fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub(crate) fn from_state(state: u64) -> Self {
+        Self {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// number argument, numeric strings are accepted as well
+fn check_number(vm: &VirtualMachine, argc: usize, i: usize, func: &str) -> Result<f64, VMError> {
+    check_numeric(vm, argc, i, func).map(|n| n.as_float().unwrap())
+}
+
+// like check_number, but keeps the subtype of the argument
+fn check_numeric(
+    vm: &VirtualMachine,
+    argc: usize,
+    i: usize,
+    func: &str,
+) -> Result<LuaValue, VMError> {
+    let got = match get_arg(vm, argc, i) {
+        n @ (LuaValue::Number(_) | LuaValue::Integer(_)) => return Ok(n),
+        LuaValue::String(ptr) => match str_to_value(ptr.get()) {
+            Some(n) => return Ok(n),
+            None => "string",
+        },
+        _ if i >= argc => "no value",
+        other => type_name(&other),
+    };
+    Err(bad_argument(
+        vm,
+        i,
+        func,
+        &format!("number expected, got {}", got),
+    ))
+}
+
+fn push_number(vm: &mut VirtualMachine, n: f64) -> Result<usize, VMError> {
+    vm.value_stack.push(LuaValue::Number(n));
+    Ok(1)
+}
+
+fn push_integer(vm: &mut VirtualMachine, n: i64) -> Result<usize, VMError> {
+    vm.value_stack.push(LuaValue::Integer(n));
+    Ok(1)
+}
+
+// floor and ceil produce integers whenever the result fits, floats otherwise
+fn push_rounded(vm: &mut VirtualMachine, n: f64) -> Result<usize, VMError> {
+    match float_to_integer(n) {
+        Some(i) => push_integer(vm, i),
+        None => push_number(vm, n),
+    }
+}
+
+pub fn lua_math_floor(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    match check_numeric(vm, argc, 0, "floor")? {
+        LuaValue::Integer(i) => push_integer(vm, i),
+        n => push_rounded(vm, n.as_float().unwrap().floor()),
+    }
+}
+
+pub fn lua_math_ceil(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    match check_numeric(vm, argc, 0, "ceil")? {
+        LuaValue::Integer(i) => push_integer(vm, i),
+        n => push_rounded(vm, n.as_float().unwrap().ceil()),
+    }
+}
+
+pub fn lua_math_abs(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    match check_numeric(vm, argc, 0, "abs")? {
+        LuaValue::Integer(i) => push_integer(vm, i.wrapping_abs()),
+        n => push_number(vm, n.as_float().unwrap().abs()),
+    }
+}
+
+pub fn lua_math_sqrt(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let n = check_number(vm, argc, 0, "sqrt")?;
+    push_number(vm, n.sqrt())
+}
+
+pub fn lua_math_exp(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let n = check_number(vm, argc, 0, "exp")?;
+    push_number(vm, n.exp())
+}
+
+// math.log(x [, base]), natural logarithm by default
+pub fn lua_math_log(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let n = check_number(vm, argc, 0, "log")?;
+    let res = match get_arg(vm, argc, 1) {
+        LuaValue::Nil => n.ln(),
+        _ => {
+            let base = check_number(vm, argc, 1, "log")?;
+            if base == 2.0 {
+                n.log2()
+            } else if base == 10.0 {
+                n.log10()
+            } else {
+                n.ln() / base.ln()
+            }
+        }
+    };
+    push_number(vm, res)
+}
+
+pub fn lua_math_sin(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let n = check_number(vm, argc, 0, "sin")?;
+    push_number(vm, n.sin())
+}
+
+pub fn lua_math_cos(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let n = check_number(vm, argc, 0, "cos")?;
+    push_number(vm, n.cos())
+}
+
+pub fn lua_math_tan(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let n = check_number(vm, argc, 0, "tan")?;
+    push_number(vm, n.tan())
+}
+
+// math.fmod(x, y), the remainder rounds towards zero unlike the % operator
+// two integers give an integer remainder, a zero divisor is then an error
+pub fn lua_math_fmod(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let x = check_numeric(vm, argc, 0, "fmod")?;
+    let y = check_numeric(vm, argc, 1, "fmod")?;
+    match (x, y) {
+        (LuaValue::Integer(_), LuaValue::Integer(0)) => Err(bad_argument(vm, 1, "fmod", "zero")),
+        (LuaValue::Integer(a), LuaValue::Integer(b)) => push_integer(vm, a.wrapping_rem(b)),
+        (x, y) => push_number(vm, x.as_float().unwrap() % y.as_float().unwrap()),
+    }
+}
+
+// the winning argument is returned as is, so its subtype survives
+pub fn lua_math_max(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let mut best = check_numeric(vm, argc, 0, "max")?;
+    for i in 1..argc {
+        let n = check_numeric(vm, argc, i, "max")?;
+        if compare_numbers(&n, &best) == Some(Ordering::Greater) {
+            best = n;
+        }
+    }
+    vm.value_stack.push(best);
+    Ok(1)
+}
+
+pub fn lua_math_min(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let mut best = check_numeric(vm, argc, 0, "min")?;
+    for i in 1..argc {
+        let n = check_numeric(vm, argc, i, "min")?;
+        if compare_numbers(&n, &best) == Some(Ordering::Less) {
+            best = n;
+        }
+    }
+    vm.value_stack.push(best);
+    Ok(1)
+}
+
+// math.type(x), "integer" or "float" for numbers, nil for anything else
+pub fn lua_math_type(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    if argc == 0 {
+        return Err(bad_argument(vm, 0, "type", "value expected"));
+    }
+    match get_arg(vm, argc, 0) {
+        LuaValue::Integer(_) => push_string(vm, "integer".to_string())?,
+        LuaValue::Number(_) => push_string(vm, "float".to_string())?,
+        _ => vm.value_stack.push(LuaValue::Nil),
+    }
+    Ok(1)
+}
+
+// math.tointeger(x), floats with an exact integer value convert, anything else is nil
+pub fn lua_math_tointeger(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let res = match get_arg(vm, argc, 0) {
+        n @ (LuaValue::Number(_) | LuaValue::Integer(_)) => {
+            n.as_integer().map_or(LuaValue::Nil, LuaValue::Integer)
+        }
+        _ => LuaValue::Nil,
+    };
+    vm.value_stack.push(res);
+    Ok(1)
+}
+
+// math.random([m [, n]])
+// no arguments: a float in [0, 1); one argument: an integer in [1, m]; two: an integer in [m, n]
+pub fn lua_math_random(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let (low, high) = match argc {
+        0 => {
+            let r = vm.rng.next_f64();
+            return push_number(vm, r);
+        }
+        1 => (1, check_integer(vm, argc, 0, "random")?),
+        2 => (
+            check_integer(vm, argc, 0, "random")?,
+            check_integer(vm, argc, 1, "random")?,
+        ),
+        _ => {
+            return Err(vm.error(ErrorKind::InvalidCall(
+                "IllegalInvocationException: wrong number of arguments to 'random'".into(),
+            )));
+        }
+    };
+    if low > high {
+        return Err(bad_argument(vm, argc - 1, "random", "interval is empty"));
+    }
+
+    // the span may be the whole u64 range, in which case any value will do
+    let span = (high as i128 - low as i128 + 1) as u128;
+    let r = vm.rng.next_u64() as u128;
+    let offset = if span > u64::MAX as u128 { r } else { r % span };
+    push_integer(vm, (low as i128 + offset as i128) as i64)
+}
+
+// math.randomseed([x]), without an argument the generator is reseeded from the clock
+pub fn lua_math_randomseed(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    vm.rng = match get_arg(vm, argc, 0) {
+        LuaValue::Nil => LuaRng::from_time(),
+        _ => LuaRng::with_seed(check_number(vm, argc, 0, "randomseed")?.to_bits()),
+    };
+    Ok(0)
+}
+
+// ---------------------------------------------------------------------------
+// os library
+// ---------------------------------------------------------------------------
+
+pub const OS_LIB: &[(&str, CFunction)] = &[
+    ("time", lua_os_time),
+    ("clock", lua_os_clock),
+    ("date", lua_os_date),
+];
+
+// there is no time zone database, local time is UTC: os.date("%H") and os.date("!%H") agree
+// and os.time{...} reads its fields as UTC
+
+// days since 1970-01-01 of a proleptic gregorian date, month is 1-based
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// inverse of days_from_civil: (year, month, day)
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (
+        if month <= 2 {
+            yoe + era * 400 + 1
+        } else {
+            yoe + era * 400
+        },
+        month,
+        day,
+    )
+}
+
+// broken-down time, the fields of os.date("*t")
+struct DateTime {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    min: i64,
+    sec: i64,
+    // 1 = sunday
+    wday: i64,
+    // 1 = january 1st
+    yday: i64,
+}
+
+impl DateTime {
+    fn from_timestamp(t: i64) -> Self {
+        let days = t.div_euclid(86400);
+        let secs = t.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year,
+            month,
+            day,
+            hour: secs / 3600,
+            min: secs % 3600 / 60,
+            sec: secs % 60,
+            // 1970-01-01 was a thursday
+            wday: (days + 4).rem_euclid(7) + 1,
+            yday: days - days_from_civil(year, 1, 1) + 1,
+        }
+    }
+}
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+fn unix_time() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// integer field of an os.time / os.date table, nil falls back to `default`
+fn date_field(
+    vm: &mut VirtualMachine,
+    table: Gc<LuaTable>,
+    name: &str,
+    default: Option<i64>,
+) -> Result<i64, VMError> {
+    let key = new_string(vm, name.to_string())?;
+    let val = vm.heap.data(table).get(&key);
+    match (val, default) {
+        (LuaValue::Integer(i), _) => Ok(i),
+        (LuaValue::Number(n), _) if n.fract() == 0.0 && n.abs() < 2f64.powi(53) => Ok(n as i64),
+        (LuaValue::Nil, Some(default)) => Ok(default),
+        (LuaValue::Nil, None) => Err(bad_argument(
+            vm,
+            0,
+            "time",
+            &format!("field '{}' missing in date table", name),
+        )),
+        _ => Err(bad_argument(
+            vm,
+            0,
+            "time",
+            &format!("field '{}' is not an integer", name),
+        )),
+    }
+}
+
+// os.time([table]), seconds since the epoch; out of range fields are normalized
+pub fn lua_os_time(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let table = match get_arg(vm, argc, 0) {
+        LuaValue::Nil => return push_integer(vm, unix_time()),
+        LuaValue::Table(ptr) => ptr,
+        other => {
+            return Err(bad_argument(
+                vm,
+                0,
+                "time",
+                &format!("table expected, got {}", type_name(&other)),
+            ));
+        }
+    };
+
+    let year = date_field(vm, table, "year", None)?;
+    let month = date_field(vm, table, "month", None)?;
+    let day = date_field(vm, table, "day", None)?;
+    let hour = date_field(vm, table, "hour", Some(12))?;
+    let min = date_field(vm, table, "min", Some(0))?;
+    let sec = date_field(vm, table, "sec", Some(0))?;
+
+    // fold months into years first, days/hours/... simply add up
+    let months = year * 12 + month - 1;
+    let days = days_from_civil(months.div_euclid(12), months.rem_euclid(12) + 1, 1) + day - 1;
+    push_integer(vm, days * 86400 + hour * 3600 + min * 60 + sec)
+}
+
+// os.clock(), seconds of (wall clock) time the VM has been running
+pub fn lua_os_clock(vm: &mut VirtualMachine, _argc: usize) -> Result<usize, VMError> {
+    let elapsed = vm.started.elapsed().as_secs_f64();
+    push_number(vm, elapsed)
+}
+
+// os.date([format [, time]])
+// "*t" returns a table, otherwise the strftime conversions
+// %a %A %b %B %c %d %H %I %j %m %M %p %S %w %x %X %y %Y %% are supported
+pub fn lua_os_date(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let format = match get_arg(vm, argc, 0) {
+        LuaValue::Nil => "%c".to_string(),
+        _ => check_string(vm, argc, 0, "date")?,
+    };
+    let t = match get_arg(vm, argc, 1) {
+        LuaValue::Nil => unix_time(),
+        _ => check_integer(vm, argc, 1, "date")?,
+    };
+    let format = format.strip_prefix('!').unwrap_or(&format);
+    let dt = DateTime::from_timestamp(t);
+
+    if format.starts_with("*t") {
+        let table = new_table(vm, LuaTable::new())?;
+        vm.value_stack.push(LuaValue::Table(table));
+        let fields = [
+            ("year", dt.year),
+            ("month", dt.month),
+            ("day", dt.day),
+            ("hour", dt.hour),
+            ("min", dt.min),
+            ("sec", dt.sec),
+            ("wday", dt.wday),
+            ("yday", dt.yday),
+        ];
+        for (name, value) in fields {
+            set_field(vm, table, name, LuaValue::Integer(value))?;
+        }
+        set_field(vm, table, "isdst", LuaValue::Boolean(false))?;
+        return Ok(1);
+    }
+
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let Some(conv) = chars.next() else {
+            return Err(bad_argument(
+                vm,
+                0,
+                "date",
+                "invalid conversion specifier '%'",
+            ));
+        };
+        let hour12 = if dt.hour % 12 == 0 { 12 } else { dt.hour % 12 };
+        let weekday = WEEKDAYS[dt.wday as usize - 1];
+        let month = MONTHS[dt.month as usize - 1];
+        let piece = match conv {
+            'a' => weekday[..3].to_string(),
+            'A' => weekday.to_string(),
+            'b' => month[..3].to_string(),
+            'B' => month.to_string(),
+            'c' => format!(
+                "{} {} {:>2} {:02}:{:02}:{:02} {}",
+                &weekday[..3],
+                &month[..3],
+                dt.day,
+                dt.hour,
+                dt.min,
+                dt.sec,
+                dt.year
+            ),
+            'd' => format!("{:02}", dt.day),
+            'H' => format!("{:02}", dt.hour),
+            'I' => format!("{:02}", hour12),
+            'j' => format!("{:03}", dt.yday),
+            'm' => format!("{:02}", dt.month),
+            'M' => format!("{:02}", dt.min),
+            'p' => if dt.hour < 12 { "AM" } else { "PM" }.to_string(),
+            'S' => format!("{:02}", dt.sec),
+            'w' => (dt.wday - 1).to_string(),
+            'x' => format!("{:02}/{:02}/{:02}", dt.month, dt.day, dt.year % 100),
+            'X' => format!("{:02}:{:02}:{:02}", dt.hour, dt.min, dt.sec),
+            'y' => format!("{:02}", dt.year % 100),
+            'Y' => dt.year.to_string(),
+            '%' => "%".to_string(),
+            other => {
+                return Err(bad_argument(
+                    vm,
+                    0,
+                    "date",
+                    &format!("invalid conversion specifier '%{}'", other),
+                ));
+            }
+        };
+        out.push_str(&piece);
+    }
+    push_string(vm, out)?;
+    Ok(1)
+}
+
+// ---------------------------------------------------------------------------
+// io library
+// ---------------------------------------------------------------------------
+
+pub const IO_LIB: &[(&str, CFunction)] = &[
+    ("write", lua_io_write),
+    ("read", lua_io_read),
+    ("lines", lua_io_lines),
+];
+
+fn io_error(vm: &VirtualMachine, func: &str, err: std::io::Error) -> VMError {
+    vm.error(ErrorKind::IOError(format!("'{}' failed: {}", func, err)))
+}
+
+// io.write(...), strings and numbers are written to the VM's `output` as they are
+pub fn lua_io_write(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
     for i in 0..argc {
-        // 现在调用约定改了，
-        // 参数全都是全局栈上面，get_reg 自带一层当前栈帧偏移，所以直接用 get_reg 就行了
-        // - Li
-        let val = vm.get_reg(i);
-
-        let s = match val {
-            LuaValue::Nil => "nil".to_string(),
-            LuaValue::Boolean(b) => b.to_string(),
-            LuaValue::Number(n) => n.to_string(),
-            LuaValue::String(ptr) => unsafe { (*(*ptr)).data.clone() },
-            LuaValue::Table(ptr) => format!("table: {:p}", *ptr),
-            LuaValue::Function(ptr) => format!("function: {:p}", *ptr),
-            LuaValue::CFunc(f) => format!("function: {:p}", f),
-            _ => "unknown".to_string(),
+        let s = check_string(vm, argc, i, "write")?;
+        vm.output
+            .write_all(s.as_bytes())
+            .map_err(|e| io_error(vm, "write", e))?;
+    }
+    Ok(0)
+}
+
+// one line of input without the line terminator (with it if `keep_newline`), None at end of input
+fn read_line(
+    vm: &mut VirtualMachine,
+    func: &str,
+    keep_newline: bool,
+) -> Result<Option<String>, VMError> {
+    let mut line = String::new();
+    match vm.input.read_line(&mut line) {
+        Ok(0) => Ok(None),
+        Ok(_) => {
+            if !keep_newline && line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            Ok(Some(line))
+        }
+        Err(e) => Err(io_error(vm, func, e)),
+    }
+}
+
+// a numeral, leading whitespace is skipped; None if the input does not start with one
+fn read_number(vm: &mut VirtualMachine) -> Result<Option<LuaValue>, VMError> {
+    let mut numeral = Vec::new();
+    let mut skipping = true;
+    loop {
+        let next = match vm.input.fill_buf() {
+            Ok(buf) => buf.first().copied(),
+            Err(e) => return Err(io_error(vm, "read", e)),
         };
+        let Some(b) = next else { break };
+        if skipping && b.is_ascii_whitespace() {
+            vm.input.consume(1);
+            continue;
+        }
+        skipping = false;
+        if !(b.is_ascii_hexdigit() || matches!(b, b'x' | b'X' | b'.' | b'+' | b'-' | b'p' | b'P'))
+            || numeral.len() >= 200
+        {
+            break;
+        }
+        numeral.push(b);
+        vm.input.consume(1);
+    }
+    Ok(str_to_value(&String::from_utf8_lossy(&numeral)))
+}
 
-        print!("{}", s);
+// io.read(...), formats: "l" (default), "L", "n", "a" or a byte count
+// reads from the VM's `input`; one result per format, nil once the input is exhausted
+pub fn lua_io_read(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    // a prompt written with io.write must be visible before we block
+    let _ = vm.output.flush();
 
-        if i < argc - 1 {
-            print!("\t");
+    let count = argc.max(1);
+    for i in 0..count {
+        let format = match get_arg(vm, argc, i) {
+            LuaValue::Nil => "l".to_string(),
+            LuaValue::Number(_) | LuaValue::Integer(_) => {
+                let n = check_integer(vm, argc, i, "read")?.max(0) as usize;
+                let mut bytes = Vec::new();
+                (&mut vm.input)
+                    .take(n as u64)
+                    .read_to_end(&mut bytes)
+                    .map_err(|e| io_error(vm, "read", e))?;
+                if bytes.is_empty() && n > 0 {
+                    vm.value_stack.push(LuaValue::Nil);
+                    return Ok(i + 1);
+                }
+                push_bytes(vm, &bytes)?;
+                continue;
+            }
+            _ => check_string(vm, argc, i, "read")?,
+        };
+
+        let read = match format.trim_start_matches('*').chars().next() {
+            Some('l') => read_line(vm, "read", false)?
+                .map(|l| new_string(vm, l))
+                .transpose()?,
+            Some('L') => read_line(vm, "read", true)?
+                .map(|l| new_string(vm, l))
+                .transpose()?,
+            Some('n') => read_number(vm)?,
+            Some('a') => {
+                let mut all = String::new();
+                vm.input
+                    .read_to_string(&mut all)
+                    .map_err(|e| io_error(vm, "read", e))?;
+                Some(new_string(vm, all)?)
+            }
+            _ => return Err(bad_argument(vm, i, "read", "invalid format")),
+        };
+
+        match read {
+            Some(val) => vm.value_stack.push(val),
+            // like the reference implementation, stop at the first failing format
+            None => {
+                vm.value_stack.push(LuaValue::Nil);
+                return Ok(i + 1);
+            }
         }
     }
+    Ok(count)
+}
+
+// io.lines([filename]), a callable iterator over the lines of the file or of the VM's input
+pub fn lua_io_lines(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let mut state = LuaTable::new();
+    if !matches!(get_arg(vm, argc, 0), LuaValue::Nil) {
+        let filename = check_string(vm, argc, 0, "lines")?;
+        let content = std::fs::read_to_string(&filename)
+            .map_err(|e| vm.error(ErrorKind::IOError(format!("{}: {}", filename, e))))?;
+        // [1] = file content, [2] = offset of the next line
+        state.set(LuaValue::Integer(1), new_string(vm, content)?);
+        state.set(LuaValue::Integer(2), LuaValue::Integer(0));
+    }
+    push_iterator(vm, state, lua_io_lines_step)
+}
 
-    println!();
+// __call of the io.lines iterator, the state table is the first argument
+fn lua_io_lines_step(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let LuaValue::Table(state) = get_arg(vm, argc, 0) else {
+        return Err(bad_argument(vm, 0, "lines", "iterator state expected"));
+    };
+    let data = vm.heap.data(state);
+    let (content, pos) = (
+        data.get(&LuaValue::Integer(1)),
+        data.get(&LuaValue::Integer(2)),
+    );
 
+    let line = match (content, pos) {
+        (LuaValue::Nil, _) => read_line(vm, "lines", false)?,
+        (LuaValue::String(content), LuaValue::Integer(pos)) => {
+            let content = content.get();
+            let pos = pos as usize;
+            if pos >= content.len() {
+                None
+            } else {
+                let end = content[pos..].find('\n').map_or(content.len(), |i| pos + i);
+                let next = (end + 1).min(content.len());
+                vm.heap
+                    .data_mut(state)
+                    .set(LuaValue::Integer(2), LuaValue::Integer(next as i64));
+                Some(content[pos..end].trim_end_matches('\r').to_string())
+            }
+        }
+        _ => return Err(bad_argument(vm, 0, "lines", "corrupted iterator state")),
+    };
+
+    match line {
+        Some(line) => push_string(vm, line)?,
+        None => vm.value_stack.push(LuaValue::Nil),
+    }
+    Ok(1)
+}
+
+// ---------------------------------------------------------------------------
+// coroutine library
+// ---------------------------------------------------------------------------
+
+pub const COROUTINE_LIB: &[(&str, CFunction)] = &[
+    ("create", lua_coroutine_create),
+    ("resume", lua_coroutine_resume),
+    ("yield", lua_coroutine_yield),
+    ("status", lua_coroutine_status),
+    ("running", lua_coroutine_running),
+    ("isyieldable", lua_coroutine_isyieldable),
+    ("wrap", lua_coroutine_wrap),
+];
+
+fn check_coroutine(
+    vm: &VirtualMachine,
+    argc: usize,
+    i: usize,
+    func: &str,
+) -> Result<Gc<LuaCoroutine>, VMError> {
+    match get_arg(vm, argc, i) {
+        LuaValue::Coroutine(ptr) => Ok(ptr),
+        other => Err(bad_argument(
+            vm,
+            i,
+            func,
+            &format!("coroutine expected, got {}", type_name(&other)),
+        )),
+    }
+}
+
+fn check_body(vm: &VirtualMachine, argc: usize, func: &str) -> Result<LuaValue, VMError> {
+    match get_arg(vm, argc, 0) {
+        body @ LuaValue::Function(_) => Ok(body),
+        other => Err(bad_argument(
+            vm,
+            0,
+            func,
+            &format!("Lua function expected, got {}", type_name(&other)),
+        )),
+    }
+}
+
+// coroutine.create(f)
+pub fn lua_coroutine_create(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let body = check_body(vm, argc, "create")?;
+    let co = vm.create_coroutine(body)?;
+    vm.value_stack.push(co);
+    Ok(1)
+}
+
+// coroutine.resume(co, ...)
+// returns true followed by the values yielded or returned, or false and the error value
+pub fn lua_coroutine_resume(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let co = check_coroutine(vm, argc, 0, "resume")?;
+    let args = (1..argc).map(|i| get_arg(vm, argc, i)).collect();
+
+    match vm.resume_coroutine(co, args) {
+        Ok(values) => {
+            let count = values.len();
+            vm.value_stack.push(LuaValue::Boolean(true));
+            for val in values {
+                vm.value_stack.push(val);
+            }
+            Ok(count + 1)
+        }
+        Err(err) => {
+            let val = error_value(vm, err)?;
+            vm.value_stack.push(LuaValue::Boolean(false));
+            vm.value_stack.push(val);
+            Ok(2)
+        }
+    }
+}
+
+// coroutine.yield(...)
+// the arguments become the results of the resume, the results of the next resume
+// are what this call returns
+pub fn lua_coroutine_yield(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let values = (0..argc).map(|i| get_arg(vm, argc, i)).collect();
+    vm.request_yield(values)?;
+    Ok(0)
+}
+
+// coroutine.status(co): "suspended", "running", "normal" or "dead"
+pub fn lua_coroutine_status(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let co = check_coroutine(vm, argc, 0, "status")?;
+    let status = match vm.heap.data(co).status {
+        CoroutineStatus::Suspended => "suspended",
+        CoroutineStatus::Running => "running",
+        CoroutineStatus::Normal => "normal",
+        CoroutineStatus::Dead => "dead",
+    };
+    push_string(vm, status.to_string())?;
+    Ok(1)
+}
+
+// coroutine.running(), nil on the main thread
+pub fn lua_coroutine_running(vm: &mut VirtualMachine, _argc: usize) -> Result<usize, VMError> {
+    let current = vm
+        .current_thread
+        .map(LuaValue::Coroutine)
+        .unwrap_or(LuaValue::Nil);
+    vm.value_stack.push(current);
+    Ok(1)
+}
+
+// coroutine.isyieldable()
+pub fn lua_coroutine_isyieldable(vm: &mut VirtualMachine, _argc: usize) -> Result<usize, VMError> {
+    let yieldable = vm.current_thread.is_some();
+    vm.value_stack.push(LuaValue::Boolean(yieldable));
+    Ok(1)
+}
+
+// coroutine.wrap(f)
+// a callable that resumes a new coroutine running f, errors inside it are raised again
+pub fn lua_coroutine_wrap(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let body = check_body(vm, argc, "wrap")?;
+    let co = vm.create_coroutine(body)?;
+    let mut state = LuaTable::new();
+    state.set(LuaValue::Integer(1), co);
+    push_iterator(vm, state, lua_coroutine_wrap_step)
+}
+
+// __call of a wrapped coroutine, the state table is the first argument
+fn lua_coroutine_wrap_step(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let LuaValue::Table(state) = get_arg(vm, argc, 0) else {
+        return Err(bad_argument(vm, 0, "wrap", "coroutine state expected"));
+    };
+    let LuaValue::Coroutine(co) = vm.heap.data(state).get(&LuaValue::Integer(1)) else {
+        return Err(bad_argument(vm, 0, "wrap", "corrupted coroutine state"));
+    };
+    let args = (1..argc).map(|i| get_arg(vm, argc, i)).collect();
+
+    let values: Vec<LuaValue> = vm.resume_coroutine(co, args)?;
+    let count = values.len();
+    for val in values {
+        vm.value_stack.push(val);
+    }
+    Ok(count)
+}
+
+// ---------------------------------------------------------------------------
+// debug library
+// ---------------------------------------------------------------------------
+
+pub const DEBUG_LIB: &[(&str, CFunction)] = &[
+    ("sethook", lua_debug_sethook),
+    ("gethook", lua_debug_gethook),
+    ("getinfo", lua_debug_getinfo),
+    ("getlocal", lua_debug_getlocal),
+    ("traceback", lua_debug_traceback),
+];
+
+// debug.sethook([f, mask [, count]])
+// mask letters: "c" calls, "r" returns, "l" lines; a count > 0 calls f every count instructions
+// with "count"; f receives the event name and, for "line", the line. No arguments remove the hook
+pub fn lua_debug_sethook(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let func = get_arg(vm, argc, 0);
+    if matches!(func, LuaValue::Nil) {
+        vm.remove_hook();
+        return Ok(0);
+    }
+    if !matches!(
+        func,
+        LuaValue::Function(_) | LuaValue::CFunc(_) | LuaValue::NativeClosure(_)
+    ) {
+        return Err(bad_argument(
+            vm,
+            0,
+            "sethook",
+            &format!("function expected, got {}", type_name(&func)),
+        ));
+    }
+    let letters = match get_arg(vm, argc, 1) {
+        LuaValue::Nil => String::new(),
+        _ => check_string(vm, argc, 1, "sethook")?,
+    };
+    let count = opt_integer(vm, argc, 2, "sethook", 0)?;
+
+    let mut mask = HookMask::count(count.clamp(0, u32::MAX as i64) as u32);
+    for letter in letters.chars() {
+        mask = mask
+            | match letter {
+                'c' => HookMask::CALL,
+                'r' => HookMask::RETURN,
+                'l' => HookMask::LINE,
+                _ => HookMask::NONE,
+            };
+    }
+    vm.install_hook(mask, HookCallback::Lua(func));
     Ok(0)
 }
+
+// debug.gethook()
+// the hook function, "external hook" for one set by the host, followed by its mask letters and count;
+// nil without a hook
+pub fn lua_debug_gethook(vm: &mut VirtualMachine, _argc: usize) -> Result<usize, VMError> {
+    let mask = vm.hook_mask();
+    let result = match vm.lua_hook() {
+        Some(func) => func,
+        None if !mask.is_empty() => new_string(vm, "external hook".to_string())?,
+        None => {
+            vm.value_stack.push(LuaValue::Nil);
+            return Ok(1);
+        }
+    };
+    vm.value_stack.push(result);
+    let letters = [
+        (HookMask::CALL, 'c'),
+        (HookMask::RETURN, 'r'),
+        (HookMask::LINE, 'l'),
+    ]
+    .iter()
+    .filter(|(flag, _)| mask.contains(*flag))
+    .map(|(_, letter)| *letter)
+    .collect();
+    push_string(vm, letters)?;
+    vm.value_stack
+        .push(LuaValue::Integer(mask.instructions() as i64));
+    Ok(3)
+}
+
+// debug.getinfo(level)
+// a table with currentline, short_src, source, name and what ("Lua", "main" or "C") of the
+// function running at `level`: 0 is getinfo itself, 1 its caller and so on; nil past the stack
+pub fn lua_debug_getinfo(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let level = check_integer(vm, argc, 0, "getinfo")?;
+    if level < 0 {
+        return Err(bad_argument(vm, 0, "getinfo", "level out of range"));
+    }
+    let Some(info) = vm.frame_info(level as usize) else {
+        vm.value_stack.push(LuaValue::Nil);
+        return Ok(1);
+    };
+
+    let table = new_table(vm, LuaTable::new())?;
+    vm.value_stack.push(LuaValue::Table(table));
+
+    let what = if info.native {
+        "C"
+    } else if info.func_name == "_start" || info.func_name.ends_with("::_start") {
+        "main"
+    } else {
+        "Lua"
+    };
+    let line = match info.line {
+        Some(line) if !info.native => LuaValue::Integer(line as i64),
+        _ => LuaValue::Integer(-1),
+    };
+    set_field(vm, table, "currentline", line)?;
+    let fields = [
+        ("short_src", info.chunk_name.to_string()),
+        ("source", format!("@{}", info.chunk_name)),
+        ("name", source_function_name(&info.func_name).to_string()),
+        ("what", what.to_string()),
+    ];
+    for (name, value) in fields {
+        let value = new_string(vm, value)?;
+        set_field(vm, table, name, value)?;
+    }
+    Ok(1)
+}
+
+// the name of a function in the source: `f` for `__local_fn_f_0`, the internal name of a
+// `local function f`, without the chunk prefix of the facade (`__chunk_1::`)
+fn source_function_name(internal: &str) -> &str {
+    let short = internal.rsplit("::").next().unwrap_or(internal);
+    short
+        .strip_prefix("__local_fn_")
+        .and_then(|rest| rest.rsplit_once('_'))
+        .filter(|(_, id)| id.parse::<usize>().is_ok())
+        .map_or(short, |(name, _)| name)
+}
+
+// debug.getlocal(level, n)
+// name and value of the n-th local of the function running at `level` (counted like getinfo),
+// nil if it has no such local
+pub fn lua_debug_getlocal(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let level = check_integer(vm, argc, 0, "getlocal")?;
+    let n = check_integer(vm, argc, 1, "getlocal")?;
+    if level < 0 || vm.frame_info(level as usize).is_none() {
+        return Err(bad_argument(vm, 0, "getlocal", "level out of range"));
+    }
+    let Some((name, value)) = vm.frame_local(level as usize, n.max(0) as usize) else {
+        vm.value_stack.push(LuaValue::Nil);
+        return Ok(1);
+    };
+    let name = new_string(vm, name)?;
+    vm.value_stack.push(name);
+    vm.value_stack.push(value);
+    Ok(2)
+}
+
+// debug.traceback([message [, level]])
+// message followed by the call stack from `level` (default 1, the caller) down, as a string;
+// a message that is neither a string, a number nor nil is returned unchanged
+pub fn lua_debug_traceback(vm: &mut VirtualMachine, argc: usize) -> Result<usize, VMError> {
+    let message = get_arg(vm, argc, 0);
+    let message = match message {
+        LuaValue::Nil => None,
+        LuaValue::String(_) | LuaValue::Number(_) | LuaValue::Integer(_) => {
+            Some(raw_tostring(&message))
+        }
+        _ => {
+            vm.value_stack.push(message);
+            return Ok(1);
+        }
+    };
+    let level = opt_integer(vm, argc, 1, "traceback", 1)?;
+
+    let mut text = message.map_or(String::new(), |m| m + "\n");
+    text.push_str("stack traceback:");
+    for line in vm.traceback(level.max(0) as usize) {
+        text.push_str("\n\t");
+        text.push_str(&line);
+    }
+    let text = new_string(vm, text)?;
+    vm.value_stack.push(text);
+    Ok(1)
+}
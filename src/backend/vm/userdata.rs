@@ -0,0 +1,65 @@
+// Myula userdata
+// Changelog:
+// 2026-02-24: Initial version. A userdata is a heap object holding any `'static` Rust value; the host
+//            creates it (`create_userdata`), scripts pass it around like a table they cannot look into,
+//            and native functions get the value back by its type (`get_userdata` / `get_userdata_mut`).
+//            What scripts can do with it comes from the metatable the host gives it: __index and
+//            __newindex (a table or a function), __call and __tostring. The value is dropped when the
+//            collector frees the object.
+
+use crate::backend::vm::VirtualMachine;
+use crate::backend::vm::error::{ErrorKind, VMError};
+use crate::backend::vm::heap::Gc;
+use crate::common::object::{LuaTable, LuaUserData, LuaValue};
+use std::any::Any;
+
+impl VirtualMachine {
+    /// move `value` onto the heap as a userdata without a metatable
+    ///
+    /// may run a collection, like every allocation of the VM
+    pub fn create_userdata<T: Any>(&mut self, value: T) -> Result<LuaValue, VMError> {
+        // allocated with an empty payload first, a retried allocation would need the value twice
+        let ptr = self.alloc(|heap| {
+            heap.alloc_userdata(LuaUserData {
+                value: Box::new(()),
+                metatable: None,
+            })
+        })?;
+        self.heap.data_mut(ptr).value = Box::new(value);
+        Ok(LuaValue::UserData(ptr))
+    }
+
+    /// the Rust value of a userdata, None for other values and for a userdata holding another type
+    pub fn get_userdata<T: Any>(&self, value: &LuaValue) -> Option<&T> {
+        match value {
+            LuaValue::UserData(ptr) => self.heap.data(*ptr).value.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn get_userdata_mut<T: Any>(&mut self, value: &LuaValue) -> Option<&mut T> {
+        match value {
+            LuaValue::UserData(ptr) => self.heap.data_mut(*ptr).value.downcast_mut(),
+            _ => None,
+        }
+    }
+
+    /// give a userdata its metatable, scripts cannot change it (setmetatable only takes tables)
+    pub fn set_userdata_metatable(
+        &mut self,
+        value: &LuaValue,
+        metatable: Option<Gc<LuaTable>>,
+    ) -> Result<(), VMError> {
+        let LuaValue::UserData(ptr) = *value else {
+            return Err(self.error(ErrorKind::TypeError(format!(
+                "TypeMismatchException: userdata expected, got {:?}",
+                value
+            ))));
+        };
+        if let Some(mt) = metatable {
+            self.heap.write_barrier(ptr, &LuaValue::Table(mt));
+        }
+        self.heap.data_mut(ptr).metatable = metatable;
+        Ok(())
+    }
+}
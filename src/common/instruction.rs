@@ -0,0 +1,488 @@
+use std::fmt;
+
+use crate::common::opcode::{OpCode, UnaryOpType};
+
+// packed 32-bit form of an `OpCode`, the only form the dispatch loop reads
+//
+//   iABC   | C: 8 | B: 8 | A: 8 | op: 8 |
+//   iABx   |    Bx: 16   | A: 8 | op: 8 |   Bx unsigned (constant, upvalue, prototype index)
+//   iAsBx  |   sBx: 16   | A: 8 | op: 8 |   sBx signed (jump offset)
+//   isJ    |        sJ: 24      | op: 8 |   sJ signed (jump offset)
+//
+// an instruction whose operands do not fit is encoded as OP_WIDE, the VM then runs the
+// `OpCode` kept at the same pc of the function's `bytecode`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Instruction(u32);
+
+pub const OP_LOADK: u8 = 0;
+pub const OP_LOADNIL: u8 = 1;
+pub const OP_LOADBOOL: u8 = 2;
+pub const OP_MOVE: u8 = 3;
+pub const OP_GETGLOBAL: u8 = 4;
+pub const OP_SETGLOBAL: u8 = 5;
+pub const OP_GETUPVAL: u8 = 6;
+pub const OP_SETUPVAL: u8 = 7;
+pub const OP_ADD: u8 = 8;
+pub const OP_SUB: u8 = 9;
+pub const OP_MUL: u8 = 10;
+pub const OP_DIV: u8 = 11;
+pub const OP_MOD: u8 = 12;
+pub const OP_POW: u8 = 13;
+pub const OP_CONCAT: u8 = 14;
+pub const OP_AND: u8 = 15;
+pub const OP_OR: u8 = 16;
+pub const OP_ADDK: u8 = 17;
+pub const OP_SUBK: u8 = 18;
+pub const OP_UNOP: u8 = 19;
+pub const OP_EQ: u8 = 20;
+pub const OP_NE: u8 = 21;
+pub const OP_LT: u8 = 22;
+pub const OP_GT: u8 = 23;
+pub const OP_LE: u8 = 24;
+pub const OP_GE: u8 = 25;
+pub const OP_TEST: u8 = 26;
+pub const OP_JUMP: u8 = 27;
+pub const OP_JMPFALSE: u8 = 28;
+pub const OP_NEWTABLE: u8 = 29;
+pub const OP_GETTABLE: u8 = 30;
+pub const OP_SETTABLE: u8 = 31;
+pub const OP_GETFIELD: u8 = 32;
+pub const OP_SETFIELD: u8 = 33;
+pub const OP_FNPROTO: u8 = 34;
+pub const OP_CALL: u8 = 35;
+pub const OP_PUSH: u8 = 36;
+pub const OP_RETURN: u8 = 37;
+pub const OP_TAILCALL: u8 = 38;
+pub const OP_HALT: u8 = 39;
+pub const OP_SWITCH: u8 = 40;
+// quickened forms the VM rewrites a hot ADD into at runtime, never emitted nor serialized,
+// see dispatch::quicken
+pub const OP_ADDII: u8 = 41;
+pub const OP_ADDNN: u8 = 42;
+pub const OP_CONCATN: u8 = 43;
+pub const OP_SETLIST: u8 = 44;
+pub const OP_GETRESULT: u8 = 45;
+pub const OP_WIDE: u8 = 255;
+
+// mnemonics by opcode tag, the same as `OpCode::name`
+const NAMES: [&str; OP_GETRESULT as usize + 1] = [
+    "LOADK",
+    "LOADNIL",
+    "LOADBOOL",
+    "MOVE",
+    "GETGLOBAL",
+    "SETGLOBAL",
+    "GETUPVAL",
+    "SETUPVAL",
+    "ADD",
+    "SUB",
+    "MUL",
+    "DIV",
+    "MOD",
+    "POW",
+    "CONCAT",
+    "AND",
+    "OR",
+    "ADDK",
+    "SUBK",
+    "UNOP",
+    "EQ",
+    "NE",
+    "LT",
+    "GT",
+    "LE",
+    "GE",
+    "TEST",
+    "JUMP",
+    "JMPFALSE",
+    "NEWTABLE",
+    "GETTABLE",
+    "SETTABLE",
+    "GETFIELD",
+    "SETFIELD",
+    "FNPROTO",
+    "CALL",
+    "PUSH",
+    "RETURN",
+    "TAILCALL",
+    "HALT",
+    "SWITCH",
+    "ADDII",
+    "ADDNN",
+    "CONCATN",
+    "SETLIST",
+    "GETRESULT",
+];
+
+const SJ_MIN: i32 = -(1 << 23);
+const SJ_MAX: i32 = (1 << 23) - 1;
+
+impl Instruction {
+    fn abc(op: u8, a: u16, b: u16, c: u16) -> Option<Self> {
+        let a = u8::try_from(a).ok()? as u32;
+        let b = u8::try_from(b).ok()? as u32;
+        let c = u8::try_from(c).ok()? as u32;
+        Some(Instruction(op as u32 | a << 8 | b << 16 | c << 24))
+    }
+
+    fn abx(op: u8, a: u16, bx: u16) -> Option<Self> {
+        let a = u8::try_from(a).ok()? as u32;
+        Some(Instruction(op as u32 | a << 8 | (bx as u32) << 16))
+    }
+
+    fn asbx(op: u8, a: u16, sbx: i32) -> Option<Self> {
+        let a = u8::try_from(a).ok()? as u32;
+        let sbx = i16::try_from(sbx).ok()? as u16 as u32;
+        Some(Instruction(op as u32 | a << 8 | sbx << 16))
+    }
+
+    fn isj(op: u8, sj: i32) -> Option<Self> {
+        if !(SJ_MIN..=SJ_MAX).contains(&sj) {
+            return None;
+        }
+        Some(Instruction(op as u32 | (sj as u32) << 8))
+    }
+
+    // the packed form of op, None if one of its operands is too wide for it
+    pub fn encode(op: &OpCode) -> Option<Self> {
+        match *op {
+            OpCode::LoadK { dest, const_idx } => Self::abx(OP_LOADK, dest, const_idx),
+            OpCode::LoadNil { dest } => Self::abc(OP_LOADNIL, dest, 0, 0),
+            OpCode::LoadBool { dest, value } => Self::abc(OP_LOADBOOL, dest, value as u16, 0),
+            OpCode::Move { dest, src } => Self::abc(OP_MOVE, dest, src, 0),
+            OpCode::GetGlobal { dest, name_idx } => Self::abx(OP_GETGLOBAL, dest, name_idx),
+            OpCode::SetGlobal { name_idx, src } => Self::abx(OP_SETGLOBAL, src, name_idx),
+            OpCode::GetUpVal { dest, upval_idx } => Self::abx(OP_GETUPVAL, dest, upval_idx),
+            OpCode::SetUpVal { upval_idx, src } => Self::abx(OP_SETUPVAL, src, upval_idx),
+            OpCode::Add { dest, left, right } => Self::abc(OP_ADD, dest, left, right),
+            OpCode::Sub { dest, left, right } => Self::abc(OP_SUB, dest, left, right),
+            OpCode::Mul { dest, left, right } => Self::abc(OP_MUL, dest, left, right),
+            OpCode::Div { dest, left, right } => Self::abc(OP_DIV, dest, left, right),
+            OpCode::Mod { dest, left, right } => Self::abc(OP_MOD, dest, left, right),
+            OpCode::Pow { dest, left, right } => Self::abc(OP_POW, dest, left, right),
+            OpCode::Concat { dest, left, right } => Self::abc(OP_CONCAT, dest, left, right),
+            OpCode::ConcatN { dest, start, count } => Self::abc(OP_CONCATN, dest, start, count),
+            OpCode::And { dest, left, right } => Self::abc(OP_AND, dest, left, right),
+            OpCode::Or { dest, left, right } => Self::abc(OP_OR, dest, left, right),
+            OpCode::AddK {
+                dest,
+                left,
+                const_idx,
+            } => Self::abc(OP_ADDK, dest, left, const_idx),
+            OpCode::SubK {
+                dest,
+                left,
+                const_idx,
+            } => Self::abc(OP_SUBK, dest, left, const_idx),
+            OpCode::UnOp { dest, src, op } => {
+                let op = match op {
+                    UnaryOpType::Neg => 0,
+                    UnaryOpType::Not => 1,
+                    UnaryOpType::Len => 2,
+                };
+                Self::abc(OP_UNOP, dest, src, op)
+            }
+            OpCode::Eq { dest, left, right } => Self::abc(OP_EQ, dest, left, right),
+            OpCode::Ne { dest, left, right } => Self::abc(OP_NE, dest, left, right),
+            OpCode::Lt { dest, left, right } => Self::abc(OP_LT, dest, left, right),
+            OpCode::Gt { dest, left, right } => Self::abc(OP_GT, dest, left, right),
+            OpCode::Le { dest, left, right } => Self::abc(OP_LE, dest, left, right),
+            OpCode::Ge { dest, left, right } => Self::abc(OP_GE, dest, left, right),
+            OpCode::Test { reg } => Self::abc(OP_TEST, reg, 0, 0),
+            OpCode::Jump { offset } => Self::isj(OP_JUMP, offset),
+            OpCode::JumpIfFalse { reg, offset } => Self::asbx(OP_JMPFALSE, reg, offset),
+            OpCode::NewTable {
+                dest,
+                size_array,
+                size_hash,
+            } => Self::abc(OP_NEWTABLE, dest, size_array, size_hash),
+            OpCode::GetTable { dest, table, key } => Self::abc(OP_GETTABLE, dest, table, key),
+            OpCode::SetTable { table, key, value } => Self::abc(OP_SETTABLE, table, key, value),
+            OpCode::SetList {
+                table,
+                count,
+                batch,
+            } => Self::abc(OP_SETLIST, table, count, batch),
+            OpCode::GetField { dest, table, key } => Self::abc(OP_GETFIELD, dest, table, key),
+            OpCode::SetField { table, key, value } => Self::abc(OP_SETFIELD, table, key, value),
+            OpCode::FnProto { dest, proto_idx } => Self::abx(OP_FNPROTO, dest, proto_idx),
+            OpCode::Call {
+                func_reg,
+                argc,
+                retc,
+            } => Self::abc(OP_CALL, func_reg, argc as u16, retc as u16),
+            OpCode::GetResult { dest, index } => Self::abc(OP_GETRESULT, dest, index as u16, 0),
+            OpCode::Push { src } => Self::abc(OP_PUSH, src, 0, 0),
+            OpCode::Return { start, count } => Self::abc(OP_RETURN, start, count as u16, 0),
+            OpCode::TailCall { func_reg, argc } => Self::abc(OP_TAILCALL, func_reg, argc as u16, 0),
+            OpCode::Halt => Some(Instruction(OP_HALT as u32)),
+            OpCode::Switch { reg, table } => Self::abx(OP_SWITCH, reg, table),
+        }
+    }
+
+    // the `OpCode` this instruction was encoded from, None for OP_WIDE
+    pub fn decode(self) -> Option<OpCode> {
+        let (a, b, c, bx) = (self.a(), self.b(), self.c(), self.bx());
+        Some(match self.op() {
+            OP_LOADK => OpCode::LoadK {
+                dest: a,
+                const_idx: bx,
+            },
+            OP_LOADNIL => OpCode::LoadNil { dest: a },
+            OP_LOADBOOL => OpCode::LoadBool {
+                dest: a,
+                value: b != 0,
+            },
+            OP_MOVE => OpCode::Move { dest: a, src: b },
+            OP_GETGLOBAL => OpCode::GetGlobal {
+                dest: a,
+                name_idx: bx,
+            },
+            OP_SETGLOBAL => OpCode::SetGlobal {
+                name_idx: bx,
+                src: a,
+            },
+            OP_GETUPVAL => OpCode::GetUpVal {
+                dest: a,
+                upval_idx: bx,
+            },
+            OP_SETUPVAL => OpCode::SetUpVal {
+                upval_idx: bx,
+                src: a,
+            },
+            OP_ADD | OP_ADDII | OP_ADDNN => OpCode::Add {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_SUB => OpCode::Sub {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_MUL => OpCode::Mul {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_DIV => OpCode::Div {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_MOD => OpCode::Mod {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_POW => OpCode::Pow {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_CONCAT => OpCode::Concat {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_AND => OpCode::And {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_OR => OpCode::Or {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_ADDK => OpCode::AddK {
+                dest: a,
+                left: b,
+                const_idx: c,
+            },
+            OP_SUBK => OpCode::SubK {
+                dest: a,
+                left: b,
+                const_idx: c,
+            },
+            OP_UNOP => OpCode::UnOp {
+                dest: a,
+                src: b,
+                op: self.unary_op(),
+            },
+            OP_EQ => OpCode::Eq {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_NE => OpCode::Ne {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_LT => OpCode::Lt {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_GT => OpCode::Gt {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_LE => OpCode::Le {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_GE => OpCode::Ge {
+                dest: a,
+                left: b,
+                right: c,
+            },
+            OP_TEST => OpCode::Test { reg: a },
+            OP_JUMP => OpCode::Jump { offset: self.sj() },
+            OP_JMPFALSE => OpCode::JumpIfFalse {
+                reg: a,
+                offset: self.sbx(),
+            },
+            OP_NEWTABLE => OpCode::NewTable {
+                dest: a,
+                size_array: b,
+                size_hash: c,
+            },
+            OP_GETTABLE => OpCode::GetTable {
+                dest: a,
+                table: b,
+                key: c,
+            },
+            OP_SETTABLE => OpCode::SetTable {
+                table: a,
+                key: b,
+                value: c,
+            },
+            OP_GETFIELD => OpCode::GetField {
+                dest: a,
+                table: b,
+                key: c,
+            },
+            OP_SETFIELD => OpCode::SetField {
+                table: a,
+                key: b,
+                value: c,
+            },
+            OP_FNPROTO => OpCode::FnProto {
+                dest: a,
+                proto_idx: bx,
+            },
+            OP_CALL => OpCode::Call {
+                func_reg: a,
+                argc: b as u8,
+                retc: c as u8,
+            },
+            OP_GETRESULT => OpCode::GetResult {
+                dest: a,
+                index: b as u8,
+            },
+            OP_PUSH => OpCode::Push { src: a },
+            OP_RETURN => OpCode::Return {
+                start: a,
+                count: b as u8,
+            },
+            OP_TAILCALL => OpCode::TailCall {
+                func_reg: a,
+                argc: b as u8,
+            },
+            OP_HALT => OpCode::Halt,
+            OP_SWITCH => OpCode::Switch { reg: a, table: bx },
+            OP_CONCATN => OpCode::ConcatN {
+                dest: a,
+                start: b,
+                count: c,
+            },
+            OP_SETLIST => OpCode::SetList {
+                table: a,
+                count: b,
+                batch: c,
+            },
+            _ => return None,
+        })
+    }
+
+    #[inline(always)]
+    pub fn op(self) -> u8 {
+        self.0 as u8
+    }
+
+    #[inline(always)]
+    pub fn a(self) -> u16 {
+        (self.0 >> 8) as u8 as u16
+    }
+
+    #[inline(always)]
+    pub fn b(self) -> u16 {
+        (self.0 >> 16) as u8 as u16
+    }
+
+    #[inline(always)]
+    pub fn c(self) -> u16 {
+        (self.0 >> 24) as u16
+    }
+
+    #[inline(always)]
+    pub fn bx(self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+
+    #[inline(always)]
+    pub fn sbx(self) -> i32 {
+        (self.0 >> 16) as u16 as i16 as i32
+    }
+
+    #[inline(always)]
+    pub fn sj(self) -> i32 {
+        // arithmetic shift sign-extends the 24 bits
+        (self.0 as i32) >> 8
+    }
+
+    // the unary operator in C of OP_UNOP
+    #[inline(always)]
+    pub fn unary_op(self) -> UnaryOpType {
+        match self.c() {
+            0 => UnaryOpType::Neg,
+            1 => UnaryOpType::Not,
+            _ => UnaryOpType::Len,
+        }
+    }
+
+    // the mnemonic of the opcode, "WIDE" for an instruction that was too wide to pack
+    pub fn name(self) -> &'static str {
+        NAMES.get(self.op() as usize).copied().unwrap_or("WIDE")
+    }
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    // the same operands under another opcode, for quickening
+    pub fn with_op(self, op: u8) -> Self {
+        Instruction(self.0 & !0xff | op as u32)
+    }
+}
+
+// the packed form of a function's bytecode, pc for pc
+pub fn encode_all(bytecode: &[OpCode]) -> Vec<Instruction> {
+    bytecode
+        .iter()
+        .map(|op| Instruction::encode(op).unwrap_or(Instruction(OP_WIDE as u32)))
+        .collect()
+}
+
+impl fmt::Debug for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.decode() {
+            Some(op) => write!(f, "{:08x} ({})", self.0, op),
+            None => write!(f, "{:08x} (WIDE)", self.0),
+        }
+    }
+}
@@ -1,2 +1,3 @@
+pub mod instruction;
 pub mod object;
 pub mod opcode;
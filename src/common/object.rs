@@ -1,14 +1,168 @@
-use crate::backend::vm::VirtualMachine;
 use crate::backend::vm::error::VMError;
+use crate::backend::vm::heap::Gc;
+use crate::backend::vm::stack::{GlobalStack, StackFrame};
+use crate::backend::vm::{LoadedFunction, VirtualMachine};
+use std::any::Any;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt;
+use std::rc::Rc;
 
+/// a native function: it reads its `argc` arguments from the top of the value stack, pushes its
+/// results onto the value stack and returns how many it pushed; the caller moves them into the
+/// registers the CALL asked for, padding with nil or dropping the extra ones
 pub type CFunction = fn(&mut VirtualMachine, usize) -> Result<usize, VMError>;
 
-#[derive(Clone, PartialEq)]
+/// a native function that carries state, same calling convention as `CFunction`
+pub type NativeFn = Box<dyn FnMut(&mut VirtualMachine, usize) -> Result<usize, VMError>>;
+
+/// a host closure registered with `VirtualMachine::register_function`, heap allocated like a Lua function
+pub struct NativeClosure {
+    pub name: String,
+    // taken out while the closure runs, so that it can borrow the VM mutably
+    pub func: Option<NativeFn>,
+}
+
+/// a Rust value handed to scripts, see `VirtualMachine::create_userdata`; scripts only see it through
+/// its metatable, which the host sets
+pub struct LuaUserData {
+    pub value: Box<dyn Any>,
+    pub metatable: Option<Gc<LuaTable>>,
+}
+
+/// the one table representation: `LuaValue::Table` points at a heap allocated `GCObject<LuaTable>`,
+/// table opcodes, metatables, GC marking and the builtins all work on this type
+///
+/// like the reference implementation it has two parts: the values of the keys 1..=n live in `array`,
+/// every other key in the hash part `data`
+#[derive(Clone, PartialEq, Default)]
 pub struct LuaTable {
+    // t[1..=array.len()], may hold nil holes but never ends with nil
+    pub array: Vec<LuaValue>,
     pub data: HashMap<LuaValue, LuaValue>,
-    pub metatable: Option<*mut GCObject<LuaTable>>,
+    pub metatable: Option<Gc<LuaTable>>,
+}
+
+impl LuaTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_sizes(0, capacity)
+    }
+
+    /// preallocated for `array` consecutive integer keys and `hash` other ones
+    pub fn with_sizes(array: usize, hash: usize) -> Self {
+        Self {
+            array: Vec::with_capacity(array),
+            data: HashMap::with_capacity(hash),
+            metatable: None,
+        }
+    }
+
+    // position in `array` of an integer key, if it falls inside the array part
+    fn array_index(&self, key: &LuaValue) -> Option<usize> {
+        match key {
+            LuaValue::Integer(i) if *i >= 1 && (*i as u64) <= self.array.len() as u64 => {
+                Some(*i as usize - 1)
+            }
+            _ => None,
+        }
+    }
+
+    /// raw lookup, absent keys read as nil
+    pub fn get(&self, key: &LuaValue) -> LuaValue {
+        // fast path, integer keys of the array part skip hashing
+        if let Some(idx) = self.array_index(key) {
+            return self.array[idx];
+        }
+        match key.to_table_key() {
+            Some(int_key) => match self.array_index(&int_key) {
+                Some(idx) => Some(&self.array[idx]),
+                None => self.data.get(&int_key),
+            },
+            None => self.data.get(key),
+        }
+        .cloned()
+        .unwrap_or(LuaValue::Nil)
+    }
+
+    /// raw store, assigning nil removes the key so absent and nil stay indistinguishable
+    pub fn set(&mut self, key: LuaValue, val: LuaValue) {
+        let key = key.to_table_key().unwrap_or(key);
+        if let Some(idx) = self.array_index(&key) {
+            self.array[idx] = val;
+            // keep the last slot non-nil
+            while matches!(self.array.last(), Some(LuaValue::Nil)) {
+                self.array.pop();
+            }
+            return;
+        }
+
+        if matches!(val, LuaValue::Nil) {
+            self.data.remove(&key);
+        } else if key == LuaValue::Integer(self.array.len() as i64 + 1) {
+            self.array.push(val);
+            // the keys that follow move over from the hash part
+            while let Some(next) = self
+                .data
+                .remove(&LuaValue::Integer(self.array.len() as i64 + 1))
+            {
+                self.array.push(next);
+            }
+        } else {
+            self.data.insert(key, val);
+        }
+    }
+
+    /// the length operator: a border n such that t[n] is not nil and t[n + 1] is
+    pub fn len(&self) -> usize {
+        // the last array slot is never nil, so the border is at or after it
+        let mut n = self.array.len();
+        while self.data.contains_key(&LuaValue::Integer((n + 1) as i64)) {
+            n += 1;
+        }
+        n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.array.is_empty() && self.data.is_empty()
+    }
+
+    /// every key-value pair, the array part first
+    pub fn iter(&self) -> impl Iterator<Item = (LuaValue, &LuaValue)> {
+        self.array
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| !matches!(v, LuaValue::Nil))
+            .map(|(i, v)| (LuaValue::Integer(i as i64 + 1), v))
+            .chain(self.data.iter().map(|(k, v)| (*k, v)))
+    }
+
+    /// the pair that follows `key` in `iter` order, the first one for nil and Some(None) after the
+    /// last; None for a key that is not in the table. The hash part is searched for `key`, so
+    /// walking a large hash part this way is quadratic
+    pub fn next(&self, key: &LuaValue) -> Option<Option<(LuaValue, LuaValue)>> {
+        let key = key.to_table_key().unwrap_or(*key);
+        let start = match key {
+            LuaValue::Nil => 0,
+            _ => match self.array_index(&key) {
+                Some(idx) => idx + 1,
+                None => {
+                    let mut rest = self.data.iter().skip_while(|(k, _)| **k != key);
+                    rest.next()?;
+                    return Some(rest.next().map(|(k, v)| (*k, *v)));
+                }
+            },
+        };
+        let in_array = self.array[start.min(self.array.len())..]
+            .iter()
+            .enumerate()
+            .find(|(_, v)| !matches!(v, LuaValue::Nil))
+            .map(|(i, v)| (LuaValue::Integer((start + i) as i64 + 1), *v));
+        Some(in_array.or_else(|| self.data.iter().next().map(|(k, v)| (*k, *v))))
+    }
 }
 #[repr(C)]
 pub struct HeaderOnly;
@@ -17,6 +171,12 @@ pub struct HeaderOnly;
 #[derive(Debug)]
 pub struct GCObject<T> {
     pub mark: bool,
+    // survived a collection in generational mode, minor collections do not trace it
+    pub old: bool,
+    // old object already queued in the heap's remembered set
+    pub remembered: bool,
+    // strings: hash of the content, computed once when the string is interned; 0 for other objects
+    pub hash: u64,
     pub kind: ObjectKind,
     pub size: usize,
     pub next: *mut GCObject<HeaderOnly>,
@@ -29,21 +189,31 @@ pub enum ObjectKind {
     Table,
     Function,
     UpValue,
+    NativeClosure,
+    Coroutine,
+    UserData,
 }
 
-#[derive(Clone, PartialEq)]
+/// a register, a table slot or a constant; heap objects are raw GC pointers, so a value is
+/// 16 bytes and copied around freely
+#[derive(Clone, Copy)]
 pub enum LuaValue {
     Nil,
     Number(f64),
+    Integer(i64),
     Boolean(bool),
-    String(*mut GCObject<String>),
-    Table(*mut GCObject<LuaTable>),
-    Function(*mut GCObject<LFunction>),
+    String(Gc<String>),
+    Table(Gc<LuaTable>),
+    Function(Gc<LFunction>),
     CFunc(CFunction),
-    UserData(*mut std::ffi::c_void),
-    TempString(String),
+    NativeClosure(Gc<NativeClosure>),
+    Coroutine(Gc<LuaCoroutine>),
+    UserData(Gc<LuaUserData>),
 }
 
+// every variant is a tag and at most 8 bytes of payload
+const _: () = assert!(std::mem::size_of::<LuaValue>() == 16);
+
 #[derive(Debug, Clone)]
 pub enum LuaUpValueState {
     Open(usize),      // inside a stack frame, offset relative to the bottom of the stack
@@ -53,6 +223,48 @@ pub enum LuaUpValueState {
 #[derive(Debug, Clone)]
 pub struct LuaUpValue {
     pub value: LuaUpValueState,
+    // the coroutine whose value stack an open upvalue points into, None for the main thread
+    pub thread: Option<Gc<LuaCoroutine>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoroutineStatus {
+    Suspended,
+    Running,
+    // resumed another coroutine and waits for it
+    Normal,
+    Dead,
+}
+
+/// a coroutine: a Lua function with a call stack and a value stack of its own
+///
+/// while it runs its stacks are swapped into the VM, so they are only filled in here
+/// while it is suspended
+pub struct LuaCoroutine {
+    pub func: LuaValue,
+    pub status: CoroutineStatus,
+    pub call_stack: Vec<StackFrame>,
+    pub value_stack: GlobalStack,
+    // the result register of the yield call in the frame that made it, and the retc of that call;
+    // the values of the next resume are delivered there
+    pub resume_dest: Option<(usize, u8)>,
+}
+
+impl LuaCoroutine {
+    pub fn new(func: LuaValue) -> Self {
+        Self {
+            func,
+            status: CoroutineStatus::Suspended,
+            call_stack: Vec::new(),
+            value_stack: GlobalStack::default(),
+            resume_dest: None,
+        }
+    }
+
+    /// resumed before, i.e. the next resume continues after a yield
+    pub fn is_started(&self) -> bool {
+        !self.call_stack.is_empty()
+    }
 }
 
 impl LuaValue {
@@ -63,6 +275,105 @@ impl LuaValue {
             _ => true,
         }
     }
+
+    /// numeric value as a float, integers are converted (possibly rounding beyond 2^53)
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            LuaValue::Number(n) => Some(*n),
+            LuaValue::Integer(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    /// numeric value as an integer, floats only convert when they hold an exact integer value
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            LuaValue::Integer(i) => Some(*i),
+            LuaValue::Number(n) => float_to_integer(*n),
+            _ => None,
+        }
+    }
+
+    /// the `==` operator without metamethods: numbers compare by mathematical value across subtypes
+    pub fn raw_equal(&self, other: &LuaValue) -> bool {
+        match (self, other) {
+            (LuaValue::Integer(_), LuaValue::Number(_))
+            | (LuaValue::Number(_), LuaValue::Integer(_)) => {
+                compare_numbers(self, other) == Some(Ordering::Equal)
+            }
+            _ => self == other,
+        }
+    }
+
+    /// float keys with an exact integer value are stored as integers, so t[1] and t[1.0] are the same slot
+    pub fn to_table_key(&self) -> Option<LuaValue> {
+        match self {
+            LuaValue::Number(n) => float_to_integer(*n).map(LuaValue::Integer),
+            _ => None,
+        }
+    }
+}
+
+/// orders two numbers of either subtype exactly, None if either is not a number or is NaN
+pub fn compare_numbers(a: &LuaValue, b: &LuaValue) -> Option<Ordering> {
+    match (a, b) {
+        (LuaValue::Integer(i), LuaValue::Integer(j)) => Some(i.cmp(j)),
+        (LuaValue::Number(x), LuaValue::Number(y)) => x.partial_cmp(y),
+        (LuaValue::Integer(i), LuaValue::Number(f)) => compare_int_float(*i, *f),
+        (LuaValue::Number(f), LuaValue::Integer(i)) => {
+            compare_int_float(*i, *f).map(Ordering::reverse)
+        }
+        _ => None,
+    }
+}
+
+// i64 -> f64 rounds above 2^53, so compare against the integral part of the float instead
+fn compare_int_float(i: i64, f: f64) -> Option<Ordering> {
+    if f.is_nan() {
+        None
+    } else if f >= 9223372036854775808.0 {
+        Some(Ordering::Less)
+    } else if f < -9223372036854775808.0 {
+        Some(Ordering::Greater)
+    } else {
+        match i.cmp(&(f.trunc() as i64)) {
+            Ordering::Equal => 0.0.partial_cmp(&f.fract()),
+            ord => Some(ord),
+        }
+    }
+}
+
+/// exact float to integer conversion, fails for fractional, non-finite and out of range values
+pub fn float_to_integer(n: f64) -> Option<i64> {
+    // 2^63 itself is out of range, every float below it in magnitude fits
+    if n.fract() == 0.0 && (-9223372036854775808.0..9223372036854775808.0).contains(&n) {
+        Some(n as i64)
+    } else {
+        None
+    }
+}
+
+// objects are equal when they are the same object, except strings: only the short ones are
+// interned, a long string built at runtime is equal to any other string with its content
+impl PartialEq for LuaValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (LuaValue::Nil, LuaValue::Nil) => true,
+            (LuaValue::Number(a), LuaValue::Number(b)) => a == b,
+            (LuaValue::Integer(a), LuaValue::Integer(b)) => a == b,
+            (LuaValue::Boolean(a), LuaValue::Boolean(b)) => a == b,
+            (LuaValue::String(a), LuaValue::String(b)) => {
+                a == b || (a.content_hash() == b.content_hash() && a.get() == b.get())
+            }
+            (LuaValue::Table(a), LuaValue::Table(b)) => a == b,
+            (LuaValue::Function(a), LuaValue::Function(b)) => a == b,
+            (LuaValue::CFunc(a), LuaValue::CFunc(b)) => std::ptr::fn_addr_eq(*a, *b),
+            (LuaValue::NativeClosure(a), LuaValue::NativeClosure(b)) => a == b,
+            (LuaValue::Coroutine(a), LuaValue::Coroutine(b)) => a == b,
+            (LuaValue::UserData(a), LuaValue::UserData(b)) => a == b,
+            _ => false,
+        }
+    }
 }
 
 impl Eq for LuaValue {}
@@ -80,13 +391,16 @@ impl std::hash::Hash for LuaValue {
                 };
                 bits.hash(state);
             }
+            LuaValue::Integer(i) => i.hash(state),
             LuaValue::Boolean(b) => b.hash(state),
-            LuaValue::String(p) => (*p as usize).hash(state),
-            LuaValue::Table(p) => (*p as usize).hash(state),
-            LuaValue::Function(p) => (*p as usize).hash(state),
-            LuaValue::UserData(p) => (*p as usize).hash(state),
+            // the cached hash of the content, the same for equal short and long strings
+            LuaValue::String(p) => p.content_hash().hash(state),
+            LuaValue::Table(p) => p.addr().hash(state),
+            LuaValue::Function(p) => p.addr().hash(state),
+            LuaValue::UserData(p) => p.addr().hash(state),
             LuaValue::CFunc(f) => (*f as *const () as usize).hash(state),
-            LuaValue::TempString(s) => s.hash(state),
+            LuaValue::NativeClosure(p) => p.addr().hash(state),
+            LuaValue::Coroutine(p) => p.addr().hash(state),
         }
     }
 }
@@ -96,19 +410,15 @@ impl fmt::Debug for LuaValue {
         match self {
             LuaValue::Nil => write!(f, "Nil"),
             LuaValue::Number(n) => write!(f, "Number({})", n),
+            LuaValue::Integer(i) => write!(f, "Integer({})", i),
             LuaValue::Boolean(b) => write!(f, "Bool({})", b),
-            LuaValue::String(ptr) => unsafe {
-                if ptr.is_null() {
-                    write!(f, "String(NULL)")
-                } else {
-                    write!(f, "String(\"{}\")", (*(*ptr)).data)
-                }
-            },
+            LuaValue::String(ptr) => write!(f, "String(\"{}\")", ptr.get()),
             LuaValue::Table(ptr) => write!(f, "Table({:p})", ptr),
             LuaValue::Function(ptr) => write!(f, "LFunc({:p})", ptr),
             LuaValue::CFunc(_) => write!(f, "CFunc"),
+            LuaValue::NativeClosure(ptr) => write!(f, "NativeClosure({:p})", ptr),
+            LuaValue::Coroutine(ptr) => write!(f, "Coroutine({:p})", ptr),
             LuaValue::UserData(ptr) => write!(f, "UserData({:p})", ptr),
-            LuaValue::TempString(s) => write!(f, "TempString(\"{}\")", s),
         }
     }
 }
@@ -117,28 +427,71 @@ impl fmt::Display for LuaValue {
         match self {
             LuaValue::Nil => write!(f, "nil"),
             LuaValue::Number(n) => write!(f, "{}", n),
+            LuaValue::Integer(i) => write!(f, "{}", i),
             LuaValue::Boolean(b) => write!(f, "{}", b),
-            LuaValue::String(ptr) => unsafe {
-                if ptr.is_null() {
-                    write!(f, "null")
-                } else {
-                    write!(f, "\"{}\"", (*(*ptr)).data)
-                }
-            },
-            LuaValue::TempString(s) => write!(f, "\"{}\"", s),
+            LuaValue::String(ptr) => write!(f, "\"{}\"", ptr.get()),
             _ => write!(f, "{:?}", self),
         }
     }
 }
 
-#[derive(Debug)]
+/// a constant pool entry as the emitter produces it and a .myb image stores it, strings are
+/// only turned into heap objects by `VirtualMachine::load_functions`
+#[derive(Debug, Clone)]
+pub enum Constant {
+    Nil,
+    Number(f64),
+    Integer(i64),
+    String(String),
+}
+
+// numbers compare by their bits, so 0.0 and -0.0 stay two constants and NaN is deduplicated
+impl PartialEq for Constant {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Constant::Nil, Constant::Nil) => true,
+            (Constant::Number(a), Constant::Number(b)) => a.to_bits() == b.to_bits(),
+            (Constant::Integer(a), Constant::Integer(b)) => a == b,
+            (Constant::String(a), Constant::String(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Constant {}
+
+impl std::hash::Hash for Constant {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Constant::Nil => (),
+            Constant::Number(n) => n.to_bits().hash(state),
+            Constant::Integer(i) => i.hash(state),
+            Constant::String(s) => s.hash(state),
+        }
+    }
+}
+
+/// a closure: the prototype it was made from, shared with the VM and every other closure of it,
+/// and the upvalues it captured
 pub struct LFunction {
-    pub name: String,
-    pub opcodes: Vec<crate::common::opcode::OpCode>,
-    pub constants: Vec<LuaValue>,
-    pub upvalues: Vec<*mut GCObject<LuaUpValue>>,
-    pub num_locals: usize,
-    pub max_stack_size: usize,
+    pub proto: Rc<LoadedFunction>,
+    pub upvalues: Vec<Gc<LuaUpValue>>,
+}
+
+impl LFunction {
+    pub fn name(&self) -> &Rc<str> {
+        &self.proto.name
+    }
+}
+
+impl fmt::Debug for LFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LFunction")
+            .field("proto", &self.proto.name)
+            .field("upvalues", &self.upvalues)
+            .finish()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -148,22 +501,15 @@ pub struct LuaObject {
 
 #[derive(Clone)]
 pub struct LuaSymbol {
-    pub name: *mut GCObject<String>,
+    pub name: Gc<String>,
     pub value: LuaValue,
 }
 
 impl fmt::Debug for LuaSymbol {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        unsafe {
-            let name_str = if self.name.is_null() {
-                "NULL"
-            } else {
-                &(*self.name).data
-            };
-            f.debug_struct("LuaSymbol")
-                .field("name", &name_str)
-                .field("value", &self.value)
-                .finish()
-        }
+        f.debug_struct("LuaSymbol")
+            .field("name", self.name.get())
+            .field("value", &self.value)
+            .finish()
     }
 }
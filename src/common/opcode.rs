@@ -1,4 +1,13 @@
 use std::fmt;
+
+// values stored by one SETLIST at most, its batch number counts in these
+pub const SETLIST_BATCH: usize = 50;
+
+// the argc / retc of a CALL or the count of a RETURN that stands for "all of them": a CALL with this
+// retc keeps every result, as argc or RETURN count it takes the values pushed for it followed by
+// every result of the CALL right before it
+pub const MULTRET: u8 = u8::MAX;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum UnaryOpType {
     Neg,
@@ -77,6 +86,12 @@ pub enum OpCode {
         left: u16,
         right: u16,
     },
+    // R[dest] = R[start] .. R[start + 1] .. ... .. R[start + count - 1]
+    ConcatN {
+        dest: u16,
+        start: u16,
+        count: u16,
+    },
     And {
         dest: u16,
         left: u16,
@@ -87,6 +102,18 @@ pub enum OpCode {
         left: u16,
         right: u16,
     },
+    // R[dest] = R[left] + K[const_idx], the constant is a number
+    AddK {
+        dest: u16,
+        left: u16,
+        const_idx: u16,
+    },
+    // R[dest] = R[left] - K[const_idx], the constant is a number
+    SubK {
+        dest: u16,
+        left: u16,
+        const_idx: u16,
+    },
 
     UnOp {
         dest: u16,
@@ -131,6 +158,16 @@ pub enum OpCode {
     Jump {
         offset: i32,
     },
+    // jump by offset when R[reg] is falsy, TEST and the JUMP after it in one instruction
+    JumpIfFalse {
+        reg: u16,
+        offset: i32,
+    },
+    // jump by the offset jump_tables[table] gives for R[reg], see JumpTable
+    Switch {
+        reg: u16,
+        table: u16,
+    },
 
     NewTable {
         dest: u16,
@@ -147,27 +184,264 @@ pub enum OpCode {
         key: u16,
         value: u16,
     },
+    // R[table][batch * SETLIST_BATCH + i] = the i-th of the last `count` values pushed, i from 1;
+    // the values are popped
+    SetList {
+        table: u16,
+        count: u16,
+        batch: u16,
+    },
+    // R[dest] = R[table][K[key]], the key is an interned string constant
+    GetField {
+        dest: u16,
+        table: u16,
+        key: u16,
+    },
+    // R[table][K[key]] = R[value]
+    SetField {
+        table: u16,
+        key: u16,
+        value: u16,
+    },
 
     FnProto {
         dest: u16,
         proto_idx: u16,
     },
+    // R[func_reg](the last argc values pushed), the first result goes to R[func_reg]; unless retc is 1
+    // the VM also keeps the results for the GETRESULTs after the CALL
     Call {
         func_reg: u16,
         argc: u8,
         retc: u8,
     },
+    // R[dest] = result `index` of the last CALL, nil past the values it returned
+    GetResult {
+        dest: u16,
+        index: u8,
+    },
     Push {
         src: u16,
     },
+    // return R[start], ..., R[start + count - 1]
     Return {
         start: u16,
         count: u8,
     },
+    // return R[func_reg](args...), the callee reuses the frame of the caller
+    TailCall {
+        func_reg: u16,
+        argc: u8,
+    },
 
     Halt,
 }
 
+// the jump table of a SWITCH: a number with an integer value in low..low + targets.len()
+// jumps by the offset at its index, anything else by default; like all jump offsets
+// they are relative to the pc of the SWITCH
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpTable {
+    pub low: i64,
+    pub targets: Vec<i32>,
+    pub default: i32,
+}
+
+impl JumpTable {
+    pub fn offset(&self, key: i64) -> i32 {
+        key.checked_sub(self.low)
+            .and_then(|i| usize::try_from(i).ok())
+            .and_then(|i| self.targets.get(i).copied())
+            .unwrap_or(self.default)
+    }
+}
+
+// the role an operand plays in an instruction, the disassembler uses it to name registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    Reg,   // register index, relative to the frame base
+    Const, // index into the constant pool of the owning function
+    UpVal, // index into the upvalue list of the owning closure
+    Proto, // index into the sub-prototype list of the owning function
+}
+
+impl OpCode {
+    // visit every u16 operand of the instruction mutably, the one match over every opcode
+    // that visit_operands is written on
+    //
+    // jump offsets and immediate counts (argc, retc, result index, table size hints) are not visited,
+    // since they are not indices into any relocatable space
+    fn visit_operands_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(OperandKind, &mut u16),
+    {
+        use OperandKind::*;
+        match self {
+            OpCode::LoadK { dest, const_idx } => {
+                f(Reg, dest);
+                f(Const, const_idx);
+            }
+            OpCode::LoadNil { dest } | OpCode::LoadBool { dest, .. } => f(Reg, dest),
+            OpCode::Move { dest, src } => {
+                f(Reg, dest);
+                f(Reg, src);
+            }
+            OpCode::GetGlobal { dest, name_idx } => {
+                f(Reg, dest);
+                f(Const, name_idx);
+            }
+            OpCode::SetGlobal { name_idx, src } => {
+                f(Const, name_idx);
+                f(Reg, src);
+            }
+            OpCode::GetUpVal { dest, upval_idx } => {
+                f(Reg, dest);
+                f(UpVal, upval_idx);
+            }
+            OpCode::SetUpVal { upval_idx, src } => {
+                f(UpVal, upval_idx);
+                f(Reg, src);
+            }
+            OpCode::Add { dest, left, right }
+            | OpCode::Sub { dest, left, right }
+            | OpCode::Mul { dest, left, right }
+            | OpCode::Div { dest, left, right }
+            | OpCode::Mod { dest, left, right }
+            | OpCode::Pow { dest, left, right }
+            | OpCode::Concat { dest, left, right }
+            | OpCode::And { dest, left, right }
+            | OpCode::Or { dest, left, right }
+            | OpCode::Eq { dest, left, right }
+            | OpCode::Ne { dest, left, right }
+            | OpCode::Lt { dest, left, right }
+            | OpCode::Gt { dest, left, right }
+            | OpCode::Le { dest, left, right }
+            | OpCode::Ge { dest, left, right } => {
+                f(Reg, dest);
+                f(Reg, left);
+                f(Reg, right);
+            }
+            OpCode::ConcatN { dest, start, .. } => {
+                f(Reg, dest);
+                f(Reg, start);
+            }
+            OpCode::AddK {
+                dest,
+                left,
+                const_idx,
+            }
+            | OpCode::SubK {
+                dest,
+                left,
+                const_idx,
+            } => {
+                f(Reg, dest);
+                f(Reg, left);
+                f(Const, const_idx);
+            }
+            OpCode::UnOp { dest, src, .. } => {
+                f(Reg, dest);
+                f(Reg, src);
+            }
+            OpCode::Test { reg } | OpCode::JumpIfFalse { reg, .. } | OpCode::Switch { reg, .. } => {
+                f(Reg, reg)
+            }
+            OpCode::Jump { .. } => {}
+            OpCode::NewTable { dest, .. } => f(Reg, dest),
+            OpCode::GetTable { dest, table, key } => {
+                f(Reg, dest);
+                f(Reg, table);
+                f(Reg, key);
+            }
+            OpCode::SetTable { table, key, value } => {
+                f(Reg, table);
+                f(Reg, key);
+                f(Reg, value);
+            }
+            OpCode::SetList { table, .. } => f(Reg, table),
+            OpCode::GetField { dest, table, key } => {
+                f(Reg, dest);
+                f(Reg, table);
+                f(Const, key);
+            }
+            OpCode::SetField { table, key, value } => {
+                f(Reg, table);
+                f(Const, key);
+                f(Reg, value);
+            }
+            OpCode::FnProto { dest, proto_idx } => {
+                f(Reg, dest);
+                f(Proto, proto_idx);
+            }
+            OpCode::Call { func_reg, .. } => f(Reg, func_reg),
+            OpCode::GetResult { dest, .. } => f(Reg, dest),
+            OpCode::Push { src } => f(Reg, src),
+            OpCode::Return { start, .. } => f(Reg, start),
+            OpCode::TailCall { func_reg, .. } => f(Reg, func_reg),
+            OpCode::Halt => {}
+        }
+    }
+
+    // the mnemonic the listing shows, e.g. "GETFIELD"
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpCode::LoadK { .. } => "LOADK",
+            OpCode::LoadNil { .. } => "LOADNIL",
+            OpCode::LoadBool { .. } => "LOADBOOL",
+            OpCode::Move { .. } => "MOVE",
+            OpCode::GetGlobal { .. } => "GETGLOBAL",
+            OpCode::SetGlobal { .. } => "SETGLOBAL",
+            OpCode::GetUpVal { .. } => "GETUPVAL",
+            OpCode::SetUpVal { .. } => "SETUPVAL",
+            OpCode::Add { .. } => "ADD",
+            OpCode::Sub { .. } => "SUB",
+            OpCode::Mul { .. } => "MUL",
+            OpCode::Div { .. } => "DIV",
+            OpCode::Mod { .. } => "MOD",
+            OpCode::Pow { .. } => "POW",
+            OpCode::Concat { .. } => "CONCAT",
+            OpCode::ConcatN { .. } => "CONCATN",
+            OpCode::And { .. } => "AND",
+            OpCode::Or { .. } => "OR",
+            OpCode::AddK { .. } => "ADDK",
+            OpCode::SubK { .. } => "SUBK",
+            OpCode::UnOp { .. } => "UNOP",
+            OpCode::Eq { .. } => "EQ",
+            OpCode::Ne { .. } => "NE",
+            OpCode::Lt { .. } => "LT",
+            OpCode::Gt { .. } => "GT",
+            OpCode::Le { .. } => "LE",
+            OpCode::Ge { .. } => "GE",
+            OpCode::Test { .. } => "TEST",
+            OpCode::Jump { .. } => "JUMP",
+            OpCode::JumpIfFalse { .. } => "JMPFALSE",
+            OpCode::Switch { .. } => "SWITCH",
+            OpCode::NewTable { .. } => "NEWTABLE",
+            OpCode::GetTable { .. } => "GETTABLE",
+            OpCode::SetTable { .. } => "SETTABLE",
+            OpCode::SetList { .. } => "SETLIST",
+            OpCode::GetField { .. } => "GETFIELD",
+            OpCode::SetField { .. } => "SETFIELD",
+            OpCode::FnProto { .. } => "FNPROTO",
+            OpCode::Call { .. } => "CALL",
+            OpCode::GetResult { .. } => "GETRESULT",
+            OpCode::Push { .. } => "PUSH",
+            OpCode::Return { .. } => "RETURN",
+            OpCode::TailCall { .. } => "TAILCALL",
+            OpCode::Halt => "HALT",
+        }
+    }
+
+    // every u16 operand of the instruction with the role it plays, in operand order
+    pub fn visit_operands<F>(&self, mut f: F)
+    where
+        F: FnMut(OperandKind, u16),
+    {
+        let mut copy = *self;
+        copy.visit_operands_mut(|kind, idx| f(kind, *idx));
+    }
+}
+
 impl fmt::Display for OpCode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -197,6 +471,16 @@ impl fmt::Display for OpCode {
             OpCode::Pow { dest, left, right } => {
                 write!(f, "POW      R{} R{} R{}", dest, left, right)
             }
+            OpCode::AddK {
+                dest,
+                left,
+                const_idx,
+            } => write!(f, "ADDK     R{} R{} K{}", dest, left, const_idx),
+            OpCode::SubK {
+                dest,
+                left,
+                const_idx,
+            } => write!(f, "SUBK     R{} R{} K{}", dest, left, const_idx),
             OpCode::Eq { dest, left, right } => {
                 write!(f, "EQ       R{} R{} R{}", dest, left, right)
             }
@@ -227,19 +511,37 @@ impl fmt::Display for OpCode {
             OpCode::SetTable { table, key, value } => {
                 write!(f, "SETTABLE R{} R{} R{}", table, key, value)
             }
+            OpCode::SetList {
+                table,
+                count,
+                batch,
+            } => write!(f, "SETLIST  R{} {} {}", table, count, batch),
+            OpCode::GetField { dest, table, key } => {
+                write!(f, "GETFIELD R{} R{} K{}", dest, table, key)
+            }
+            OpCode::SetField { table, key, value } => {
+                write!(f, "SETFIELD R{} K{} R{}", table, key, value)
+            }
             OpCode::Call {
                 func_reg,
                 argc,
                 retc,
             } => write!(f, "CALL     R{} {} {}", func_reg, argc, retc),
+            OpCode::GetResult { dest, index } => write!(f, "GETRESULT R{} {}", dest, index),
             OpCode::Push { src } => write!(f, "PUSH     R{}", src),
             OpCode::Return { start, count } => write!(f, "RETURN   R{} {}", start, count),
+            OpCode::TailCall { func_reg, argc } => write!(f, "TAILCALL R{} {}", func_reg, argc),
             OpCode::Jump { offset } => write!(f, "JUMP     {}", offset),
             OpCode::Test { reg } => write!(f, "TEST     R{}", reg),
+            OpCode::JumpIfFalse { reg, offset } => write!(f, "JMPFALSE R{} {}", reg, offset),
+            OpCode::Switch { reg, table } => write!(f, "SWITCH   R{} T{}", reg, table),
             OpCode::FnProto { dest, proto_idx } => write!(f, "FNPROTO  R{} K{}", dest, proto_idx),
             OpCode::Concat { dest, left, right } => {
                 write!(f, "CONCAT   R{} R{} R{}", dest, left, right)
             }
+            OpCode::ConcatN { dest, start, count } => {
+                write!(f, "CONCATN  R{} R{} {}", dest, start, count)
+            }
             OpCode::Halt => write!(f, "HALT"),
             _ => write!(f, "{:?}", self),
         }
@@ -0,0 +1,397 @@
+// Myula bytecode debugger
+// Changelog:
+// 2026-02-24: Initial version. A `Debugger` is attached to a VM as a hook on every instruction and stops
+//            before the first one; at each stop it reads commands (myulac --debug reads them from stdin):
+//            step a number of instructions, run to a breakpoint at a function and pc, show the registers
+//            of the current frame, the call stack, the code around the pc and the value stack, or dump the
+//            whole VM state with `dump_internal_state`. End of input detaches it and the script runs on.
+
+use crate::backend::vm::VirtualMachine;
+use crate::backend::vm::error::{ErrorKind, VMError};
+use crate::backend::vm::hook::HookMask;
+use crate::backend::vm::std_lib::raw_tostring;
+use crate::common::object::LuaValue;
+use std::io::{BufRead, Write};
+
+pub const PROMPT: &str = "(mdb) ";
+
+// instructions shown before and after the current one by `list`
+const LIST_CONTEXT: usize = 4;
+
+const HELP: &str = "\
+commands:
+  step [n]          s   run n instructions (default 1) and stop
+  continue          c   run until a breakpoint is reached
+  break [func] pc   b   stop before instruction pc of func (default: the current function)
+  delete n          d   remove breakpoint n
+  breakpoints       bl  list the breakpoints
+  registers         r   registers of the current frame
+  backtrace         bt  the call stack, innermost frame first
+  list              l   the code around the current instruction
+  stack             vs  every slot of the value stack
+  functions         f   the compiled functions
+  dump                  the whole VM state
+  quit              q   stop the script
+  help              h   this text
+an empty line repeats the last command";
+
+/// where execution stops: before instruction `pc` of every function matching `function`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub function: String,
+    pub pc: usize,
+}
+
+// what the debugger does after a command
+enum Action {
+    // read the next command
+    Prompt,
+    // let the VM run on
+    Resume,
+    // stop the script
+    Quit,
+}
+
+pub struct Debugger {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    breakpoints: Vec<Breakpoint>,
+    // instructions to run before stopping again, None runs until a breakpoint
+    steps_left: Option<u64>,
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Self {
+            input,
+            output,
+            breakpoints: Vec::new(),
+            steps_left: Some(1),
+            last_command: String::new(),
+        }
+    }
+
+    /// stop at `pc` of `function`, either an internal name (`__local_fn_add_3`) or the name in the source
+    pub fn add_breakpoint(&mut self, function: &str, pc: usize) {
+        self.breakpoints.push(Breakpoint {
+            function: function.to_string(),
+            pc,
+        });
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// install the debugger as the hook of `vm`, replacing any other hook; it stops before the next
+    /// instruction runs
+    pub fn attach(mut self, vm: &mut VirtualMachine) {
+        vm.set_hook(HookMask::count(1), move |vm, _| self.on_instruction(vm));
+    }
+
+    fn on_instruction(&mut self, vm: &mut VirtualMachine) -> Result<(), VMError> {
+        let Some(frame) = vm.frame_info(0) else {
+            return Ok(());
+        };
+        let hit = self
+            .breakpoints
+            .iter()
+            .position(|bp| bp.pc == frame.pc && function_matches(&frame.func_name, &bp.function));
+        let stepped = match self.steps_left.as_mut() {
+            Some(left) => {
+                *left = left.saturating_sub(1);
+                *left == 0
+            }
+            None => false,
+        };
+        if hit.is_none() && !stepped {
+            return Ok(());
+        }
+
+        if let Some(idx) = hit {
+            let _ = writeln!(self.output, "breakpoint #{} reached", idx + 1);
+        }
+        self.show_location(vm);
+        loop {
+            let _ = write!(self.output, "{}", PROMPT);
+            let _ = self.output.flush();
+            let mut line = String::new();
+            // end of input: nobody is left to ask, the script runs to its end
+            if matches!(self.input.read_line(&mut line), Ok(0) | Err(_)) {
+                let _ = writeln!(self.output);
+                vm.remove_hook();
+                return Ok(());
+            }
+            let line = line.trim();
+            let command = if line.is_empty() {
+                self.last_command.clone()
+            } else {
+                self.last_command = line.to_string();
+                line.to_string()
+            };
+            match self.command(vm, &command) {
+                Action::Prompt => {}
+                Action::Resume => return Ok(()),
+                Action::Quit => {
+                    return Err(vm.error(ErrorKind::Interrupted(
+                        "execution stopped from the debugger".into(),
+                    )));
+                }
+            }
+        }
+    }
+
+    fn command(&mut self, vm: &mut VirtualMachine, command: &str) -> Action {
+        let mut words = command.split_whitespace();
+        let Some(name) = words.next() else {
+            return Action::Prompt;
+        };
+        let args: Vec<&str> = words.collect();
+        match name {
+            "step" | "s" => match args.first().map(|n| n.parse::<u64>()) {
+                None => {
+                    self.steps_left = Some(1);
+                    Action::Resume
+                }
+                Some(Ok(n)) if n > 0 => {
+                    self.steps_left = Some(n);
+                    Action::Resume
+                }
+                Some(_) => self.complain("step takes a positive number of instructions"),
+            },
+            "continue" | "c" => {
+                self.steps_left = None;
+                Action::Resume
+            }
+            "break" | "b" => self.set_breakpoint(vm, &args),
+            "delete" | "d" => match args.first().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if n >= 1 && n <= self.breakpoints.len() => {
+                    let bp = self.breakpoints.remove(n - 1);
+                    let _ = writeln!(
+                        self.output,
+                        "deleted breakpoint #{} at {} pc {}",
+                        n, bp.function, bp.pc
+                    );
+                    Action::Prompt
+                }
+                _ => self.complain("usage: delete <breakpoint number>, see `breakpoints`"),
+            },
+            "breakpoints" | "bl" => {
+                if self.breakpoints.is_empty() {
+                    let _ = writeln!(self.output, "no breakpoints");
+                }
+                for (i, bp) in self.breakpoints.iter().enumerate() {
+                    let _ = writeln!(self.output, "  #{} {} pc {}", i + 1, bp.function, bp.pc);
+                }
+                Action::Prompt
+            }
+            "registers" | "r" => {
+                self.show_registers(vm);
+                Action::Prompt
+            }
+            "backtrace" | "bt" => {
+                self.show_backtrace(vm);
+                Action::Prompt
+            }
+            "list" | "l" => {
+                self.show_code(vm);
+                Action::Prompt
+            }
+            "stack" | "vs" => {
+                self.show_value_stack(vm);
+                Action::Prompt
+            }
+            "functions" | "f" => {
+                let mut names: Vec<_> = vm.func_meta.keys().collect();
+                names.sort();
+                for name in names {
+                    let _ = writeln!(
+                        self.output,
+                        "  {} ({} instructions)",
+                        name,
+                        vm.func_meta[name].bytecode.len()
+                    );
+                }
+                Action::Prompt
+            }
+            "dump" => {
+                let _ = vm.write_internal_state(&mut self.output);
+                Action::Prompt
+            }
+            "quit" | "q" => Action::Quit,
+            "help" | "h" => {
+                let _ = writeln!(self.output, "{}", HELP);
+                Action::Prompt
+            }
+            _ => self.complain(&format!("unknown command '{}', try `help`", name)),
+        }
+    }
+
+    fn complain(&mut self, message: &str) -> Action {
+        let _ = writeln!(self.output, "{}", message);
+        Action::Prompt
+    }
+
+    // break <pc> | break <function> <pc>
+    fn set_breakpoint(&mut self, vm: &VirtualMachine, args: &[&str]) -> Action {
+        let (function, pc) = match args {
+            [pc] => (vm.frame_info(0).map(|f| f.func_name), pc),
+            [function, pc] => (Some(function.to_string()), pc),
+            _ => return self.complain("usage: break [function] <pc>"),
+        };
+        let (Some(function), Ok(pc)) = (function, pc.parse::<usize>()) else {
+            return self.complain("usage: break [function] <pc>");
+        };
+
+        let matching: Vec<_> = vm
+            .func_meta
+            .iter()
+            .filter(|(name, _)| function_matches(name, &function))
+            .collect();
+        if matching.is_empty() {
+            return self.complain(&format!(
+                "no function named '{}', see `functions`",
+                function
+            ));
+        }
+        if matching.iter().all(|(_, meta)| pc >= meta.bytecode.len()) {
+            return self.complain(&format!("'{}' has no instruction at pc {}", function, pc));
+        }
+        self.add_breakpoint(&function, pc);
+        let _ = writeln!(
+            self.output,
+            "breakpoint #{} at {} pc {}",
+            self.breakpoints.len(),
+            function,
+            pc
+        );
+        Action::Prompt
+    }
+
+    // e.g. "__local_fn_add_3 (main.lua:2) pc 004: ADD      R2 R0 R1"
+    fn show_location(&mut self, vm: &VirtualMachine) {
+        let Some(frame) = vm.frame_info(0) else {
+            return;
+        };
+        let instr = vm
+            .call_stack
+            .last()
+            .and_then(|f| f.meta.as_ref())
+            .and_then(|meta| meta.bytecode.get(frame.pc))
+            .map_or(String::new(), |op| op.to_string());
+        let line = frame.line.map_or("?".to_string(), |l| l.to_string());
+        let _ = writeln!(
+            self.output,
+            "{} ({}:{}) pc {:03}: {}",
+            frame.func_name, frame.chunk_name, line, frame.pc, instr
+        );
+    }
+
+    fn show_registers(&mut self, vm: &VirtualMachine) {
+        let Some(frame) = vm.call_stack.last() else {
+            return;
+        };
+        let num_locals = frame.meta.as_ref().map_or(0, |meta| meta.num_locals);
+        for reg in 0..frame.reg_count {
+            let Some(val) = vm.frame_register(0, reg) else {
+                break;
+            };
+            let kind = if reg < num_locals { "local" } else { "temp" };
+            let _ = writeln!(
+                self.output,
+                "  R{:<4} {:<6} {}",
+                reg,
+                kind,
+                show_value(&val)
+            );
+        }
+    }
+
+    fn show_backtrace(&mut self, vm: &VirtualMachine) {
+        for level in 0..vm.call_stack.len() {
+            let Some(frame) = vm.frame_info(level) else {
+                break;
+            };
+            if frame.native {
+                let _ = writeln!(self.output, "  #{} {} [native]", level, frame.func_name);
+                continue;
+            }
+            let line = frame.line.map_or("?".to_string(), |l| l.to_string());
+            let _ = writeln!(
+                self.output,
+                "  #{} {} ({}:{}) pc {:03}",
+                level, frame.func_name, frame.chunk_name, line, frame.pc
+            );
+        }
+    }
+
+    fn show_code(&mut self, vm: &VirtualMachine) {
+        let Some(frame) = vm.call_stack.last() else {
+            return;
+        };
+        let Some(meta) = frame.meta.as_ref() else {
+            return;
+        };
+        let pc = frame.instr_pc;
+        let end = (pc + LIST_CONTEXT + 1).min(meta.bytecode.len());
+        for at in pc.saturating_sub(LIST_CONTEXT)..end {
+            let marker = if at == pc {
+                "=>"
+            } else if self
+                .breakpoints
+                .iter()
+                .any(|bp| bp.pc == at && function_matches(&frame.func_name, &bp.function))
+            {
+                " *"
+            } else {
+                "  "
+            };
+            let line = match meta.line_info.get(at) {
+                Some(l) if *l > 0 => format!("[{:>4}]", l),
+                _ => "[   -]".to_string(),
+            };
+            let _ = writeln!(
+                self.output,
+                "{} {:03} {} {}",
+                marker, at, line, meta.bytecode[at]
+            );
+        }
+    }
+
+    fn show_value_stack(&mut self, vm: &VirtualMachine) {
+        // the frame each slot belongs to, by the frame's base
+        let mut bases: Vec<_> = vm
+            .call_stack
+            .iter()
+            .map(|f| (f.base_offset, &*f.func_name))
+            .collect();
+        bases.sort_by_key(|(base, _)| *base);
+        for (idx, val) in vm.value_stack.values.iter().enumerate() {
+            for (_, name) in bases.iter().filter(|(base, _)| *base == idx) {
+                let _ = writeln!(self.output, "  -- {}", name);
+            }
+            let _ = writeln!(self.output, "  [{}] {}", idx, show_value(val));
+        }
+    }
+}
+
+// strings are quoted so "1" and 1 can be told apart
+fn show_value(val: &LuaValue) -> String {
+    match val {
+        LuaValue::String(_) => format!("{:?}", raw_tostring(val)),
+        _ => raw_tostring(val),
+    }
+}
+
+// a breakpoint names a function by its internal name, the name after the chunk prefix of the facade
+// (`__chunk_1::_start`), or the name in the source (`add` for `__local_fn_add_3`)
+fn function_matches(internal: &str, name: &str) -> bool {
+    let short = internal.rsplit("::").next().unwrap_or(internal);
+    internal == name
+        || short == name
+        || short
+            .strip_prefix("__local_fn_")
+            .and_then(|rest| rest.rsplit_once('_'))
+            .is_some_and(|(source, id)| source == name && id.parse::<usize>().is_ok())
+}
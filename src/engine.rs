@@ -0,0 +1,571 @@
+// Myula embedding engine
+// Changelog:
+// 2026-02-23: Initial version. `Engine::eval_expr` evaluates a single expression, e.g. the contents of a
+//            config file such as `{width = 800, height = 600}`, inside a restricted sandbox and converts
+//            the result into the owned `Value` tree, so hosts never touch GC pointers.
+// 2026-02-24: `Value::display_lua` stringifies a value exactly like print/tostring would inside the
+//            script. Tables and functions remember the heap address they had when the snapshot was taken.
+// 2026-02-24: `Value::Integer` mirrors the integer subtype; integers and floats with the same
+//            mathematical value compare equal, like `==` does in the script.
+// 2026-02-24: The `Myula` facade: compile whole chunks once (`Chunk`), run them on a persistent VM,
+//            read and write globals and call script functions with `Value`s, without touching the
+//            lexer / parser / IR / scanner pipeline.
+// 2026-02-24: `Value::Thread` for coroutines, which like functions cannot leave the VM.
+// 2026-02-24: `Myula::with_config` runs scripts on a VM restricted by a `VmConfig`.
+// 2026-02-24: `Value::UserData` for userdata, which like functions cannot leave the VM.
+// 2026-02-24: `Chunk` shares its functions through `Arc` and is `Send + Sync`, compile once and run it
+//            on a `Myula` per worker thread.
+// 2026-02-24: `EngineError::Emit` for a chunk that does not fit the bytecode operands.
+
+use crate::backend::translator::emitter::EmitError;
+use crate::backend::translator::scanner::Scanner;
+use crate::backend::vm::config::VmConfig;
+use crate::backend::vm::error::{ErrorKind, VMError};
+use crate::backend::vm::heap::Heap;
+use crate::backend::vm::std_lib::format_number;
+use crate::backend::vm::{FuncMetadata, LogLevel, VirtualMachine, share_functions};
+use crate::common::object::{LuaTable, LuaValue, float_to_integer};
+use crate::frontend::ir::{IRGenerator, IRGeneratorError};
+use crate::frontend::lexer::Lexer;
+use crate::frontend::parser::ast::{Expr, Expression, Literal, Statement, Stmt};
+use crate::frontend::parser::{Parser, ParserError};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+/// an owned snapshot of a Lua value, detached from the VM heap
+///
+/// `addr` is the heap address the table / function had in the VM, it is only used for
+/// display and is ignored when comparing values
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    Integer(i64),
+    String(String),
+    // entries ordered by key: booleans, then numbers, then strings
+    Table {
+        addr: usize,
+        entries: Vec<(Value, Value)>,
+    },
+    // functions cannot leave the VM, only their presence is reported
+    Function {
+        addr: usize,
+    },
+    // a coroutine, reported the same way
+    Thread {
+        addr: usize,
+    },
+    // a Rust value created with `VirtualMachine::create_userdata`, reported the same way
+    UserData {
+        addr: usize,
+    },
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Integer(i), Value::Number(n)) | (Value::Number(n), Value::Integer(i)) => {
+                LuaValue::Integer(*i).raw_equal(&LuaValue::Number(*n))
+            }
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Table { entries: a, .. }, Value::Table { entries: b, .. }) => a == b,
+            (Value::Function { .. }, Value::Function { .. }) => true,
+            (Value::Thread { .. }, Value::Thread { .. }) => true,
+            (Value::UserData { .. }, Value::UserData { .. }) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Value {
+    /// look up a string key of a table
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Table { entries, .. } => entries
+                .iter()
+                .find(|(k, _)| matches!(k, Value::String(s) if s == key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// look up an integer key of a table, i.e. an array element (1-based)
+    pub fn index(&self, idx: usize) -> Option<&Value> {
+        match self {
+            Value::Table { entries, .. } => entries
+                .iter()
+                .find(|(k, _)| matches!(k, Value::Integer(i) if *i == idx as i64))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Integer(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    /// integers, and floats holding an exact integer value
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            Value::Number(n) => float_to_integer(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// the string print/tostring produce for this value inside a script,
+    /// e.g. `nil`, `0.1`, `1e+15` or `table: 0x55d0c2a4b2c0`
+    ///
+    /// `__tostring` is not consulted, the snapshot no longer has a metatable
+    pub fn display_lua(&self) -> String {
+        match self {
+            Value::Nil => "nil".to_string(),
+            Value::Boolean(b) => b.to_string(),
+            Value::Number(n) => format_number(*n),
+            Value::Integer(i) => i.to_string(),
+            Value::String(s) => s.clone(),
+            Value::Table { addr, .. } => format!("table: {:#x}", addr),
+            Value::Function { addr } => format!("function: {:#x}", addr),
+            Value::Thread { addr } => format!("thread: {:#x}", addr),
+            Value::UserData { addr } => format!("userdata: {:#x}", addr),
+        }
+    }
+
+    // total order used to sort table entries
+    fn sort_key(&self) -> (u8, f64, &str) {
+        match self {
+            Value::Boolean(b) => (0, *b as u8 as f64, ""),
+            Value::Number(n) => (1, *n, ""),
+            Value::Integer(i) => (1, *i as f64, ""),
+            Value::String(s) => (2, 0.0, s),
+            _ => (3, 0.0, ""),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum EngineError {
+    // the source is not a single well-formed expression
+    Parse(Vec<ParserError>),
+    // the source parses but cannot be compiled, e.g. a goto without a label
+    Compile(Vec<IRGeneratorError>),
+    // a function does not fit the bytecode, e.g. too many constants
+    Emit(EmitError),
+    // the expression uses a construct the sandbox forbids
+    Sandbox(String),
+    Runtime(VMError),
+    // the result cannot be represented as a `Value`, e.g. a cyclic table
+    Conversion(String),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Parse(errs) => {
+                write!(f, "SyntaxException: ")?;
+                for (i, e) in errs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{} (line {})", e.message, e.line)?;
+                }
+                Ok(())
+            }
+            EngineError::Compile(errs) => {
+                write!(f, "CompilationException: ")?;
+                for (i, e) in errs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{:?}", e)?;
+                }
+                Ok(())
+            }
+            EngineError::Emit(e) => write!(f, "CompilationException: {}", e),
+            EngineError::Sandbox(m) => write!(f, "SandboxViolationException: {}", m),
+            EngineError::Runtime(e) => write!(f, "{}", e),
+            EngineError::Conversion(m) => write!(f, "ConversionException: {}", m),
+        }
+    }
+}
+
+pub struct Engine {
+    log_level: LogLevel,
+    allow_calls: bool,
+    allow_functions: bool,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    /// a fully restricted engine: no function calls, no function definitions
+    pub fn new() -> Self {
+        Self {
+            log_level: LogLevel::Release,
+            allow_calls: false,
+            allow_functions: false,
+        }
+    }
+
+    pub fn with_log_level(mut self, log_level: LogLevel) -> Self {
+        self.log_level = log_level;
+        self
+    }
+
+    /// allow calling functions (builtins included) inside evaluated expressions
+    pub fn with_calls(mut self, enabled: bool) -> Self {
+        self.allow_calls = enabled;
+        self
+    }
+
+    /// allow function literals inside evaluated expressions
+    pub fn with_functions(mut self, enabled: bool) -> Self {
+        self.allow_functions = enabled;
+        self
+    }
+
+    /// evaluate a single expression and return its value
+    ///
+    /// the source is compiled as `return (<expr>)`, anything that does not parse
+    /// back into exactly that shape is rejected, so statements cannot be smuggled in
+    pub fn eval_expr(&self, expr: &str) -> Result<Value, EngineError> {
+        let source = format!("return ({})", expr);
+        let mut lexer = Lexer::new(&source);
+        let mut parser = Parser::new(&mut lexer);
+        let program = parser.parse();
+        if !parser.get_err().is_empty() {
+            return Err(EngineError::Parse(parser.get_err().clone()));
+        }
+
+        match program.body.as_slice() {
+            [
+                Stmt {
+                    node: Statement::ReturnStmt { values },
+                    ..
+                },
+            ] if values.len() == 1 => self.check_expr(&values[0])?,
+            _ => {
+                return Err(EngineError::Sandbox(
+                    "source must consist of a single expression".into(),
+                ));
+            }
+        }
+
+        let mut ir_gen = IRGenerator::new();
+        ir_gen.generate(&program);
+
+        let mut scanner = Scanner::new();
+        scanner.global_scan(ir_gen.get_module());
+
+        let mut vm = VirtualMachine::new();
+        vm.init(&ir_gen, self.log_level, &mut scanner)
+            .map_err(EngineError::Emit)?;
+        vm.execute().map_err(EngineError::Runtime)?;
+
+        let result = vm.return_buffer.first().cloned().unwrap_or(LuaValue::Nil);
+        to_value(&vm.heap, &result, &mut HashSet::new())
+    }
+
+    // reject the constructs the sandbox does not allow
+    fn check_expr(&self, expr: &Expr) -> Result<(), EngineError> {
+        match &expr.node {
+            Expression::Identifier(_) => Ok(()),
+            Expression::Literal(Literal::Function { .. }) if !self.allow_functions => Err(
+                EngineError::Sandbox("function definitions are not allowed".into()),
+            ),
+            Expression::Literal(_) => Ok(()),
+            Expression::BinOp { left, right, .. } => {
+                self.check_expr(left)?;
+                self.check_expr(right)
+            }
+            Expression::UnOp { operand, .. } => self.check_expr(operand),
+            Expression::FnCall { .. } | Expression::MethodCall { .. } if !self.allow_calls => Err(
+                EngineError::Sandbox("function calls are not allowed".into()),
+            ),
+            Expression::FnCall { callee, arguments } => {
+                self.check_expr(callee)?;
+                arguments.iter().try_for_each(|arg| self.check_expr(arg))
+            }
+            Expression::MethodCall {
+                object, arguments, ..
+            } => {
+                self.check_expr(object)?;
+                arguments.iter().try_for_each(|arg| self.check_expr(arg))
+            }
+            Expression::IndexOf { collection, index } => {
+                self.check_expr(collection)?;
+                self.check_expr(index)
+            }
+            Expression::MemberAccess { collection, .. } => self.check_expr(collection),
+            Expression::TableCtor { fields } => {
+                for (key, value) in fields {
+                    if let Some(key) = key {
+                        self.check_expr(key)?;
+                    }
+                    self.check_expr(value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// a compiled chunk, independent of any VM: it can be run any number of times,
+/// on any number of `Myula` instances, also on other threads (it is `Send + Sync`);
+/// clones share the compiled functions
+#[derive(Clone)]
+pub struct Chunk {
+    funcs: HashMap<String, Arc<FuncMetadata>>,
+}
+
+/// a Lua state for host programs: the standard library is loaded once, globals survive
+/// from one chunk to the next, and values cross the boundary as owned `Value`s
+pub struct Myula {
+    vm: VirtualMachine,
+    // number of chunks loaded so far, their functions are renamed apart with it
+    chunks_loaded: usize,
+}
+
+impl Default for Myula {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Myula {
+    pub fn new() -> Self {
+        Self::with_config(VmConfig::default())
+    }
+
+    /// scripts run on a VM restricted by `config`, e.g. `VmConfig::sandboxed()`
+    pub fn with_config(config: VmConfig) -> Self {
+        let mut vm = VirtualMachine::with_config(config);
+        vm.load_standard_library();
+        Self {
+            vm,
+            chunks_loaded: 0,
+        }
+    }
+
+    /// the VM underneath, e.g. to seed math.random or register a native library
+    pub fn vm_mut(&mut self) -> &mut VirtualMachine {
+        &mut self.vm
+    }
+
+    pub fn compile(source: &str) -> Result<Chunk, EngineError> {
+        let mut lexer = Lexer::new(source);
+        let mut parser = Parser::new(&mut lexer);
+        let program = parser.parse();
+        if !parser.get_err().is_empty() {
+            return Err(EngineError::Parse(parser.get_err().clone()));
+        }
+
+        let mut ir_gen = IRGenerator::new();
+        ir_gen.generate(&program);
+        if !ir_gen.get_err().is_empty() {
+            return Err(EngineError::Compile(ir_gen.get_err().clone()));
+        }
+
+        let mut scanner = Scanner::new();
+        scanner.global_scan(ir_gen.get_module());
+
+        let funcs =
+            VirtualMachine::compile(&ir_gen, &mut scanner, false).map_err(EngineError::Emit)?;
+        Ok(Chunk {
+            funcs: share_functions(funcs),
+        })
+    }
+
+    /// run a chunk to completion and return what it returns at top level
+    pub fn run(&mut self, chunk: &Chunk) -> Result<Vec<Value>, EngineError> {
+        // every chunk names its entry `_start` and numbers its functions from 0,
+        // closures of earlier chunks may still be stored in globals; a restored VM
+        // can already have functions under the next prefix
+        let mut prefix;
+        loop {
+            self.chunks_loaded += 1;
+            prefix = format!("__chunk_{}::", self.chunks_loaded);
+            if !self.vm.func_meta.contains_key(&format!("{}_start", prefix)) {
+                break;
+            }
+        }
+
+        self.vm.return_buffer.clear();
+        self.vm.load_chunk(&chunk.funcs, &prefix);
+        self.vm.execute().map_err(EngineError::Runtime)?;
+
+        let results = std::mem::take(&mut self.vm.return_buffer);
+        results
+            .iter()
+            .map(|val| to_value(&self.vm.heap, val, &mut HashSet::new()))
+            .collect()
+    }
+
+    /// compile and run in one go
+    pub fn exec(&mut self, source: &str) -> Result<Vec<Value>, EngineError> {
+        let chunk = Self::compile(source)?;
+        self.run(&chunk)
+    }
+
+    /// a snapshot of a global, nil if it is not set
+    pub fn get_global(&self, name: &str) -> Result<Value, EngineError> {
+        match self.vm.get_global(name) {
+            Some(val) => to_value(&self.vm.heap, val, &mut HashSet::new()),
+            None => Ok(Value::Nil),
+        }
+    }
+
+    /// setting a global to nil removes it
+    pub fn set_global(&mut self, name: &str, value: &Value) -> Result<(), EngineError> {
+        let val = from_value(&mut self.vm, value)?;
+        self.vm.set_global(name, val);
+        Ok(())
+    }
+
+    /// call the global function `name` and return its first result
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, EngineError> {
+        let func = match self.vm.get_global(name) {
+            Some(
+                func @ (LuaValue::Function(_) | LuaValue::CFunc(_) | LuaValue::NativeClosure(_)),
+            ) => *func,
+            _ => {
+                return Err(EngineError::Runtime(self.vm.error(ErrorKind::InvalidCall(
+                    format!("global '{}' is not a function", name),
+                ))));
+            }
+        };
+
+        let mut lua_args = Vec::with_capacity(args.len());
+        for arg in args {
+            lua_args.push(from_value(&mut self.vm, arg)?);
+        }
+
+        // unwinds the frames of a failed call, so the VM stays usable
+        let results = self
+            .vm
+            .protected_call(func, lua_args)
+            .map_err(EngineError::Runtime)?;
+        let result = results.first().copied().unwrap_or(LuaValue::Nil);
+        to_value(&self.vm.heap, &result, &mut HashSet::new())
+    }
+}
+
+// copy a host value into the VM heap
+fn from_value(vm: &mut VirtualMachine, val: &Value) -> Result<LuaValue, EngineError> {
+    let out_of_memory =
+        |vm: &VirtualMachine| EngineError::Runtime(vm.error(ErrorKind::OutOfMemory));
+    let res = match val {
+        Value::Nil => LuaValue::Nil,
+        Value::Boolean(b) => LuaValue::Boolean(*b),
+        Value::Number(n) => LuaValue::Number(*n),
+        Value::Integer(i) => LuaValue::Integer(*i),
+        Value::String(s) => match vm.heap.alloc_string(s.clone()) {
+            Some(ptr) => LuaValue::String(ptr),
+            None => return Err(out_of_memory(vm)),
+        },
+        Value::Table { entries, .. } => {
+            let mut table = LuaTable::with_capacity(entries.len());
+            for (k, v) in entries {
+                let key = from_value(vm, k)?;
+                if key == LuaValue::Nil {
+                    return Err(EngineError::Conversion("table key is nil".into()));
+                }
+                table.set(key, from_value(vm, v)?);
+            }
+            match vm.heap.alloc_table(table) {
+                Some(ptr) => LuaValue::Table(ptr),
+                None => return Err(out_of_memory(vm)),
+            }
+        }
+        Value::Function { .. } => {
+            return Err(EngineError::Conversion(
+                "functions cannot be passed into the VM".into(),
+            ));
+        }
+        Value::Thread { .. } => {
+            return Err(EngineError::Conversion(
+                "coroutines cannot be passed into the VM".into(),
+            ));
+        }
+        Value::UserData { .. } => {
+            return Err(EngineError::Conversion(
+                "userdata cannot be passed into the VM".into(),
+            ));
+        }
+    };
+    Ok(res)
+}
+
+// copy a VM value out of the heap, `visiting` holds the tables on the current path
+fn to_value(
+    heap: &Heap,
+    val: &LuaValue,
+    visiting: &mut HashSet<usize>,
+) -> Result<Value, EngineError> {
+    let res = match val {
+        LuaValue::Nil => Value::Nil,
+        LuaValue::Boolean(b) => Value::Boolean(*b),
+        LuaValue::Number(n) => Value::Number(*n),
+        LuaValue::Integer(i) => Value::Integer(*i),
+        LuaValue::String(ptr) => Value::String(ptr.get().clone()),
+        LuaValue::Function(ptr) => Value::Function { addr: ptr.addr() },
+        LuaValue::CFunc(f) => Value::Function {
+            addr: *f as *const () as usize,
+        },
+        LuaValue::NativeClosure(ptr) => Value::Function { addr: ptr.addr() },
+        LuaValue::Coroutine(ptr) => Value::Thread { addr: ptr.addr() },
+        LuaValue::Table(ptr) => {
+            if !visiting.insert(ptr.addr()) {
+                return Err(EngineError::Conversion(
+                    "cyclic table cannot be converted".into(),
+                ));
+            }
+
+            let mut entries = Vec::new();
+            for (k, v) in heap.data(*ptr).iter() {
+                entries.push((to_value(heap, &k, visiting)?, to_value(heap, v, visiting)?));
+            }
+            entries.sort_by(|(a, _), (b, _)| {
+                a.sort_key()
+                    .partial_cmp(&b.sort_key())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+
+            visiting.remove(&ptr.addr());
+            Value::Table {
+                addr: ptr.addr(),
+                entries,
+            }
+        }
+        LuaValue::UserData(ptr) => Value::UserData { addr: ptr.addr() },
+    };
+    Ok(res)
+}
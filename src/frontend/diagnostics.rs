@@ -0,0 +1,103 @@
+// Myula compiler frontend diagnostics
+//
+// Changelog:
+//      26-02-24: Initial version. Collects the errors of the lexer, the parser and the IR generator
+//                with their lines, so a driver can report all of them and refuse to run the program
+
+use std::fmt;
+
+use crate::frontend::ir::IRGenerator;
+use crate::frontend::parser::Parser;
+
+// exit status of a driver that refused to run the program
+pub const EXIT_SYNTAX_ERROR: i32 = 1;
+pub const EXIT_COMPILE_ERROR: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    Lexer,
+    Parser,
+    IR,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub stage: Stage,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the lexer's errors and the syntax errors, ordered by line; a lexer error comes before
+    // the syntax error it usually causes on the same line
+    pub fn add_parse(&mut self, parser: &Parser) {
+        let lexer = parser.get_lexer();
+        let mut found: Vec<Diagnostic> = lexer
+            .get_err()
+            .iter()
+            .zip(lexer.get_err_lines())
+            .map(|(err, &line)| Diagnostic {
+                stage: Stage::Lexer,
+                line,
+                message: err.to_string(),
+            })
+            .chain(parser.get_err().iter().map(|err| Diagnostic {
+                stage: Stage::Parser,
+                line: err.line,
+                message: err.message.clone(),
+            }))
+            .collect();
+        found.sort_by_key(|d| (d.line, d.stage));
+        self.items.extend(found);
+    }
+
+    pub fn add_ir(&mut self, ir_gen: &IRGenerator) {
+        let mut found: Vec<Diagnostic> = ir_gen
+            .get_err()
+            .iter()
+            .zip(ir_gen.get_err_lines())
+            .map(|(err, &line)| Diagnostic {
+                stage: Stage::IR,
+                line,
+                message: err.to_string(),
+            })
+            .collect();
+        found.sort_by_key(|d| d.line);
+        self.items.extend(found);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.items.iter()
+    }
+
+    // EXIT_SYNTAX_ERROR if the source does not even lex and parse, EXIT_COMPILE_ERROR if only
+    // the IR generator rejected it, 0 without errors
+    pub fn exit_code(&self) -> i32 {
+        if self.items.iter().any(|d| d.stage != Stage::IR) {
+            EXIT_SYNTAX_ERROR
+        } else if self.items.is_empty() {
+            0
+        } else {
+            EXIT_COMPILE_ERROR
+        }
+    }
+}
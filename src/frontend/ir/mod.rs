@@ -26,10 +26,65 @@
 //                now it will try to close the current basic block only when a block is active,
 //                instead of unconditionally closing a block, which may panic
 //      26-02-20: UpVal analysis and handling in IR generation
-
-use std::collections::HashMap;
-
+//      26-02-22: Method calls, the receiver is passed as the implicit first argument
+//      26-02-23: Positional table fields are numbered on their own, keyed fields no longer
+//                advance the implicit index, i.e. {a = 1, 2} stores 2 at [1]
+//      26-02-24: Opt-in shadowing analysis (`with_warn_shadow`): redeclared locals and locals/params
+//                hiding a variable of an enclosing function are reported as IRShadowWarning
+//      26-02-24: Integer literals load through ImmInt, table ctor sizes and implicit indices are integers
+//      26-02-24: Constant folding of numeric arithmetic, constant division/modulo by zero and
+//                wrapping integer arithmetic are reported as IRConstWarning
+//      26-02-24: `and`/`or` short-circuit: the right operand is generated in its own basic block,
+//                both paths write the result register through the new Move instruction
+//      26-02-24: Assignment statements with several targets/values, all of them are evaluated before
+//                the first store; lvalues are resolved by generate_assign_target, stores by generate_store
+//      26-02-24: Lexical scopes: blocks (do/if/while/repeat bodies) push a scope, every `local` gets its
+//                own slot and goes out of sight at the end of its block; a name declared more than once
+//                in a function is keyed as "name@slot" in IRFunction::local_variables
+//      26-02-24: goto and labels, a label starts a basic block; gotos to labels further down jump to
+//                placeholder blocks that are patched to the label once it is reached
+//      26-02-24: Every instruction records the source line of the statement it was generated for
+//                (IRBasicBlock::lines / terminator_line), the emitter turns them into a line table
+//      26-02-24: IR optimization passes live in `opt`; `generate` runs constant folding and propagation
+//                (opt::const_fold) on the finished module unless disabled with `with_const_fold(false)`
+//      26-02-24: ... followed by dead code elimination (opt::dce), `with_dce(false)` turns it off
+//      26-02-24: IRInstruction::def_reg / operands and IRTerminator::operands describe what an instruction
+//                defines and reads, for the optimization passes and the scanner's liveness analysis
+//      26-02-24: NewTable takes its size hints as immediates instead of loading them into registers
+//      26-02-24: Record the line of every error (`get_err_lines`), `IRGeneratorError` displays as a message;
+//                an undefined label is reported on the line of its goto, and its goto returns
+//      26-02-24: `elseif` branches, each condition is tested in the false block of the one before it;
+//                the Switch terminator, a jump table built by opt::jump_table, `with_jump_tables(false)`
+//                turns the pass off
+//      26-02-24: Loop-invariant code motion (opt::licm) between the jump tables and dce: loads of constants and
+//                of unchanged globals move into a preheader block before the loop, `with_licm(false)` turns it off
+//      26-02-24: operands_mut next to operands; common subexpression elimination (opt::cse) right after
+//                constant folding, `with_cse(false)` turns it off
+//      26-02-24: Input the generator cannot lower is an error instead of a panic: statements after a
+//                block's return (StatementAfterReturn) and unary `+` (UnsupportedOperator)
+//      26-02-24: Every instruction also records the source span of the innermost expression (or the
+//                statement) it was generated for (IRBasicBlock::spans / terminator_span)
+//      26-02-24: IRFunction::local_positions, the source offset of the name declaring each slot;
+//                the store of an assignment records the span of its target
+//      26-02-24: ConcatN instruction, a chain of three or more `..` operands is concatenated at once
+//      26-02-24: SetList instruction, the positional fields of a table constructor are stored in batches
+//                of SETLIST_BATCH once their values are evaluated, instead of one SetIndex per field
+//      26-02-24: `local function f` declares f before generating the function, so f can call itself; the
+//                names of a run of adjacent `local function` statements are all declared before the first
+//                one, so those functions can call each other
+//      26-02-24: The operators along the left edge of a left associative chain `a + b + c` are lowered in a
+//                loop, innermost first, so long chains no longer recurse once per operator
+//      26-02-24: Calls take as many results as their targets need (Call::results, CallResult); a call as the
+//                last argument, return value or assigned value passes on every result it returns
+
+pub mod opt;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::common::opcode::SETLIST_BATCH;
 use crate::frontend::parser;
+use crate::frontend::parser::ast::Span;
 
 pub struct IRGenerator {
     module: IRModule,
@@ -38,6 +93,18 @@ pub struct IRGenerator {
     next_func_id: usize,
 
     errors: Vec<IRGeneratorError>,
+    // the line of each error in `errors`
+    error_lines: Vec<usize>,
+
+    warn_shadow: bool,
+    warnings: Vec<IRShadowWarning>,
+    const_warnings: Vec<IRConstWarning>,
+
+    const_fold: bool,
+    cse: bool,
+    jump_tables: bool,
+    licm: bool,
+    dce: bool,
 }
 
 type IRLocalVarSlot = usize;
@@ -60,8 +127,15 @@ struct IRFunctionContext {
     name: String,
     params: Vec<String>,
 
-    // local variable name -> slot number
+    // every local declared in the function, unique name -> slot number
+    // see IRFunction::local_variables
     local_variables: HashMap<String, IRLocalVarSlot>,
+    // see IRFunction::local_positions
+    local_positions: Vec<usize>,
+    // visible locals, one map per enclosing block, innermost last
+    scopes: Vec<HashMap<String, IRLocalInfo>>,
+    // labels, one entry per enclosing block like `scopes`
+    labels: Vec<IRLabelScope>,
     upvalues: HashMap<String, IRUpVal>,
 
     // names of sub function prototypes
@@ -72,6 +146,152 @@ struct IRFunctionContext {
 
     next_reg: usize,
     next_block_id: usize,
+
+    // source line of the statement being generated, recorded with every instruction
+    current_line: usize,
+    // span of the expression or statement being generated, recorded with every instruction
+    current_span: Span,
+    // slots of `local function` statements declared ahead of them, by the position of the name
+    declared_functions: HashMap<usize, IRLocalVarSlot>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct IRLabelScope {
+    // label name -> basic block starting at the label
+    defined: HashMap<String, usize>,
+    // gotos to labels not seen yet: label name -> placeholder block the goto jumps to and
+    // the goto's line, unresolved ones move to the enclosing block when this one ends
+    pending: Vec<(String, usize, usize)>,
+}
+
+#[derive(Debug, Clone)]
+struct IRLocalInfo {
+    slot: IRLocalVarSlot,
+    // source offset of the declaring name
+    pos: usize,
+    // captured as an upvalue by a nested function
+    captured: bool,
+}
+
+// a declaration hiding another variable of the same name,
+// legal Lua but frequently a bug
+#[derive(Debug, Clone, PartialEq)]
+pub struct IRShadowWarning {
+    pub name: String,
+    // source offset of the shadowing declaration
+    pub pos: usize,
+    // source offset of the declaration being shadowed
+    pub shadowed_pos: usize,
+    // the shadowing declaration is a function parameter
+    pub is_param: bool,
+    // the shadowed variable belongs to an enclosing function, i.e. it is an upvalue here
+    pub outer: bool,
+    // the shadowed variable has been captured by a closure
+    pub captured: bool,
+}
+
+// resolves a source offset to line:column
+fn line_col(source: &str, pos: usize) -> String {
+    let before = &source[..pos.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    format!("{}:{}", line, col)
+}
+
+impl IRShadowWarning {
+    // human readable form, offsets are resolved to line:column against `source`
+    pub fn describe(&self, source: &str) -> String {
+        let line_col = |pos: usize| line_col(source, pos);
+
+        let what = if self.is_param { "parameter" } else { "local" };
+        let mut msg = if self.outer {
+            format!(
+                "{} '{}' at {} shadows upvalue '{}' declared at {}",
+                what,
+                self.name,
+                line_col(self.pos),
+                self.name,
+                line_col(self.shadowed_pos)
+            )
+        } else {
+            format!(
+                "{} '{}' at {} redeclares local '{}' declared at {}",
+                what,
+                self.name,
+                line_col(self.pos),
+                self.name,
+                line_col(self.shadowed_pos)
+            )
+        };
+        if self.captured {
+            msg.push_str(" (the shadowed variable is captured by a closure)");
+        }
+        msg
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IRConstWarningKind {
+    DivisionByZero,
+    ModuloByZero,
+    // integer arithmetic wrapped around, the folded value is the wrapped one like at runtime
+    IntegerOverflow,
+}
+
+// a problem found while folding constant arithmetic,
+// the program still compiles and behaves as if nothing had been folded
+#[derive(Debug, Clone, PartialEq)]
+pub struct IRConstWarning {
+    pub kind: IRConstWarningKind,
+    // source offset of the offending operator
+    pub pos: usize,
+}
+
+impl IRConstWarning {
+    // human readable form, offsets are resolved to line:column against `source`
+    pub fn describe(&self, source: &str) -> String {
+        let what = match self.kind {
+            IRConstWarningKind::DivisionByZero => "constant division by zero",
+            IRConstWarningKind::ModuloByZero => "constant modulo by zero",
+            IRConstWarningKind::IntegerOverflow => "constant integer overflow",
+        };
+        format!("{} at {}", what, line_col(source, self.pos))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum IRConstNum {
+    Int(i64),
+    Float(f64),
+}
+
+impl IRConstNum {
+    fn as_float(self) -> f64 {
+        match self {
+            IRConstNum::Int(i) => i as f64,
+            IRConstNum::Float(n) => n,
+        }
+    }
+}
+
+// integer arithmetic, along with whether it wrapped around
+type IRConstIntOp = fn(i64, i64) -> (i64, bool);
+
+// result of evaluating a constant subexpression
+#[derive(Debug, Clone, Copy)]
+struct IRConstValue {
+    value: IRConstNum,
+    // first operator inside the subexpression whose integer arithmetic wrapped
+    overflow_pos: Option<usize>,
+}
+
+impl IRConstValue {
+    fn new(value: IRConstNum) -> Self {
+        IRConstValue {
+            value,
+            overflow_pos: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -79,6 +299,40 @@ pub enum IRGeneratorError {
     UndefinedVariable(String),
     InvalidLValue,
     MultipleReturnStatements,
+    // goto without a visible label of that name in the same function
+    UndefinedLabel(String),
+    // a label declared twice where both are visible
+    DuplicateLabel(String),
+    // the block already returned, there is nowhere to put the code of the statement
+    StatementAfterReturn,
+    // an operator the parser accepts but that has no meaning in Lua, e.g. unary `+`
+    UnsupportedOperator(String),
+}
+
+impl fmt::Display for IRGeneratorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IRGeneratorError::UndefinedVariable(name) => {
+                write!(f, "undefined variable '{}'", name)
+            }
+            IRGeneratorError::InvalidLValue => write!(f, "cannot assign to this expression"),
+            IRGeneratorError::MultipleReturnStatements => {
+                write!(f, "more than one return statement in a block")
+            }
+            IRGeneratorError::UndefinedLabel(label) => {
+                write!(f, "no visible label '{}' for goto", label)
+            }
+            IRGeneratorError::DuplicateLabel(label) => {
+                write!(f, "label '{}' already defined", label)
+            }
+            IRGeneratorError::StatementAfterReturn => {
+                write!(f, "statement after the return statement of a block")
+            }
+            IRGeneratorError::UnsupportedOperator(op) => {
+                write!(f, "unsupported operator '{}'", op)
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -100,6 +354,7 @@ pub enum IROperand {
     // the values should be put into constant pool
     // Immediate values should only be used in LoadImm instruction
     ImmFloat(f64),  // immediate float value
+    ImmInt(i64),    // immediate integer value
     ImmBool(bool),  // immediate boolean value
     ImmStr(String), // immediate string value
     Nil,            // nil value
@@ -113,7 +368,8 @@ impl IROperand {
             IROperand::Proto(name) => format!("@{}", name),
             IROperand::Slot(slot) => format!("%local_{}", slot),
             IROperand::UpVal(slot) => format!("%upval_{}", slot),
-            IROperand::ImmFloat(f) => format!("${}", f),
+            IROperand::ImmFloat(f) => format!("${:?}", f),
+            IROperand::ImmInt(i) => format!("${}", i),
             IROperand::ImmBool(b) => format!("${}", b),
             IROperand::ImmStr(s) => format!("$\"{}\"", s),
             IROperand::Nil => "$nil".to_string(),
@@ -253,10 +509,22 @@ pub enum IRInstruction {
     // %dest = Call %callee, [args]
     // Invoke function %callee with arguments [args],
     // store the return value into %dest
+    //
+    // results is the number of values the caller takes, the ones after the first are read by the
+    // CallResult instructions right after the call; None takes every value, for a call that is the
+    // last argument of another call or the last value of a return, which then expands to all of them
     Call {
         dest: usize,
         callee: IROperand,
         args: Vec<IROperand>,
+        results: Option<usize>,
+    },
+    // %dest = CallResult index
+    // the result at position index (the first one is 0) of the Call right before it,
+    // nil when the callee returned fewer values
+    CallResult {
+        dest: usize,
+        index: usize,
     },
     // %dest = IndexOf %collection, %index
     // Get the element at %index from %collection,
@@ -336,6 +604,30 @@ pub enum IRInstruction {
         table: IROperand,
         key: IROperand,
     },
+    // %dest = Move %src
+    // copy the value of %src into %dest
+    // this is the only instruction that may define the same %dest more than once,
+    // on different paths into a merge block (a phi lowered to copies),
+    // e.g. both the left and the right operand of a short-circuit `and`/`or`
+    Move {
+        dest: usize,
+        src: IROperand,
+    },
+    // %dest = ConcatN [%src1, %src2, ...]
+    // concatenate all of the operands, left to right, into one string
+    // a chain `a .. b .. c` of three or more operands, two of them are a Binary Concat
+    ConcatN {
+        dest: usize,
+        srcs: Vec<IROperand>,
+    },
+    // %nil = SetList %table, batch, [%value1, %value2, ...]
+    // store the values at consecutive integer keys of %table, the first one is batch * SETLIST_BATCH + 1;
+    // at most SETLIST_BATCH values, the positional fields of a table constructor
+    SetList {
+        table: IROperand,
+        batch: usize,
+        values: Vec<IROperand>,
+    },
     // %dest = FnProto @func_name
     // Instantiate a function prototype @func_name,
     // store the function reference into %dest
@@ -347,6 +639,148 @@ pub enum IRInstruction {
 }
 
 impl IRInstruction {
+    // register this instruction defines, None for Drop
+    pub fn def_reg(&self) -> Option<usize> {
+        match self {
+            IRInstruction::LoadImm { dest, .. }
+            | IRInstruction::Binary { dest, .. }
+            | IRInstruction::Unary { dest, .. }
+            | IRInstruction::LoadLocal { dest, .. }
+            | IRInstruction::StoreLocal { dest, .. }
+            | IRInstruction::LoadGlobal { dest, .. }
+            | IRInstruction::StoreGlobal { dest, .. }
+            | IRInstruction::LoadUpVal { dest, .. }
+            | IRInstruction::StoreUpVal { dest, .. }
+            | IRInstruction::Call { dest, .. }
+            | IRInstruction::CallResult { dest, .. }
+            | IRInstruction::IndexOf { dest, .. }
+            | IRInstruction::SetIndex { dest, .. }
+            | IRInstruction::MemberOf { dest, .. }
+            | IRInstruction::SetMember { dest, .. }
+            | IRInstruction::NewTable { dest, .. }
+            | IRInstruction::SetTable { dest, .. }
+            | IRInstruction::GetTable { dest, .. }
+            | IRInstruction::Move { dest, .. }
+            | IRInstruction::ConcatN { dest, .. }
+            | IRInstruction::FnProto { dest, .. } => Some(*dest),
+            IRInstruction::Drop { .. } | IRInstruction::SetList { .. } => None,
+        }
+    }
+
+    // operands this instruction reads, registers and immediates alike
+    pub fn operands(&self) -> Vec<&IROperand> {
+        match self {
+            IRInstruction::LoadImm { value, .. } => vec![value],
+            IRInstruction::Binary { src1, src2, .. } => vec![src1, src2],
+            IRInstruction::Unary { src, .. }
+            | IRInstruction::LoadLocal { src, .. }
+            | IRInstruction::LoadUpVal { src, .. }
+            | IRInstruction::Drop { src }
+            | IRInstruction::Move { src, .. } => vec![src],
+            IRInstruction::StoreLocal { dst, src, .. }
+            | IRInstruction::StoreUpVal { dst, src, .. } => {
+                vec![dst, src]
+            }
+            IRInstruction::LoadGlobal { name, .. } => vec![name],
+            IRInstruction::StoreGlobal { name, src, .. } => vec![name, src],
+            IRInstruction::Call { callee, args, .. } => {
+                let mut ops = vec![callee];
+                ops.extend(args);
+                ops
+            }
+            IRInstruction::IndexOf {
+                collection, index, ..
+            } => vec![collection, index],
+            IRInstruction::SetIndex {
+                collection,
+                index,
+                value,
+                ..
+            } => vec![collection, index, value],
+            IRInstruction::MemberOf {
+                collection, member, ..
+            } => vec![collection, member],
+            IRInstruction::SetMember {
+                collection,
+                member,
+                value,
+                ..
+            } => vec![collection, member, value],
+            IRInstruction::NewTable {
+                size_array,
+                size_hash,
+                ..
+            } => vec![size_array, size_hash],
+            IRInstruction::SetTable {
+                table, key, value, ..
+            } => vec![table, key, value],
+            IRInstruction::GetTable { table, key, .. } => vec![table, key],
+            IRInstruction::ConcatN { srcs, .. } => srcs.iter().collect(),
+            IRInstruction::CallResult { .. } => vec![],
+            IRInstruction::SetList { table, values, .. } => {
+                std::iter::once(table).chain(values.iter()).collect()
+            }
+            IRInstruction::FnProto { func_proto, .. } => vec![func_proto],
+        }
+    }
+
+    // the same operands as `operands`, for passes that rename registers
+    pub fn operands_mut(&mut self) -> Vec<&mut IROperand> {
+        match self {
+            IRInstruction::LoadImm { value, .. } => vec![value],
+            IRInstruction::Binary { src1, src2, .. } => vec![src1, src2],
+            IRInstruction::Unary { src, .. }
+            | IRInstruction::LoadLocal { src, .. }
+            | IRInstruction::LoadUpVal { src, .. }
+            | IRInstruction::Drop { src }
+            | IRInstruction::Move { src, .. } => vec![src],
+            IRInstruction::StoreLocal { dst, src, .. }
+            | IRInstruction::StoreUpVal { dst, src, .. } => {
+                vec![dst, src]
+            }
+            IRInstruction::LoadGlobal { name, .. } => vec![name],
+            IRInstruction::StoreGlobal { name, src, .. } => vec![name, src],
+            IRInstruction::Call { callee, args, .. } => {
+                let mut ops = vec![callee];
+                ops.extend(args);
+                ops
+            }
+            IRInstruction::IndexOf {
+                collection, index, ..
+            } => vec![collection, index],
+            IRInstruction::SetIndex {
+                collection,
+                index,
+                value,
+                ..
+            } => vec![collection, index, value],
+            IRInstruction::MemberOf {
+                collection, member, ..
+            } => vec![collection, member],
+            IRInstruction::SetMember {
+                collection,
+                member,
+                value,
+                ..
+            } => vec![collection, member, value],
+            IRInstruction::NewTable {
+                size_array,
+                size_hash,
+                ..
+            } => vec![size_array, size_hash],
+            IRInstruction::SetTable {
+                table, key, value, ..
+            } => vec![table, key, value],
+            IRInstruction::GetTable { table, key, .. } => vec![table, key],
+            IRInstruction::ConcatN { srcs, .. } => srcs.iter_mut().collect(),
+            IRInstruction::CallResult { .. } => vec![],
+            IRInstruction::SetList { table, values, .. } => {
+                std::iter::once(table).chain(values.iter_mut()).collect()
+            }
+            IRInstruction::FnProto { func_proto, .. } => vec![func_proto],
+        }
+    }
+
     pub fn to_string(&self) -> String {
         match self {
             IRInstruction::LoadImm { dest, value } => {
@@ -409,13 +843,26 @@ impl IRInstruction {
             IRInstruction::Drop { src } => {
                 format!("%nil = Drop {}", src.to_string())
             }
-            IRInstruction::Call { dest, callee, args } => {
+            IRInstruction::Call {
+                dest,
+                callee,
+                args,
+                results,
+            } => {
                 let args_str = args
                     .iter()
                     .map(|arg| arg.to_string())
                     .collect::<Vec<_>>()
                     .join(", ");
-                format!("%{} = Call {}, [{}]", dest, callee.to_string(), args_str)
+                let call = format!("%{} = Call {}, [{}]", dest, callee.to_string(), args_str);
+                match results {
+                    Some(1) => call,
+                    Some(n) => format!("{} -> {}", call, n),
+                    None => format!("{} -> all", call),
+                }
+            }
+            IRInstruction::CallResult { dest, index } => {
+                format!("%{} = CallResult {}", dest, index)
             }
             IRInstruction::IndexOf {
                 dest,
@@ -503,6 +950,34 @@ impl IRInstruction {
                     key.to_string()
                 )
             }
+            IRInstruction::Move { dest, src } => {
+                format!("%{} = Move {}", dest, src.to_string())
+            }
+            IRInstruction::ConcatN { dest, srcs } => {
+                let srcs_str = srcs
+                    .iter()
+                    .map(|src| src.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("%{} = ConcatN [{}]", dest, srcs_str)
+            }
+            IRInstruction::SetList {
+                table,
+                batch,
+                values,
+            } => {
+                let values_str = values
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!(
+                    "%nil = SetList {}, {}, [{}]",
+                    table.to_string(),
+                    batch,
+                    values_str
+                )
+            }
             IRInstruction::FnProto {
                 dest,
                 func_proto: func_name,
@@ -534,9 +1009,37 @@ pub enum IRTerminator {
     // FallThrough
     // no operation, just fall through to the next basic block
     FallThrough,
+    // Switch %value, low, [_Tag..], _Tag_default
+    // jump to targets[%value - low] when %value is a number with an integer value in range,
+    // otherwise to default; built from `if x == 1 ... elseif x == 2 ...` by opt::jump_table
+    Switch {
+        value: IROperand,
+        low: i64,
+        targets: Vec<usize>,
+        default: usize,
+    },
 }
 
 impl IRTerminator {
+    // operands this terminator reads
+    pub fn operands(&self) -> Vec<&IROperand> {
+        match self {
+            IRTerminator::Return(ops) => ops.iter().collect(),
+            IRTerminator::Branch { cond, .. } => vec![cond],
+            IRTerminator::Switch { value, .. } => vec![value],
+            IRTerminator::Jump(_) | IRTerminator::FallThrough => vec![],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut IROperand> {
+        match self {
+            IRTerminator::Return(ops) => ops.iter_mut().collect(),
+            IRTerminator::Branch { cond, .. } => vec![cond],
+            IRTerminator::Switch { value, .. } => vec![value],
+            IRTerminator::Jump(_) | IRTerminator::FallThrough => vec![],
+        }
+    }
+
     pub fn to_string(&self) -> String {
         match self {
             IRTerminator::Return(operands) => {
@@ -563,6 +1066,25 @@ impl IRTerminator {
                 )
             }
             IRTerminator::FallThrough => "FallThrough".to_string(),
+            IRTerminator::Switch {
+                value,
+                low,
+                targets,
+                default,
+            } => {
+                let targets_str = targets
+                    .iter()
+                    .map(|target| format!("_Tag{}", target))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!(
+                    "Switch {}, {}, [{}], _Tag{}",
+                    value.to_string(),
+                    low,
+                    targets_str,
+                    default
+                )
+            }
         }
     }
 }
@@ -574,6 +1096,12 @@ pub struct IRBasicBlock {
     pub id: usize,
     pub instructions: Vec<IRInstruction>,
     pub terminator: IRTerminator,
+    // source line of each instruction, then of the terminator (0 if unknown)
+    pub lines: Vec<usize>,
+    pub terminator_line: usize,
+    // source span of each instruction, then of the terminator (empty if unknown)
+    pub spans: Vec<Span>,
+    pub terminator_span: Span,
 }
 
 impl IRBasicBlock {
@@ -598,6 +1126,8 @@ impl IRBasicBlock {
 struct IRActiveBlock {
     pub id: usize,
     pub instructions: Vec<IRInstruction>,
+    pub lines: Vec<usize>,
+    pub spans: Vec<Span>,
 }
 
 // a function prototype, which is a template for function instances
@@ -621,12 +1151,24 @@ pub struct IRFunction {
     pub name: String,
     pub params: Vec<String>,
     pub basic_blocks: Vec<IRBasicBlock>,
-    pub local_variables: HashMap<String, IRLocalVarSlot>, // local variable name -> slot number
-    pub upvalues: HashMap<String, IRUpVal>,               // upvalue name -> upvalue info
-    pub sub_functions: Vec<String>,                       // names of sub function prototypes
+    // local variable name -> slot number, every slot of the function appears once;
+    // a name declared again (shadowing or redeclaring) is keyed as "name@slot", see local_name
+    pub local_variables: HashMap<String, IRLocalVarSlot>,
+    // source offset of the name declaring each slot, indexed by slot number
+    pub local_positions: Vec<usize>,
+    pub upvalues: HashMap<String, IRUpVal>, // upvalue name -> upvalue info
+    pub sub_functions: Vec<String>,         // names of sub function prototypes
 }
 
 impl IRFunction {
+    // source name of the local variable in `slot`
+    pub fn local_name(&self, slot: IRLocalVarSlot) -> Option<&str> {
+        self.local_variables
+            .iter()
+            .find(|(_, s)| **s == slot)
+            .map(|(name, _)| name.split('@').next().unwrap_or(name))
+    }
+
     pub fn to_string(&self) -> String {
         let local_vars_str = if self.local_variables.is_empty() {
             "; <no local variables>".to_string()
@@ -718,6 +1260,29 @@ enum IRValueScope {
     UpVal(IRUpVal),
 }
 
+// where an assignment stores to, with the table and key of a field already evaluated
+#[derive(Debug, Clone)]
+enum IRAssignTarget {
+    Local(usize),
+    Global(String),
+    UpVal(IRUpValSlot),
+    // string key, SetMember
+    Member {
+        collection: IROperand,
+        member: IROperand,
+    },
+    // numeric literal key, SetIndex
+    Index {
+        collection: IROperand,
+        index: IROperand,
+    },
+    // any other key, SetTable
+    Table {
+        table: IROperand,
+        key: IROperand,
+    },
+}
+
 impl IRGenerator {
     pub fn new() -> IRGenerator {
         return IRGenerator {
@@ -725,13 +1290,74 @@ impl IRGenerator {
             function_contexts: vec![],
             next_func_id: 0,
             errors: vec![],
+            error_lines: vec![],
+            warn_shadow: false,
+            warnings: vec![],
+            const_warnings: vec![],
+            const_fold: true,
+            cse: true,
+            jump_tables: true,
+            licm: true,
+            dce: true,
         };
     }
 
+    // report shadowed locals and upvalues, see IRShadowWarning
+    pub fn with_warn_shadow(mut self, enabled: bool) -> Self {
+        self.warn_shadow = enabled;
+        self
+    }
+
+    // fold and propagate constants on the generated IR, see opt::const_fold; on by default
+    pub fn with_const_fold(mut self, enabled: bool) -> Self {
+        self.const_fold = enabled;
+        self
+    }
+
+    // reuse the register of an earlier identical side-effect free instruction in the same block,
+    // see opt::cse; on by default
+    pub fn with_cse(mut self, enabled: bool) -> Self {
+        self.cse = enabled;
+        self
+    }
+
+    // turn long `elseif` chains comparing a local with integer constants into a Switch,
+    // see opt::jump_table; on by default
+    pub fn with_jump_tables(mut self, enabled: bool) -> Self {
+        self.jump_tables = enabled;
+        self
+    }
+
+    // move invariant loads out of loops into a preheader and turn `x * 2` in loops into `x + x` when x is
+    // a number, see opt::licm; on by default
+    pub fn with_licm(mut self, enabled: bool) -> Self {
+        self.licm = enabled;
+        self
+    }
+
+    // remove unreachable blocks and unused side-effect free instructions, see opt::dce; on by default
+    pub fn with_dce(mut self, enabled: bool) -> Self {
+        self.dce = enabled;
+        self
+    }
+
+    pub fn get_warnings(&self) -> &Vec<IRShadowWarning> {
+        &self.warnings
+    }
+
+    pub fn get_const_warnings(&self) -> &Vec<IRConstWarning> {
+        &self.const_warnings
+    }
+
     pub fn get_err(&self) -> &Vec<IRGeneratorError> {
         &self.errors
     }
 
+    // the line each error of `get_err` was found on, in the same order
+    pub fn get_err_lines(&self) -> &[usize] {
+        &self.error_lines
+    }
+
     fn current_context_mut(&mut self) -> &mut IRFunctionContext {
         self.function_contexts
             .last_mut()
@@ -745,8 +1371,13 @@ impl IRGenerator {
     }
 
     fn emit(&mut self, instr: IRInstruction) {
-        if let Some(active_block) = &mut self.current_context_mut().active_block {
+        let ctx = self.current_context_mut();
+        let line = ctx.current_line;
+        let span = ctx.current_span;
+        if let Some(active_block) = &mut ctx.active_block {
             active_block.instructions.push(instr);
+            active_block.lines.push(line);
+            active_block.spans.push(span);
         } else {
             panic!("No active block to emit instruction");
         }
@@ -784,6 +1415,8 @@ impl IRGenerator {
         self.current_context_mut().active_block = Some(IRActiveBlock {
             id,
             instructions: vec![],
+            lines: vec![],
+            spans: vec![],
         });
         id
     }
@@ -795,6 +1428,10 @@ impl IRGenerator {
                 id: active_block.id,
                 instructions: active_block.instructions,
                 terminator,
+                lines: active_block.lines,
+                terminator_line: ctx.current_line,
+                spans: active_block.spans,
+                terminator_span: ctx.current_span,
             };
             ctx.basic_blocks.push(bb);
         } else {
@@ -834,16 +1471,53 @@ impl IRGenerator {
             name,
             params: params,
             local_variables: HashMap::new(),
+            local_positions: vec![],
+            // parameters and the top level locals of the body
+            scopes: vec![HashMap::new()],
+            labels: vec![IRLabelScope::default()],
             upvalues: HashMap::new(),
             sub_functions: vec![],
             active_block: None,
             basic_blocks: vec![],
             next_reg: 0,
             next_block_id: 0,
+            // the prologue belongs to the line the function is defined on
+            current_line: self
+                .function_contexts
+                .last()
+                .map_or(0, |ctx| ctx.current_line),
+            current_span: self
+                .function_contexts
+                .last()
+                .map_or(Span::default(), |ctx| ctx.current_span),
+            declared_functions: HashMap::new(),
         });
     }
 
+    // generate with the instructions mapped back to `span`
+    fn with_span<T>(&mut self, span: Span, generate: impl FnOnce(&mut Self) -> T) -> T {
+        let outer = std::mem::replace(&mut self.current_context_mut().current_span, span);
+        let result = generate(self);
+        self.current_context_mut().current_span = outer;
+        result
+    }
+
     fn close_function(&mut self) {
+        // gotos cannot leave the function
+        let unresolved: Vec<_> = self
+            .current_context_mut()
+            .labels
+            .drain(..)
+            .flat_map(|scope| scope.pending)
+            .collect();
+        for (label, placeholder, line) in unresolved {
+            self.emit_err_at(IRGeneratorError::UndefinedLabel(label), line);
+            // the goto still needs a block to land on if the module is emitted regardless
+            // (myulac --force), it leaves the function
+            self.open_bb_lazy(placeholder);
+            self.close_bb(IRTerminator::Return(vec![IROperand::Unit]));
+        }
+
         // leave the function scope
         let local_vars = self.current_context().local_variables.clone();
 
@@ -856,6 +1530,7 @@ impl IRGenerator {
             params: ctx.params,
             basic_blocks: ctx.basic_blocks,
             local_variables: local_vars,
+            local_positions: ctx.local_positions,
             upvalues: ctx.upvalues,
             sub_functions: ctx.sub_functions,
         };
@@ -863,20 +1538,141 @@ impl IRGenerator {
     }
 
     fn emit_err(&mut self, err: IRGeneratorError) {
+        let line = self
+            .function_contexts
+            .last()
+            .map_or(0, |ctx| ctx.current_line);
+        self.emit_err_at(err, line);
+    }
+
+    fn emit_err_at(&mut self, err: IRGeneratorError, line: usize) {
         self.errors.push(err);
+        self.error_lines.push(line);
+    }
+
+    fn open_scope(&mut self) {
+        let ctx = self.current_context_mut();
+        ctx.scopes.push(HashMap::new());
+        ctx.labels.push(IRLabelScope::default());
+    }
+
+    // locals of the block go out of sight, their slots are not reused
+    // since a closure may still refer to them until the frame is popped
+    fn close_scope(&mut self) {
+        let ctx = self.current_context_mut();
+        ctx.scopes.pop();
+        // a label later in an enclosing block is still visible to the pending gotos
+        if let Some(scope) = ctx.labels.pop()
+            && let Some(outer) = ctx.labels.last_mut()
+        {
+            outer.pending.extend(scope.pending);
+        }
+    }
+
+    fn generate_goto(&mut self, label: &str) {
+        let defined = self
+            .current_context()
+            .labels
+            .iter()
+            .rev()
+            .find_map(|scope| scope.defined.get(label).copied());
+        let target = match defined {
+            // backward jump, the block is already there
+            Some(bb_id) => bb_id,
+            None => {
+                let placeholder = self.alloc_bb_id();
+                let ctx = self.current_context_mut();
+                let line = ctx.current_line;
+                ctx.labels
+                    .last_mut()
+                    .unwrap()
+                    .pending
+                    .push((label.to_string(), placeholder, line));
+                placeholder
+            }
+        };
+        self.try_close_bb(IRTerminator::Jump(target));
+        // whatever follows the goto in this block is unreachable but still needs a block
+        self.open_bb();
+    }
+
+    fn generate_label(&mut self, name: &str) {
+        let visible = self
+            .current_context()
+            .labels
+            .iter()
+            .any(|scope| scope.defined.contains_key(name));
+        if visible {
+            self.emit_err(IRGeneratorError::DuplicateLabel(name.to_string()));
+            return;
+        }
+
+        let label_bb_id = self.alloc_bb_id();
+        self.try_close_bb(IRTerminator::Jump(label_bb_id));
+
+        // patch the gotos that were waiting for this label
+        let scope = self.current_context_mut().labels.last_mut().unwrap();
+        let (resolved, pending): (Vec<_>, Vec<_>) = scope
+            .pending
+            .drain(..)
+            .partition(|(label, _, _)| label == name);
+        scope.pending = pending;
+        scope.defined.insert(name.to_string(), label_bb_id);
+        for (_, placeholder, _) in resolved {
+            self.open_bb_lazy(placeholder);
+            self.close_bb(IRTerminator::Jump(label_bb_id));
+        }
+
+        self.open_bb_lazy(label_bb_id);
     }
 
-    // declaring a local variable includes
-    fn decl_local(&mut self, name: String) -> IRLocalVarSlot {
-        let slot = self.current_context_mut().local_variables.len();
-        self.current_context_mut()
-            .local_variables
-            .insert(name.clone(), slot);
+    // declare a new local variable in the innermost scope, it always gets a fresh slot,
+    // also reports what it shadows when the shadowing analysis is on
+    fn decl_local(&mut self, name: &str, pos: usize, is_param: bool) -> IRLocalVarSlot {
+        let shadowed = match Self::visible_local(self.current_context(), name) {
+            Some(info) => Some((info.clone(), false)),
+            None => self.function_contexts[..self.function_contexts.len() - 1]
+                .iter()
+                .rev()
+                .find_map(|ctx| Self::visible_local(ctx, name))
+                .map(|info| (info.clone(), true)),
+        };
+
+        if self.warn_shadow
+            && let Some((info, outer)) = &shadowed
+        {
+            self.warnings.push(IRShadowWarning {
+                name: name.to_string(),
+                pos,
+                shadowed_pos: info.pos,
+                is_param,
+                outer: *outer,
+                captured: info.captured,
+            });
+        }
+
+        let ctx = self.current_context_mut();
+        let slot = ctx.local_variables.len();
+        let key = if ctx.local_variables.contains_key(name) {
+            format!("{}@{}", name, slot)
+        } else {
+            name.to_string()
+        };
+        ctx.local_variables.insert(key, slot);
+        ctx.local_positions.push(pos);
+        ctx.scopes.last_mut().unwrap().insert(
+            name.to_string(),
+            IRLocalInfo {
+                slot,
+                pos,
+                captured: false,
+            },
+        );
         slot
     }
 
-    fn find_local(&self, name: &String) -> Option<IRLocalVarSlot> {
-        self.current_context().local_variables.get(name).cloned()
+    fn visible_local<'c>(ctx: &'c IRFunctionContext, name: &str) -> Option<&'c IRLocalInfo> {
+        ctx.scopes.iter().rev().find_map(|scope| scope.get(name))
     }
 
     fn add_upval_to_context(&mut self, func_idx: usize, name: &String, ty: IRUpValType) -> IRUpVal {
@@ -890,8 +1686,8 @@ impl IRGenerator {
     fn var_scope_impl(&mut self, func_idx: usize, name: &String) -> Option<IRValueScope> {
         let current_context = &self.function_contexts[func_idx];
 
-        if let Some(&slot) = current_context.local_variables.get(name) {
-            return Some(IRValueScope::Local(slot));
+        if let Some(info) = Self::visible_local(current_context, name) {
+            return Some(IRValueScope::Local(info.slot));
         }
 
         if let Some(uv) = current_context.upvalues.get(name) {
@@ -902,6 +1698,14 @@ impl IRGenerator {
             if let Some(parent_scope) = self.var_scope_impl(func_idx - 1, name) {
                 match parent_scope {
                     IRValueScope::Local(slot) => {
+                        if let Some(info) = self.function_contexts[func_idx - 1]
+                            .scopes
+                            .iter_mut()
+                            .rev()
+                            .find_map(|scope| scope.get_mut(name.as_str()))
+                        {
+                            info.captured = true;
+                        }
                         let uv =
                             self.add_upval_to_context(func_idx, name, IRUpValType::LocalVar(slot));
                         return Some(IRValueScope::UpVal(uv));
@@ -938,59 +1742,95 @@ impl IRGenerator {
 
     fn generate_assignment(
         &mut self,
-        lhs: &parser::ast::Expression,
-        rhs: &parser::ast::Expression,
+        lhs: &parser::ast::Expr,
+        rhs: &parser::ast::Expr,
     ) -> IROperand {
+        let target = self.generate_assign_target(lhs);
         let src = self.generate_expr(rhs);
-        match lhs {
-            parser::ast::Expression::Identifier(name) => {
-                let scope = self.var_scope(name);
-                match scope {
-                    Some(IRValueScope::Local(slot)) => {
-                        // local variable
-                        // store the value into the local variable
-                        // assign the value to a new register and return it
-                        let dest_reg = self.alloc_reg();
-                        self.emit(IRInstruction::StoreLocal {
-                            dest: dest_reg,
-                            dst: IROperand::Slot(slot),
-                            src: src.clone(),
-                        });
-                        return IROperand::Reg(dest_reg);
-                    }
-                    Some(IRValueScope::Global) | None => {
-                        // global variable
-                        // first generate a LoadConst instruction to load the global variable name
-                        let name_reg = self.alloc_reg();
-                        self.emit(IRInstruction::LoadImm {
-                            dest: name_reg,
-                            value: IROperand::ImmStr(name.clone()),
-                        });
-
-                        // if the variable is not declared, then also default to global
-                        let dest_reg = self.alloc_reg();
-                        self.emit(IRInstruction::StoreGlobal {
-                            dest: dest_reg,
-                            name: IROperand::Reg(name_reg),
-                            src: src.clone(),
-                        });
-                        return IROperand::Reg(dest_reg);
-                    }
-                    Some(IRValueScope::UpVal(upval)) => {
-                        // upvalue
-                        let dest_reg = self.alloc_reg();
-                        self.emit(IRInstruction::StoreUpVal {
-                            dest: dest_reg,
-                            dst: IROperand::UpVal(upval.slot),
-                            src: src.clone(),
-                        });
-                        return IROperand::Reg(dest_reg);
-                    }
-                };
+        match target {
+            Some(target) => {
+                self.with_span(lhs.span, |generator| generator.generate_store(target, src))
             }
-            // non-trivial lvalue, like table member access or indexing
-            parser::ast::Expression::MemberAccess { collection, member } => {
-                // generate code for table member assignment
+            None => src,
+        }
+    }
+
+    // a, b, c = x, y
+    // every target and value is evaluated before the first store, so `a, b = b, a` swaps
+    fn generate_multiple_assignment(
+        &mut self,
+        targets: &[parser::ast::Expr],
+        values: &[parser::ast::Expr],
+    ) {
+        let resolved: Vec<_> = targets
+            .iter()
+            .map(|target| self.generate_assign_target(target))
+            .collect();
+        let srcs = self.generate_value_list(values, resolved.len());
+
+        for ((target, lhs), src) in resolved.into_iter().zip(targets).zip(srcs) {
+            if let Some(target) = target {
+                // the store belongs to the target, not to the whole statement
+                self.with_span(lhs.span, |generator| {
+                    let stored = generator.generate_store(target, src);
+                    generator.emit(IRInstruction::Drop { src: stored });
+                });
+            }
+        }
+    }
+
+    // evaluate an expression list into exactly `count` operands, a call at the end of the list
+    // fills the missing ones with its results; missing values are nil, surplus values are
+    // evaluated and dropped
+    fn generate_value_list(
+        &mut self,
+        values: &[parser::ast::Expr],
+        count: usize,
+    ) -> Vec<IROperand> {
+        let mut srcs = vec![];
+        for (i, value) in values.iter().enumerate() {
+            if i + 1 == values.len() && i < count && Self::is_call(value) {
+                srcs.extend(self.generate_call_values(value, count - i));
+            } else {
+                srcs.push(self.generate_expr(value));
+            }
+        }
+
+        for surplus in srcs.drain(count.min(srcs.len())..) {
+            self.emit(IRInstruction::Drop { src: surplus });
+        }
+        while srcs.len() < count {
+            let nil_reg = self.alloc_reg();
+            self.emit(IRInstruction::LoadImm {
+                dest: nil_reg,
+                value: IROperand::Nil,
+            });
+            srcs.push(IROperand::Reg(nil_reg));
+        }
+        srcs
+    }
+
+    // resolve an lvalue, the table and key of a field are evaluated here,
+    // the store itself is left to generate_store
+    fn generate_assign_target(&mut self, lhs: &parser::ast::Expr) -> Option<IRAssignTarget> {
+        self.with_span(lhs.span, |generator| {
+            generator.generate_assign_target_node(&lhs.node)
+        })
+    }
+
+    fn generate_assign_target_node(
+        &mut self,
+        lhs: &parser::ast::Expression,
+    ) -> Option<IRAssignTarget> {
+        match lhs {
+            parser::ast::Expression::Identifier(name) => match self.var_scope(name) {
+                Some(IRValueScope::Local(slot)) => Some(IRAssignTarget::Local(slot)),
+                // if the variable is not declared, then also default to global
+                Some(IRValueScope::Global) | None => Some(IRAssignTarget::Global(name.clone())),
+                Some(IRValueScope::UpVal(upval)) => Some(IRAssignTarget::UpVal(upval.slot)),
+            },
+            // non-trivial lvalue, like table member access or indexing
+            parser::ast::Expression::MemberAccess { collection, member } => {
                 // here for x.y.z.w, we unwrap it to (x.y.z) and w,
                 // and generate code for getting the table reference of x.y.z first,
                 // then set the member w in that table
@@ -1001,15 +1841,10 @@ impl IRGenerator {
                     dest: key_reg,
                     value: IROperand::ImmStr(member.clone()),
                 });
-
-                let dest_reg = self.alloc_reg();
-                self.emit(IRInstruction::SetMember {
-                    dest: dest_reg,
+                Some(IRAssignTarget::Member {
                     collection: collection_reg,
                     member: IROperand::Reg(key_reg),
-                    value: src.clone(),
-                });
-                return IROperand::Reg(dest_reg);
+                })
             }
             parser::ast::Expression::IndexOf { collection, index } => {
                 // collection and index
@@ -1017,7 +1852,7 @@ impl IRGenerator {
                 // but we need setter instructions instead of getter instructions
                 let collection_reg = self.generate_expr(collection);
 
-                match &**index {
+                match &index.node {
                     parser::ast::Expression::Literal(parser::ast::Literal::String(s)) => {
                         // string literal key
                         let key_reg = self.alloc_reg();
@@ -1025,65 +1860,218 @@ impl IRGenerator {
                             dest: key_reg,
                             value: IROperand::ImmStr(s.clone()),
                         });
-
-                        let dest_reg = self.alloc_reg();
-                        self.emit(IRInstruction::SetMember {
-                            dest: dest_reg,
+                        Some(IRAssignTarget::Member {
                             collection: collection_reg,
                             member: IROperand::Reg(key_reg),
-                            value: src.clone(),
-                        });
-                        IROperand::Reg(dest_reg)
+                        })
                     }
-                    parser::ast::Expression::Literal(parser::ast::Literal::Number(n)) => {
+                    parser::ast::Expression::Literal(
+                        lit @ (parser::ast::Literal::Number(_) | parser::ast::Literal::Integer(_)),
+                    ) => {
                         // numeric index
                         let key_reg = self.alloc_reg();
                         self.emit(IRInstruction::LoadImm {
                             dest: key_reg,
-                            value: IROperand::ImmFloat(*n),
+                            value: match lit {
+                                parser::ast::Literal::Integer(i) => IROperand::ImmInt(*i),
+                                parser::ast::Literal::Number(n) => IROperand::ImmFloat(*n),
+                                _ => unreachable!(),
+                            },
                         });
-
-                        let dest_reg = self.alloc_reg();
-                        self.emit(IRInstruction::SetIndex {
-                            dest: dest_reg,
+                        Some(IRAssignTarget::Index {
                             collection: collection_reg,
                             index: IROperand::Reg(key_reg),
-                            value: src.clone(),
-                        });
-                        IROperand::Reg(dest_reg)
+                        })
                     }
                     _ => {
                         // general case
                         let key_reg = self.generate_expr(index);
-                        let dest_reg = self.alloc_reg();
-                        self.emit(IRInstruction::SetTable {
-                            dest: dest_reg,
+                        Some(IRAssignTarget::Table {
                             table: collection_reg,
                             key: key_reg,
-                            value: src.clone(),
-                        });
-                        IROperand::Reg(dest_reg)
+                        })
                     }
                 }
             }
             _ => {
                 self.emit_err(IRGeneratorError::InvalidLValue);
-                src
+                None
             }
         }
     }
 
+    // store src into a resolved lvalue, returns the register holding the stored value
+    fn generate_store(&mut self, target: IRAssignTarget, src: IROperand) -> IROperand {
+        let dest_reg = self.alloc_reg();
+        match target {
+            IRAssignTarget::Local(slot) => {
+                self.emit(IRInstruction::StoreLocal {
+                    dest: dest_reg,
+                    dst: IROperand::Slot(slot),
+                    src,
+                });
+            }
+            IRAssignTarget::Global(name) => {
+                // first generate a LoadConst instruction to load the global variable name
+                let name_reg = self.alloc_reg();
+                self.emit(IRInstruction::LoadImm {
+                    dest: name_reg,
+                    value: IROperand::ImmStr(name),
+                });
+                self.emit(IRInstruction::StoreGlobal {
+                    dest: dest_reg,
+                    name: IROperand::Reg(name_reg),
+                    src,
+                });
+            }
+            IRAssignTarget::UpVal(slot) => {
+                self.emit(IRInstruction::StoreUpVal {
+                    dest: dest_reg,
+                    dst: IROperand::UpVal(slot),
+                    src,
+                });
+            }
+            IRAssignTarget::Member { collection, member } => {
+                self.emit(IRInstruction::SetMember {
+                    dest: dest_reg,
+                    collection,
+                    member,
+                    value: src,
+                });
+            }
+            IRAssignTarget::Index { collection, index } => {
+                self.emit(IRInstruction::SetIndex {
+                    dest: dest_reg,
+                    collection,
+                    index,
+                    value: src,
+                });
+            }
+            IRAssignTarget::Table { table, key } => {
+                self.emit(IRInstruction::SetTable {
+                    dest: dest_reg,
+                    table,
+                    key,
+                    value: src,
+                });
+            }
+        }
+        IROperand::Reg(dest_reg)
+    }
+
     fn generate_binary_expr(
         &mut self,
         op: &parser::ast::BinOp,
-        left: &parser::ast::Expression,
-        right: &parser::ast::Expression,
+        left: &parser::ast::Expr,
+        right: &parser::ast::Expr,
+        op_pos: usize,
     ) -> IROperand {
-        if let parser::ast::BinOp::Assign = op {
-            return self.generate_assignment(left, right);
+        match op {
+            parser::ast::BinOp::Assign => return self.generate_assignment(left, right),
+            parser::ast::BinOp::Concat => {
+                // `..` is right associative, a chain `a .. b .. c` nests to the right
+                let mut operands = vec![left];
+                let mut rest = right;
+                while let parser::ast::Expression::BinOp {
+                    left,
+                    operator: parser::ast::BinOp::Concat,
+                    right,
+                    ..
+                } = &rest.node
+                {
+                    operands.push(left);
+                    rest = right;
+                }
+                operands.push(rest);
+                if operands.len() > 2 {
+                    return self.generate_concat_chain(&operands);
+                }
+            }
+            _ => {}
+        }
+
+        // a left associative chain `a + b + c` nests to the left, the operators along that edge are
+        // lowered innermost first in a loop instead of recursing once per operator
+        let mut pending = vec![(self.current_context_mut().current_span, op, right, op_pos)];
+        let mut left = left;
+        let mut result = loop {
+            let (span, op, right, op_pos) = *pending.last().unwrap();
+            if let Some(value) = self.fold_binary(op, left, right, op_pos) {
+                pending.pop();
+                break self.with_span(span, |generator| generator.load_const(value));
+            }
+            match &left.node {
+                parser::ast::Expression::BinOp {
+                    left: inner,
+                    operator,
+                    right,
+                    op_pos,
+                } if !matches!(
+                    operator,
+                    parser::ast::BinOp::Assign | parser::ast::BinOp::Concat
+                ) =>
+                {
+                    pending.push((left.span, operator, right, *op_pos));
+                    left = inner;
+                }
+                _ => break self.generate_expr(left),
+            }
+        };
+
+        while let Some((span, op, right, _)) = pending.pop() {
+            result = self.with_span(span, |generator| match op {
+                parser::ast::BinOp::And | parser::ast::BinOp::Or => {
+                    generator.generate_logical_expr(op, result, right)
+                }
+                _ => generator.generate_arith_expr(op, result, right),
+            });
         }
+        result
+    }
 
-        let left_reg = self.generate_expr(left);
+    // the value of `left op right` when both operands are numeric constants, a failed fold is
+    // reported and left to the VM, which raises the error if it is ever reached
+    fn fold_binary(
+        &mut self,
+        op: &parser::ast::BinOp,
+        left: &parser::ast::Expr,
+        right: &parser::ast::Expr,
+        op_pos: usize,
+    ) -> Option<IRConstNum> {
+        if matches!(op, parser::ast::BinOp::And | parser::ast::BinOp::Or) {
+            return None;
+        }
+        let (l, r) = (Self::eval_const(left)?, Self::eval_const(right)?);
+        match Self::fold_arith(op, l.value, r.value) {
+            Ok(Some((value, overflowed))) => {
+                // one report per folded expression, pointing at the first operator that wrapped
+                let overflow_pos = l
+                    .overflow_pos
+                    .or(r.overflow_pos)
+                    .or(overflowed.then_some(op_pos));
+                if let Some(pos) = overflow_pos {
+                    self.const_warnings.push(IRConstWarning {
+                        kind: IRConstWarningKind::IntegerOverflow,
+                        pos,
+                    });
+                }
+                Some(value)
+            }
+            Ok(None) => None,
+            Err(kind) => {
+                self.const_warnings
+                    .push(IRConstWarning { kind, pos: op_pos });
+                None
+            }
+        }
+    }
+
+    fn generate_arith_expr(
+        &mut self,
+        op: &parser::ast::BinOp,
+        left_reg: IROperand,
+        right: &parser::ast::Expr,
+    ) -> IROperand {
         let right_reg = self.generate_expr(right);
         let dest_reg = self.alloc_reg();
 
@@ -1101,9 +2089,9 @@ impl IRGenerator {
             parser::ast::BinOp::Gt => IRBinOp::Gt,
             parser::ast::BinOp::Leq => IRBinOp::Leq,
             parser::ast::BinOp::Geq => IRBinOp::Geq,
-            parser::ast::BinOp::And => IRBinOp::And,
-            parser::ast::BinOp::Or => IRBinOp::Or,
-            parser::ast::BinOp::Assign => unreachable!(),
+            parser::ast::BinOp::And | parser::ast::BinOp::Or | parser::ast::BinOp::Assign => {
+                unreachable!()
+            }
         };
 
         self.emit(IRInstruction::Binary {
@@ -1116,20 +2104,197 @@ impl IRGenerator {
         IROperand::Reg(dest_reg)
     }
 
+    // every operand of a `..` chain is evaluated, left to right, before one ConcatN joins them,
+    // instead of a Binary Concat and an intermediate string per operator
+    fn generate_concat_chain(&mut self, operands: &[&parser::ast::Expr]) -> IROperand {
+        let srcs = operands
+            .iter()
+            .map(|operand| self.generate_expr(operand))
+            .collect();
+        let dest_reg = self.alloc_reg();
+        self.emit(IRInstruction::ConcatN {
+            dest: dest_reg,
+            srcs,
+        });
+        IROperand::Reg(dest_reg)
+    }
+
+    // `a and b` / `a or b`, b is only evaluated when a does not decide the result:
+    //
+    //     %r = Move %a
+    //     Branch %a, rhs, merge    (or: Branch %a, merge, rhs)
+    //   rhs:
+    //     %r = Move %b
+    //   merge:
+    fn generate_logical_expr(
+        &mut self,
+        op: &parser::ast::BinOp,
+        left_reg: IROperand,
+        right: &parser::ast::Expr,
+    ) -> IROperand {
+        let dest_reg = self.alloc_reg();
+        self.emit(IRInstruction::Move {
+            dest: dest_reg,
+            src: left_reg.clone(),
+        });
+
+        let rhs_bb_id = self.alloc_bb_id();
+        let merge_bb_id = self.alloc_bb_id();
+        let (br_true, br_false) = match op {
+            parser::ast::BinOp::And => (rhs_bb_id, merge_bb_id),
+            _ => (merge_bb_id, rhs_bb_id),
+        };
+        self.try_close_bb(IRTerminator::Branch {
+            cond: left_reg,
+            br_true,
+            br_false,
+        });
+
+        self.open_bb_lazy(rhs_bb_id);
+        let right_reg = self.generate_expr(right);
+        self.emit(IRInstruction::Move {
+            dest: dest_reg,
+            src: right_reg,
+        });
+        self.try_close_bb(IRTerminator::Jump(merge_bb_id));
+
+        self.open_bb_lazy(merge_bb_id);
+        IROperand::Reg(dest_reg)
+    }
+
+    // value of a numeric constant expression: literals combined with + - * / % and unary minus;
+    // None if anything else is involved or an operation cannot be folded
+    fn eval_const(expr: &parser::ast::Expr) -> Option<IRConstValue> {
+        match &expr.node {
+            parser::ast::Expression::Literal(parser::ast::Literal::Integer(i)) => {
+                Some(IRConstValue::new(IRConstNum::Int(*i)))
+            }
+            parser::ast::Expression::Literal(parser::ast::Literal::Number(n)) => {
+                Some(IRConstValue::new(IRConstNum::Float(*n)))
+            }
+            parser::ast::Expression::UnOp {
+                operator: parser::ast::UnOp::Neg,
+                operand,
+            } => {
+                let inner = Self::eval_const(operand)?;
+                let value = match inner.value {
+                    IRConstNum::Int(i) => IRConstNum::Int(i.wrapping_neg()),
+                    IRConstNum::Float(n) => IRConstNum::Float(-n),
+                };
+                Some(IRConstValue { value, ..inner })
+            }
+            parser::ast::Expression::BinOp {
+                left,
+                operator,
+                right,
+                op_pos,
+            } => {
+                let l = Self::eval_const(left)?;
+                let r = Self::eval_const(right)?;
+                let (value, overflowed) = Self::fold_arith(operator, l.value, r.value).ok()??;
+                Some(IRConstValue {
+                    value,
+                    overflow_pos: l
+                        .overflow_pos
+                        .or(r.overflow_pos)
+                        .or(overflowed.then_some(*op_pos)),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    // fold one arithmetic operator the way the VM evaluates it, the flag tells whether
+    // integer arithmetic wrapped around; Ok(None) for operators that are not folded
+    fn fold_arith(
+        op: &parser::ast::BinOp,
+        l: IRConstNum,
+        r: IRConstNum,
+    ) -> Result<Option<(IRConstNum, bool)>, IRConstWarningKind> {
+        let (int_op, float_op): (IRConstIntOp, fn(f64, f64) -> f64) = match op {
+            parser::ast::BinOp::Add => (i64::overflowing_add, |a, b| a + b),
+            parser::ast::BinOp::Sub => (i64::overflowing_sub, |a, b| a - b),
+            parser::ast::BinOp::Mul => (i64::overflowing_mul, |a, b| a * b),
+            parser::ast::BinOp::Div => {
+                if r.as_float() == 0.0 {
+                    return Err(IRConstWarningKind::DivisionByZero);
+                }
+                return Ok(Some((
+                    IRConstNum::Float(l.as_float() / r.as_float()),
+                    false,
+                )));
+            }
+            parser::ast::BinOp::Mod => {
+                if r.as_float() == 0.0 {
+                    return Err(IRConstWarningKind::ModuloByZero);
+                }
+                (
+                    |a, b| {
+                        let r = a.wrapping_rem(b);
+                        (if r != 0 && (r ^ b) < 0 { r + b } else { r }, false)
+                    },
+                    |a, b| {
+                        let r = a % b;
+                        if r != 0.0 && (r < 0.0) != (b < 0.0) {
+                            r + b
+                        } else {
+                            r
+                        }
+                    },
+                )
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(match (l, r) {
+            (IRConstNum::Int(a), IRConstNum::Int(b)) => {
+                let (res, overflowed) = int_op(a, b);
+                (IRConstNum::Int(res), overflowed)
+            }
+            _ => (
+                IRConstNum::Float(float_op(l.as_float(), r.as_float())),
+                false,
+            ),
+        }))
+    }
+
+    fn load_const(&mut self, value: IRConstNum) -> IROperand {
+        let dest_reg = self.alloc_reg();
+        self.emit(IRInstruction::LoadImm {
+            dest: dest_reg,
+            value: match value {
+                IRConstNum::Int(i) => IROperand::ImmInt(i),
+                IRConstNum::Float(n) => IROperand::ImmFloat(n),
+            },
+        });
+        IROperand::Reg(dest_reg)
+    }
+
     fn generate_unary_expr(
         &mut self,
         op: &parser::ast::UnOp,
-        operand: &parser::ast::Expression,
+        operand: &parser::ast::Expr,
     ) -> IROperand {
+        if let parser::ast::UnOp::Neg = op
+            && let Some(folded) = Self::eval_const(operand)
+        {
+            let value = match folded.value {
+                IRConstNum::Int(i) => IRConstNum::Int(i.wrapping_neg()),
+                IRConstNum::Float(n) => IRConstNum::Float(-n),
+            };
+            return self.load_const(value);
+        }
         let operand_reg = self.generate_expr(operand);
-        let dest_reg = self.alloc_reg();
-
         let ir_op = match op {
-            parser::ast::UnOp::Pos => unimplemented!(), // why do you need this sh*t?
+            parser::ast::UnOp::Pos => {
+                self.emit_err(IRGeneratorError::UnsupportedOperator("+".into()));
+                return operand_reg;
+            }
             parser::ast::UnOp::Neg => IRUnOp::Neg,
             parser::ast::UnOp::Not => IRUnOp::Not,
             parser::ast::UnOp::TblLen => IRUnOp::TblLen,
         };
+        let dest_reg = self.alloc_reg();
 
         self.emit(IRInstruction::Unary {
             dest: dest_reg,
@@ -1143,6 +2308,7 @@ impl IRGenerator {
     fn generate_simple_literal(&mut self, lit: &parser::ast::Literal) -> IROperand {
         let imm_val = match lit {
             parser::ast::Literal::Number(n) => IROperand::ImmFloat(*n),
+            parser::ast::Literal::Integer(i) => IROperand::ImmInt(*i),
             parser::ast::Literal::String(s) => IROperand::ImmStr(s.clone()),
             parser::ast::Literal::Boolean(b) => IROperand::ImmBool(*b),
             parser::ast::Literal::Nil => IROperand::Nil,
@@ -1161,7 +2327,7 @@ impl IRGenerator {
 
     fn generate_table_ctor_expr(
         &mut self,
-        fields: &Vec<(Option<parser::ast::Expression>, parser::ast::Expression)>,
+        fields: &[(Option<parser::ast::Expr>, parser::ast::Expr)],
     ) -> IROperand {
         // make table prototype
 
@@ -1175,22 +2341,8 @@ impl IRGenerator {
                     (a, h + 1)
                 }
             });
-            let asize_op = IROperand::ImmFloat(asize as f64);
-            let hsize_op = IROperand::ImmFloat(hsize as f64);
-
-            let asize_reg = self.alloc_reg();
-            self.emit(IRInstruction::LoadImm {
-                dest: asize_reg,
-                value: asize_op,
-            });
-
-            let hsize_reg = self.alloc_reg();
-            self.emit(IRInstruction::LoadImm {
-                dest: hsize_reg,
-                value: hsize_op,
-            });
-
-            (IROperand::Reg(asize_reg), IROperand::Reg(hsize_reg))
+            // immediates, the emitter turns them into the NEWTABLE size hints
+            (IROperand::ImmInt(asize), IROperand::ImmInt(hsize))
         };
 
         // create table register
@@ -1204,8 +2356,10 @@ impl IRGenerator {
         let tbl_reg = IROperand::Reg(tbl_reg);
 
         // set fields
-        // lua tables are 1-indexed!!!
-        let mut idx = 1;
+        // lua tables are 1-indexed!!! the values of positional fields wait in `positional`
+        // for the SetList of their batch
+        let mut positional = Vec::new();
+        let mut batch = 0;
 
         fields.iter().for_each(|(key_opt, value_expr)| {
             match key_opt {
@@ -1220,7 +2374,7 @@ impl IRGenerator {
                     let key_reg = self.generate_expr(k);
                     let value_reg = self.generate_expr(value_expr);
                     let dest_reg = self.alloc_reg();
-                    match k {
+                    match &k.node {
                         parser::ast::Expression::Literal(parser::ast::Literal::String(_)) => {
                             // string literal key, can use SetMember instruction
                             self.emit(IRInstruction::SetMember {
@@ -1230,7 +2384,9 @@ impl IRGenerator {
                                 value: value_reg,
                             });
                         }
-                        parser::ast::Expression::Literal(parser::ast::Literal::Number(_)) => {
+                        parser::ast::Expression::Literal(
+                            parser::ast::Literal::Number(_) | parser::ast::Literal::Integer(_),
+                        ) => {
                             // numeric key, can use IndexOf instruction
                             // this is basically array-like access, but with explicit keys
                             self.emit(IRInstruction::SetIndex {
@@ -1252,32 +2408,140 @@ impl IRGenerator {
                     }
                 }
                 None => {
-                    // array-like field, key is the next index
-                    // we can directly use the index as the key operand in SetIndex
-                    let key_reg = self.alloc_reg();
-                    self.emit(IRInstruction::LoadImm {
-                        dest: key_reg,
-                        value: IROperand::ImmFloat(idx as f64),
-                    });
-
-                    let value_reg = self.generate_expr(value_expr);
-
-                    let dest_reg = self.alloc_reg();
-                    self.emit(IRInstruction::SetIndex {
-                        dest: dest_reg,
-                        collection: tbl_reg.clone(),
-                        index: IROperand::Reg(key_reg),
-                        value: value_reg,
-                    });
+                    // array-like field, stored with the others of its batch once the batch is full
+                    // or the constructor ends, as Lua does
+                    positional.push(self.generate_expr(value_expr));
+                    if positional.len() == SETLIST_BATCH {
+                        self.emit(IRInstruction::SetList {
+                            table: tbl_reg.clone(),
+                            batch,
+                            values: std::mem::take(&mut positional),
+                        });
+                        batch += 1;
+                    }
                 }
             }
-            idx += 1;
         });
+        if !positional.is_empty() {
+            self.emit(IRInstruction::SetList {
+                table: tbl_reg.clone(),
+                batch,
+                values: positional,
+            });
+        }
 
         tbl_reg
     }
 
-    fn generate_expr(&mut self, expr: &parser::ast::Expression) -> IROperand {
+    fn is_call(expr: &parser::ast::Expr) -> bool {
+        matches!(
+            expr.node,
+            parser::ast::Expression::FnCall { .. } | parser::ast::Expression::MethodCall { .. }
+        )
+    }
+
+    // a call that takes `results` of the values the callee returns (None: all of them),
+    // the operand is the register of the first one
+    fn generate_call(
+        &mut self,
+        call: &parser::ast::Expression,
+        results: Option<usize>,
+    ) -> IROperand {
+        let (callee, args) = match call {
+            parser::ast::Expression::FnCall { callee, arguments } => {
+                // any fn
+                let callee_reg = self.generate_expr(callee);
+                (callee_reg, self.generate_args(vec![], arguments))
+            }
+            parser::ast::Expression::MethodCall {
+                object,
+                method,
+                arguments,
+            } => {
+                // obj:m(args) => obj.m(obj, args)
+                // the receiver is evaluated exactly once and reused as the first argument
+                let object_reg = self.generate_expr(object);
+
+                let method_reg = self.alloc_reg();
+                self.emit(IRInstruction::LoadImm {
+                    dest: method_reg,
+                    value: IROperand::ImmStr(method.clone()),
+                });
+
+                let callee_reg = self.alloc_reg();
+                self.emit(IRInstruction::MemberOf {
+                    dest: callee_reg,
+                    collection: object_reg.clone(),
+                    member: IROperand::Reg(method_reg),
+                });
+
+                (
+                    IROperand::Reg(callee_reg),
+                    self.generate_args(vec![object_reg], arguments),
+                )
+            }
+            _ => unreachable!("generate_call on an expression that is not a call"),
+        };
+
+        let dest_reg = self.alloc_reg();
+        self.emit(IRInstruction::Call {
+            dest: dest_reg,
+            callee,
+            args,
+            results,
+        });
+        IROperand::Reg(dest_reg)
+    }
+
+    // the arguments of a call after the ones in `args`, a call as the last argument passes on
+    // every value it returns
+    fn generate_args(
+        &mut self,
+        mut args: Vec<IROperand>,
+        arguments: &[parser::ast::Expr],
+    ) -> Vec<IROperand> {
+        if let Some((last, leading)) = arguments.split_last() {
+            for arg in leading {
+                let arg_reg = self.generate_expr(arg);
+                args.push(arg_reg);
+            }
+            args.push(self.generate_multi_expr(last));
+        }
+        args
+    }
+
+    // the last argument of a call or the last value of a return, a call there takes all its results
+    fn generate_multi_expr(&mut self, expr: &parser::ast::Expr) -> IROperand {
+        if Self::is_call(expr) {
+            self.with_span(expr.span, |generator| {
+                generator.generate_call(&expr.node, None)
+            })
+        } else {
+            self.generate_expr(expr)
+        }
+    }
+
+    // a call that fills `count` values, e.g. the two targets of `local a, b = f()`
+    fn generate_call_values(&mut self, call: &parser::ast::Expr, count: usize) -> Vec<IROperand> {
+        self.with_span(call.span, |generator| {
+            let mut values = vec![generator.generate_call(&call.node, Some(count))];
+            for index in 1..count {
+                let dest = generator.alloc_reg();
+                generator.emit(IRInstruction::CallResult { dest, index });
+                values.push(IROperand::Reg(dest));
+            }
+            values
+        })
+    }
+
+    // the instructions of an expression map back to its span, those of its operands to theirs
+    fn generate_expr(&mut self, expr: &parser::ast::Expr) -> IROperand {
+        self.with_span(expr.span, |generator| {
+            generator.generate_expr_node(&expr.node)
+        })
+    }
+
+    fn generate_expr_node(&mut self, expr: &parser::ast::Expression) -> IROperand {
         match expr {
             parser::ast::Expression::Identifier(name) => {
                 let scope = self.var_scope(name);
@@ -1323,15 +2587,22 @@ impl IRGenerator {
             }
             parser::ast::Expression::Literal(lit) => match lit {
                 parser::ast::Literal::Number(_)
+                | parser::ast::Literal::Integer(_)
                 | parser::ast::Literal::String(_)
                 | parser::ast::Literal::Boolean(_)
                 | parser::ast::Literal::Nil => {
                     return self.generate_simple_literal(lit);
                 }
-                parser::ast::Literal::Function { name, params, body } => {
+                parser::ast::Literal::Function {
+                    name,
+                    params,
+                    body,
+                    param_pos,
+                } => {
                     // function literal
                     // this generates a function prototype and returns the function reference
-                    let func_operand = self.generate_fn_decl_impl(true, name, params, body);
+                    let func_operand =
+                        self.generate_fn_decl_impl(true, name, params, param_pos, body);
 
                     // instantiate the function prototype
                     let dest_reg = self.alloc_reg();
@@ -1348,28 +2619,13 @@ impl IRGenerator {
                 left,
                 operator,
                 right,
-            } => self.generate_binary_expr(operator, left, right),
+                op_pos,
+            } => self.generate_binary_expr(operator, left, right, *op_pos),
             parser::ast::Expression::UnOp { operator, operand } => {
                 self.generate_unary_expr(operator, operand)
             }
-            parser::ast::Expression::FnCall { callee, arguments } => {
-                // any fn
-                let callee_reg = self.generate_expr(callee);
-                // args
-                let mut arg_regs = vec![];
-                for arg in arguments {
-                    let arg_reg = self.generate_expr(arg);
-                    arg_regs.push(arg_reg);
-                }
-
-                let dest_reg = self.alloc_reg();
-                self.emit(IRInstruction::Call {
-                    dest: dest_reg,
-                    callee: callee_reg,
-                    args: arg_regs,
-                });
-
-                IROperand::Reg(dest_reg)
+            parser::ast::Expression::FnCall { .. } | parser::ast::Expression::MethodCall { .. } => {
+                self.generate_call(expr, Some(1))
             }
             parser::ast::Expression::IndexOf { collection, index } => {
                 // collection and index
@@ -1380,7 +2636,7 @@ impl IRGenerator {
                 let collection_reg = self.generate_expr(collection);
                 let index_reg = self.generate_expr(index);
 
-                match **index {
+                match index.node {
                     parser::ast::Expression::Literal(parser::ast::Literal::String(_)) => {
                         // string literal key, can use MemberOf instruction
                         // if backend implements MemberOf, it can prehash the member name
@@ -1393,7 +2649,9 @@ impl IRGenerator {
                         });
                         IROperand::Reg(dest_reg)
                     }
-                    parser::ast::Expression::Literal(parser::ast::Literal::Number(_)) => {
+                    parser::ast::Expression::Literal(
+                        parser::ast::Literal::Number(_) | parser::ast::Literal::Integer(_),
+                    ) => {
                         // numeric index, can use IndexOf instruction
                         // if backend implements IndexOf, this can be optimized as array access
                         let dest_reg = self.alloc_reg();
@@ -1438,48 +2696,56 @@ impl IRGenerator {
         }
     }
 
+    // statements of a block in a scope of their own
+    fn generate_block(&mut self, body: &[parser::ast::Stmt]) {
+        self.open_scope();
+        self.generate_stmts(body);
+        self.close_scope();
+    }
+
     fn generate_if_expr(
         &mut self,
-        condition: &parser::ast::Expression,
-        then_branch: &Vec<parser::ast::Statement>,
-        else_branch: &Option<Vec<parser::ast::Statement>>,
+        condition: &parser::ast::Expr,
+        then_branch: &[parser::ast::Stmt],
+        elif_branches: &[(parser::ast::Expr, Vec<parser::ast::Stmt>)],
+        else_branch: &Option<Vec<parser::ast::Stmt>>,
     ) {
-        let cond_reg = self.generate_expr(condition);
-
-        // then, else blocks
-        // todo: elif branches
-        let then_bb_id = self.alloc_bb_id();
-        let else_bb_id = self.alloc_bb_id();
         let merge_bb_id = self.alloc_bb_id();
 
-        self.try_close_bb(IRTerminator::Branch {
-            cond: cond_reg,
-            br_true: then_bb_id,
-            br_false: else_bb_id,
-        });
+        // every `elseif` is tested in the false block of the condition before it,
+        // each body jumps to the common merge block
+        let clauses = std::iter::once((condition, then_branch)).chain(
+            elif_branches
+                .iter()
+                .map(|(cond, body)| (cond, body.as_slice())),
+        );
+        for (condition, body) in clauses {
+            let cond_reg = self.generate_expr(condition);
+            let then_bb_id = self.alloc_bb_id();
+            let else_bb_id = self.alloc_bb_id();
+
+            self.try_close_bb(IRTerminator::Branch {
+                cond: cond_reg,
+                br_true: then_bb_id,
+                br_false: else_bb_id,
+            });
 
-        self.open_bb_lazy(then_bb_id);
-        for stmt in then_branch {
-            self.generate_stmt(stmt);
+            self.open_bb_lazy(then_bb_id);
+            self.generate_block(body);
+            self.try_close_bb(IRTerminator::Jump(merge_bb_id));
+
+            self.open_bb_lazy(else_bb_id);
         }
-        self.try_close_bb(IRTerminator::Jump(merge_bb_id));
 
-        self.open_bb_lazy(else_bb_id);
         if let Some(else_branch) = else_branch {
-            for stmt in else_branch {
-                self.generate_stmt(stmt);
-            }
+            self.generate_block(else_branch);
         }
         self.try_close_bb(IRTerminator::Jump(merge_bb_id));
 
         self.open_bb_lazy(merge_bb_id);
     }
 
-    fn generate_while_expr(
-        &mut self,
-        condition: &parser::ast::Expression,
-        body: &Vec<parser::ast::Statement>,
-    ) {
+    fn generate_while_expr(&mut self, condition: &parser::ast::Expr, body: &[parser::ast::Stmt]) {
         let cond_bb_id = self.alloc_bb_id();
         let body_bb_id = self.alloc_bb_id();
         let merge_bb_id = self.alloc_bb_id();
@@ -1498,9 +2764,7 @@ impl IRGenerator {
 
         // loop body block
         self.open_bb_lazy(body_bb_id);
-        for stmt in body {
-            self.generate_stmt(stmt);
-        }
+        self.generate_block(body);
         // after body, jump back to condition check
         self.try_close_bb(IRTerminator::Jump(cond_bb_id));
 
@@ -1508,11 +2772,7 @@ impl IRGenerator {
         self.open_bb_lazy(merge_bb_id);
     }
 
-    fn generate_repeat_expr(
-        &mut self,
-        body: &Vec<parser::ast::Statement>,
-        condition: &parser::ast::Expression,
-    ) {
+    fn generate_repeat_expr(&mut self, body: &[parser::ast::Stmt], condition: &parser::ast::Expr) {
         let body_bb_id = self.alloc_bb_id();
         let cond_bb_id = self.alloc_bb_id();
         let merge_bb_id = self.alloc_bb_id();
@@ -1521,16 +2781,17 @@ impl IRGenerator {
         self.try_close_bb(IRTerminator::FallThrough);
 
         // loop body block
+        // the locals of the body are still visible in the condition
         self.open_bb_lazy(body_bb_id);
-        for stmt in body {
-            self.generate_stmt(stmt);
-        }
+        self.open_scope();
+        self.generate_stmts(body);
         // after body, fall through to condition check
         self.try_close_bb(IRTerminator::FallThrough);
 
         // condition check block
         self.open_bb_lazy(cond_bb_id);
         let cond_reg = self.generate_expr(condition);
+        self.close_scope();
         self.try_close_bb(IRTerminator::Branch {
             cond: cond_reg,
             br_true: merge_bb_id,
@@ -1545,8 +2806,9 @@ impl IRGenerator {
         &mut self,
         is_local: bool,
         name: &Option<String>,
-        params: &Vec<String>,
-        body: &Vec<parser::ast::Statement>,
+        params: &[String],
+        param_pos: &[usize],
+        body: &[parser::ast::Stmt],
     ) -> IROperand {
         let func_name = if let Some(name) = name {
             if is_local {
@@ -1564,18 +2826,16 @@ impl IRGenerator {
         };
 
         // create a new function context
-        self.open_function(func_name.clone(), params.clone());
+        self.open_function(func_name.clone(), params.to_vec());
 
         // declare parameters as local variables
-        for param in params {
-            self.decl_local(param.clone());
+        for (i, param) in params.iter().enumerate() {
+            self.decl_local(param, param_pos.get(i).copied().unwrap_or(0), true);
         }
 
         // generate function body
         self.open_bb();
-        for stmt in body {
-            self.generate_stmt(stmt);
-        }
+        self.generate_stmts(body);
 
         // if the block is still open, close it with a return
         self.try_close_bb(IRTerminator::Return(vec![IROperand::Unit]));
@@ -1586,53 +2846,131 @@ impl IRGenerator {
         IROperand::Proto(func_name)
     }
 
-    fn generate_return_stmt(&mut self, values: &Vec<parser::ast::Expression>) {
-        let mut ret_operands = vec![];
-        for val in values {
-            let val_reg = self.generate_expr(val);
-            ret_operands.push(val_reg);
-        }
-
-        // this should be the last instruction in the current basic block
+    fn generate_return_stmt(&mut self, values: &[parser::ast::Expr]) {
+        // this should be the last instruction in the current basic block, a second return has
+        // no block left to evaluate its values in
         if !self.has_active_bb() {
             self.emit_err(IRGeneratorError::MultipleReturnStatements);
+            return;
+        }
+
+        // a call as the last value returns every value it returns
+        let mut ret_operands = vec![];
+        if let Some((last, leading)) = values.split_last() {
+            for val in leading {
+                let val_reg = self.generate_expr(val);
+                ret_operands.push(val_reg);
+            }
+            ret_operands.push(self.generate_multi_expr(last));
         }
         self.close_bb(IRTerminator::Return(ret_operands));
     }
 
-    fn generate_stmt(&mut self, stmt: &parser::ast::Statement) {
-        match stmt {
+    // the name of a `local function NAME` statement
+    fn local_function_name(stmt: &parser::ast::Stmt) -> Option<&str> {
+        match &stmt.node {
+            parser::ast::Statement::Declaration { names, values, .. } => {
+                match (names.as_slice(), values.first().map(|value| &value.node)) {
+                    (
+                        [name],
+                        Some(parser::ast::Expression::Literal(parser::ast::Literal::Function {
+                            name: Some(fn_name),
+                            ..
+                        })),
+                    ) if fn_name == name => Some(name),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // where the name of a `local function` statement is
+    fn local_function_pos(stmt: &parser::ast::Stmt) -> usize {
+        match &stmt.node {
+            parser::ast::Statement::Declaration { name_pos, .. } => {
+                name_pos.first().copied().unwrap_or(0)
+            }
+            _ => 0,
+        }
+    }
+
+    // statements of a block in the current scope; at the first of a run of `local function`
+    // statements the names of the whole run are declared, until a name repeats
+    fn generate_stmts(&mut self, body: &[parser::ast::Stmt]) {
+        for (i, stmt) in body.iter().enumerate() {
+            let starts_run = Self::local_function_name(stmt).is_some()
+                && (i == 0 || Self::local_function_name(&body[i - 1]).is_none());
+            if starts_run {
+                let mut seen = HashSet::new();
+                for next in &body[i..] {
+                    let Some(name) = Self::local_function_name(next) else {
+                        break;
+                    };
+                    if !seen.insert(name) {
+                        break;
+                    }
+                    let pos = Self::local_function_pos(next);
+                    let slot = self.decl_local(name, pos, false);
+                    self.current_context_mut()
+                        .declared_functions
+                        .insert(pos, slot);
+                }
+            }
+            self.generate_stmt(stmt);
+        }
+    }
+
+    fn generate_stmt(&mut self, stmt: &parser::ast::Stmt) {
+        self.current_context_mut().current_line = stmt.line;
+        self.current_context_mut().current_span = stmt.span;
+        // a second return reports itself, see generate_return_stmt
+        if !self.has_active_bb() && !matches!(stmt.node, parser::ast::Statement::ReturnStmt { .. })
+        {
+            self.emit_err(IRGeneratorError::StatementAfterReturn);
+            return;
+        }
+        match &stmt.node {
             parser::ast::Statement::ExprStatement(expr) => {
                 let reg = self.generate_expr(expr);
                 // drop the result of the expression statement, since not used
                 self.emit(IRInstruction::Drop { src: reg });
             }
-            parser::ast::Statement::Declaration { names, values } => {
-                for (name, value) in names.iter().zip(values.iter()) {
-                    let src = self.generate_expr(value);
+            parser::ast::Statement::Declaration { names, values, .. }
+                if Self::local_function_name(stmt).is_some() =>
+            {
+                // `local function f` is `local f; f = function ...`, f is visible in its own body
+                let pos = Self::local_function_pos(stmt);
+                let declared = self.current_context_mut().declared_functions.remove(&pos);
+                let slot = declared.unwrap_or_else(|| self.decl_local(&names[0], pos, false));
+                let src = self.generate_expr(&values[0]);
+                let dest_reg = self.alloc_reg();
+                self.emit(IRInstruction::StoreLocal {
+                    dest: dest_reg,
+                    dst: IROperand::Slot(slot),
+                    src,
+                });
+                self.emit(IRInstruction::Drop {
+                    src: IROperand::Reg(dest_reg),
+                });
+            }
+            parser::ast::Statement::Declaration {
+                names,
+                values,
+                name_pos,
+            } => {
+                // the values are evaluated before any of the names is in scope,
+                // so `local x = x` reads the outer x
+                let srcs = self.generate_value_list(values, names.len());
+                for (i, (name, src)) in names.iter().zip(srcs).enumerate() {
                     // by default, 'Declaration' is for local variables
-                    let scope = self.find_local(name);
-                    let slot = if let Some(slot) = scope {
-                        // redefinition of local variable in the same scope
-                        // this can happen, for example, in:
-                        // if cond then
-                        //     local x = 1
-                        // else
-                        //     local x = 2 -- redefinition in the same scope
-                        // end
-                        //
-                        // if already declared, just use the existing register
-                        slot
-                    } else {
-                        // otherwise, declare a new local variable
-                        self.decl_local(name.clone())
-                    };
+                    let slot = self.decl_local(name, name_pos.get(i).copied().unwrap_or(0), false);
 
                     let dest_reg = self.alloc_reg();
                     self.emit(IRInstruction::StoreLocal {
                         dest: dest_reg,
                         dst: IROperand::Slot(slot),
-                        src: src.clone(),
+                        src,
                     });
 
                     // StoreLocal returns the value stored, but we don't need it for declaration
@@ -1642,16 +2980,25 @@ impl IRGenerator {
                     });
                 }
             }
+            parser::ast::Statement::Assignment { targets, values } => {
+                self.generate_multiple_assignment(targets, values);
+            }
             parser::ast::Statement::IfStmt {
                 condition,
                 then_branch,
                 elif_branches,
                 else_branch,
             } => {
-                if !elif_branches.is_empty() {
-                    unimplemented!("Elif branches are not supported yet");
-                }
-                self.generate_if_expr(condition, then_branch, else_branch);
+                self.generate_if_expr(condition, then_branch, elif_branches, else_branch);
+            }
+            parser::ast::Statement::Goto { label } => {
+                self.generate_goto(label);
+            }
+            parser::ast::Statement::Label { name } => {
+                self.generate_label(name);
+            }
+            parser::ast::Statement::DoStmt { body } => {
+                self.generate_block(body);
             }
             parser::ast::Statement::WhileStmt { condition, body } => {
                 self.generate_while_expr(condition, body);
@@ -1662,7 +3009,6 @@ impl IRGenerator {
             parser::ast::Statement::ReturnStmt { values } => {
                 self.generate_return_stmt(values);
             }
-            _ => unimplemented!(),
         }
     }
 
@@ -1673,9 +3019,7 @@ impl IRGenerator {
         self.open_function("_start".to_string(), vec![]);
 
         self.open_bb();
-        for stmt in &module.body {
-            self.generate_stmt(stmt);
-        }
+        self.generate_stmts(&module.body);
 
         // if the block is still open, close it with a return
         self.try_close_bb(IRTerminator::Return(vec![IROperand::Unit]));
@@ -1685,6 +3029,21 @@ impl IRGenerator {
 
     pub fn generate(&mut self, program: &parser::ast::Program) {
         self.generate_module(program);
+        if self.const_fold {
+            opt::const_fold::run(&mut self.module);
+        }
+        if self.cse {
+            opt::cse::run(&mut self.module);
+        }
+        if self.jump_tables {
+            opt::jump_table::run(&mut self.module);
+        }
+        if self.licm {
+            opt::licm::run(&mut self.module);
+        }
+        if self.dce {
+            opt::dce::run(&mut self.module);
+        }
     }
 
     pub fn get_module(&self) -> &IRModule {
@@ -0,0 +1,365 @@
+// Myula compiler IR constant folding and propagation
+//
+// Changelog:
+//      26-02-24: Initial version. Runs on the generated IR of every function:
+//                - Binary / Unary instructions whose operands are constants become a LoadImm of the result,
+//                  evaluated exactly like the VM would (integer wrapping, float division, Lua modulo),
+//                  operations that would raise at runtime (1 % 0, 1 < "a") are left alone
+//                - constants stored into a local are forwarded to loads of that local in the same block,
+//                  unless a closure captures the local and may change it behind our back
+//                - Branch on a constant becomes a Jump, blocks that can no longer be reached are removed
+//                - LoadImm instructions whose register is no longer used are removed
+//      26-02-24: Dropping the leftover loads and unreachable blocks is the job of the dce pass,
+//                this pass only prunes the blocks it needs gone to merge their neighbours
+//      26-02-24: A ConcatN of string constants folds to one string
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use crate::common::object::{LuaValue, compare_numbers};
+use crate::frontend::ir::{
+    IRBasicBlock, IRBinOp, IRConstNum, IRFunction, IRGenerator, IRInstruction, IRModule, IROperand,
+    IRTerminator, IRUnOp, IRUpValType,
+};
+use crate::frontend::parser::ast::{BinOp, Span};
+
+use super::dce::remove_unreachable_blocks;
+
+pub fn run(module: &mut IRModule) {
+    // slots of each function that one of its closures captures
+    let mut captured: HashMap<String, HashSet<usize>> = HashMap::new();
+    for func in &module.functions {
+        let slots = module
+            .functions
+            .iter()
+            .filter(|child| func.sub_functions.contains(&child.name))
+            .flat_map(|child| child.upvalues.values())
+            .filter_map(|upval| match upval.ty {
+                IRUpValType::LocalVar(slot) => Some(slot),
+                IRUpValType::UpVal(_) => None,
+            })
+            .collect();
+        captured.insert(func.name.clone(), slots);
+    }
+
+    for func in &mut module.functions {
+        let captured = captured.remove(&func.name).unwrap_or_default();
+        fold_function(func, &captured);
+    }
+}
+
+fn fold_function(func: &mut IRFunction, captured: &HashSet<usize>) {
+    // folding a branch makes blocks unreachable or leaves a block with a single predecessor,
+    // merging it into that predecessor exposes more constants (e.g. the result of `and`/`or`)
+    loop {
+        fold_constants(func, captured);
+        let removed = remove_unreachable_blocks(func);
+        let merged = merge_blocks(func);
+        if !removed && !merged {
+            break;
+        }
+    }
+}
+
+fn fold_constants(func: &mut IRFunction, captured: &HashSet<usize>) {
+    // a register is a constant only if it is defined once, Move may define one several times
+    let mut defs: HashMap<usize, usize> = HashMap::new();
+    for block in &func.basic_blocks {
+        for instr in &block.instructions {
+            if let Some(dest) = instr.def_reg() {
+                *defs.entry(dest).or_insert(0) += 1;
+            }
+        }
+    }
+
+    // the IR is in SSA form, so a constant register holds its value wherever it is used;
+    // blocks are not in dominance order, so repeat until nothing changes
+    let mut consts: HashMap<usize, IROperand> = HashMap::new();
+    loop {
+        let mut changed = false;
+        for block in &mut func.basic_blocks {
+            changed |= fold_block(block, &defs, &mut consts, captured);
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+fn fold_block(
+    block: &mut IRBasicBlock,
+    defs: &HashMap<usize, usize>,
+    consts: &mut HashMap<usize, IROperand>,
+    captured: &HashSet<usize>,
+) -> bool {
+    let mut changed = false;
+    // local slot -> constant last stored into it in this block
+    let mut slot_consts: HashMap<usize, IROperand> = HashMap::new();
+    // register written by Move -> constant it holds from that Move to the end of this block
+    let mut move_consts: HashMap<usize, IROperand> = HashMap::new();
+    let const_of = |consts: &HashMap<usize, IROperand>,
+                    move_consts: &HashMap<usize, IROperand>,
+                    op: &IROperand| match op {
+        IROperand::Reg(r) => consts.get(r).or_else(|| move_consts.get(r)).cloned(),
+        _ => None,
+    };
+
+    for instr in &mut block.instructions {
+        let folded = match instr {
+            IRInstruction::LoadLocal {
+                dest,
+                src: IROperand::Slot(slot),
+            } => slot_consts.get(slot).map(|value| (*dest, value.clone())),
+            IRInstruction::StoreLocal {
+                dst: IROperand::Slot(slot),
+                src,
+                ..
+            } => {
+                match const_of(consts, &move_consts, src) {
+                    Some(value) if !captured.contains(slot) => slot_consts.insert(*slot, value),
+                    _ => slot_consts.remove(slot),
+                };
+                None
+            }
+            IRInstruction::Move { dest, src } => {
+                match const_of(consts, &move_consts, src) {
+                    Some(value) => move_consts.insert(*dest, value),
+                    None => move_consts.remove(dest),
+                };
+                None
+            }
+            IRInstruction::Binary {
+                dest,
+                src1,
+                src2,
+                operator,
+            } => match (
+                const_of(consts, &move_consts, src1),
+                const_of(consts, &move_consts, src2),
+            ) {
+                (Some(l), Some(r)) => fold_binary(operator, &l, &r).map(|value| (*dest, value)),
+                _ => None,
+            },
+            IRInstruction::Unary {
+                dest,
+                operator,
+                src,
+            } => const_of(consts, &move_consts, src)
+                .and_then(|value| fold_unary(operator, &value))
+                .map(|value| (*dest, value)),
+            // like a Binary Concat, only a chain of string constants
+            IRInstruction::ConcatN { dest, srcs } => srcs
+                .iter()
+                .map(|src| match const_of(consts, &move_consts, src) {
+                    Some(IROperand::ImmStr(s)) => Some(s),
+                    _ => None,
+                })
+                .collect::<Option<String>>()
+                .map(|joined| (*dest, IROperand::ImmStr(joined))),
+            _ => None,
+        };
+        if let Some((dest, value)) = folded {
+            *instr = IRInstruction::LoadImm { dest, value };
+            changed = true;
+        }
+
+        if let IRInstruction::LoadImm { dest, value } = instr
+            && defs.get(dest) == Some(&1)
+            && is_constant(value)
+            && !consts.contains_key(dest)
+        {
+            consts.insert(*dest, value.clone());
+            changed = true;
+        }
+    }
+
+    if let IRTerminator::Branch {
+        cond,
+        br_true,
+        br_false,
+    } = &block.terminator
+        && let Some(value) = const_of(consts, &move_consts, cond)
+    {
+        let target = if is_truthy(&value) {
+            *br_true
+        } else {
+            *br_false
+        };
+        block.terminator = IRTerminator::Jump(target);
+        changed = true;
+    }
+
+    changed
+}
+
+fn fold_binary(op: &IRBinOp, l: &IROperand, r: &IROperand) -> Option<IROperand> {
+    let arith = match op {
+        IRBinOp::Add => Some(BinOp::Add),
+        IRBinOp::Sub => Some(BinOp::Sub),
+        IRBinOp::Mul => Some(BinOp::Mul),
+        IRBinOp::Div => Some(BinOp::Div),
+        IRBinOp::Mod => Some(BinOp::Mod),
+        _ => None,
+    };
+    if let Some(arith) = arith {
+        // division and modulo by zero stay, the VM decides what they do
+        let (value, _) = IRGenerator::fold_arith(&arith, as_num(l)?, as_num(r)?).ok()??;
+        return Some(from_num(value));
+    }
+
+    match op {
+        IRBinOp::Eq => Some(IROperand::ImmBool(const_equal(l, r))),
+        IRBinOp::Neq => Some(IROperand::ImmBool(!const_equal(l, r))),
+        IRBinOp::Lt | IRBinOp::Gt | IRBinOp::Leq | IRBinOp::Geq => {
+            let ord = match (l, r) {
+                (IROperand::ImmStr(a), IROperand::ImmStr(b)) => Some(a.cmp(b)),
+                // anything but two numbers or two strings raises at runtime, NaN compares false
+                _ => compare_numbers(&as_value(l)?, &as_value(r)?),
+            };
+            Some(IROperand::ImmBool(ord.is_some_and(|ord| match op {
+                IRBinOp::Lt => ord == Ordering::Less,
+                IRBinOp::Gt => ord == Ordering::Greater,
+                IRBinOp::Leq => ord != Ordering::Greater,
+                _ => ord != Ordering::Less,
+            })))
+        }
+        // numbers are left to the VM, which owns their string form
+        IRBinOp::Concat => match (l, r) {
+            (IROperand::ImmStr(a), IROperand::ImmStr(b)) => Some(IROperand::ImmStr(a.clone() + b)),
+            _ => None,
+        },
+        IRBinOp::And => Some(if is_truthy(l) { r.clone() } else { l.clone() }),
+        IRBinOp::Or => Some(if is_truthy(l) { l.clone() } else { r.clone() }),
+        _ => None,
+    }
+}
+
+fn fold_unary(op: &IRUnOp, value: &IROperand) -> Option<IROperand> {
+    match op {
+        IRUnOp::Neg => match as_num(value)? {
+            IRConstNum::Int(i) => Some(IROperand::ImmInt(i.wrapping_neg())),
+            IRConstNum::Float(n) => Some(IROperand::ImmFloat(-n)),
+        },
+        IRUnOp::Not => Some(IROperand::ImmBool(!is_truthy(value))),
+        IRUnOp::TblLen => match value {
+            IROperand::ImmStr(s) => Some(IROperand::ImmInt(s.len() as i64)),
+            _ => None,
+        },
+    }
+}
+
+fn is_constant(value: &IROperand) -> bool {
+    matches!(
+        value,
+        IROperand::ImmInt(_)
+            | IROperand::ImmFloat(_)
+            | IROperand::ImmBool(_)
+            | IROperand::ImmStr(_)
+            | IROperand::Nil
+    )
+}
+
+fn is_truthy(value: &IROperand) -> bool {
+    !matches!(value, IROperand::Nil | IROperand::ImmBool(false))
+}
+
+fn as_num(value: &IROperand) -> Option<IRConstNum> {
+    match value {
+        IROperand::ImmInt(i) => Some(IRConstNum::Int(*i)),
+        IROperand::ImmFloat(n) => Some(IRConstNum::Float(*n)),
+        _ => None,
+    }
+}
+
+fn from_num(value: IRConstNum) -> IROperand {
+    match value {
+        IRConstNum::Int(i) => IROperand::ImmInt(i),
+        IRConstNum::Float(n) => IROperand::ImmFloat(n),
+    }
+}
+
+fn as_value(value: &IROperand) -> Option<LuaValue> {
+    match as_num(value)? {
+        IRConstNum::Int(i) => Some(LuaValue::Integer(i)),
+        IRConstNum::Float(n) => Some(LuaValue::Number(n)),
+    }
+}
+
+// raw equality, an integer equals the float of the same value
+fn const_equal(l: &IROperand, r: &IROperand) -> bool {
+    match (l, r) {
+        (IROperand::ImmStr(a), IROperand::ImmStr(b)) => a == b,
+        (IROperand::ImmBool(a), IROperand::ImmBool(b)) => a == b,
+        (IROperand::Nil, IROperand::Nil) => true,
+        _ => match (as_value(l), as_value(r)) {
+            (Some(a), Some(b)) => compare_numbers(&a, &b) == Some(Ordering::Equal),
+            _ => false,
+        },
+    }
+}
+
+// append a block to the block jumping to it when that jump is its only way in;
+// only blocks laid out further down are moved up, so code never moves behind its users
+fn merge_blocks(func: &mut IRFunction) -> bool {
+    let mut merged = false;
+    'scan: loop {
+        let blocks = &func.basic_blocks;
+        let mut preds: HashMap<usize, usize> = HashMap::new();
+        for (i, block) in blocks.iter().enumerate() {
+            match &block.terminator {
+                IRTerminator::Jump(target) => *preds.entry(*target).or_insert(0) += 1,
+                IRTerminator::Branch {
+                    br_true, br_false, ..
+                } => {
+                    *preds.entry(*br_true).or_insert(0) += 1;
+                    *preds.entry(*br_false).or_insert(0) += 1;
+                }
+                IRTerminator::Switch {
+                    targets, default, ..
+                } => {
+                    for target in targets.iter().chain(std::iter::once(default)) {
+                        *preds.entry(*target).or_insert(0) += 1;
+                    }
+                }
+                IRTerminator::FallThrough => {
+                    if let Some(next) = blocks.get(i + 1) {
+                        *preds.entry(next.id).or_insert(0) += 1;
+                    }
+                }
+                IRTerminator::Return(_) => {}
+            }
+        }
+
+        for i in 0..blocks.len() {
+            let IRTerminator::Jump(target) = blocks[i].terminator else {
+                continue;
+            };
+            let Some(j) = blocks.iter().position(|b| b.id == target) else {
+                continue;
+            };
+            // a block falling through at the very end has nowhere to jump to once moved
+            let falls_off =
+                matches!(blocks[j].terminator, IRTerminator::FallThrough) && j + 1 == blocks.len();
+            if j <= i || preds.get(&target) != Some(&1) || falls_off {
+                continue;
+            }
+
+            let mut block = func.basic_blocks.remove(j);
+            if let IRTerminator::FallThrough = block.terminator {
+                block.terminator = IRTerminator::Jump(func.basic_blocks[j].id);
+            }
+            let pred = &mut func.basic_blocks[i];
+            pred.lines.resize(pred.instructions.len(), 0);
+            pred.spans.resize(pred.instructions.len(), Span::default());
+            pred.instructions.append(&mut block.instructions);
+            pred.lines.append(&mut block.lines);
+            pred.spans.append(&mut block.spans);
+            pred.terminator = block.terminator;
+            pred.terminator_line = block.terminator_line;
+            pred.terminator_span = block.terminator_span;
+            merged = true;
+            continue 'scan;
+        }
+        return merged;
+    }
+}
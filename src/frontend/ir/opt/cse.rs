@@ -0,0 +1,162 @@
+// Myula compiler IR common subexpression elimination
+//
+// Changelog:
+//      26-02-24: Initial version. Runs after constant folding, local value numbering on every basic block:
+//                an instruction computing the same value as an earlier one of the block (same operation on
+//                the same operands) is removed and its register is replaced by the earlier one everywhere
+//                - arithmetic and comparisons always compute the same value; a register loaded with a constant
+//                  counts as that constant, the loads themselves stay (most become constant operands) for dce
+//                - loads of locals, upvalues, globals and table fields only until the next instruction that
+//                  may write any of them (a store or a call); an __index handler is taken to have no
+//                  side effects, `t.a + t.a` looks `a` up once
+//                - a function about to be called is loaded on its own, its register receives the result
+
+use std::collections::{HashMap, HashSet};
+
+use crate::frontend::ir::{IRFunction, IRInstruction, IRModule, IROperand, IRUnOp};
+use crate::frontend::parser::ast::Span;
+
+pub fn run(module: &mut IRModule) {
+    for func in &mut module.functions {
+        number_values(func);
+    }
+}
+
+// what an instruction computes, as far as value numbering is concerned
+enum Value {
+    // depends on its operands only
+    Pure(String),
+    // reads state a store or a call may change
+    Load(String),
+}
+
+fn number_values(func: &mut IRFunction) {
+    let mut defs: HashMap<usize, usize> = HashMap::new();
+    // the VM returns the result of a call in the register of the function it called,
+    // the callee cannot be read again afterwards
+    let mut callees: HashSet<usize> = HashSet::new();
+    // register -> the constant it is loaded with
+    let mut literals: HashMap<usize, String> = HashMap::new();
+    for block in &func.basic_blocks {
+        for instr in &block.instructions {
+            if let Some(dest) = instr.def_reg() {
+                *defs.entry(dest).or_insert(0) += 1;
+            }
+            if let IRInstruction::LoadImm { dest, value } = instr {
+                literals.insert(*dest, format!("{:?}", value));
+            }
+            if let IRInstruction::Call {
+                callee: IROperand::Reg(reg),
+                ..
+            } = instr
+            {
+                callees.insert(*reg);
+            }
+        }
+    }
+    literals.retain(|reg, _| defs.get(reg) == Some(&1));
+
+    // removed register -> the register holding the same value
+    let mut replaced: HashMap<usize, usize> = HashMap::new();
+    for block in &mut func.basic_blocks {
+        let mut pure: HashMap<String, usize> = HashMap::new();
+        let mut loads: HashMap<String, usize> = HashMap::new();
+        let instructions = std::mem::take(&mut block.instructions);
+        let mut lines = std::mem::take(&mut block.lines);
+        lines.resize(instructions.len(), 0);
+        let mut spans = std::mem::take(&mut block.spans);
+        spans.resize(instructions.len(), Span::default());
+        for ((mut instr, line), span) in instructions.into_iter().zip(lines).zip(spans) {
+            rename(instr.operands_mut(), &replaced);
+            if writes_state(&instr) {
+                loads.clear();
+            }
+            // a register a Move defines on several paths holds different values
+            let numbered = instr
+                .def_reg()
+                .filter(|dest| defs.get(dest) == Some(&1) && !callees.contains(dest))
+                .zip(value_of(&instr, &literals));
+            if let Some((dest, value)) = numbered {
+                let (known, key) = match value {
+                    Value::Pure(key) => (&mut pure, key),
+                    Value::Load(key) => (&mut loads, key),
+                };
+                if let Some(&earlier) = known.get(&key) {
+                    replaced.insert(dest, earlier);
+                    continue;
+                }
+                known.insert(key, dest);
+            }
+            block.instructions.push(instr);
+            block.lines.push(line);
+            block.spans.push(span);
+        }
+    }
+    if replaced.is_empty() {
+        return;
+    }
+
+    // the value may be used past its block, or in a block laid out before it
+    for block in &mut func.basic_blocks {
+        for instr in &mut block.instructions {
+            rename(instr.operands_mut(), &replaced);
+        }
+        rename(block.terminator.operands_mut(), &replaced);
+    }
+}
+
+fn rename(operands: Vec<&mut IROperand>, replaced: &HashMap<usize, usize>) {
+    for op in operands {
+        if let IROperand::Reg(reg) = op
+            && let Some(earlier) = replaced.get(reg)
+        {
+            *reg = *earlier;
+        }
+    }
+}
+
+// the key two instructions computing the same value share; constants are compared by their
+// Debug form, which tells 0.0 from -0.0 unlike ==
+fn value_of(instr: &IRInstruction, literals: &HashMap<usize, String>) -> Option<Value> {
+    let key = |name: &str| {
+        let ops: Vec<String> = instr
+            .operands()
+            .iter()
+            .map(|op| match op {
+                IROperand::Reg(reg) if literals.contains_key(reg) => literals[reg].clone(),
+                op => format!("{:?}", op),
+            })
+            .collect();
+        format!("{} {}", name, ops.join(" "))
+    };
+    match instr {
+        IRInstruction::Binary { operator, .. } => Some(Value::Pure(key(&operator.to_string()))),
+        // the length of a table changes with its contents
+        IRInstruction::Unary { operator, .. } if *operator == IRUnOp::TblLen => {
+            Some(Value::Load(key("len")))
+        }
+        IRInstruction::Unary { operator, .. } => Some(Value::Pure(key(&format!("{:?}", operator)))),
+        IRInstruction::LoadLocal { .. } => Some(Value::Load(key("LoadLocal"))),
+        IRInstruction::LoadUpVal { .. } => Some(Value::Load(key("LoadUpVal"))),
+        IRInstruction::LoadGlobal { .. } => Some(Value::Load(key("LoadGlobal"))),
+        IRInstruction::IndexOf { .. } => Some(Value::Load(key("IndexOf"))),
+        IRInstruction::MemberOf { .. } => Some(Value::Load(key("MemberOf"))),
+        IRInstruction::GetTable { .. } => Some(Value::Load(key("GetTable"))),
+        // a new table or closure each time, a store or call is not a value
+        _ => None,
+    }
+}
+
+// instructions after which a load may read something else
+fn writes_state(instr: &IRInstruction) -> bool {
+    matches!(
+        instr,
+        IRInstruction::StoreLocal { .. }
+            | IRInstruction::StoreGlobal { .. }
+            | IRInstruction::StoreUpVal { .. }
+            | IRInstruction::SetIndex { .. }
+            | IRInstruction::SetMember { .. }
+            | IRInstruction::SetTable { .. }
+            | IRInstruction::Call { .. }
+    )
+}
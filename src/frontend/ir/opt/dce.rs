@@ -0,0 +1,161 @@
+// Myula compiler IR dead code elimination
+//
+// Changelog:
+//      26-02-24: Initial version. Runs on every function after constant folding:
+//                - basic blocks that cannot be reached from the entry block are removed
+//                - instructions without side effects whose register is never read (or only dropped)
+//                  are removed together with their Drop, repeated until nothing more dies
+
+use std::collections::{HashMap, HashSet};
+
+use crate::frontend::ir::{
+    IRBinOp, IRFunction, IRInstruction, IRModule, IROperand, IRTerminator, IRUnOp,
+};
+use crate::frontend::parser::ast::Span;
+
+pub fn run(module: &mut IRModule) {
+    for func in &mut module.functions {
+        remove_unreachable_blocks(func);
+        remove_dead_instructions(func);
+    }
+}
+
+pub(super) fn remove_unreachable_blocks(func: &mut IRFunction) -> bool {
+    let Some(entry) = func.basic_blocks.first() else {
+        return false;
+    };
+    let index: HashMap<usize, usize> = func
+        .basic_blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| (block.id, i))
+        .collect();
+
+    let mut reachable = vec![false; func.basic_blocks.len()];
+    let mut pending = vec![index[&entry.id]];
+    while let Some(i) = pending.pop() {
+        if reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        let block = &func.basic_blocks[i];
+        match &block.terminator {
+            IRTerminator::Jump(target) => pending.extend(index.get(target)),
+            IRTerminator::Branch {
+                br_true, br_false, ..
+            } => {
+                pending.extend(index.get(br_true));
+                pending.extend(index.get(br_false));
+            }
+            IRTerminator::Switch {
+                targets, default, ..
+            } => {
+                pending.extend(targets.iter().filter_map(|target| index.get(target)));
+                pending.extend(index.get(default));
+            }
+            // falls into the block laid out next
+            IRTerminator::FallThrough => {
+                if i + 1 < func.basic_blocks.len() {
+                    pending.push(i + 1);
+                }
+            }
+            IRTerminator::Return(_) => {}
+        }
+    }
+
+    let before = func.basic_blocks.len();
+    let mut i = 0;
+    func.basic_blocks.retain(|_| {
+        i += 1;
+        reachable[i - 1]
+    });
+    func.basic_blocks.len() != before
+}
+
+// instructions that can be dropped when nobody reads their result: they neither raise
+// nor change any state visible to the program (arithmetic may raise, loading a global may too)
+pub(super) fn is_pure(instr: &IRInstruction) -> bool {
+    match instr {
+        IRInstruction::LoadImm { .. }
+        | IRInstruction::LoadLocal { .. }
+        | IRInstruction::LoadUpVal { .. }
+        | IRInstruction::Move { .. }
+        | IRInstruction::NewTable { .. }
+        | IRInstruction::FnProto { .. } => true,
+        IRInstruction::Unary { operator, .. } => *operator == IRUnOp::Not,
+        IRInstruction::Binary { operator, .. } => matches!(
+            operator,
+            IRBinOp::Eq | IRBinOp::Neq | IRBinOp::And | IRBinOp::Or
+        ),
+        _ => false,
+    }
+}
+
+fn remove_dead_instructions(func: &mut IRFunction) {
+    loop {
+        // registers read by anything but Drop
+        let mut used: HashSet<usize> = HashSet::new();
+        // whether every definition of a register could be removed
+        let mut pure_defs: HashMap<usize, bool> = HashMap::new();
+        for block in &func.basic_blocks {
+            for instr in &block.instructions {
+                if let Some(dest) = instr.def_reg() {
+                    *pure_defs.entry(dest).or_insert(true) &= is_pure(instr);
+                }
+                if matches!(instr, IRInstruction::Drop { .. }) {
+                    continue;
+                }
+                for op in instr.operands() {
+                    if let IROperand::Reg(r) = op {
+                        used.insert(*r);
+                    }
+                }
+            }
+            for op in block.terminator.operands() {
+                if let IROperand::Reg(r) = op {
+                    used.insert(*r);
+                }
+            }
+        }
+
+        let dead = |reg: &usize| !used.contains(reg) && pure_defs.get(reg) == Some(&true);
+        let mut removed = false;
+        for block in &mut func.basic_blocks {
+            let keep: Vec<bool> = block
+                .instructions
+                .iter()
+                .map(|instr| match instr {
+                    IRInstruction::Drop {
+                        src: IROperand::Reg(r),
+                    } => !dead(r),
+                    _ => instr.def_reg().is_none_or(|dest| !dead(&dest)),
+                })
+                .collect();
+            if keep.iter().all(|k| *k) {
+                continue;
+            }
+            removed = true;
+            // lines and spans run parallel to the instructions
+            block.lines.resize(keep.len(), 0);
+            block.spans.resize(keep.len(), Span::default());
+            let mut i = 0;
+            block.instructions.retain(|_| {
+                i += 1;
+                keep[i - 1]
+            });
+            let mut i = 0;
+            block.lines.retain(|_| {
+                i += 1;
+                keep[i - 1]
+            });
+            let mut i = 0;
+            block.spans.retain(|_| {
+                i += 1;
+                keep[i - 1]
+            });
+        }
+        if !removed {
+            break;
+        }
+    }
+}
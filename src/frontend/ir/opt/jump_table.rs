@@ -0,0 +1,246 @@
+// Myula compiler IR jump tables
+//
+// Changelog:
+//      26-02-24: Initial version. Runs after constant folding and before dead code elimination:
+//                a chain of blocks testing `local == integer constant`, as generated for
+//                `if x == 1 then ... elseif x == 2 then ...`, becomes a single Switch terminator
+//                when it has at least MIN_CASES cases and the constants are dense enough for a table;
+//                the tests it replaces are left unreachable (or unused) for dce to remove
+
+use std::collections::{HashMap, HashSet};
+
+use crate::frontend::ir::{
+    IRBasicBlock, IRBinOp, IRFunction, IRInstruction, IRModule, IROperand, IRTerminator,
+};
+
+// shorter chains are as fast as compare and branch
+const MIN_CASES: usize = 4;
+// the table may have at most this many entries per case, the gaps jump to the default
+const MAX_SPREAD: i64 = 2;
+
+pub fn run(module: &mut IRModule) {
+    for func in &mut module.functions {
+        build_jump_tables(func);
+    }
+}
+
+// what a block ending in `Branch (LoadLocal slot) == k` tests
+struct CaseTest {
+    slot: usize,
+    // the register the local was loaded into
+    value: usize,
+    key: i64,
+    br_true: usize,
+    br_false: usize,
+}
+
+fn build_jump_tables(func: &mut IRFunction) {
+    let index: HashMap<usize, usize> = func
+        .basic_blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| (block.id, i))
+        .collect();
+    let preds = predecessor_counts(func);
+    // tests already taken into a table, they are unreachable now
+    let mut replaced: HashSet<usize> = HashSet::new();
+
+    for head in 0..func.basic_blocks.len() {
+        if replaced.contains(&func.basic_blocks[head].id) {
+            continue;
+        }
+        let Some(first) = case_test(&func.basic_blocks[head]) else {
+            continue;
+        };
+
+        // follow the false edges as long as they only test the same local again
+        let mut cases: Vec<(i64, usize)> = vec![(first.key, first.br_true)];
+        let mut chain = Vec::new();
+        let mut default = first.br_false;
+        while let Some(&next) = index.get(&default) {
+            let block = &func.basic_blocks[next];
+            let Some(test) = case_test(block) else {
+                break;
+            };
+            if test.slot != first.slot || !only_test(block) || preds.get(&block.id) != Some(&1) {
+                break;
+            }
+            cases.push((test.key, test.br_true));
+            chain.push(block.id);
+            default = test.br_false;
+        }
+
+        if let Some(terminator) = jump_table(first.value, &cases, default) {
+            func.basic_blocks[head].terminator = terminator;
+            replaced.extend(chain);
+        }
+    }
+}
+
+fn jump_table(value: usize, cases: &[(i64, usize)], default: usize) -> Option<IRTerminator> {
+    if cases.len() < MIN_CASES {
+        return None;
+    }
+    let low = cases.iter().map(|(key, _)| *key).min()?;
+    let high = cases.iter().map(|(key, _)| *key).max()?;
+    let span = high.checked_sub(low)?.checked_add(1)?;
+    if span > cases.len() as i64 * MAX_SPREAD || span > u16::MAX as i64 {
+        return None;
+    }
+
+    let mut targets = vec![None; span as usize];
+    for (key, target) in cases {
+        // a repeated constant can never be reached past its first test
+        targets[(key - low) as usize].get_or_insert(*target);
+    }
+    Some(IRTerminator::Switch {
+        value: IROperand::Reg(value),
+        low,
+        targets: targets
+            .into_iter()
+            .map(|target| target.unwrap_or(default))
+            .collect(),
+        default,
+    })
+}
+
+fn case_test(block: &IRBasicBlock) -> Option<CaseTest> {
+    let IRTerminator::Branch {
+        cond: IROperand::Reg(cond),
+        br_true,
+        br_false,
+    } = block.terminator
+    else {
+        return None;
+    };
+    let (pos, src1, src2) =
+        block
+            .instructions
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(pos, instr)| match instr {
+                IRInstruction::Binary {
+                    dest,
+                    src1,
+                    src2,
+                    operator: IRBinOp::Eq,
+                } if *dest == cond => Some((pos, src1, src2)),
+                _ => None,
+            })?;
+    let before = &block.instructions[..pos];
+    let ((slot, value), key) = match (loaded_local(before, src1), int_constant(before, src2)) {
+        (Some(local), Some(key)) => (local, key),
+        _ => (loaded_local(before, src2)?, int_constant(before, src1)?),
+    };
+
+    // the local must still hold what was loaded when the branch is taken
+    if stored_after_load(&block.instructions, slot, value) {
+        return None;
+    }
+    Some(CaseTest {
+        slot,
+        value,
+        key,
+        br_true,
+        br_false,
+    })
+}
+
+// (slot, register) when op is a register loaded from a local slot in instrs
+fn loaded_local(instrs: &[IRInstruction], op: &IROperand) -> Option<(usize, usize)> {
+    let IROperand::Reg(reg) = op else {
+        return None;
+    };
+    match last_def(instrs, *reg)? {
+        IRInstruction::LoadLocal {
+            src: IROperand::Slot(slot),
+            ..
+        } => Some((*slot, *reg)),
+        _ => None,
+    }
+}
+
+fn int_constant(instrs: &[IRInstruction], op: &IROperand) -> Option<i64> {
+    match op {
+        IROperand::ImmInt(i) => Some(*i),
+        IROperand::Reg(reg) => match last_def(instrs, *reg)? {
+            IRInstruction::LoadImm {
+                value: IROperand::ImmInt(i),
+                ..
+            } => Some(*i),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn last_def(instrs: &[IRInstruction], reg: usize) -> Option<&IRInstruction> {
+    instrs
+        .iter()
+        .rev()
+        .find(|instr| instr.def_reg() == Some(reg))
+}
+
+fn stored_after_load(instrs: &[IRInstruction], slot: usize, value: usize) -> bool {
+    let Some(load) = instrs
+        .iter()
+        .rposition(|instr| instr.def_reg() == Some(value))
+    else {
+        return true;
+    };
+    instrs[load..].iter().any(|instr| {
+        matches!(
+            instr,
+            IRInstruction::StoreLocal {
+                dst: IROperand::Slot(s),
+                ..
+            } if *s == slot
+        )
+    })
+}
+
+// a block that does nothing but the test, skipping it changes nothing else
+fn only_test(block: &IRBasicBlock) -> bool {
+    block.instructions.iter().all(|instr| {
+        matches!(
+            instr,
+            IRInstruction::LoadLocal { .. }
+                | IRInstruction::LoadImm { .. }
+                | IRInstruction::Binary {
+                    operator: IRBinOp::Eq,
+                    ..
+                }
+        )
+    })
+}
+
+fn predecessor_counts(func: &IRFunction) -> HashMap<usize, usize> {
+    let blocks = &func.basic_blocks;
+    let mut preds: HashMap<usize, usize> = HashMap::new();
+    for (i, block) in blocks.iter().enumerate() {
+        match &block.terminator {
+            IRTerminator::Jump(target) => *preds.entry(*target).or_insert(0) += 1,
+            IRTerminator::Branch {
+                br_true, br_false, ..
+            } => {
+                *preds.entry(*br_true).or_insert(0) += 1;
+                *preds.entry(*br_false).or_insert(0) += 1;
+            }
+            IRTerminator::Switch {
+                targets, default, ..
+            } => {
+                for target in targets.iter().chain(std::iter::once(default)) {
+                    *preds.entry(*target).or_insert(0) += 1;
+                }
+            }
+            IRTerminator::FallThrough => {
+                if let Some(next) = blocks.get(i + 1) {
+                    *preds.entry(next.id).or_insert(0) += 1;
+                }
+            }
+            IRTerminator::Return(_) => {}
+        }
+    }
+    preds
+}
@@ -0,0 +1,409 @@
+// Myula compiler IR loop-invariant code motion
+//
+// Changelog:
+//      26-02-24: Initial version. Runs after the jump tables and before dead code elimination, on every loop
+//                (a block some later block jumps back to, entered from outside only through that block):
+//                - `x * 2` inside the loop becomes `x + x`, which needs no constant register
+//                - LoadImm anywhere in the loop moves into a preheader block placed before the loop head
+//                - in the loop head (the condition of a while loop), LoadGlobal and arithmetic on values
+//                  already moved out follow them, as long as nothing before them in the head can have an
+//                  effect and nothing in the loop can assign a global (stores, calls, metamethods)
+//                a __gc finalizer that assigns a global while the loop runs is not taken into account
+//      26-02-24: `x * 2` is only rewritten when x is known to be a number, for a table or a string the
+//                error (or the __mul metamethod) has to be the one of the multiplication
+
+use std::collections::{HashMap, HashSet};
+
+use crate::frontend::ir::{
+    IRBasicBlock, IRBinOp, IRFunction, IRInstruction, IRModule, IROperand, IRTerminator, IRUnOp,
+    IRUpValType,
+};
+use crate::frontend::parser::ast::Span;
+
+use super::dce::is_pure;
+
+pub fn run(module: &mut IRModule) {
+    let captured = captured_slots(module);
+    for func in &mut module.functions {
+        let numbers = numeric_regs(func, captured.get(&func.name));
+        for head in loop_heads(func) {
+            if let Some((start, end)) = find_loop(func, head) {
+                reduce_strength(func, start, end, &numbers);
+                hoist_invariants(func, start, end);
+            }
+        }
+    }
+}
+
+// ids of the blocks a back edge jumps to, outer loops first
+fn loop_heads(func: &IRFunction) -> Vec<usize> {
+    let index = block_index(func);
+    let mut heads = Vec::new();
+    for i in 0..func.basic_blocks.len() {
+        for succ in successors(&func.basic_blocks, i) {
+            if index.get(&succ).is_some_and(|&target| target <= i) && !heads.contains(&succ) {
+                heads.push(succ);
+            }
+        }
+    }
+    heads.sort_by_key(|head| index[head]);
+    heads
+}
+
+// the layout range [start, end] of the loop headed by `head`, None when a block other than
+// the head can be entered from outside of it (a goto into the body, or a test a Switch
+// replaced that dce has not removed yet)
+fn find_loop(func: &IRFunction, head: usize) -> Option<(usize, usize)> {
+    let blocks = &func.basic_blocks;
+    let start = *block_index(func).get(&head)?;
+    let end = (start..blocks.len())
+        .filter(|&i| successors(blocks, i).contains(&head))
+        .max()?;
+    let inside: HashSet<usize> = blocks[start..=end].iter().map(|b| b.id).collect();
+    for (i, _) in blocks.iter().enumerate() {
+        if (start..=end).contains(&i) {
+            continue;
+        }
+        let enters_body = successors(blocks, i)
+            .iter()
+            .any(|succ| *succ != head && inside.contains(succ));
+        if enters_body {
+            return None;
+        }
+    }
+    Some((start, end))
+}
+
+// `x * 2` and `2 * x` become `x + x` when x is a number, the same value for integers (both
+// wrap) and floats, without loading the 2
+fn reduce_strength(func: &mut IRFunction, start: usize, end: usize, numbers: &HashSet<usize>) {
+    let twos = int_literals(func, 2);
+    let is_two = |op: &IROperand| match op {
+        IROperand::ImmInt(2) => true,
+        IROperand::Reg(reg) => twos.contains(reg),
+        _ => false,
+    };
+    let is_number = |op: &IROperand| is_numeric(op, numbers);
+    for block in &mut func.basic_blocks[start..=end] {
+        for instr in &mut block.instructions {
+            let IRInstruction::Binary {
+                dest,
+                src1,
+                src2,
+                operator: IRBinOp::Mul,
+            } = instr
+            else {
+                continue;
+            };
+            let x = if is_two(src2) && is_number(src1) {
+                src1.clone()
+            } else if is_two(src1) && is_number(src2) {
+                src2.clone()
+            } else {
+                continue;
+            };
+            *instr = IRInstruction::Binary {
+                dest: *dest,
+                src1: x.clone(),
+                src2: x,
+                operator: IRBinOp::Add,
+            };
+        }
+    }
+}
+
+fn hoist_invariants(func: &mut IRFunction, start: usize, end: usize) {
+    let head = func.basic_blocks[start].id;
+    let single_defs = single_defs(func);
+    let globals_stable = func.basic_blocks[start..=end]
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .all(|instr| !may_assign_global(instr));
+
+    let defined_inside: HashSet<usize> = func.basic_blocks[start..=end]
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|instr| instr.def_reg())
+        .collect();
+
+    // registers defined in the preheader
+    let mut hoisted: HashSet<usize> = HashSet::new();
+    let mut instructions = Vec::new();
+    let mut lines = Vec::new();
+    let mut spans = Vec::new();
+    for (pos, block) in func.basic_blocks[start..=end].iter_mut().enumerate() {
+        // the head runs first on every way into the loop, until one of its instructions does
+        // something we would be moving the rest in front of
+        let mut in_order = pos == 0;
+        let old = std::mem::take(&mut block.instructions);
+        let mut old_lines = std::mem::take(&mut block.lines);
+        old_lines.resize(old.len(), 0);
+        let mut old_spans = std::mem::take(&mut block.spans);
+        old_spans.resize(old.len(), Span::default());
+        for ((instr, line), span) in old.into_iter().zip(old_lines).zip(old_spans) {
+            let invariant = |op: &IROperand| match op {
+                IROperand::Reg(reg) => !defined_inside.contains(reg) || hoisted.contains(reg),
+                _ => true,
+            };
+            let movable = instr
+                .def_reg()
+                .is_some_and(|dest| single_defs.contains(&dest))
+                && match &instr {
+                    IRInstruction::LoadImm { .. } => true,
+                    IRInstruction::LoadGlobal { name, .. } => {
+                        in_order && globals_stable && invariant(name)
+                    }
+                    IRInstruction::Binary { src1, src2, .. } => {
+                        in_order && invariant(src1) && invariant(src2)
+                    }
+                    // not the length, the table may grow in the loop
+                    IRInstruction::Unary { operator, src, .. } => {
+                        in_order && *operator != IRUnOp::TblLen && invariant(src)
+                    }
+                    _ => false,
+                };
+            if movable {
+                hoisted.extend(instr.def_reg());
+                instructions.push(instr);
+                lines.push(line);
+                spans.push(span);
+            } else {
+                if !is_pure(&instr) {
+                    in_order = false;
+                }
+                block.instructions.push(instr);
+                block.lines.push(line);
+                block.spans.push(span);
+            }
+        }
+    }
+    if instructions.is_empty() {
+        return;
+    }
+
+    // every way into the loop now goes through the preheader, the back edges still go to the head
+    let preheader = func.basic_blocks.iter().map(|b| b.id).max().unwrap_or(0) + 1;
+    for block in &mut func.basic_blocks[..start] {
+        retarget(&mut block.terminator, head, preheader);
+    }
+    for block in &mut func.basic_blocks[end + 1..] {
+        retarget(&mut block.terminator, head, preheader);
+    }
+    let terminator_line = lines.last().copied().unwrap_or(0);
+    let terminator_span = spans.last().copied().unwrap_or_default();
+    func.basic_blocks.insert(
+        start,
+        IRBasicBlock {
+            id: preheader,
+            instructions,
+            terminator: IRTerminator::FallThrough,
+            lines,
+            terminator_line,
+            spans,
+            terminator_span,
+        },
+    );
+}
+
+// instructions after which a global may hold something else: the store itself, a call,
+// and table accesses, which run the __index / __newindex handler of a userdata
+fn may_assign_global(instr: &IRInstruction) -> bool {
+    matches!(
+        instr,
+        IRInstruction::StoreGlobal { .. }
+            | IRInstruction::Call { .. }
+            | IRInstruction::IndexOf { .. }
+            | IRInstruction::SetIndex { .. }
+            | IRInstruction::MemberOf { .. }
+            | IRInstruction::SetMember { .. }
+            | IRInstruction::GetTable { .. }
+            | IRInstruction::SetTable { .. }
+    )
+}
+
+// registers defined exactly once in the function, a Move target of two paths cannot be moved
+fn single_defs(func: &IRFunction) -> HashSet<usize> {
+    let mut defs: HashMap<usize, usize> = HashMap::new();
+    for block in &func.basic_blocks {
+        for instr in &block.instructions {
+            if let Some(dest) = instr.def_reg() {
+                *defs.entry(dest).or_insert(0) += 1;
+            }
+        }
+    }
+    defs.into_iter()
+        .filter(|(_, count)| *count == 1)
+        .map(|(reg, _)| reg)
+        .collect()
+}
+
+// the local slots of each function a closure inside it captures, by function name; a store
+// through the upvalue does not show up in the function itself
+fn captured_slots(module: &IRModule) -> HashMap<String, HashSet<usize>> {
+    let mut captured: HashMap<String, HashSet<usize>> = HashMap::new();
+    for func in &module.functions {
+        let slots = captured.entry(func.name.clone()).or_default();
+        for sub in &func.sub_functions {
+            let Some(sub) = module.functions.iter().find(|f| &f.name == sub) else {
+                continue;
+            };
+            for upval in sub.upvalues.values() {
+                if let IRUpValType::LocalVar(slot) = upval.ty {
+                    slots.insert(slot);
+                }
+            }
+        }
+    }
+    captured
+}
+
+fn is_numeric(op: &IROperand, numbers: &HashSet<usize>) -> bool {
+    match op {
+        IROperand::ImmInt(_) | IROperand::ImmFloat(_) => true,
+        IROperand::Reg(reg) => numbers.contains(reg),
+        _ => false,
+    }
+}
+
+// registers that hold a number whenever they are read: number literals, locals that are only
+// ever assigned numbers, and arithmetic or negation of those; parameters and captured locals
+// may hold anything. Starts from every register and local and drops the ones with a definition
+// that is not known to be a number until nothing changes, so `i = i + 1` keeps a counter
+fn numeric_regs(func: &IRFunction, captured: Option<&HashSet<usize>>) -> HashSet<usize> {
+    let instructions: Vec<&IRInstruction> = func
+        .basic_blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .collect();
+    let mut numbers: HashSet<usize> = instructions
+        .iter()
+        .filter_map(|instr| instr.def_reg())
+        .collect();
+    let mut slots: HashSet<usize> = instructions
+        .iter()
+        .filter_map(|instr| match instr {
+            IRInstruction::StoreLocal {
+                dst: IROperand::Slot(slot),
+                ..
+            } => Some(*slot),
+            _ => None,
+        })
+        .filter(|slot| *slot >= func.params.len() && !captured.is_some_and(|c| c.contains(slot)))
+        .collect();
+
+    loop {
+        let mut changed = false;
+        for instr in &instructions {
+            if let IRInstruction::StoreLocal {
+                dst: IROperand::Slot(slot),
+                src,
+                ..
+            } = instr
+                && !is_numeric(src, &numbers)
+            {
+                changed |= slots.remove(slot);
+            }
+            let Some(dest) = instr.def_reg() else {
+                continue;
+            };
+            let number = match instr {
+                IRInstruction::LoadImm { value, .. } => is_numeric(value, &numbers),
+                IRInstruction::LoadLocal {
+                    src: IROperand::Slot(slot),
+                    ..
+                } => slots.contains(slot),
+                IRInstruction::Move { src, .. }
+                | IRInstruction::Unary {
+                    operator: IRUnOp::Neg,
+                    src,
+                    ..
+                } => is_numeric(src, &numbers),
+                IRInstruction::Binary {
+                    operator:
+                        IRBinOp::Add
+                        | IRBinOp::Sub
+                        | IRBinOp::Mul
+                        | IRBinOp::Div
+                        | IRBinOp::Mod
+                        | IRBinOp::Pow,
+                    src1,
+                    src2,
+                    ..
+                } => is_numeric(src1, &numbers) && is_numeric(src2, &numbers),
+                _ => false,
+            };
+            if !number {
+                changed |= numbers.remove(&dest);
+            }
+        }
+        if !changed {
+            return numbers;
+        }
+    }
+}
+
+// registers only ever loaded with the integer `value`
+fn int_literals(func: &IRFunction, value: i64) -> HashSet<usize> {
+    let single = single_defs(func);
+    func.basic_blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|instr| match instr {
+            IRInstruction::LoadImm {
+                dest,
+                value: IROperand::ImmInt(i),
+            } if *i == value && single.contains(dest) => Some(*dest),
+            _ => None,
+        })
+        .collect()
+}
+
+fn retarget(terminator: &mut IRTerminator, from: usize, to: usize) {
+    let swap = |target: &mut usize| {
+        if *target == from {
+            *target = to;
+        }
+    };
+    match terminator {
+        IRTerminator::Jump(target) => swap(target),
+        IRTerminator::Branch {
+            br_true, br_false, ..
+        } => {
+            swap(br_true);
+            swap(br_false);
+        }
+        IRTerminator::Switch {
+            targets, default, ..
+        } => {
+            targets.iter_mut().for_each(swap);
+            swap(default);
+        }
+        IRTerminator::FallThrough | IRTerminator::Return(_) => {}
+    }
+}
+
+fn successors(blocks: &[IRBasicBlock], i: usize) -> Vec<usize> {
+    match &blocks[i].terminator {
+        IRTerminator::Jump(target) => vec![*target],
+        IRTerminator::Branch {
+            br_true, br_false, ..
+        } => vec![*br_true, *br_false],
+        IRTerminator::Switch {
+            targets, default, ..
+        } => targets
+            .iter()
+            .chain(std::iter::once(default))
+            .copied()
+            .collect(),
+        IRTerminator::FallThrough => blocks.get(i + 1).map(|next| next.id).into_iter().collect(),
+        IRTerminator::Return(_) => vec![],
+    }
+}
+
+fn block_index(func: &IRFunction) -> HashMap<usize, usize> {
+    func.basic_blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| (block.id, i))
+        .collect()
+}
@@ -0,0 +1,14 @@
+// Myula compiler IR optimization passes
+//
+// Changelog:
+//      26-02-24: Initial version, constant folding and propagation (const_fold)
+//      26-02-24: Dead code elimination (dce)
+//      26-02-24: Jump tables for `elseif` chains over integer constants (jump_table)
+//      26-02-24: Loop-invariant code motion and `x * 2` strength reduction in loops (licm)
+//      26-02-24: Common subexpression elimination by local value numbering (cse)
+
+pub mod const_fold;
+pub mod cse;
+pub mod dce;
+pub mod jump_table;
+pub mod licm;
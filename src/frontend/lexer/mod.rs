@@ -5,9 +5,26 @@
 //      26-02-10: Initial version
 //      26-02-13: Added '@' operator for legacy table ctor
 //      26-02-20: Added '%' and '#' operators for modulo and length
+//      26-02-24: Numerals without a fractional part lex as integers
+//      26-02-24: Streaming mode (`Lexer::from_reader`) over any BufRead source, only the bytes of the
+//                token being scanned are kept in memory; both modes track the current line
+//      26-02-24: Remember where the last token started (`get_token_pos`)
+//      26-02-24: 'goto' keyword and '::'
+//      26-02-24: Remember the line the last token started on (`get_token_line`)
+//      26-02-24: Record the line of every error (`get_err_lines`), `LexerError` displays as a message
+//      26-02-24: Full lua numerals: hex integers and floats ("0xFF", "0x1p4"), exponents ("1e10", "3.5e-2"),
+//                a leading '.' (".5"); a malformed numeral is one error carrying its text
+//      26-02-24: Long comments `--[[ ... ]]` and `--[==[ ... ]==]`
+//      26-02-24: The source is read as UTF-8 characters instead of bytes, multi-byte characters are never
+//                split; identifiers may contain unicode letters
+//      26-02-24: `Lexer::from_read` streams any `Read` through a BufReader; `tokens()` iterates over the
+//                remaining tokens with their positions (SpannedToken), for tools that only need the token stream
+//      26-02-24: `with_comments(true)` returns comments as Token::Comment instead of skipping them
 
 pub mod token;
 
+use std::fmt;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
 use std::vec::Vec;
 
 use crate::frontend::lexer::token::Token;
@@ -16,45 +33,264 @@ use crate::frontend::lexer::token::Token;
 pub enum LexerError {
     UnexpectedCharacter(char),
     UnterminatedString,
-    InvalidNumber,
+    // a long comment `--[[` without its closing bracket
+    UnterminatedComment,
+    // the text of the malformed numeral
+    InvalidNumber(String),
+    // the underlying reader failed, the input is treated as ending there
+    ReadFailed(String),
+}
+
+impl fmt::Display for LexerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexerError::UnexpectedCharacter(c) => write!(f, "unexpected character '{}'", c),
+            LexerError::UnterminatedString => write!(f, "unfinished string"),
+            LexerError::UnterminatedComment => write!(f, "unfinished long comment"),
+            LexerError::InvalidNumber(text) => write!(f, "malformed number near '{}'", text),
+            LexerError::ReadFailed(e) => write!(f, "failed to read the source: {}", e),
+        }
+    }
+}
+
+// consumed bytes of a streamed source are dropped once the window has grown past this
+const STREAM_KEEP: usize = 8 * 1024;
+
+enum Source<'a> {
+    Slice(&'a str),
+    // `window` holds the stream bytes [base, base + window.len()),
+    // everything before the start of the current token may be dropped
+    Stream {
+        reader: Box<dyn BufRead + 'a>,
+        window: Vec<u8>,
+        base: usize,
+        eof: bool,
+    },
+}
+
+/// a token, the source offsets it covers and the line it starts on
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
 }
 
 pub struct Lexer<'a> {
-    input: &'a str,
+    source: Source<'a>,
     pos: usize,
+    // offset of the first byte of the last returned token
+    token_pos: usize,
+    // line the last token started on
+    token_line: usize,
+    line: usize,
     errors: Vec<LexerError>,
+    // the line of each error in `errors`
+    error_lines: Vec<usize>,
+    // comments are tokens instead of whitespace, see `with_comments`
+    keep_comments: bool,
 }
 
-impl Lexer<'_> {
-    pub fn new(input: &'_ str) -> Lexer<'_> {
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Lexer<'a> {
         return Lexer {
-            input: input,
+            source: Source::Slice(input),
             pos: 0,
+            token_pos: 0,
+            token_line: 1,
+            line: 1,
             errors: vec![],
+            error_lines: vec![],
+            keep_comments: false,
         };
     }
 
+    /// tokenize a stream instead of an in-memory string, e.g. a large file or a pipe;
+    /// positions are byte offsets from the start of the stream, as with `new`
+    pub fn from_reader(reader: impl BufRead + 'a) -> Lexer<'a> {
+        Lexer {
+            source: Source::Stream {
+                reader: Box::new(reader),
+                window: Vec::new(),
+                base: 0,
+                eof: false,
+            },
+            pos: 0,
+            token_pos: 0,
+            token_line: 1,
+            line: 1,
+            errors: vec![],
+            error_lines: vec![],
+            keep_comments: false,
+        }
+    }
+
+    /// return every comment as a Token::Comment holding its text as written (`--` and any long
+    /// brackets included), for tools that have to keep them; the parser skips them
+    pub fn with_comments(mut self, keep: bool) -> Self {
+        self.keep_comments = keep;
+        self
+    }
+
+    /// tokenize an unbuffered reader, e.g. a File or a socket, in chunks read as the lexer needs them
+    pub fn from_read(reader: impl Read + 'a) -> Lexer<'a> {
+        Self::from_reader(BufReader::new(reader))
+    }
+
+    /// the remaining tokens up to (not including) Eof; lexing goes on after an error, the errors
+    /// are collected in `get_err` as usual
+    pub fn tokens(&mut self) -> Tokens<'_, 'a> {
+        Tokens {
+            lexer: self,
+            done: false,
+        }
+    }
+}
+
+pub struct Tokens<'l, 'a> {
+    lexer: &'l mut Lexer<'a>,
+    done: bool,
+}
+
+impl Iterator for Tokens<'_, '_> {
+    type Item = SpannedToken;
+
+    fn next(&mut self) -> Option<SpannedToken> {
+        if self.done {
+            return None;
+        }
+        let token = self.lexer.next_token();
+        if token == Token::Eof {
+            self.done = true;
+            return None;
+        }
+        Some(SpannedToken {
+            token,
+            start: self.lexer.get_token_pos(),
+            end: self.lexer.get_pos(),
+            line: self.lexer.get_token_line(),
+        })
+    }
+}
+
+impl Lexer<'_> {
     pub fn get_err(&self) -> &Vec<LexerError> {
         return &self.errors;
     }
 
+    // the line each error of `get_err` was found on, in the same order
+    pub fn get_err_lines(&self) -> &[usize] {
+        &self.error_lines
+    }
+
     pub fn get_pos(&self) -> usize {
         return self.pos;
     }
 
+    // source offset of the token returned last
+    pub fn get_token_pos(&self) -> usize {
+        self.token_pos
+    }
+
+    // 1-based line the last token started on
+    pub fn get_token_line(&self) -> usize {
+        self.token_line
+    }
+
+    // 1-based line of the current position
+    pub fn get_line(&self) -> usize {
+        self.line
+    }
+
     fn emit_err(&mut self, err: LexerError) {
         self.errors.push(err);
+        self.error_lines.push(self.token_line);
+    }
+
+    // the byte at an absolute offset, pulling more of the stream in if needed
+    fn byte_at(&mut self, pos: usize) -> Option<u8> {
+        match &mut self.source {
+            Source::Slice(input) => input.as_bytes().get(pos).copied(),
+            Source::Stream {
+                reader,
+                window,
+                base,
+                eof,
+            } => {
+                while pos >= *base + window.len() && !*eof {
+                    match reader.fill_buf() {
+                        Ok([]) => *eof = true,
+                        Ok(chunk) => {
+                            let n = chunk.len();
+                            window.extend_from_slice(chunk);
+                            reader.consume(n);
+                        }
+                        Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                        Err(e) => {
+                            *eof = true;
+                            self.errors.push(LexerError::ReadFailed(e.to_string()));
+                            self.error_lines.push(self.line);
+                            return None;
+                        }
+                    }
+                }
+                window.get(pos - *base).copied()
+            }
+        }
+    }
+
+    // the character starting at a byte offset and its length in bytes, a byte that does not
+    // start a valid UTF-8 sequence reads as U+FFFD on its own so positions stay on boundaries
+    fn char_at(&mut self, pos: usize) -> Option<(char, usize)> {
+        let first = self.byte_at(pos)?;
+        if first.is_ascii() {
+            return Some((first as char, 1));
+        }
+        let len = match first {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => return Some((char::REPLACEMENT_CHARACTER, 1)),
+        };
+        let mut bytes = [first, 0, 0, 0];
+        for (i, byte) in bytes.iter_mut().enumerate().take(len).skip(1) {
+            *byte = self.byte_at(pos + i).unwrap_or(0);
+        }
+        match std::str::from_utf8(&bytes[..len]) {
+            Ok(decoded) => decoded.chars().next().map(|c| (c, len)),
+            Err(_) => Some((char::REPLACEMENT_CHARACTER, 1)),
+        }
+    }
+
+    // source text between two offsets of the current token
+    fn text(&self, begin: usize, end: usize) -> String {
+        match &self.source {
+            Source::Slice(input) => input[begin..end].to_string(),
+            Source::Stream { window, base, .. } => {
+                String::from_utf8_lossy(&window[begin - base..end - base]).into_owned()
+            }
+        }
+    }
+
+    // called between tokens, nothing before `pos` is needed anymore
+    fn release_consumed(&mut self) {
+        if let Source::Stream { window, base, .. } = &mut self.source
+            && self.pos - *base > STREAM_KEEP
+        {
+            window.drain(..self.pos - *base);
+            *base = self.pos;
+        }
     }
 
-    fn is_eof(&self) -> bool {
-        return self.pos >= self.input.len();
+    fn is_eof(&mut self) -> bool {
+        self.byte_at(self.pos).is_none()
     }
 
     fn skip_ws(&mut self) {
-        while !self.is_eof() {
-            let c = self.input.as_bytes()[self.pos] as char;
+        while let Some(c) = self.peek_char() {
             if c.is_whitespace() {
-                self.pos += 1;
+                self.advance();
             } else {
                 break;
             }
@@ -67,77 +303,120 @@ impl Lexer<'_> {
             if self.is_eof() {
                 break;
             }
-            if self.input.as_bytes()[self.pos] as char == '-' {
-                if self.pos + 1 < self.input.len()
-                    && self.input.as_bytes()[self.pos + 1] as char == '-'
-                {
-                    // single line comment
-                    self.pos += 2;
-                    while !self.is_eof() {
-                        let c = self.input.as_bytes()[self.pos] as char;
-                        if c == '\n' {
-                            break;
-                        }
-                        self.pos += 1;
-                    }
-                } else {
-                    break;
-                }
-            } else {
+            if !self.at_comment() || self.keep_comments {
                 break;
             }
+            self.skip_comment();
         }
     }
 
-    fn peek_char(&self) -> Option<char> {
-        if self.is_eof() {
-            None
-        } else {
-            Some(self.input.as_bytes()[self.pos] as char)
+    fn at_comment(&mut self) -> bool {
+        self.byte_at(self.pos) == Some(b'-') && self.byte_at(self.pos + 1) == Some(b'-')
+    }
+
+    // from `--` to the end of the line, or to the closing bracket of a long comment
+    fn skip_comment(&mut self) {
+        self.advance();
+        self.advance();
+        if let Some(level) = self.long_bracket_level() {
+            self.skip_long_comment(level);
+            return;
+        }
+        while let Some(c) = self.peek_char() {
+            if c == '\n' {
+                break;
+            }
+            self.advance();
         }
     }
 
-    fn advance(&mut self) -> Option<char> {
-        if self.is_eof() {
-            None
-        } else {
-            let c = self.input.as_bytes()[self.pos] as char;
-            self.pos += 1;
-            Some(c)
+    // the number of '=' of a long bracket `[==[` starting at the current position
+    fn long_bracket_level(&mut self) -> Option<usize> {
+        if self.byte_at(self.pos) != Some(b'[') {
+            return None;
+        }
+        let mut level = 0;
+        while self.byte_at(self.pos + 1 + level) == Some(b'=') {
+            level += 1;
         }
+        (self.byte_at(self.pos + 1 + level) == Some(b'[')).then_some(level)
     }
 
-    fn num_literal(&mut self) -> Token {
-        let begin_pos = self.pos;
-        loop {
-            let c = self.peek_char();
-            match c {
-                Some(ch) if ch.is_ascii_digit() => {
+    // skip `[==[ ... ]==]`, only a closing bracket of the same level ends it
+    fn skip_long_comment(&mut self, level: usize) {
+        let start_line = self.line;
+        for _ in 0..level + 2 {
+            self.advance();
+        }
+        while let Some(c) = self.advance() {
+            if c != ']' {
+                continue;
+            }
+            let mut closing = 0;
+            while self.byte_at(self.pos + closing) == Some(b'=') {
+                closing += 1;
+            }
+            if closing == level && self.byte_at(self.pos + closing) == Some(b']') {
+                for _ in 0..level + 1 {
                     self.advance();
                 }
-                _ => break,
+                return;
             }
         }
+        self.errors.push(LexerError::UnterminatedComment);
+        self.error_lines.push(start_line);
+    }
 
-        // fractional
-        if self.peek_char() == Some('.') {
-            self.advance(); // consume '.'
-            loop {
-                let c = self.peek_char();
-                match c {
-                    Some(ch) if ch.is_ascii_digit() => {
+    fn peek_char(&mut self) -> Option<char> {
+        self.char_at(self.pos).map(|(c, _)| c)
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let (c, len) = self.char_at(self.pos)?;
+        self.pos += len;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    // a lua numeral: decimal or hex ("0x"), both with an optional fraction and exponent
+    // ('e' for decimal, 'p' and a binary exponent for hex)
+    fn num_literal(&mut self) -> Token {
+        let begin_pos = self.pos;
+        let is_hex = self.peek_char() == Some('0')
+            && matches!(self.byte_at(self.pos + 1), Some(b'x') | Some(b'X'));
+        if is_hex {
+            self.advance();
+            self.advance();
+        }
+        let exponent = if is_hex { ['p', 'P'] } else { ['e', 'E'] };
+
+        // as in lua, take everything that could belong to the numeral and judge it afterwards,
+        // so `1.2.3` or `3x` is one malformed number instead of several tokens
+        loop {
+            match self.peek_char() {
+                Some(c) if exponent.contains(&c) => {
+                    self.advance();
+                    if matches!(self.peek_char(), Some('+') | Some('-')) {
                         self.advance();
                     }
-                    _ => break,
                 }
+                Some(c) if c.is_ascii_hexdigit() || c == '.' => {
+                    self.advance();
+                }
+                _ => break,
             }
         }
+        while matches!(self.peek_char(), Some(c) if c.is_alphanumeric() || c == '_') {
+            self.advance();
+        }
 
-        let num_str = &self.input[begin_pos..self.pos];
-        match num_str.parse::<f64>() {
-            Ok(num) => Token::NumLit(num),
-            Err(_) => {
-                self.emit_err(LexerError::InvalidNumber);
+        let num_str = self.text(begin_pos, self.pos);
+        match parse_numeral(&num_str) {
+            Some(token) => token,
+            None => {
+                self.emit_err(LexerError::InvalidNumber(num_str));
                 Token::NumLit(0.0)
             }
         }
@@ -147,23 +426,21 @@ impl Lexer<'_> {
         let quote_char = self.advance().unwrap(); // consume opening quote
         let mut escape = false;
         let begin_pos = self.pos;
-        while !self.is_eof() {
-            let c = self.advance().unwrap();
+        while let Some(c) = self.advance() {
             if escape {
                 escape = false;
             } else if c == '\\' {
                 escape = true;
             } else if c == quote_char {
                 // closing quote
-                let str_lit = &self.input[begin_pos..self.pos - 1];
-                return Token::StrLit(str_lit.to_string());
+                return Token::StrLit(self.text(begin_pos, self.pos - 1));
             }
         }
         self.emit_err(LexerError::UnterminatedString);
         Token::StrLit(String::new())
     }
 
-    fn is_keyword(s: &str) -> Option<Token> {
+    pub(crate) fn is_keyword(s: &str) -> Option<Token> {
         match s {
             "and" => Some(Token::KwAnd),
             "or" => Some(Token::KwOr),
@@ -183,6 +460,7 @@ impl Lexer<'_> {
             "function" => Some(Token::KwFunction),
             "return" => Some(Token::KwReturn),
             "local" => Some(Token::KwLocal),
+            "goto" => Some(Token::KwGoto),
             _ => None,
         }
     }
@@ -192,17 +470,17 @@ impl Lexer<'_> {
         loop {
             let c = self.peek_char();
             match c {
-                Some(ch) if ch.is_ascii_alphanumeric() || ch == '_' => {
+                Some(ch) if ch.is_alphanumeric() || ch == '_' => {
                     self.advance();
                 }
                 _ => break,
             }
         }
-        let ident_str = &self.input[begin_pos..self.pos];
-        if let Some(kw_token) = Lexer::is_keyword(ident_str) {
+        let ident_str = self.text(begin_pos, self.pos);
+        if let Some(kw_token) = Lexer::is_keyword(&ident_str) {
             kw_token
         } else {
-            Token::Ident(ident_str.to_string())
+            Token::Ident(ident_str)
         }
     }
 
@@ -217,17 +495,33 @@ impl Lexer<'_> {
     }
 
     pub fn next_token(&mut self) -> Token {
+        self.release_consumed();
         self.skip_ws_and_comments();
+        self.token_pos = self.pos;
+        self.token_line = self.line;
 
         if self.is_eof() {
             return Token::Eof;
         }
+        if self.at_comment() {
+            self.skip_comment();
+            return Token::Comment(self.text(self.token_pos, self.pos));
+        }
 
         let c = self.peek_char();
         match c {
             Some(ch) if ch.is_ascii_digit() => self.num_literal(),
+            // `.5`
+            Some('.')
+                if self
+                    .byte_at(self.pos + 1)
+                    .is_some_and(|b| b.is_ascii_digit()) =>
+            {
+                self.num_literal()
+            }
             Some('"') | Some('\'') => self.str_literal(),
-            Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => self.ident_or_keyword(),
+            // identifiers may use any unicode letter
+            Some(ch) if ch.is_alphabetic() || ch == '_' => self.ident_or_keyword(),
             _ => {
                 match self.advance() {
                     Some(chr) => match chr {
@@ -251,7 +545,7 @@ impl Lexer<'_> {
                         ']' => Token::RBracket,
                         ',' => Token::Comma,
                         ';' => Token::Semicolon,
-                        ':' => Token::Colon,
+                        ':' => self.double_char_op(':', Token::DoubleColon, Token::Colon),
                         '@' => Token::At,
                         other => {
                             self.emit_err(LexerError::UnexpectedCharacter(other));
@@ -267,3 +561,53 @@ impl Lexer<'_> {
         }
     }
 }
+
+// the token for a numeral scanned by `num_literal`, None if it is malformed
+fn parse_numeral(text: &str) -> Option<Token> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        return parse_hex_numeral(hex);
+    }
+    let well_formed = text
+        .bytes()
+        .all(|b| b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-'));
+    if !well_formed {
+        return None;
+    }
+    // a decimal integer that overflows i64 falls back to a float, as in lua 5.3
+    let is_float = text.contains(['.', 'e', 'E']);
+    if !is_float && let Ok(int) = text.parse::<i64>() {
+        return Some(Token::IntLit(int));
+    }
+    text.parse::<f64>().ok().map(Token::NumLit)
+}
+
+// hex digits with an optional fraction and binary exponent; a hex integer wraps around
+// instead of overflowing, as in lua
+fn parse_hex_numeral(text: &str) -> Option<Token> {
+    let (mantissa, exponent) = match text.find(['p', 'P']) {
+        Some(i) => (&text[..i], Some(text[i + 1..].parse::<i32>().ok()?)),
+        None => (text, None),
+    };
+    let (int_part, frac_part) = match mantissa.split_once('.') {
+        Some((int_part, frac_part)) => (int_part, Some(frac_part)),
+        None => (mantissa, None),
+    };
+    let digits: Vec<u32> = int_part
+        .chars()
+        .chain(frac_part.unwrap_or("").chars())
+        .map(|c| c.to_digit(16))
+        .collect::<Option<_>>()?;
+    if digits.is_empty() {
+        return None;
+    }
+
+    if frac_part.is_none() && exponent.is_none() {
+        let int = digits
+            .iter()
+            .fold(0i64, |acc, &d| acc.wrapping_mul(16).wrapping_add(d as i64));
+        return Some(Token::IntLit(int));
+    }
+    let mantissa = digits.iter().fold(0.0, |acc, &d| acc * 16.0 + d as f64);
+    let scale = exponent.unwrap_or(0) - 4 * frac_part.map_or(0, str::len) as i32;
+    Some(Token::NumLit(mantissa * 2f64.powi(scale)))
+}
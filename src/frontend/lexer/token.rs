@@ -5,6 +5,9 @@
 //      26-02-10: Initial version
 //      26-02-13: Added '@' operator for legacy table ctor
 //      26-02-20: Added '%' and '#' operators for modulo and length
+//      26-02-24: Integer literals get their own token
+//      26-02-24: 'goto' and '::' for labels
+//      26-02-24: Comment, only produced by a lexer built `with_comments(true)`
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
@@ -12,8 +15,12 @@ pub enum Token {
 
     Eof,
 
+    // the text of a comment as written, `--` included
+    Comment(String),
+
     Ident(String),
     NumLit(f64),
+    IntLit(i64),
     StrLit(String),
 
     Assign,
@@ -45,6 +52,7 @@ pub enum Token {
     Dot,
     Semicolon,
     Colon,
+    DoubleColon,
     At,
 
     KwAnd,
@@ -67,4 +75,5 @@ pub enum Token {
     KwFunction,
     KwReturn,
     KwLocal,
+    KwGoto,
 }
@@ -1,3 +1,4 @@
+pub mod diagnostics;
 pub mod ir;
 pub mod lexer;
 pub mod parser;
@@ -116,4 +117,52 @@ mod tests {
         println!("{}", ir_gen.get_module().to_string());
         println!("IR Generation Errors: {:#?}", ir_gen.get_err());
     }
+
+    #[test]
+    fn method_definitions() {
+        let mut lexer = Lexer::new(
+            "
+        local Account = { ui = { widgets = {} } }
+        function Account:deposit(amount)
+            self.balance = self.balance + amount
+        end
+        function Account.ui.widgets:draw()
+            return self
+        end
+        function Account.new(balance)
+            return { balance = balance }
+        end
+        local acc = Account.new(10)
+        acc:deposit(5)
+        ",
+        );
+        let mut parser = Parser::new(&mut lexer);
+        let ast = parser.parse();
+        assert!(parser.get_err().is_empty(), "{:#?}", parser.get_err());
+
+        let mut ir_gen = IRGenerator::new();
+        ir_gen.generate(&ast);
+        let module = ir_gen.get_module();
+
+        let find = |suffix: &str| {
+            module
+                .functions
+                .iter()
+                .find(|f| f.name.contains(suffix))
+                .unwrap_or_else(|| panic!("no prototype for {}", suffix))
+        };
+
+        let deposit = find("Account:deposit");
+        assert_eq!(deposit.params, vec!["self", "amount"]);
+        assert_eq!(deposit.local_variables.get("self"), Some(&0));
+        assert_eq!(deposit.local_variables.get("amount"), Some(&1));
+
+        let draw = find("Account.ui.widgets:draw");
+        assert_eq!(draw.params, vec!["self"]);
+        assert_eq!(draw.local_variables.get("self"), Some(&0));
+
+        let new = find("Account.new");
+        assert_eq!(new.params, vec!["balance"]);
+        assert!(!new.local_variables.contains_key("self"));
+    }
 }
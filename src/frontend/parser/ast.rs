@@ -5,35 +5,102 @@
 //      26-02-10: Initial version
 //      26-02-11: Added more AST node types
 //      26-02-13: Table ctors, member access
+//      26-02-22: Method calls with implicit self
+//      26-02-24: Local declarations and function parameters carry the source offsets of their names
+//      26-02-24: Integer literals
+//      26-02-24: Binary expressions record the source offset of the operator
+//      26-02-24: Assignment statement, `a, b = b, a`
+//      26-02-24: do ... end blocks
+//      26-02-24: goto and labels
+//      26-02-24: Statements in bodies are `Stmt`s, which carry the line the statement starts on
+//      26-02-24: Every statement and expression carries its source `Span` and a `NodeId`,
+//                expressions are `Expr`s wrapping the `Expression` node
 
 #[derive(Debug, Clone)]
 pub struct Program {
-    pub body: Vec<Statement>,
+    pub body: Vec<Stmt>,
+}
+
+/// the source bytes `start..end` a node was parsed from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+}
+
+/// identifies a statement or expression within one parsed program, the parser numbers
+/// the nodes in the order it completes them, so the same source always gets the same ids
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub u32);
+
+// a statement and the 1-based source line it starts on, the line ends up in the
+// line table of the compiled function
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub line: usize,
+    pub span: Span,
+    pub id: NodeId,
+    pub node: Statement,
+}
+
+// an expression node with its span and id, the span of a parenthesized expression
+// is the one of the expression inside
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub span: Span,
+    pub id: NodeId,
+    pub node: Expression,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
-    ExprStatement(Box<Expression>),
+    ExprStatement(Box<Expr>),
     Declaration {
         names: Vec<String>,
-        values: Vec<Expression>,
+        values: Vec<Expr>,
+        // source offset of each name, used for diagnostics only
+        name_pos: Vec<usize>,
+    },
+    // targets = values at statement level, e.g. a, t.x = 1, 2
+    // `=` is not allowed inside an expression, a BinOp::Assign only comes from `function a.b() end`
+    Assignment {
+        targets: Vec<Expr>,
+        values: Vec<Expr>,
     },
     IfStmt {
-        condition: Box<Expression>,
-        then_branch: Vec<Statement>,
-        elif_branches: Vec<(Expression, Vec<Statement>)>,
-        else_branch: Option<Vec<Statement>>,
+        condition: Box<Expr>,
+        then_branch: Vec<Stmt>,
+        elif_branches: Vec<(Expr, Vec<Stmt>)>,
+        else_branch: Option<Vec<Stmt>>,
+    },
+    // do ... end, only opens a scope
+    DoStmt {
+        body: Vec<Stmt>,
     },
     WhileStmt {
-        condition: Box<Expression>,
-        body: Vec<Statement>,
+        condition: Box<Expr>,
+        body: Vec<Stmt>,
     },
     RepeatStmt {
-        body: Vec<Statement>,
-        condition: Box<Expression>,
+        body: Vec<Stmt>,
+        condition: Box<Expr>,
     },
     ReturnStmt {
-        values: Vec<Expression>,
+        values: Vec<Expr>,
+    },
+    // goto name
+    Goto {
+        label: String,
+    },
+    // ::name::
+    Label {
+        name: String,
     },
 }
 
@@ -42,43 +109,55 @@ pub enum Expression {
     Identifier(String),
     Literal(Literal),
     BinOp {
-        left: Box<Expression>,
+        left: Box<Expr>,
         operator: BinOp,
-        right: Box<Expression>,
+        right: Box<Expr>,
+        // source offset of the operator, used for diagnostics only
+        op_pos: usize,
     },
     UnOp {
         operator: UnOp,
-        operand: Box<Expression>,
+        operand: Box<Expr>,
     },
     FnCall {
-        callee: Box<Expression>,
-        arguments: Vec<Expression>,
+        callee: Box<Expr>,
+        arguments: Vec<Expr>,
+    },
+    // obj:method(args), sugar for obj.method(obj, args)
+    // but obj is only evaluated once
+    MethodCall {
+        object: Box<Expr>,
+        method: String,
+        arguments: Vec<Expr>,
     },
     IndexOf {
-        collection: Box<Expression>,
-        index: Box<Expression>,
+        collection: Box<Expr>,
+        index: Box<Expr>,
     },
     MemberAccess {
-        collection: Box<Expression>,
+        collection: Box<Expr>,
         member: String,
     },
     TableCtor {
         // {key: value, ...} - table
         // {value, value, ...} - arraylike, with implicit keys 1, 2, 3, ...
         // {key: value, value, ...} - mixed
-        fields: Vec<(Option<Expression>, Expression)>,
+        fields: Vec<(Option<Expr>, Expr)>,
     },
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
     Number(f64),
+    Integer(i64),
     String(String),
     Boolean(bool),
     Function {
         params: Vec<String>,
-        body: Vec<Statement>,
+        body: Vec<Stmt>,
         name: Option<String>,
+        // source offset of each parameter, an implicit 'self' gets the offset of the method name
+        param_pos: Vec<usize>,
     },
     Nil,
 }
@@ -9,8 +9,30 @@
 //      26-02-13: Added table constructor parsing and member access parsing
 //      26-02-18: Added concat operator parsing
 //      26-02-20: Allow nil-initialization of local variables by omitting the initializer
+//      26-02-22: Method call `obj:m(...)` and method definition `function a.b:m(...)` sugar
+//      26-02-24: Record the source offsets of declared local names and parameters
+//      26-02-24: The parser borrows the lexer separately from the lexer's source, so streaming lexers work
+//      26-02-24: Binary expressions carry the source offset of their operator
+//      26-02-24: Statement level `=` with target and value lists parses into Statement::Assignment
+//      26-02-24: do ... end blocks
+//      26-02-24: goto statements and ::labels::
+//      26-02-24: Errors raised at the end of the input are UnexpectedEof, so a REPL can ask for more lines
+//      26-02-24: Every parsed statement records the line it starts on
+//      26-02-24: Error recovery: a statement that fails to parse is skipped up to the next statement keyword
+//                or block end, so one run reports every error; errors carry the line of the offending token
+//      26-02-24: Nesting depth limit: statements, expressions and operator / postfix chains deeper than
+//                `max_depth` stop the parse with NestingTooDeep instead of overflowing the stack
+//      26-02-24: Binary operator chains `a + b + c` no longer count every operator as a nesting level, a
+//                chain's operators plus the levels below it are limited by the longer `max_chain` instead
+//      26-02-24: AST printer (printer.rs), `Program::to_source` regenerates source from a parsed tree
+//      26-02-24: Statements and expressions get their source span and a node id, numbered in the order
+//                the nodes are completed
+//      26-02-24: Comment tokens of a lexer built `with_comments` are skipped
+//      26-02-24: `=` is only parsed by the assignment and declaration statements, chained assignment and
+//                `=` inside an expression are errors
 
 pub mod ast;
+mod printer;
 
 use crate::frontend::lexer::{Lexer, token::Token};
 
@@ -20,33 +42,83 @@ pub enum ParserErrorType {
     UnclosedBrackets,
     UnexpectedEof,
     InvalidExpression,
+    NestingTooDeep,
 }
 
+/// how deep statements and expressions may nest before the parser gives up, every later phase
+/// walks the tree recursively too, so this bounds their stack use as well
+pub const MAX_NESTING_DEPTH: usize = 200;
+
+/// how many levels a binary operator chain `a + b + c` may put above its deepest operand, one per
+/// operator plus the levels the chain is nested in; chains are parsed and lowered in a loop, but the
+/// other walks over the tree recurse along them
+pub const MAX_CHAIN_LENGTH: usize = 1000;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct ParserError {
     pub err_type: ParserErrorType,
     pub message: String,
     pub pos: usize,
+    pub line: usize,
 }
 
-pub struct Parser<'a> {
-    lexer: &'a mut Lexer<'a>,
+pub struct Parser<'a, 'src> {
+    lexer: &'a mut Lexer<'src>,
     current_token: Option<Token>,
     next_token: Option<Token>,
+    // source offset of the peeked token
+    next_token_pos: usize,
+    // line the peeked token starts on
+    next_token_line: usize,
+    // source offset just past the last consumed token, where the node being completed ends
+    prev_token_end: usize,
+    // id of the next node completed
+    next_node_id: u32,
     errors: Vec<ParserError>,
+    // nesting of the construct being parsed, see `nested`
+    depth: usize,
+    max_depth: usize,
+    max_chain: usize,
+    // deepest level reached inside the binary operator chain being parsed, its operators included
+    deepest: usize,
+    // set once the input nested too deep, the parse unwinds without reporting anything else
+    too_deep: bool,
 }
 
-impl Parser<'_> {
-    pub fn new<'a>(lexer: &'a mut Lexer<'a>) -> Parser<'a> {
-        let next = lexer.next_token();
+impl Parser<'_, '_> {
+    pub fn new<'a, 'src>(lexer: &'a mut Lexer<'src>) -> Parser<'a, 'src> {
+        let next = Self::significant_token(lexer);
+        let next_pos = lexer.get_token_pos();
+        let next_line = lexer.get_token_line();
         return Parser {
             lexer: lexer,
             current_token: None,
             next_token: Some(next),
+            next_token_pos: next_pos,
+            next_token_line: next_line,
+            prev_token_end: 0,
+            next_node_id: 0,
             errors: vec![],
+            depth: 0,
+            max_depth: MAX_NESTING_DEPTH,
+            max_chain: MAX_CHAIN_LENGTH,
+            deepest: 0,
+            too_deep: false,
         };
     }
 
+    /// allow statements and expressions to nest `max_depth` levels deep instead of `MAX_NESTING_DEPTH`
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// allow chains of binary operators `max_chain` operators long instead of `MAX_CHAIN_LENGTH`
+    pub fn with_max_chain_length(mut self, max_chain: usize) -> Self {
+        self.max_chain = max_chain;
+        self
+    }
+
     pub fn get_err(&self) -> &Vec<ParserError> {
         return &self.errors;
     }
@@ -56,17 +128,162 @@ impl Parser<'_> {
     }
 
     fn emit_err(&mut self, err_type: ParserErrorType, message: String) {
-        let pos = self.lexer.get_pos();
+        if self.too_deep {
+            return;
+        }
+        let pos = self.next_token_pos;
+        let line = self.next_token_line;
+        // whatever went wrong, the input simply ended too early
+        let err_type = if self.peek_token() == &Token::Eof {
+            ParserErrorType::UnexpectedEof
+        } else {
+            err_type
+        };
         self.errors.push(ParserError {
             err_type: err_type,
             message: message,
             pos: pos,
+            line,
         });
     }
 
+    // `extra` more levels below the current one, reports the input as too deep if they do not fit;
+    // a postfix chain such as `f()()` builds one level per link without recursing
+    fn check_depth(&mut self, extra: usize) -> bool {
+        if self.depth + extra <= self.max_depth {
+            self.deepest = self.deepest.max(self.depth + extra);
+            return true;
+        }
+        let msg = format!("Nesting deeper than {} levels", self.max_depth);
+        self.emit_err(ParserErrorType::NestingTooDeep, msg);
+        self.too_deep = true;
+        false
+    }
+
+    // `links` operators of a binary operator chain parsed, each one puts the whole chain so far one level
+    // further down; reports the chain as too long if its deepest operand ends up past `max_chain`
+    fn check_chain(&mut self, links: usize) -> bool {
+        if self.deepest + links <= self.max_chain {
+            return true;
+        }
+        let msg = format!("Operator chain deeper than {} levels", self.max_chain);
+        self.emit_err(ParserErrorType::NestingTooDeep, msg);
+        self.too_deep = true;
+        false
+    }
+
+    // parse one level further down
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Option<T>) -> Option<T> {
+        if self.too_deep || !self.check_depth(1) {
+            return None;
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    // tokens that can start a statement or close a block, parsing resumes at one of them after an error
+    fn is_sync_token(token: &Token) -> bool {
+        matches!(
+            token,
+            Token::KwLocal
+                | Token::KwIf
+                | Token::KwDo
+                | Token::KwWhile
+                | Token::KwRepeat
+                | Token::KwFunction
+                | Token::KwReturn
+                | Token::KwGoto
+                | Token::DoubleColon
+                | Token::KwEnd
+                | Token::KwElse
+                | Token::KwElseIf
+                | Token::KwUntil
+                | Token::Eof
+        )
+    }
+
+    // skip the rest of a statement that failed to parse: up to a statement keyword or block end,
+    // or the first token on a later line than the error, which most likely starts the next statement
+    fn synchronize(&mut self) {
+        let err_line = self.errors.last().map_or(self.next_token_line, |e| e.line);
+        while !Parser::is_sync_token(self.peek_token()) && self.next_token_line <= err_line {
+            self.advance_tokens();
+        }
+    }
+
+    // skip to the token that resumes the construct (`then`, `do`, `)`), unless a statement starts first
+    fn skip_to(&mut self, token: Token) {
+        while self.peek_token() != &token && !Parser::is_sync_token(self.peek_token()) {
+            self.advance_tokens();
+        }
+    }
+
+    // statements up to (not including) one of the terminators or the end of the input
+    fn parse_block(&mut self, terminators: &[Token]) -> Vec<ast::Stmt> {
+        // a function body costs the later phases far more stack than an expression, so the
+        // block counts as a level of its own on top of the statement that opens it
+        self.nested(|parser| Some(parser.parse_block_impl(terminators)))
+            .unwrap_or_default()
+    }
+
+    fn parse_block_impl(&mut self, terminators: &[Token]) -> Vec<ast::Stmt> {
+        let mut body: Vec<ast::Stmt> = vec![];
+        while self.peek_token() != &Token::Eof
+            && !terminators.contains(self.peek_token())
+            && !self.too_deep
+        {
+            let start = self.next_token_pos;
+            if let Some(stmt) = self.parse_statement() {
+                body.push(stmt);
+                continue;
+            }
+            // a stray keyword such as `end` fails without consuming anything
+            if self.next_token_pos == start {
+                self.advance_tokens();
+            }
+            self.synchronize();
+        }
+        body
+    }
+
+    // source offset of the peeked identifier token, the lexer has just moved past it
+    fn ident_pos(&self, name: &str) -> usize {
+        self.lexer.get_pos().saturating_sub(name.len())
+    }
+
     fn advance_tokens(&mut self) {
+        self.prev_token_end = self.lexer.get_pos();
         self.current_token = self.next_token.take();
-        self.next_token = Some(self.lexer.next_token());
+        self.next_token = Some(Self::significant_token(self.lexer));
+        self.next_token_pos = self.lexer.get_token_pos();
+        self.next_token_line = self.lexer.get_token_line();
+    }
+
+    // the next token that is not a comment, a lexer built `with_comments` returns them too
+    fn significant_token(lexer: &mut Lexer<'_>) -> Token {
+        loop {
+            match lexer.next_token() {
+                Token::Comment(_) => {}
+                token => return token,
+            }
+        }
+    }
+
+    fn node_id(&mut self) -> ast::NodeId {
+        let id = ast::NodeId(self.next_node_id);
+        self.next_node_id += 1;
+        id
+    }
+
+    // an expression from `start` up to the last consumed token
+    fn expr(&mut self, start: usize, node: ast::Expression) -> ast::Expr {
+        ast::Expr {
+            span: ast::Span::new(start, self.prev_token_end),
+            id: self.node_id(),
+            node,
+        }
     }
 
     fn peek_token(&self) -> &Token {
@@ -101,7 +318,7 @@ impl Parser<'_> {
         }
     }
 
-    fn binop_precedence(op: &ast::BinOp) -> Option<u8> {
+    pub(crate) fn binop_precedence(op: &ast::BinOp) -> Option<u8> {
         match op {
             ast::BinOp::Assign => Some(0),
             ast::BinOp::Or => Some(1),
@@ -119,7 +336,7 @@ impl Parser<'_> {
         }
     }
 
-    fn is_binop_right_assoc(op: &ast::BinOp) -> bool {
+    pub(crate) fn is_binop_right_assoc(op: &ast::BinOp) -> bool {
         match op {
             ast::BinOp::Pow | ast::BinOp::Concat => true,
             _ => false,
@@ -143,16 +360,60 @@ impl Parser<'_> {
             Token::Geq => Some(ast::BinOp::Geq),
             Token::KwAnd => Some(ast::BinOp::And),
             Token::KwOr => Some(ast::BinOp::Or),
-            Token::Assign => Some(ast::BinOp::Assign),
+            // `=` is no operator, an expression ends before it
             _ => None,
         }
     }
 
-    fn parse_fn_call_expression(&mut self, callee: ast::Expression) -> Option<ast::Expression> {
+    fn parse_fn_call_expression(&mut self, callee: ast::Expr) -> Option<ast::Expression> {
+        let args = self.parse_call_arguments()?;
+
+        Some(ast::Expression::FnCall {
+            callee: Box::new(callee),
+            arguments: args,
+        })
+    }
+
+    fn parse_method_call_expression(&mut self, object: ast::Expr) -> Option<ast::Expression> {
+        self.advance_tokens(); // consume ':'
+        let method = match self.peek_token().clone() {
+            Token::Ident(name) => {
+                self.advance_tokens();
+                name
+            }
+            _ => {
+                let msg = format!(
+                    "Expected method name after ':', found {:?}",
+                    self.peek_token()
+                );
+                self.emit_err(ParserErrorType::UnexpectedToken, msg);
+                return None;
+            }
+        };
+
+        if self.peek_token() != &Token::LParen {
+            let msg = format!(
+                "Expected '(' after method name '{}', found {:?}",
+                method,
+                self.peek_token()
+            );
+            self.emit_err(ParserErrorType::UnexpectedToken, msg);
+            return None;
+        }
+        let args = self.parse_call_arguments()?;
+
+        Some(ast::Expression::MethodCall {
+            object: Box::new(object),
+            method,
+            arguments: args,
+        })
+    }
+
+    fn parse_call_arguments(&mut self) -> Option<Vec<ast::Expr>> {
         self.advance_tokens(); // consume '('
 
         // args
-        let mut args: Vec<ast::Expression> = vec![];
+        let mut args: Vec<ast::Expr> = vec![];
         if self.peek_token() != &Token::RParen {
             loop {
                 let arg_expr = self.parse_expression();
@@ -182,13 +443,10 @@ impl Parser<'_> {
             return None;
         }
 
-        Some(ast::Expression::FnCall {
-            callee: Box::new(callee),
-            arguments: args,
-        })
+        Some(args)
     }
 
-    fn parse_index_expression(&mut self, collection: ast::Expression) -> Option<ast::Expression> {
+    fn parse_index_expression(&mut self, collection: ast::Expr) -> Option<ast::Expression> {
         self.advance_tokens(); // consume '['
         let index_expr = self.parse_expression();
         if index_expr.is_none() {
@@ -219,11 +477,10 @@ impl Parser<'_> {
         }
 
         self.expect(Token::LBrace);
-        let mut fields: Vec<(Option<ast::Expression>, ast::Expression)> = vec![];
+        let mut fields: Vec<(Option<ast::Expr>, ast::Expr)> = vec![];
         if self.peek_token() != &Token::RBrace {
             loop {
-                let key_expr: Option<ast::Expression>;
-                let value_expr: ast::Expression;
+                let key_expr: Option<ast::Expr>;
 
                 // check if it's a key-value pair or just a value
                 if self.peek_token() == &Token::LBracket {
@@ -257,6 +514,7 @@ impl Parser<'_> {
                 } else if let Token::Ident(_) = self.peek_token() {
                     // key-value pair with identifier key
                     // { key = value, ... }
+                    let key_start = self.next_token_pos;
                     let key = match self.peek_token().clone() {
                         Token::Ident(name) => {
                             self.advance_tokens();
@@ -266,7 +524,8 @@ impl Parser<'_> {
                     };
 
                     // for this style of key, we convert it to string literal
-                    key_expr = Some(ast::Expression::Literal(ast::Literal::String(key)));
+                    let key = ast::Expression::Literal(ast::Literal::String(key));
+                    key_expr = Some(self.expr(key_start, key));
 
                     if !self.expect(Token::Assign) {
                         self.emit_err(
@@ -290,7 +549,7 @@ impl Parser<'_> {
                     );
                     return None;
                 }
-                value_expr = value.unwrap();
+                let value_expr = value.unwrap();
 
                 fields.push((key_expr, value_expr));
 
@@ -307,7 +566,12 @@ impl Parser<'_> {
         Some(ast::Expression::TableCtor { fields })
     }
 
-    fn parse_unary_or_primary_expression(&mut self) -> Option<ast::Expression> {
+    fn parse_unary_or_primary_expression(&mut self) -> Option<ast::Expr> {
+        self.nested(Parser::parse_unary_or_primary_expression_impl)
+    }
+
+    fn parse_unary_or_primary_expression_impl(&mut self) -> Option<ast::Expr> {
+        let start = self.next_token_pos;
         let token = self.peek_token().clone();
         let simple = match token {
             // unary operators
@@ -353,6 +617,10 @@ impl Parser<'_> {
                 self.advance_tokens();
                 Some(ast::Expression::Literal(ast::Literal::Number(num)))
             }
+            Token::IntLit(int) => {
+                self.advance_tokens();
+                Some(ast::Expression::Literal(ast::Literal::Integer(int)))
+            }
             Token::StrLit(s) => {
                 self.advance_tokens();
                 Some(ast::Expression::Literal(ast::Literal::String(s)))
@@ -370,14 +638,14 @@ impl Parser<'_> {
                 Some(ast::Expression::Literal(ast::Literal::Nil))
             }
 
-            // parentheses
+            // parentheses, the expression inside is the node
             Token::LParen => {
                 self.advance_tokens(); // consume '('
                 let expr = self.parse_expression()?;
                 if !self.expect(Token::RParen) {
                     return None;
                 }
-                Some(expr)
+                return self.parse_postfix_chain(start, expr);
             }
 
             // function literal
@@ -405,11 +673,24 @@ impl Parser<'_> {
         if simple.is_none() {
             return None;
         }
-        let mut simple = simple.unwrap();
+        let simple = self.expr(start, simple.unwrap());
+        self.parse_postfix_chain(start, simple)
+    }
 
-        // postfix exprs: fn calls, indexing
+    // postfix exprs: fn calls, indexing; each link spans from `start` of the first operand
+    fn parse_postfix_chain(&mut self, start: usize, mut simple: ast::Expr) -> Option<ast::Expr> {
+        let mut links = 0;
         loop {
             let next_tok = self.peek_token().clone();
+            if matches!(
+                next_tok,
+                Token::LParen | Token::Colon | Token::LBracket | Token::Dot
+            ) {
+                links += 1;
+                if !self.check_depth(links) {
+                    return None;
+                }
+            }
             match next_tok {
                 Token::LParen => {
                     // fn call
@@ -419,11 +700,16 @@ impl Parser<'_> {
                     }
 
                     if let Some(expr) = fn_call_expr {
-                        simple = expr;
+                        simple = self.expr(start, expr);
                     } else {
                         return None;
                     }
                 }
+                Token::Colon => {
+                    // method call
+                    let expr = self.parse_method_call_expression(simple)?;
+                    simple = self.expr(start, expr);
+                }
                 Token::LBracket => {
                     // indexing
                     let index_expr = self.parse_index_expression(simple);
@@ -431,7 +717,7 @@ impl Parser<'_> {
                         return None;
                     }
                     if let Some(expr) = index_expr {
-                        simple = expr;
+                        simple = self.expr(start, expr);
                     } else {
                         return None;
                     }
@@ -453,10 +739,11 @@ impl Parser<'_> {
                             return None;
                         }
                     };
-                    simple = ast::Expression::MemberAccess {
+                    let expr = ast::Expression::MemberAccess {
                         collection: Box::new(simple),
                         member: member_name,
                     };
+                    simple = self.expr(start, expr);
                 }
                 _ => break,
             }
@@ -465,18 +752,24 @@ impl Parser<'_> {
         Some(simple)
     }
 
-    fn parse_binary_expression_impl(&mut self, min_prec: u8) -> Option<ast::Expression> {
-        let lhs = self.parse_unary_or_primary_expression();
-        if lhs.is_none() {
-            self.emit_err(
-                ParserErrorType::InvalidExpression,
-                "Failed to parse left-hand side expression".to_string(),
-            );
-            return None;
-        }
+    fn parse_binary_expression_impl(&mut self, min_prec: u8) -> Option<ast::Expr> {
+        self.nested(|parser| parser.parse_binary_chain(min_prec))
+    }
 
-        let mut left_expr = lhs.unwrap();
+    // the operands of a chain are measured on their own, the chain's operators add to the deepest of them
+    fn parse_binary_chain(&mut self, min_prec: u8) -> Option<ast::Expr> {
+        let outer = std::mem::replace(&mut self.deepest, self.depth);
+        let expr = self.parse_binary_links(min_prec);
+        self.deepest = self.deepest.max(outer);
+        expr
+    }
 
+    fn parse_binary_links(&mut self, min_prec: u8) -> Option<ast::Expr> {
+        let start = self.next_token_pos;
+        // the operand has already reported why it failed
+        let mut left_expr = self.parse_unary_or_primary_expression()?;
+
+        let mut links = 0;
         loop {
             let op = Parser::token_to_ast_binop(self.peek_token());
             if op.is_none() {
@@ -494,6 +787,7 @@ impl Parser<'_> {
                 break;
             }
 
+            let op_pos = self.next_token_pos;
             self.advance_tokens(); // consume operator
 
             let mut next_min_prec = prec;
@@ -507,33 +801,75 @@ impl Parser<'_> {
             }
             let rhs = rhs.unwrap();
 
-            left_expr = ast::Expression::BinOp {
+            links += 1;
+            if !self.check_chain(links) {
+                return None;
+            }
+
+            let expr = ast::Expression::BinOp {
                 left: Box::new(left_expr),
                 operator: op,
                 right: Box::new(rhs),
+                op_pos,
             };
+            left_expr = self.expr(start, expr);
         }
 
+        self.deepest += links;
         return Some(left_expr);
     }
 
-    fn parse_binary_expression(&mut self) -> Option<ast::Expression> {
+    fn parse_binary_expression(&mut self) -> Option<ast::Expr> {
         self.parse_binary_expression_impl(0)
     }
 
-    fn parse_expression(&mut self) -> Option<ast::Expression> {
-        self.parse_binary_expression()
+    fn parse_expression(&mut self) -> Option<ast::Expr> {
+        let expr = self.parse_binary_expression()?;
+        if self.peek_token() == &Token::Assign {
+            self.emit_err(
+                ParserErrorType::InvalidExpression,
+                "Assignment is a statement and cannot be used inside an expression, use '==' to compare"
+                    .to_string(),
+            );
+            return None;
+        }
+        Some(expr)
+    }
+
+    // the values after the '=' of a declaration or an assignment
+    fn parse_assigned_values(&mut self) -> Option<Vec<ast::Expr>> {
+        let mut values: Vec<ast::Expr> = vec![];
+        loop {
+            values.push(self.parse_binary_expression()?);
+            if self.peek_token() == &Token::Comma {
+                self.advance_tokens(); // consume ','
+            } else {
+                break;
+            }
+        }
+        if self.peek_token() == &Token::Assign {
+            self.emit_err(
+                ParserErrorType::InvalidExpression,
+                "Assignments cannot be chained, assign a value list instead: `a, b = c, c`"
+                    .to_string(),
+            );
+            return None;
+        }
+        Some(values)
     }
 
-    fn parse_function_decl_inner(&mut self) -> Option<(Vec<String>, Vec<ast::Statement>)> {
+    fn parse_function_decl_inner(&mut self) -> Option<(Vec<String>, Vec<usize>, Vec<ast::Stmt>)> {
         self.expect(Token::LParen);
 
         // parameters
         let mut params: Vec<String> = vec![];
+        let mut param_pos: Vec<usize> = vec![];
+        let mut params_ok = true;
         if self.peek_token() != &Token::RParen {
             loop {
                 match self.peek_token().clone() {
                     Token::Ident(param_name) => {
+                        param_pos.push(self.ident_pos(&param_name));
                         params.push(param_name);
                         self.advance_tokens();
                         if self.peek_token() == &Token::Comma {
@@ -549,7 +885,10 @@ impl Parser<'_> {
                             self.peek_token()
                         );
                         self.emit_err(ParserErrorType::UnexpectedToken, msg);
-                        return None;
+                        // still parse the body, so its `end` is not taken for a stray one
+                        params_ok = false;
+                        self.skip_to(Token::RParen);
+                        break;
                     }
                 }
             }
@@ -558,65 +897,130 @@ impl Parser<'_> {
         self.expect(Token::RParen);
 
         // function body
-        let mut body: Vec<ast::Statement> = vec![];
-        while self.peek_token() != &Token::KwEnd {
-            if let Some(stmt) = self.parse_statement() {
-                body.push(stmt);
-            } else {
-                break;
-            }
-        }
+        let body = self.parse_block(&[Token::KwEnd]);
         self.expect(Token::KwEnd);
 
-        Some((params, body))
+        if !params_ok {
+            return None;
+        }
+        Some((params, param_pos, body))
     }
 
     fn parse_function_decl_statement(&mut self, is_local: bool) -> Option<ast::Statement> {
         // dont expect local here, handled in local decl
 
+        let start = self.next_token_pos;
         self.expect(Token::KwFunction);
 
         // function name
-        let name = match self.peek_token().clone() {
-            Token::Ident(func_name) => {
-                self.advance_tokens();
-                func_name
+        // funcname ::= Name {'.' Name} [':' Name]
+        let mut path: Vec<String> = vec![];
+        // where the name starts and each of its segments ends, the spans of the assignment target
+        let path_start = self.next_token_pos;
+        let mut path_ends: Vec<usize> = vec![];
+        let mut name_pos;
+        let mut is_method = false;
+        loop {
+            match self.peek_token().clone() {
+                Token::Ident(func_name) => {
+                    name_pos = self.ident_pos(&func_name);
+                    self.advance_tokens();
+                    path.push(func_name);
+                    path_ends.push(self.prev_token_end);
+                }
+                _ => {
+                    let msg = format!(
+                        "Expected function name identifier, found {:?}. Note that anonymous functions not \
+                        bound to any variable are meaningless!",
+                        self.peek_token()
+                    );
+                    self.emit_err(ParserErrorType::UnexpectedToken, msg);
+                    return None;
+                }
             }
-            _ => {
-                let msg = format!(
-                    "Expected function name identifier, found {:?}. Note that anonymous functions not \
-                    bound to any variable are meaningless!",
-                    self.peek_token()
-                );
-                self.emit_err(ParserErrorType::UnexpectedToken, msg);
-                return None;
+
+            if is_method {
+                break;
             }
-        };
+            match self.peek_token() {
+                Token::Dot => self.advance_tokens(),
+                Token::Colon => {
+                    self.advance_tokens();
+                    is_method = true;
+                }
+                _ => break,
+            }
+        }
 
-        let (params, body) = self.parse_function_decl_inner()?;
+        if is_local && path.len() > 1 {
+            self.emit_err(
+                ParserErrorType::UnexpectedToken,
+                "Local function declarations cannot have a field path or method name".to_string(),
+            );
+            return None;
+        }
+
+        let (mut params, mut param_pos, body) = self.parse_function_decl_inner()?;
+
+        // the method form takes an implicit 'self' as its first parameter
+        // function t.a.b:m(x) <=> t.a.b.m = function(self, x)
+        if is_method {
+            params.insert(0, "self".to_string());
+            param_pos.insert(0, name_pos);
+        }
+
+        let name = if is_method {
+            let method = path.last().unwrap();
+            format!("{}:{}", path[..path.len() - 1].join("."), method)
+        } else {
+            path.join(".")
+        };
 
         // for named functions, we treat them as assignment to a function literal
         let func_literal = ast::Expression::Literal(ast::Literal::Function {
             name: Some(name.clone()),
             params,
             body,
+            param_pos,
         });
+        let func_literal = self.expr(start, func_literal);
 
         if is_local {
             // local decl
             Some(ast::Statement::Declaration {
                 names: vec![name],
                 values: vec![func_literal],
+                name_pos: vec![name_pos],
             })
         } else {
             // assignment
-            // global decl actually
+            // global decl actually, or a field store for `function a.b.c()`
+            let mut segments = path.into_iter().zip(path_ends);
+            let (first, end) = segments.next().unwrap();
+            let mut target = ast::Expr {
+                span: ast::Span::new(path_start, end),
+                id: self.node_id(),
+                node: ast::Expression::Identifier(first),
+            };
+            for (member, end) in segments {
+                target = ast::Expr {
+                    span: ast::Span::new(path_start, end),
+                    id: self.node_id(),
+                    node: ast::Expression::MemberAccess {
+                        collection: Box::new(target),
+                        member,
+                    },
+                };
+            }
+            let assign = ast::Expression::BinOp {
+                left: Box::new(target),
+                operator: ast::BinOp::Assign,
+                right: Box::new(func_literal),
+                // there is no '=' in `function name() ... end`, point at the name
+                op_pos: name_pos,
+            };
             Some(ast::Statement::ExprStatement(Box::new(
-                ast::Expression::BinOp {
-                    left: Box::new(ast::Expression::Identifier(name)),
-                    operator: ast::BinOp::Assign,
-                    right: Box::new(func_literal),
-                },
+                self.expr(start, assign),
             )))
         }
     }
@@ -624,12 +1028,13 @@ impl Parser<'_> {
     fn parse_function_decl_expression(&mut self) -> Option<ast::Expression> {
         self.expect(Token::KwFunction);
 
-        let (params, body) = self.parse_function_decl_inner()?;
+        let (params, param_pos, body) = self.parse_function_decl_inner()?;
 
         Some(ast::Expression::Literal(ast::Literal::Function {
             params,
             body,
             name: None,
+            param_pos,
         }))
     }
 
@@ -642,9 +1047,11 @@ impl Parser<'_> {
         }
 
         let mut names: Vec<String> = vec![];
+        let mut name_pos: Vec<usize> = vec![];
         loop {
             match self.peek_token().clone() {
                 Token::Ident(name) => {
+                    name_pos.push(self.ident_pos(&name));
                     names.push(name);
                     self.advance_tokens();
                     if self.peek_token() == &Token::Comma {
@@ -667,104 +1074,95 @@ impl Parser<'_> {
 
         if self.peek_token() != &Token::Assign {
             // local declaration without initialization, e.g. "local a, b, c"
-            // nil-initialize them
-            let values = vec![ast::Expression::Literal(ast::Literal::Nil); names.len()];
-            return Some(ast::Statement::Declaration { names, values });
+            // nil-initialize them, the nils are empty spans after the names
+            let end = self.prev_token_end;
+            let values = names
+                .iter()
+                .map(|_| self.expr(end, ast::Expression::Literal(ast::Literal::Nil)))
+                .collect();
+            return Some(ast::Statement::Declaration {
+                names,
+                values,
+                name_pos,
+            });
         }
 
         self.expect(Token::Assign);
+        let values = self.parse_assigned_values()?;
 
-        let mut values: Vec<ast::Expression> = vec![];
-        loop {
-            let expr = self.parse_expression()?;
-            values.push(expr);
-            if self.peek_token() == &Token::Comma {
-                self.advance_tokens(); // consume ','
-                continue;
-            } else {
-                break;
-            }
-        }
-
-        Some(ast::Statement::Declaration { names, values })
+        Some(ast::Statement::Declaration {
+            names,
+            values,
+            name_pos,
+        })
     }
 
     fn parse_if_statement(&mut self) -> Option<ast::Statement> {
         self.expect(Token::KwIf);
-        let cond = self.parse_expression()?;
+        // a broken condition still lets the branches be parsed, they may hold more errors
+        let cond = self.parse_expression();
+        if cond.is_none() {
+            self.skip_to(Token::KwThen);
+        }
 
         self.expect(Token::KwThen);
-        let mut then_branch: Vec<ast::Statement> = vec![];
-        while self.peek_token() != &Token::KwElse
-            && self.peek_token() != &Token::KwElseIf
-            && self.peek_token() != &Token::KwEnd
-        {
-            if let Some(stmt) = self.parse_statement() {
-                then_branch.push(stmt);
-            } else {
-                break;
-            }
-        }
+        let then_branch = self.parse_block(&[Token::KwElse, Token::KwElseIf, Token::KwEnd]);
 
-        let mut elif_branches: Vec<(ast::Expression, Vec<ast::Statement>)> = vec![];
+        let mut elif_branches: Vec<(ast::Expr, Vec<ast::Stmt>)> = vec![];
+        let mut elif_ok = true;
         while self.peek_token() == &Token::KwElseIf {
             self.advance_tokens(); // consume 'elseif'
-            let elif_cond = self.parse_expression()?;
+            let elif_cond = self.parse_expression();
+            if elif_cond.is_none() {
+                self.skip_to(Token::KwThen);
+            }
             self.expect(Token::KwThen);
-            let mut elif_branch: Vec<ast::Statement> = vec![];
-            while self.peek_token() != &Token::KwElse
-                && self.peek_token() != &Token::KwElseIf
-                && self.peek_token() != &Token::KwEnd
-            {
-                if let Some(stmt) = self.parse_statement() {
-                    elif_branch.push(stmt);
-                } else {
-                    break;
-                }
+            let elif_branch = self.parse_block(&[Token::KwElse, Token::KwElseIf, Token::KwEnd]);
+            match elif_cond {
+                Some(elif_cond) => elif_branches.push((elif_cond, elif_branch)),
+                None => elif_ok = false,
             }
-            elif_branches.push((elif_cond, elif_branch));
         }
 
         let else_branch = if self.peek_token() == &Token::KwElse {
             self.advance_tokens(); // consume 'else'
-            let mut else_branch: Vec<ast::Statement> = vec![];
-            while self.peek_token() != &Token::KwEnd {
-                if let Some(stmt) = self.parse_statement() {
-                    else_branch.push(stmt);
-                } else {
-                    break;
-                }
-            }
-            Some(else_branch)
+            Some(self.parse_block(&[Token::KwEnd]))
         } else {
             None
         };
         self.expect(Token::KwEnd);
 
+        if !elif_ok {
+            return None;
+        }
         Some(ast::Statement::IfStmt {
-            condition: Box::new(cond),
+            condition: Box::new(cond?),
             then_branch,
             elif_branches,
             else_branch,
         })
     }
 
+    fn parse_do_statement(&mut self) -> Option<ast::Statement> {
+        self.expect(Token::KwDo);
+
+        let body = self.parse_block(&[Token::KwEnd]);
+        self.expect(Token::KwEnd);
+        Some(ast::Statement::DoStmt { body })
+    }
+
     fn parse_while_statement(&mut self) -> Option<ast::Statement> {
         self.expect(Token::KwWhile);
-        let condition = self.parse_expression()?;
+        let condition = self.parse_expression();
+        if condition.is_none() {
+            self.skip_to(Token::KwDo);
+        }
         self.expect(Token::KwDo);
 
-        let mut body: Vec<ast::Statement> = vec![];
-        while self.peek_token() != &Token::KwEnd {
-            if let Some(stmt) = self.parse_statement() {
-                body.push(stmt);
-            } else {
-                break;
-            }
-        }
+        let body = self.parse_block(&[Token::KwEnd]);
         self.expect(Token::KwEnd);
         Some(ast::Statement::WhileStmt {
-            condition: Box::new(condition),
+            condition: Box::new(condition?),
             body,
         })
     }
@@ -772,14 +1170,7 @@ impl Parser<'_> {
     fn parse_repeat_statement(&mut self) -> Option<ast::Statement> {
         self.expect(Token::KwRepeat);
 
-        let mut body: Vec<ast::Statement> = vec![];
-        while self.peek_token() != &Token::KwUntil {
-            if let Some(stmt) = self.parse_statement() {
-                body.push(stmt);
-            } else {
-                break;
-            }
-        }
+        let body = self.parse_block(&[Token::KwUntil]);
         self.expect(Token::KwUntil);
         let condition = self.parse_expression()?;
         Some(ast::Statement::RepeatStmt {
@@ -791,32 +1182,104 @@ impl Parser<'_> {
     fn parse_return_statement(&mut self) -> Option<ast::Statement> {
         self.expect(Token::KwReturn);
 
-        let mut values: Vec<ast::Expression> = vec![];
-        loop {
-            let expr = self.parse_expression();
-            if expr.is_none() {
-                // no more expressions
-                break;
-            }
-            let expr = expr.unwrap();
+        let mut values: Vec<ast::Expr> = vec![];
+        // a bare `return` ends its block
+        let ends_block = matches!(
+            self.peek_token(),
+            Token::KwEnd | Token::KwElse | Token::KwElseIf | Token::KwUntil | Token::Eof
+        );
+        if !ends_block {
+            loop {
+                let expr = self.parse_expression();
+                if expr.is_none() {
+                    // no more expressions
+                    break;
+                }
+                let expr = expr.unwrap();
 
-            values.push(expr);
-            if self.peek_token() == &Token::Comma {
-                self.advance_tokens(); // consume ','
-                continue;
-            } else {
-                break;
+                values.push(expr);
+                if self.peek_token() == &Token::Comma {
+                    self.advance_tokens(); // consume ','
+                    continue;
+                } else {
+                    break;
+                }
             }
         }
 
         Some(ast::Statement::ReturnStmt { values })
     }
 
-    fn parse_statement(&mut self) -> Option<ast::Statement> {
-        let next_tok = self.peek_token().clone();
-        match next_tok {
+    fn parse_expression_or_assignment_statement(&mut self) -> Option<ast::Statement> {
+        // the expression parser stops before '=', what it read may be the first target
+        let first = self.parse_binary_expression()?;
+        if self.peek_token() != &Token::Comma && self.peek_token() != &Token::Assign {
+            return Some(ast::Statement::ExprStatement(Box::new(first)));
+        }
+
+        let mut targets: Vec<ast::Expr> = vec![first];
+        while self.peek_token() == &Token::Comma {
+            self.advance_tokens(); // consume ','
+            targets.push(self.parse_binary_expression()?);
+        }
+
+        if !self.expect(Token::Assign) {
+            return None;
+        }
+        let values = self.parse_assigned_values()?;
+
+        Some(ast::Statement::Assignment { targets, values })
+    }
+
+    fn parse_goto_statement(&mut self) -> Option<ast::Statement> {
+        self.expect(Token::KwGoto);
+        match self.peek_token().clone() {
+            Token::Ident(label) => {
+                self.advance_tokens();
+                Some(ast::Statement::Goto { label })
+            }
+            _ => {
+                let msg = format!(
+                    "Expected label name after 'goto', found {:?}",
+                    self.peek_token()
+                );
+                self.emit_err(ParserErrorType::UnexpectedToken, msg);
+                None
+            }
+        }
+    }
+
+    fn parse_label_statement(&mut self) -> Option<ast::Statement> {
+        self.expect(Token::DoubleColon);
+        let name = match self.peek_token().clone() {
+            Token::Ident(name) => name,
+            _ => {
+                let msg = format!(
+                    "Expected label name after '::', found {:?}",
+                    self.peek_token()
+                );
+                self.emit_err(ParserErrorType::UnexpectedToken, msg);
+                return None;
+            }
+        };
+        self.advance_tokens();
+        if !self.expect(Token::DoubleColon) {
+            return None;
+        }
+        Some(ast::Statement::Label { name })
+    }
+
+    fn parse_statement(&mut self) -> Option<ast::Stmt> {
+        self.nested(Parser::parse_statement_impl)
+    }
+
+    fn parse_statement_impl(&mut self) -> Option<ast::Stmt> {
+        let line = self.next_token_line;
+        let start = self.next_token_pos;
+        let node = match self.peek_token().clone() {
             Token::KwLocal => self.parse_local_decl_statement(),
             Token::KwIf => self.parse_if_statement(),
+            Token::KwDo => self.parse_do_statement(),
             Token::KwWhile => self.parse_while_statement(),
             Token::KwRepeat => self.parse_repeat_statement(),
             Token::KwFunction => {
@@ -824,27 +1287,33 @@ impl Parser<'_> {
                 self.parse_function_decl_statement(false)
             }
             Token::KwReturn => self.parse_return_statement(),
+            Token::KwGoto => self.parse_goto_statement(),
+            Token::DoubleColon => self.parse_label_statement(),
+            Token::KwEnd | Token::KwElse | Token::KwElseIf | Token::KwUntil => {
+                // the enclosing block would have stopped at it
+                let msg = format!(
+                    "Unexpected {:?} without a matching block",
+                    self.peek_token()
+                );
+                self.emit_err(ParserErrorType::UnexpectedToken, msg);
+                None
+            }
             _ => {
-                // default is expression statement
-                self.parse_expression()
-                    .map(|expr| ast::Statement::ExprStatement(Box::new(expr)))
+                // default is expression statement, or an assignment
+                self.parse_expression_or_assignment_statement()
             }
-        }
+        }?;
+        Some(ast::Stmt {
+            line,
+            span: ast::Span::new(start, self.prev_token_end),
+            id: self.node_id(),
+            node,
+        })
     }
 
     fn parse_program(&mut self) -> ast::Program {
-        let mut body: Vec<ast::Statement> = vec![];
-        loop {
-            if self.peek_token() == &Token::Eof {
-                break;
-            }
-
-            if let Some(stmt) = self.parse_statement() {
-                body.push(stmt);
-            } else {
-                break;
-            }
-        }
+        // a stray `end` or `until` at the top level is reported and skipped like any other error
+        let body = self.parse_block(&[]);
         return ast::Program { body: body };
     }
 
@@ -0,0 +1,389 @@
+// Myula compiler AST printer
+//
+// Changelog:
+//      26-02-24: Initial version. `Program::to_source` / Display turn a syntax tree back into Lua that
+//                parses to the same tree, with parentheses only where operator precedence needs them
+
+use super::Parser;
+use super::ast::{BinOp, Expr, Expression, Literal, Program, Statement, Stmt, UnOp};
+use crate::frontend::lexer::Lexer;
+use std::fmt::{self, Write};
+
+const INDENT: &str = "    ";
+
+// binds tighter than every binary operator, the operand of a unary operator
+const UNARY_PREC: u8 = u8::MAX;
+
+impl Program {
+    /// Lua source for the program, parsing it again gives the same tree up to source positions
+    pub fn to_source(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Printer { out: f, depth: 0 }.block(&self.body)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Printer { out: f, depth: 0 }.expr(self, 0)
+    }
+}
+
+struct Printer<'a, W: Write> {
+    out: &'a mut W,
+    // blocks the current line is nested in
+    depth: usize,
+}
+
+fn binop_token(op: &BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        BinOp::Mod => "%",
+        BinOp::Pow => "^",
+        BinOp::Concat => "..",
+        BinOp::Eq => "==",
+        BinOp::Neq => "~=",
+        BinOp::Lt => "<",
+        BinOp::Gt => ">",
+        BinOp::Leq => "<=",
+        BinOp::Geq => ">=",
+        BinOp::And => "and",
+        BinOp::Or => "or",
+        BinOp::Assign => "=",
+    }
+}
+
+// a name the lexer reads back as one identifier, so `t.name` / `{name = v}` can be used for it
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| (c.is_alphabetic() || c == '_') && !c.is_ascii_digit())
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && Lexer::is_keyword(s).is_none()
+}
+
+// the lexer keeps escapes as written, so the text goes back between quotes untouched; a `"` that is
+// not escaped means it was written between single quotes
+fn quote(s: &str) -> String {
+    let mut escaped = false;
+    let mut bare_double = false;
+    for c in s.chars() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            bare_double = true;
+        }
+    }
+    let q = if bare_double { '\'' } else { '"' };
+    format!("{}{}{}", q, s, q)
+}
+
+fn number(n: f64) -> String {
+    if n.is_infinite() {
+        // the literal that overflowed to it
+        return "1e999".to_string();
+    }
+    // Debug keeps a fraction or exponent, so it lexes as a float again
+    format!("{:?}", n)
+}
+
+impl<W: Write> Printer<'_, W> {
+    fn indent(&mut self) -> fmt::Result {
+        for _ in 0..self.depth {
+            self.out.write_str(INDENT)?;
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, body: &[Stmt]) -> fmt::Result {
+        for stmt in body {
+            self.indent()?;
+            self.stmt(&stmt.node)?;
+            self.out.write_char('\n')?;
+        }
+        Ok(())
+    }
+
+    // the statements one level deeper, then the indentation of the line that closes them
+    fn nested_block(&mut self, body: &[Stmt]) -> fmt::Result {
+        self.out.write_char('\n')?;
+        self.depth += 1;
+        self.block(body)?;
+        self.depth -= 1;
+        self.indent()
+    }
+
+    fn list(&mut self, exprs: &[Expr]) -> fmt::Result {
+        for (i, expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.out.write_str(", ")?;
+            }
+            self.expr(expr, 0)?;
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &Statement) -> fmt::Result {
+        match stmt {
+            Statement::ExprStatement(expr) => match &expr.node {
+                // `function a.b:m() end` is parsed into this assignment
+                Expression::BinOp {
+                    operator: BinOp::Assign,
+                    right,
+                    ..
+                } if matches!(
+                    &right.node,
+                    Expression::Literal(Literal::Function { name: Some(_), .. })
+                ) =>
+                {
+                    self.out.write_str("function ")?;
+                    self.function(right)
+                }
+                // statements start below `=`, any other assignment expression needs parentheses
+                _ => self.expr(expr, 1),
+            },
+            Statement::Declaration { names, values, .. } => {
+                if let ([name], [func]) = (names.as_slice(), values.as_slice())
+                    && let Expression::Literal(Literal::Function { name: Some(n), .. }) = &func.node
+                    && n == name
+                {
+                    self.out.write_str("local function ")?;
+                    return self.function(func);
+                }
+                write!(self.out, "local {}", names.join(", "))?;
+                // `local a` is parsed with a nil for every name
+                if values
+                    .iter()
+                    .all(|v| matches!(v.node, Expression::Literal(Literal::Nil)))
+                    && values.len() == names.len()
+                {
+                    return Ok(());
+                }
+                self.out.write_str(" = ")?;
+                self.list(values)
+            }
+            Statement::Assignment { targets, values } => {
+                for (i, target) in targets.iter().enumerate() {
+                    if i > 0 {
+                        self.out.write_str(", ")?;
+                    }
+                    self.expr(target, 1)?;
+                }
+                self.out.write_str(" = ")?;
+                self.list(values)
+            }
+            Statement::IfStmt {
+                condition,
+                then_branch,
+                elif_branches,
+                else_branch,
+            } => {
+                self.out.write_str("if ")?;
+                self.expr(condition, 0)?;
+                self.out.write_str(" then")?;
+                self.nested_block(then_branch)?;
+                for (condition, body) in elif_branches {
+                    self.out.write_str("elseif ")?;
+                    self.expr(condition, 0)?;
+                    self.out.write_str(" then")?;
+                    self.nested_block(body)?;
+                }
+                if let Some(body) = else_branch {
+                    self.out.write_str("else")?;
+                    self.nested_block(body)?;
+                }
+                self.out.write_str("end")
+            }
+            Statement::DoStmt { body } => {
+                self.out.write_str("do")?;
+                self.nested_block(body)?;
+                self.out.write_str("end")
+            }
+            Statement::WhileStmt { condition, body } => {
+                self.out.write_str("while ")?;
+                self.expr(condition, 0)?;
+                self.out.write_str(" do")?;
+                self.nested_block(body)?;
+                self.out.write_str("end")
+            }
+            Statement::RepeatStmt { body, condition } => {
+                self.out.write_str("repeat")?;
+                self.nested_block(body)?;
+                self.out.write_str("until ")?;
+                self.expr(condition, 0)
+            }
+            Statement::ReturnStmt { values } => {
+                self.out.write_str("return")?;
+                if !values.is_empty() {
+                    self.out.write_char(' ')?;
+                    self.list(values)?;
+                }
+                Ok(())
+            }
+            Statement::Goto { label } => write!(self.out, "goto {}", label),
+            Statement::Label { name } => write!(self.out, "::{}::", name),
+        }
+    }
+
+    // name, parameters and body of a function literal, after the `function` keyword
+    fn function(&mut self, func: &Expr) -> fmt::Result {
+        let Expression::Literal(Literal::Function {
+            params, body, name, ..
+        }) = &func.node
+        else {
+            return self.expr(func, 0);
+        };
+        let mut params = params.as_slice();
+        if let Some(name) = name {
+            self.out.write_str(name)?;
+            // the method form declares its `self` implicitly
+            if name.contains(':') && params.first().is_some_and(|p| p == "self") {
+                params = &params[1..];
+            }
+        }
+        write!(self.out, "({})", params.join(", "))?;
+        self.nested_block(body)?;
+        self.out.write_str("end")
+    }
+
+    // an expression where anything binding looser than `min_prec` has to be parenthesized
+    fn expr(&mut self, expr: &Expr, min_prec: u8) -> fmt::Result {
+        match &expr.node {
+            Expression::Identifier(name) => self.out.write_str(name),
+            Expression::Literal(lit) => self.literal(lit),
+            Expression::BinOp {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                let prec = Parser::binop_precedence(operator).unwrap_or(0);
+                if prec < min_prec {
+                    self.out.write_char('(')?;
+                    self.expr(expr, 0)?;
+                    return self.out.write_char(')');
+                }
+                // the side an operator associates to may hold the same operator without parentheses
+                let (left_prec, right_prec) = if Parser::is_binop_right_assoc(operator) {
+                    (prec + 1, prec)
+                } else {
+                    (prec, prec + 1)
+                };
+                self.expr(left, left_prec)?;
+                write!(self.out, " {} ", binop_token(operator))?;
+                self.expr(right, right_prec)
+            }
+            Expression::UnOp { operator, operand } => {
+                let mut text = String::new();
+                Printer {
+                    out: &mut text,
+                    depth: self.depth,
+                }
+                .expr(operand, UNARY_PREC)?;
+                let op = match operator {
+                    UnOp::Pos => "+",
+                    UnOp::Neg => "-",
+                    UnOp::Not => "not ",
+                    UnOp::TblLen => "#",
+                };
+                self.out.write_str(op)?;
+                // `- -x` is not a comment
+                if op == "-" && text.starts_with('-') {
+                    self.out.write_char(' ')?;
+                }
+                self.out.write_str(&text)
+            }
+            Expression::FnCall { callee, arguments } => {
+                self.prefix(callee)?;
+                self.out.write_char('(')?;
+                self.list(arguments)?;
+                self.out.write_char(')')
+            }
+            Expression::MethodCall {
+                object,
+                method,
+                arguments,
+            } => {
+                self.prefix(object)?;
+                write!(self.out, ":{}(", method)?;
+                self.list(arguments)?;
+                self.out.write_char(')')
+            }
+            Expression::IndexOf { collection, index } => {
+                self.prefix(collection)?;
+                self.out.write_char('[')?;
+                self.expr(index, 0)?;
+                self.out.write_char(']')
+            }
+            Expression::MemberAccess { collection, member } => {
+                self.prefix(collection)?;
+                write!(self.out, ".{}", member)
+            }
+            Expression::TableCtor { fields } => {
+                self.out.write_char('{')?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        self.out.write_str(", ")?;
+                    }
+                    match key {
+                        Some(Expr {
+                            node: Expression::Literal(Literal::String(s)),
+                            ..
+                        }) if is_name(s) => write!(self.out, "{} = ", s)?,
+                        Some(key) => {
+                            self.out.write_char('[')?;
+                            self.expr(key, 0)?;
+                            self.out.write_str("] = ")?;
+                        }
+                        None => {}
+                    }
+                    self.expr(value, 0)?;
+                }
+                self.out.write_char('}')
+            }
+        }
+    }
+
+    // what a call, index or member access applies to, Lua only allows a name or another
+    // postfix expression there without parentheses
+    fn prefix(&mut self, expr: &Expr) -> fmt::Result {
+        match &expr.node {
+            Expression::Identifier(_)
+            | Expression::FnCall { .. }
+            | Expression::MethodCall { .. }
+            | Expression::IndexOf { .. }
+            | Expression::MemberAccess { .. } => self.expr(expr, 0),
+            _ => {
+                self.out.write_char('(')?;
+                self.expr(expr, 0)?;
+                self.out.write_char(')')
+            }
+        }
+    }
+
+    fn literal(&mut self, lit: &Literal) -> fmt::Result {
+        match lit {
+            Literal::Number(n) => self.out.write_str(&number(*n)),
+            Literal::Integer(i) => write!(self.out, "{}", i),
+            Literal::String(s) => self.out.write_str(&quote(s)),
+            Literal::Boolean(b) => write!(self.out, "{}", b),
+            Literal::Nil => self.out.write_str("nil"),
+            Literal::Function { params, body, .. } => {
+                // the name of a function statement is only printed by the statement itself
+                write!(self.out, "function({})", params.join(", "))?;
+                self.nested_block(body)?;
+                self.out.write_str("end")
+            }
+        }
+    }
+}
@@ -1,3 +1,10 @@
 pub mod backend;
 pub mod common;
+pub mod debugger;
+pub mod engine;
 pub mod frontend;
+pub mod lsp;
+pub mod repl;
+pub mod trace;
+
+pub use engine::{Chunk, EngineError, Myula, Value};
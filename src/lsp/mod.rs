@@ -0,0 +1,569 @@
+// Myula language server
+// Changelog:
+// 2026-02-24: Initial version. A Language Server Protocol server over stdio (`myulac --lsp`): open documents
+//            are parsed and lowered to IR on every change and their errors published as diagnostics;
+//            document symbols are the functions of the IR module, go-to-definition follows a local's slot
+//            (and an upvalue's capture) back to the name declaring it, hover shows the type the Scanner
+//            inferred for that slot
+
+pub mod json;
+
+use crate::backend::translator::scanner::{Scanner, VarKind};
+use crate::frontend::diagnostics::Diagnostics;
+use crate::frontend::ir::{
+    IRFunction, IRGenerator, IRInstruction, IRModule, IROperand, IRUpValType,
+};
+use crate::frontend::lexer::Lexer;
+use crate::frontend::parser::Parser;
+use crate::frontend::parser::ast::Span;
+use json::Json;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+// JSON-RPC error codes: a message that is not JSON, a request the server does not implement
+const PARSE_ERROR: i32 = -32700;
+const METHOD_NOT_FOUND: i32 = -32601;
+// LSP SymbolKind
+const SYMBOL_METHOD: usize = 6;
+const SYMBOL_FUNCTION: usize = 12;
+// LSP DiagnosticSeverity
+const SEVERITY_ERROR: usize = 1;
+// LSP TextDocumentSyncKind, every change sends the whole document
+const SYNC_FULL: usize = 1;
+
+struct Document {
+    text: String,
+    // None while the document has syntax errors, the IR of a tree the parser recovered
+    // is not worth navigating
+    analysis: Option<Analysis>,
+}
+
+struct Analysis {
+    ir_gen: IRGenerator,
+    scanner: Scanner,
+}
+
+impl Analysis {
+    fn module(&self) -> &IRModule {
+        self.ir_gen.get_module()
+    }
+}
+
+#[derive(Default)]
+pub struct LanguageServer {
+    // uri -> open document
+    documents: HashMap<String, Document>,
+    shutdown_requested: bool,
+    exited: bool,
+}
+
+impl LanguageServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// true once the client sent `exit`
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// the exit status `exit` asks for, 1 if the client did not shut the server down first
+    pub fn exit_code(&self) -> i32 {
+        if self.shutdown_requested { 0 } else { 1 }
+    }
+
+    /// serve the messages read from `input` until `exit` or the end of the input
+    pub fn run<R: BufRead, W: Write>(&mut self, mut input: R, mut out: W) -> io::Result<()> {
+        while !self.exited {
+            let Some(body) = read_message(&mut input)? else {
+                break;
+            };
+            let replies = match Json::parse(&body) {
+                Some(message) => self.handle(&message),
+                // there is no id to answer to
+                None => vec![Json::object([
+                    ("jsonrpc", "2.0".into()),
+                    ("id", Json::Null),
+                    (
+                        "error",
+                        Json::object([
+                            ("code", Json::Number(PARSE_ERROR as f64)),
+                            ("message", "invalid JSON".into()),
+                        ]),
+                    ),
+                ])],
+            };
+            for reply in &replies {
+                write_message(&mut out, reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// answer one message, returns the responses and notifications to send back
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").as_str().unwrap_or("");
+        let params = message.get("params");
+        let id = message.get("id");
+        // a notification has no id and gets no response
+        if id.is_null() {
+            return self.notification(method, params);
+        }
+        let result = match method {
+            "initialize" => Ok(Json::object([
+                (
+                    "capabilities",
+                    Json::object([
+                        ("textDocumentSync", SYNC_FULL.into()),
+                        ("documentSymbolProvider", true.into()),
+                        ("definitionProvider", true.into()),
+                        ("hoverProvider", true.into()),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    Json::object([("name", "myula".into()), ("version", "1.0".into())]),
+                ),
+            ])),
+            "shutdown" => {
+                self.shutdown_requested = true;
+                Ok(Json::Null)
+            }
+            "textDocument/documentSymbol" => Ok(self.document_symbols(params)),
+            "textDocument/definition" => Ok(self.definition(params)),
+            "textDocument/hover" => Ok(self.hover(params)),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method '{}'", method))),
+        };
+        let outcome = match result {
+            Ok(result) => ("result", result),
+            Err((code, message)) => (
+                "error",
+                Json::object([
+                    ("code", Json::Number(code as f64)),
+                    ("message", message.into()),
+                ]),
+            ),
+        };
+        vec![Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", id.clone()),
+            outcome,
+        ])]
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params.get("textDocument").get("uri").as_str();
+        match (method, uri) {
+            ("exit", _) => {
+                self.exited = true;
+                Vec::new()
+            }
+            ("textDocument/didOpen", Some(uri)) => {
+                let text = params
+                    .get("textDocument")
+                    .get("text")
+                    .as_str()
+                    .unwrap_or("");
+                vec![self.update(uri, text.to_string())]
+            }
+            // full sync, the last change holds the whole text
+            ("textDocument/didChange", Some(uri)) => {
+                match params
+                    .get("contentChanges")
+                    .as_array()
+                    .last()
+                    .and_then(|change| change.get("text").as_str())
+                {
+                    Some(text) => vec![self.update(uri, text.to_string())],
+                    None => Vec::new(),
+                }
+            }
+            // the client forgets the diagnostics of a closed document only when told so
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(uri);
+                vec![publish_diagnostics(uri, Vec::new())]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // analyze the new text of a document, returns the publishDiagnostics notification for it
+    fn update(&mut self, uri: &str, text: String) -> Json {
+        let (diagnostics, analysis) = analyze(&text);
+        let lines = LineIndex::new(&text);
+        let items = diagnostics
+            .iter()
+            .map(|d| {
+                Json::object([
+                    ("range", lines.line_range(d.line)),
+                    ("severity", SEVERITY_ERROR.into()),
+                    ("source", "myula".into()),
+                    ("message", d.message.clone().into()),
+                ])
+            })
+            .collect();
+        self.documents
+            .insert(uri.to_string(), Document { text, analysis });
+        publish_diagnostics(uri, items)
+    }
+
+    // the document a request is about, with its analysis
+    fn document(&self, params: &Json) -> Option<(&str, &Document, &Analysis)> {
+        let uri = params.get("textDocument").get("uri").as_str()?;
+        let (uri, doc) = self.documents.get_key_value(uri)?;
+        Some((uri, doc, doc.analysis.as_ref()?))
+    }
+
+    fn document_symbols(&self, params: &Json) -> Json {
+        let Some((_, doc, analysis)) = self.document(params) else {
+            return Json::Array(Vec::new());
+        };
+        let module = analysis.module();
+        let lines = LineIndex::new(&doc.text);
+        match module.functions.iter().find(|f| f.name == "_start") {
+            Some(start) => Json::Array(function_symbols(module, start, &lines)),
+            None => Json::Array(Vec::new()),
+        }
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        let Some((uri, doc, analysis)) = self.document(params) else {
+            return Json::Null;
+        };
+        let lines = LineIndex::new(&doc.text);
+        let Some(offset) = lines.offset(params.get("position")) else {
+            return Json::Null;
+        };
+        match local_at(analysis.module(), &doc.text, offset) {
+            Some(local) => Json::object([
+                ("uri", uri.into()),
+                ("range", lines.range(local.declared_at())),
+            ]),
+            None => Json::Null,
+        }
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let Some((_, doc, analysis)) = self.document(params) else {
+            return Json::Null;
+        };
+        let lines = LineIndex::new(&doc.text);
+        let Some(offset) = lines.offset(params.get("position")) else {
+            return Json::Null;
+        };
+        let Some(local) = local_at(analysis.module(), &doc.text, offset) else {
+            return Json::Null;
+        };
+        let ty = analysis
+            .scanner
+            .lifetimes
+            .get(&(local.func.name.clone(), VarKind::Slot(local.slot)))
+            .and_then(|lt| lt.inferred_type.as_deref())
+            .unwrap_or("Dynamic");
+        let kind = if local.slot < local.func.params.len() {
+            "parameter"
+        } else {
+            "local"
+        };
+        Json::object([
+            (
+                "contents",
+                Json::object([
+                    ("kind", "plaintext".into()),
+                    ("value", format!("{} {}: {}", kind, local.name, ty).into()),
+                ]),
+            ),
+            ("range", lines.range(local.found_at)),
+        ])
+    }
+}
+
+// the errors of the lexer, the parser and the IR generator, and the IR and scanner results when
+// the source parses
+fn analyze(text: &str) -> (Diagnostics, Option<Analysis>) {
+    let mut diagnostics = Diagnostics::new();
+    let mut lexer = Lexer::new(text);
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+    diagnostics.add_parse(&parser);
+    if !diagnostics.is_empty() {
+        return (diagnostics, None);
+    }
+
+    // without the optimizations every use of a local keeps its load and its span
+    let mut ir_gen = IRGenerator::new()
+        .with_const_fold(false)
+        .with_cse(false)
+        .with_jump_tables(false)
+        .with_licm(false)
+        .with_dce(false);
+    ir_gen.generate(&program);
+    diagnostics.add_ir(&ir_gen);
+    let mut scanner = Scanner::new();
+    scanner.global_scan(ir_gen.get_module());
+    (diagnostics, Some(Analysis { ir_gen, scanner }))
+}
+
+fn publish_diagnostics(uri: &str, items: Vec<Json>) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        (
+            "params",
+            Json::object([("uri", uri.into()), ("diagnostics", Json::Array(items))]),
+        ),
+    ])
+}
+
+// source name of a function prototype: `foo` for `__local_fn_foo_3`, None for an anonymous function
+fn function_name(proto: &str) -> Option<&str> {
+    let mangled = proto.strip_prefix("__local_fn_")?;
+    mangled.rsplit_once('_').map(|(name, _)| name)
+}
+
+// DocumentSymbols for the functions defined in `func`, each with its own nested functions as children;
+// a function's range is the span of the FnProto creating it
+fn function_symbols(module: &IRModule, func: &IRFunction, lines: &LineIndex) -> Vec<Json> {
+    let mut symbols = Vec::new();
+    for block in &func.basic_blocks {
+        for (inst, span) in block.instructions.iter().zip(&block.spans) {
+            let IRInstruction::FnProto {
+                func_proto: IROperand::Proto(proto),
+                ..
+            } = inst
+            else {
+                continue;
+            };
+            let Some(child) = module.functions.iter().find(|f| &f.name == proto) else {
+                continue;
+            };
+            let name = function_name(proto).unwrap_or("<anonymous>");
+            let kind = if name.contains(':') {
+                SYMBOL_METHOD
+            } else {
+                SYMBOL_FUNCTION
+            };
+            symbols.push(Json::object([
+                ("name", name.into()),
+                (
+                    "detail",
+                    format!("function({})", child.params.join(", ")).into(),
+                ),
+                ("kind", kind.into()),
+                ("range", lines.range(*span)),
+                ("selectionRange", lines.range(*span)),
+                (
+                    "children",
+                    Json::Array(function_symbols(module, child, lines)),
+                ),
+            ]));
+        }
+    }
+    symbols
+}
+
+// a local variable found under the cursor, resolved to the function and slot declaring it
+struct LocalRef<'a> {
+    func: &'a IRFunction,
+    slot: usize,
+    name: &'a str,
+    // span of the name the cursor is on
+    found_at: Span,
+}
+
+impl LocalRef<'_> {
+    fn declared_at(&self) -> Span {
+        let pos = self.func.local_positions[self.slot];
+        Span::new(pos, pos + self.name.len())
+    }
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    // the cursor right after a name is still on it
+    span.start <= offset && offset <= span.end
+}
+
+// the local whose declaring name or use is at `offset`
+fn local_at<'a>(module: &'a IRModule, text: &str, offset: usize) -> Option<LocalRef<'a>> {
+    // a declaration, the implicit `self` of a method shares its position with the method name
+    // and is skipped by comparing the text
+    for func in &module.functions {
+        for (slot, &pos) in func.local_positions.iter().enumerate() {
+            let Some(name) = func.local_name(slot) else {
+                continue;
+            };
+            let span = Span::new(pos, pos + name.len());
+            if contains(span, offset) && text.get(span.start..span.end) == Some(name) {
+                return Some(LocalRef {
+                    func,
+                    slot,
+                    name,
+                    found_at: span,
+                });
+            }
+        }
+    }
+
+    // a use: the innermost load or store of a variable whose span is just its name,
+    // a store to a local spans the whole assignment
+    let mut best: Option<LocalRef> = None;
+    for func in &module.functions {
+        for block in &func.basic_blocks {
+            for (inst, &span) in block.instructions.iter().zip(&block.spans) {
+                let var = match inst {
+                    IRInstruction::LoadLocal { src, .. } | IRInstruction::LoadUpVal { src, .. } => {
+                        src
+                    }
+                    IRInstruction::StoreLocal { dst, .. }
+                    | IRInstruction::StoreUpVal { dst, .. } => dst,
+                    _ => continue,
+                };
+                if !contains(span, offset)
+                    || best
+                        .as_ref()
+                        .is_some_and(|b| b.found_at.end - b.found_at.start <= span.end - span.start)
+                {
+                    continue;
+                }
+                let (func, slot) = match var {
+                    IROperand::Slot(slot) => (func, *slot),
+                    IROperand::UpVal(index) => match captured_slot(module, func, *index) {
+                        Some(local) => local,
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+                if let Some(name) = func.local_name(slot)
+                    && text.get(span.start..span.end) == Some(name)
+                {
+                    best = Some(LocalRef {
+                        func,
+                        slot,
+                        name,
+                        found_at: span,
+                    });
+                }
+            }
+        }
+    }
+    best
+}
+
+// the function and slot of the local an upvalue of `func` captures, through as many
+// enclosing functions as it was passed down
+fn captured_slot<'a>(
+    module: &'a IRModule,
+    func: &'a IRFunction,
+    index: usize,
+) -> Option<(&'a IRFunction, usize)> {
+    let upval = func.upvalues.values().find(|u| u.slot == index)?;
+    let parent = module
+        .functions
+        .iter()
+        .find(|f| f.sub_functions.contains(&func.name))?;
+    match upval.ty {
+        IRUpValType::LocalVar(slot) => Some((parent, slot)),
+        IRUpValType::UpVal(index) => captured_slot(module, parent, index),
+    }
+}
+
+// converts between byte offsets and LSP positions: zero based lines, characters counted in UTF-16 units
+struct LineIndex<'a> {
+    text: &'a str,
+    // byte offset each line starts at
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(text: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { text, starts }
+    }
+
+    fn line_text(&self, line: usize) -> &'a str {
+        let start = self.starts[line];
+        let end = self
+            .starts
+            .get(line + 1)
+            .map_or(self.text.len(), |&s| s - 1);
+        self.text[start..end].trim_end_matches('\r')
+    }
+
+    fn position(&self, offset: usize) -> Json {
+        let offset = offset.min(self.text.len());
+        let line = self.starts.partition_point(|&s| s <= offset) - 1;
+        let before = self.text.get(self.starts[line]..offset).unwrap_or("");
+        Json::object([
+            ("line", line.into()),
+            ("character", before.encode_utf16().count().into()),
+        ])
+    }
+
+    fn offset(&self, position: &Json) -> Option<usize> {
+        let line = position.get("line").as_usize()?;
+        let mut units = position.get("character").as_usize()?;
+        let start = *self.starts.get(line)?;
+        let mut offset = start;
+        for c in self.line_text(line).chars() {
+            if units < c.len_utf16() {
+                break;
+            }
+            units -= c.len_utf16();
+            offset += c.len_utf8();
+        }
+        Some(offset)
+    }
+
+    fn range(&self, span: Span) -> Json {
+        Json::object([
+            ("start", self.position(span.start)),
+            ("end", self.position(span.end)),
+        ])
+    }
+
+    // the whole of a one based source line, where a diagnostic without a column points
+    fn line_range(&self, line: usize) -> Json {
+        let line = line.saturating_sub(1).min(self.starts.len() - 1);
+        let start = self.starts[line];
+        self.range(Span::new(start, start + self.line_text(line).len()))
+    }
+}
+
+// one message body, None at the end of the input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without a Content-Length header",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn write_message(out: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
@@ -1,9 +1,25 @@
 use clap::{Parser, ValueEnum};
-use myula::backend::translator::scanner::{Scanner, VarKind};
+use myula::Myula;
+use myula::backend::deserializer::{MYB_MAGIC, deserialize_module, serialize_module};
+use myula::backend::disasm::{disassemble_module, module_to_json};
+use myula::backend::translator::emitter::EmitError;
+use myula::backend::translator::scanner::Scanner;
+use myula::backend::vm::FuncMetadata;
+use myula::backend::vm::config::VmConfig;
+use myula::backend::vm::error::TRACEBACK_MAX_LINES;
+use myula::backend::vm::heap::GcMode;
 use myula::backend::vm::{LogLevel, VirtualMachine};
+use myula::debugger::Debugger;
+use myula::frontend::diagnostics::{Diagnostics, EXIT_COMPILE_ERROR};
 use myula::frontend::lexer::Lexer;
+use myula::lsp::LanguageServer;
+use myula::repl::Repl;
+use myula::trace::TraceCollector;
+use std::collections::HashMap;
 use std::fs;
+use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 #[derive(Parser)]
 #[command(name = "myulac")]
@@ -11,92 +27,580 @@ use std::path::{Path, PathBuf};
 #[command(author = "Yuyang Feng && Zimeng Li")]
 #[command(about = "Myula: A high-performance unified Lua compiler and VM", long_about = None)]
 struct Cli {
-    input: PathBuf,
+    #[arg(required_unless_present_any = ["repl", "lsp"])]
+    input: Option<PathBuf>,
 
     #[arg(short, long, value_enum, default_value_t = LogLevel::Release)]
     mode: LogLevel,
-}
 
-struct TraceGuard<'a> {
-    mode: LogLevel,
-    ir_gen: &'a myula::frontend::ir::IRGenerator,
-    scanner: &'a Scanner,
-    vm_ptr: *const VirtualMachine,
+    /// print every frame of a runtime error traceback instead of a folded summary
+    #[arg(long, conflicts_with = "traceback_lines")]
+    full_traceback: bool,
+
+    /// lines a runtime error traceback is cut down to by dropping lines from its middle (default 22)
+    #[arg(long, value_name = "LINES")]
+    traceback_lines: Option<usize>,
+
+    /// warn about locals and parameters shadowing another variable of the same name
+    #[arg(long)]
+    warn_shadow: bool,
+
+    /// seed math.random with a fixed value so runs are reproducible
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// garbage collector: full mark-and-sweep, or generational with frequent young-only collections
+    #[arg(long, value_enum, default_value_t = GcMode::Full)]
+    gc: GcMode,
+
+    /// collect garbage after every allocation (slow), to find values the collector fails to see
+    #[arg(long)]
+    gc_stress: bool,
+
+    /// call depth at which a StackOverflowError is raised (default 1000)
+    #[arg(long, value_name = "FRAMES")]
+    max_stack: Option<usize>,
+
+    /// largest heap the script may use, e.g. 2G or 64M; beyond it allocations raise an OutOfMemoryError
+    /// (default 512M)
+    #[arg(long, value_name = "SIZE", value_parser = parse_heap_size)]
+    max_heap: Option<usize>,
+
+    /// heap size that triggers the first full collection, e.g. 16M (default 1M)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    gc_threshold: Option<usize>,
+
+    /// compile to a bytecode image at this path instead of running, `myulac out.myb` runs it later;
+    /// with --emit the dump is written here instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// start an interactive session instead of running a file
+    #[arg(long, conflicts_with = "output")]
+    repl: bool,
+
+    /// serve the Language Server Protocol on stdin/stdout for an editor: diagnostics, document symbols,
+    /// go-to-definition and hover for locals
+    #[arg(long, conflicts_with_all = ["input", "repl", "output"])]
+    lsp: bool,
+
+    /// stop after a phase and dump its result instead of running: the syntax tree, the syntax tree
+    /// printed back as Lua source, the IR, or the compiled bytecode (a listing, or JSON for tools)
+    #[arg(long, value_enum, conflicts_with = "repl")]
+    emit: Option<Emit>,
+
+    /// only check the program: report every lexer, syntax and compile error (and warning) without
+    /// running it; the exit status is 0 for a program that would run, 1 for syntax and 2 for compile errors
+    #[arg(long, conflicts_with_all = ["repl", "emit", "output", "force", "time", "profile", "debug", "trace_out"])]
+    check: bool,
+
+    /// report lexer, syntax and compile errors but go on anyway; the program that runs is
+    /// whatever the compiler recovered
+    #[arg(long)]
+    force: bool,
+
+    /// report on stderr how long parsing, IR generation, emitting (register allocation, bytecode
+    /// and linking) and the run took, with the instructions executed and the GC cycles
+    #[arg(long, conflicts_with = "repl")]
+    time: bool,
+
+    /// count the instructions, calls and allocated bytes of every function and time every opcode
+    /// kind, the sorted report is printed on stderr when the program ends
+    #[arg(long, conflicts_with = "repl")]
+    profile: bool,
+
+    /// step through the program instruction by instruction: breakpoints at a function and pc,
+    /// registers, the call stack and the value stack; commands are read from stdin (`help` lists them)
+    #[arg(long, conflicts_with = "repl")]
+    debug: bool,
+
+    /// write what the compiler and VM did as JSON to this path: the IR, the register allocation,
+    /// the bytecode, the final VM state and the phase timings as Chrome trace events
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["repl", "emit", "output"])]
+    trace_out: Option<PathBuf>,
 }
 
-impl<'a> Drop for TraceGuard<'a> {
-    fn drop(&mut self) {
-        if self.mode == LogLevel::Trace {
-            let vm_ref = unsafe { &*self.vm_ptr };
-
-            println!(
-                "\n{:^105}",
-                "*************************************************************************"
-            );
-            println!("{:^105}", "MYULA COMPILER DIAGNOSTIC TRACE (AUTO-DUMP)");
-            println!(
-                "{:^105}",
-                "*************************************************************************"
-            );
-
-            print_ir_report(self.ir_gen);
-            print_scanner_report(self.scanner);
-            print_emitter_report(vm_ref);
-
-            println!("\n{:^105}\n", "--- END OF TRACE DATA ---");
-        }
-    }
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Emit {
+    Ast,
+    Source,
+    Ir,
+    Bytecode,
+    BytecodeJson,
 }
 
 fn main() {
     let cli = Cli::parse();
-    let file_path = &cli.input;
+    if cli.repl {
+        run_repl(&cli);
+        return;
+    }
+    if cli.lsp {
+        run_lsp();
+    }
+    let file_path = cli.input.as_ref().unwrap();
 
     if !file_path.exists() {
         eprintln!("[Error] Source file not found: {}", file_path.display());
         std::process::exit(1);
     }
 
-    let source = fs::read_to_string(file_path).expect(&format!(
-        "Critical: Failed to read source file at {}",
-        file_path.display()
-    ));
+    let mut timer = PhaseTimer::new(&cli);
+    if is_bytecode_image(file_path) {
+        if cli.check {
+            check_precompiled(&cli);
+            return;
+        }
+        run_precompiled(&cli, timer);
+        return;
+    }
 
-    if cli.mode != LogLevel::Release {
+    // the source is streamed through the lexer instead of being read into memory up front
+    let file = fs::File::open(file_path).unwrap_or_else(|_| {
+        panic!(
+            "Critical: Failed to read source file at {}",
+            file_path.display()
+        )
+    });
+
+    if cli.mode != LogLevel::Release && cli.emit.is_none() {
         println!("[Myula] Compiling: {}", file_path.display());
     }
 
-    let mut lexer = Lexer::new(&source);
+    let mut lexer = Lexer::from_read(file);
     let mut parser = myula::frontend::parser::Parser::new(&mut lexer);
     let program = parser.parse();
+    timer.lap("parse");
+    // the parser recovers after each error, so this is every syntax error in the file
+    let mut diagnostics = Diagnostics::new();
+    diagnostics.add_parse(&parser);
+    check_diagnostics(&cli, &diagnostics);
+    if cli.emit == Some(Emit::Ast) {
+        write_emitted(&cli, format!("{:#?}\n", program));
+        return;
+    }
+    if cli.emit == Some(Emit::Source) {
+        write_emitted(&cli, program.to_source());
+        return;
+    }
 
-    let mut ir_gen = myula::frontend::ir::IRGenerator::new();
+    let mut ir_gen = myula::frontend::ir::IRGenerator::new().with_warn_shadow(cli.warn_shadow);
     ir_gen.generate(&program);
+    timer.lap("ir");
+    if let Some(trace) = &mut timer.trace {
+        trace.record_ir(&ir_gen);
+    }
+    // e.g. a goto without a matching label, the bytecode would jump nowhere
+    let mut diagnostics = Diagnostics::new();
+    diagnostics.add_ir(&ir_gen);
+    check_diagnostics(&cli, &diagnostics);
+    let warnings = ir_gen.get_warnings();
+    let const_warnings = ir_gen.get_const_warnings();
+    if !warnings.is_empty() || !const_warnings.is_empty() {
+        // only needed to turn the warning offsets into line:col
+        let source = fs::read_to_string(file_path).unwrap_or_else(|_| {
+            panic!(
+                "Critical: Failed to read source file at {}",
+                file_path.display()
+            )
+        });
+        for warning in warnings {
+            eprintln!("[Warning] {}", warning.describe(&source));
+        }
+        for warning in const_warnings {
+            eprintln!("[Warning] {}", warning.describe(&source));
+        }
+    }
 
+    // the errors would have ended the process above, no VM is needed to know the program runs
+    if cli.check {
+        return;
+    }
+
+    if cli.emit == Some(Emit::Ir) {
+        write_emitted(&cli, format!("{}\n", ir_gen.get_module().to_string()));
+        return;
+    }
+
+    timer.skip();
     let mut scanner = Scanner::new();
     scanner.global_scan(&ir_gen.get_module());
 
-    let mut vm = VirtualMachine::new();
-    vm.init(&ir_gen, cli.mode, &mut scanner);
+    if let Some(emit) = cli.emit {
+        // the same bytecode the VM would run in this mode
+        let debug_info = cli.mode != LogLevel::Release;
+        let funcs = check_emit(
+            &cli,
+            VirtualMachine::compile(&ir_gen, &mut scanner, debug_info),
+        );
+        timer.lap("emit");
+        write_emitted(&cli, render_bytecode(&funcs, emit));
+        return;
+    }
 
-    let _guard = TraceGuard {
-        mode: cli.mode,
-        ir_gen: &ir_gen,
-        scanner: &scanner,
-        vm_ptr: &vm as *const VirtualMachine,
+    if let Some(out_path) = &cli.output {
+        let funcs = check_emit(&cli, VirtualMachine::compile(&ir_gen, &mut scanner, false));
+        timer.lap("emit");
+        if let Err(e) = fs::write(out_path, serialize_module(&funcs)) {
+            eprintln!("[Error] Failed to write {}: {}", out_path.display(), e);
+            std::process::exit(1);
+        }
+        if cli.mode != LogLevel::Release {
+            println!("[Myula] Wrote bytecode image: {}", out_path.display());
+        }
+        return;
+    }
+
+    let mut vm = VirtualMachine::with_config(vm_config(&cli));
+    vm.chunk_name = file_path.display().to_string().into();
+    vm.traceback_limit = traceback_limit(&cli);
+    vm.set_gc_mode(cli.gc);
+    vm.heap.stress = cli.gc_stress;
+    if cli.seed.is_some() {
+        vm.set_random_seed(cli.seed);
+    }
+    check_emit(&cli, vm.init(&ir_gen, cli.mode, &mut scanner));
+    timer.lap("emit");
+    if let Some(trace) = &mut timer.trace {
+        trace.record_allocation(&scanner);
+    }
+
+    if cli.mode != LogLevel::Release {
+        println!("--- [VM Execution Start] ---");
+    }
+
+    if cli.profile {
+        vm.enable_profiler();
+    }
+    if cli.debug {
+        attach_debugger(&mut vm);
+    }
+    timer.skip();
+    vm.run();
+    timer.lap("run");
+    timer.report_stats(&cli, &vm);
+    if let Some(profiler) = &vm.profiler {
+        eprint!("{}", profiler);
+    }
+
+    if cli.mode != LogLevel::Release {
+        println!("--- [VM Execution Finished] ---");
+    }
+    if cli.mode == LogLevel::Trace {
+        print_trace_report(&ir_gen, &scanner, &vm);
+    }
+}
+
+// the limits given on the command line, defaults for the rest
+fn vm_config(cli: &Cli) -> VmConfig {
+    let mut config = VmConfig::default();
+    if let Some(frames) = cli.max_stack {
+        config = config.with_max_call_stack(frames);
+    }
+    if let Some(bytes) = cli.max_heap {
+        config = config.with_heap_limit(bytes);
+    }
+    if let Some(bytes) = cli.gc_threshold {
+        config = config.with_gc_threshold(bytes);
+    }
+    config
+}
+
+// lines of a runtime error traceback given on the command line, None prints every frame
+fn traceback_limit(cli: &Cli) -> Option<usize> {
+    if cli.full_traceback {
+        return None;
+    }
+    Some(cli.traceback_lines.unwrap_or(TRACEBACK_MAX_LINES))
+}
+
+// a byte count with an optional binary unit: 4096, 64K, 512M, 2G (a trailing B is allowed)
+fn parse_size(arg: &str) -> Result<usize, String> {
+    let upper = arg.trim().to_ascii_uppercase();
+    let digits = upper.strip_suffix('B').unwrap_or(&upper);
+    let (digits, shift) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 10),
+        Some('M') => (&digits[..digits.len() - 1], 20),
+        Some('G') => (&digits[..digits.len() - 1], 30),
+        _ => (digits, 0),
     };
+    let count: usize = digits.trim().parse().map_err(|_| {
+        format!(
+            "'{}' is not a size, expected e.g. 4096, 64K, 512M or 2G",
+            arg
+        )
+    })?;
+    count
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("'{}' is too large", arg))
+}
+
+// the standard library alone takes a few kilobytes, below this nothing could run
+fn parse_heap_size(arg: &str) -> Result<usize, String> {
+    const MIN_HEAP: usize = 64 * 1024;
+    let bytes = parse_size(arg)?;
+    if bytes < MIN_HEAP {
+        return Err(format!("the heap needs at least {}K", MIN_HEAP >> 10));
+    }
+    Ok(bytes)
+}
+
+// wall-clock time of each phase of the driver, printed to stderr as the phase ends (--time);
+// with --trace-out the phases and what they produced are collected for the trace file
+struct PhaseTimer {
+    enabled: bool,
+    start: Instant,
+    trace: Option<TraceCollector>,
+}
+
+impl PhaseTimer {
+    fn new(cli: &Cli) -> Self {
+        Self {
+            enabled: cli.time,
+            start: Instant::now(),
+            trace: cli.trace_out.as_ref().map(|_| TraceCollector::new()),
+        }
+    }
+
+    // the phase that started at the last lap or skip has ended
+    fn lap(&mut self, phase: &str) {
+        let elapsed = self.start.elapsed();
+        if self.enabled {
+            let ms = elapsed.as_secs_f64() * 1000.0;
+            eprintln!("[Time] {:<6} {:>12.3} ms", phase, ms);
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.phase(phase, elapsed);
+        }
+        self.start = Instant::now();
+    }
+
+    // leave the time since the last lap out, e.g. reporting diagnostics or the execution banner
+    fn skip(&mut self) {
+        self.start = Instant::now();
+    }
+
+    fn report_stats(&mut self, cli: &Cli, vm: &VirtualMachine) {
+        if self.enabled {
+            eprintln!(
+                "[Time] {} instructions, {} full and {} minor GC cycles",
+                vm.stats.instructions, vm.stats.full_collections, vm.stats.minor_collections
+            );
+            if vm.stats.jit_compiled > 0 {
+                eprintln!(
+                    "[Time] {} functions compiled to native code, {} calls ran natively",
+                    vm.stats.jit_compiled, vm.stats.jit_calls
+                );
+            }
+        }
+        if let (Some(trace), Some(path)) = (&mut self.trace, &cli.trace_out) {
+            trace.record_vm(vm);
+            if let Err(e) = trace.write(path) {
+                eprintln!("[Error] Failed to write {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// report every frontend error and stop before anything runs, unless --force
+fn check_diagnostics(cli: &Cli, diagnostics: &Diagnostics) {
+    if diagnostics.is_empty() {
+        return;
+    }
+    let file_path = cli.input.as_ref().unwrap();
+    for diagnostic in diagnostics.iter() {
+        eprintln!("[Error] {}:{}", file_path.display(), diagnostic);
+    }
+    if cli.force {
+        eprintln!("[Warning] continuing despite the errors above (--force)");
+        return;
+    }
+    std::process::exit(diagnostics.exit_code());
+}
+
+// a function that does not fit the bytecode ends the process like a compile error
+fn check_emit<T>(cli: &Cli, result: Result<T, EmitError>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("[Error] {}: {}", cli.input.as_ref().unwrap().display(), e);
+        std::process::exit(EXIT_COMPILE_ERROR);
+    })
+}
+
+// a .myb image is recognized by its magic, not by the file extension
+fn is_bytecode_image(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && &magic == MYB_MAGIC
+}
+
+// --check on a module written with -o: it is well-formed if it loads
+fn check_precompiled(cli: &Cli) {
+    let file_path = cli.input.as_ref().unwrap();
+    let loaded = fs::read(file_path)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| deserialize_module(&bytes).map_err(|e| e.to_string()));
+    if let Err(e) = loaded {
+        eprintln!("[Error] {}: {}", file_path.display(), e);
+        std::process::exit(1);
+    }
+}
+
+// run a module written with -o, there is no IR or register map to trace
+fn run_precompiled(cli: &Cli, mut timer: PhaseTimer) {
+    let file_path = cli.input.as_ref().unwrap();
+    let bytes = fs::read(file_path).unwrap_or_else(|_| {
+        panic!(
+            "Critical: Failed to read bytecode file at {}",
+            file_path.display()
+        )
+    });
+    let funcs = match deserialize_module(&bytes) {
+        Ok(funcs) => funcs,
+        Err(e) => {
+            eprintln!("[Error] {}", e);
+            std::process::exit(1);
+        }
+    };
+    match cli.emit {
+        Some(Emit::Ast | Emit::Source | Emit::Ir) => {
+            eprintln!(
+                "[Error] {} is already compiled, only its bytecode can be emitted",
+                file_path.display()
+            );
+            std::process::exit(1);
+        }
+        Some(emit) => {
+            write_emitted(cli, render_bytecode(&funcs, emit));
+            return;
+        }
+        None => {}
+    }
+    if cli.output.is_some() {
+        eprintln!("[Error] {} is already compiled", file_path.display());
+        std::process::exit(1);
+    }
+
+    let mut vm = VirtualMachine::with_config(vm_config(cli));
+    vm.chunk_name = file_path.display().to_string().into();
+    vm.traceback_limit = traceback_limit(cli);
+    vm.set_gc_mode(cli.gc);
+    vm.heap.stress = cli.gc_stress;
+    if cli.seed.is_some() {
+        vm.set_random_seed(cli.seed);
+    }
+    vm.init_precompiled(funcs, cli.mode);
+    timer.lap("load");
 
     if cli.mode != LogLevel::Release {
         println!("--- [VM Execution Start] ---");
     }
 
+    if cli.profile {
+        vm.enable_profiler();
+    }
+    if cli.debug {
+        attach_debugger(&mut vm);
+    }
+    timer.skip();
     vm.run();
+    timer.lap("run");
+    timer.report_stats(cli, &vm);
+    if let Some(profiler) = &vm.profiler {
+        eprint!("{}", profiler);
+    }
 
     if cli.mode != LogLevel::Release {
         println!("--- [VM Execution Finished] ---");
     }
 }
 
+// the debugger talks to the terminal, what the script prints goes in between
+fn attach_debugger(vm: &mut VirtualMachine) {
+    println!("Myula debugger, `help` lists the commands");
+    Debugger::new(
+        Box::new(BufReader::new(std::io::stdin())),
+        Box::new(std::io::stdout()),
+    )
+    .attach(vm);
+}
+
+fn run_repl(cli: &Cli) {
+    let mut repl = Repl::with_state(Myula::with_config(vm_config(cli)));
+    let vm = repl.state_mut().vm_mut();
+    vm.chunk_name = "stdin".into();
+    vm.traceback_limit = traceback_limit(cli);
+    vm.set_gc_mode(cli.gc);
+    vm.heap.stress = cli.gc_stress;
+    vm.log_level = cli.mode;
+    if cli.seed.is_some() {
+        vm.set_random_seed(cli.seed);
+    }
+
+    println!("Myula 1.0, end the input (Ctrl-D) to quit");
+    let stdin = std::io::stdin();
+    if let Err(e) = repl.run(stdin.lock(), std::io::stdout()) {
+        eprintln!("[Error] {}", e);
+        std::process::exit(1);
+    }
+}
+
+// ends the process, with status 1 unless the client shut the server down before `exit`
+fn run_lsp() -> ! {
+    let mut server = LanguageServer::new();
+    let stdin = std::io::stdin();
+    if let Err(e) = server.run(stdin.lock(), std::io::stdout()) {
+        eprintln!("[Error] {}", e);
+        std::process::exit(1);
+    }
+    std::process::exit(server.exit_code());
+}
+
+fn render_bytecode(funcs: &HashMap<String, FuncMetadata>, emit: Emit) -> String {
+    match emit {
+        Emit::BytecodeJson => format!("{}\n", module_to_json(funcs)),
+        _ => disassemble_module(funcs),
+    }
+}
+
+// an --emit dump goes to -o if given, stdout otherwise
+fn write_emitted(cli: &Cli, text: String) {
+    match &cli.output {
+        Some(out_path) => {
+            if let Err(e) = fs::write(out_path, text) {
+                eprintln!("[Error] Failed to write {}: {}", out_path.display(), e);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", text),
+    }
+}
+
+// --mode trace: the IR, the register allocation and the VM state after the run, as text
+fn print_trace_report(
+    ir_gen: &myula::frontend::ir::IRGenerator,
+    scanner: &Scanner,
+    vm: &VirtualMachine,
+) {
+    println!(
+        "\n{:^105}",
+        "*************************************************************************"
+    );
+    println!("{:^105}", "MYULA COMPILER DIAGNOSTIC TRACE (AUTO-DUMP)");
+    println!(
+        "{:^105}",
+        "*************************************************************************"
+    );
+
+    print_ir_report(ir_gen);
+    print_scanner_report(scanner);
+    print_emitter_report(vm);
+
+    println!("\n{:^105}\n", "--- END OF TRACE DATA ---");
+}
+
 fn print_ir_report(ir_gen: &myula::frontend::ir::IRGenerator) {
     let module = ir_gen.get_module();
     println!(
@@ -111,14 +615,12 @@ fn print_emitter_report(vm: &VirtualMachine) {
         "\n{:30} {:^40} {:30}",
         "==========================", "VM FINAL STATE", "=========================="
     );
-    vm.dump_internal_state();
+    let _ = vm.write_internal_state(&mut std::io::stdout());
 }
 
 fn print_scanner_report(scanner: &Scanner) {
-    let mut funcs: Vec<String> = scanner.func_stack_info.keys().cloned().collect();
-    funcs.sort();
-
-    if funcs.is_empty() {
+    let analyses = scanner.analyses();
+    if analyses.is_empty() {
         println!("[Warning] No function definitions detected for analysis.");
         return;
     }
@@ -128,13 +630,11 @@ fn print_scanner_report(scanner: &Scanner) {
         "==========================", "REGISTER ALLOCATION", "=========================="
     );
 
-    for func in funcs {
-        let (num_locals, max_stack) = scanner.func_stack_info.get(&func).unwrap();
-
-        println!("\n▶ Subroutine: [{}]", func);
+    for analysis in analyses {
+        println!("\n▶ Subroutine: [{}]", analysis.name);
         println!(
             "  Metrics:  [{} Locals] [{} Max Stack]",
-            num_locals, max_stack
+            analysis.num_locals, analysis.max_stack
         );
 
         println!("{:-<105}", "");
@@ -144,43 +644,23 @@ fn print_scanner_report(scanner: &Scanner) {
         );
         println!("{:-<105}", "");
 
-        let mut vars: Vec<_> = scanner
-            .lifetimes
-            .iter()
-            .filter(|((f, _), _)| f == &func)
-            .collect();
-
-        vars.sort_by(
-            |((_, kind_a), lt_a), ((_, kind_b), lt_b)| match (kind_a, kind_b) {
-                (VarKind::Slot(id_a), VarKind::Slot(id_b)) => id_a.cmp(id_b),
-                (VarKind::Slot(_), VarKind::Reg(_)) => std::cmp::Ordering::Less,
-                (VarKind::Reg(_), VarKind::Slot(_)) => std::cmp::Ordering::Greater,
-                (VarKind::Reg(_), VarKind::Reg(_)) => lt_a.start.cmp(&lt_b.start),
-            },
-        );
-
-        for ((_, kind), lt) in vars {
-            let p_idx = scanner
-                .reg_map
-                .get(&(func.clone(), kind.clone()))
-                .expect("CRITICAL: Physical register mapping missing");
-
-            let name = match kind {
-                VarKind::Reg(id) => format!("%{}", id),
-                VarKind::Slot(id) => format!("%local_{}", id),
-            };
-
-            let kind_str = if lt.is_fixed { "LOCAL" } else { "TEMP" };
-            let ty_str = lt.inferred_type.as_deref().unwrap_or("Dynamic");
-            let strategy = if lt.is_fixed {
-                "Fixed Slot"
-            } else {
-                "Reusable"
-            };
-
+        let locals = analysis.locals.iter().map(|a| (a, "LOCAL", "Fixed Slot"));
+        let temps = analysis.temps.iter().map(|a| (a, "TEMP", "Reusable"));
+        for (alloc, kind_str, strategy) in locals.chain(temps) {
+            let (start, end) = analysis.lifetimes[&alloc.var];
+            let ty_str = analysis
+                .types
+                .get(&alloc.var)
+                .map_or("Dynamic", String::as_str);
             println!(
                 "{:<15} | {:<8} | {:<12} | R[{:<9}] | {:>3} -> {:<8} | {:<12}",
-                name, kind_str, ty_str, p_idx, lt.start, lt.end, strategy
+                alloc.var.to_string(),
+                kind_str,
+                ty_str,
+                alloc.reg,
+                start,
+                end,
+                strategy
             );
         }
     }
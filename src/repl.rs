@@ -0,0 +1,142 @@
+// Myula interactive REPL
+// Changelog:
+// 2026-02-24: Initial version. Every input is compiled as its own chunk and loaded into the same `Myula`
+//            state, so globals and functions defined earlier stay visible. Expressions print their value,
+//            input that ends inside an unfinished block or expression is continued on the next line.
+// 2026-02-24: Only input whose first syntax error is the end of the input is continued.
+
+use crate::engine::{EngineError, Myula, Value};
+use crate::frontend::lexer::Lexer;
+use crate::frontend::parser::ast::{Expr, Expression, Statement, Stmt};
+use crate::frontend::parser::{Parser, ParserErrorType};
+use std::io::{self, BufRead, Write};
+
+pub const PROMPT: &str = "> ";
+pub const CONTINUATION_PROMPT: &str = ">> ";
+
+/// what became of one line of input
+#[derive(Debug)]
+pub enum ReplOutcome {
+    // the input so far is an unfinished chunk, nothing was run
+    NeedMore,
+    // the chunk ran, the values are what an expression evaluated to (empty for statements)
+    Done(Result<Vec<Value>, EngineError>),
+}
+
+pub struct Repl {
+    lua: Myula,
+    // lines of the chunk being continued
+    pending: String,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self::with_state(Myula::new())
+    }
+
+    /// run on a state prepared by the host, e.g. with functions already registered
+    pub fn with_state(lua: Myula) -> Self {
+        Self {
+            lua,
+            pending: String::new(),
+        }
+    }
+
+    pub fn state_mut(&mut self) -> &mut Myula {
+        &mut self.lua
+    }
+
+    /// true while a chunk is being continued, i.e. the next prompt is CONTINUATION_PROMPT
+    pub fn is_continuing(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    pub fn feed_line(&mut self, line: &str) -> ReplOutcome {
+        if !self.pending.is_empty() {
+            self.pending.push('\n');
+        }
+        self.pending.push_str(line);
+
+        // `1 + 2` is run as `return 1 + 2` and its value shown; calls stay statements,
+        // they print through their own output
+        let result = match expression_source(&self.pending) {
+            Some(expr_source) => self.lua.exec(&expr_source),
+            None if is_incomplete(&self.pending) => return ReplOutcome::NeedMore,
+            None => self.lua.exec(&self.pending).map(|_| Vec::new()),
+        };
+        self.pending.clear();
+        ReplOutcome::Done(result)
+    }
+
+    /// read lines until the input ends, printing prompts, values and errors to `out`
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut out: W) -> io::Result<()> {
+        write!(out, "{}", PROMPT)?;
+        out.flush()?;
+        for line in input.lines() {
+            match self.feed_line(&line?) {
+                ReplOutcome::NeedMore => {}
+                ReplOutcome::Done(Ok(values)) => {
+                    if !values.is_empty() {
+                        let shown: Vec<String> = values.iter().map(Value::display_lua).collect();
+                        writeln!(out, "{}", shown.join("\t"))?;
+                    }
+                }
+                ReplOutcome::Done(Err(e)) => writeln!(out, "{}", e)?,
+            }
+            let prompt = if self.is_continuing() {
+                CONTINUATION_PROMPT
+            } else {
+                PROMPT
+            };
+            write!(out, "{}", prompt)?;
+            out.flush()?;
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+}
+
+// the source only failed to parse because it ended too early; the parser recovers from
+// errors, so a real error followed by a missing `end` must not count as unfinished
+fn is_incomplete(source: &str) -> bool {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(&mut lexer);
+    parser.parse();
+    parser
+        .get_err()
+        .first()
+        .is_some_and(|e| e.err_type == ParserErrorType::UnexpectedEof)
+}
+
+// `return <source>` if the source is a list of expressions that are not just calls
+fn expression_source(source: &str) -> Option<String> {
+    let expr_source = format!("return {}", source);
+    let is_expression = {
+        let mut lexer = Lexer::new(&expr_source);
+        let mut parser = Parser::new(&mut lexer);
+        let program = parser.parse();
+        let is_call = |v: &Expr| {
+            matches!(
+                v.node,
+                Expression::FnCall { .. } | Expression::MethodCall { .. }
+            )
+        };
+        parser.get_err().is_empty()
+            && match program.body.as_slice() {
+                [
+                    Stmt {
+                        node: Statement::ReturnStmt { values },
+                        ..
+                    },
+                ] => !values.is_empty() && !values.iter().all(is_call),
+                _ => false,
+            }
+    };
+    is_expression.then_some(expr_source)
+}
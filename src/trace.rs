@@ -0,0 +1,148 @@
+// Myula compiler trace
+// Changelog:
+// 2026-02-24: Initial version. A `TraceCollector` is handed each phase of the driver as it finishes: the IR,
+//            the register allocation of every function, the emitted bytecode and the VM state after the
+//            run. `myulac --trace-out` writes it as one JSON file; the phase spans are Chrome trace events
+//            (`traceEvents`), so the file also opens in chrome://tracing, Perfetto or speedscope.
+
+use crate::backend::disasm::{json_string, module_to_json};
+use crate::backend::translator::scanner::{FunctionAnalysis, Scanner};
+use crate::backend::vm::std_lib::type_name;
+use crate::backend::vm::{FuncMetadata, VirtualMachine};
+use crate::frontend::ir::IRGenerator;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// what the driver saw of one program, recorded phase by phase; a part that was never recorded
+/// (e.g. the IR of a precompiled image) is `null` in the output
+pub struct TraceCollector {
+    origin: Instant,
+    // (phase, start, duration) in microseconds since `origin`
+    phases: Vec<(String, u128, u128)>,
+    ir: Option<String>,
+    allocation: Option<String>,
+    bytecode: Option<String>,
+    vm: Option<String>,
+}
+
+impl Default for TraceCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TraceCollector {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            phases: Vec::new(),
+            ir: None,
+            allocation: None,
+            bytecode: None,
+            vm: None,
+        }
+    }
+
+    /// a phase that took `elapsed` has just ended
+    pub fn phase(&mut self, name: &str, elapsed: Duration) {
+        let end = self.origin.elapsed().as_micros();
+        let dur = elapsed.as_micros().min(end);
+        self.phases.push((name.to_string(), end - dur, dur));
+    }
+
+    pub fn record_ir(&mut self, ir_gen: &IRGenerator) {
+        self.ir = Some(json_string(&ir_gen.get_module().to_string()));
+    }
+
+    /// the physical register and live range of every virtual register and local slot
+    pub fn record_allocation(&mut self, scanner: &Scanner) {
+        let out: Vec<String> = scanner
+            .analyses()
+            .iter()
+            .map(FunctionAnalysis::to_json)
+            .collect();
+        self.allocation = Some(format!("[{}]", out.join(",")));
+    }
+
+    pub fn record_bytecode(&mut self, funcs: &HashMap<String, FuncMetadata>) {
+        self.bytecode = Some(module_to_json(funcs));
+    }
+
+    /// what the VM has loaded and run, its globals by type and what is left on the call stack
+    pub fn record_vm(&mut self, vm: &VirtualMachine) {
+        if self.bytecode.is_none() {
+            let funcs: HashMap<String, FuncMetadata> = vm
+                .func_meta
+                .iter()
+                .map(|(name, func)| (name.clone(), (*func.meta).clone()))
+                .collect();
+            self.record_bytecode(&funcs);
+        }
+        let mut globals: Vec<_> = vm.named_globals().collect();
+        globals.sort_by_key(|(name, _)| *name);
+        let globals: Vec<String> = globals
+            .into_iter()
+            .map(|(name, val)| format!("{}:\"{}\"", json_string(name), type_name(val)))
+            .collect();
+        let frames: Vec<String> = vm
+            .call_stack
+            .iter()
+            .map(|frame| {
+                format!(
+                    "{{\"function\":{},\"pc\":{}}}",
+                    json_string(&frame.func_name),
+                    frame.pc
+                )
+            })
+            .collect();
+        let heap = vm.heap.stats();
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "{{\"instructions\":{},\"full_collections\":{},\"minor_collections\":{},",
+            vm.stats.instructions, vm.stats.full_collections, vm.stats.minor_collections
+        );
+        let _ = write!(
+            out,
+            "\"heap\":{{\"bytes\":{},\"strings\":{},\"tables\":{},\"functions\":{},\"upvalues\":{},\"userdata\":{}}},",
+            heap.bytes, heap.strings, heap.tables, heap.functions, heap.upvalues, heap.userdata
+        );
+        let _ = write!(
+            out,
+            "\"globals\":{{{}}},\"call_stack\":[{}]}}",
+            globals.join(","),
+            frames.join(",")
+        );
+        self.vm = Some(out);
+    }
+
+    pub fn to_json(&self) -> String {
+        let events: Vec<String> = self
+            .phases
+            .iter()
+            .map(|(name, ts, dur)| {
+                format!(
+                    "{{\"name\":{},\"cat\":\"phase\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":1}}",
+                    json_string(name),
+                    ts,
+                    dur
+                )
+            })
+            .collect();
+        let part = |val: &Option<String>| val.clone().unwrap_or_else(|| "null".to_string());
+        format!(
+            "{{\"traceEvents\":[{}],\"displayTimeUnit\":\"ms\",\"ir\":{},\"allocation\":{},\"bytecode\":{},\"vm\":{}}}\n",
+            events.join(","),
+            part(&self.ir),
+            part(&self.allocation),
+            part(&self.bytecode),
+            part(&self.vm)
+        )
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+}
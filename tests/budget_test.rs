@@ -0,0 +1,78 @@
+use myula::Myula;
+use myula::backend::vm::error::ErrorKind;
+use myula::engine::{EngineError, Value};
+use std::time::{Duration, Instant};
+
+const FOREVER: &str = "local i = 0\nwhile true do\n  i = i + 1\nend\n";
+
+fn budget_exceeded(result: Result<Vec<Value>, EngineError>) -> String {
+    match result {
+        Err(EngineError::Runtime(err)) => {
+            assert!(matches!(err.kind, ErrorKind::BudgetExceeded(_)), "{}", err);
+            err.get_message()
+        }
+        other => panic!(
+            "expected the budget to run out, got {:?}",
+            other.map(|_| ())
+        ),
+    }
+}
+
+#[test]
+fn test_fuel_stops_a_runaway_loop_deterministically() {
+    let mut counts = Vec::new();
+    for _ in 0..2 {
+        let mut lua = Myula::new();
+        lua.vm_mut().set_fuel(Some(10_000));
+        let message = budget_exceeded(lua.exec(FOREVER));
+        assert!(
+            message.starts_with("BudgetExceededException"),
+            "{}",
+            message
+        );
+        assert_eq!(lua.vm_mut().fuel(), Some(0));
+        counts.push(lua.vm_mut().stats.instructions);
+    }
+    assert_eq!(counts, [10_000, 10_000]);
+}
+
+#[test]
+fn test_fuel_is_used_up_across_chunks_and_can_be_refilled() {
+    let mut lua = Myula::new();
+    lua.vm_mut().set_fuel(Some(1_000));
+    lua.exec("x = 1 + 2").unwrap();
+    let left = lua.vm_mut().fuel().unwrap();
+    assert!(left > 0 && left < 1_000);
+
+    budget_exceeded(lua.exec(FOREVER));
+    // still empty, nothing runs until the host sets it again
+    budget_exceeded(lua.exec("y = 1"));
+
+    lua.vm_mut().set_fuel(None);
+    lua.exec("y = 1").unwrap();
+    assert_eq!(lua.get_global("y").unwrap().as_integer(), Some(1));
+}
+
+#[test]
+fn test_pcall_cannot_swallow_the_budget() {
+    let mut lua = Myula::new();
+    lua.vm_mut().set_fuel(Some(5_000));
+    budget_exceeded(lua.exec(&format!(
+        "local function spin()\n{}end\nlocal ok = pcall(spin)\ncaught = true\n",
+        FOREVER
+    )));
+    assert_eq!(lua.get_global("caught").unwrap(), Value::Nil);
+}
+
+#[test]
+fn test_timeout_stops_a_runaway_loop() {
+    let mut lua = Myula::new();
+    lua.vm_mut().set_timeout(Some(Duration::from_millis(50)));
+    let start = Instant::now();
+    let message = budget_exceeded(lua.exec(FOREVER));
+    assert!(message.contains("time limit"), "{}", message);
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    lua.vm_mut().set_timeout(None);
+    lua.exec("z = 3").unwrap();
+}
@@ -0,0 +1,295 @@
+mod common;
+
+use common::{global_integer, global_number, global_string};
+use myula::backend::deserializer::{
+    BYTECODE_FORMAT_VERSION, FormatError, HEADER_SIZE, LAYOUT_FINGERPRINT, MYB_MAGIC,
+    deserialize_module, read_header, serialize_module, write_header,
+};
+use myula::backend::translator::scanner::Scanner;
+use myula::backend::vm::{LogLevel, VirtualMachine};
+use myula::common::opcode::OpCode;
+use myula::frontend::ir::{IRGenerator, IRUpValType};
+use myula::frontend::lexer::Lexer;
+use myula::frontend::parser::Parser;
+
+fn compile_image(source: &str) -> Vec<u8> {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+
+    let mut ir_gen = IRGenerator::new();
+    ir_gen.generate(&program);
+
+    let mut scanner = Scanner::new();
+    scanner.global_scan(ir_gen.get_module());
+
+    serialize_module(&VirtualMachine::compile(&ir_gen, &mut scanner, false).unwrap())
+}
+
+#[test]
+fn test_header_round_trip() {
+    let mut image = Vec::new();
+    write_header(&mut image);
+    assert_eq!(image.len(), HEADER_SIZE);
+    image.extend_from_slice(b"payload");
+
+    assert_eq!(read_header(&image), Ok(&b"payload"[..]));
+}
+
+#[test]
+fn test_mismatched_images_are_refused() {
+    let mut image = Vec::new();
+    write_header(&mut image);
+
+    let mut newer = image.clone();
+    newer[4..6].copy_from_slice(&(BYTECODE_FORMAT_VERSION + 1).to_le_bytes());
+    assert_eq!(
+        read_header(&newer),
+        Err(FormatError::VersionMismatch {
+            found: BYTECODE_FORMAT_VERSION + 1,
+            expected: BYTECODE_FORMAT_VERSION,
+        })
+    );
+
+    let mut dev_build = image.clone();
+    dev_build[6..].copy_from_slice(&(!LAYOUT_FINGERPRINT).to_le_bytes());
+    assert!(matches!(
+        read_header(&dev_build),
+        Err(FormatError::LayoutMismatch { .. })
+    ));
+
+    assert_eq!(read_header(&MYB_MAGIC[..3]), Err(FormatError::Truncated));
+    assert_eq!(
+        read_header(b"print(\"not bytecode\")"),
+        Err(FormatError::NotBytecode)
+    );
+}
+
+#[test]
+fn test_module_round_trip() {
+    let source = r#"
+        local function make_counter(step)
+            local n = 0
+            return function()
+                n = n + step
+                return n
+            end
+        end
+        local c = make_counter(2)
+        c()
+        count = c()
+        half = 7 / 2
+        big = -9007199254740993
+        name = "my" .. "ula"
+        t = {10, 20, x = 30}
+        sum = t[1] + t[2] + t.x
+        local i = 0
+        while i < 5 do
+            i = i + 1
+        end
+        loops = i - 0.5
+    "#;
+    let image = compile_image(source);
+    assert!(image.starts_with(MYB_MAGIC));
+    // functions are written in name order, the same source gives the same image
+    assert_eq!(image, compile_image(source));
+
+    let funcs = deserialize_module(&image).unwrap();
+    assert!(funcs.contains_key("_start"));
+    // parameters take the first slots
+    let (_, counter) = funcs
+        .iter()
+        .find(|(name, _)| name.contains("make_counter"))
+        .unwrap();
+    assert_eq!(counter.local_names[..2], ["step", "n"]);
+    // the span table survives, pc for pc
+    assert_eq!(counter.span_info.len(), counter.bytecode.len());
+    assert!(counter.span_info.iter().any(|span| span.end > span.start));
+
+    let mut vm = VirtualMachine::new();
+    vm.init_precompiled(funcs, LogLevel::Release);
+    vm.run();
+
+    assert_eq!(global_integer(&vm, "count"), 4);
+    assert_eq!(global_number(&vm, "half"), 3.5);
+    assert_eq!(global_integer(&vm, "big"), -9007199254740993);
+    assert_eq!(global_string(&vm, "name"), "myula");
+    assert_eq!(global_integer(&vm, "sum"), 60);
+    assert_eq!(global_number(&vm, "loops"), 4.5);
+}
+
+#[test]
+fn test_malformed_module_is_refused() {
+    let image = compile_image("x = 1");
+
+    let truncated = &image[..image.len() - 3];
+    assert!(matches!(
+        deserialize_module(truncated),
+        Err(FormatError::Malformed(_))
+    ));
+
+    let mut trailing = image.clone();
+    trailing.push(0);
+    assert!(matches!(
+        deserialize_module(&trailing),
+        Err(FormatError::Malformed(_))
+    ));
+
+    // the header is checked before the payload
+    let mut old = image.clone();
+    old[4..6].copy_from_slice(&(BYTECODE_FORMAT_VERSION - 1).to_le_bytes());
+    assert!(matches!(
+        deserialize_module(&old),
+        Err(FormatError::VersionMismatch { .. })
+    ));
+}
+
+#[test]
+fn test_out_of_range_operands_are_refused() {
+    let image = compile_image(
+        r#"
+        local n = 0
+        local function bump() n = n + 1 end
+        while n < 3 do
+            bump()
+        end
+        name = "done"
+        "#,
+    );
+
+    // rewrite the first instruction of `_start` that `pick` accepts, the image must then be refused
+    let refused = |pick: &dyn Fn(&OpCode, usize) -> Option<OpCode>, expected: &str| {
+        let mut funcs = deserialize_module(&image).unwrap();
+        let start = funcs.get_mut("_start").unwrap();
+        let size = start.max_stack_size;
+        let (pc, op) = start
+            .bytecode
+            .iter()
+            .enumerate()
+            .find_map(|(pc, op)| pick(op, size).map(|op| (pc, op)))
+            .expect("no instruction to corrupt");
+        start.bytecode[pc] = op;
+        match deserialize_module(&serialize_module(&funcs)) {
+            Err(FormatError::Malformed(msg)) => assert!(msg.contains(expected), "{}", msg),
+            other => panic!("expected a malformed image, got {:?}", other.map(|_| ())),
+        }
+    };
+
+    refused(
+        &|op, size| match *op {
+            OpCode::Move { src, .. } => Some(OpCode::Move {
+                dest: size as u16,
+                src,
+            }),
+            _ => None,
+        },
+        "register",
+    );
+    refused(
+        &|op, _| match *op {
+            OpCode::LoadK { dest, .. } => Some(OpCode::LoadK {
+                dest,
+                const_idx: 999,
+            }),
+            _ => None,
+        },
+        "constant 999",
+    );
+    refused(
+        &|op, _| match *op {
+            OpCode::FnProto { dest, .. } => Some(OpCode::FnProto { dest, proto_idx: 7 }),
+            _ => None,
+        },
+        "prototype 7",
+    );
+    refused(
+        &|op, _| match *op {
+            OpCode::Jump { .. } => Some(OpCode::Jump { offset: 1000 }),
+            _ => None,
+        },
+        "jump",
+    );
+    refused(
+        &|op, size| match *op {
+            OpCode::Return { .. } => Some(OpCode::Return {
+                start: size as u16 - 1,
+                count: 2,
+            }),
+            _ => None,
+        },
+        "registers",
+    );
+    refused(
+        &|op, _| match *op {
+            OpCode::Move { dest, .. } => Some(OpCode::GetUpVal { dest, upval_idx: 0 }),
+            _ => None,
+        },
+        "upvalue 0",
+    );
+
+    // a prototype may only capture registers its parent has
+    let mut funcs = deserialize_module(&image).unwrap();
+    let size = funcs["_start"].max_stack_size;
+    let (_, bump) = funcs
+        .iter_mut()
+        .find(|(name, _)| name.contains("bump"))
+        .unwrap();
+    bump.upvalues_metadata[0].ty = IRUpValType::LocalVar(size);
+    assert!(matches!(
+        deserialize_module(&serialize_module(&funcs)),
+        Err(FormatError::Malformed(_))
+    ));
+
+    // neither is an image without an entry
+    let mut funcs = deserialize_module(&image).unwrap();
+    let start = funcs.remove("_start").unwrap();
+    funcs.insert("_begin".into(), start);
+    assert!(matches!(
+        deserialize_module(&serialize_module(&funcs)),
+        Err(FormatError::Malformed(_))
+    ));
+}
+
+#[test]
+fn test_corrupted_images_never_crash_the_vm() {
+    let image = compile_image(
+        r#"
+        local function make(step)
+            local n = 0
+            return function() n = n + step return n end
+        end
+        local c = make(2)
+        local t = {}
+        local i = 1
+        while i <= 5 do
+            t[i] = c() .. "!"
+            i = i + 1
+        end
+        joined = table.concat(t, ",")
+        "#,
+    );
+
+    // corrupt three payload bytes at a time; whatever loads must end in an error or run to the end
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    for _ in 0..2000 {
+        let mut corrupted = image.clone();
+        for _ in 0..3 {
+            let at = HEADER_SIZE + next() as usize % (image.len() - HEADER_SIZE);
+            corrupted[at] = next() as u8;
+        }
+        let Ok(funcs) = deserialize_module(&corrupted) else {
+            continue;
+        };
+        let mut vm = VirtualMachine::new();
+        vm.set_stdout(Box::new(std::io::sink()));
+        vm.set_fuel(Some(100_000));
+        vm.init_precompiled(funcs, LogLevel::Release);
+        let _ = vm.execute();
+    }
+}
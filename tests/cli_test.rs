@@ -0,0 +1,367 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+// a fresh directory holding one script
+fn script(test: &str, source: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("myula_cli_{}_{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("main.lua");
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn myulac(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_myula"))
+        .args(args)
+        .output()
+        .expect("failed to start myulac")
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+const SOURCE: &str = "local function add(a, b)\n  return a + b\nend\nprint(\"ran\", add(1, 2))\n";
+
+#[test]
+fn test_emit_stops_after_each_phase() {
+    let path = script("phases", SOURCE);
+    let path = path.to_str().unwrap();
+
+    let ast = stdout(&myulac(&["--emit=ast", path]));
+    assert!(ast.starts_with("Program {"), "{}", ast);
+    assert!(ast.contains("\"add\""), "{}", ast);
+
+    let source = stdout(&myulac(&["--emit=source", path]));
+    assert_eq!(
+        source,
+        "local function add(a, b)\n    return a + b\nend\nprint(\"ran\", add(1, 2))\n"
+    );
+
+    let ir = stdout(&myulac(&["--emit=ir", path]));
+    assert!(ir.contains("function _start"), "{}", ir);
+
+    let bytecode = stdout(&myulac(&["--emit=bytecode", path]));
+    assert!(bytecode.starts_with("function _start ("), "{}", bytecode);
+    assert!(bytecode.contains("RETURN"), "{}", bytecode);
+
+    // nothing was run
+    for dump in [&ast, &ir, &bytecode] {
+        assert!(!dump.contains("ran\t3"), "{}", dump);
+    }
+}
+
+#[test]
+fn test_emit_writes_to_output_file() {
+    let path = script("output", SOURCE);
+    let out = path.with_file_name("main.ir");
+
+    let output = myulac(&[
+        "--emit=ir",
+        "-o",
+        out.to_str().unwrap(),
+        path.to_str().unwrap(),
+    ]);
+    assert!(stdout(&output).is_empty());
+    let ir = fs::read_to_string(&out).unwrap();
+    assert!(ir.contains("function _start"), "{}", ir);
+}
+
+#[test]
+fn test_precompiled_image_only_emits_bytecode() {
+    let path = script("image", SOURCE);
+    let image = path.with_file_name("main.myb");
+    stdout(&myulac(&[
+        "-o",
+        image.to_str().unwrap(),
+        path.to_str().unwrap(),
+    ]));
+
+    let json = stdout(&myulac(&["--emit=bytecode-json", image.to_str().unwrap()]));
+    assert!(json.starts_with("{\"functions\":["), "{}", json);
+
+    let refused = myulac(&["--emit=ast", image.to_str().unwrap()]);
+    assert!(!refused.status.success());
+}
+
+#[test]
+fn test_broken_program_is_refused_before_running() {
+    let path = script("broken", "print(\"ran\")\ns = \"abc\n");
+    let output = myulac(&[path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("main.lua:2: unfinished string"),
+        "{}",
+        stderr
+    );
+
+    let path = script("undefined_label", "print(\"ran\")\ngoto nowhere\n");
+    let output = myulac(&[path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_check_reports_errors_without_running() {
+    let path = script("check", SOURCE);
+    let output = myulac(&["--check", path.to_str().unwrap()]);
+    assert!(stdout(&output).is_empty());
+    assert!(output.stderr.is_empty());
+
+    let path = script("check_broken", "print(\"ran\"\nlocal = 1\ns = \"abc\n");
+    let output = myulac(&["--check", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    // every error, not just the first
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("main.lua:2: Expected identifier"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("main.lua:3: unfinished string"),
+        "{}",
+        stderr
+    );
+
+    let path = script("check_label", "print(\"ran\")\ngoto nowhere\n");
+    let output = myulac(&["--check", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+
+    let image = path.with_file_name("main.myb");
+    let source = script("check_image", SOURCE);
+    stdout(&myulac(&[
+        "-o",
+        image.to_str().unwrap(),
+        source.to_str().unwrap(),
+    ]));
+    assert!(stdout(&myulac(&["--check", image.to_str().unwrap()])).is_empty());
+    let bytes = fs::read(&image).unwrap();
+    fs::write(&image, &bytes[..bytes.len() - 3]).unwrap();
+    let output = myulac(&["--check", image.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_force_runs_what_was_recovered() {
+    let path = script("force", "print(\"ran\")\ngoto nowhere\nprint(\"after\")\n");
+    let output = myulac(&["--force", path.to_str().unwrap()]);
+    let out = stdout(&output);
+    assert!(out.starts_with("ran\n"), "{}", out);
+    // the unresolved goto leaves the chunk
+    assert!(!out.contains("after"), "{}", out);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("no visible label 'nowhere'"), "{}", stderr);
+}
+
+#[test]
+fn test_time_reports_every_phase_on_stderr() {
+    let path = script("time", SOURCE);
+    let output = myulac(&["--time", path.to_str().unwrap()]);
+    assert!(stdout(&output).starts_with("ran\t3\n"));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for phase in ["parse", "ir", "emit", "run"] {
+        assert!(
+            stderr.contains(&format!("[Time] {:<6}", phase)),
+            "{}",
+            stderr
+        );
+    }
+    assert!(stderr.contains(" instructions, "), "{}", stderr);
+    assert!(stderr.contains("GC cycles"), "{}", stderr);
+}
+
+#[test]
+fn test_trace_out_writes_every_phase_as_json() {
+    let path = script("trace", SOURCE);
+    let trace = path.with_file_name("trace.json");
+    let output = myulac(&[
+        "--trace-out",
+        trace.to_str().unwrap(),
+        path.to_str().unwrap(),
+    ]);
+    assert!(stdout(&output).starts_with("ran\t3\n"));
+
+    let json = fs::read_to_string(&trace).unwrap();
+    assert!(json.starts_with("{\"traceEvents\":["), "{}", json);
+    for phase in ["parse", "ir", "emit", "run"] {
+        assert!(
+            json.contains(&format!(
+                "{{\"name\":\"{}\",\"cat\":\"phase\",\"ph\":\"X\"",
+                phase
+            )),
+            "{}",
+            json
+        );
+    }
+    assert!(json.contains("\"ir\":\"function "), "{}", json);
+    assert!(json.contains("\"allocation\":[{\"function\":"), "{}", json);
+    assert!(json.contains("\"bytecode\":{\"functions\":["), "{}", json);
+    assert!(json.contains("\"print\":\"function\""), "{}", json);
+
+    // a precompiled image has no IR or register map, only what the VM loaded and did
+    let image = path.with_file_name("main.myb");
+    stdout(&myulac(&[
+        "-o",
+        image.to_str().unwrap(),
+        path.to_str().unwrap(),
+    ]));
+    stdout(&myulac(&[
+        "--trace-out",
+        trace.to_str().unwrap(),
+        image.to_str().unwrap(),
+    ]));
+    let json = fs::read_to_string(&trace).unwrap();
+    assert!(json.contains("\"ir\":null,\"allocation\":null"), "{}", json);
+    assert!(json.contains("\"vm\":{\"instructions\":"), "{}", json);
+}
+
+#[test]
+fn test_profile_reports_functions_and_opcodes_on_stderr() {
+    let path = script("profile", SOURCE);
+    let output = myulac(&["--profile", path.to_str().unwrap()]);
+    assert!(stdout(&output).starts_with("ran\t3\n"));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("_start (") && stderr.contains("main.lua:1)"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("add"), "{}", stderr);
+    for opcode in ["CALL", "RETURN", "ADD"] {
+        assert!(stderr.contains(&format!("\n{:<40}", opcode)), "{}", stderr);
+    }
+}
+
+#[test]
+fn test_limits_are_set_from_the_command_line() {
+    let path = script(
+        "limits",
+        "function depth(n)\n  if n == 0 then return 0 end\n  return 1 + depth(n - 1)\nend\nprint(depth(1500))\n",
+    );
+    let path = path.to_str().unwrap();
+
+    let output = myulac(&[path]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("StackOverflowError"), "{}", stderr);
+
+    let output = myulac(&[
+        "--max-stack",
+        "2000",
+        "--max-heap",
+        "64M",
+        "--gc-threshold",
+        "4m",
+        path,
+    ]);
+    assert!(stdout(&output).starts_with("1500\n"));
+
+    for (flag, value) in [
+        ("--max-heap", "1K"),
+        ("--gc-threshold", "lots"),
+        ("--max-stack", "-1"),
+    ] {
+        let output = myulac(&[flag, value, path]);
+        assert!(!output.status.success(), "{} {}", flag, value);
+        assert!(output.stdout.is_empty());
+    }
+}
+
+#[test]
+fn test_debug_reads_commands_from_stdin() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let path = script("debug", SOURCE);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_myula"))
+        .args(["--debug", path.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start myulac");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"break add 0\ncontinue\nregisters\ncontinue\n")
+        .unwrap();
+    let out = stdout(&child.wait_with_output().unwrap());
+
+    assert!(
+        out.contains("_start (") && out.contains("pc 000"),
+        "{}",
+        out
+    );
+    assert!(out.contains("breakpoint #1 reached"), "{}", out);
+    assert!(out.contains("R0    local  1\n"), "{}", out);
+    assert!(out.contains("ran\t3\n"), "{}", out);
+}
+
+#[test]
+fn test_lsp_serves_stdio_until_exit() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_myula"))
+        .arg("--lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start myulac");
+    let input: String = [
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#,
+        r#"{"jsonrpc":"2.0","method":"exit"}"#,
+    ]
+    .iter()
+    .map(|body| format!("Content-Length: {}\r\n\r\n{}", body.len(), body))
+    .collect();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let out = stdout(&child.wait_with_output().unwrap());
+
+    assert!(out.starts_with("Content-Length: "), "{}", out);
+    assert!(out.contains("\"definitionProvider\":true"), "{}", out);
+    assert!(
+        out.ends_with("{\"jsonrpc\":\"2.0\",\"id\":2,\"result\":null}"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn test_print_formats_like_tostring() {
+    let path = script(
+        "print",
+        "local p = setmetatable({}, { __tostring = function() return \"<point>\" end })\n\
+         print()\n\
+         print(nil, false, 3, 2.0, 0.1, \"s\", p)\n\
+         io.write(\"same line \")\n\
+         print(\"done\")\n",
+    );
+    let out = stdout(&myulac(&[path.to_str().unwrap()]));
+    assert!(
+        out.starts_with("\nnil\tfalse\t3\t2.0\t0.1\ts\t<point>\nsame line done\n"),
+        "{}",
+        out
+    );
+}
@@ -0,0 +1,133 @@
+#![allow(dead_code)]
+
+use myula::backend::translator::scanner::Scanner;
+use myula::backend::vm::error::VMError;
+use myula::backend::vm::{LogLevel, VirtualMachine};
+use myula::common::object::LuaValue;
+use myula::frontend::ir::IRGenerator;
+use myula::frontend::lexer::Lexer;
+use myula::frontend::parser::Parser;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// an output sink for `VirtualMachine::set_stdout` that the test can read back after the run
+#[derive(Clone, Default)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// compile and run a chunk, the returned VM can be inspected afterwards
+pub fn run_source(source: &str) -> VirtualMachine {
+    run_source_with_input(source, "")
+}
+
+// same as run_source, io.read / io.lines read from `input` instead of stdin
+pub fn run_source_with_input(source: &str, input: &str) -> VirtualMachine {
+    let mut vm = VirtualMachine::new();
+    vm.input = Box::new(std::io::Cursor::new(input.as_bytes().to_vec()));
+    run_source_on(&mut vm, source);
+    vm
+}
+
+// lower a chunk to IR with the generator `configure` makes of a default one, e.g. with a pass
+// turned off
+pub fn ir_with(source: &str, configure: impl FnOnce(IRGenerator) -> IRGenerator) -> IRGenerator {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+    assert!(parser.get_err().is_empty(), "{:#?}", parser.get_err());
+
+    let mut ir_gen = configure(IRGenerator::new());
+    ir_gen.generate(&program);
+    ir_gen
+}
+
+// compile a chunk into an existing (new or reset) VM and run it
+pub fn run_source_on(vm: &mut VirtualMachine, source: &str) {
+    init_source_on(vm, source);
+    vm.run();
+}
+
+// compile a chunk into an existing VM and prepare its entry frame without running it
+pub fn init_source_on(vm: &mut VirtualMachine, source: &str) {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+    assert!(parser.get_err().is_empty(), "{:#?}", parser.get_err());
+
+    let mut ir_gen = IRGenerator::new();
+    ir_gen.generate(&program);
+
+    let mut scanner = Scanner::new();
+    scanner.global_scan(ir_gen.get_module());
+
+    vm.init(&ir_gen, LogLevel::Release, &mut scanner).unwrap();
+}
+
+pub fn global_number(vm: &VirtualMachine, name: &str) -> f64 {
+    match vm.get_global(name) {
+        Some(LuaValue::Number(n)) => *n,
+        Some(LuaValue::Integer(i)) => *i as f64,
+        other => panic!("global '{}' is not a number: {:?}", name, other),
+    }
+}
+
+pub fn global_integer(vm: &VirtualMachine, name: &str) -> i64 {
+    match vm.get_global(name) {
+        Some(LuaValue::Integer(i)) => *i,
+        other => panic!("global '{}' is not an integer: {:?}", name, other),
+    }
+}
+
+pub fn global_string(vm: &VirtualMachine, name: &str) -> String {
+    match vm.get_global(name) {
+        Some(LuaValue::String(ptr)) => ptr.get().clone(),
+        other => panic!("global '{}' is not a string: {:?}", name, other),
+    }
+}
+
+pub fn global_is_nil(vm: &VirtualMachine, name: &str) -> bool {
+    matches!(vm.get_global(name), None | Some(LuaValue::Nil))
+}
+
+// compile a chunk with debug info and step it until the first runtime error
+pub fn run_until_error(source: &str) -> Option<VMError> {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+    assert!(parser.get_err().is_empty(), "{:#?}", parser.get_err());
+
+    let mut ir_gen = IRGenerator::new();
+    ir_gen.generate(&program);
+
+    let mut scanner = Scanner::new();
+    scanner.global_scan(ir_gen.get_module());
+
+    let mut vm = VirtualMachine::new();
+    vm.init(&ir_gen, LogLevel::Debug, &mut scanner).unwrap();
+
+    while let Some(frame) = vm.call_stack.last_mut() {
+        frame.instr_pc = frame.pc;
+        let instr = vm.func_meta[&*frame.func_name].code[frame.pc];
+        if let Err(e) = vm.execute_instruction(instr) {
+            return Some(e);
+        }
+    }
+    None
+}
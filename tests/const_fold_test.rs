@@ -0,0 +1,173 @@
+mod common;
+
+use myula::frontend::ir::{
+    IRBinOp, IRConstWarning, IRConstWarningKind, IRInstruction, IRModule, IROperand,
+};
+
+fn const_warnings(source: &str) -> Vec<IRConstWarning> {
+    common::ir_with(source, |ir_gen| ir_gen)
+        .get_const_warnings()
+        .clone()
+}
+
+#[test]
+fn test_constant_division_by_zero_is_reported() {
+    let source = "local a = 1 / 0\nlocal b = 7 % (2 - 2)\nlocal c = 4 / 2\n";
+    let warnings = const_warnings(source);
+
+    assert_eq!(warnings.len(), 2);
+    assert_eq!(warnings[0].kind, IRConstWarningKind::DivisionByZero);
+    assert_eq!(warnings[0].pos, source.find('/').unwrap());
+    assert_eq!(warnings[1].kind, IRConstWarningKind::ModuloByZero);
+    assert_eq!(warnings[1].pos, source.find('%').unwrap());
+
+    assert_eq!(
+        warnings[0].describe(source),
+        "constant division by zero at 1:13"
+    );
+    assert_eq!(
+        warnings[1].describe(source),
+        "constant modulo by zero at 2:13"
+    );
+}
+
+#[test]
+fn test_constant_overflow_is_reported() {
+    let source = "local a = 9223372036854775807 + 1\nlocal b = 2 * 3 + 1.5\n";
+    let warnings = const_warnings(source);
+
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].kind, IRConstWarningKind::IntegerOverflow);
+    assert_eq!(
+        warnings[0].describe(source),
+        "constant integer overflow at 1:31"
+    );
+}
+
+#[test]
+fn test_folded_values_match_runtime() {
+    let vm = common::run_source(
+        "a = -7 % 3\nb = 7 / 2\nc = 9223372036854775807 + 1\nd = -(2 * 3)\ne = 5.5 % -2\n",
+    );
+    assert_eq!(common::global_integer(&vm, "a"), 2);
+    assert_eq!(common::global_number(&vm, "b"), 3.5);
+    assert_eq!(common::global_integer(&vm, "c"), i64::MIN);
+    assert_eq!(common::global_integer(&vm, "d"), -6);
+    assert_eq!(common::global_number(&vm, "e"), -0.5);
+
+    // not folded away, the division still fails when it is reached
+    let err = common::run_until_error("x = 1 % 0\n").expect("modulo by zero should fail");
+    assert!(format!("{:?}", err).contains("Arithmetic"), "{:?}", err);
+}
+
+#[test]
+fn test_modulo_operator() {
+    let is_mod = |instr: &IRInstruction| {
+        matches!(
+            instr,
+            IRInstruction::Binary {
+                operator: IRBinOp::Mod,
+                ..
+            }
+        )
+    };
+    let loads_two = |instr: &IRInstruction| {
+        matches!(
+            instr,
+            IRInstruction::LoadImm {
+                value: IROperand::ImmInt(2),
+                ..
+            }
+        )
+    };
+    // both operands literal, the mod is replaced by its result
+    let folded = ir("x = 17 % 5\n", true);
+    assert!(!instructions(&folded).any(is_mod));
+    assert!(instructions(&folded).any(loads_two));
+    // a constant local is propagated by the IR pass, a global is not known
+    let folded = ir("local a = 17\nx = a % 5\n", true);
+    assert!(!instructions(&folded).any(is_mod));
+    assert!(instructions(&folded).any(loads_two));
+    assert!(instructions(&ir("x = a % 5\n", true)).any(is_mod));
+
+    let vm = common::run_source(
+        "local a, b = 17, -5\nx = a % b\ny = 17 % 5\nz = 5.5 % 2\nw = a % 5 * 2\n",
+    );
+    assert_eq!(common::global_integer(&vm, "x"), -3);
+    assert_eq!(common::global_integer(&vm, "y"), 2);
+    assert_eq!(common::global_number(&vm, "z"), 1.5);
+    // same precedence as * and /, left associative
+    assert_eq!(common::global_integer(&vm, "w"), 4);
+}
+
+fn ir(source: &str, const_fold: bool) -> IRModule {
+    common::ir_with(source, |ir_gen| ir_gen.with_const_fold(const_fold))
+        .get_module()
+        .clone()
+}
+
+fn instructions(module: &IRModule) -> impl Iterator<Item = &IRInstruction> {
+    module
+        .functions
+        .iter()
+        .flat_map(|f| &f.basic_blocks)
+        .flat_map(|b| &b.instructions)
+}
+
+fn instr_count(module: &IRModule) -> usize {
+    instructions(module).count()
+}
+
+#[test]
+fn test_constant_branches_are_eliminated() {
+    let source = "
+local n = 10
+local limit = n * 2 + 1
+if limit > 20 and \"a\" < \"b\" then
+    x = limit
+else
+    x = 0
+end
+while false do
+    x = -1
+end
+";
+    let folded = ir(source, true);
+    let text = folded.to_string();
+    assert!(!text.contains("Branch"), "{}", text);
+    assert!(!text.contains("mul") && !text.contains("gt"), "{}", text);
+    assert!(instr_count(&folded) < instr_count(&ir(source, false)));
+
+    let vm = common::run_source(source);
+    assert_eq!(common::global_integer(&vm, "x"), 21);
+}
+
+#[test]
+fn test_captured_locals_are_not_propagated() {
+    let vm = common::run_source(
+        "
+local a = 1
+local bump = function() a = a + 4 end
+bump()
+x = a + 1
+y = 1 < 2 == true
+",
+    );
+    assert_eq!(common::global_integer(&vm, "x"), 6);
+
+    // comparisons that fail at runtime are not folded away
+    let err = common::run_until_error("x = 1 < \"a\"\n")
+        .expect("comparing a number and a string must fail");
+    assert!(format!("{:?}", err).contains("TypeError"), "{:?}", err);
+}
+
+#[test]
+fn test_constant_concat_chain_is_folded() {
+    let text = ir(
+        "x = \"a\" .. \"b\" .. \"c\"\ny = \"a\" .. z .. \"c\"\n",
+        true,
+    )
+    .to_string();
+    assert_eq!(text.matches("ConcatN").count(), 1, "{}", text);
+    assert!(text.contains("\"abc\""), "{}", text);
+}
@@ -0,0 +1,201 @@
+mod common;
+
+use common::{global_integer, global_is_nil, global_number, global_string, run_source};
+
+#[test]
+fn test_branches_and_loops_jump_to_their_blocks() {
+    let vm = run_source(
+        "
+        taken = 0
+        if 1 < 2 then
+            taken = 1
+        else
+            taken = 2
+        end
+
+        count = 0
+        local i = 0
+        while i < 10 do
+            if i > 4 then
+                count = count + 1
+            end
+            i = i + 1
+        end
+
+        steps = 0
+        repeat
+            steps = steps + 1
+        until steps >= 3
+        ",
+    );
+
+    assert_eq!(global_number(&vm, "taken"), 1.0);
+    assert_eq!(global_number(&vm, "count"), 5.0);
+    assert_eq!(global_number(&vm, "steps"), 3.0);
+}
+
+#[test]
+fn test_elseif_takes_the_first_true_branch() {
+    let vm = run_source(
+        "
+        function grade(score)
+            local g = \"F\"
+            if score >= 90 then
+                g = \"A\"
+            elseif score >= 80 then
+                g = \"B\"
+            elseif score >= 70 then
+                g = \"C\"
+            end
+            return g
+        end
+        grades = grade(95) .. grade(85) .. grade(75) .. grade(10)
+
+        local n = 0
+        if n > 0 then
+            sign = 1
+        elseif n < 0 then
+            sign = -1
+        else
+            sign = 0
+        end
+        ",
+    );
+
+    assert_eq!(global_string(&vm, "grades"), "ABCF");
+    assert_eq!(global_integer(&vm, "sign"), 0);
+}
+
+#[test]
+fn test_and_or_short_circuit() {
+    let vm = run_source(
+        "
+        calls = 0
+        local function bump(v)
+            calls = calls + 1
+            return v
+        end
+
+        a = false and bump(1)
+        b = nil or bump(2)
+        c = 3 or bump(3)
+        d = 4 and bump(4)
+        e = nil and nil.field
+        f = (1 and false) or \"fallback\"
+
+        hits = 0
+        local i = 0
+        while i < 5 and bump(true) do
+            if i > 1 or bump(false) then
+                hits = hits + 1
+            end
+            i = i + 1
+        end
+        ",
+    );
+
+    assert!(matches!(
+        vm.get_global("a"),
+        Some(myula::common::object::LuaValue::Boolean(false))
+    ));
+    assert_eq!(global_integer(&vm, "b"), 2);
+    assert_eq!(global_integer(&vm, "c"), 3);
+    assert_eq!(global_integer(&vm, "d"), 4);
+    assert!(global_is_nil(&vm, "e"));
+    assert_eq!(global_string(&vm, "f"), "fallback");
+    assert_eq!(global_integer(&vm, "hits"), 3);
+    // b, d, five loop conditions and the first two inner ifs
+    assert_eq!(global_number(&vm, "calls"), 9.0);
+}
+
+#[test]
+fn test_goto_and_labels() {
+    let vm = run_source(
+        "
+        -- continue
+        odd_sum = 0
+        local i = 0
+        while i < 10 do
+            i = i + 1
+            if i % 2 == 0 then
+                goto continue
+            end
+            odd_sum = odd_sum + i
+            ::continue::
+        end
+
+        -- backward jump as a loop
+        local n = 0
+        ::again::
+        n = n + 1
+        if n < 5 then goto again end
+        count = n
+
+        -- forward out of nested blocks, skipping the rest
+        reached = 0
+        do
+            if true then
+                do goto done end
+                reached = 1
+            end
+            reached = 2
+        end
+        ::done::
+
+        -- labels of a finished block are out of sight, the name can be reused
+        do ::skip:: end
+        do goto skip ::skip:: end
+        ",
+    );
+    assert_eq!(global_integer(&vm, "odd_sum"), 25);
+    assert_eq!(global_integer(&vm, "count"), 5);
+    assert_eq!(global_integer(&vm, "reached"), 0);
+}
+
+#[test]
+fn test_goto_label_errors() {
+    let errors = |source: &str| {
+        let mut lexer = myula::frontend::lexer::Lexer::new(source);
+        let mut parser = myula::frontend::parser::Parser::new(&mut lexer);
+        let program = parser.parse();
+        assert!(parser.get_err().is_empty(), "{:#?}", parser.get_err());
+        let mut ir_gen = myula::frontend::ir::IRGenerator::new();
+        ir_gen.generate(&program);
+        format!("{:?}", ir_gen.get_err())
+    };
+
+    assert_eq!(errors("goto nowhere"), "[UndefinedLabel(\"nowhere\")]");
+    // a label inside a nested block is not visible from outside
+    assert_eq!(
+        errors("goto inner\ndo ::inner:: end"),
+        "[UndefinedLabel(\"inner\")]"
+    );
+    // nor across functions
+    assert_eq!(
+        errors("::top::\nlocal f = function() goto top end"),
+        "[UndefinedLabel(\"top\")]"
+    );
+    assert_eq!(errors("::a::\ndo ::a:: end"), "[DuplicateLabel(\"a\")]");
+    assert_eq!(errors("do ::a:: end\n::a::"), "[]");
+}
+
+#[test]
+fn test_input_that_cannot_be_lowered_is_an_error() {
+    let errors = |source: &str| {
+        let mut lexer = myula::frontend::lexer::Lexer::new(source);
+        let mut parser = myula::frontend::parser::Parser::new(&mut lexer);
+        let program = parser.parse();
+        assert!(parser.get_err().is_empty(), "{:#?}", parser.get_err());
+        let mut ir_gen = myula::frontend::ir::IRGenerator::new();
+        ir_gen.generate(&program);
+        format!("{:?}", ir_gen.get_err())
+    };
+
+    assert_eq!(errors("x = +1"), "[UnsupportedOperator(\"+\")]");
+    assert_eq!(
+        errors("function f() return 1 x = 2 end"),
+        "[StatementAfterReturn]"
+    );
+    assert_eq!(errors("if a then return 1 end x = 2 do return end"), "[]");
+    assert_eq!(errors("return 1 return 2"), "[MultipleReturnStatements]");
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use myula::backend::vm::VirtualMachine;
+use myula::backend::vm::heap::GcMode;
+
+#[test]
+fn test_resume_and_yield_pass_values() {
+    let vm = common::run_source(
+        "
+local co = coroutine.create(function(a, b)
+    local c, d = coroutine.yield(a + 1, b + 1)
+    local e = coroutine.yield(c * d)
+    return e .. \"!\", e, 3
+end)
+before = coroutine.status(co)
+local ok, x, y = coroutine.resume(co, 10, 20)
+first_ok, first, second = tostring(ok), x, y
+suspended = coroutine.status(co)
+local _, product = coroutine.resume(co, 4, 5)
+product_seen = product
+local _, result, word, three = coroutine.resume(co, \"done\")
+result_seen, word_seen, three_seen = result, word, three
+after = coroutine.status(co)
+local ok_again, err = coroutine.resume(co)
+again, again_err = tostring(ok_again), err
+
+-- a yield spread into a call or a return passes every value on
+local relay = coroutine.wrap(function()
+    return coroutine.yield()
+end)
+relay()
+local r1, r2 = relay(7, 8)
+relayed = r1 + r2
+",
+    );
+    assert_eq!(common::global_string(&vm, "before"), "suspended");
+    assert_eq!(common::global_string(&vm, "first_ok"), "true");
+    // every value of a yield reaches resume, every argument of resume reaches the yield
+    assert_eq!(common::global_integer(&vm, "first"), 11);
+    assert_eq!(common::global_integer(&vm, "second"), 21);
+    assert_eq!(common::global_integer(&vm, "product_seen"), 20);
+    assert_eq!(common::global_string(&vm, "result_seen"), "done!");
+    assert_eq!(common::global_string(&vm, "word_seen"), "done");
+    assert_eq!(common::global_integer(&vm, "three_seen"), 3);
+    assert_eq!(common::global_string(&vm, "suspended"), "suspended");
+    assert_eq!(common::global_string(&vm, "after"), "dead");
+    // resuming a dead coroutine fails with an error value
+    assert_eq!(common::global_string(&vm, "again"), "false");
+    assert!(common::global_string(&vm, "again_err").contains("dead"));
+    assert_eq!(common::global_integer(&vm, "relayed"), 15);
+}
+
+#[test]
+fn test_wrap_generator_shares_upvalues() {
+    let vm = common::run_source(
+        "
+local count = 0
+local gen = coroutine.wrap(function()
+    local i = 0
+    while i < 3 do
+        i = i + 1
+        count = count + 1
+        coroutine.yield(i * 10)
+    end
+    return \"end\"
+end)
+a = gen()
+b = gen()
+c = gen()
+d = gen()
+seen = count
+
+-- a closure escaping a suspended coroutine keeps writing into its stack
+local bump
+local co = coroutine.create(function()
+    local x = 1
+    bump = function() x = x + 1 return x end
+    coroutine.yield()
+    inside = x
+end)
+coroutine.resume(co)
+bump()
+bump()
+coroutine.resume(co)
+",
+    );
+    assert_eq!(common::global_integer(&vm, "a"), 10);
+    assert_eq!(common::global_integer(&vm, "b"), 20);
+    assert_eq!(common::global_integer(&vm, "c"), 30);
+    assert_eq!(common::global_string(&vm, "d"), "end");
+    assert_eq!(common::global_integer(&vm, "seen"), 3);
+    assert_eq!(common::global_integer(&vm, "inside"), 3);
+}
+
+#[test]
+fn test_errors_kill_the_coroutine() {
+    let vm = common::run_source(
+        "
+local bad = coroutine.create(function() error(\"boom\") end)
+ok = tostring(coroutine.resume(bad))
+status = coroutine.status(bad)
+yieldable = tostring(coroutine.isyieldable())
+",
+    );
+    assert_eq!(common::global_string(&vm, "ok"), "false");
+    assert_eq!(common::global_string(&vm, "status"), "dead");
+    assert_eq!(common::global_string(&vm, "yieldable"), "false");
+
+    let err = common::run_until_error("coroutine.yield(1)\n").expect("yield outside a coroutine");
+    assert!(
+        format!("{:?}", err).contains("outside a coroutine"),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn test_suspended_coroutines_survive_collections() {
+    // created in a function of their own, so no leftover register of the chunk still refers to one
+    let source = "
+function spawn(gens)
+    local n = 1
+    while n <= 50 do
+        local k = n
+        gens[n] = coroutine.wrap(function()
+            local acc = {}
+            local i = 1
+            while true do
+                acc[i] = \"s\" .. tostring(i * k)
+                coroutine.yield(#acc)
+                i = i + 1
+            end
+        end)
+        n = n + 1
+    end
+end
+local gens = {}
+spawn(gens)
+local round = 1
+total = 0
+while round <= 10 do
+    local j = 1
+    while j <= 50 do
+        total = total + gens[j]()
+        j = j + 1
+    end
+    local junk = {}
+    local m = 1
+    while m <= 100 do
+        junk[m] = {v = m, s = \"x\" .. tostring(m)}
+        m = m + 1
+    end
+    round = round + 1
+end
+gens = nil
+collectgarbage()
+";
+    for mode in [GcMode::Full, GcMode::Generational] {
+        for stress in [false, true] {
+            let mut vm = VirtualMachine::new();
+            vm.set_gc_mode(mode);
+            vm.heap.stress = stress;
+            common::run_source_on(&mut vm, source);
+            assert_eq!(common::global_integer(&vm, "total"), 2750);
+            assert_eq!(vm.heap.stats().coroutines, 0);
+        }
+    }
+}
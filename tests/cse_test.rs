@@ -0,0 +1,54 @@
+mod common;
+
+use common::{global_integer, run_source};
+
+fn ir(source: &str, cse: bool) -> String {
+    common::ir_with(source, |ir_gen| ir_gen.with_cse(cse))
+        .get_module()
+        .to_string()
+}
+
+#[test]
+fn test_repeated_field_read_is_looked_up_once() {
+    let source = "
+function twice(t)
+    return t.a + t.a
+end
+x = twice({a = 21})
+";
+    assert_eq!(ir(source, false).matches("MemberOf").count(), 2);
+    let text = ir(source, true);
+    assert_eq!(text.matches("MemberOf").count(), 1, "{}", text);
+    assert_eq!(global_integer(&run_source(source), "x"), 42);
+}
+
+#[test]
+fn test_reads_after_a_store_or_call_are_repeated() {
+    let source = "
+function bump(t)
+    t.a = t.a + 1
+    return t.a
+end
+function after_call(t)
+    local before = t.a
+    bump(t)
+    return before * 10 + t.a
+end
+x = after_call({a = 1})
+";
+    let text = ir(source, true);
+    assert_eq!(text.matches("MemberOf").count(), 4, "{}", text);
+    assert_eq!(global_integer(&run_source(source), "x"), 12);
+}
+
+#[test]
+fn test_nested_call_of_the_same_function() {
+    // both calls load `id`, the inner one returns its result in the register it called
+    let source = "
+local function id(v)
+    return v
+end
+x = id(id(5)) + id(2) * id(2)
+";
+    assert_eq!(global_integer(&run_source(source), "x"), 9);
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use myula::frontend::ir::IRModule;
+
+fn ir(source: &str, dce: bool) -> IRModule {
+    // folding off, so everything removed here is the work of dce
+    common::ir_with(source, |ir_gen| ir_gen.with_const_fold(false).with_dce(dce))
+        .get_module()
+        .clone()
+}
+
+fn block_count(module: &IRModule) -> usize {
+    module.functions.iter().map(|f| f.basic_blocks.len()).sum()
+}
+
+#[test]
+fn test_unreachable_blocks_are_removed() {
+    let source = "
+function pick(c)
+    if c then
+        return 1
+    else
+        return 2
+    end
+    print(\"never\")
+end
+x = pick(true) + pick(false)
+";
+    let module = ir(source, true);
+    assert!(block_count(&module) < block_count(&ir(source, false)));
+    assert!(
+        !module.to_string().contains("never"),
+        "{}",
+        module.to_string()
+    );
+
+    let vm = common::run_source(source);
+    assert_eq!(common::global_integer(&vm, "x"), 3);
+}
+
+#[test]
+fn test_unused_pure_instructions_are_removed() {
+    let source = "
+local a = 1
+do
+    (function() end)
+end
+a == a
+x = a
+";
+    let text = ir(source, true).to_string();
+    assert!(!text.contains("FnProto"), "{}", text);
+    assert!(!text.contains(" eq "), "{}", text);
+
+    let vm = common::run_source(source);
+    assert_eq!(common::global_integer(&vm, "x"), 1);
+
+    // reading an undefined global raises, so it stays even when the value is unused
+    let err =
+        common::run_until_error("missing == 1\n").expect("reading an undefined global must fail");
+    assert!(format!("{:?}", err).contains("missing"), "{:?}", err);
+}
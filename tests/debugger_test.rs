@@ -0,0 +1,123 @@
+use myula::Myula;
+use myula::backend::vm::error::ErrorKind;
+use myula::debugger::Debugger;
+use myula::engine::{EngineError, Value};
+use std::cell::RefCell;
+use std::io::{Cursor, Write};
+use std::rc::Rc;
+
+const ADD: &str = "function add(a, b)\n  local s = a + b\n  return s\nend\nresult = add(1, 2)\n";
+
+// collects what the debugger prints, readable after it was moved into the hook
+#[derive(Clone, Default)]
+struct Transcript(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Transcript {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transcript {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+// run `source` under a debugger fed with `commands`
+fn debug(source: &str, commands: &str) -> (Myula, Result<Vec<Value>, EngineError>, String) {
+    let transcript = Transcript::default();
+    let mut lua = Myula::new();
+    Debugger::new(
+        Box::new(Cursor::new(commands.to_string())),
+        Box::new(transcript.clone()),
+    )
+    .attach(lua.vm_mut());
+    let result = lua.exec(source);
+    (lua, result, transcript.text())
+}
+
+#[test]
+fn test_stops_before_the_first_instruction_and_steps() {
+    let (lua, result, out) = debug(ADD, "s\n\ns 2\nc\n");
+    result.unwrap();
+    assert_eq!(lua.get_global("result").unwrap(), Value::Integer(3));
+
+    let stops: Vec<_> = out.lines().filter(|l| l.contains(" pc ")).collect();
+    assert_eq!(stops.len(), 4, "{}", out);
+    // an empty line repeats the step
+    for (stop, pc) in stops.iter().zip(["pc 000", "pc 001", "pc 002", "pc 004"]) {
+        assert!(stop.contains("_start (") && stop.contains(pc), "{}", out);
+    }
+}
+
+#[test]
+fn test_breakpoint_shows_the_registers_of_the_frame() {
+    let (_, result, out) = debug(ADD, "b add 1\nbl\nc\nr\nbt\nc\n");
+    result.unwrap();
+
+    assert!(out.contains("breakpoint #1 at add pc 1"), "{}", out);
+    assert!(out.contains("breakpoint #1 reached"), "{}", out);
+    let stop = out
+        .lines()
+        .find(|l| l.starts_with("__chunk_1::__local_fn_add_"))
+        .unwrap_or_else(|| panic!("{}", out));
+    assert!(stop.contains("pc 001"), "{}", out);
+    // the arguments are the first locals
+    assert!(out.contains("R0    local  1\n"), "{}", out);
+    assert!(out.contains("R1    local  2\n"), "{}", out);
+    // the caller is one level down
+    // the prompt is not followed by a newline, the input is not echoed
+    let backtrace: Vec<_> = out.lines().filter(|l| l.contains("  #")).collect();
+    assert!(
+        backtrace[1].contains("#0 __chunk_1::__local_fn_add_"),
+        "{}",
+        out
+    );
+    assert!(backtrace[2].contains("#1 __chunk_1::_start"), "{}", out);
+}
+
+#[test]
+fn test_bad_commands_are_reported_and_asked_again() {
+    let (_, result, out) = debug(ADD, "b nowhere 0\nb add 999\nd 1\nfly\nstep x\nc\n");
+    result.unwrap();
+    assert!(out.contains("no function named 'nowhere'"), "{}", out);
+    assert!(
+        out.contains("'add' has no instruction at pc 999"),
+        "{}",
+        out
+    );
+    assert!(out.contains("usage: delete"), "{}", out);
+    assert!(out.contains("unknown command 'fly'"), "{}", out);
+    assert!(out.contains("step takes a positive number"), "{}", out);
+}
+
+#[test]
+fn test_quit_stops_the_script_even_inside_pcall() {
+    let source = "function f()\n  done = true\nend\nok = pcall(f)\nafter = true\n";
+    let (lua, result, _) = debug(source, "b f 0\nc\nq\n");
+    let Err(EngineError::Runtime(err)) = result else {
+        panic!("the script was not stopped");
+    };
+    assert!(
+        matches!(err.kind, ErrorKind::Interrupted(_)),
+        "{:?}",
+        err.kind
+    );
+    assert_eq!(lua.get_global("done").unwrap(), Value::Nil);
+    assert_eq!(lua.get_global("after").unwrap(), Value::Nil);
+}
+
+#[test]
+fn test_end_of_input_runs_the_script_to_its_end() {
+    let (mut lua, result, out) = debug(ADD, "s\n");
+    result.unwrap();
+    assert_eq!(lua.get_global("result").unwrap(), Value::Integer(3));
+    assert_eq!(out.matches("(mdb) ").count(), 2, "{}", out);
+    assert!(lua.vm_mut().hook_mask().is_empty());
+}
@@ -0,0 +1,64 @@
+use myula::frontend::diagnostics::{Diagnostics, EXIT_COMPILE_ERROR, EXIT_SYNTAX_ERROR, Stage};
+use myula::frontend::ir::IRGenerator;
+use myula::frontend::lexer::Lexer;
+use myula::frontend::parser::Parser;
+
+fn diagnose(source: &str) -> Diagnostics {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+    let mut diagnostics = Diagnostics::new();
+    diagnostics.add_parse(&parser);
+    if diagnostics.is_empty() {
+        let mut ir_gen = IRGenerator::new();
+        ir_gen.generate(&program);
+        diagnostics.add_ir(&ir_gen);
+    }
+    diagnostics
+}
+
+fn summary(diagnostics: &Diagnostics) -> Vec<(Stage, usize)> {
+    diagnostics.iter().map(|d| (d.stage, d.line)).collect()
+}
+
+#[test]
+fn test_clean_source_has_no_diagnostics() {
+    let diagnostics = diagnose("local x = 1\nprint(x)\n");
+    assert!(diagnostics.is_empty());
+    assert_eq!(diagnostics.exit_code(), 0);
+}
+
+#[test]
+fn test_lexer_errors_are_reported_with_lines() {
+    // the parser accepts the empty string the lexer recovers with, only the lexer notices
+    let diagnostics = diagnose("print(1)\ns = \"abc\n");
+    assert_eq!(summary(&diagnostics), vec![(Stage::Lexer, 2)]);
+    assert_eq!(
+        diagnostics.iter().next().unwrap().to_string(),
+        "2: unfinished string"
+    );
+    assert_eq!(diagnostics.exit_code(), EXIT_SYNTAX_ERROR);
+}
+
+#[test]
+fn test_lexer_error_comes_before_the_syntax_error_it_causes() {
+    let diagnostics = diagnose("x = 1\ny = 2 $ 3\n");
+    let found = summary(&diagnostics);
+    assert_eq!(found[0], (Stage::Lexer, 2));
+    assert!(found[1..].iter().all(|&(stage, _)| stage == Stage::Parser));
+    assert!(diagnostics.iter().next().unwrap().message.contains("'$'"));
+}
+
+#[test]
+fn test_compile_errors_point_at_the_statement() {
+    let diagnostics = diagnose("print(1)\n\ngoto nowhere\n");
+    assert_eq!(summary(&diagnostics), vec![(Stage::IR, 3)]);
+    assert_eq!(
+        diagnostics.iter().next().unwrap().message,
+        "no visible label 'nowhere' for goto"
+    );
+    assert_eq!(diagnostics.exit_code(), EXIT_COMPILE_ERROR);
+
+    let diagnostics = diagnose("print(1)\nreturn 1\nreturn 2\n");
+    assert_eq!(summary(&diagnostics), vec![(Stage::IR, 3)]);
+}
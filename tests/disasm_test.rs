@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use myula::backend::disasm::{Disassembler, disassemble_module, module_to_json};
+use myula::backend::translator::scanner::Scanner;
+use myula::backend::vm::{FuncMetadata, VirtualMachine};
+use myula::common::opcode::OpCode;
+use myula::frontend::ir::IRGenerator;
+use myula::frontend::lexer::Lexer;
+use myula::frontend::parser::Parser;
+
+fn compile(source: &str) -> HashMap<String, FuncMetadata> {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+    assert!(parser.get_err().is_empty(), "{:#?}", parser.get_err());
+
+    let mut ir_gen = IRGenerator::new();
+    ir_gen.generate(&program);
+
+    let mut scanner = Scanner::new();
+    scanner.global_scan(ir_gen.get_module());
+
+    VirtualMachine::compile(&ir_gen, &mut scanner, true).unwrap()
+}
+
+#[test]
+fn test_listing_resolves_constants_and_lines() {
+    let funcs = compile("greeting = \"hello\"\nprint(greeting)\n");
+    let text = disassemble_module(&funcs);
+
+    assert!(text.starts_with("function _start ("), "{}", text);
+    assert!(text.contains("constants:"), "{}", text);
+    assert!(text.contains("\"hello\""), "{}", text);
+    // the global's name is shown next to the instruction that reads it
+    let get = text
+        .lines()
+        .find(|l| l.contains("GETGLOBAL"))
+        .expect("no GETGLOBAL in the listing");
+    assert!(
+        get.contains("; \"print\"") || get.contains("; \"greeting\""),
+        "{}",
+        get
+    );
+    assert!(get.contains("[   2]"), "{}", get);
+    assert!(text.contains("registers:"), "{}", text);
+}
+
+#[test]
+fn test_loop_jumps_get_targets_and_arrows() {
+    let funcs = compile("local n = 0\nwhile n < 3 do\n  n = n + 1\nend\nresult = n\n");
+    let meta = &funcs["_start"];
+    let dis = Disassembler::new("_start", meta);
+
+    let back_edge = meta
+        .bytecode
+        .iter()
+        .enumerate()
+        .find_map(|(pc, op)| match op {
+            OpCode::Jump { offset } if *offset < 0 => Some(pc),
+            _ => None,
+        })
+        .expect("a while loop jumps backwards");
+    let target = dis.jump_target(back_edge).unwrap();
+    assert!(target < back_edge);
+
+    let text = dis.render();
+    assert!(text.contains(&format!("to {:03}", target)), "{}", text);
+    assert!(text.contains('┌') && text.contains('└'), "{}", text);
+    let target_row = text
+        .lines()
+        .find(|l| l.contains(&format!("> {:03}", target)))
+        .unwrap_or_else(|| panic!("jump target {} is not marked:\n{}", target, text));
+    assert!(!target_row.is_empty());
+}
+
+#[test]
+fn test_register_ranges_cover_their_uses() {
+    let funcs = compile("local a = 1\nlocal b = a + 2\nresult = b\n");
+    let meta = &funcs["_start"];
+    let uses = Disassembler::new("_start", meta).register_uses();
+
+    assert!(!uses.is_empty());
+    for range in uses.values() {
+        assert!(range.first <= range.last);
+        assert!(range.last < meta.bytecode.len());
+    }
+}
+
+#[test]
+fn test_json_lists_every_function() {
+    let funcs =
+        compile("local function twice(x)\n  return x * 2\nend\nprint(\"a\\\"b\", twice(4))\n");
+    let json = module_to_json(&funcs);
+
+    assert!(
+        json.starts_with("{\"functions\":[{\"name\":\"_start\""),
+        "{}",
+        json
+    );
+    for name in funcs.keys() {
+        assert!(json.contains(&format!("\"name\":\"{}\"", name)), "{}", json);
+    }
+    assert!(
+        json.contains("\"type\":\"integer\",\"value\":2"),
+        "{}",
+        json
+    );
+    assert!(json.contains("\"op\":\"RETURN\""), "{}", json);
+    assert!(json.contains("\"line\":2"), "{}", json);
+    // quotes and backslashes in strings are escaped
+    assert!(json.contains("\"value\":\"a\\\\\\\"b\""), "{}", json);
+    assert_eq!(json.matches('{').count(), json.matches('}').count());
+}
+
+#[test]
+fn test_fused_opcodes_are_selected() {
+    let funcs = compile("local n = 0\nwhile n < 10 do\n  n = n + 1\nend\nresult = n - 2\n");
+    let meta = &funcs["_start"];
+
+    let has = |pred: fn(&OpCode) -> bool| meta.bytecode.iter().any(pred);
+    assert!(has(|op| matches!(op, OpCode::AddK { .. })));
+    assert!(has(|op| matches!(op, OpCode::SubK { .. })));
+    assert!(has(|op| matches!(op, OpCode::JumpIfFalse { .. })));
+    // the folded literals are never loaded into a register
+    assert!(!has(|op| matches!(
+        op,
+        OpCode::Add { .. } | OpCode::Sub { .. }
+    )));
+
+    let dis = Disassembler::new("_start", meta);
+    let branch = meta
+        .bytecode
+        .iter()
+        .position(|op| matches!(op, OpCode::JumpIfFalse { .. }))
+        .unwrap();
+    assert!(dis.jump_target(branch).unwrap() > branch);
+
+    let text = dis.render();
+    assert!(text.contains("ADDK"), "{}", text);
+    assert!(text.contains("JMPFALSE"), "{}", text);
+}
+
+#[test]
+fn test_every_opcode_maps_back_to_its_source() {
+    let source = "function f(a, b)\n    return a + b * c\nend\nprint(f(1, 2))\n";
+    let funcs = compile(source);
+    for meta in funcs.values() {
+        assert_eq!(meta.span_info.len(), meta.bytecode.len());
+        assert!(meta.span_info.iter().all(|span| span.end <= source.len()));
+    }
+
+    let (_, f) = funcs.iter().find(|(name, _)| name.contains("_f_")).unwrap();
+    let span_of = |matches: fn(&OpCode) -> bool| {
+        let pc = f.bytecode.iter().position(matches).unwrap();
+        &source[f.span_info[pc].start..f.span_info[pc].end]
+    };
+    assert_eq!(span_of(|op| matches!(op, OpCode::Mul { .. })), "b * c");
+    assert_eq!(span_of(|op| matches!(op, OpCode::Add { .. })), "a + b * c");
+    assert_eq!(span_of(|op| matches!(op, OpCode::GetGlobal { .. })), "c");
+    assert_eq!(
+        span_of(|op| matches!(op, OpCode::Return { .. })),
+        "return a + b * c"
+    );
+
+    let start = &funcs["_start"];
+    let call = start
+        .bytecode
+        .iter()
+        .position(|op| matches!(op, OpCode::Call { .. }))
+        .unwrap();
+    let span = start.span_info[call];
+    assert_eq!(&source[span.start..span.end], "f(1, 2)");
+}
@@ -0,0 +1,192 @@
+use myula::Myula;
+use myula::engine::{Engine, EngineError, Value};
+
+#[test]
+fn test_eval_expr_config_table() {
+    let engine = Engine::new();
+    let config = engine
+        .eval_expr("{width = 800, height = 600, title = \"main\" .. \" window\", 1, 2}")
+        .expect("config must evaluate");
+
+    assert_eq!(config.get("width").and_then(Value::as_number), Some(800.0));
+    assert_eq!(config.get("height").and_then(Value::as_number), Some(600.0));
+    assert_eq!(
+        config.get("title").and_then(Value::as_str),
+        Some("main window")
+    );
+    assert_eq!(config.index(1).and_then(Value::as_number), Some(1.0));
+
+    assert_eq!(engine.eval_expr("1 + 2 * 3").unwrap(), Value::Number(7.0));
+}
+
+#[test]
+fn test_eval_expr_sandbox() {
+    let engine = Engine::new();
+    assert!(matches!(
+        engine.eval_expr("tostring(1)"),
+        Err(EngineError::Sandbox(_))
+    ));
+    assert!(matches!(
+        engine.eval_expr("{on_load = function() end}"),
+        Err(EngineError::Sandbox(_))
+    ));
+    // statements cannot be smuggled past the wrapping parentheses
+    assert!(engine.eval_expr("1) x = (2").is_err());
+
+    let engine = Engine::new().with_calls(true);
+    assert_eq!(
+        engine.eval_expr("tostring(42)").unwrap(),
+        Value::String("42".into())
+    );
+}
+
+#[test]
+fn test_value_display_matches_tostring() {
+    let engine = Engine::new();
+    for expr in ["nil", "true", "10 / 2", "1 / 3", "0.1", "-0.5", "\"text\""] {
+        let shown = engine.eval_expr(expr).unwrap().display_lua();
+        let expected = Engine::new()
+            .with_calls(true)
+            .eval_expr(&format!("tostring({})", expr))
+            .unwrap();
+        assert_eq!(Some(shown.as_str()), expected.as_str(), "{}", expr);
+    }
+
+    // the snapshot keeps the address the table had inside the VM
+    let engine = Engine::new().with_calls(true).with_functions(true);
+    let pair = engine
+        .eval_expr("(function() local t = {} return {value = t, shown = tostring(t)} end)()")
+        .unwrap();
+    let shown = pair.get("value").unwrap().display_lua();
+    assert!(shown.starts_with("table: 0x"));
+    assert_eq!(
+        Some(shown.as_str()),
+        pair.get("shown").and_then(Value::as_str)
+    );
+}
+
+#[test]
+fn test_myula_globals_and_calls() {
+    let mut lua = Myula::new();
+    lua.set_global("base", &Value::Integer(10)).unwrap();
+    lua.set_global(
+        "config",
+        &Value::Table {
+            addr: 0,
+            entries: vec![(Value::String("name".into()), Value::String("demo".into()))],
+        },
+    )
+    .unwrap();
+
+    let chunk = Myula::compile(
+        r#"
+        local calls = 0
+        function add(a, b)
+            calls = calls + 1
+            return base + a + b
+        end
+        function count()
+            return calls
+        end
+        title = string.upper(config.name)
+        return base * 2
+    "#,
+    )
+    .unwrap();
+    assert_eq!(lua.run(&chunk).unwrap(), vec![Value::Integer(20)]);
+    assert_eq!(
+        lua.get_global("title").unwrap(),
+        Value::String("DEMO".into())
+    );
+    assert_eq!(lua.get_global("missing").unwrap(), Value::Nil);
+
+    assert_eq!(
+        lua.call("add", &[Value::Integer(1), Value::Number(0.5)])
+            .unwrap(),
+        Value::Number(11.5)
+    );
+
+    // a second chunk sees the globals of the first, and its closures keep working
+    lua.exec("function add2(x) return add(x, x) end").unwrap();
+    assert_eq!(
+        lua.call("add2", &[Value::Integer(3)]).unwrap(),
+        Value::Integer(16)
+    );
+    assert_eq!(lua.call("count", &[]).unwrap(), Value::Integer(2));
+
+    // the same chunk can be run again
+    lua.run(&chunk).unwrap();
+    assert_eq!(lua.call("count", &[]).unwrap(), Value::Integer(0));
+}
+
+#[test]
+fn test_myula_errors() {
+    assert!(matches!(
+        Myula::compile("x = = 1"),
+        Err(EngineError::Parse(_))
+    ));
+    assert!(matches!(
+        Myula::compile("goto nowhere"),
+        Err(EngineError::Compile(_))
+    ));
+
+    let mut lua = Myula::new();
+    lua.exec("function fail(msg) error(msg) end").unwrap();
+    assert!(matches!(
+        lua.call("fail", &[Value::String("boom".into())]),
+        Err(EngineError::Runtime(_))
+    ));
+    assert!(matches!(
+        lua.call("nope", &[]),
+        Err(EngineError::Runtime(_))
+    ));
+    assert!(matches!(
+        lua.set_global("f", &Value::Function { addr: 0 }),
+        Err(EngineError::Conversion(_))
+    ));
+
+    // a failed call leaves the state usable
+    assert_eq!(lua.exec("return 1 + 1").unwrap(), vec![Value::Integer(2)]);
+}
+
+#[test]
+fn test_chunk_runs_on_worker_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<myula::engine::Chunk>();
+    assert_send_sync::<myula::backend::vm::FuncMetadata>();
+
+    let chunk = std::sync::Arc::new(
+        Myula::compile(
+            r#"
+            function fib(n)
+                if n < 2 then return n end
+                return fib(n - 1) + fib(n - 2)
+            end
+            return fib(base) .. "!"
+        "#,
+        )
+        .unwrap(),
+    );
+
+    let workers: Vec<_> = (10..14)
+        .map(|n| {
+            let chunk = chunk.clone();
+            std::thread::spawn(move || {
+                let mut lua = Myula::new();
+                lua.set_global("base", &Value::Integer(n)).unwrap();
+                let first = lua.run(&chunk).unwrap();
+                // the constants were interned into this thread's VM, running again reuses nothing stale
+                assert_eq!(lua.run(&chunk).unwrap(), first);
+                first
+            })
+        })
+        .collect();
+    let results: Vec<_> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+    assert_eq!(
+        results,
+        ["55!", "89!", "144!", "233!"]
+            .iter()
+            .map(|s| vec![Value::String(s.to_string())])
+            .collect::<Vec<_>>()
+    );
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use myula::backend::vm::VirtualMachine;
+use myula::common::object::LuaValue;
+
+#[test]
+fn test_globals_are_fields_of_g() {
+    let vm = common::run_source(
+        r#"
+        _G.x = 1
+        _G["y"] = x + 1
+        z = _G.y * 10
+        same = _G._G == _G and _G.print == print
+        local name = "dyn" .. "amic"
+        _G[name] = 4
+        "#,
+    );
+    assert_eq!(common::global_integer(&vm, "x"), 1);
+    assert_eq!(common::global_integer(&vm, "y"), 2);
+    assert_eq!(common::global_integer(&vm, "z"), 20);
+    assert_eq!(common::global_integer(&vm, "dynamic"), 4);
+    assert!(matches!(
+        vm.get_global("same"),
+        Some(LuaValue::Boolean(true))
+    ));
+}
+
+#[test]
+fn test_missing_globals_go_to_the_index_of_g() {
+    let vm = common::run_source(
+        r#"
+        present = "here"
+        setmetatable(_G, { __index = function(t, name) return name .. "?" end })
+        a = missing
+        b = present
+        setmetatable(_G, { __index = { fallback = 7 } })
+        c = fallback
+        "#,
+    );
+    assert_eq!(common::global_string(&vm, "a"), "missing?");
+    assert_eq!(common::global_string(&vm, "b"), "here");
+    assert_eq!(common::global_integer(&vm, "c"), 7);
+}
+
+#[test]
+fn test_new_globals_go_to_the_newindex_of_g() {
+    let vm = common::run_source(
+        r#"
+        store = {}
+        existing = 1
+        seen = {}
+        setmetatable(_G, { __newindex = store })
+        fresh = 5
+        existing = 2
+        setmetatable(_G, { __newindex = function(t, k, v) seen[#seen + 1] = k .. "=" .. v end })
+        other = 3
+        "#,
+    );
+    assert!(common::global_is_nil(&vm, "fresh"));
+    assert_eq!(common::global_integer(&vm, "existing"), 2);
+    let Some(LuaValue::Table(store)) = vm.get_global("store") else {
+        panic!("store is not a table");
+    };
+    assert_eq!(vm.heap.data(*store).data.len(), 1);
+    // the function only recorded `other`, it did not store it
+    assert!(common::global_is_nil(&vm, "other"));
+    let Some(LuaValue::Table(seen)) = vm.get_global("seen") else {
+        panic!("seen is not a table");
+    };
+    assert_eq!(vm.heap.data(*seen).len(), 1);
+}
+
+#[test]
+fn test_host_sees_what_scripts_put_into_g() {
+    let mut vm = VirtualMachine::new();
+    common::run_source_on(&mut vm, "_G.answer = 42");
+    assert_eq!(common::global_integer(&vm, "answer"), 42);
+
+    vm.set_global("answer", LuaValue::Integer(43));
+    common::run_source_on(&mut vm, "copy = _G.answer");
+    assert_eq!(common::global_integer(&vm, "copy"), 43);
+}
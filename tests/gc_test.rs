@@ -0,0 +1,390 @@
+mod common;
+
+use myula::backend::vm::VirtualMachine;
+use myula::backend::vm::config::VmConfig;
+use myula::backend::vm::heap::GcMode;
+use myula::common::object::{LuaTable, LuaValue};
+
+// young values stored into an old table, an old closed upvalue and as the metatable of an old table
+const CHURN: &str = "
+keep = {}
+local function make()
+    local cap = \"start\"
+    return function(v)
+        if v then
+            cap = v
+        end
+        return cap
+    end
+end
+f = make()
+old = {}
+local n = 0
+while n < 3000 do
+    n = n + 1
+    local t = {}
+    t.s = \"str\" .. n
+    if n % 100 == 0 then
+        keep[n / 100] = t
+        f(\"cap\" .. n)
+        setmetatable(old, {tag = \"mt\" .. n})
+    end
+end
+-- more garbage, so the last stores above live through minor collections too
+while n < 4000 do
+    n = n + 1
+    local t = {s = \"tmp\" .. n}
+end
+last = keep[30].s
+first = keep[1].s
+captured = f()
+tag = getmetatable(old).tag
+";
+
+#[test]
+fn test_generational_collection_keeps_reachable_objects() {
+    let mut vm = VirtualMachine::new();
+    vm.set_gc_mode(GcMode::Generational);
+    // collect the nursery every few kilobytes so objects get old while the loop runs
+    vm.heap.nursery_threshold = 4 * 1024;
+    common::run_source_on(&mut vm, CHURN);
+
+    assert_eq!(common::global_string(&vm, "first"), "str100");
+    assert_eq!(common::global_string(&vm, "last"), "str3000");
+    assert_eq!(common::global_string(&vm, "captured"), "cap3000");
+    assert_eq!(common::global_string(&vm, "tag"), "mt3000");
+    // minor collections ran and freed the temporaries, 4000 tables were created
+    assert!(vm.heap.nursery_allocated <= vm.heap.nursery_threshold);
+    assert!(vm.heap.stats().tables < 1000, "{:?}", vm.heap.stats());
+
+    let full = common::run_source(CHURN);
+    assert_eq!(common::global_string(&full, "captured"), "cap3000");
+
+    // switching back hands the young objects to the full collector, reset frees them all
+    vm.set_gc_mode(GcMode::Full);
+    assert!(vm.heap.nursery.is_null());
+    vm.reset(false);
+    // only the empty global table is left, as in a new VM
+    assert_eq!(
+        vm.heap.total_allocated,
+        VirtualMachine::new().heap.total_allocated
+    );
+}
+
+#[test]
+fn test_collectgarbage_builtin() {
+    let mut vm = VirtualMachine::new();
+    common::run_source_on(
+        &mut vm,
+        "
+local i = 0
+while i < 500 do
+    local t = {s = \"garbage \" .. i}
+    i = i + 1
+end
+before = collectgarbage(\"count\")
+collected = collectgarbage()
+after = collectgarbage(\"count\")
+old_pause = collectgarbage(\"setpause\", 150)
+stepped = collectgarbage(\"step\")
+",
+    );
+    assert!(common::global_number(&vm, "after") < common::global_number(&vm, "before"));
+    assert_eq!(common::global_integer(&vm, "collected"), 0);
+    assert_eq!(common::global_integer(&vm, "old_pause"), 200);
+    assert_eq!(vm.heap.pause, 150);
+    // the explicit collection and the step, both full ones in the default mode
+    assert!(vm.stats.full_collections >= 2, "{:?}", vm.stats);
+    assert_eq!(vm.stats.minor_collections, 0);
+    assert!(vm.stats.instructions > 500 * 5, "{:?}", vm.stats);
+
+    vm.reset(true);
+    assert_eq!(vm.stats, Default::default());
+
+    let stats = vm.heap.stats();
+    assert_eq!(stats.bytes, vm.heap.total_allocated);
+    // the standard library tables at least
+    assert!(stats.tables >= 4, "{:?}", stats);
+
+    let err =
+        common::run_until_error("collectgarbage(\"nope\")").expect("unknown option must fail");
+    assert!(
+        err.get_message().contains("invalid option 'nope'"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_stress_mode_collects_at_every_allocation() {
+    for mode in [GcMode::Full, GcMode::Generational] {
+        let mut vm = VirtualMachine::new();
+        vm.set_gc_mode(mode);
+        vm.heap.stress = true;
+        common::run_source_on(&mut vm, CHURN);
+
+        assert_eq!(common::global_string(&vm, "first"), "str100");
+        assert_eq!(common::global_string(&vm, "last"), "str3000");
+        assert_eq!(common::global_string(&vm, "captured"), "cap3000");
+        assert_eq!(common::global_string(&vm, "tag"), "mt3000");
+        // nothing allocated by the last instructions is left uncollected
+        assert_eq!(vm.heap.allocs_since_collection, 0);
+    }
+}
+
+#[test]
+fn test_handles_stay_valid_while_rooted() {
+    let mut vm = VirtualMachine::new();
+    common::run_source_on(&mut vm, "t = {name = \"kept\"}");
+    let Some(LuaValue::Table(t)) = vm.get_global("t").cloned() else {
+        panic!("t is not a table");
+    };
+    vm.collect_garbage();
+    vm.collect_garbage();
+    let key = vm.heap.alloc_str("name").unwrap();
+    let LuaValue::String(name) = vm.heap.data(t).get(&LuaValue::String(key)) else {
+        panic!("t.name is not a string");
+    };
+    assert_eq!(name.get(), "kept");
+}
+
+#[test]
+fn test_open_upvalues_outlive_their_closures() {
+    // the closure is gone before the frame returns and closes the upvalue it captured
+    let vm = common::run_source(
+        "
+local function f()
+    local x = 1
+    local g = function() return x end
+    g = nil
+    collectgarbage()
+    x = 2
+    return x
+end
+result = f()
+",
+    );
+    assert_eq!(common::global_integer(&vm, "result"), 2);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "use after free")]
+fn test_collected_handle_is_detected() {
+    let mut vm = VirtualMachine::new();
+    let t = vm.heap.alloc_table(LuaTable::new()).unwrap();
+    // nothing refers to the table, so it is freed
+    vm.collect_garbage();
+    vm.heap.data(t);
+}
+
+#[test]
+fn test_root_scope_keeps_values_until_it_ends() {
+    let mut vm = VirtualMachine::new();
+    let kept = vm.with_roots(|vm| {
+        let s = vm
+            .alloc(|heap| heap.alloc_str("held by native code"))
+            .unwrap();
+        vm.root(LuaValue::String(s));
+        vm.with_roots(|vm| {
+            vm.collect_garbage();
+        });
+        vm.collect_garbage();
+        s.get().clone()
+    });
+    assert_eq!(kept, "held by native code");
+
+    // the scope is gone, nothing refers to the string any more
+    vm.collect_garbage();
+    assert!(!vm.heap.string_pool.contains_key("held by native code"));
+}
+
+#[test]
+fn test_allocation_collects_when_the_heap_limit_is_reached() {
+    // the threshold is never reached, only the limit makes room
+    let config = VmConfig::default()
+        .with_heap_limit(512 * 1024)
+        .with_gc_threshold(usize::MAX);
+    let mut vm = VirtualMachine::with_config(config);
+    common::run_source_on(
+        &mut vm,
+        "
+local i = 0
+local words = 0
+while i < 20000 do
+    i = i + 1
+    local line = \"item \" .. tostring(i) .. \" of many\"
+    local next_word = string.gmatch(line, \"%a+\")
+    local w = next_word()
+    while w do
+        words = words + 1
+        w = next_word()
+    end
+    local t = {n = i}
+end
+count = words
+",
+    );
+    assert_eq!(common::global_integer(&vm, "count"), 60000);
+    assert!(vm.stats.full_collections > 0, "{:?}", vm.stats);
+    assert!(vm.heap.max_allocated <= 512 * 1024);
+}
+
+// entries left in the global table `name`
+fn entry_count(vm: &VirtualMachine, name: &str) -> usize {
+    let Some(LuaValue::Table(t)) = vm.get_global(name) else {
+        panic!("{} is not a table", name);
+    };
+    vm.heap.data(*t).iter().count()
+}
+
+#[test]
+fn test_weak_tables_drop_unreachable_entries() {
+    // under stress the minor collections clear entries of young weak tables as well
+    for (mode, stress) in [
+        (GcMode::Full, false),
+        (GcMode::Generational, false),
+        (GcMode::Generational, true),
+    ] {
+        let mut vm = VirtualMachine::new();
+        vm.set_gc_mode(mode);
+        vm.heap.stress = stress;
+        common::run_source_on(
+            &mut vm,
+            "
+kept = {}
+values = setmetatable({}, {__mode = \"v\"})
+keys = setmetatable({}, {__mode = \"k\"})
+strong = {}
+local function fill()
+    local i = 1
+    while i <= 10 do
+        values[i] = {}
+        keys[{}] = i
+        strong[i] = {}
+        i = i + 1
+    end
+    values.kept = kept
+    values.name = \"strings are values\"
+    keys[kept] = \"kept\"
+end
+fill()
+collectgarbage()
+name = values.name
+same = values.kept == kept
+by_key = keys[kept]
+",
+        );
+        assert_eq!(entry_count(&vm, "values"), 2, "{:?}", mode);
+        assert_eq!(entry_count(&vm, "keys"), 1, "{:?}", mode);
+        assert_eq!(entry_count(&vm, "strong"), 10, "{:?}", mode);
+        assert_eq!(common::global_string(&vm, "name"), "strings are values");
+        assert_eq!(vm.get_global("same"), Some(&LuaValue::Boolean(true)));
+        assert_eq!(common::global_string(&vm, "by_key"), "kept");
+    }
+}
+
+#[test]
+fn test_gc_metamethod_runs_once_for_unreachable_tables() {
+    let mut vm = VirtualMachine::new();
+    common::run_source_on(
+        &mut vm,
+        "
+log = \"\"
+local mt = {__gc = function(t) log = log .. t.name .. \" \" end}
+local function make(name)
+    return setmetatable({name = name}, mt)
+end
+make(\"a\")
+make(\"b\")
+live = make(\"live\")
+collectgarbage()
+after_first = log
+collectgarbage()
+after_second = log
+-- __gc set after setmetatable does not count
+late = setmetatable({}, {})
+getmetatable(late).__gc = function() log = log .. \"late \" end
+late = nil
+collectgarbage()
+final = log
+",
+    );
+    // the most recently registered table is finalized first
+    assert_eq!(common::global_string(&vm, "after_first"), "b a ");
+    assert_eq!(common::global_string(&vm, "after_second"), "b a ");
+    assert_eq!(common::global_string(&vm, "final"), "b a ");
+}
+
+#[test]
+fn test_finalizer_can_resurrect_its_table() {
+    let mut vm = VirtualMachine::new();
+    common::run_source_on(
+        &mut vm,
+        "
+saved = nil
+weak = setmetatable({}, {__mode = \"v\"})
+local function make()
+    local t = setmetatable({tag = \"back\"}, {__gc = function(t) saved = t end})
+    weak[1] = t
+end
+make()
+collectgarbage()
+collectgarbage()
+tag = saved.tag
+-- a resurrected object leaves the weak values before its finalizer runs
+gone = weak[1] == nil
+-- an error in a finalizer does not stop the script
+setmetatable({}, {__gc = function() error(\"ignored\") end})
+collectgarbage()
+reached = true
+",
+    );
+    assert_eq!(common::global_string(&vm, "tag"), "back");
+    assert_eq!(vm.get_global("gone"), Some(&LuaValue::Boolean(true)));
+    assert_eq!(vm.get_global("reached"), Some(&LuaValue::Boolean(true)));
+}
+
+#[test]
+fn test_long_runtime_strings_are_not_interned() {
+    let mut vm = common::run_source(
+        "
+        local prefix = \"a string long enough not to be interned: \"
+        local t = {}
+        local i = 0
+        while i < 500 do
+            i = i + 1
+            t[i] = prefix .. i
+        end
+        long = prefix .. 7
+        same = long == t[7]
+        t[long] = \"found\"
+        by_content = t[prefix .. 7]
+        same_as_constant = long == \"a string long enough not to be interned: 7\"
+        short = (\"ab\" .. 1) == \"ab1\"
+        long = nil
+        ",
+    );
+    assert!(
+        vm.heap.string_pool.len() < 100,
+        "{} strings interned",
+        vm.heap.string_pool.len()
+    );
+    for name in ["same", "same_as_constant", "short"] {
+        assert_eq!(
+            vm.get_global(name),
+            Some(&LuaValue::Boolean(true)),
+            "{}",
+            name
+        );
+    }
+    assert_eq!(common::global_string(&vm, "by_content"), "found");
+
+    // freeing the runtime copies leaves the interned constant with the same content alone
+    vm.collect_garbage();
+    assert!(
+        vm.heap
+            .string_pool
+            .contains_key("a string long enough not to be interned: 7")
+    );
+}
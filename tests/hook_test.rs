@@ -0,0 +1,231 @@
+use myula::Myula;
+use myula::backend::vm::error::ErrorKind;
+use myula::backend::vm::hook::{HookEvent, HookMask};
+use myula::common::object::LuaValue;
+use myula::engine::{EngineError, Value};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const FIB: &str = "function fib(n)\n  if n < 2 then return n end\n  return fib(n - 1) + fib(n - 2)\nend\nresult = fib(10)\n";
+
+#[test]
+fn test_line_hook_steps_through_every_line() {
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let seen = lines.clone();
+    let mut lua = Myula::new();
+    lua.vm_mut().set_hook(HookMask::LINE, move |vm, event| {
+        if let HookEvent::Line(line) = event {
+            assert_eq!(vm.frame_info(0).unwrap().line, Some(line));
+            seen.borrow_mut().push(line);
+        }
+        Ok(())
+    });
+    lua.exec("local i = 0\nwhile i < 2 do\n  i = i + 1\nend\nresult = i\n")
+        .unwrap();
+
+    // the loop condition is reported again on every jump back
+    assert_eq!(*lines.borrow(), [1, 2, 3, 2, 3, 2, 5]);
+}
+
+#[test]
+fn test_call_and_return_hooks_pair_up() {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let seen = events.clone();
+    let mut lua = Myula::new();
+    lua.vm_mut()
+        .set_hook(HookMask::CALL | HookMask::RETURN, move |vm, event| {
+            let name = vm.frame_info(0).unwrap().func_name;
+            seen.borrow_mut().push((event, name));
+            Ok(())
+        });
+    lua.exec(FIB).unwrap();
+    assert_eq!(lua.get_global("result").unwrap(), Value::Integer(55));
+
+    let events = events.borrow();
+    let count = |kind: HookEvent, fib: bool| {
+        events
+            .iter()
+            .filter(|(event, name)| *event == kind && name.contains("fib") == fib)
+            .count()
+    };
+    assert_eq!(count(HookEvent::Call, true), 177);
+    assert_eq!(count(HookEvent::Return, true), 177);
+    // the main chunk is entered and left once
+    assert_eq!(count(HookEvent::Call, false), 1);
+    assert_eq!(count(HookEvent::Return, false), 1);
+    assert_eq!(events.first().unwrap().0, HookEvent::Call);
+    assert_eq!(events.last().unwrap().0, HookEvent::Return);
+}
+
+#[test]
+fn test_breakpoint_inspects_registers_and_stops_the_script() {
+    let found = Rc::new(RefCell::new(None));
+    let seen = found.clone();
+    let mut lua = Myula::new();
+    lua.vm_mut().set_hook(HookMask::LINE, move |vm, event| {
+        if event != HookEvent::Line(3) {
+            return Ok(());
+        }
+        let frame = vm.frame_info(0).unwrap();
+        *seen.borrow_mut() = (0..)
+            .map_while(|reg| vm.frame_register(0, reg))
+            .position(|val| matches!(val, LuaValue::Integer(42)))
+            .map(|reg| (frame.func_name, reg));
+        Err(vm.error(ErrorKind::InternalError("breakpoint".into())))
+    });
+
+    let result = lua.exec("local secret = 40\nsecret = secret + 2\nreached = true\n");
+    match result {
+        Err(EngineError::Runtime(err)) => assert_eq!(err.line(), Some(3)),
+        other => panic!(
+            "expected the breakpoint to stop the script, got {:?}",
+            other.map(|_| ())
+        ),
+    }
+    assert!(found.borrow().is_some());
+    assert_eq!(lua.get_global("reached").unwrap(), Value::Nil);
+}
+
+#[test]
+fn test_count_hook_and_removal() {
+    let ticks = Rc::new(RefCell::new(0u64));
+    let seen = ticks.clone();
+    let mut lua = Myula::new();
+    lua.vm_mut()
+        .set_hook(HookMask::count(100), move |_, event| {
+            assert_eq!(event, HookEvent::Count);
+            *seen.borrow_mut() += 1;
+            Ok(())
+        });
+    lua.exec(FIB).unwrap();
+    let instructions = lua.vm_mut().stats.instructions;
+    assert_eq!(*ticks.borrow(), instructions / 100);
+
+    lua.vm_mut().remove_hook();
+    assert!(lua.vm_mut().hook_mask().is_empty());
+    lua.exec(FIB).unwrap();
+    assert_eq!(*ticks.borrow(), instructions / 100);
+}
+
+#[test]
+fn test_debug_sethook_from_lua() {
+    let mut lua = Myula::new();
+    lua.exec(
+        "log = ''\n\
+         local function note(s) log = log .. s end\n\
+         function add(a, b)\n  return a + b\nend\n\
+         debug.sethook(function(event, line)\n  \
+           if event == 'line' then note(line .. ' ') else note(event .. ' ') end\n\
+         end, 'crl')\n\
+         local x = add(1, 2)\n\
+         debug.sethook()\n\
+         local function quiet() end\n\
+         debug.sethook(quiet, 'cl', 50)\n\
+         local _, hook_mask, hook_count = debug.gethook()\n\
+         debug.sethook()\n\
+         mask, count = hook_mask, hook_count\n\
+         hook = debug.gethook()\n\
+         where = debug.getinfo(1).currentline\n",
+    )
+    .unwrap();
+    // the calls made by the hook itself (note) are not reported
+    assert_eq!(
+        lua.get_global("log").unwrap(),
+        Value::String("9 call 4 return 10 ".into())
+    );
+    assert_eq!(lua.get_global("mask").unwrap(), Value::String("cl".into()));
+    assert_eq!(lua.get_global("count").unwrap(), Value::Integer(50));
+    assert_eq!(lua.get_global("hook").unwrap(), Value::Nil);
+    assert_eq!(lua.get_global("where").unwrap(), Value::Integer(17));
+
+    lua.vm_mut().set_hook(HookMask::LINE, |_, _| Ok(()));
+    lua.exec("hook = debug.gethook()").unwrap();
+    assert_eq!(
+        lua.get_global("hook").unwrap(),
+        Value::String("external hook".into())
+    );
+}
+
+#[test]
+fn test_debug_getlocal_and_traceback() {
+    let mut lua = Myula::new();
+    lua.exec(
+        "function add(a, b)\n  local sum = a + b\n  \
+           first = debug.getlocal(1, 1)\n  \
+           local name, value = debug.getlocal(1, 2)\n  \
+           second, second_value = name, value\n  \
+           third = debug.getlocal(1, 3)\n  \
+           missing = debug.getlocal(1, 9)\n  \
+           trace = debug.traceback('oops')\n  \
+           return sum\nend\n\
+         add(1, 2)\n",
+    )
+    .unwrap();
+    assert_eq!(lua.get_global("first").unwrap(), Value::String("a".into()));
+    assert_eq!(lua.get_global("second").unwrap(), Value::String("b".into()));
+    assert_eq!(lua.get_global("second_value").unwrap(), Value::Integer(2));
+    assert_eq!(
+        lua.get_global("third").unwrap(),
+        Value::String("sum".into())
+    );
+    assert_eq!(lua.get_global("missing").unwrap(), Value::Nil);
+
+    let trace = lua.get_global("trace").unwrap();
+    let lines: Vec<_> = trace.as_str().unwrap().lines().collect();
+    assert_eq!(lines[..2], ["oops", "stack traceback:"]);
+    // traceback itself is not part of it, add is the most recent call
+    assert!(
+        lines[2].contains("__local_fn_add_") && lines[2].ends_with(":8)"),
+        "{:?}",
+        lines
+    );
+    assert!(
+        lines[3].contains("_start()") && lines[3].ends_with(":11)"),
+        "{:?}",
+        lines
+    );
+    assert_eq!(lines.len(), 4);
+
+    let err = lua.exec("debug.getlocal(5, 1)").unwrap_err();
+    assert!(err.to_string().contains("level out of range"), "{}", err);
+}
+
+#[test]
+fn test_debug_getinfo_names_functions_as_in_the_source() {
+    let mut lua = Myula::new();
+    lua.exec(
+        "local function f()\n  return debug.getinfo(1).name, debug.getinfo(2).name\nend\n\
+         local inner, outer = f()\n\
+         f_name, caller_name = inner, outer\n\
+         function g()\n  return debug.getinfo(1).name\nend\n\
+         g_name = g()\n",
+    )
+    .unwrap();
+    assert_eq!(lua.get_global("f_name").unwrap(), Value::String("f".into()));
+    assert_eq!(lua.get_global("g_name").unwrap(), Value::String("g".into()));
+    assert_eq!(
+        lua.get_global("caller_name").unwrap(),
+        Value::String("_start".into())
+    );
+}
+
+#[test]
+fn test_frame_local_reads_values_by_slot_name() {
+    let found = Rc::new(RefCell::new(Vec::new()));
+    let seen = found.clone();
+    let mut lua = Myula::new();
+    lua.vm_mut().set_hook(HookMask::LINE, move |vm, event| {
+        if event == HookEvent::Line(3) {
+            *seen.borrow_mut() = (1..).map_while(|n| vm.frame_local(0, n)).collect();
+        }
+        Ok(())
+    });
+    lua.exec("local x = 40\nlocal y = x + 2\nz = y\n").unwrap();
+
+    let found = found.borrow();
+    assert_eq!(found.len(), 2);
+    assert_eq!(found[0].0, "x");
+    assert!(matches!(found[0].1, LuaValue::Integer(40)));
+    assert_eq!(found[1].0, "y");
+    assert!(matches!(found[1].1, LuaValue::Integer(42)));
+}
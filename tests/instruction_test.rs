@@ -0,0 +1,150 @@
+mod common;
+
+use myula::common::instruction::{Instruction, OP_JUMP, OP_WIDE, encode_all};
+use myula::common::opcode::{OpCode, UnaryOpType};
+
+#[test]
+fn test_every_opcode_round_trips() {
+    let ops = vec![
+        OpCode::LoadK {
+            dest: 3,
+            const_idx: 65535,
+        },
+        OpCode::LoadNil { dest: 255 },
+        OpCode::LoadBool {
+            dest: 1,
+            value: true,
+        },
+        OpCode::Move { dest: 4, src: 200 },
+        OpCode::GetGlobal {
+            dest: 0,
+            name_idx: 300,
+        },
+        OpCode::SetGlobal {
+            name_idx: 7,
+            src: 9,
+        },
+        OpCode::GetUpVal {
+            dest: 2,
+            upval_idx: 1,
+        },
+        OpCode::SetUpVal {
+            upval_idx: 1,
+            src: 2,
+        },
+        OpCode::Add {
+            dest: 1,
+            left: 2,
+            right: 3,
+        },
+        OpCode::Concat {
+            dest: 10,
+            left: 11,
+            right: 12,
+        },
+        OpCode::ConcatN {
+            dest: 3,
+            start: 20,
+            count: 4,
+        },
+        OpCode::SetList {
+            table: 7,
+            count: 50,
+            batch: 2,
+        },
+        OpCode::SubK {
+            dest: 1,
+            left: 1,
+            const_idx: 255,
+        },
+        OpCode::UnOp {
+            dest: 5,
+            src: 6,
+            op: UnaryOpType::Len,
+        },
+        OpCode::Ge {
+            dest: 0,
+            left: 1,
+            right: 2,
+        },
+        OpCode::Test { reg: 8 },
+        OpCode::Jump { offset: -8388608 },
+        OpCode::Jump { offset: 8388607 },
+        OpCode::JumpIfFalse {
+            reg: 4,
+            offset: -32768,
+        },
+        OpCode::NewTable {
+            dest: 0,
+            size_array: 3,
+            size_hash: 2,
+        },
+        OpCode::SetField {
+            table: 1,
+            key: 2,
+            value: 3,
+        },
+        OpCode::FnProto {
+            dest: 6,
+            proto_idx: 1,
+        },
+        OpCode::Call {
+            func_reg: 2,
+            argc: 255,
+            retc: 0,
+        },
+        OpCode::GetResult {
+            dest: 9,
+            index: 254,
+        },
+        OpCode::Return { start: 3, count: 1 },
+        OpCode::TailCall {
+            func_reg: 4,
+            argc: 2,
+        },
+        OpCode::Halt,
+        OpCode::Switch { reg: 7, table: 300 },
+    ];
+
+    for op in ops {
+        let instr = Instruction::encode(&op).unwrap_or_else(|| panic!("{} does not fit", op));
+        assert_eq!(instr.decode(), Some(op));
+    }
+}
+
+#[test]
+fn test_operands_that_do_not_fit_are_wide() {
+    let code = encode_all(&[
+        OpCode::Move { dest: 256, src: 0 },
+        OpCode::GetField {
+            dest: 0,
+            table: 1,
+            key: 256,
+        },
+        OpCode::JumpIfFalse {
+            reg: 0,
+            offset: 40000,
+        },
+        OpCode::Jump { offset: 1 << 23 },
+        OpCode::Jump { offset: -3 },
+    ]);
+
+    assert!(code[..4].iter().all(|instr| instr.op() == OP_WIDE));
+    assert_eq!(code[4].op(), OP_JUMP);
+    assert_eq!(code[4].sj(), -3);
+    assert_eq!(code[0].decode(), None);
+}
+
+#[test]
+fn test_wide_registers_run_through_the_opcode() {
+    // more locals than a packed register operand can address
+    let mut source = String::new();
+    for i in 0..300 {
+        source.push_str(&format!("local v{} = {}\n", i, i));
+    }
+    source.push_str("total = v0 + v299 + v150\nlast = v299\n");
+
+    let vm = common::run_source(&source);
+    assert_eq!(common::global_integer(&vm, "total"), 449);
+    assert_eq!(common::global_integer(&vm, "last"), 299);
+}
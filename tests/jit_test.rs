@@ -0,0 +1,115 @@
+#![cfg(feature = "jit")]
+
+use myula::Myula;
+use myula::backend::vm::error::ErrorKind;
+use myula::backend::vm::jit::JIT_THRESHOLD;
+use myula::engine::EngineError;
+
+fn hot_loop(body: &str, calls: u32) -> String {
+    format!(
+        "{}\nlocal k = 0\nwhile k < {} do\n  acc = acc + f(k)\n  k = k + 1\nend\n",
+        body, calls
+    )
+}
+
+#[test]
+fn test_hot_numeric_function_runs_natively() {
+    let mut lua = Myula::new();
+    lua.exec(&hot_loop(
+        "acc = 0\n\
+         function f(n)\n\
+           local s = 0\n\
+           local i = 1\n\
+           while i <= n do s = s + i * 2 - 1 i = i + 1 end\n\
+           return s\n\
+         end",
+        200,
+    ))
+    .unwrap();
+    // the sum of the first n odd numbers is n^2
+    let expected: i64 = (0..200).map(|n| n * n).sum();
+    assert_eq!(lua.get_global("acc").unwrap().as_integer(), Some(expected));
+    let stats = lua.vm_mut().stats;
+    assert_eq!(stats.jit_compiled, 1);
+    assert_eq!(stats.jit_calls, (200 - JIT_THRESHOLD + 1) as u64);
+}
+
+#[test]
+fn test_native_code_matches_the_interpreter() {
+    let script = hot_loop(
+        "acc = 0.0\n\
+         function f(n)\n\
+           local m = (n - 100) % 7 + n % -3\n\
+           if not (m > 2) or n == 5 then return m / 4 end\n\
+           return -m * 1.5\n\
+         end",
+        300,
+    );
+    let mut native = Myula::new();
+    native.exec(&script).unwrap();
+    assert!(native.vm_mut().stats.jit_calls > 0);
+
+    // a budget keeps every call interpreted
+    let mut interpreted = Myula::new();
+    interpreted.vm_mut().set_fuel(Some(1_000_000));
+    interpreted.exec(&script).unwrap();
+    assert_eq!(interpreted.vm_mut().stats.jit_calls, 0);
+
+    assert_eq!(
+        native.get_global("acc").unwrap().as_number(),
+        interpreted.get_global("acc").unwrap().as_number()
+    );
+}
+
+#[test]
+fn test_native_code_bails_out_to_the_interpreter_for_errors() {
+    let mut lua = Myula::new();
+    lua.exec(&hot_loop(
+        "acc = 0\nfunction f(n) return n % (n + 1) end",
+        100,
+    ))
+    .unwrap();
+    assert!(lua.vm_mut().stats.jit_calls > 0);
+    match lua.exec("f(-1)") {
+        Err(EngineError::Runtime(err)) => {
+            assert!(matches!(err.kind, ErrorKind::ArithmeticError(_)), "{}", err)
+        }
+        other => panic!("expected modulo by zero, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_functions_outside_the_subset_stay_interpreted() {
+    let mut lua = Myula::new();
+    lua.exec(&hot_loop(
+        "acc = 0\nstep = 2\nfunction f(n) return n + step end",
+        100,
+    ))
+    .unwrap();
+    assert_eq!(lua.get_global("acc").unwrap().as_integer(), Some(5150));
+    assert_eq!(lua.vm_mut().stats.jit_compiled, 0);
+}
+
+#[test]
+fn test_other_argument_types_get_their_own_code() {
+    let mut lua = Myula::new();
+    lua.exec(&hot_loop(
+        "acc = 0\nfunction f(n) return n + 1 end\nfunction g(n) local r = f(n + 0.5) return r end",
+        100,
+    ))
+    .unwrap();
+    lua.exec("local k = 0\nwhile k < 100 do acc = acc + g(k) k = k + 1 end")
+        .unwrap();
+    assert_eq!(lua.get_global("acc").unwrap().as_number(), Some(10150.0));
+    assert_eq!(lua.vm_mut().stats.jit_compiled, 2);
+}
+
+#[test]
+fn test_budgets_keep_calls_interpreted() {
+    let mut lua = Myula::new();
+    lua.vm_mut().set_fuel(Some(1_000_000));
+    lua.exec(&hot_loop("acc = 0\nfunction f(n) return n * 2 end", 100))
+        .unwrap();
+    assert_eq!(lua.get_global("acc").unwrap().as_integer(), Some(9900));
+    assert_eq!(lua.vm_mut().stats.jit_calls, 0);
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use common::{global_string, run_source};
+use myula::backend::deserializer::{deserialize_module, serialize_module};
+use myula::backend::translator::scanner::Scanner;
+use myula::backend::vm::{LogLevel, VirtualMachine};
+use myula::common::opcode::OpCode;
+use myula::frontend::ir::IRGenerator;
+
+fn ir_gen(source: &str, jump_tables: bool) -> IRGenerator {
+    common::ir_with(source, |ir_gen| ir_gen.with_jump_tables(jump_tables))
+}
+
+fn ir(source: &str) -> String {
+    ir_gen(source, true).get_module().to_string()
+}
+
+// 3 is missing, 1 is tested twice
+const DISPATCH: &str = r#"
+function name(op)
+    if op == 1 then
+        return "one"
+    elseif op == 2 then
+        return "two"
+    elseif 4 == op then
+        return "four"
+    elseif op == 1 then
+        return "again"
+    elseif op == 5 then
+        return "five"
+    else
+        return "other"
+    end
+end
+out = ""
+local i = 0
+while i <= 6 do
+    out = out .. name(i) .. " "
+    i = i + 1
+end
+out = out .. name(2.0) .. " " .. name(2.5) .. " " .. name("2") .. " " .. name(nil)
+"#;
+
+const EXPECTED: &str = "other one two other four five other two other other other";
+
+#[test]
+fn test_dense_elseif_chain_becomes_a_switch() {
+    let text = ir(DISPATCH);
+    assert!(text.contains("Switch %"), "{}", text);
+    assert!(!text.contains(" eq "), "{}", text);
+    assert_eq!(global_string(&run_source(DISPATCH), "out"), EXPECTED);
+
+    // the same program without the pass
+    let text = ir_gen(DISPATCH, false).get_module().to_string();
+    assert!(!text.contains("Switch"), "{}", text);
+}
+
+#[test]
+fn test_short_sparse_or_mixed_chains_keep_their_branches() {
+    for source in [
+        // too few cases
+        "function f(x) if x == 1 then y = 1 elseif x == 2 then y = 2 elseif x == 3 then y = 3 end end",
+        // too sparse for a table
+        "function f(x) if x == 1 then y = 1 elseif x == 100 then y = 2 elseif x == 1000 then y = 3 elseif x == 9 then y = 4 end end",
+        // not the same variable
+        "function f(x, z) if x == 1 then y = 1 elseif z == 2 then y = 2 elseif x == 3 then y = 3 elseif x == 4 then y = 4 end end",
+        // not an integer
+        "function f(x) if x == 1 then y = 1 elseif x == 2.5 then y = 2 elseif x == 3 then y = 3 elseif x == 4 then y = 4 end end",
+    ] {
+        let text = ir(source);
+        assert!(!text.contains("Switch"), "{}\n{}", source, text);
+    }
+}
+
+#[test]
+fn test_jump_tables_survive_a_bytecode_image() {
+    let ir_gen = ir_gen(DISPATCH, true);
+    let mut scanner = Scanner::new();
+    scanner.global_scan(ir_gen.get_module());
+    let funcs = VirtualMachine::compile(&ir_gen, &mut scanner, false).unwrap();
+
+    let (_, name) = funcs
+        .iter()
+        .find(|(name, _)| name.contains("name"))
+        .unwrap();
+    assert!(
+        name.bytecode
+            .iter()
+            .any(|op| matches!(op, OpCode::Switch { .. })),
+        "{:?}",
+        name.bytecode
+    );
+    assert_eq!(name.jump_tables.len(), 1);
+    assert_eq!(name.jump_tables[0].low, 1);
+    assert_eq!(name.jump_tables[0].targets.len(), 5);
+
+    let funcs = deserialize_module(&serialize_module(&funcs)).unwrap();
+    let mut vm = VirtualMachine::new();
+    vm.init_precompiled(funcs, LogLevel::Release);
+    vm.run();
+    assert_eq!(global_string(&vm, "out"), EXPECTED);
+}
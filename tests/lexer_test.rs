@@ -0,0 +1,279 @@
+use myula::frontend::lexer::token::Token;
+use myula::frontend::lexer::{Lexer, LexerError, SpannedToken};
+use myula::frontend::parser::Parser;
+use std::io::BufReader;
+
+// every token together with the position and line the lexer reports right after it
+fn tokens(lexer: &mut Lexer) -> Vec<(Token, usize, usize)> {
+    let mut out = Vec::new();
+    loop {
+        let tok = lexer.next_token();
+        let done = tok == Token::Eof;
+        out.push((tok, lexer.get_pos(), lexer.get_line()));
+        if done {
+            return out;
+        }
+    }
+}
+
+#[test]
+fn test_stream_matches_slice() {
+    let source = "
+        -- a comment
+        local s = \"quoted \\\" string\"
+        x = 12 + 3.25 .. 'single'
+        if x ~= nil then print(#s, x % 2) end
+        ";
+
+    let expected = tokens(&mut Lexer::new(source));
+    assert_eq!(expected.last().unwrap().2, 6);
+
+    // a tiny read buffer makes tokens straddle refills
+    let mut streamed = Lexer::from_reader(BufReader::with_capacity(3, source.as_bytes()));
+    assert_eq!(tokens(&mut streamed), expected);
+    assert!(streamed.get_err().is_empty());
+}
+
+#[test]
+fn test_stream_large_source() {
+    let mut source = String::new();
+    for i in 0..20000 {
+        source.push_str(&format!("v{} = \"value {}\" -- line {}\n", i, i, i));
+    }
+
+    let expected = tokens(&mut Lexer::new(&source));
+    let mut streamed = Lexer::from_reader(BufReader::with_capacity(1000, source.as_bytes()));
+    let got = tokens(&mut streamed);
+    assert_eq!(got, expected);
+    assert_eq!(got.last().unwrap().1, source.len());
+    assert_eq!(got.last().unwrap().2, 20001);
+}
+
+#[test]
+fn test_tokens_iterator_reports_spans() {
+    let source = "local s = 'hé' -- note\nprint(s .. 1)\n";
+    let toks: Vec<SpannedToken> = Lexer::new(source).tokens().collect();
+    let texts: Vec<_> = toks.iter().map(|t| &source[t.start..t.end]).collect();
+    assert_eq!(
+        texts,
+        ["local", "s", "=", "'hé'", "print", "(", "s", "..", "1", ")"]
+    );
+    assert_eq!(toks[3].token, Token::StrLit("hé".into()));
+    assert_eq!(toks[4].line, 2);
+
+    // the same tokens from an unbuffered reader, which is read in chunks
+    let mut streamed = Lexer::from_read(source.as_bytes());
+    assert_eq!(streamed.tokens().collect::<Vec<_>>(), toks);
+    assert!(streamed.get_err().is_empty());
+    // done at Eof, and stays done
+    let mut lexer = Lexer::new("x");
+    let mut iter = lexer.tokens();
+    assert!(iter.next().is_some());
+    assert!(iter.next().is_none());
+    assert!(iter.next().is_none());
+
+    let mut lexer = Lexer::new("a $ b");
+    let toks: Vec<_> = lexer.tokens().map(|t| t.token).collect();
+    assert_eq!(
+        toks,
+        [
+            Token::Ident("a".into()),
+            Token::Errno,
+            Token::Ident("b".into())
+        ]
+    );
+    assert_eq!(lexer.get_err().len(), 1);
+}
+
+#[test]
+fn test_comments_are_tokens_on_request() {
+    let source = "-- head\nx = 1 --[==[ long\n]] ]==] y = 2 --[[ open";
+    let mut lexer = Lexer::new(source).with_comments(true);
+    let toks: Vec<_> = lexer.tokens().collect();
+    let comments: Vec<_> = toks
+        .iter()
+        .filter_map(|t| match &t.token {
+            Token::Comment(text) => Some((text.as_str(), &source[t.start..t.end], t.line)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        comments,
+        [
+            ("-- head", "-- head", 1),
+            ("--[==[ long\n]] ]==]", "--[==[ long\n]] ]==]", 2),
+            ("--[[ open", "--[[ open", 3),
+        ]
+    );
+    assert!(matches!(
+        lexer.get_err()[..],
+        [LexerError::UnterminatedComment]
+    ));
+
+    // by default they are whitespace, and with or without them the same program is parsed
+    let plain: Vec<_> = Lexer::new(source).tokens().map(|t| t.token).collect();
+    let significant: Vec<_> = toks
+        .into_iter()
+        .map(|t| t.token)
+        .filter(|t| !matches!(t, Token::Comment(_)))
+        .collect();
+    assert_eq!(plain, significant);
+
+    let source = "-- leading\nlocal a = 1 -- trailing\nreturn a --[[ end ]]";
+    let mut kept = Lexer::new(source).with_comments(true);
+    let mut parser = Parser::new(&mut kept);
+    let program = parser.parse();
+    assert!(parser.get_err().is_empty());
+    assert_eq!(
+        format!("{:?}", program),
+        format!("{:?}", Parser::new(&mut Lexer::new(source)).parse())
+    );
+}
+
+fn numerals(source: &str) -> Vec<Token> {
+    let mut lexer = Lexer::new(source);
+    let toks: Vec<Token> = tokens(&mut lexer)
+        .into_iter()
+        .map(|(tok, _, _)| tok)
+        .collect();
+    assert!(lexer.get_err().is_empty(), "{:?}", lexer.get_err());
+    toks
+}
+
+#[test]
+fn test_numerals() {
+    assert_eq!(
+        numerals("0xFF 0Xa 1e10 3.5e-2 2E+3 .5 5. 42"),
+        vec![
+            Token::IntLit(255),
+            Token::IntLit(10),
+            Token::NumLit(1e10),
+            Token::NumLit(0.035),
+            Token::NumLit(2000.0),
+            Token::NumLit(0.5),
+            Token::NumLit(5.0),
+            Token::IntLit(42),
+            Token::Eof,
+        ]
+    );
+    // hex floats have a binary exponent, hex integers wrap around
+    assert_eq!(
+        numerals("0x1p4 0x.8 0xA.8p1 0xffffffffffffffff"),
+        vec![
+            Token::NumLit(16.0),
+            Token::NumLit(0.5),
+            Token::NumLit(21.0),
+            Token::IntLit(-1),
+            Token::Eof,
+        ]
+    );
+    // a decimal integer too large for i64 becomes a float
+    assert_eq!(
+        numerals("9223372036854775808"),
+        vec![Token::NumLit(9223372036854775808.0), Token::Eof]
+    );
+    // the sign after a hex digit 'e' is not an exponent
+    assert_eq!(
+        numerals("0xe-1"),
+        vec![
+            Token::IntLit(14),
+            Token::Minus,
+            Token::IntLit(1),
+            Token::Eof
+        ]
+    );
+}
+
+#[test]
+fn test_malformed_numerals() {
+    let mut lexer = Lexer::new("x = 3x\ny = 1.2.3\nz = 1e + 0x");
+    let toks: Vec<Token> = tokens(&mut lexer)
+        .into_iter()
+        .map(|(tok, _, _)| tok)
+        .collect();
+
+    // each is a single token, the rest of the line still lexes
+    assert_eq!(toks.iter().filter(|t| **t == Token::NumLit(0.0)).count(), 4);
+    assert_eq!(toks.iter().filter(|t| **t == Token::Plus).count(), 1);
+    let errors: Vec<String> = lexer
+        .get_err()
+        .iter()
+        .map(|e| match e {
+            LexerError::InvalidNumber(text) => text.clone(),
+            other => panic!("unexpected error {:?}", other),
+        })
+        .collect();
+    assert_eq!(errors, vec!["3x", "1.2.3", "1e", "0x"]);
+    assert_eq!(lexer.get_err_lines(), &[1, 2, 3, 3]);
+}
+
+#[test]
+fn test_long_comments() {
+    let source = "--[[ a\nblock ]] a --[==[ ]] ]=] \n ]==] b\n--[ line\nc --[=x line\nd";
+    let expected = tokens(&mut Lexer::new(source));
+    let names: Vec<Token> = expected.iter().map(|(tok, _, _)| tok.clone()).collect();
+    assert_eq!(
+        names,
+        vec![
+            Token::Ident("a".to_string()),
+            Token::Ident("b".to_string()),
+            Token::Ident("c".to_string()),
+            Token::Ident("d".to_string()),
+            Token::Eof,
+        ]
+    );
+    assert_eq!(expected.last().unwrap().2, 6);
+
+    let mut streamed = Lexer::from_reader(BufReader::with_capacity(2, source.as_bytes()));
+    assert_eq!(tokens(&mut streamed), expected);
+    assert!(streamed.get_err().is_empty());
+}
+
+#[test]
+fn test_unfinished_long_comment() {
+    let mut lexer = Lexer::new("a\n--[==[ open\n]] ]=] \nb");
+    let toks: Vec<Token> = tokens(&mut lexer)
+        .into_iter()
+        .map(|(tok, _, _)| tok)
+        .collect();
+    assert_eq!(toks, vec![Token::Ident("a".to_string()), Token::Eof]);
+    assert!(matches!(
+        lexer.get_err().as_slice(),
+        [LexerError::UnterminatedComment]
+    ));
+    assert_eq!(lexer.get_err_lines(), &[2]);
+}
+
+#[test]
+fn test_unicode_source() {
+    let source = "-- ✓ комментарий\nназвание = \"héllo 世界 🎉\" .. 'ß'\n";
+    let expected = tokens(&mut Lexer::new(source));
+    let toks: Vec<Token> = expected.iter().map(|(tok, _, _)| tok.clone()).collect();
+    assert_eq!(
+        toks,
+        vec![
+            Token::Ident("название".to_string()),
+            Token::Assign,
+            Token::StrLit("héllo 世界 🎉".to_string()),
+            Token::Concat,
+            Token::StrLit("ß".to_string()),
+            Token::Eof,
+        ]
+    );
+    assert_eq!(expected.last().unwrap().1, source.len());
+
+    // a one byte buffer splits every multi-byte character across refills
+    let mut streamed = Lexer::from_reader(BufReader::with_capacity(1, source.as_bytes()));
+    assert_eq!(tokens(&mut streamed), expected);
+    assert!(streamed.get_err().is_empty());
+}
+
+#[test]
+fn test_unexpected_unicode_character_is_reported_whole() {
+    let mut lexer = Lexer::new("x = 1 § 2");
+    tokens(&mut lexer);
+    assert!(matches!(
+        lexer.get_err().as_slice(),
+        [LexerError::UnexpectedCharacter('§')]
+    ));
+}
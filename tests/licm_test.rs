@@ -0,0 +1,144 @@
+mod common;
+
+use common::{global_integer, run_source, run_until_error};
+use myula::frontend::ir::{IRFunction, IRInstruction, IRModule, IRTerminator};
+
+fn ir(source: &str, licm: bool) -> IRModule {
+    common::ir_with(source, |ir_gen| ir_gen.with_licm(licm))
+        .get_module()
+        .clone()
+}
+
+fn function<'a>(module: &'a IRModule, name: &str) -> &'a IRFunction {
+    module
+        .functions
+        .iter()
+        .find(|f| f.name.contains(name))
+        .unwrap_or_else(|| panic!("no function {}\n{}", name, module.to_string()))
+}
+
+// index of the first block that loads a global
+fn global_load_block(func: &IRFunction) -> Option<usize> {
+    func.basic_blocks.iter().position(|block| {
+        block
+            .instructions
+            .iter()
+            .any(|instr| matches!(instr, IRInstruction::LoadGlobal { .. }))
+    })
+}
+
+const COUNT: &str = "
+function count(n)
+    local i = 0
+    local s = 0
+    while i < limit do
+        s = s + n + i * 2
+        i = i + 1
+    end
+    return s
+end
+limit = 5
+x = count(3)
+";
+
+#[test]
+fn test_invariant_loads_move_before_the_loop() {
+    let module = ir(COUNT, true);
+    let func = function(&module, "count");
+    let before = ir(COUNT, false);
+    let unmoved = function(&before, "count");
+
+    // `limit` is loaded once in a block of its own instead of in the condition
+    assert_eq!(func.basic_blocks.len(), unmoved.basic_blocks.len() + 1);
+    let preheader = global_load_block(func).unwrap();
+    assert_eq!(
+        func.basic_blocks[preheader + 1].id,
+        unmoved.basic_blocks[global_load_block(unmoved).unwrap()].id,
+        "{}",
+        module.to_string()
+    );
+    assert!(
+        !module.to_string().contains(" mul "),
+        "{}",
+        module.to_string()
+    );
+
+    assert_eq!(global_integer(&run_source(COUNT), "x"), 35);
+}
+
+#[test]
+fn test_only_numbers_are_doubled_by_addition() {
+    // `n` is a parameter and `t` a table, they keep the multiplication and its error message
+    let source = "
+function scale(n, t)
+    local i = 0
+    local s = 0
+    while i < 3 do
+        s = s + n * 2
+        i = i + 1
+    end
+    while s > 0 do
+        s = t * 2
+    end
+    return s
+end
+x = scale(1, {})
+";
+    let module = ir(source, true);
+    assert_eq!(
+        module.to_string().matches(" mul ").count(),
+        2,
+        "{}",
+        module.to_string()
+    );
+    let err = run_until_error(source).unwrap();
+    assert!(err.to_string().contains("'multiplication'"), "{}", err);
+}
+
+#[test]
+fn test_global_assigned_in_the_loop_stays_in_the_condition() {
+    let source = "
+function drain(n)
+    local i = 0
+    while i < limit do
+        limit = limit - n
+        i = i + 1
+    end
+    return i
+end
+limit = 10
+x = drain(2)
+";
+    let module = ir(source, true);
+    let func = function(&module, "drain");
+    let head = global_load_block(func).unwrap();
+    assert!(
+        matches!(
+            func.basic_blocks[head].terminator,
+            IRTerminator::Branch { .. }
+        ),
+        "{}",
+        module.to_string()
+    );
+
+    let vm = run_source(source);
+    assert_eq!(global_integer(&vm, "x"), 4);
+    assert_eq!(global_integer(&vm, "limit"), 2);
+}
+
+#[test]
+fn test_loop_that_never_runs_does_not_load_its_body() {
+    // the body reads a global that does not exist, it must only fail once the body runs
+    let source = "
+function skip(n)
+    local i = 0
+    while i < n do
+        i = i + missing
+    end
+    return i
+end
+x = skip(0)
+";
+    assert_eq!(global_integer(&run_source(source), "x"), 0);
+    assert!(run_until_error(&source.replace("skip(0)", "skip(1)")).is_some());
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use myula::backend::vm::error::ErrorKind;
+use myula::common::object::LuaValue;
+use myula::engine::{EngineError, Myula, Value};
+use std::fs;
+
+#[test]
+fn test_load_compiles_a_string_into_a_function() {
+    let vm = common::run_source(
+        r#"
+        counter = 0
+        local f = load("counter = counter + 1 return counter * 10")
+        first = f()
+        second = f()
+        local add = loadstring("return 2 + 3")
+        sum = add()
+        "#,
+    );
+    assert_eq!(common::global_integer(&vm, "first"), 10);
+    assert_eq!(common::global_integer(&vm, "second"), 20);
+    assert_eq!(common::global_integer(&vm, "counter"), 2);
+    assert_eq!(common::global_integer(&vm, "sum"), 5);
+}
+
+#[test]
+fn test_load_reads_pieces_from_a_function() {
+    let vm = common::run_source(
+        r#"
+        local parts = { "return ", "6 ", "* 7" }
+        local i = 0
+        local f = load(function()
+            i = i + 1
+            return parts[i]
+        end)
+        answer = f()
+        "#,
+    );
+    assert_eq!(common::global_integer(&vm, "answer"), 42);
+}
+
+#[test]
+fn test_load_returns_nil_for_chunks_that_do_not_compile() {
+    let vm = common::run_source(
+        r#"
+        failed = load("x = = 1") == nil
+        refused = load("return 1", "chunk", "b") == nil
+        loaded = load("return 1", "chunk", "bt") ~= nil
+        "#,
+    );
+    for name in ["failed", "refused", "loaded"] {
+        assert!(
+            matches!(vm.get_global(name), Some(LuaValue::Boolean(true))),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_runtime_errors_name_the_loaded_chunk() {
+    let mut lua = Myula::new();
+    // string literals keep `\n` as is, the newline comes from string.char
+    let script =
+        "local f = load(\"local t = nil\" .. string.char(10) .. \"return t.x\", \"=snippet\")\nf()";
+    let err = match lua.exec(script) {
+        Err(EngineError::Runtime(err)) => err,
+        other => panic!("the chunk did not fail: {:?}", other.map(|_| ())),
+    };
+    assert_eq!(err.chunk_name(), Some("snippet"), "{}", err);
+    assert_eq!(err.line(), Some(2));
+}
+
+#[test]
+fn test_dofile_runs_a_file() {
+    let dir = std::env::temp_dir().join(format!("myula_dofile_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let good = dir.join("good.lua");
+    let bad = dir.join("bad.lua");
+    fs::write(&good, "runs = runs + 1\nreturn \"done\"\n").unwrap();
+    fs::write(&bad, "return = 1\n").unwrap();
+
+    let mut lua = Myula::new();
+    let path = good.display();
+    lua.exec(&format!(
+        "runs = 0\na = dofile(\"{0}\")\nb = dofile(\"{0}\")",
+        path
+    ))
+    .unwrap();
+    assert_eq!(lua.get_global("runs").unwrap(), Value::Integer(2));
+    assert_eq!(lua.get_global("a").unwrap(), Value::String("done".into()));
+
+    match lua.exec(&format!("dofile(\"{}\")", bad.display())) {
+        Err(EngineError::Runtime(err)) => {
+            assert!(matches!(err.kind, ErrorKind::ModuleError(_)), "{}", err)
+        }
+        other => panic!("the broken file ran: {:?}", other.map(|_| ())),
+    }
+    match lua.exec(&format!(
+        "dofile(\"{}\")",
+        dir.join("missing.lua").display()
+    )) {
+        Err(EngineError::Runtime(err)) => {
+            assert!(matches!(err.kind, ErrorKind::IOError(_)), "{}", err)
+        }
+        other => panic!("a missing file ran: {:?}", other.map(|_| ())),
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
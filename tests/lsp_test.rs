@@ -0,0 +1,266 @@
+use myula::lsp::LanguageServer;
+use myula::lsp::json::Json;
+use std::io::Cursor;
+
+const URI: &str = "file:///test.lua";
+
+fn frame(body: &str) -> String {
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+// the bodies of the framed messages in `out`
+fn unframe(mut out: &str) -> Vec<Json> {
+    let mut messages = Vec::new();
+    while let Some(rest) = out.strip_prefix("Content-Length: ") {
+        let (length, rest) = rest.split_once("\r\n\r\n").unwrap();
+        let length: usize = length.parse().unwrap();
+        messages.push(Json::parse(&rest[..length]).unwrap());
+        out = &rest[length..];
+    }
+    assert!(out.is_empty(), "unframed output: {:?}", out);
+    messages
+}
+
+fn request(server: &mut LanguageServer, method: &str, params: Json) -> Json {
+    let message = Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("id", 1usize.into()),
+        ("method", method.into()),
+        ("params", params),
+    ]);
+    let mut replies = server.handle(&message);
+    assert_eq!(replies.len(), 1);
+    replies.pop().unwrap()
+}
+
+// opens the document, returns the diagnostics published for it
+fn open(server: &mut LanguageServer, text: &str) -> Vec<Json> {
+    let message = Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/didOpen".into()),
+        (
+            "params",
+            Json::object([(
+                "textDocument",
+                Json::object([("uri", URI.into()), ("text", text.into())]),
+            )]),
+        ),
+    ]);
+    let replies = server.handle(&message);
+    assert_eq!(
+        replies[0].get("method").as_str(),
+        Some("textDocument/publishDiagnostics")
+    );
+    replies[0]
+        .get("params")
+        .get("diagnostics")
+        .as_array()
+        .to_vec()
+}
+
+fn at(line: usize, character: usize) -> Json {
+    Json::object([
+        ("textDocument", Json::object([("uri", URI.into())])),
+        (
+            "position",
+            Json::object([("line", line.into()), ("character", character.into())]),
+        ),
+    ])
+}
+
+// (start line, start character, end line, end character)
+fn range(range: &Json) -> (usize, usize, usize, usize) {
+    let point = |p: &Json| {
+        (
+            p.get("line").as_usize().unwrap(),
+            p.get("character").as_usize().unwrap(),
+        )
+    };
+    let (start, end) = (point(range.get("start")), point(range.get("end")));
+    (start.0, start.1, end.0, end.1)
+}
+
+#[test]
+fn test_json_round_trip() {
+    let text = r#"{"a":[1,-2.5,true,null],"s":"q\"\\\né😀","o":{}}"#;
+    let value = Json::parse(text).unwrap();
+    assert_eq!(value.get("s").as_str(), Some("q\"\\\né\u{1f600}"));
+    assert_eq!(value.get("a").as_array().len(), 4);
+    assert!(value.get("missing").get("deeper").is_null());
+    assert_eq!(
+        value.to_string(),
+        "{\"a\":[1,-2.5,true,null],\"s\":\"q\\\"\\\\\\né\u{1f600}\",\"o\":{}}"
+    );
+    assert_eq!(Json::parse(&value.to_string()), Some(value));
+
+    assert!(Json::parse("{\"a\":}").is_none());
+    assert!(Json::parse("[1] 2").is_none());
+    assert!(Json::parse(&"[".repeat(1000)).is_none());
+}
+
+#[test]
+fn test_session_over_framed_stdio() {
+    let input = [
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+        r#"{"jsonrpc":"2.0","id":"two","method":"workspace/symbol","params":{}}"#,
+        r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#,
+        r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        r#"{"jsonrpc":"2.0","id":4,"method":"shutdown"}"#,
+    ]
+    .map(frame)
+    .concat();
+    let mut out = Vec::new();
+    let mut server = LanguageServer::new();
+    server.run(Cursor::new(input), &mut out).unwrap();
+
+    let replies = unframe(&String::from_utf8(out).unwrap());
+    // nothing for the notification, nothing after exit
+    assert_eq!(replies.len(), 3);
+    let capabilities = replies[0].get("result").get("capabilities");
+    assert_eq!(capabilities.get("textDocumentSync").as_usize(), Some(1));
+    assert_eq!(capabilities.get("hoverProvider"), &Json::Bool(true));
+    assert_eq!(replies[1].get("id").as_str(), Some("two"));
+    assert_eq!(replies[1].get("error").get("code"), &Json::Number(-32601.0));
+    assert!(replies[2].get("result").is_null());
+    assert!(server.has_exited());
+    assert_eq!(server.exit_code(), 0);
+}
+
+#[test]
+fn test_diagnostics_follow_the_document() {
+    let mut server = LanguageServer::new();
+    let diagnostics = open(&mut server, "local x = 1\nif x then\n  print(x\nend\n");
+    assert!(!diagnostics.is_empty());
+    assert_eq!(range(diagnostics[0].get("range")).0, 3);
+    assert_eq!(diagnostics[0].get("severity").as_usize(), Some(1));
+    // no navigation in a document that does not parse
+    assert!(
+        request(&mut server, "textDocument/hover", at(0, 6))
+            .get("result")
+            .is_null()
+    );
+
+    let change = Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/didChange".into()),
+        (
+            "params",
+            Json::object([
+                ("textDocument", Json::object([("uri", URI.into())])),
+                (
+                    "contentChanges",
+                    Json::Array(vec![Json::object([(
+                        "text",
+                        "local x = 1\nif x then\n  print(x)\nend\ngoto nowhere\n".into(),
+                    )])]),
+                ),
+            ]),
+        ),
+    ]);
+    let replies = server.handle(&change);
+    let diagnostics = replies[0].get("params").get("diagnostics").as_array();
+    // only the compile error is left
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(range(diagnostics[0].get("range")), (4, 0, 4, 12));
+}
+
+#[test]
+fn test_document_symbols_are_the_functions() {
+    let mut server = LanguageServer::new();
+    let source = "local function outer(a)\n  local function inner() return a end\n  return inner\nend\n\
+                  function t:m(x) return x end\n\
+                  local f = function() end\n";
+    assert!(open(&mut server, source).is_empty());
+    let reply = request(
+        &mut server,
+        "textDocument/documentSymbol",
+        Json::object([("textDocument", Json::object([("uri", URI.into())]))]),
+    );
+    let symbols = reply.get("result").as_array();
+    let names: Vec<_> = symbols
+        .iter()
+        .map(|s| s.get("name").as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["outer", "t:m", "<anonymous>"]);
+    assert_eq!(symbols[0].get("kind").as_usize(), Some(12));
+    assert_eq!(symbols[1].get("kind").as_usize(), Some(6));
+    assert_eq!(symbols[1].get("detail").as_str(), Some("function(self, x)"));
+    assert_eq!(range(symbols[0].get("range")).0, 0);
+    assert_eq!(range(symbols[1].get("range")).0, 4);
+
+    let children = symbols[0].get("children").as_array();
+    assert_eq!(children.len(), 1);
+    assert_eq!(children[0].get("name").as_str(), Some("inner"));
+    assert_eq!(range(children[0].get("range")).0, 1);
+}
+
+#[test]
+fn test_definition_of_locals_and_upvalues() {
+    let mut server = LanguageServer::new();
+    let source = "local count = 0\n\
+                  local function bump(by)\n  count = count + by\n  return function() return count end\nend\n\
+                  local count = 5\nprint(count)\n";
+    assert!(open(&mut server, source).is_empty());
+    let definition = |server: &mut LanguageServer, line, character| {
+        let reply = request(server, "textDocument/definition", at(line, character));
+        let result = reply.get("result").clone();
+        (!result.is_null()).then(|| range(result.get("range")))
+    };
+
+    // a parameter, an upvalue written and read, the upvalue of an upvalue
+    assert_eq!(definition(&mut server, 2, 19), Some((1, 20, 1, 22)));
+    assert_eq!(definition(&mut server, 2, 3), Some((0, 6, 0, 11)));
+    assert_eq!(definition(&mut server, 2, 12), Some((0, 6, 0, 11)));
+    assert_eq!(definition(&mut server, 3, 27), Some((0, 6, 0, 11)));
+    // the redeclared local, and a declaration is its own definition
+    assert_eq!(definition(&mut server, 6, 7), Some((5, 6, 5, 11)));
+    assert_eq!(definition(&mut server, 5, 8), Some((5, 6, 5, 11)));
+    // a global and a keyword
+    assert_eq!(definition(&mut server, 6, 2), None);
+    assert_eq!(definition(&mut server, 3, 4), None);
+}
+
+#[test]
+fn test_hover_shows_the_inferred_type() {
+    let mut server = LanguageServer::new();
+    let source = "local n = 1\nlocal s = \"é\" local f = 2.5\n\
+                  local function g(p) return p, n end\n";
+    assert!(open(&mut server, source).is_empty());
+    let hover = |server: &mut LanguageServer, line, character| {
+        let reply = request(server, "textDocument/hover", at(line, character));
+        let result = reply.get("result");
+        (
+            result
+                .get("contents")
+                .get("value")
+                .as_str()
+                .map(str::to_string),
+            (!result.is_null()).then(|| range(result.get("range"))),
+        )
+    };
+
+    assert_eq!(
+        hover(&mut server, 0, 6),
+        (Some("local n: Integer".into()), Some((0, 6, 0, 7)))
+    );
+    assert_eq!(
+        hover(&mut server, 1, 6).0.as_deref(),
+        Some("local s: String")
+    );
+    // characters are counted in UTF-16 units, é is one
+    assert_eq!(
+        hover(&mut server, 1, 20),
+        (Some("local f: Float".into()), Some((1, 20, 1, 21)))
+    );
+    assert_eq!(
+        hover(&mut server, 2, 27).0.as_deref(),
+        Some("parameter p: Dynamic")
+    );
+    // n read as an upvalue of g
+    assert_eq!(
+        hover(&mut server, 2, 30).0.as_deref(),
+        Some("local n: Integer")
+    );
+    assert_eq!(hover(&mut server, 2, 0), (None, None));
+}
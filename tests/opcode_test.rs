@@ -0,0 +1,12 @@
+use myula::common::opcode::{OpCode, OperandKind};
+
+#[test]
+fn test_visit_operands_order() {
+    let op = OpCode::SetGlobal {
+        name_idx: 5,
+        src: 2,
+    };
+    let mut seen = vec![];
+    op.visit_operands(|kind, idx| seen.push((kind, idx)));
+    assert_eq!(seen, vec![(OperandKind::Const, 5), (OperandKind::Reg, 2)]);
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use myula::backend::vm::VirtualMachine;
+use myula::engine::{EngineError, Myula};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// a fresh directory of module files, package.path points into it
+fn module_dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("myula_{}_{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (name, source) in files {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    }
+    dir
+}
+
+fn with_path(dir: &Path, source: &str) -> String {
+    format!(
+        "package.path = \"{0}/?.lua;{0}/?/init.lua\"\nloads = 0\n{1}",
+        dir.display(),
+        source
+    )
+}
+
+#[test]
+fn test_modules_are_loaded_once_and_cached() {
+    let dir = module_dir(
+        "cache",
+        &[
+            (
+                "counter.lua",
+                "
+local M = {}
+local count = 0
+loads = loads + 1
+function M.bump()
+    count = count + 1
+    return count
+end
+return M
+",
+            ),
+            (
+                "tools/init.lua",
+                "
+local counter = require(\"counter\")
+return {twice = function() counter.bump() return counter.bump() end}
+",
+            ),
+            ("sideeffect.lua", "flag = \"set\"\n"),
+        ],
+    );
+    let vm = common::run_source(&with_path(
+        &dir,
+        "
+local counter = require(\"counter\")
+counter.bump()
+local tools = require(\"tools\")
+bumped = tools.twice()
+same = tostring(require(\"counter\") == counter)
+plain = tostring(require(\"sideeffect\"))
+cached = tostring(package.loaded.sideeffect)
+libs = tostring(package.loaded.string == string)
+",
+    ));
+    assert_eq!(common::global_integer(&vm, "loads"), 1);
+    assert_eq!(common::global_integer(&vm, "bumped"), 3);
+    assert_eq!(common::global_string(&vm, "same"), "true");
+    // a module that returns nothing is recorded as true
+    assert_eq!(common::global_string(&vm, "plain"), "true");
+    assert_eq!(common::global_string(&vm, "flag"), "set");
+    assert_eq!(common::global_string(&vm, "cached"), "true");
+    assert_eq!(common::global_string(&vm, "libs"), "true");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_module_errors() {
+    let dir = module_dir(
+        "errors",
+        &[
+            ("broken.lua", "local x = = 1\n"),
+            ("a.lua", "require(\"b\")\nreturn 1\n"),
+            ("b.lua", "require(\"a\")\nreturn 2\n"),
+            ("fails.lua", "local t = nil\nreturn t.x\n"),
+        ],
+    );
+    let message = |module: &str| {
+        let mut lua = Myula::new();
+        match lua.exec(&with_path(&dir, &format!("require(\"{}\")", module))) {
+            Err(EngineError::Runtime(err)) => err,
+            other => panic!("require(\"{}\") did not fail: {:?}", module, other.err()),
+        }
+    };
+
+    let err = message("missing");
+    assert!(
+        err.get_message().contains("module 'missing' not found"),
+        "{}",
+        err
+    );
+    assert!(err.get_message().contains("missing/init.lua"), "{}", err);
+    let err = message("broken");
+    assert!(
+        err.get_message().contains("error loading module 'broken'"),
+        "{}",
+        err
+    );
+    let err = message("a");
+    assert!(
+        err.get_message().contains("loop while loading module 'a'"),
+        "{}",
+        err
+    );
+
+    // runtime errors point into the module, the frames below it into the main chunk
+    let err = message("fails");
+    assert!(err.chunk_name().unwrap().ends_with("fails.lua"), "{}", err);
+    assert_eq!(err.line(), Some(2));
+    assert_eq!(err.stack_chunks.first().unwrap().as_ref(), "?");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_reset_forgets_required_modules() {
+    let dir = module_dir("reset", &[("m.lua", "loads = loads + 1\nreturn {}\n")]);
+    let mut vm = VirtualMachine::new();
+    common::run_source_on(&mut vm, &with_path(&dir, "require(\"m\")"));
+    assert_eq!(common::global_integer(&vm, "loads"), 1);
+
+    vm.reset(true);
+    common::run_source_on(
+        &mut vm,
+        &with_path(
+            &dir,
+            "require(\"m\")\nstill = tostring(package.loaded.math == math)",
+        ),
+    );
+    assert_eq!(common::global_integer(&vm, "loads"), 1);
+    assert_eq!(common::global_string(&vm, "still"), "true");
+    fs::remove_dir_all(dir).unwrap();
+}
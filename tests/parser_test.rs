@@ -0,0 +1,393 @@
+mod common;
+
+use myula::frontend::lexer::Lexer;
+use myula::frontend::parser::ast::{
+    Expr, Expression, Literal, NodeId, Program, Span, Statement, Stmt,
+};
+use myula::frontend::parser::{
+    MAX_CHAIN_LENGTH, MAX_NESTING_DEPTH, Parser, ParserError, ParserErrorType,
+};
+
+fn parse(source: &str) -> (Program, Vec<ParserError>) {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+    let errors = parser.get_err().clone();
+    (program, errors)
+}
+
+#[test]
+fn test_every_syntax_error_is_reported() {
+    let (program, errors) = parse(
+        "local a = = 3
+print(\"ok\")
+if x == then
+    print(1 +)
+end
+function f(a, 1)
+    return
+end
+y = 2
+",
+    );
+    let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+    assert_eq!(lines, vec![1, 3, 4, 6], "{:#?}", errors);
+
+    // the statements around the broken ones still parse
+    let assigned = program
+        .body
+        .iter()
+        .any(|stmt| matches!(stmt.node, Statement::Assignment { .. }));
+    assert!(assigned, "{}", program.body.len());
+}
+
+#[test]
+fn test_stray_block_end_is_skipped() {
+    let (_, errors) = parse("x = 1\nend\nuntil\ny = 2\n");
+    assert_eq!(errors.len(), 2, "{:#?}", errors);
+    assert!(errors[0].message.contains("KwEnd"), "{:#?}", errors);
+    assert_eq!(errors[1].line, 3);
+
+    // a bare return before `end` is not an error
+    let (_, errors) = parse("function f()\n    return\nend\n");
+    assert!(errors.is_empty(), "{:#?}", errors);
+
+    // a missing `end` is still reported as the input ending early
+    let (_, errors) = parse("while true do\n    x = 1\n");
+    assert_eq!(errors.len(), 1, "{:#?}", errors);
+    assert_eq!(errors[0].err_type, ParserErrorType::UnexpectedEof);
+}
+
+#[test]
+fn test_assignment_is_a_statement_only() {
+    let (_, errors) = parse("a = b = 1\nlocal c = d = 2\nprint(x = 1)\nif y = 2 then end\nz = 3\n");
+    // the call also reports its broken argument
+    let mut lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+    lines.dedup();
+    assert_eq!(lines, vec![1, 2, 3, 4], "{:#?}", errors);
+    assert!(
+        errors
+            .iter()
+            .all(|e| e.err_type == ParserErrorType::InvalidExpression),
+        "{:#?}",
+        errors
+    );
+    assert!(errors[0].message.contains("chained"), "{:#?}", errors);
+    assert!(errors[2].message.contains("'=='"), "{:#?}", errors);
+
+    // the statement forms and comparisons still parse
+    let (program, errors) = parse("a, t.x = 1, b == 2\nlocal c = a == 1\nfunction t.f() end\n");
+    assert!(errors.is_empty(), "{:#?}", errors);
+    assert_eq!(program.body.len(), 3);
+}
+
+// builds a source nesting n levels deep
+type Shape = fn(usize) -> String;
+
+// each shape nests its innermost `x = 1` / `1` n levels deep
+fn nesting_shapes() -> Vec<(&'static str, Shape)> {
+    vec![
+        ("parentheses", |n| {
+            format!("x = {}1{}", "(".repeat(n), ")".repeat(n))
+        }),
+        ("unary operators", |n| format!("x = {}1", "- ".repeat(n))),
+        ("left associative chain", |n| {
+            format!("x = 1{}", " + 1".repeat(n))
+        }),
+        ("right associative chain", |n| {
+            format!("x = 1{}", " .. 1".repeat(n))
+        }),
+        ("postfix chain", |n| {
+            format!("t = {{}} t.t = t x = t{}", ".t".repeat(n))
+        }),
+        ("table constructors", |n| {
+            format!("x = {}1{}", "{".repeat(n), "}".repeat(n))
+        }),
+        ("blocks", |n| {
+            format!("{}x = 1{}", "do ".repeat(n), " end".repeat(n))
+        }),
+        ("functions", |n| {
+            format!("{}x = 1{}", "function f() ".repeat(n), " end f()".repeat(n))
+        }),
+    ]
+}
+
+#[test]
+fn test_deep_nesting_is_an_error_not_a_stack_overflow() {
+    for (shape, make) in nesting_shapes() {
+        let (_, errors) = parse(&make(100_000));
+        assert_eq!(errors.len(), 1, "{}: {:#?}", shape, errors);
+        assert_eq!(
+            errors[0].err_type,
+            ParserErrorType::NestingTooDeep,
+            "{}",
+            shape
+        );
+    }
+}
+
+#[test]
+fn test_nesting_up_to_the_limit_runs() {
+    for (shape, make) in nesting_shapes() {
+        // the deepest input of this shape that still parses
+        let source = (1..=MAX_NESTING_DEPTH)
+            .rev()
+            .map(make)
+            .find(|source| parse(source).1.is_empty())
+            .unwrap();
+        let vm = common::run_source(&source);
+        assert!(!common::global_is_nil(&vm, "x"), "{}", shape);
+    }
+}
+
+#[test]
+fn test_nesting_limit_is_configurable() {
+    let source = format!("x = {}1{}", "(".repeat(20), ")".repeat(20));
+    assert!(parse(&source).1.is_empty());
+
+    let mut lexer = Lexer::new(&source);
+    let mut parser = Parser::new(&mut lexer).with_max_depth(16);
+    parser.parse();
+    let errors = parser.get_err();
+    assert_eq!(errors.len(), 1, "{:#?}", errors);
+    assert_eq!(errors[0].err_type, ParserErrorType::NestingTooDeep);
+}
+
+#[test]
+fn test_operator_chains_are_not_limited_by_the_nesting_depth() {
+    for op in ["+", "==", "and"] {
+        // a global keeps constant folding from collapsing the chain before it is run
+        let chain = |n: usize| format!("a = 1 x = a{}", format!(" {} a", op).repeat(n));
+        // the longest chain that still parses
+        let n = (1..=MAX_CHAIN_LENGTH)
+            .rev()
+            .find(|&n| parse(&chain(n)).1.is_empty())
+            .unwrap();
+        assert!(n > MAX_CHAIN_LENGTH - 10, "{}: {}", op, n);
+        let vm = common::run_source(&chain(n));
+        assert!(!common::global_is_nil(&vm, "x"), "{}", op);
+    }
+}
+
+#[test]
+fn test_chains_nested_in_chains_share_the_limit() {
+    // each chain fits on its own, together they would put the innermost `1` 20 * 900 levels deep
+    let mut source = "1".to_string();
+    for _ in 0..20 {
+        source = format!("({}{})", source, " + 1".repeat(900));
+    }
+    let (_, errors) = parse(&format!("x = {}", source));
+    assert_eq!(errors.len(), 1, "{:#?}", errors);
+    assert_eq!(errors[0].err_type, ParserErrorType::NestingTooDeep);
+}
+
+#[test]
+fn test_chain_limit_is_configurable() {
+    let source = format!("x = 1{}", " + 1".repeat(20));
+    assert!(parse(&source).1.is_empty());
+
+    let mut lexer = Lexer::new(&source);
+    let mut parser = Parser::new(&mut lexer).with_max_chain_length(16);
+    parser.parse();
+    let errors = parser.get_err();
+    assert_eq!(errors.len(), 1, "{:#?}", errors);
+    assert_eq!(errors[0].err_type, ParserErrorType::NestingTooDeep);
+}
+
+fn reprint(source: &str) -> String {
+    let (program, errors) = parse(source);
+    assert!(errors.is_empty(), "{}\n{:#?}", source, errors);
+    program.to_source()
+}
+
+fn lua_scripts() -> Vec<std::path::PathBuf> {
+    let mut paths: Vec<_> = std::fs::read_dir("lua_tests/self")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lua"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_printed_source_keeps_precedence() {
+    for source in [
+        "x = (1 + 2) * 3\n",
+        "x = 1 + 2 * 3\n",
+        "x = 1 - (2 - 3)\n",
+        "x = 1 - 2 - 3\n",
+        "x = 2 ^ 3 ^ 2\n",
+        "x = (2 ^ 3) ^ 2\n",
+        "x = a .. b .. c\n",
+        "x = (a .. b) .. c\n",
+        "x = -(y ^ 2)\n",
+        "x = -y ^ 2\n",
+        "x = - -y\n",
+        "x = not (a == b)\n",
+        "x = (a or b) and c\n",
+        "x = #t + (f or g)(1)\n",
+        "x = (\"%d\"):format(1) .. (1).y .. ({1})[1]\n",
+        "x = (function(a)\n    return a\nend)(1)\n",
+    ] {
+        assert_eq!(reprint(source), source);
+    }
+}
+
+#[test]
+fn test_printed_source_keeps_statement_forms() {
+    let source = "local function f(a, b)\n    return a, b\nend\n\
+                  function t.m:g(x)\n    return self, x\nend\n\
+                  local a, b\n\
+                  local c = {1, name = 'q\"', [\"not a name\"] = 2, [3] = {}}\n\
+                  a, t.x = t[1], 1.5\n\
+                  if a then\n    goto done\nelseif b then\n    do\n        a = 1\n    end\nelse\n    repeat\n        a = a - 1\n    until a < 0\nend\n\
+                  while false do\nend\n\
+                  ::done::\n\
+                  obj:m(\"s\\n\")\n";
+    assert_eq!(reprint(source), source);
+}
+
+#[test]
+fn test_printing_a_reparsed_program_gives_the_same_source() {
+    for path in lua_scripts() {
+        let printed = reprint(&std::fs::read_to_string(&path).unwrap());
+        assert_eq!(reprint(&printed), printed, "{}", path.display());
+    }
+}
+
+#[test]
+fn test_printed_source_runs_the_same() {
+    let run = |source: &str| {
+        let capture = common::Capture::default();
+        let mut lua = myula::Myula::new();
+        lua.vm_mut().set_stdout(Box::new(capture.clone()));
+        // line numbers in error messages differ between the two, the output before them must not
+        let ok = lua.exec(source).is_ok();
+        (capture.text(), ok)
+    };
+    for path in lua_scripts() {
+        let source = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(run(&reprint(&source)), run(&source), "{}", path.display());
+    }
+}
+
+#[test]
+fn test_nodes_carry_their_source_span() {
+    let source = "local x = a + b * c\nt.f(x, \"s\")\n";
+    let (program, errors) = parse(source);
+    assert!(errors.is_empty(), "{:#?}", errors);
+    let text = |span: Span| &source[span.start..span.end];
+
+    assert_eq!(text(program.body[0].span), "local x = a + b * c");
+    assert_eq!(text(program.body[1].span), "t.f(x, \"s\")");
+
+    let Statement::Declaration { values, .. } = &program.body[0].node else {
+        panic!("{:#?}", program.body[0]);
+    };
+    assert_eq!(text(values[0].span), "a + b * c");
+    let Expression::BinOp { left, right, .. } = &values[0].node else {
+        panic!("{:#?}", values[0]);
+    };
+    assert_eq!(text(left.span), "a");
+    assert_eq!(text(right.span), "b * c");
+
+    let Statement::ExprStatement(call) = &program.body[1].node else {
+        panic!("{:#?}", program.body[1]);
+    };
+    let Expression::FnCall { callee, arguments } = &call.node else {
+        panic!("{:#?}", call);
+    };
+    assert_eq!(text(callee.span), "t.f");
+    assert_eq!(text(arguments[1].span), "\"s\"");
+}
+
+#[test]
+fn test_node_ids_are_unique_and_stable() {
+    fn collect_expr(expr: &Expr, ids: &mut Vec<NodeId>) {
+        ids.push(expr.id);
+        match &expr.node {
+            Expression::BinOp { left, right, .. } => {
+                collect_expr(left, ids);
+                collect_expr(right, ids);
+            }
+            Expression::UnOp { operand, .. } => collect_expr(operand, ids),
+            Expression::FnCall { callee, arguments } => {
+                collect_expr(callee, ids);
+                arguments.iter().for_each(|arg| collect_expr(arg, ids));
+            }
+            Expression::MethodCall {
+                object, arguments, ..
+            } => {
+                collect_expr(object, ids);
+                arguments.iter().for_each(|arg| collect_expr(arg, ids));
+            }
+            Expression::IndexOf { collection, index } => {
+                collect_expr(collection, ids);
+                collect_expr(index, ids);
+            }
+            Expression::MemberAccess { collection, .. } => collect_expr(collection, ids),
+            Expression::TableCtor { fields } => {
+                for (key, value) in fields {
+                    key.iter().for_each(|key| collect_expr(key, ids));
+                    collect_expr(value, ids);
+                }
+            }
+            Expression::Literal(Literal::Function { body, .. }) => collect_block(body, ids),
+            Expression::Identifier(_) | Expression::Literal(_) => {}
+        }
+    }
+    fn collect_block(body: &[Stmt], ids: &mut Vec<NodeId>) {
+        for stmt in body {
+            ids.push(stmt.id);
+            match &stmt.node {
+                Statement::ExprStatement(expr) => collect_expr(expr, ids),
+                Statement::Declaration { values, .. } | Statement::ReturnStmt { values } => {
+                    values.iter().for_each(|value| collect_expr(value, ids))
+                }
+                Statement::Assignment { targets, values } => targets
+                    .iter()
+                    .chain(values)
+                    .for_each(|e| collect_expr(e, ids)),
+                Statement::IfStmt {
+                    condition,
+                    then_branch,
+                    elif_branches,
+                    else_branch,
+                } => {
+                    collect_expr(condition, ids);
+                    collect_block(then_branch, ids);
+                    for (condition, body) in elif_branches {
+                        collect_expr(condition, ids);
+                        collect_block(body, ids);
+                    }
+                    else_branch.iter().for_each(|body| collect_block(body, ids));
+                }
+                Statement::WhileStmt { condition, body }
+                | Statement::RepeatStmt { body, condition } => {
+                    collect_expr(condition, ids);
+                    collect_block(body, ids);
+                }
+                Statement::DoStmt { body } => collect_block(body, ids),
+                Statement::Goto { .. } | Statement::Label { .. } => {}
+            }
+        }
+    }
+    let ids_of = |source: &str| {
+        let (program, errors) = parse(source);
+        assert!(errors.is_empty(), "{:#?}", errors);
+        let mut ids = Vec::new();
+        collect_block(&program.body, &mut ids);
+        ids
+    };
+
+    for path in lua_scripts() {
+        let source = std::fs::read_to_string(&path).unwrap();
+        let ids = ids_of(&source);
+        let mut unique = ids.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), ids.len(), "{}", path.display());
+        assert_eq!(ids_of(&source), ids, "{}", path.display());
+    }
+}
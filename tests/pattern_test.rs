@@ -0,0 +1,70 @@
+use myula::backend::vm::pattern::{Capture, Match, find, match_at};
+
+fn find_str(src: &str, pat: &str) -> Option<Match> {
+    find(src.as_bytes(), pat.as_bytes(), 0).expect("pattern must be valid")
+}
+
+fn captured<'a>(src: &'a str, m: &Match) -> Vec<&'a str> {
+    m.values()
+        .iter()
+        .map(|cap| match cap {
+            Capture::Slice(start, end) => &src[*start..*end],
+            Capture::Position(_) => panic!("unexpected position capture"),
+        })
+        .collect()
+}
+
+#[test]
+fn test_classes_and_quantifiers() {
+    let src = "key = value123";
+    let m = find_str(src, "%a+").unwrap();
+    assert_eq!((m.start, m.end), (0, 3));
+
+    let m = find_str(src, "%d+$").unwrap();
+    assert_eq!(captured(src, &m), vec!["123"]);
+
+    let m = find_str(src, "^(%w+)%s*=%s*(%w+)$").unwrap();
+    assert_eq!(captured(src, &m), vec!["key", "value123"]);
+
+    let m = find_str("<a><b>", "<.->").unwrap();
+    assert_eq!((m.start, m.end), (0, 3));
+    let m = find_str("<a><b>", "<.*>").unwrap();
+    assert_eq!((m.start, m.end), (0, 6));
+
+    let m = find_str("colour", "colou?r").unwrap();
+    assert_eq!(m.end, 6);
+    assert!(find_str("abc", "[^a-c]").is_none());
+    assert_eq!(find_str("x]y", "[]]").unwrap().start, 1);
+    assert!(find_str("abc", "^b").is_none());
+}
+
+#[test]
+fn test_special_items() {
+    let src = "f(a(b)c) tail";
+    let m = find_str(src, "%b()").unwrap();
+    assert_eq!(&src[m.start..m.end], "(a(b)c)");
+
+    let src = "THE (quick) fox";
+    let m = find_str(src, "%f[%a]%a+%f[%A]").unwrap();
+    assert_eq!(&src[m.start..m.end], "THE");
+
+    let src = "hello hello world";
+    let m = find_str(src, "(%a+) %1").unwrap();
+    assert_eq!(captured(src, &m), vec!["hello"]);
+
+    let m = find_str("abc", "()b()").unwrap();
+    assert_eq!(m.captures, vec![Capture::Position(2), Capture::Position(3)]);
+
+    // '^' only anchors in find
+    assert!(match_at(b"^a", b"^a", 0).unwrap().is_some());
+}
+
+#[test]
+fn test_malformed_patterns() {
+    assert!(find(b"a", b"%", 0).is_err());
+    assert!(find(b"a", b"[a", 0).is_err());
+    assert!(find(b"a", b"(a", 0).is_err());
+    assert!(find(b"a", b"a)", 0).is_err());
+    assert!(find(b"a", b"%1", 0).is_err());
+    assert!(find(b"a", b"%f", 0).is_err());
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use myula::backend::vm::VirtualMachine;
+
+const SOURCE: &str = "
+function fib(n)
+    if n < 2 then return n end
+    return fib(n - 1) + fib(n - 2)
+end
+local t = {}
+local i = 0
+while i < 10 do
+    i = i + 1
+    t[i] = 'x' .. i
+end
+result = fib(10)
+";
+
+#[test]
+fn test_profiler_counts_calls_and_instructions_per_function() {
+    let mut vm = VirtualMachine::new();
+    vm.enable_profiler();
+    common::run_source_on(&mut vm, SOURCE);
+    assert_eq!(common::global_integer(&vm, "result"), 55);
+
+    let profiler = vm.profiler.as_ref().unwrap();
+    let functions = profiler.functions();
+    let fib = functions.iter().find(|f| f.name.contains("fib")).unwrap();
+    let start = functions.iter().find(|f| f.name == "_start").unwrap();
+    assert_eq!(fib.calls, 177);
+    assert_eq!(start.calls, 1);
+    // fib runs the most instructions, so it is listed first
+    assert_eq!(functions[0].name, fib.name);
+    assert_eq!(fib.instructions + start.instructions, vm.stats.instructions);
+    // the strings and the table are allocated by _start, fib allocates nothing
+    assert!(start.alloc_bytes > 0);
+    assert_eq!(fib.alloc_bytes, 0);
+
+    let opcodes = profiler.opcodes();
+    let count = |name: &str| opcodes.iter().find(|(op, _)| *op == name).unwrap().1.count;
+    // every call of fib goes through a CALL, the entry frame does not
+    assert_eq!(count("CALL"), 177);
+    assert_eq!(count("CONCAT"), 10);
+    assert_eq!(
+        opcodes.iter().map(|(_, p)| p.count).sum::<u64>(),
+        vm.stats.instructions
+    );
+}
+
+#[test]
+fn test_profiler_is_off_by_default_and_survives_reset() {
+    let mut vm = common::run_source(SOURCE);
+    assert!(vm.profiler.is_none());
+
+    vm.enable_profiler();
+    vm.reset(false);
+    common::run_source_on(&mut vm, "function f() return 1 end\nf()\nf()\n");
+    let profiler = vm.profiler.as_ref().unwrap();
+    assert!(profiler.functions().iter().all(|f| !f.name.contains("fib")));
+    let f = profiler
+        .functions()
+        .into_iter()
+        .find(|f| f.name.contains("f"))
+        .unwrap();
+    assert_eq!(f.calls, 2);
+}
@@ -0,0 +1,175 @@
+mod common;
+
+use common::{
+    global_integer, global_is_nil, global_number, global_string, run_source, run_until_error,
+};
+use myula::common::object::LuaValue;
+
+#[test]
+fn test_pcall_catches_errors() {
+    let vm = run_source(
+        "
+        failed = pcall(error, \"boom\")
+        passed = pcall(function(a) return a * 2 end, 21)
+        xpcall(function() error(\"boom\") end, function(m) msg = m end)
+        xpcall(function() local t = nil return t.x end, function(m) vm_msg = m end)
+        xpcall(function() error({code = 7}) end, function(e) code = e.code end)
+        handled = xpcall(error, function(m) return m .. \"!\" end, \"raised\")
+        value = assert(5, \"unused\")
+        xpcall(assert, function(m) assert_msg = m end, false)
+        xpcall(assert, function(m) custom_msg = m end, nil, \"custom\")
+        ",
+    );
+
+    assert!(matches!(
+        vm.get_global("failed"),
+        Some(LuaValue::Boolean(false))
+    ));
+    assert!(matches!(
+        vm.get_global("passed"),
+        Some(LuaValue::Boolean(true))
+    ));
+    assert!(matches!(
+        vm.get_global("handled"),
+        Some(LuaValue::Boolean(false))
+    ));
+    assert_eq!(global_string(&vm, "msg"), "boom");
+    assert!(global_string(&vm, "vm_msg").contains("Exception"));
+    assert_eq!(global_number(&vm, "code"), 7.0);
+    assert_eq!(global_number(&vm, "value"), 5.0);
+    assert_eq!(global_string(&vm, "assert_msg"), "assertion failed!");
+    assert_eq!(global_string(&vm, "custom_msg"), "custom");
+}
+
+#[test]
+fn test_pcall_returns_the_error_and_every_result() {
+    let vm = run_source(
+        "
+        local ok, err = pcall(error, \"boom\")
+        failed, err_msg = ok, err
+        local ok2, a, b, c = pcall(function(x) return x, x + 1, x + 2 end, 10)
+        passed, sum = ok2, a + b + c
+        local ok3, none = pcall(function() end)
+        empty_ok, empty = ok3, none
+        local _, table_err = pcall(error, {code = 7})
+        err_code = table_err.code
+        local xok, handled = xpcall(error, function(m) return m .. \"!\" end, \"raised\")
+        x_failed, x_handled = xok, handled
+        local xok2, first, second = xpcall(function() return 1, 2 end, print)
+        x_passed, x_sum = xok2, first + second
+        ",
+    );
+
+    assert!(matches!(
+        vm.get_global("failed"),
+        Some(LuaValue::Boolean(false))
+    ));
+    assert_eq!(global_string(&vm, "err_msg"), "boom");
+    assert!(matches!(
+        vm.get_global("passed"),
+        Some(LuaValue::Boolean(true))
+    ));
+    assert_eq!(global_integer(&vm, "sum"), 33);
+    assert!(matches!(
+        vm.get_global("empty_ok"),
+        Some(LuaValue::Boolean(true))
+    ));
+    assert!(global_is_nil(&vm, "empty"));
+    assert_eq!(global_integer(&vm, "err_code"), 7);
+    assert!(matches!(
+        vm.get_global("x_failed"),
+        Some(LuaValue::Boolean(false))
+    ));
+    assert_eq!(global_string(&vm, "x_handled"), "raised!");
+    assert!(matches!(
+        vm.get_global("x_passed"),
+        Some(LuaValue::Boolean(true))
+    ));
+    assert_eq!(global_integer(&vm, "x_sum"), 3);
+}
+
+#[test]
+fn test_errors_unwind_to_the_protected_frame() {
+    let vm = run_source(
+        "
+        function dive(n)
+            local depth = n
+            keep = function() return depth end
+            if n == 0 then
+                error(\"bottom\")
+            end
+            return dive(n - 1)
+        end
+
+        ok = xpcall(dive, function(m) msg = m end, 5)
+        -- the closure outlives its unwound frame
+        kept = keep()
+        -- and the VM keeps running normally afterwards
+        function sum(n)
+            if n == 0 then
+                return 0
+            end
+            return n + sum(n - 1)
+        end
+        total = sum(10)
+        nested = pcall(function() return pcall(error, \"inner\") end)
+        ",
+    );
+
+    assert!(matches!(
+        vm.get_global("ok"),
+        Some(LuaValue::Boolean(false))
+    ));
+    assert_eq!(global_string(&vm, "msg"), "bottom");
+    assert_eq!(global_number(&vm, "kept"), 0.0);
+    assert_eq!(global_number(&vm, "total"), 55.0);
+    assert!(matches!(
+        vm.get_global("nested"),
+        Some(LuaValue::Boolean(true))
+    ));
+    assert!(vm.call_stack.is_empty());
+}
+
+#[test]
+fn test_xpcall_handler_runs_before_the_unwind() {
+    let vm = run_source(
+        "
+        function inner()
+            error(\"deep\")
+        end
+        function outer()
+            inner()
+        end
+
+        ok = xpcall(outer, function(m) trace = debug.traceback(m) end)
+        -- an error in the handler is unwound together with the failed call
+        again = xpcall(outer, function(m) error(\"again\") end)
+        ",
+    );
+
+    assert!(matches!(
+        vm.get_global("ok"),
+        Some(LuaValue::Boolean(false))
+    ));
+    let trace = global_string(&vm, "trace");
+    assert!(trace.starts_with("deep\nstack traceback:"), "{}", trace);
+    assert!(trace.contains("inner"), "{}", trace);
+    assert!(trace.contains("outer"), "{}", trace);
+    assert!(matches!(
+        vm.get_global("again"),
+        Some(LuaValue::Boolean(false))
+    ));
+    assert!(vm.call_stack.is_empty());
+}
+
+#[test]
+fn test_uncaught_error_ends_the_program() {
+    let err = run_until_error("error(\"fatal\")").expect("expected an error");
+    assert!(err.to_string().contains("RuntimeException: fatal"));
+
+    let err = run_until_error("assert(1 > 2)").expect("expected an error");
+    assert!(
+        err.to_string()
+            .contains("RuntimeException: assertion failed!")
+    );
+}
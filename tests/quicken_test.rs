@@ -0,0 +1,58 @@
+use myula::Myula;
+
+// a budget keeps calls out of the JIT (feature `jit`), so every ADD runs in the interpreter
+fn interpreted() -> Myula {
+    let mut lua = Myula::new();
+    lua.vm_mut().set_fuel(Some(u64::MAX / 2));
+    lua
+}
+
+#[test]
+fn test_hot_integer_add_is_quickened() {
+    let mut lua = interpreted();
+    lua.exec("s = 0\nlocal i = 1\nwhile i <= 100 do\n  s = s + i\n  i = i + 1\nend\n")
+        .unwrap();
+    assert_eq!(lua.get_global("s").unwrap().as_integer(), Some(5050));
+    assert!(lua.vm_mut().stats.quickened >= 1);
+    assert_eq!(lua.vm_mut().stats.deopts, 0);
+}
+
+#[test]
+fn test_quickened_add_deopts_when_the_types_change() {
+    let mut lua = interpreted();
+    lua.exec(
+        "function add(a, b) return a + b end\n\
+         s = 0\n\
+         local i = 1\n\
+         while i <= 50 do s = add(s, i) i = i + 1 end\n\
+         f = add(0.5, 0.25)\n\
+         t = add(1, 0.5)\n",
+    )
+    .unwrap();
+    assert_eq!(lua.get_global("s").unwrap().as_integer(), Some(1275));
+    assert_eq!(lua.get_global("f").unwrap().as_number(), Some(0.75));
+    assert_eq!(lua.get_global("t").unwrap().as_number(), Some(1.5));
+    assert!(lua.vm_mut().stats.quickened >= 1);
+    assert!(lua.vm_mut().stats.deopts >= 1);
+}
+
+#[test]
+fn test_add_that_keeps_deopting_stays_generic() {
+    let mut lua = interpreted();
+    lua.exec(
+        "function add(a, b) return a + b end\n\
+         s = 0\n\
+         local round = 0\n\
+         while round < 20 do\n\
+           local i = 0\n\
+           while i < 20 do s = add(s, 1) i = i + 1 end\n\
+           f = add(0.5, 0.5)\n\
+           round = round + 1\n\
+         end\n",
+    )
+    .unwrap();
+    assert_eq!(lua.get_global("s").unwrap().as_integer(), Some(400));
+    // the ADD in `add` went back to generic four times, then stopped being quickened
+    let deopts = lua.vm_mut().stats.deopts;
+    assert_eq!(deopts, 4);
+}
@@ -0,0 +1,70 @@
+use myula::engine::{EngineError, Value};
+use myula::repl::{CONTINUATION_PROMPT, PROMPT, Repl, ReplOutcome};
+
+fn values(outcome: ReplOutcome) -> Vec<Value> {
+    match outcome {
+        ReplOutcome::Done(Ok(values)) => values,
+        other => panic!("expected values, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_repl_keeps_globals_and_prints_expressions() {
+    let mut repl = Repl::new();
+    assert_eq!(values(repl.feed_line("1 + 2")), vec![Value::Integer(3)]);
+    // statements print nothing, assignments included
+    assert_eq!(values(repl.feed_line("x = 10")), vec![]);
+    assert_eq!(values(repl.feed_line("x * 2")), vec![Value::Integer(20)]);
+
+    // an unfinished block is continued on the next lines
+    assert!(matches!(
+        repl.feed_line("function sq(n)"),
+        ReplOutcome::NeedMore
+    ));
+    assert!(repl.is_continuing());
+    assert!(matches!(
+        repl.feed_line("  return n * n"),
+        ReplOutcome::NeedMore
+    ));
+    assert_eq!(values(repl.feed_line("end")), vec![]);
+    assert!(!repl.is_continuing());
+
+    assert_eq!(
+        values(repl.feed_line("sq(x) + 1")),
+        vec![Value::Integer(101)]
+    );
+    assert!(matches!(repl.feed_line("y = (1 +"), ReplOutcome::NeedMore));
+    assert_eq!(values(repl.feed_line("2)")), vec![]);
+    assert_eq!(values(repl.feed_line("y")), vec![Value::Integer(3)]);
+}
+
+#[test]
+fn test_repl_recovers_from_errors() {
+    let mut repl = Repl::new();
+    assert!(matches!(
+        repl.feed_line("y = = 1"),
+        ReplOutcome::Done(Err(EngineError::Parse(_)))
+    ));
+    assert!(matches!(
+        repl.feed_line("error(\"boom\")"),
+        ReplOutcome::Done(Err(EngineError::Runtime(_)))
+    ));
+    // nothing of the failed chunk is left behind
+    assert_eq!(
+        values(repl.feed_line("\"a\" .. \"b\"")),
+        vec![Value::String("ab".into())]
+    );
+}
+
+#[test]
+fn test_repl_session_output() {
+    let input = "s = \"my\"\ns .. \"ula\"\nif s then\nt = 1\nend\nt\n";
+    let mut out = Vec::new();
+    Repl::new().run(input.as_bytes(), &mut out).unwrap();
+
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("myula\n"), "{}", out);
+    assert!(out.contains(CONTINUATION_PROMPT), "{}", out);
+    assert!(out.contains("1\n"), "{}", out);
+    assert!(out.starts_with(PROMPT), "{}", out);
+}
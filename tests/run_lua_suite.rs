@@ -0,0 +1,75 @@
+// runs every script under lua_tests/ and compares what it printed with the `.expected` file next to it,
+// a script that fails ends its output with "error: <message>"
+//
+// after an intended change of output, `BLESS=1 cargo test --test run_lua_suite` rewrites the files
+
+mod common;
+
+use common::Capture;
+use myula::Myula;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn scripts(dir: &Path, found: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            scripts(&path, found);
+        } else if path.extension().is_some_and(|ext| ext == "lua") {
+            found.push(path);
+        }
+    }
+}
+
+fn run_script(path: &Path) -> String {
+    let source = fs::read_to_string(path).unwrap();
+    let capture = Capture::default();
+
+    let mut lua = Myula::new();
+    lua.vm_mut().set_stdout(Box::new(capture.clone()));
+    lua.vm_mut().chunk_name = path.to_string_lossy().replace('\\', "/").into();
+    let result = lua.exec(&source);
+
+    let mut output = capture.text();
+    if let Err(err) = result {
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        output.push_str(&format!("error: {}\n", err));
+    }
+    output
+}
+
+#[test]
+fn test_lua_suite_matches_expected_output() {
+    let bless = std::env::var_os("BLESS").is_some();
+    let mut paths = Vec::new();
+    scripts(Path::new("lua_tests"), &mut paths);
+    paths.sort();
+    assert!(!paths.is_empty(), "no scripts under lua_tests/");
+
+    let mut failures = Vec::new();
+    for path in &paths {
+        let output = run_script(path);
+        let expected_path = path.with_extension("expected");
+        if bless {
+            fs::write(&expected_path, &output).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == output => {}
+            Ok(expected) => failures.push(format!(
+                "{}: output differs\n--- expected\n{}--- actual\n{}",
+                path.display(),
+                expected,
+                output
+            )),
+            Err(_) => failures.push(format!(
+                "{}: no {} (run with BLESS=1 to create it)",
+                path.display(),
+                expected_path.display()
+            )),
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
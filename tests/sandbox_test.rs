@@ -0,0 +1,96 @@
+use myula::Myula;
+use myula::backend::vm::config::VmConfig;
+use myula::backend::vm::error::ErrorKind;
+use myula::engine::{EngineError, Value};
+
+fn runtime_error(result: Result<Vec<Value>, EngineError>) -> ErrorKind {
+    match result {
+        Err(EngineError::Runtime(err)) => err.kind,
+        other => panic!("expected a runtime error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_sandboxed_vm_leaves_out_io_os_require_and_debug() {
+    let mut lua = Myula::with_config(VmConfig::sandboxed());
+    for name in ["io", "os", "require", "dofile", "package", "debug"] {
+        assert_eq!(lua.get_global(name).unwrap(), Value::Nil, "{}", name);
+    }
+    assert!(matches!(
+        runtime_error(lua.exec("io.write('x')")),
+        ErrorKind::UndefinedVariable(name) if name == "io"
+    ));
+    assert_eq!(
+        lua.exec("return string.upper('ok') .. math.floor(2.5)")
+            .unwrap(),
+        vec![Value::String("OK2".into())]
+    );
+
+    // each group can be switched on its own
+    let lua = Myula::with_config(VmConfig::sandboxed().with_os(true));
+    assert!(matches!(lua.get_global("os").unwrap(), Value::Table { .. }));
+    assert_eq!(lua.get_global("io").unwrap(), Value::Nil);
+}
+
+#[test]
+fn test_heap_limit_is_per_vm() {
+    const FILL: &str = "local t = {}\nlocal i = 0\nwhile i < 50000 do\n  i = i + 1\n  t[i] = 'item ' .. i\nend\nreturn i\n";
+
+    let mut small = Myula::with_config(VmConfig::default().with_heap_limit(1024 * 1024));
+    assert!(matches!(
+        runtime_error(small.exec(FILL)),
+        ErrorKind::OutOfMemory
+    ));
+    // what the failed chunk held is garbage now, the VM keeps working
+    small.vm_mut().collect_garbage();
+    assert_eq!(small.exec("return 1 + 1").unwrap(), vec![Value::Integer(2)]);
+
+    let mut large = Myula::new();
+    assert_eq!(large.exec(FILL).unwrap(), vec![Value::Integer(50000)]);
+}
+
+#[test]
+fn test_global_whitelist_refuses_other_assignments() {
+    let mut lua = Myula::with_config(VmConfig::default().with_global_whitelist(["result"]));
+    lua.exec("result = 1 + 2").unwrap();
+    assert_eq!(lua.get_global("result").unwrap(), Value::Integer(3));
+
+    let kind = runtime_error(lua.exec("other = 1"));
+    assert!(matches!(kind, ErrorKind::SandboxViolation(_)), "{:?}", kind);
+    assert!(matches!(
+        runtime_error(lua.exec("function helper() end")),
+        ErrorKind::SandboxViolation(_)
+    ));
+    assert_eq!(lua.get_global("other").unwrap(), Value::Nil);
+
+    // a script can catch the refusal, locals are not affected
+    lua.exec("local ok = pcall(function() other = 1 end)\nlocal x = 5\nresult = ok")
+        .unwrap();
+    assert_eq!(lua.get_global("result").unwrap(), Value::Boolean(false));
+
+    // the host is not restricted
+    lua.set_global("other", &Value::Integer(7)).unwrap();
+    assert_eq!(lua.get_global("other").unwrap(), Value::Integer(7));
+
+    // nor can the script go through `_G`, it does not have one
+    assert!(matches!(
+        runtime_error(lua.exec("_G.other = 1")),
+        ErrorKind::UndefinedVariable(name) if name == "_G"
+    ));
+}
+
+#[test]
+fn test_call_depth_and_gc_threshold_are_per_vm() {
+    const DEEP: &str = "function depth(n)\n  if n == 0 then return 0 end\n  return 1 + depth(n - 1)\nend\nreturn depth(50)\n";
+
+    let mut shallow = Myula::with_config(VmConfig::default().with_max_call_stack(20));
+    let kind = runtime_error(shallow.exec(DEEP));
+    assert!(matches!(kind, ErrorKind::StackOverflow), "{:?}", kind);
+    let mut deep = Myula::with_config(VmConfig::default().with_max_call_stack(100));
+    assert_eq!(deep.exec(DEEP).unwrap(), vec![Value::Integer(50)]);
+
+    let mut lua = Myula::with_config(VmConfig::default().with_gc_threshold(16 * 1024 * 1024));
+    assert_eq!(lua.vm_mut().heap.threshold, 16 * 1024 * 1024);
+    lua.vm_mut().reset(true);
+    assert_eq!(lua.vm_mut().heap.threshold, 16 * 1024 * 1024);
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use myula::backend::translator::scanner::{Scanner, VarKind};
+use myula::frontend::ir::{
+    IRBasicBlock, IRBinOp, IRFunction, IRGenerator, IRInstruction, IRModule, IROperand,
+    IRTerminator,
+};
+use myula::frontend::lexer::Lexer;
+use myula::frontend::parser::Parser;
+use myula::frontend::parser::ast::Span;
+use myula::lsp::json::Json;
+
+fn block(id: usize, instructions: Vec<IRInstruction>, terminator: IRTerminator) -> IRBasicBlock {
+    let lines = vec![0; instructions.len()];
+    let spans = vec![Span::default(); instructions.len()];
+    IRBasicBlock {
+        id,
+        instructions,
+        terminator,
+        lines,
+        terminator_line: 0,
+        spans,
+        terminator_span: Span::default(),
+    }
+}
+
+fn add(dest: usize, src1: usize, src2: usize) -> IRInstruction {
+    IRInstruction::Binary {
+        dest,
+        operator: IRBinOp::Add,
+        src1: IROperand::Reg(src1),
+        src2: IROperand::Reg(src2),
+    }
+}
+
+#[test]
+fn test_value_live_around_loop_keeps_its_register() {
+    // %0 is read early in the loop body only, the temps after it must not take its register
+    // or the next iteration reads theirs
+    let func = IRFunction {
+        name: "_start".to_string(),
+        params: Vec::new(),
+        basic_blocks: vec![
+            block(
+                0,
+                vec![IRInstruction::LoadImm {
+                    dest: 0,
+                    value: IROperand::ImmInt(1),
+                }],
+                IRTerminator::Jump(1),
+            ),
+            block(
+                1,
+                vec![IRInstruction::LoadImm {
+                    dest: 1,
+                    value: IROperand::ImmBool(true),
+                }],
+                IRTerminator::Branch {
+                    cond: IROperand::Reg(1),
+                    br_true: 2,
+                    br_false: 3,
+                },
+            ),
+            block(
+                2,
+                vec![add(2, 0, 0), add(3, 2, 2), add(4, 3, 3)],
+                IRTerminator::Jump(1),
+            ),
+            block(3, Vec::new(), IRTerminator::Return(Vec::new())),
+        ],
+        local_variables: HashMap::new(),
+        local_positions: Vec::new(),
+        upvalues: HashMap::new(),
+        sub_functions: Vec::new(),
+    };
+    let module = IRModule {
+        functions: vec![func],
+    };
+
+    let mut scanner = Scanner::new();
+    scanner.global_scan(&module);
+
+    let reg = |id| scanner.reg_map[&("_start".to_string(), VarKind::Reg(id))];
+    for temp in 1..=4 {
+        assert_ne!(reg(0), reg(temp), "%0 shares a register with %{}", temp);
+    }
+
+    // %0 lives up to the back edge
+    let lifetime = &scanner.lifetimes[&("_start".to_string(), VarKind::Reg(0))];
+    let back_edge = scanner.lifetimes[&("_start".to_string(), VarKind::Reg(4))].end + 1;
+    assert!(lifetime.end >= back_edge, "{:?}", lifetime);
+}
+
+// registers used by the main chunk of `source`
+fn chunk_usage(source: &str) -> usize {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+    let mut ir_gen = IRGenerator::new().with_dce(false);
+    ir_gen.generate(&program);
+
+    let mut scanner = Scanner::new();
+    scanner.global_scan(ir_gen.get_module());
+    scanner.func_stack_info["_start"].1
+}
+
+#[test]
+fn test_dropped_values_free_their_registers() {
+    // the surplus values are dropped after the whole list is evaluated, but none of them is read
+    let one = chunk_usage("local a = 1, print(1)\n");
+    let many = chunk_usage("local a = 1, print(1), print(2), print(3), print(4)\n");
+    assert_eq!(one, many);
+
+    // a dropped value dies at its definition
+    let module = {
+        let mut lexer = Lexer::new("x = 1\n");
+        let mut parser = Parser::new(&mut lexer);
+        let program = parser.parse();
+        let mut ir_gen = IRGenerator::new();
+        ir_gen.generate(&program);
+        ir_gen.get_module().clone()
+    };
+    let mut scanner = Scanner::new();
+    scanner.global_scan(&module);
+    let dropped: Vec<usize> = module.functions[0]
+        .basic_blocks
+        .iter()
+        .flat_map(|b| &b.instructions)
+        .filter_map(|instr| match instr {
+            IRInstruction::Drop {
+                src: IROperand::Reg(id),
+            } => Some(*id),
+            _ => None,
+        })
+        .collect();
+    assert!(!dropped.is_empty(), "{}", module.to_string());
+    for id in dropped {
+        let lifetime = &scanner.lifetimes[&("_start".to_string(), VarKind::Reg(id))];
+        assert_eq!(lifetime.start, lifetime.end, "%{}: {:?}", id, lifetime);
+    }
+}
+
+#[test]
+fn test_function_analysis_reports_the_allocation() {
+    let mut lexer =
+        Lexer::new("local a = 1\nlocal function f(x) return x end\nprint(f(a) .. \"s\")\n");
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+    let mut ir_gen = IRGenerator::new();
+    ir_gen.generate(&program);
+    let mut scanner = Scanner::new();
+    scanner.global_scan(ir_gen.get_module());
+
+    let analyses = scanner.analyses();
+    // by name, the local function sorts before the main chunk
+    let names: Vec<&str> = analyses.iter().map(|a| a.name.as_str()).collect();
+    assert_eq!(names.len(), 2);
+    assert!(names.is_sorted(), "{:?}", names);
+    assert!(scanner.analysis("missing").is_none());
+
+    let start = scanner.analysis("_start").unwrap();
+    assert_eq!(start.locals.len(), start.num_locals);
+    for (slot, alloc) in start.locals.iter().enumerate() {
+        assert_eq!(alloc.var, VarKind::Slot(slot));
+        assert_eq!(alloc.reg, slot);
+    }
+    // temporaries go above the locals, ordered by where they start
+    assert!(!start.temps.is_empty());
+    let starts: Vec<usize> = start
+        .temps
+        .iter()
+        .map(|t| start.lifetimes[&t.var].0)
+        .collect();
+    assert!(starts.is_sorted(), "{:?}", starts);
+    for temp in &start.temps {
+        assert!(matches!(temp.var, VarKind::Reg(_)));
+        assert!(temp.reg >= start.num_locals && temp.reg < start.max_stack);
+    }
+    // the literal 1 and the function prototype have known types
+    let types: Vec<&str> = start.types.values().map(String::as_str).collect();
+    assert!(
+        types.contains(&"Integer") && types.contains(&"Function"),
+        "{:?}",
+        types
+    );
+
+    let json = Json::parse(&start.to_json()).unwrap();
+    assert_eq!(json.get("function").as_str(), Some("_start"));
+    assert_eq!(json.get("locals").as_usize(), Some(start.num_locals));
+    let registers = json.get("registers").as_array();
+    assert_eq!(registers.len(), start.locals.len() + start.temps.len());
+    assert_eq!(registers[0].get("name").as_str(), Some("%local_0"));
+    assert_eq!(registers[0].get("kind").as_str(), Some("local"));
+}
@@ -0,0 +1,83 @@
+use myula::frontend::ir::{IRGenerator, IRShadowWarning};
+use myula::frontend::lexer::Lexer;
+use myula::frontend::parser::Parser;
+
+fn shadow_warnings(source: &str, enabled: bool) -> Vec<IRShadowWarning> {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+    assert!(parser.get_err().is_empty(), "{:#?}", parser.get_err());
+
+    let mut ir_gen = IRGenerator::new().with_warn_shadow(enabled);
+    ir_gen.generate(&program);
+    ir_gen.get_warnings().clone()
+}
+
+#[test]
+fn test_shadowed_locals_are_reported() {
+    let source =
+        "local x = 1\nlocal get = function() return x end\nlocal x = 2\nlocal y, x = 3, 4\n";
+    let warnings = shadow_warnings(source, true);
+
+    assert_eq!(warnings.len(), 2);
+    assert_eq!(warnings[0].name, "x");
+    assert_eq!(warnings[0].pos, source.find("x = 2").unwrap());
+    assert_eq!(warnings[0].shadowed_pos, 6);
+    assert!(!warnings[0].outer && !warnings[0].is_param);
+    // the first x was captured by `get`, which keeps seeing the first x
+    assert!(warnings[0].captured);
+    assert_eq!(warnings[1].shadowed_pos, warnings[0].pos);
+
+    assert_eq!(
+        warnings[0].describe(source),
+        "local 'x' at 3:7 redeclares local 'x' declared at 1:7 \
+         (the shadowed variable is captured by a closure)"
+    );
+
+    assert!(shadow_warnings(source, false).is_empty());
+}
+
+#[test]
+fn test_parameters_shadowing_upvalues_are_reported() {
+    let source = "
+    local count = 0
+    local total = 0
+    local function add(count)
+        local total = count
+        return total
+    end
+    local other = function(a, b) return a + b end
+    ";
+    let warnings = shadow_warnings(source, true);
+
+    assert_eq!(warnings.len(), 2);
+    let param = &warnings[0];
+    assert_eq!(param.name, "count");
+    assert!(param.is_param && param.outer && !param.captured);
+    assert_eq!(param.pos, source.find("count)").unwrap());
+    assert_eq!(param.shadowed_pos, source.find("count = 0").unwrap());
+
+    let local = &warnings[1];
+    assert_eq!(local.name, "total");
+    assert!(!local.is_param && local.outer);
+    assert!(
+        local
+            .describe(source)
+            .starts_with("local 'total' at 5:15 shadows upvalue 'total' declared at 3:11")
+    );
+}
+
+#[test]
+fn test_block_locals_shadow_only_while_visible() {
+    let source = "local a = 1\ndo\n    local a = 2\n    local b = 3\nend\nlocal b = 4\n";
+    let warnings = shadow_warnings(source, true);
+
+    // b of the do block is out of sight when the second b is declared
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].name, "a");
+    assert!(!warnings[0].outer);
+    assert_eq!(
+        warnings[0].describe(source),
+        "local 'a' at 3:11 redeclares local 'a' declared at 1:7"
+    );
+}
@@ -0,0 +1,192 @@
+mod common;
+
+use myula::Myula;
+use myula::backend::vm::VirtualMachine;
+use myula::backend::vm::config::VmConfig;
+use myula::backend::vm::error::ErrorKind;
+use myula::backend::vm::hook::{HookEvent, HookMask};
+use myula::backend::vm::snapshot::VmImage;
+use myula::common::object::LuaValue;
+use myula::engine::Value;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn test_restore_brings_back_globals_closures_and_cycles() {
+    let mut lua = Myula::new();
+    lua.exec(
+        r#"
+        local count = 0
+        function bump(n)
+            count = count + n
+            return count
+        end
+        bump(5)
+
+        node = { name = "a" }
+        node.self = node
+        node.next = { name = "b", back = node }
+        shared = node.next
+        list = { 10, 20, 30 }
+
+        obj = setmetatable({}, { greeting = "hello" })
+        upper = string.upper
+        math.randomseed(7)
+        "#,
+    )
+    .unwrap();
+    let image = lua.vm_mut().snapshot().unwrap();
+    // `return f(x)` is a tail call, which drops the argument when a chunk returns it directly
+    let expected = lua
+        .exec("local n = bump(1) local r = math.random(1, 1000000) return n .. \":\" .. r")
+        .unwrap();
+
+    // the image survives a trip through storage
+    let image = VmImage::from_bytes(image.into_bytes()).unwrap();
+    let mut restored = Myula::new();
+    restored.vm_mut().restore(&image).unwrap();
+
+    assert_eq!(
+        restored
+            .exec("local n = bump(1) local r = math.random(1, 1000000) return n .. \":\" .. r")
+            .unwrap(),
+        expected
+    );
+    assert_eq!(
+        restored.exec("local v = upper(\"abc\") return v").unwrap(),
+        vec![Value::String("ABC".into())]
+    );
+    for (expr, expected) in [
+        ("node.self == node", Value::Boolean(true)),
+        ("node.next.back == node", Value::Boolean(true)),
+        ("shared == node.next", Value::Boolean(true)),
+        ("#list", Value::Integer(3)),
+        ("list[3]", Value::Integer(30)),
+    ] {
+        let source = format!("local v = {} return v", expr);
+        assert_eq!(restored.exec(&source).unwrap(), vec![expected], "{}", expr);
+    }
+    assert_eq!(
+        restored
+            .exec("local mt = getmetatable(obj) local v = mt.greeting return v")
+            .unwrap(),
+        vec![Value::String("hello".into())]
+    );
+}
+
+const LONG_RUNNING: &str = r#"
+function run()
+    local total = 0
+    local add = function(n) total = total + n end
+    local i = 1
+    while i <= 200 do
+        add(i)
+        i = i + 1
+    end
+    return total
+end
+result = run()
+"#;
+
+#[test]
+fn test_restored_call_stack_continues_where_it_was_saved() {
+    let mut vm = VirtualMachine::new();
+    common::init_source_on(&mut vm, LONG_RUNNING);
+    let saved: Rc<RefCell<Option<VmImage>>> = Rc::default();
+    let slot = saved.clone();
+    vm.set_hook(HookMask::count(500), move |vm, event| {
+        assert_eq!(event, HookEvent::Count);
+        if slot.borrow().is_none() {
+            // inside run(), `total` is still an open upvalue of its frame
+            assert!(vm.call_stack.len() >= 2);
+            *slot.borrow_mut() = Some(vm.snapshot()?);
+        }
+        Ok(())
+    });
+    vm.execute().unwrap();
+    assert_eq!(common::global_integer(&vm, "result"), 20100);
+
+    let image = saved.borrow_mut().take().expect("the hook took a snapshot");
+    let mut restored = VirtualMachine::new();
+    restored.restore(&image).unwrap();
+    assert!(restored.call_stack.len() >= 2);
+    restored.execute().unwrap();
+    assert_eq!(common::global_integer(&restored, "result"), 20100);
+}
+
+#[test]
+fn test_host_functions_are_found_by_their_global_path() {
+    fn host_vm() -> Myula {
+        let mut lua = Myula::new();
+        lua.vm_mut().register_function("twice", |vm, argc| {
+            let n = vm.native_arg(argc, 0).as_integer().unwrap_or(0);
+            vm.value_stack.push(LuaValue::Integer(n * 2));
+            Ok(1)
+        });
+        lua
+    }
+
+    let mut lua = host_vm();
+    lua.exec("helpers = { double = twice }").unwrap();
+    let image = lua.vm_mut().snapshot().unwrap();
+
+    let mut restored = host_vm();
+    restored.vm_mut().restore(&image).unwrap();
+    assert_eq!(
+        restored
+            .exec("local n = helpers.double(21) return n")
+            .unwrap(),
+        vec![Value::Integer(42)]
+    );
+
+    // a VM without the host function cannot take the image
+    let mut bare = Myula::new();
+    let err = bare.vm_mut().restore(&image).unwrap_err();
+    assert!(err.get_message().contains("'twice'"), "{}", err);
+    // and is left usable
+    assert_eq!(bare.exec("return 1 + 1").unwrap(), vec![Value::Integer(2)]);
+
+    // neither can a sandbox, the image refers to the os and debug libraries
+    let mut sandbox = Myula::with_config(VmConfig::sandboxed());
+    let err = sandbox.vm_mut().restore(&image).unwrap_err();
+    assert!(err.get_message().contains("is not available"), "{}", err);
+}
+
+#[test]
+fn test_restored_g_is_the_global_table() {
+    let mut lua = Myula::new();
+    lua.exec(
+        "holder = { env = _G }\n_G.counter = 3\nsetmetatable(_G, { __index = { fallback = 9 } })",
+    )
+    .unwrap();
+    let image = lua.vm_mut().snapshot().unwrap();
+
+    let mut restored = Myula::new();
+    restored.vm_mut().restore(&image).unwrap();
+    restored
+        .exec("holder.env.counter = counter + 1\nsame = _G == holder.env\nfound = fallback")
+        .unwrap();
+    assert_eq!(restored.get_global("same").unwrap(), Value::Boolean(true));
+    assert_eq!(restored.get_global("counter").unwrap(), Value::Integer(4));
+    assert_eq!(restored.get_global("found").unwrap(), Value::Integer(9));
+}
+
+#[test]
+fn test_unsaveable_state_is_refused() {
+    let mut lua = Myula::new();
+    lua.exec("co = coroutine.create(function() end)").unwrap();
+    let err = lua.vm_mut().snapshot().unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::SnapshotError(_)), "{}", err);
+
+    let mut lua = Myula::new();
+    let data = lua.vm_mut().create_userdata(1u8).unwrap();
+    lua.vm_mut().set_global("data", data);
+    assert!(lua.vm_mut().snapshot().is_err());
+
+    assert!(VmImage::from_bytes(b"not an image".to_vec()).is_err());
+    let mut bytes = Myula::new().vm_mut().snapshot().unwrap().into_bytes();
+    bytes.truncate(bytes.len() - 3);
+    let truncated = VmImage::from_bytes(bytes).unwrap();
+    let err = Myula::new().vm_mut().restore(&truncated).unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::SnapshotError(_)), "{}", err);
+}
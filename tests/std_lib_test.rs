@@ -0,0 +1,434 @@
+mod common;
+
+use common::{
+    global_integer, global_is_nil, global_number, global_string, run_source, run_source_on,
+    run_source_with_input, run_until_error,
+};
+use myula::backend::vm::VirtualMachine;
+
+#[test]
+fn test_tostring() {
+    let vm = run_source(
+        "
+        a = tostring(10)
+        b = tostring(0.1)
+        c = tostring(nil)
+        d = tostring(false)
+        e = tostring(tonumber(\"1e100\"))
+
+        local mt = {}
+        mt.__tostring = function(p) return \"Point(\" .. p.x .. \")\" end
+        local p = setmetatable({ x = 3 }, mt)
+        f = tostring(p)
+        same_mt = getmetatable(p) == mt
+        ",
+    );
+
+    assert_eq!(global_string(&vm, "a"), "10");
+    assert_eq!(global_string(&vm, "b"), "0.1");
+    assert_eq!(global_string(&vm, "c"), "nil");
+    assert_eq!(global_string(&vm, "d"), "false");
+    assert_eq!(global_string(&vm, "e"), "1e+100");
+    assert_eq!(global_string(&vm, "f"), "Point(3)");
+    assert!(matches!(
+        vm.get_global("same_mt"),
+        Some(myula::common::object::LuaValue::Boolean(true))
+    ));
+}
+
+#[test]
+fn test_tonumber() {
+    let vm = run_source(
+        "
+        a = tonumber(\" 12.5 \")
+        b = tonumber(\"0x1F\")
+        c = tonumber(\"-0x10\")
+        d = tonumber(\"ff\", 16)
+        e = tonumber(\"zz\", 36)
+        f = tonumber(\"102\", 2)
+        g = tonumber(\"abc\")
+        h = tonumber(\"inf\")
+        i = tonumber(42)
+        ",
+    );
+
+    assert_eq!(global_number(&vm, "a"), 12.5);
+    assert_eq!(global_number(&vm, "b"), 31.0);
+    assert_eq!(global_number(&vm, "c"), -16.0);
+    assert_eq!(global_number(&vm, "d"), 255.0);
+    assert_eq!(global_number(&vm, "e"), 1295.0);
+    assert!(global_is_nil(&vm, "f"));
+    assert!(global_is_nil(&vm, "g"));
+    assert!(global_is_nil(&vm, "h"));
+    assert_eq!(global_number(&vm, "i"), 42.0);
+}
+
+#[test]
+fn test_string_library() {
+    let vm = run_source(
+        "
+        local s = \"Hello, World\"
+        len = string.len(s)
+        sub1 = string.sub(s, 1, 5)
+        sub2 = string.sub(s, -5)
+        sub3 = string.sub(s, 8, 100)
+        empty = string.sub(s, 5, 2)
+        up = string.upper(s)
+        low = s:lower()
+        rep = string.rep(\"ab\", 3, \"-\")
+        byte = string.byte(\"A\")
+        last = string.byte(s, -1)
+        chars = string.char(72, 105)
+        pos = string.find(s, \"World\", 1, true)
+        plain = string.find(s, \"o\", 6)
+        missing = string.find(s, \"xyz\", 1, true)
+        num_len = string.len(12.5)
+        ",
+    );
+
+    assert_eq!(global_number(&vm, "len"), 12.0);
+    assert_eq!(global_string(&vm, "sub1"), "Hello");
+    assert_eq!(global_string(&vm, "sub2"), "World");
+    assert_eq!(global_string(&vm, "sub3"), "World");
+    assert_eq!(global_string(&vm, "empty"), "");
+    assert_eq!(global_string(&vm, "up"), "HELLO, WORLD");
+    assert_eq!(global_string(&vm, "low"), "hello, world");
+    assert_eq!(global_string(&vm, "rep"), "ab-ab-ab");
+    assert_eq!(global_number(&vm, "byte"), 65.0);
+    assert_eq!(global_number(&vm, "last"), 100.0);
+    assert_eq!(global_string(&vm, "chars"), "Hi");
+    assert_eq!(global_number(&vm, "pos"), 8.0);
+    assert_eq!(global_number(&vm, "plain"), 9.0);
+    assert!(global_is_nil(&vm, "missing"));
+    assert_eq!(global_number(&vm, "num_len"), 4.0);
+}
+
+#[test]
+fn test_string_builders() {
+    let vm = run_source(
+        "
+        rev = string.reverse(\"abc\")
+        empty_rep = string.rep(\"x\", 0, \",\")
+        neg_rep = string.rep(\"x\", -3)
+        f1 = string.format(\"%5.2f|%-3d|%x|%s\", 3.14159, 7, 255, \"ok\")
+        f2 = string.format(\"%05d|%+d|%e|%g|%g\", -42, 5, 12345.678, 0.0001, 100000000000000)
+        f3 = string.format(\"%q\", string.char(97, 34, 98, 10, 0, 49))
+        f4 = string.format(\"%.3s|%c%c|%%\", \"abcdef\", 72, 105)
+        f5 = (\"%s=%s\"):format(\"x\", nil)
+        ",
+    );
+
+    assert_eq!(global_string(&vm, "rev"), "cba");
+    assert_eq!(global_string(&vm, "empty_rep"), "");
+    assert_eq!(global_string(&vm, "neg_rep"), "");
+    assert_eq!(global_string(&vm, "f1"), " 3.14|7  |ff|ok");
+    assert_eq!(
+        global_string(&vm, "f2"),
+        "-0042|+5|1.234568e+04|0.0001|1e+14"
+    );
+    assert_eq!(global_string(&vm, "f3"), "\"a\\\"b\\\n\\0001\"");
+    assert_eq!(global_string(&vm, "f4"), "abc|Hi|%");
+    assert_eq!(global_string(&vm, "f5"), "x=nil");
+
+    let err = run_until_error("s = string.format(\"%d\", 1.5)").expect("expected an error");
+    assert!(
+        err.to_string()
+            .contains("number has no integer representation")
+    );
+    let err = run_until_error("s = string.format(\"%y\", 1)").expect("expected an error");
+    assert!(err.to_string().contains("invalid conversion"));
+}
+
+#[test]
+fn test_string_patterns() {
+    let vm = run_source(
+        "
+        local line = \"name = Myula, version = 42\"
+        key = string.match(line, \"(%a+)%s*=\")
+        version = tonumber(line:match(\"version = (%d+)\"))
+        pos = string.find(line, \"%d\")
+        replaced = string.gsub(line, \"%a+\", string.upper)
+        swapped = string.gsub(\"hello world\", \"(%w+) (%w+)\", \"%2 %1\")
+        looked_up = string.gsub(\"$a-$b\", \"%$(%a)\", { a = \"1\", b = \"2\" })
+        once = string.gsub(\"aaa\", \"a\", \"b\", 1)
+
+        -- the extra results: the end and the captures of find, every capture of match,
+        -- the number of replacements of gsub
+        find_start, find_end, find_key, find_value = string.find(line, \"(%a+) = (%d+)\")
+        plain_start, plain_end = string.find(line, \"Myula\", 1, true)
+        m_key, m_value, m_pos = string.match(line, \"(%a+) = (%a+)()\")
+        local _, replacements = string.gsub(line, \"%a+\", \"x\")
+        gsub_count = replacements
+        local _, capped = string.gsub(\"aaa\", \"a\", \"b\", 2)
+        gsub_capped = capped
+
+        joined = \"\"
+        local next_word = string.gmatch(\"one two  three\", \"%a+\")
+        local w = next_word()
+        while w do
+            joined = joined .. w .. \";\"
+            w = next_word()
+        end
+        ",
+    );
+
+    assert_eq!(global_string(&vm, "key"), "name");
+    assert_eq!(global_number(&vm, "version"), 42.0);
+    assert_eq!(global_number(&vm, "pos"), 25.0);
+    assert_eq!(global_string(&vm, "replaced"), "NAME = MYULA, VERSION = 42");
+    assert_eq!(global_string(&vm, "swapped"), "world hello");
+    assert_eq!(global_string(&vm, "looked_up"), "1-2");
+    assert_eq!(global_string(&vm, "once"), "baa");
+    assert_eq!(global_integer(&vm, "find_start"), 15);
+    assert_eq!(global_integer(&vm, "find_end"), 26);
+    assert_eq!(global_string(&vm, "find_key"), "version");
+    assert_eq!(global_string(&vm, "find_value"), "42");
+    assert_eq!(global_integer(&vm, "plain_start"), 8);
+    assert_eq!(global_integer(&vm, "plain_end"), 12);
+    assert_eq!(global_string(&vm, "m_key"), "name");
+    assert_eq!(global_string(&vm, "m_value"), "Myula");
+    assert_eq!(global_integer(&vm, "m_pos"), 13);
+    assert_eq!(global_integer(&vm, "gsub_count"), 3);
+    assert_eq!(global_integer(&vm, "gsub_capped"), 2);
+    assert_eq!(global_string(&vm, "joined"), "one;two;three;");
+}
+
+#[test]
+fn test_math_library() {
+    let vm = run_source(
+        "
+        floor = math.floor(-3.5)
+        ceil = math.ceil(3.2)
+        abs = math.abs(-7)
+        sqrt = math.sqrt(\"16\")
+        max = math.max(3, 9, -1)
+        min = math.min(3, 9, -1)
+        fmod = math.fmod(-7, 3)
+        log = math.log(8, 2)
+        big = math.huge > 999999999999 and -math.huge < 0
+        pi = math.pi
+
+        math.randomseed(42)
+        first = math.random(1, 100)
+        math.randomseed(42)
+        again = math.random(1, 100)
+
+        in_range = true
+        local i = 0
+        while i < 200 do
+            local r = math.random(3)
+            local f = math.random()
+            if r < 1 or r > 3 or r ~= math.floor(r) or f < 0 or f >= 1 then
+                in_range = false
+            end
+            i = i + 1
+        end
+        ",
+    );
+
+    assert_eq!(global_number(&vm, "floor"), -4.0);
+    assert_eq!(global_number(&vm, "ceil"), 4.0);
+    assert_eq!(global_number(&vm, "abs"), 7.0);
+    assert_eq!(global_number(&vm, "sqrt"), 4.0);
+    assert_eq!(global_number(&vm, "max"), 9.0);
+    assert_eq!(global_number(&vm, "min"), -1.0);
+    assert_eq!(global_number(&vm, "fmod"), -1.0);
+    assert_eq!(global_number(&vm, "log"), 3.0);
+    assert_eq!(global_number(&vm, "pi"), std::f64::consts::PI);
+    assert_eq!(global_number(&vm, "first"), global_number(&vm, "again"));
+    assert!(matches!(
+        vm.get_global("big"),
+        Some(myula::common::object::LuaValue::Boolean(true))
+    ));
+    assert!(matches!(
+        vm.get_global("in_range"),
+        Some(myula::common::object::LuaValue::Boolean(true))
+    ));
+}
+
+#[test]
+fn test_seeded_random_is_reproducible() {
+    let source = "a = math.random(1, 1000000)\nb = math.random(1, 1000000)\nf = math.random()\n";
+    let draws = |vm: &VirtualMachine| {
+        (
+            global_integer(vm, "a"),
+            global_integer(vm, "b"),
+            global_number(vm, "f"),
+        )
+    };
+
+    let mut first = VirtualMachine::new();
+    first.set_random_seed(Some(7));
+    let mut second = VirtualMachine::new();
+    second.set_random_seed(Some(7));
+
+    // the VMs do not share generator state, interleaving runs changes nothing
+    run_source_on(&mut first, source);
+    run_source_on(&mut second, "math.random()");
+    second.reset(true);
+    run_source_on(&mut second, source);
+    assert_eq!(draws(&first), draws(&second));
+
+    // reset starts over from the configured seed
+    let expected = draws(&first);
+    first.reset(true);
+    run_source_on(&mut first, source);
+    assert_eq!(draws(&first), expected);
+
+    let mut other = VirtualMachine::new();
+    other.set_random_seed(Some(8));
+    run_source_on(&mut other, source);
+    assert_ne!(draws(&other), expected);
+}
+
+#[test]
+fn test_os_library() {
+    let vm = run_source(
+        "
+        y2k = os.time({year = 2000, month = 1, day = 1, hour = 0})
+        rolled = os.time({year = 2000, month = 13, day = 1, hour = 0})
+        noon = os.time({year = 1970, month = 1, day = 2})
+        stamp = os.date(\"!%Y-%m-%d %H:%M:%S %a %b %j\", 951782400 + 3661)
+        local t = os.date(\"*t\", 0)
+        wday = t.wday
+        yday = t.yday
+        year = t.year
+        now = os.time()
+        clock = os.clock()
+        ",
+    );
+
+    assert_eq!(global_number(&vm, "y2k"), 946684800.0);
+    assert_eq!(global_number(&vm, "rolled"), 978307200.0);
+    assert_eq!(global_number(&vm, "noon"), 86400.0 + 12.0 * 3600.0);
+    assert_eq!(
+        global_string(&vm, "stamp"),
+        "2000-02-29 01:01:01 Tue Feb 060"
+    );
+    assert_eq!(global_number(&vm, "wday"), 5.0);
+    assert_eq!(global_number(&vm, "yday"), 1.0);
+    assert_eq!(global_number(&vm, "year"), 1970.0);
+    assert!(global_number(&vm, "now") > 1.7e9);
+    assert!(global_number(&vm, "clock") >= 0.0);
+}
+
+#[test]
+fn test_io_library() {
+    let vm = run_source_with_input(
+        "
+        first = io.read()
+        num = io.read(\"n\")
+        rest = io.read(\"l\")
+        two = io.read(2)
+        local it = io.lines()
+        third = it()
+        fourth = it()
+        eof = it()
+        after = io.read()
+        ",
+        "hello\n  42 rest\nxyline3\r\nline4",
+    );
+
+    assert_eq!(global_string(&vm, "first"), "hello");
+    assert_eq!(global_number(&vm, "num"), 42.0);
+    assert_eq!(global_string(&vm, "rest"), " rest");
+    assert_eq!(global_string(&vm, "two"), "xy");
+    assert_eq!(global_string(&vm, "third"), "line3");
+    assert_eq!(global_string(&vm, "fourth"), "line4");
+    assert!(global_is_nil(&vm, "eof"));
+    assert!(global_is_nil(&vm, "after"));
+
+    let path = std::env::temp_dir().join(format!("myula_io_lines_{}.txt", std::process::id()));
+    std::fs::write(&path, "alpha\nbeta\n").unwrap();
+    let vm = run_source(&format!(
+        "
+        local it = io.lines(\"{}\")
+        a = it()
+        b = it()
+        c = it()
+        ",
+        path.display()
+    ));
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(global_string(&vm, "a"), "alpha");
+    assert_eq!(global_string(&vm, "b"), "beta");
+    assert!(global_is_nil(&vm, "c"));
+
+    let err =
+        run_until_error("it = io.lines(\"/nonexistent/myula.txt\")").expect("expected an error");
+    assert!(err.to_string().contains("IOException"));
+}
+
+#[test]
+fn test_base_library() {
+    let vm = run_source(
+        "
+        kinds = type(nil) .. \",\" .. type(1) .. \",\" .. type(\"s\") .. \",\" .. type({}) .. \",\" .. type(print)
+        count = select(\"#\", 1, nil, 3)
+        second = select(2, \"a\", \"b\", \"c\")
+        last = select(-1, \"a\", \"b\", \"c\")
+        middle = unpack({ 7, 8, 9 }, 2)
+        u1, u2, u3 = unpack({ 7, 8, 9 })
+        s1, s2 = select(2, \"a\", \"b\", \"c\")
+        nk, nv = next({ 42 })
+        same = rawequal(\"x\", \"x\") and not rawequal({}, {})
+        len = rawlen({ 1, 2, 3 }) + rawlen(\"abcd\")
+
+        -- rawset and rawget go around the metatable of _G
+        setmetatable(_G, { __newindex = function(t, k, v) end })
+        ignored = 1
+        rawset(_G, \"direct\", 2)
+        rawset(_G, \"missing\", rawget(_G, \"ignored\") == nil)
+
+        local t = { 10, 20, 30, x = 40, y = 50 }
+        local visited = 0
+        local sum = 0
+        local k = next(t)
+        while k ~= nil do
+            visited = visited + 1
+            sum = sum + rawget(t, k)
+            k = next(t, k)
+        end
+        rawset(_G, \"walked\", visited)
+        rawset(_G, \"total\", sum)
+        rawset(_G, \"empty\", next({}) == nil)
+        ",
+    );
+
+    assert_eq!(
+        global_string(&vm, "kinds"),
+        "nil,number,string,table,function"
+    );
+    assert_eq!(global_integer(&vm, "count"), 3);
+    assert_eq!(global_string(&vm, "second"), "b");
+    assert_eq!(global_string(&vm, "last"), "c");
+    assert_eq!(global_integer(&vm, "middle"), 8);
+    assert_eq!(global_integer(&vm, "u1"), 7);
+    assert_eq!(global_integer(&vm, "u2"), 8);
+    assert_eq!(global_integer(&vm, "u3"), 9);
+    assert_eq!(global_string(&vm, "s1"), "b");
+    assert_eq!(global_string(&vm, "s2"), "c");
+    assert_eq!(global_integer(&vm, "nk"), 1);
+    assert_eq!(global_integer(&vm, "nv"), 42);
+    assert_eq!(global_integer(&vm, "len"), 7);
+    assert!(global_is_nil(&vm, "ignored"));
+    assert_eq!(global_integer(&vm, "direct"), 2);
+    assert_eq!(global_integer(&vm, "walked"), 5);
+    assert_eq!(global_integer(&vm, "total"), 150);
+    for name in ["same", "missing", "empty"] {
+        assert!(
+            matches!(
+                vm.get_global(name),
+                Some(myula::common::object::LuaValue::Boolean(true))
+            ),
+            "{}",
+            name
+        );
+    }
+
+    let err = run_until_error("next({}, \"nope\")").expect("expected an error");
+    assert!(err.to_string().contains("invalid key to 'next'"), "{}", err);
+    let err = run_until_error("select(0, 1)").expect("expected an error");
+    assert!(err.to_string().contains("index out of range"), "{}", err);
+}
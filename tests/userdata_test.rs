@@ -0,0 +1,123 @@
+use myula::backend::vm::VirtualMachine;
+use myula::common::object::{LuaTable, LuaValue};
+use myula::engine::{Myula, Value};
+use std::cell::Cell;
+use std::rc::Rc;
+
+mod common;
+
+struct Counter {
+    count: i64,
+}
+
+// a global `counter` userdata whose metatable gives scripts counter:add(n) and counter.count
+fn counter_vm() -> VirtualMachine {
+    let mut vm = VirtualMachine::new();
+    vm.register_function("counter_add", |vm, argc| {
+        let this = vm.native_arg(argc, 0);
+        let n = vm.native_arg(argc, 1).as_integer().unwrap_or(1);
+        let counter = vm.get_userdata_mut::<Counter>(&this).unwrap();
+        counter.count += n;
+        let count = counter.count;
+        vm.value_stack.push(LuaValue::Integer(count));
+        Ok(1)
+    });
+    vm.register_function("counter_index", |vm, argc| {
+        let this = vm.native_arg(argc, 0);
+        let count = vm.get_userdata::<Counter>(&this).unwrap().count;
+        let result = match vm.native_arg(argc, 1) {
+            LuaValue::String(s) if s.get() == "count" => LuaValue::Integer(count),
+            LuaValue::String(s) if s.get() == "add" => *vm.get_global("counter_add").unwrap(),
+            _ => LuaValue::Nil,
+        };
+        vm.value_stack.push(result);
+        Ok(1)
+    });
+
+    let counter = vm.create_userdata(Counter { count: 0 }).unwrap();
+    let index = *vm.get_global("counter_index").unwrap();
+    let key = vm.heap.alloc_str("__index").unwrap();
+    let mut mt = LuaTable::new();
+    mt.set(LuaValue::String(key), index);
+    let mt = vm.heap.alloc_table(mt).unwrap();
+    vm.set_userdata_metatable(&counter, Some(mt)).unwrap();
+    vm.set_global("counter", counter);
+    vm
+}
+
+#[test]
+fn test_scripts_use_userdata_through_its_metatable() {
+    let mut vm = counter_vm();
+    common::run_source_on(
+        &mut vm,
+        "
+counter:add(2)
+last = counter:add(3)
+count = counter.count
+has_mt = getmetatable(counter) ~= nil
+name = tostring(counter)
+same = counter == counter
+",
+    );
+    assert_eq!(common::global_integer(&vm, "last"), 5);
+    assert_eq!(common::global_integer(&vm, "count"), 5);
+    assert_eq!(vm.get_global("has_mt"), Some(&LuaValue::Boolean(true)));
+    assert!(common::global_string(&vm, "name").starts_with("userdata: "));
+    assert_eq!(vm.get_global("same"), Some(&LuaValue::Boolean(true)));
+
+    let counter = *vm.get_global("counter").unwrap();
+    assert_eq!(vm.get_userdata::<Counter>(&counter).unwrap().count, 5);
+    // the payload is only handed out as the type it was created with
+    assert!(vm.get_userdata::<String>(&counter).is_none());
+    assert!(vm.get_userdata::<Counter>(&LuaValue::Integer(1)).is_none());
+}
+
+#[test]
+fn test_userdata_without_metatable_cannot_be_indexed() {
+    let mut lua = Myula::new();
+    let vm = lua.vm_mut();
+    let plain = vm.create_userdata(42u32).unwrap();
+    vm.set_global("plain", plain);
+    assert!(
+        vm.set_userdata_metatable(&LuaValue::Integer(1), None)
+            .is_err()
+    );
+
+    let err = lua.exec("x = plain.field").unwrap_err();
+    assert!(err.to_string().contains("non-table value"), "{}", err);
+}
+
+struct DropFlag(Rc<Cell<bool>>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.set(true);
+    }
+}
+
+#[test]
+fn test_payload_is_dropped_with_the_object() {
+    let dropped = Rc::new(Cell::new(false));
+    let mut vm = VirtualMachine::new();
+    let ud = vm.create_userdata(DropFlag(dropped.clone())).unwrap();
+    vm.set_global("ud", ud);
+    vm.collect_garbage();
+    assert!(!dropped.get());
+    assert_eq!(vm.heap.stats().userdata, 1);
+
+    vm.set_global("ud", LuaValue::Nil);
+    vm.collect_garbage();
+    assert!(dropped.get());
+    assert_eq!(vm.heap.stats().userdata, 0);
+}
+
+#[test]
+fn test_facade_reports_userdata() {
+    let mut lua = Myula::new();
+    let ud = lua.vm_mut().create_userdata(1.5f64).unwrap();
+    lua.vm_mut().set_global("ud", ud);
+    assert!(matches!(
+        lua.get_global("ud").unwrap(),
+        Value::UserData { .. }
+    ));
+}
@@ -34,7 +34,7 @@ fn test_vm_from_lua_file() {
     scanner.global_scan(&ir_gen.get_module());
 
     let mut vm = VirtualMachine::new();
-    vm.init(&ir_gen, LogLevel::Debug, &mut scanner);
+    vm.init(&ir_gen, LogLevel::Debug, &mut scanner).unwrap();
 
     // 5. 打印 VM 内部状态（查看生成的 OpCode 和寄存器分配）
     println!("\n--- 编译产物展示 ---");
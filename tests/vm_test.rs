@@ -1,5 +1,10 @@
+mod common;
+
 use myula::backend::translator::scanner::Scanner;
+use myula::backend::vm::error::{ErrorKind, TRACEBACK_MAX_LINES};
 use myula::backend::vm::{LogLevel, VirtualMachine};
+use myula::common::object::LuaValue;
+use myula::common::opcode::{MULTRET, OpCode};
 use myula::frontend::ir::IRGenerator;
 use myula::frontend::lexer::Lexer;
 use myula::frontend::parser::Parser;
@@ -31,7 +36,1181 @@ fn test_vm_from_file() {
     scanner.global_scan(&ir_gen.get_module());
 
     let mut vm = VirtualMachine::new();
-    vm.init(&ir_gen, LogLevel::Debug, &mut scanner);
+    vm.init(&ir_gen, LogLevel::Debug, &mut scanner).unwrap();
 
     vm.run();
 }
+
+#[test]
+fn test_error_names_offending_operand() {
+    let err = common::run_until_error(
+        "
+        local app = {}
+        local port = app.config.port
+        ",
+    )
+    .expect("indexing a nil field must fail");
+    assert!(err.get_message().contains("field 'config'"), "{}", err);
+
+    let err = common::run_until_error(
+        "
+        limit = 10
+        limit()
+        ",
+    )
+    .expect("calling a number must fail");
+    assert!(err.get_message().contains("global 'limit'"), "{}", err);
+}
+
+#[test]
+fn test_keyed_fields_do_not_advance_the_positional_index() {
+    let vm = common::run_source(
+        "t = {a = \"x\", 10, b = \"y\", 20}\n\
+         first = t[1]\n\
+         second = t[2]\n\
+         third = t[3]\n",
+    );
+    assert_eq!(common::global_number(&vm, "first"), 10.0);
+    assert_eq!(common::global_number(&vm, "second"), 20.0);
+    assert!(common::global_is_nil(&vm, "third"));
+}
+
+#[test]
+fn test_error_reports_source_lines() {
+    let err = common::run_until_error(
+        "local x = 1
+
+local function f()
+    local y = nil
+    return y()
+end
+f()
+",
+    )
+    .expect("calling nil must fail");
+    assert_eq!(err.line(), Some(5), "{}", err);
+    assert!(err.to_string().contains(":5: "), "{}", err);
+
+    let lines = err.traceback_lines(Some(TRACEBACK_MAX_LINES));
+    assert!(lines[0].ends_with(":5)"), "{:#?}", lines);
+    assert!(
+        lines[1].contains("at _start()") && lines[1].ends_with(":7)"),
+        "{:#?}",
+        lines
+    );
+}
+
+#[test]
+fn test_deep_recursion_traceback_is_folded() {
+    let err = common::run_until_error(
+        "
+        function dive(n)
+            return 1 + dive(n + 1)
+        end
+        dive(1)
+        ",
+    )
+    .expect("unbounded recursion must overflow");
+    assert!(matches!(err.kind, ErrorKind::StackOverflow), "{}", err);
+
+    let lines = err.traceback_lines(Some(TRACEBACK_MAX_LINES));
+    assert!(lines.len() <= TRACEBACK_MAX_LINES + 1, "{:#?}", lines);
+    assert!(lines[0].contains("dive"), "{:#?}", lines);
+    assert!(
+        lines[1].starts_with(&format!(
+            "... {} more frames like",
+            err.stack_trace.len() - 2
+        )),
+        "{:#?}",
+        lines
+    );
+    assert!(
+        lines.last().unwrap().contains("at _start()"),
+        "{:#?}",
+        lines
+    );
+}
+
+#[test]
+fn test_traceback_limit_is_configurable() {
+    // 40 different functions deep, nothing to fold
+    let mut source = String::from("function f40() error(\"bottom\") end\n");
+    for i in (1..40).rev() {
+        source += &format!("function f{}() return 1 + f{}() end\n", i, i + 1);
+    }
+    source += "f1()\n";
+    let err = common::run_until_error(&source).expect("expected an error");
+
+    let full = err.traceback_lines(None);
+    assert_eq!(full.len(), err.stack_trace.len(), "{:#?}", full);
+
+    let lines = err.traceback_lines(Some(7));
+    assert_eq!(lines.len(), 8, "{:#?}", lines);
+    assert_eq!(lines[..4], full[..4]);
+    assert!(
+        lines[4].starts_with(&format!("... ({} traceback lines omitted", full.len() - 7)),
+        "{:#?}",
+        lines
+    );
+    assert_eq!(lines[5..], full[full.len() - 3..]);
+}
+
+#[test]
+fn test_tail_calls_reuse_the_frame() {
+    let source = "
+        function count(n, acc)
+            if n == 0 then
+                return acc
+            end
+            return count(n - 1, acc + 1)
+        end
+        function ping(n)
+            if n == 0 then
+                return \"ping\"
+            end
+            return pong(n - 1)
+        end
+        function pong(n)
+            if n == 0 then
+                return \"pong\"
+            end
+            return ping(n - 1)
+        end
+        function shout(s)
+            return string.upper(s)
+        end
+        deep = count(100000, 0)
+        last = ping(5001)
+        loud = shout(\"abc\")
+        ";
+    let vm = common::run_source(source);
+    assert_eq!(common::global_integer(&vm, "deep"), 100000);
+    assert_eq!(common::global_string(&vm, "last"), "pong");
+    assert_eq!(common::global_string(&vm, "loud"), "ABC");
+
+    // one in each of count, ping, pong and shout
+    let tail_calls = vm
+        .func_meta
+        .values()
+        .flat_map(|meta| meta.bytecode.iter())
+        .filter(|op| matches!(op, OpCode::TailCall { .. }))
+        .count();
+    assert_eq!(tail_calls, 4);
+    // calls whose result is used are not tail calls
+    assert!(
+        !vm.func_meta["_start"]
+            .bytecode
+            .iter()
+            .any(|op| matches!(op, OpCode::TailCall { .. }))
+    );
+}
+
+#[test]
+fn test_table_length_and_nil_assignment() {
+    let vm = common::run_source(
+        "
+        t = {10, 20, 30, name = \"list\"}
+        full = #t
+        t.name = nil
+        t[3] = nil
+        trimmed = #t
+        t[2] = nil
+        t[4] = 40
+        border = #t
+        ",
+    );
+
+    assert_eq!(common::global_number(&vm, "full"), 3.0);
+    assert_eq!(common::global_number(&vm, "trimmed"), 2.0);
+    assert_eq!(common::global_number(&vm, "border"), 1.0);
+
+    // nil assignments remove the entries instead of storing nil
+    let Some(myula::common::object::LuaValue::Table(t)) = vm.get_global("t") else {
+        panic!("t is not a table");
+    };
+    let table = vm.heap.data(*t);
+    assert_eq!(table.iter().count(), 2);
+    assert!(
+        !table
+            .get(&myula::common::object::LuaValue::Number(2.0))
+            .is_truthy()
+    );
+}
+
+#[test]
+fn test_table_array_part() {
+    let vm = common::run_source(
+        "
+        t = {10, 20, 30, name = \"list\"}
+        i = 4
+        while i <= 100 do
+            t[i] = i * 10
+            i = i + 1
+        end
+        -- filled out of order, the keys move into the array part once the gap closes
+        u = {}
+        u[3] = 3
+        u[2] = 2
+        u[1] = 1
+        u[2.0] = 22
+        n = #t
+        m = #u
+        second = u[2]
+        t[50] = nil
+        hole = t[50]
+        still = #t
+        ",
+    );
+    assert_eq!(common::global_integer(&vm, "n"), 100);
+    assert_eq!(common::global_integer(&vm, "m"), 3);
+    assert_eq!(common::global_integer(&vm, "second"), 22);
+    assert!(common::global_is_nil(&vm, "hole"));
+    assert_eq!(common::global_integer(&vm, "still"), 100);
+
+    let Some(LuaValue::Table(u)) = vm.get_global("u") else {
+        panic!("u is not a table");
+    };
+    let u = vm.heap.data(*u);
+    assert_eq!(u.array.len(), 3);
+    assert!(u.data.is_empty());
+
+    // the constructor's field counts reach NEWTABLE as size hints
+    let hinted = vm.func_meta["_start"].bytecode.iter().any(|op| {
+        matches!(
+            op,
+            OpCode::NewTable {
+                size_array: 3,
+                size_hash: 1,
+                ..
+            }
+        )
+    });
+    assert!(hinted);
+}
+
+#[test]
+fn test_field_access_uses_string_constants() {
+    let mut vm = common::run_source(
+        "
+        point = {x = 1}
+        point.y = 2
+        point.x = point.x + point.y
+        function point.scale(k)
+            return point.x * k
+        end
+        scaled = point.scale(10)
+        shout = (\"abc\"):upper()
+        key = \"y\"
+        by_key = point[key]
+        ",
+    );
+    assert_eq!(common::global_integer(&vm, "scaled"), 30);
+    assert_eq!(common::global_string(&vm, "shout"), "ABC");
+    assert_eq!(common::global_integer(&vm, "by_key"), 2);
+
+    let bytecode = &vm.func_meta["_start"].bytecode;
+    assert!(
+        bytecode
+            .iter()
+            .any(|op| matches!(op, OpCode::GetField { .. }))
+    );
+    assert!(
+        bytecode
+            .iter()
+            .any(|op| matches!(op, OpCode::SetField { .. }))
+    );
+    // a key only known at runtime still goes through GETTABLE
+    assert!(
+        bytecode
+            .iter()
+            .any(|op| matches!(op, OpCode::GetTable { .. }))
+    );
+
+    // the cached hash depends on the content only, not on the VM or the allocation
+    let mut other = VirtualMachine::new();
+    let a = vm.heap.alloc_str("scale").unwrap();
+    let b = other.heap.alloc_str("scale").unwrap();
+    let c = vm.heap.alloc_str("scalf").unwrap();
+    assert_eq!(a.content_hash(), b.content_hash());
+    assert_ne!(a.content_hash(), c.content_hash());
+}
+
+#[test]
+fn test_integer_subtype() {
+    let vm = common::run_source(
+        "
+        sum = 7 + 3
+        mixed = 7 + 0.5
+        quotient = 6 / 3
+        floor_mod = -7 % 3
+        float_mod = 7.5 % -2
+        wrapped = 9223372036854775807 + 1
+        neg = -(3 * 4)
+        same = 1 == 1.0
+        less = 2 < 2.5
+        t = {}
+        t[1.0] = \"one\"
+        by_int = t[1]
+        len = #t
+        int_str = tostring(10)
+        float_str = tostring(10 / 2)
+        int_type = math.type(sum)
+        float_type = math.type(quotient)
+        floored = math.floor(3.7)
+        converted = math.tointeger(4.0)
+        parsed = tonumber(\"42\")
+        formatted = string.format(\"%d\", 3.0)
+        ",
+    );
+
+    assert_eq!(common::global_integer(&vm, "sum"), 10);
+    assert_eq!(common::global_number(&vm, "mixed"), 7.5);
+    assert!(matches!(
+        vm.get_global("quotient"),
+        Some(myula::common::object::LuaValue::Number(n)) if *n == 2.0
+    ));
+    assert_eq!(common::global_integer(&vm, "floor_mod"), 2);
+    assert_eq!(common::global_number(&vm, "float_mod"), -0.5);
+    assert_eq!(common::global_integer(&vm, "wrapped"), i64::MIN);
+    assert_eq!(common::global_integer(&vm, "neg"), -12);
+    assert!(matches!(
+        vm.get_global("same"),
+        Some(myula::common::object::LuaValue::Boolean(true))
+    ));
+    assert!(matches!(
+        vm.get_global("less"),
+        Some(myula::common::object::LuaValue::Boolean(true))
+    ));
+    assert_eq!(common::global_string(&vm, "by_int"), "one");
+    assert_eq!(common::global_integer(&vm, "len"), 1);
+    assert_eq!(common::global_string(&vm, "int_str"), "10");
+    assert_eq!(common::global_string(&vm, "float_str"), "5.0");
+    assert_eq!(common::global_string(&vm, "int_type"), "integer");
+    assert_eq!(common::global_string(&vm, "float_type"), "float");
+    assert_eq!(common::global_integer(&vm, "floored"), 3);
+    assert_eq!(common::global_integer(&vm, "converted"), 4);
+    assert_eq!(common::global_integer(&vm, "parsed"), 42);
+    assert_eq!(common::global_string(&vm, "formatted"), "3");
+}
+
+#[test]
+fn test_reset_reuses_vm() {
+    let mut vm = myula::backend::vm::VirtualMachine::new();
+    common::run_source_on(
+        &mut vm,
+        "
+        leftover = {}
+        i = 0
+        while i < 200 do
+            leftover[i] = \"entry \" .. i
+            i = i + 1
+        end
+        print = nil
+        ",
+    );
+    let used = vm.heap.total_allocated;
+
+    vm.reset(true);
+    assert!(vm.heap.total_allocated < used);
+    assert!(vm.get_global("print").is_some());
+    assert!(common::global_is_nil(&vm, "leftover"));
+
+    common::run_source_on(
+        &mut vm,
+        "
+        -- reading an undefined global raises an error
+        gone = not pcall(function() return leftover end)
+        upper = string.upper(\"warm\")
+        ",
+    );
+    assert!(matches!(
+        vm.get_global("gone"),
+        Some(myula::common::object::LuaValue::Boolean(true))
+    ));
+    assert_eq!(common::global_string(&vm, "upper"), "WARM");
+
+    // without the standard library nothing survives, init loads it again
+    vm.reset(false);
+    assert!(vm.heap.data(vm.globals).is_empty());
+    // only the empty global table is left, as in a new VM
+    assert_eq!(
+        vm.heap.total_allocated,
+        VirtualMachine::new().heap.total_allocated
+    );
+    common::run_source_on(&mut vm, "n = #\"abc\"");
+    assert_eq!(common::global_integer(&vm, "n"), 3);
+}
+
+#[test]
+fn test_multiple_assignment() {
+    let vm = common::run_source(
+        "
+        local a, b = 1, 2
+        a, b = b, a
+        sa, sb = a, b
+
+        t = {10, 20, 30}
+        local i, j = 1, 3
+        t[i], t[j] = t[j], t[i]
+        first, last = t[1], t[3]
+
+        -- targets are resolved before any store, so the index uses the old i
+        i, t[i] = 2, 99
+        first_again = t[1]
+
+        x, y, z = 7
+        p, q = 1, 2, 3
+        chained = 1
+        m = (chained == 1) and 5
+        n = 0
+        n = n + 1
+
+        -- a trailing call fills the targets left over, a call before it gives one value
+        local function three() return 1, 2, 3 end
+        c1, c2, c3 = 0, three()
+        d1, d2, d3, d4 = three()
+        e1, e2 = three(), 9
+        local u, v = 0, 0
+        u, v = three()
+        uv = u * 10 + v
+        fields = {}
+        fields.a, fields[2] = three()
+        fa, f2 = fields.a, fields[2]
+        ",
+    );
+    assert_eq!(common::global_integer(&vm, "sa"), 2);
+    assert_eq!(common::global_integer(&vm, "sb"), 1);
+    assert_eq!(common::global_integer(&vm, "first"), 30);
+    assert_eq!(common::global_integer(&vm, "last"), 10);
+    assert_eq!(common::global_integer(&vm, "first_again"), 99);
+    assert_eq!(common::global_integer(&vm, "x"), 7);
+    assert!(common::global_is_nil(&vm, "y"));
+    assert!(common::global_is_nil(&vm, "z"));
+    assert_eq!(common::global_integer(&vm, "p"), 1);
+    assert_eq!(common::global_integer(&vm, "q"), 2);
+    assert_eq!(common::global_integer(&vm, "m"), 5);
+    assert_eq!(common::global_integer(&vm, "n"), 1);
+    assert_eq!(common::global_integer(&vm, "c1"), 0);
+    assert_eq!(common::global_integer(&vm, "c2"), 1);
+    assert_eq!(common::global_integer(&vm, "c3"), 2);
+    assert_eq!(common::global_integer(&vm, "d1"), 1);
+    assert_eq!(common::global_integer(&vm, "d2"), 2);
+    assert_eq!(common::global_integer(&vm, "d3"), 3);
+    assert!(common::global_is_nil(&vm, "d4"));
+    assert_eq!(common::global_integer(&vm, "e1"), 1);
+    assert_eq!(common::global_integer(&vm, "e2"), 9);
+    assert_eq!(common::global_integer(&vm, "uv"), 12);
+    assert_eq!(common::global_integer(&vm, "fa"), 1);
+    assert_eq!(common::global_integer(&vm, "f2"), 2);
+}
+
+#[test]
+fn test_assignment_to_captured_locals() {
+    let vm = common::run_source(
+        "
+local n = 0
+local function counter()
+    n = n + 1
+    return n
+end
+counter()
+counter()
+local function a()
+    local function b()
+        n = n + 5
+    end
+    b()
+end
+a()
+count = n
+
+local x, y = 1, 2
+local function swap()
+    x, y = y, x
+end
+swap()
+first = x
+second = y
+",
+    );
+    // the writes land in the captured locals, no global of that name appears
+    assert_eq!(common::global_integer(&vm, "count"), 7);
+    assert_eq!(common::global_integer(&vm, "first"), 2);
+    assert_eq!(common::global_integer(&vm, "second"), 1);
+    assert!(common::global_is_nil(&vm, "n"));
+    assert!(common::global_is_nil(&vm, "x"));
+}
+
+#[test]
+fn test_block_scopes() {
+    let vm = common::run_source(
+        "
+        local x = 1
+        do
+            local x = 2
+            inner = x
+        end
+        after_do = x
+
+        if true then
+            local y = 10
+            x = x + y
+        end
+        leaked = pcall(function() return y end)
+        after_if = x
+
+        local get = function() return x end
+        local x = 100
+        captured = get()
+        latest = x
+
+        local n = 0
+        repeat
+            local done = n >= 2
+            n = n + 1
+        until done
+        loops = n
+
+        local a, b = 5
+        b_nil = b == nil
+        local s = 3
+        local s, t = s + 1, s
+        sum = s + t
+        ",
+    );
+    assert_eq!(common::global_integer(&vm, "inner"), 2);
+    assert_eq!(common::global_integer(&vm, "after_do"), 1);
+    assert_eq!(common::global_integer(&vm, "after_if"), 11);
+    assert!(matches!(
+        vm.get_global("leaked"),
+        Some(myula::common::object::LuaValue::Boolean(false))
+    ));
+    // a redeclared local is a new variable, the closure keeps the old one
+    assert_eq!(common::global_integer(&vm, "captured"), 11);
+    assert_eq!(common::global_integer(&vm, "latest"), 100);
+    assert_eq!(common::global_integer(&vm, "loops"), 3);
+    assert!(matches!(
+        vm.get_global("b_nil"),
+        Some(myula::common::object::LuaValue::Boolean(true))
+    ));
+    // both values read the outer s
+    assert_eq!(common::global_integer(&vm, "sum"), 7);
+}
+
+#[test]
+fn test_register_function_closure() {
+    use myula::common::object::LuaValue;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let sink = log.clone();
+    let mut total = 0;
+
+    let mut vm = VirtualMachine::new();
+    vm.register_function("record", move |vm, argc| {
+        let n = vm.native_arg(argc, 0).as_integer().unwrap_or(0);
+        total += n;
+        sink.borrow_mut().push(n);
+        vm.value_stack.push(LuaValue::Integer(total));
+        Ok(1)
+    });
+    vm.register_function("reenter", |vm, _argc| {
+        let this = vm.get_global("reenter").cloned().unwrap();
+        vm.call_value(this, vec![])?;
+        Ok(0)
+    });
+
+    common::run_source_on(
+        &mut vm,
+        r#"
+        record(1)
+        record(2)
+        total = record(3)
+        name = tostring(record)
+        local t = {f = record}
+        via_table = t.f(10)
+        ok = pcall(reenter)
+        after = pcall(record, 0)
+    "#,
+    );
+
+    assert_eq!(*log.borrow(), vec![1, 2, 3, 10, 0]);
+    assert_eq!(common::global_integer(&vm, "total"), 6);
+    assert_eq!(common::global_integer(&vm, "via_table"), 16);
+    assert!(common::global_string(&vm, "name").starts_with("function: "));
+    // a closure cannot run inside itself, but it is usable again afterwards
+    assert!(matches!(
+        vm.get_global("ok"),
+        Some(LuaValue::Boolean(false))
+    ));
+    assert!(matches!(
+        vm.get_global("after"),
+        Some(LuaValue::Boolean(true))
+    ));
+}
+
+#[test]
+fn test_falling_off_the_end_returns_nil() {
+    let vm = common::run_source(
+        "
+local function nothing()
+    local x = 5
+end
+local function bare()
+    return
+end
+a = 1
+a = nothing()
+b = 2
+b = bare()
+",
+    );
+    assert!(common::global_is_nil(&vm, "a"));
+    assert!(common::global_is_nil(&vm, "b"));
+}
+
+#[test]
+fn test_unicode_strings_round_trip() {
+    let vm = common::run_source(
+        "
+local ключ = \"héllo 世界\"
+s = ключ .. ' 🎉'
+n = #s
+t = {[ключ] = 1}
+found = t[\"héllo 世界\"]
+",
+    );
+    assert_eq!(common::global_string(&vm, "s"), "héllo 世界 🎉");
+    // the length operator counts bytes, as in lua
+    assert_eq!(
+        common::global_integer(&vm, "n"),
+        "héllo 世界 🎉".len() as i64
+    );
+    assert_eq!(common::global_integer(&vm, "found"), 1);
+}
+
+#[test]
+fn test_constant_operands_and_branches() {
+    let vm = common::run_source(
+        "
+local total = 0
+local i = 0
+while i < 10 do
+    i = i + 1
+    if i % 2 == 0 then
+        total = total + 1
+    else
+        total = total - 0.5
+    end
+end
+wrapped = 9223372036854775807 + 1
+mixed = total + 0.25
+neg = 3 - 5
+",
+    );
+    assert_eq!(common::global_number(&vm, "mixed"), 2.75);
+    assert_eq!(common::global_integer(&vm, "wrapped"), i64::MIN);
+    assert_eq!(common::global_integer(&vm, "neg"), -2);
+
+    let err = common::run_until_error(
+        "
+local t = {}
+local u = t + 1
+",
+    )
+    .expect("adding a table and a number must fail");
+    assert!(err.get_message().contains("'addition'"), "{}", err);
+}
+
+#[test]
+fn test_signed_zero_constants_stay_apart() {
+    let vm = common::run_source(
+        "
+a = tostring(0.0)
+b = tostring(-0.0)
+",
+    );
+    assert_eq!(common::global_string(&vm, "a"), "0.0");
+    assert_eq!(common::global_string(&vm, "b"), "-0.0");
+}
+
+#[test]
+fn test_closures_share_their_prototype() {
+    let vm = common::run_source(
+        "function counter()\n\
+           local n = 0\n\
+           return function() n = n + 1 return n end\n\
+         end\n\
+         a = counter()\n\
+         b = counter()\n\
+         a()\n\
+         x = a() + b()\n",
+    );
+    assert_eq!(common::global_integer(&vm, "x"), 3);
+    let (Some(LuaValue::Function(a)), Some(LuaValue::Function(b))) =
+        (vm.get_global("a"), vm.get_global("b"))
+    else {
+        panic!("a and b are not closures");
+    };
+    let (a, b) = (vm.heap.data(*a), vm.heap.data(*b));
+    // one prototype, loaded once; each closure only has its own upvalues
+    assert!(std::rc::Rc::ptr_eq(&a.proto, &b.proto));
+    assert!(std::rc::Rc::ptr_eq(&a.proto, &vm.protos[a.proto.id]));
+    assert_ne!(a.upvalues[0], b.upvalues[0]);
+}
+
+#[test]
+fn test_calls_fill_every_target_with_results() {
+    let vm = common::run_source(
+        r#"
+        local function two() return 1, 2 end
+        local p, q = two()
+        local t = {7}
+        k, v, w = next(t)
+        local function three() return 0, two() end
+        local a, b, c = three()
+        local function swap(x, y, z) return z, y, x end
+        local x, y, z = swap(0, two())
+        first, second = two(), 10
+        P, Q, A, B, C, X, Y, Z = p, q, a, b, c, x, y, z
+        "#,
+    );
+    assert_eq!(common::global_integer(&vm, "P"), 1);
+    assert_eq!(common::global_integer(&vm, "Q"), 2);
+    // next pushes a key and a value, w gets nil
+    assert_eq!(common::global_integer(&vm, "k"), 1);
+    assert_eq!(common::global_integer(&vm, "v"), 7);
+    assert!(common::global_is_nil(&vm, "w"));
+    // a call as the last value of a return returns all of its results
+    assert_eq!(common::global_integer(&vm, "A"), 0);
+    assert_eq!(common::global_integer(&vm, "B"), 1);
+    assert_eq!(common::global_integer(&vm, "C"), 2);
+    // ... and as the last argument passes all of them
+    assert_eq!(common::global_integer(&vm, "X"), 2);
+    assert_eq!(common::global_integer(&vm, "Y"), 1);
+    assert_eq!(common::global_integer(&vm, "Z"), 0);
+    // anywhere else it gives one value
+    assert_eq!(common::global_integer(&vm, "first"), 1);
+    assert_eq!(common::global_integer(&vm, "second"), 10);
+
+    // a target after the first reads its result with GETRESULT, the results of a call passed on
+    // to another call are not pushed, that CALL takes them with MULTRET
+    let bytecode = &vm.func_meta["_start"].bytecode;
+    let indices: Vec<_> = bytecode
+        .iter()
+        .filter_map(|op| match op {
+            OpCode::GetResult { index, .. } => Some(*index),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(indices, [1, 1, 2, 1, 2, 1, 2]);
+    assert!(bytecode.iter().any(|op| matches!(
+        op,
+        OpCode::Call {
+            argc: MULTRET,
+            retc: 3,
+            ..
+        }
+    )));
+}
+
+#[test]
+fn test_program_output_goes_to_the_stdout_sink() {
+    let out = common::Capture::default();
+    let mut vm = VirtualMachine::new();
+    vm.set_stdout(Box::new(out.clone()));
+    common::run_source_on(&mut vm, "print('a', 1)\nio.write('b', 2.5)\n");
+    assert_eq!(out.text(), "a\t1\nb2.5Program exited with code 0.\n");
+
+    // the debug log and the state dump go there too
+    let out = common::Capture::default();
+    let mut vm = VirtualMachine::new();
+    vm.set_stdout(Box::new(out.clone()));
+    let mut lexer = Lexer::new("x = 1");
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse();
+    let mut ir_gen = IRGenerator::new();
+    ir_gen.generate(&program);
+    let mut scanner = Scanner::new();
+    scanner.global_scan(ir_gen.get_module());
+    vm.init(&ir_gen, LogLevel::Debug, &mut scanner).unwrap();
+    vm.dump_internal_state();
+    let text = out.text();
+    assert!(text.contains("[DEBUG] Starting scanner..."), "{}", text);
+    assert!(text.contains("VIRTUAL MACHINE INTERNAL STATE"), "{}", text);
+}
+
+#[test]
+fn test_concat_chain_is_one_opcode() {
+    let vm = common::run_source(
+        "
+        local name, n = \"x\", 3
+        joined = name .. n .. \"-\" .. 2.5 .. name
+        local function wrap(v) return \"<\" .. v .. \">\" .. v end
+        wrapped = wrap(1) .. wrap(\"q\")
+        grouped = (name .. n) .. name
+        ",
+    );
+    assert_eq!(common::global_string(&vm, "joined"), "x3-2.5x");
+    assert_eq!(common::global_string(&vm, "wrapped"), "<1>1<q>q");
+    assert_eq!(common::global_string(&vm, "grouped"), "x3x");
+
+    let bytecode = &vm.func_meta["_start"].bytecode;
+    assert!(
+        bytecode
+            .iter()
+            .any(|op| matches!(op, OpCode::ConcatN { count, .. } if *count == 5))
+    );
+
+    let err = common::run_until_error("local a = \"a\"\nx = a .. a .. nil\n")
+        .expect("concatenating nil should fail");
+    assert!(format!("{:?}", err).contains("nil value"), "{:?}", err);
+}
+
+#[test]
+fn test_table_constructor_stores_positional_fields_in_batches() {
+    let items: Vec<String> = (1..=120).map(|i| i.to_string()).collect();
+    let vm = common::run_source(&format!(
+        "
+        local function nine() return 9 end
+        big = {{{}}}
+        count, fiftieth, last = #big, big[50], big[120]
+        mixed = {{1, 2, x = \"k\", 0 + nine(), [1] = \"one\", 5}}
+        first, third, x = mixed[1], mixed[3], mixed.x
+        ",
+        items.join(", ")
+    ));
+    assert_eq!(common::global_integer(&vm, "count"), 120);
+    assert_eq!(common::global_integer(&vm, "fiftieth"), 50);
+    assert_eq!(common::global_integer(&vm, "last"), 120);
+    // positional fields are stored after the keyed ones before them, as in Lua
+    assert_eq!(common::global_integer(&vm, "first"), 1);
+    assert_eq!(common::global_integer(&vm, "third"), 9);
+    assert_eq!(common::global_string(&vm, "x"), "k");
+
+    let bytecode = &vm.func_meta["_start"].bytecode;
+    let batches: Vec<_> = bytecode
+        .iter()
+        .filter_map(|op| match op {
+            OpCode::SetList { count, batch, .. } => Some((*count, *batch)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(batches, [(50, 0), (50, 1), (20, 2), (4, 0)]);
+    assert!(
+        bytecode
+            .iter()
+            .any(|op| matches!(op, OpCode::NewTable { size_array, .. } if *size_array == 120))
+    );
+    // only `[1] = "one"` is stored on its own
+    assert_eq!(
+        bytecode
+            .iter()
+            .filter(|op| matches!(op, OpCode::SetTable { .. }))
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn test_local_functions_call_themselves_and_each_other() {
+    let vm = common::run_source(
+        "
+        local function fact(n) if n <= 1 then return 1 end return n * fact(n - 1) end
+        local function is_even(n) if n == 0 then return true end return is_odd(n - 1) end
+        local function is_odd(n) if n == 0 then return false end return is_even(n - 1) end
+        factorial = fact(10)
+        even, odd = is_even(10), is_odd(10)
+        do
+            local function ping(n) if n == 0 then return \"ping\" end return pong(n - 1) end
+            local function pong(n) if n == 0 then return \"pong\" end return ping(n - 1) end
+            nested = ping(3)
+        end
+        ",
+    );
+    assert_eq!(common::global_integer(&vm, "factorial"), 3628800);
+    assert_eq!(vm.get_global("even"), Some(&LuaValue::Boolean(true)));
+    assert_eq!(vm.get_global("odd"), Some(&LuaValue::Boolean(false)));
+    assert_eq!(common::global_string(&vm, "nested"), "pong");
+
+    // only adjacent declarations are known ahead, a statement in between ends the run
+    let err = common::run_until_error(
+        "local function f() return g() end\nx = 1\nlocal function g() return 1 end\ny = f()\n",
+    )
+    .expect("g is not visible in f");
+    assert!(
+        matches!(err.kind, ErrorKind::UndefinedVariable(ref name) if name == "g"),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn test_local_initializer_reads_the_outer_variable() {
+    let vm = common::run_source(
+        "
+        local x = 1
+        do local x = x + 1 inner = x end
+        outer = x
+        local function f() local x = x + 10 return x end
+        from_function = f()
+        local n, sum = 0, 0
+        while n < 2 do n = n + 1 local x = x + n sum = sum + x end
+        total = sum
+        repeat local x = x + 5 until x > 1
+        local a, b = 1, 2
+        do local a, b = b, a swapped = a * 10 + b end
+        kept = a * 10 + b
+        local z = 3
+        local g = function() return z end
+        local z = z + 1
+        captured, redeclared = g(), z
+        ",
+    );
+    assert_eq!(common::global_integer(&vm, "inner"), 2);
+    assert_eq!(common::global_integer(&vm, "outer"), 1);
+    assert_eq!(common::global_integer(&vm, "from_function"), 11);
+    assert_eq!(common::global_integer(&vm, "total"), 2 + 3);
+    assert_eq!(common::global_integer(&vm, "swapped"), 21);
+    assert_eq!(common::global_integer(&vm, "kept"), 12);
+    // the closure keeps the first z, the redeclaration has a slot of its own
+    assert_eq!(common::global_integer(&vm, "captured"), 3);
+    assert_eq!(common::global_integer(&vm, "redeclared"), 4);
+}
+
+#[test]
+fn test_functions_too_large_for_the_operands_are_errors() {
+    use myula::backend::translator::emitter::EmitError;
+
+    let scan = |source: &str| {
+        let mut lexer = Lexer::new(source);
+        let mut parser = Parser::new(&mut lexer);
+        let program = parser.parse();
+        let mut ir_gen = IRGenerator::new();
+        ir_gen.generate(&program);
+        let mut scanner = Scanner::new();
+        scanner.global_scan(ir_gen.get_module());
+        (ir_gen, scanner)
+    };
+
+    // one constant more than an index can address
+    let strings: Vec<String> = (0..=u16::MAX as usize + 1)
+        .map(|i| format!("\"s{}\"", i))
+        .collect();
+    let (ir_gen, mut scanner) = scan(&format!("local t = {{{}}}", strings.join(", ")));
+    assert_eq!(
+        VirtualMachine::compile(&ir_gen, &mut scanner, false).err(),
+        Some(EmitError::TooManyConstants {
+            function: "_start".into(),
+            count: u16::MAX as usize + 2,
+        })
+    );
+    let (ir_gen, mut scanner) = scan(&format!("local t = {{{}}}", strings[1..].join(", ")));
+    assert!(VirtualMachine::compile(&ir_gen, &mut scanner, false).is_ok());
+
+    let args = vec!["1"; 256].join(", ");
+    let (ir_gen, mut scanner) = scan(&format!("print({})\nreturn print({})", args, args));
+    assert_eq!(
+        VirtualMachine::compile(&ir_gen, &mut scanner, false).err(),
+        Some(EmitError::TooManyArguments {
+            function: "_start".into(),
+            count: 256,
+        })
+    );
+
+    // allocating that many registers takes the scanner minutes, so the frame it
+    // reports is enlarged instead
+    let (ir_gen, mut scanner) = scan("local a = 1\nprint(a)");
+    let frame = scanner.func_stack_info.get_mut("_start").unwrap();
+    frame.1 = u16::MAX as usize + 2;
+    let err = VirtualMachine::compile(&ir_gen, &mut scanner, false)
+        .err()
+        .unwrap();
+    assert_eq!(
+        err,
+        EmitError::FrameTooLarge {
+            function: "_start".into(),
+            registers: u16::MAX as usize + 2,
+        }
+    );
+    assert!(err.to_string().contains("65536"), "{}", err);
+    let mut vm = VirtualMachine::new();
+    assert_eq!(vm.init(&ir_gen, LogLevel::Release, &mut scanner), Err(err));
+}
+
+#[test]
+fn test_indices_too_wide_for_the_operands_are_errors() {
+    use myula::backend::translator::emitter::{BytecodeEmitter, EmitError};
+    use myula::common::opcode::SETLIST_BATCH;
+    use myula::frontend::ir::{
+        IRBasicBlock, IRFunction, IRInstruction, IRModule, IROperand, IRTerminator,
+    };
+    use myula::frontend::parser::ast::Span;
+    use std::collections::HashMap;
+
+    // the programs reaching these limits are too large to parse in a test, the IR is built directly
+    let block =
+        |id: usize, instructions: Vec<IRInstruction>, terminator: IRTerminator| IRBasicBlock {
+            id,
+            lines: vec![0; instructions.len()],
+            spans: vec![Span::default(); instructions.len()],
+            instructions,
+            terminator,
+            terminator_line: 0,
+            terminator_span: Span::default(),
+        };
+    let function = |basic_blocks: Vec<IRBasicBlock>, sub_functions: Vec<String>| IRFunction {
+        name: "_start".to_string(),
+        params: Vec::new(),
+        basic_blocks,
+        local_variables: HashMap::new(),
+        local_positions: Vec::new(),
+        upvalues: HashMap::new(),
+        sub_functions,
+    };
+    let emit = |func: IRFunction| {
+        let module = IRModule {
+            functions: vec![func],
+        };
+        let mut scanner = Scanner::new();
+        scanner.global_scan(&module);
+        BytecodeEmitter::new(&module.functions[0], &scanner)
+            .emit()
+            .err()
+    };
+    let wide = u16::MAX as usize + 1;
+
+    let fields = |batch| {
+        function(
+            vec![block(
+                0,
+                vec![
+                    IRInstruction::NewTable {
+                        dest: 0,
+                        size_array: IROperand::ImmInt(0),
+                        size_hash: IROperand::ImmInt(0),
+                    },
+                    IRInstruction::LoadImm {
+                        dest: 1,
+                        value: IROperand::ImmInt(1),
+                    },
+                    IRInstruction::SetList {
+                        table: IROperand::Reg(0),
+                        batch,
+                        values: vec![IROperand::Reg(1)],
+                    },
+                ],
+                IRTerminator::Return(vec![IROperand::Reg(0)]),
+            )],
+            Vec::new(),
+        )
+    };
+    assert_eq!(
+        emit(fields(wide)),
+        Some(EmitError::TooManyTableFields {
+            function: "_start".into(),
+            count: wide * SETLIST_BATCH + 1,
+        })
+    );
+    assert_eq!(emit(fields(wide - 1)), None);
+
+    let nested = |count: usize| {
+        function(
+            vec![block(
+                0,
+                vec![IRInstruction::FnProto {
+                    dest: 0,
+                    func_proto: IROperand::Proto(format!("f{}", count - 1)),
+                }],
+                IRTerminator::Return(vec![IROperand::Reg(0)]),
+            )],
+            (0..count).map(|i| format!("f{}", i)).collect(),
+        )
+    };
+    assert_eq!(
+        emit(nested(wide + 1)),
+        Some(EmitError::TooManyNestedFunctions {
+            function: "_start".into(),
+            count: wide + 1,
+        })
+    );
+    assert_eq!(emit(nested(wide)), None);
+
+    for store in [false, true] {
+        let upvalue = |slot| {
+            let access = if store {
+                IRInstruction::StoreUpVal {
+                    dest: 1,
+                    dst: IROperand::UpVal(slot),
+                    src: IROperand::Reg(0),
+                }
+            } else {
+                IRInstruction::LoadUpVal {
+                    dest: 1,
+                    src: IROperand::UpVal(slot),
+                }
+            };
+            let load = IRInstruction::LoadImm {
+                dest: 0,
+                value: IROperand::ImmInt(1),
+            };
+            function(
+                vec![block(
+                    0,
+                    vec![load, access],
+                    IRTerminator::Return(vec![IROperand::Reg(1)]),
+                )],
+                Vec::new(),
+            )
+        };
+        assert_eq!(
+            emit(upvalue(wide)),
+            Some(EmitError::TooManyUpvalues {
+                function: "_start".into(),
+                count: wide + 1,
+            })
+        );
+        assert_eq!(emit(upvalue(wide - 1)), None);
+    }
+
+    // every block but the last switches to the next one
+    let switches = |count: usize| {
+        let mut blocks = vec![block(
+            0,
+            vec![IRInstruction::LoadImm {
+                dest: 0,
+                value: IROperand::ImmInt(1),
+            }],
+            IRTerminator::Jump(1),
+        )];
+        for id in 1..=count {
+            blocks.push(block(
+                id,
+                Vec::new(),
+                IRTerminator::Switch {
+                    value: IROperand::Reg(0),
+                    low: 1,
+                    targets: vec![id + 1],
+                    default: id + 1,
+                },
+            ));
+        }
+        blocks.push(block(
+            count + 1,
+            Vec::new(),
+            IRTerminator::Return(Vec::new()),
+        ));
+        function(blocks, Vec::new())
+    };
+    let err = emit(switches(wide + 1)).unwrap();
+    assert_eq!(
+        err,
+        EmitError::TooManyJumpTables {
+            function: "_start".into(),
+            count: wide + 1,
+        }
+    );
+    assert!(err.to_string().contains("65536"), "{}", err);
+    assert_eq!(emit(switches(wide)), None);
+}